@@ -0,0 +1,9 @@
+// Compiles the runtime library into the compiler, for running programs in memory
+fn main() {
+    println!("cargo:rerun-if-changed=runtime/runtime.c");
+    cc::Build::new()
+        .file("runtime/runtime.c")
+        .compile("ruffle_runtime");
+    // For `fmod`
+    println!("cargo:rustc-link-lib=m");
+}
@@ -0,0 +1,14 @@
+/*
+ * The entry point of an executable, which is left out of the runtime library when it's compiled
+ * into the compiler to run programs in memory.
+ */
+
+#include <stdint.h>
+
+/* Defined by the compiled program: initializes its globals, then calls its `main`. */
+extern int64_t ruffle_main(void);
+
+int main(void) {
+    ruffle_main();
+    return 0;
+}
@@ -0,0 +1,62 @@
+// Runs a Ruffle program compiled with `--emit=wasm` in a browser or Node, where there's no WASI
+// to give it the functions it imports. Its output goes to the console, its input is always at its
+// end, and it has no files, so opening or finding one fails with `ENOSYS`.
+
+const ENOSYS = 52;
+
+class Exit extends Error {
+    constructor(code) {
+        super(`exited with code ${code}`);
+        this.code = code;
+    }
+}
+
+// Instantiates a module from its bytes, runs it, and returns the code it exited with. What it
+// writes to stderr goes to `console.error`.
+export async function run(bytes) {
+    let memory;
+    const decoder = new TextDecoder();
+    const wasi = {
+        fd_write(fd, iovs, count, written) {
+            const view = new DataView(memory.buffer);
+            let text = "";
+            let total = 0;
+            for (let i = 0; i < count; i++) {
+                const ptr = view.getUint32(iovs + 8 * i, true);
+                const len = view.getUint32(iovs + 8 * i + 4, true);
+                text += decoder.decode(new Uint8Array(memory.buffer, ptr, len));
+                total += len;
+            }
+            (fd === 2 ? console.error : console.log)(text.replace(/\n$/, ""));
+            view.setUint32(written, total, true);
+            return 0;
+        },
+        fd_read(fd, iovs, count, read) {
+            new DataView(memory.buffer).setUint32(read, 0, true);
+            return 0;
+        },
+        proc_exit(code) {
+            throw new Exit(code);
+        },
+        path_open() {
+            return ENOSYS;
+        },
+        path_filestat_get() {
+            return ENOSYS;
+        },
+        fd_close() {
+            return 0;
+        },
+    };
+    const { instance } = await WebAssembly.instantiate(bytes, { wasi_snapshot_preview1: wasi });
+    memory = instance.exports.memory;
+    try {
+        instance.exports._start();
+        return 0;
+    } catch (error) {
+        if (error instanceof Exit) {
+            return error.code;
+        }
+        throw error;
+    }
+}
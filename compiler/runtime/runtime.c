@@ -0,0 +1,683 @@
+/*
+ * The runtime library of Ruffle programs compiled to native code.
+ *
+ * Every value is 64 bits. Integers are sign or zero extended from their type, floats are their
+ * bits, and `bool`s and `char`s are their numbers. Everything else is a pointer to an object on
+ * the heap, except for the unit value `()`, which is null. An object has a header, then 64-bit
+ * slots for its fields, elements or captured values, or the bytes of a string. The header points
+ * to the object's shape, which says what each slot holds, so that objects can be compared
+ * without knowing their types.
+ */
+
+#include <errno.h>
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+
+typedef int64_t rf_value;
+
+enum {
+    RF_TUPLE,
+    /* A struct, or a variant of an enum, whose tag is the index of the variant. */
+    RF_VARIANT,
+    RF_ARRAY,
+    RF_STRING,
+    /* A range, whose tag says which bounds it has, and whether it includes its end. */
+    RF_RANGE,
+    /* A function or closure, whose first slot points to the code that calls it. */
+    RF_FUNCTION,
+    /* A map, whose length is its number of entries, and whose tag is the size of its table. */
+    RF_MAP,
+};
+
+typedef struct rf_object {
+    uint32_t kind;
+    uint32_t tag;
+    int64_t len;
+    /* A letter for each slot: 'i' for integers, `bool`s and `char`s, 'f' for floats, and 'o'
+       for objects. An array has one letter, for all of its elements, a map has two, for its keys
+       and its values, and a string has none. A map's slots are its keys and values in pairs, in
+       the order they were inserted, then its table, which finds them by the hashes of their
+       keys. */
+    const char *shape;
+    rf_value slots[];
+} rf_object;
+
+static rf_object *object(rf_value value) { return (rf_object *)(intptr_t)value; }
+
+static char *bytes(rf_object *string) { return (char *)string->slots; }
+
+void rf_fail(const char *message) __attribute__((noreturn));
+
+void rf_fail(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "panicked: %s\n", message);
+    exit(101);
+}
+
+void rf_panic(rf_value message) __attribute__((noreturn));
+
+void rf_panic(rf_value message) {
+    rf_object *string = object(message);
+    fflush(stdout);
+    fprintf(stderr, "panicked: %.*s\n", (int)string->len, bytes(string));
+    exit(101);
+}
+
+static rf_object *allocate(uint32_t kind, uint32_t tag, int64_t len, size_t size,
+                           const char *shape) {
+    rf_object *result = calloc(1, sizeof(rf_object) + size);
+    if (!result) {
+        rf_fail("out of memory");
+    }
+    result->kind = kind;
+    result->tag = tag;
+    result->len = len;
+    result->shape = shape;
+    return result;
+}
+
+rf_value rf_alloc(int64_t kind, int64_t tag, int64_t len, const char *shape) {
+    rf_object *result = allocate(kind, tag, len, len * sizeof(rf_value), shape);
+    return (rf_value)(intptr_t)result;
+}
+
+rf_value rf_string(const char *data, int64_t len) {
+    /* One more byte than the string, so that it ends in 0 */
+    rf_object *result = allocate(RF_STRING, 0, len, len + 1, NULL);
+    memcpy(bytes(result), data, len);
+    return (rf_value)(intptr_t)result;
+}
+
+rf_value rf_concat(rf_value lhs, rf_value rhs) {
+    rf_object *a = object(lhs), *b = object(rhs);
+    rf_object *result = allocate(RF_STRING, 0, a->len + b->len, a->len + b->len + 1, NULL);
+    memcpy(bytes(result), bytes(a), a->len);
+    memcpy(bytes(result) + a->len, bytes(b), b->len);
+    return (rf_value)(intptr_t)result;
+}
+
+/* Compares strings by their bytes, which orders them by their characters. */
+int64_t rf_compare_strings(rf_value lhs, rf_value rhs) {
+    rf_object *a = object(lhs), *b = object(rhs);
+    int64_t len = a->len < b->len ? a->len : b->len;
+    int order = memcmp(bytes(a), bytes(b), len);
+    if (order != 0) {
+        return order < 0 ? -1 : 1;
+    }
+    return (a->len > b->len) - (a->len < b->len);
+}
+
+static double to_float(rf_value value) {
+    double result;
+    memcpy(&result, &value, sizeof result);
+    return result;
+}
+
+static rf_value from_float(double value) {
+    rf_value result;
+    memcpy(&result, &value, sizeof result);
+    return result;
+}
+
+/* The remainder of dividing floats, for the backends that don't have an instruction for it. */
+rf_value rf_float_rem(rf_value lhs, rf_value rhs) {
+    return from_float(fmod(to_float(lhs), to_float(rhs)));
+}
+
+static int64_t maps_equal(rf_object *a, rf_object *b);
+
+/* The functions of the `math` module that take floats. */
+rf_value rf_sqrt(rf_value x) { return from_float(sqrt(to_float(x))); }
+
+rf_value rf_floor(rf_value x) { return from_float(floor(to_float(x))); }
+
+rf_value rf_ceil(rf_value x) { return from_float(ceil(to_float(x))); }
+
+rf_value rf_pow(rf_value x, rf_value y) { return from_float(pow(to_float(x), to_float(y))); }
+
+/* Returns whether two values are equal, where `kind` is the letter of their shape. */
+int64_t rf_equal(rf_value lhs, rf_value rhs, int64_t kind) {
+    if (kind == 'f') {
+        return to_float(lhs) == to_float(rhs);
+    }
+    if (kind != 'o' || lhs == rhs) {
+        return lhs == rhs;
+    }
+    rf_object *a = object(lhs), *b = object(rhs);
+    /* Maps with the same entries can have tables of different sizes */
+    if (a && b && a->kind == RF_MAP && b->kind == RF_MAP) {
+        return maps_equal(a, b);
+    }
+    if (!a || !b || a->kind != b->kind || a->tag != b->tag || a->len != b->len) {
+        return 0;
+    }
+    switch (a->kind) {
+    case RF_STRING:
+        return memcmp(bytes(a), bytes(b), a->len) == 0;
+    case RF_FUNCTION:
+        return 0;
+    }
+    for (int64_t i = 0; i < a->len; i++) {
+        char slot = a->kind == RF_ARRAY ? a->shape[0] : a->shape[i];
+        if (!rf_equal(a->slots[i], b->slots[i], slot)) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+static rf_object *copy(rf_object *original) {
+    size_t size = original->len * sizeof(rf_value);
+    rf_object *result =
+        allocate(original->kind, original->tag, original->len, size, original->shape);
+    memcpy(result->slots, original->slots, size);
+    return result;
+}
+
+rf_value rf_set_field(rf_value base, int64_t index, rf_value value) {
+    rf_object *result = copy(object(base));
+    result->slots[index] = value;
+    return (rf_value)(intptr_t)result;
+}
+
+int64_t rf_len(rf_value array) { return object(array)->len; }
+
+static void check_index(rf_object *array, int64_t index) {
+    if (index < 0 || index >= array->len) {
+        rf_fail("index out of bounds");
+    }
+}
+
+rf_value rf_index(rf_value array, int64_t index) {
+    check_index(object(array), index);
+    return object(array)->slots[index];
+}
+
+rf_value rf_set_index(rf_value array, int64_t index, rf_value value) {
+    check_index(object(array), index);
+    rf_object *result = copy(object(array));
+    result->slots[index] = value;
+    return (rf_value)(intptr_t)result;
+}
+
+/* A copy of an array with a value added to its end. */
+rf_value rf_push(rf_value array, rf_value value) {
+    rf_object *a = object(array);
+    size_t size = (a->len + 1) * sizeof(rf_value);
+    rf_object *result = allocate(RF_ARRAY, 0, a->len + 1, size, a->shape);
+    memcpy(result->slots, a->slots, a->len * sizeof(rf_value));
+    result->slots[a->len] = value;
+    return (rf_value)(intptr_t)result;
+}
+
+/* A copy of an array without its last element, if it has one. */
+rf_value rf_pop(rf_value array) {
+    rf_object *result = copy(object(array));
+    if (result->len > 0) {
+        result->len--;
+    }
+    return (rf_value)(intptr_t)result;
+}
+
+/* The number of characters of a string, which is how strings are indexed and sliced. */
+int64_t rf_string_len(rf_value value) {
+    rf_object *string = object(value);
+    int64_t count = 0;
+    for (int64_t i = 0; i < string->len; i++) {
+        /* Every byte but the ones that continue a character starts one */
+        count += (bytes(string)[i] & 0xc0) != 0x80;
+    }
+    return count;
+}
+
+/* Decodes the UTF-8 character that starts at `data`, setting `len` to how many bytes it takes. */
+static uint32_t decode(const unsigned char *data, int *len) {
+    unsigned char byte = data[0];
+    *len = byte < 0x80 ? 1 : byte < 0xe0 ? 2 : byte < 0xf0 ? 3 : 4;
+    uint32_t c = *len == 1 ? byte : byte & (0x7f >> *len);
+    for (int j = 1; j < *len; j++) {
+        c = c << 6 | (data[j] & 0x3f);
+    }
+    return c;
+}
+
+/* Encodes a character as UTF-8 at `data`, returning how many bytes it takes. */
+static int encode(uint32_t c, char *data) {
+    if (c < 0x80) {
+        data[0] = (char)c;
+        return 1;
+    }
+    int len = c < 0x800 ? 2 : c < 0x10000 ? 3 : 4;
+    for (int j = len - 1; j > 0; j--) {
+        data[j] = (char)(0x80 | (c & 0x3f));
+        c >>= 6;
+    }
+    data[0] = (char)((0xf00 >> len) | c);
+    return len;
+}
+
+/* The text of a value that `as string` converts, whose kind is 'i' for a signed integer, 'u' for
+   an unsigned one, 'b' for a `bool` and 'c' for a `char`. */
+rf_value rf_to_string(rf_value value, int64_t kind) {
+    char text[24];
+    int len;
+    switch (kind) {
+    case 'i':
+        len = snprintf(text, sizeof text, "%lld", (long long)value);
+        break;
+    case 'u':
+        len = snprintf(text, sizeof text, "%llu", (unsigned long long)value);
+        break;
+    case 'b':
+        return value ? rf_string("true", 4) : rf_string("false", 5);
+    default:
+        len = encode((uint32_t)value, text);
+    }
+    return rf_string(text, len);
+}
+
+/* Decodes the UTF-8 of a string into an array of its characters. */
+rf_value rf_chars(rf_value value) {
+    rf_object *string = object(value);
+    const unsigned char *data = (const unsigned char *)bytes(string);
+    int64_t count = rf_string_len(value);
+    rf_object *result = allocate(RF_ARRAY, 0, count, count * sizeof(rf_value), "i");
+    int64_t n = 0;
+    for (int64_t i = 0; i < string->len;) {
+        int len;
+        result->slots[n++] = decode(data + i, &len);
+        i += len;
+    }
+    return (rf_value)(intptr_t)result;
+}
+
+/* The offset of the byte that starts a character of a string, or of its end for the index after
+   its last character. */
+static int64_t char_offset(rf_object *string, int64_t index) {
+    int64_t count = 0;
+    for (int64_t i = 0; i < string->len; i++) {
+        if ((bytes(string)[i] & 0xc0) != 0x80 && count++ == index) {
+            return i;
+        }
+    }
+    if (index != count) {
+        rf_fail("index out of bounds");
+    }
+    return string->len;
+}
+
+rf_value rf_char_at(rf_value value, int64_t index) {
+    rf_object *string = object(value);
+    int64_t offset = char_offset(string, index);
+    if (offset == string->len) {
+        rf_fail("index out of bounds");
+    }
+    int len;
+    return decode((const unsigned char *)bytes(string) + offset, &len);
+}
+
+/* The characters of a string in a range, whose tag says which bounds it has. */
+rf_value rf_slice(rf_value value, rf_value bounds) {
+    rf_object *string = object(value), *range = object(bounds);
+    int64_t start = range->tag & 1 ? range->slots[0] : 0;
+    int64_t end = rf_string_len(value);
+    if (range->tag & 2) {
+        end = range->slots[1] + (range->tag >> 2 & 1);
+    }
+    if (start > end) {
+        rf_fail("index out of bounds");
+    }
+    int64_t from = char_offset(string, start), to = char_offset(string, end);
+    return rf_string(bytes(string) + from, to - from);
+}
+
+/* Returns whether `pattern` occurs in `string` at the offset `at`. */
+static int occurs_at(rf_object *string, rf_object *pattern, int64_t at) {
+    return at + pattern->len <= string->len &&
+           memcmp(bytes(string) + at, bytes(pattern), pattern->len) == 0;
+}
+
+int64_t rf_contains(rf_value value, rf_value pattern) {
+    for (int64_t i = 0; i <= object(value)->len; i++) {
+        if (occurs_at(object(value), object(pattern), i)) {
+            return 1;
+        }
+    }
+    return 0;
+}
+
+/* The parts of a string between each occurrence of a separator, which can't be empty. */
+rf_value rf_split(rf_value value, rf_value separator) {
+    rf_object *string = object(value), *sep = object(separator);
+    if (sep->len == 0) {
+        rf_fail("empty separator");
+    }
+    int64_t count = 1;
+    for (int64_t i = 0; i < string->len;) {
+        if (occurs_at(string, sep, i)) {
+            count++;
+            i += sep->len;
+        } else {
+            i++;
+        }
+    }
+    rf_object *result = allocate(RF_ARRAY, 0, count, count * sizeof(rf_value), "o");
+    int64_t n = 0, start = 0;
+    for (int64_t i = 0; i < string->len;) {
+        if (occurs_at(string, sep, i)) {
+            result->slots[n++] = rf_string(bytes(string) + start, i - start);
+            i += sep->len;
+            start = i;
+        } else {
+            i++;
+        }
+    }
+    result->slots[n] = rf_string(bytes(string) + start, string->len - start);
+    return (rf_value)(intptr_t)result;
+}
+
+/* Copies a string with its ASCII letters in upper case, or lower case. */
+static rf_value change_case(rf_value value, int upper) {
+    rf_object *string = object(value);
+    rf_value result = rf_string(bytes(string), string->len);
+    char *data = bytes(object(result));
+    for (int64_t i = 0; i < string->len; i++) {
+        char c = data[i];
+        if (upper && c >= 'a' && c <= 'z') {
+            data[i] = c - 'a' + 'A';
+        } else if (!upper && c >= 'A' && c <= 'Z') {
+            data[i] = c - 'A' + 'a';
+        }
+    }
+    return result;
+}
+
+rf_value rf_to_upper(rf_value value) { return change_case(value, 1); }
+
+rf_value rf_to_lower(rf_value value) { return change_case(value, 0); }
+
+void rf_print(rf_value text) {
+    rf_object *string = object(text);
+    fwrite(bytes(string), 1, string->len, stdout);
+}
+
+void rf_println(rf_value text) {
+    rf_print(text);
+    putchar('\n');
+}
+
+/* Reads a line from the standard input, without its newline, which is empty at the end of the
+   input. */
+rf_value rf_input(void) {
+    /* What was printed before is often a prompt for the line */
+    fflush(stdout);
+    size_t len = 0, capacity = 64;
+    char *line = malloc(capacity);
+    int c;
+    while (line && (c = getchar()) != EOF && c != '\n') {
+        if (len == capacity) {
+            capacity *= 2;
+            line = realloc(line, capacity);
+            if (!line) {
+                break;
+            }
+        }
+        line[len++] = (char)c;
+    }
+    if (!line) {
+        rf_fail("out of memory");
+    }
+    rf_value result = rf_string(line, len);
+    free(line);
+    return result;
+}
+
+/* The message of the error that the last of the file functions below failed with, which is empty
+   when it succeeded. */
+static rf_value fs_error;
+
+/* Keeps the message of an error number for `rf_fs_error`, where 0 is no error, and `EILSEQ` is a
+   file that isn't UTF-8. The messages of the errors programs usually meet are the same as the
+   interpreter gives. */
+static void set_fs_error(int error) {
+    const char *message;
+    switch (error) {
+    case 0:
+        message = "";
+        break;
+    case ENOENT:
+        message = "no such file or directory";
+        break;
+    case EACCES:
+    case EPERM:
+        message = "permission denied";
+        break;
+    case EISDIR:
+        message = "is a directory";
+        break;
+    case ENOTDIR:
+        message = "not a directory";
+        break;
+    case EILSEQ:
+        message = "not valid UTF-8";
+        break;
+    default:
+        message = strerror(error);
+    }
+    fs_error = rf_string(message, strlen(message));
+}
+
+/* Returns whether bytes are UTF-8, without overlong encodings or surrogates. */
+static int is_utf8(const unsigned char *data, int64_t len) {
+    int64_t i = 0;
+    while (i < len) {
+        unsigned char byte = data[i++];
+        int more = byte < 0x80                  ? 0
+                   : byte >= 0xc2 && byte < 0xe0 ? 1
+                   : byte >= 0xe0 && byte < 0xf0 ? 2
+                   : byte >= 0xf0 && byte < 0xf5 ? 3
+                                                 : -1;
+        if (more < 0 || len - i < more) {
+            return 0;
+        }
+        uint32_t c = byte & (0x7f >> more);
+        for (int j = 0; j < more; j++) {
+            if ((data[i] & 0xc0) != 0x80) {
+                return 0;
+            }
+            c = c << 6 | (data[i++] & 0x3f);
+        }
+        if ((more == 2 && (c < 0x800 || (c >= 0xd800 && c < 0xe000))) ||
+            (more == 3 && (c < 0x10000 || c > 0x10ffff))) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+/* Reads a file, returning an empty string when it can't be read. */
+rf_value rf_read_file(rf_value path) {
+    FILE *file = fopen(bytes(object(path)), "rb");
+    if (!file) {
+        set_fs_error(errno);
+        return rf_string("", 0);
+    }
+    size_t len = 0, capacity = 4096, read;
+    char *contents = malloc(capacity);
+    while (contents && (read = fread(contents + len, 1, capacity - len, file)) > 0) {
+        len += read;
+        if (len == capacity) {
+            capacity *= 2;
+            contents = realloc(contents, capacity);
+        }
+    }
+    if (!contents) {
+        rf_fail("out of memory");
+    }
+    int error = ferror(file) ? errno : 0;
+    fclose(file);
+    if (!error && !is_utf8((unsigned char *)contents, len)) {
+        error = EILSEQ;
+    }
+    set_fs_error(error);
+    rf_value result = rf_string(contents, error ? 0 : len);
+    free(contents);
+    return result;
+}
+
+/* Replaces a file, creating it when it doesn't exist. */
+rf_value rf_write_file(rf_value path, rf_value contents) {
+    rf_object *string = object(contents);
+    FILE *file = fopen(bytes(object(path)), "wb");
+    int error = file ? 0 : errno;
+    if (file) {
+        if (fwrite(bytes(string), 1, string->len, file) < (size_t)string->len) {
+            error = errno;
+        }
+        if (fclose(file) != 0 && !error) {
+            error = errno;
+        }
+    }
+    set_fs_error(error);
+    return 0;
+}
+
+/* Returns whether a file or directory exists, which is false without an error when it isn't
+   found, and with one when it can't be found out. */
+int64_t rf_file_exists(rf_value path) {
+    struct stat info;
+    int error = stat(bytes(object(path)), &info) == 0 ? 0 : errno;
+    set_fs_error(error == ENOENT ? 0 : error);
+    return !error;
+}
+
+rf_value rf_fs_error(void) { return fs_error; }
+
+static uint64_t mix(uint64_t hash, uint64_t word) { return (hash ^ word) * 0x100000001b3; }
+
+/* Hashes a value, where `kind` is the letter of its shape, so that equal values have equal
+   hashes. Floats, functions, ranges and maps aren't keys, so they're never hashed. */
+static uint64_t hash(rf_value value, char kind) {
+    uint64_t result = 0xcbf29ce484222325;
+    rf_object *o = object(value);
+    if (kind != 'o' || !o) {
+        return mix(result, value);
+    }
+    result = mix(mix(result, o->kind), o->tag);
+    if (o->kind == RF_STRING) {
+        for (int64_t i = 0; i < o->len; i++) {
+            result = mix(result, (unsigned char)bytes(o)[i]);
+        }
+        return result;
+    }
+    for (int64_t i = 0; i < o->len; i++) {
+        result = mix(result, hash(o->slots[i], o->kind == RF_ARRAY ? o->shape[0] : o->shape[i]));
+    }
+    return result;
+}
+
+static rf_value *table(rf_object *map) { return map->slots + 2 * map->len; }
+
+/* Returns the index of the entry of a map whose key is `key`, or -1, setting `slot` to where the
+   entry is in the table, or where it would go. The table is never full, so there's always an
+   empty slot to stop at. */
+static int64_t lookup(rf_object *map, rf_value key, uint64_t *slot) {
+    if (map->tag == 0) {
+        return -1;
+    }
+    uint64_t mask = map->tag - 1;
+    for (uint64_t i = hash(key, map->shape[0]) & mask;; i = (i + 1) & mask) {
+        /* Each slot holds 1 more than the index of its entry, or 0 when it's empty */
+        rf_value entry = table(map)[i];
+        if (entry == 0 || rf_equal(map->slots[2 * (entry - 1)], key, map->shape[0])) {
+            *slot = i;
+            return entry - 1;
+        }
+    }
+}
+
+/* Allocates a map with room for `len` entries, whose table is at most half full so that a key
+   takes few steps to find. */
+static rf_object *new_map(int64_t len, const char *shape) {
+    int64_t capacity = 8;
+    while (capacity < 2 * len) {
+        capacity *= 2;
+    }
+    size_t size = (2 * len + capacity) * sizeof(rf_value);
+    return allocate(RF_MAP, capacity, len, size, shape);
+}
+
+/* Fills in the table of a map whose entries are in place. */
+static rf_value finish_map(rf_object *map) {
+    for (int64_t i = 0; i < map->len; i++) {
+        uint64_t slot;
+        lookup(map, map->slots[2 * i], &slot);
+        table(map)[slot] = i + 1;
+    }
+    return (rf_value)(intptr_t)map;
+}
+
+int64_t rf_map_find(rf_value map, rf_value key) {
+    uint64_t slot;
+    return lookup(object(map), key, &slot);
+}
+
+/* A copy of a map with the value of a key set, and an entry for the key added to its end when it
+   doesn't have one. */
+rf_value rf_map_insert(rf_value map, rf_value key, rf_value value) {
+    rf_object *m = object(map);
+    uint64_t slot;
+    int64_t index = lookup(m, key, &slot);
+    if (index >= 0) {
+        size_t size = (2 * m->len + m->tag) * sizeof(rf_value);
+        rf_object *result = allocate(RF_MAP, m->tag, m->len, size, m->shape);
+        memcpy(result->slots, m->slots, size);
+        result->slots[2 * index + 1] = value;
+        return (rf_value)(intptr_t)result;
+    }
+    rf_object *result = new_map(m->len + 1, m->shape);
+    memcpy(result->slots, m->slots, 2 * m->len * sizeof(rf_value));
+    result->slots[2 * m->len] = key;
+    result->slots[2 * m->len + 1] = value;
+    return finish_map(result);
+}
+
+/* A copy of a map without an entry, keeping the order of the others. */
+rf_value rf_map_remove(rf_value map, int64_t index) {
+    rf_object *m = object(map);
+    check_index(m, index);
+    rf_object *result = new_map(m->len - 1, m->shape);
+    memcpy(result->slots, m->slots, 2 * index * sizeof(rf_value));
+    memcpy(result->slots + 2 * index, m->slots + 2 * (index + 1),
+           2 * (m->len - index - 1) * sizeof(rf_value));
+    return finish_map(result);
+}
+
+rf_value rf_map_key(rf_value map, int64_t index) {
+    check_index(object(map), index);
+    return object(map)->slots[2 * index];
+}
+
+rf_value rf_map_value(rf_value map, int64_t index) {
+    check_index(object(map), index);
+    return object(map)->slots[2 * index + 1];
+}
+
+/* Maps are equal when they have the same entries, in any order. */
+static int64_t maps_equal(rf_object *a, rf_object *b) {
+    if (a->len != b->len) {
+        return 0;
+    }
+    for (int64_t i = 0; i < a->len; i++) {
+        uint64_t slot;
+        int64_t j = lookup(b, a->slots[2 * i], &slot);
+        if (j < 0 || !rf_equal(a->slots[2 * i + 1], b->slots[2 * j + 1], a->shape[1])) {
+            return 0;
+        }
+    }
+    return 1;
+}
@@ -0,0 +1,624 @@
+//! Contains the abstract syntax tree produced by parsing a Ruffle source file.
+
+use serde::{Deserialize, Serialize};
+
+use crate::lexer::Span;
+
+/// A parsed source file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Program {
+    pub items: Vec<Item>,
+}
+
+/// An identifier with its span in the source code.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A `::` separated path, such as `Error::OhNo`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Path {
+    pub segments: Vec<Ident>,
+    pub span: Span,
+}
+
+/// A top level declaration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Item {
+    pub kind: ItemKind,
+    /// Whether the item is declared with `pub`, making it visible outside of its module.
+    pub public: bool,
+    pub span: Span,
+    /// The doc comments written before the item.
+    pub docs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ItemKind {
+    /// `const NAME: Type = value;`
+    Const(GlobalDecl),
+    /// `static NAME: Type = value;`
+    Static(GlobalDecl),
+    /// `fn name(params) -> Type { ... }`
+    Fn(FunctionDecl),
+    /// `extern fn name(params) -> Type;`
+    Extern(ExternDecl),
+    /// `struct Name { field: Type, ... }`
+    Struct(StructDecl),
+    /// `enum Name { Variant, ... }`
+    Enum(EnumDecl),
+    /// `trait Name { fn method(self); ... }`
+    Trait(TraitDecl),
+    /// `impl Trait for Type { ... }` or `impl Type { ... }`
+    Impl(ImplDecl),
+    /// `mod name { ... }` or `mod name;`
+    Mod(ModDecl),
+    /// `use a::b::c;`
+    Use(Path),
+    /// An item that failed to parse.
+    Error,
+}
+
+/// A trait declaration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraitDecl {
+    pub name: Ident,
+    pub methods: Vec<TraitMethod>,
+}
+
+impl TraitDecl {
+    /// Returns the methods that implementors have to provide.
+    pub fn required(&self) -> impl Iterator<Item = &TraitMethod> {
+        self.methods.iter().filter(|method| method.body.is_none())
+    }
+
+    /// Returns the methods that have a default implementation.
+    pub fn provided(&self) -> impl Iterator<Item = &TraitMethod> {
+        self.methods.iter().filter(|method| method.body.is_some())
+    }
+}
+
+/// A method declared in a trait.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraitMethod {
+    pub name: Ident,
+    pub generics: Vec<GenericParam>,
+    pub params: Vec<Param>,
+    pub ret: Option<TypeExpr>,
+    /// The default implementation, `None` when the method is required.
+    pub body: Option<Block>,
+    pub span: Span,
+    pub docs: Vec<String>,
+}
+
+/// An `impl` block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImplDecl {
+    /// The implemented trait, `None` for methods on the type itself.
+    pub trait_ref: Option<Path>,
+    pub self_ty: TypeExpr,
+    /// The methods, which are all [`ItemKind::Fn`] items.
+    pub methods: Vec<Item>,
+}
+
+/// A module declaration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModDecl {
+    pub name: Ident,
+    /// The items declared inline, `None` when the module is in its own file.
+    pub items: Option<Vec<Item>>,
+}
+
+/// A function declaration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionDecl {
+    pub name: Ident,
+    pub generics: Vec<GenericParam>,
+    pub params: Vec<Param>,
+    /// The return type, `None` when the function returns nothing.
+    pub ret: Option<TypeExpr>,
+    pub body: Block,
+}
+
+/// A function declared with `extern`, which the host provides instead of the program.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternDecl {
+    pub name: Ident,
+    pub params: Vec<Param>,
+    /// The return type, `None` when the function returns nothing.
+    pub ret: Option<TypeExpr>,
+}
+
+/// A type parameter of a generic function, such as `T: Printable`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenericParam {
+    pub name: Ident,
+    /// The traits that the type given for the parameter has to implement.
+    pub bounds: Vec<Path>,
+    pub span: Span,
+}
+
+/// A struct declaration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StructDecl {
+    pub name: Ident,
+    pub fields: Vec<FieldDecl>,
+}
+
+/// A named field in a struct declaration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldDecl {
+    /// Whether the field is declared with `pub`, making it visible outside of the struct's module.
+    pub public: bool,
+    pub name: Ident,
+    pub ty: TypeExpr,
+    pub span: Span,
+}
+
+/// An enum declaration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnumDecl {
+    pub name: Ident,
+    pub variants: Vec<Variant>,
+}
+
+/// A variant of an enum.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Variant {
+    pub name: Ident,
+    pub kind: VariantKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum VariantKind {
+    /// `Empty`
+    Unit,
+    /// `Circle(float)`
+    Tuple(Vec<TypeExpr>),
+    /// `Rect { w: float, h: float }`
+    Struct(Vec<FieldDecl>),
+}
+
+/// A function parameter. A `self` parameter is stored as `self: Self`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Param {
+    pub pattern: Pattern,
+    pub ty: TypeExpr,
+    pub span: Span,
+}
+
+impl Param {
+    /// Returns whether this is a `self` or `mut self` parameter.
+    pub fn is_self(&self) -> bool {
+        matches!(
+            &self.pattern.kind,
+            PatternKind::Ident(ident) | PatternKind::Mut(ident) if ident.name == "self"
+        )
+    }
+}
+
+/// A `const` or `static` declaration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GlobalDecl {
+    pub name: Ident,
+    pub ty: Option<TypeExpr>,
+    pub value: Expr,
+}
+
+/// A statement inside a block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StmtKind {
+    /// `let pattern: Type = value;`
+    Let {
+        pattern: Pattern,
+        ty: Option<TypeExpr>,
+        value: Option<Expr>,
+    },
+    /// An expression followed by a `;`.
+    Expr(Expr),
+    /// `return value;`
+    Return(Option<Expr>),
+    /// `yield value;`, in a generator.
+    Yield(Expr),
+    /// `break;`
+    Break,
+    /// `continue;`
+    Continue,
+    /// An item declared inside a block.
+    Item(Item),
+    /// A statement that failed to parse.
+    Error,
+}
+
+/// A `{ ... }` block of statements.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    /// The final expression without a `;`, which gives the block its value.
+    pub tail: Option<Box<Expr>>,
+    pub span: Span,
+}
+
+/// An expression.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ExprKind {
+    Literal(Literal),
+    Path(Path),
+    /// An expression wrapped in parentheses.
+    Paren(Box<Expr>),
+    /// `(a, b)`, where `()` is the unit value.
+    Tuple(Vec<Expr>),
+    /// `[a, b, c]`
+    Array(Vec<Expr>),
+    /// A `{ ... }` block.
+    Block(Block),
+    /// `if cond { ... } else { ... }`
+    If {
+        cond: Box<Expr>,
+        then_branch: Block,
+        /// Either a [`ExprKind::Block`] or another [`ExprKind::If`].
+        else_branch: Option<Box<Expr>>,
+    },
+    /// `while cond { ... }`
+    While {
+        cond: Box<Expr>,
+        body: Block,
+    },
+    /// `loop { ... }`, which runs until it's left with `break` or `return`.
+    Loop(Block),
+    /// `for binding in iter { ... }`
+    For(ForLoop),
+    /// `|x, y: int| x + y`
+    Closure(Closure),
+    /// `match value { pattern => expr, ... }`
+    Match(MatchExpr),
+    /// `start..end` or `start..=end`, where either bound can be left out.
+    Range {
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+        inclusive: bool,
+    },
+    /// `callee(args)`
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    /// `receiver.method(args)`
+    MethodCall {
+        receiver: Box<Expr>,
+        method: Ident,
+        args: Vec<Expr>,
+    },
+    /// `base.field`
+    Field {
+        base: Box<Expr>,
+        field: Ident,
+    },
+    /// `base[index]`
+    Index {
+        base: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `expr?`, propagating a failure out of the enclosing function. It's the same as
+    /// `match expr { Some(value) => value, None => return None }` for an `Option`, and
+    /// `match expr { Ok(value) => value, Err(error) => return Err(error) }` for a `Result`.
+    Try(Box<Expr>),
+    /// `Name { field: value, ... }`
+    StructLit {
+        path: Path,
+        fields: Vec<FieldInit>,
+    },
+    /// `expr as Type`, converting between numbers and characters. An integer becomes another
+    /// integer type by keeping its low bits, wrapping around when it doesn't fit. A `float`
+    /// becomes an integer by rounding toward zero, saturating at the bounds of the type, with NaN
+    /// becoming 0. An integer becomes the nearest `float`. A `char` becomes its code point, and
+    /// an integer becomes the `char` with that code point, failing at run time when there isn't
+    /// one. `true` and `false` become 1 and 0.
+    Cast {
+        expr: Box<Expr>,
+        ty: TypeExpr,
+    },
+    /// `op expr`
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    /// `lhs op rhs`
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    /// `target = value`
+    Assign {
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+    /// `target op= value`
+    CompoundAssign {
+        op: BinaryOp,
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+}
+
+impl ExprKind {
+    /// Returns whether the expression ends in a block, which means it can be used as a statement
+    /// without a `;`.
+    pub fn is_block_like(&self) -> bool {
+        matches!(
+            self,
+            ExprKind::Block(_)
+                | ExprKind::If { .. }
+                | ExprKind::While { .. }
+                | ExprKind::Loop(_)
+                | ExprKind::For(_)
+                | ExprKind::Match(_)
+        )
+    }
+}
+
+/// A `for` loop over a range or collection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForLoop {
+    pub pattern: Pattern,
+    pub iter: Box<Expr>,
+    pub body: Block,
+}
+
+/// An anonymous function.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Closure {
+    pub params: Vec<ClosureParam>,
+    pub body: Box<Expr>,
+}
+
+/// A closure parameter, whose type can be left out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClosureParam {
+    pub pattern: Pattern,
+    pub ty: Option<TypeExpr>,
+    pub span: Span,
+}
+
+/// A `match` expression.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchExpr {
+    pub scrutinee: Box<Expr>,
+    pub arms: Vec<MatchArm>,
+}
+
+/// A `pattern => expr` arm of a `match` expression.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+    pub span: Span,
+}
+
+/// A pattern that a value is matched against.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PatternKind {
+    /// `_`, which matches anything.
+    Wildcard,
+    /// A literal value, such as `1` or `"hello"`.
+    Literal(Literal),
+    /// A name that's bound to the matched value.
+    Ident(Ident),
+    /// `mut name`, binding a variable that can be reassigned.
+    Mut(Ident),
+    /// `(a, b)`
+    Tuple(Vec<Pattern>),
+    /// A unit enum variant, such as `Shape::Empty`.
+    Path(Path),
+    /// `Shape::Circle(r)`
+    TupleVariant { path: Path, fields: Vec<Pattern> },
+    /// `Shape::Rect { w, h: 1.0 }`
+    StructVariant {
+        path: Path,
+        fields: Vec<FieldPattern>,
+    },
+}
+
+/// A field in a struct variant pattern. `Rect { w }` is shorthand for `Rect { w: w }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldPattern {
+    pub name: Ident,
+    pub pattern: Pattern,
+    pub span: Span,
+}
+
+/// A field in a struct literal. `Point { x }` is shorthand for `Point { x: x }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldInit {
+    pub name: Ident,
+    pub value: Expr,
+    pub span: Span,
+}
+
+/// A binary operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BinaryOp {
+    // Arithmetic
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+
+    // Comparison
+    Eq,
+    Ne,
+    TripleEq,
+    TripleNe,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+
+    // Logical
+    And,
+    Or,
+
+    // Bitwise
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+}
+
+impl BinaryOp {
+    /// Returns the operator as it's written in the source code.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::TripleEq => "===",
+            BinaryOp::TripleNe => "!==",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+        }
+    }
+
+    /// Returns whether the operator compares its operands, producing a `bool`.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq
+                | BinaryOp::Ne
+                | BinaryOp::TripleEq
+                | BinaryOp::TripleNe
+                | BinaryOp::Lt
+                | BinaryOp::Le
+                | BinaryOp::Gt
+                | BinaryOp::Ge
+        )
+    }
+}
+
+/// A prefix operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UnaryOp {
+    /// `-`
+    Neg,
+    /// `!`
+    Not,
+}
+
+impl UnaryOp {
+    /// Returns the operator as it's written in the source code.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
+        }
+    }
+}
+
+/// A literal value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Literal {
+    Integer(u64),
+    /// An integer literal too large to store, kept as written.
+    OversizedInteger(String),
+    Float(f64),
+    String(String),
+    Char(char),
+    Bool(bool),
+}
+
+/// A type annotation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TypeExpr {
+    pub kind: TypeExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TypeExprKind {
+    /// A named type, such as `i32` or `Error`.
+    Path(Path),
+    /// A generic type applied to arguments, such as `Vec<int>`.
+    Generic { path: Path, args: Vec<TypeExpr> },
+    /// `(int, string)`, where `()` is the unit type.
+    Tuple(Vec<TypeExpr>),
+    /// `fn(int) -> int`
+    Fn {
+        params: Vec<TypeExpr>,
+        /// The return type, `None` when the function returns nothing.
+        ret: Option<Box<TypeExpr>>,
+    },
+    /// `[int]`, or `[int; 4]` with a fixed length.
+    Array {
+        elem: Box<TypeExpr>,
+        len: Option<Box<Expr>>,
+    },
+    /// `!`, the return type of a function that never returns.
+    Never,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    #[test]
+    fn test_json_round_trip() {
+        let source = "enum E { A(int), B { x: float } }\nfn f(p: (int, [u8; 2])) { let (a, _) = p; match a { E::A(x) => x, _ => 'c' } }";
+        let program = parse_source(source).unwrap();
+
+        let json = serde_json::to_string(&program).unwrap();
+        let deserialized: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, program);
+    }
+
+    #[test]
+    fn test_json_shape() {
+        let program = parse_source("const X: int = 1;").unwrap();
+        let json = serde_json::to_value(&program).unwrap();
+
+        let item = &json["items"][0];
+        assert_eq!(item["span"], serde_json::json!({ "start": 0, "end": 17 }));
+        assert_eq!(item["kind"]["Const"]["name"]["name"], "X");
+        assert_eq!(
+            item["kind"]["Const"]["value"]["kind"],
+            serde_json::json!({ "Literal": { "Integer": 1 } })
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Checks a program once it's loaded: resolves its names, checks its types and then its control
+//! flow, and lowers it to HIR if none of that found an error. Every command that compiles or runs
+//! a file starts here, so they all find the same errors and warnings.
+
+use crate::{
+    diagnostic::{Diagnostic, Severity},
+    flow, hir,
+    loader::LoadedProgram,
+    resolve::{self, LintOptions, Resolution},
+    typeck,
+};
+
+/// Checks a program, returning its errors and warnings, and its names and HIR unless one of them
+/// is an error.
+pub fn check(loaded: &LoadedProgram) -> (Vec<Diagnostic>, Option<(Resolution, hir::Program)>) {
+    check_with(loaded, LintOptions::default())
+}
+
+/// Checks a program like [`check`], also warning about the opt-in lints enabled in `options`.
+pub fn check_with(
+    loaded: &LoadedProgram,
+    options: LintOptions,
+) -> (Vec<Diagnostic>, Option<(Resolution, hir::Program)>) {
+    let program = &loaded.program;
+    let res = resolve::resolve_with(program, options);
+    let types = typeck::check(program, &res);
+    let flow = flow::check(program, &res, &types);
+    let mut diagnostics = loaded.errors.clone();
+    diagnostics.extend(res.errors.iter().map(|error| error.to_diagnostic()));
+    diagnostics.extend(types.errors.iter().map(|error| error.to_diagnostic()));
+    diagnostics.extend(flow.errors.iter().map(|error| error.to_diagnostic()));
+    diagnostics.extend(res.lints.iter().map(|lint| lint.to_diagnostic()));
+    diagnostics.extend(flow.lints.iter().map(|lint| lint.to_diagnostic()));
+    let is_error = |diagnostic: &Diagnostic| diagnostic.severity == Severity::Error;
+    if diagnostics.iter().any(is_error) {
+        return (diagnostics, None);
+    }
+    let lowered = hir::lower(program, &res, &types);
+    (diagnostics, Some((res, lowered)))
+}
+
+/// Returns an error for each generator, for the backends that can't run them. Only the VM keeps
+/// a generator's frame alive between its `yield`s.
+pub fn generators(lowered: &hir::Program) -> Vec<Diagnostic> {
+    (lowered.fns.iter().filter(|f| f.generator))
+        .map(|f| {
+            let message = format!("`{}` is a generator, which only the VM runs", f.name);
+            Diagnostic::error(message, f.span.clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::load_source;
+
+    #[test]
+    fn test_check() {
+        let (diagnostics, checked) = check(&load_source("main.rf", "fn main() { return; 1; }"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        let (_, lowered) = checked.unwrap();
+        assert_eq!(lowered.fns.len(), 1);
+
+        let (diagnostics, checked) = check(&load_source("main.rf", "fn main() { 1 + true; }"));
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert!(checked.is_none());
+
+        let source = "fn upto(n: int) Gen<int> { yield n; }\nfn main() { upto(1); }";
+        let (_, checked) = check(&load_source("main.rf", source));
+        let (_, lowered) = checked.unwrap();
+        let errors = generators(&lowered);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "`upto` is a generator, which only the VM runs"
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! Lists the x86-64 machine code the Cranelift backend compiles a program to as assembly, in AT&T
+//! or Intel syntax, so that it can be read without a disassembler.
+//!
+//! The code is compiled as it would be for an object file, and then disassembled. Each function
+//! is listed under its symbol with the name it has in the program, and its instructions under
+//! comments with the lines of the source they were compiled from. The operands that are only
+//! filled in by the linker are shown as the symbols they refer to, the targets of jumps as labels,
+//! and the constants Cranelift puts after a function's code as bytes.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    ops::Range,
+};
+
+use cranelift_codegen::binemit::Reloc;
+use cranelift_module::{
+    default_libcall_names, DataId, FuncId as ClFuncId, Linkage, Module, ModuleDeclarations,
+    ModuleError, ModuleRelocTarget,
+};
+use iced_x86::{
+    Decoder, DecoderOptions, FlowControl, Formatter, GasFormatter, Instruction, IntelFormatter,
+    SymbolResolver, SymbolResult,
+};
+
+use super::{
+    cranelift::{translate, Code},
+    object,
+};
+use crate::{
+    mir::{opt::OptLevel, Program},
+    source_map::SourceMap,
+};
+
+/// The syntax of a listing, chosen with `--asm-syntax=<syntax>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// The syntax of the GNU assembler, with the source before the destination.
+    Att,
+    Intel,
+}
+
+/// Compiles a program for x86-64 and lists its functions in the order they're defined, with the
+/// entry last. The program's spans are offsets into the source map's text.
+pub fn emit_asm(
+    program: &Program,
+    level: OptLevel,
+    syntax: Syntax,
+    source_map: &SourceMap,
+) -> Result<String, Box<ModuleError>> {
+    let isa = cranelift_codegen::isa::lookup_by_name("x86_64-unknown-linux-gnu").unwrap();
+    let mut module = object::module(isa, level)?;
+    let translated = translate(&mut module, program)?;
+
+    let names: HashMap<ClFuncId, &str> = (translated.fns.iter())
+        .zip(&program.fns)
+        .map(|(&id, func)| (id, func.name.as_str()))
+        .collect();
+    let mut out = String::from("\t.text\n");
+    if syntax == Syntax::Intel {
+        out.push_str("\t.intel_syntax noprefix\n");
+    }
+    for code in &translated.code {
+        let decls = module.declarations();
+        let decl = decls.get_function_decl(code.func);
+        let symbol = decl.linkage_name(code.func);
+        out.push('\n');
+        match names.get(&code.func) {
+            Some(name) => writeln!(out, "# {} {}", symbol, name).unwrap(),
+            None => writeln!(out, "# {}", symbol).unwrap(),
+        }
+        if decl.linkage == Linkage::Export {
+            writeln!(out, "\t.globl {}", symbol).unwrap();
+        }
+        writeln!(out, "{}:", symbol).unwrap();
+        list(&mut out, code, &symbol, decls, syntax, source_map);
+    }
+    Ok(out)
+}
+
+/// Lists the code of a function, then its constants.
+fn list(
+    out: &mut String,
+    code: &Code,
+    symbol: &str,
+    decls: &ModuleDeclarations,
+    syntax: Syntax,
+    source_map: &SourceMap,
+) {
+    let instructions: Vec<_> = Decoder::with_ip(64, &code.bytes, 0, DecoderOptions::NONE)
+        .into_iter()
+        .collect();
+    let relocs: Vec<_> = (code.relocs.iter())
+        .map(|reloc| {
+            let name = match reloc.name {
+                ModuleRelocTarget::User { namespace: 0, .. } => {
+                    let id = ClFuncId::from_name(&reloc.name);
+                    decls.get_function_decl(id).linkage_name(id).into_owned()
+                }
+                ModuleRelocTarget::User { .. } => {
+                    let id = DataId::from_name(&reloc.name);
+                    decls.get_data_decl(id).linkage_name(id).into_owned()
+                }
+                ModuleRelocTarget::LibCall(libcall) => default_libcall_names()(libcall),
+                ModuleRelocTarget::KnownSymbol(symbol) => format!("{:?}", symbol),
+                ModuleRelocTarget::FunctionOffset(_, offset) => label(symbol, offset.into()),
+            };
+            // Cranelift loads the addresses of symbols from the global offset table when its code
+            // can be put anywhere
+            match reloc.kind {
+                Reloc::X86GOTPCRel4 => (reloc.offset.into(), name + "@GOTPCREL"),
+                Reloc::X86CallPLTRel4 => (reloc.offset.into(), name + "@PLT"),
+                _ => (reloc.offset.into(), name),
+            }
+        })
+        .collect();
+    let relocated = |inst: &Instruction| relocs.iter().any(|(offset, _)| spans(inst, *offset));
+    // The constants start at the first address the code reads from itself
+    let end = code.bytes.len() as u64;
+    let reads = |inst: &&Instruction| {
+        inst.is_ip_rel_memory_operand() && !relocated(inst) && inst.ip_rel_memory_address() < end
+    };
+    let constants = (instructions.iter())
+        .filter(reads)
+        .map(|inst| inst.ip_rel_memory_address())
+        .min()
+        .unwrap_or(end);
+    // They're aligned with zeros, which no code ends with, since it ends by returning, trapping,
+    // or jumping back
+    let padding = (code.bytes[..constants as usize].iter().rev())
+        .take_while(|&&byte| byte == 0)
+        .count();
+    let code_end = constants - padding as u64;
+    let instructions: Vec<_> = (instructions.into_iter())
+        .take_while(|inst| inst.ip() < code_end)
+        .collect();
+
+    let mut labels = BTreeMap::new();
+    for inst in &instructions {
+        let jumps = matches!(
+            inst.flow_control(),
+            FlowControl::UnconditionalBranch | FlowControl::ConditionalBranch
+        );
+        if jumps && !relocated(inst) {
+            let target = inst.near_branch_target();
+            labels.insert(target, label(symbol, target));
+        }
+        if reads(&inst) {
+            let target = inst.ip_rel_memory_address();
+            labels.insert(target, label(symbol, target));
+        }
+    }
+
+    let symbols = Box::new(Symbols {
+        relocs,
+        labels: labels.clone(),
+    });
+    let mut formatter: Box<dyn Formatter> = match syntax {
+        Syntax::Att => Box::new(GasFormatter::with_options(Some(symbols), None)),
+        Syntax::Intel => Box::new(IntelFormatter::with_options(Some(symbols), None)),
+    };
+    let options = formatter.options_mut();
+    options.set_rip_relative_addresses(true);
+    // As the GNU assembler writes them in either syntax
+    options.set_hex_prefix("0x");
+    options.set_hex_suffix("");
+    let mut text = String::new();
+    // The line the last instructions were compiled from
+    let mut line = None;
+    for inst in &instructions {
+        if let Some(label) = labels.get(&inst.ip()) {
+            writeln!(out, "{}:", label).unwrap();
+        }
+        let srcloc = (code.srclocs.iter()).find(|(range, _)| range.contains(&(inst.ip() as u32)));
+        let location = srcloc.and_then(|(_, offset)| source_map.location(*offset as usize));
+        if let Some(location) = location {
+            if line != Some((location.path, location.row)) {
+                line = Some((location.path, location.row));
+                let file = source_map.file_at(srcloc.unwrap().1 as usize).unwrap();
+                let text = source_map.source(file).lines().nth(location.row - 1);
+                let text = text.unwrap_or_default().trim();
+                writeln!(
+                    out,
+                    "\t# {}:{}: {}",
+                    location.path.display(),
+                    location.row,
+                    text
+                )
+                .unwrap();
+            }
+        }
+        text.clear();
+        formatter.format(inst, &mut text);
+        writeln!(out, "\t{}", text).unwrap();
+    }
+    let mut offset = code_end;
+    for (&start, label) in labels.range(constants..) {
+        bytes(out, &code.bytes, offset..start);
+        writeln!(out, "{}:", label).unwrap();
+        offset = start;
+    }
+    bytes(out, &code.bytes, offset..end);
+}
+
+fn label(symbol: &str, offset: u64) -> String {
+    format!(".L{}_{:x}", symbol, offset)
+}
+
+/// Whether the bytes of an instruction include an offset into its function.
+fn spans(inst: &Instruction, offset: u64) -> bool {
+    (inst.ip()..inst.next_ip()).contains(&offset)
+}
+
+fn bytes(out: &mut String, bytes: &[u8], range: Range<u64>) {
+    let bytes = &bytes[range.start as usize..range.end as usize];
+    for line in bytes.chunks(8) {
+        let line: Vec<_> = line.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+        writeln!(out, "\t.byte {}", line.join(", ")).unwrap();
+    }
+}
+
+/// Names the addresses in the operands of a function's instructions.
+struct Symbols {
+    /// The symbol each relocation refers to, by its offset into the function.
+    relocs: Vec<(u64, String)>,
+    labels: BTreeMap<u64, String>,
+}
+
+impl SymbolResolver for Symbols {
+    fn symbol(
+        &mut self,
+        inst: &Instruction,
+        _operand: u32,
+        _inst_operand: Option<u32>,
+        address: u64,
+        _address_size: u32,
+    ) -> Option<SymbolResult<'_>> {
+        // The address in a relocated operand is a placeholder until the function is linked
+        let reloc = self.relocs.iter().find(|(offset, _)| spans(inst, *offset));
+        if let Some((_, name)) = reloc {
+            return Some(SymbolResult::with_str(address, name));
+        }
+        (self.labels.get(&address)).map(|label| SymbolResult::with_str(address, label))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+
+    #[test]
+    fn test_emit_asm() {
+        let source = "
+fn double(n: u8) u8 { n * 2 }
+fn main() { double(3); }";
+        let program = built(source);
+        let mut source_map = SourceMap::new();
+        source_map.add_file("main.rf", source);
+        assert_eq!(
+            emit_asm(&program, OptLevel::O2, Syntax::Att, &source_map).unwrap(),
+            "	.text
+
+# fn0 double
+fn0:
+	push %rbp
+	mov %rsp,%rbp
+	mov %rdi,%rax
+	# main.rf:2: fn double(n: u8) u8 { n * 2 }
+	mulb .Lfn0_38(%rip)
+	seto %r10b
+	test %r10b,%r10b
+	jne .Lfn0_23
+	movzbq %al,%rax
+	mov %rbp,%rsp
+	pop %rbp
+	ret
+.Lfn0_23:
+	mov .Ldata0@GOTPCREL(%rip),%rdi
+	mov rf_fail@GOTPCREL(%rip),%rsi
+	call *%rsi
+	ud2
+	.byte 0x00, 0x00, 0x00
+.Lfn0_38:
+	.byte 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00
+
+# fn1 main
+fn1:
+	push %rbp
+	mov %rsp,%rbp
+	# main.rf:3: fn main() { double(3); }
+	mov $3,%edi
+	call fn0
+	xor %rax,%rax
+	mov %rbp,%rsp
+	pop %rbp
+	ret
+
+# ruffle_main
+	.globl ruffle_main
+ruffle_main:
+	push %rbp
+	mov %rsp,%rbp
+	call fn1
+	xor %rax,%rax
+	mov %rbp,%rsp
+	pop %rbp
+	ret
+"
+        );
+        let intel = emit_asm(&program, OptLevel::O2, Syntax::Intel, &source_map).unwrap();
+        assert!(
+            intel.contains("\tmov rdi,[rip+.Ldata0@GOTPCREL]\n"),
+            "{}",
+            intel
+        );
+
+        // Each run of instructions is under the line it was compiled from
+        let source = "fn mix(a: int, b: int) int {
+    let c = a * b;
+    c + a
+}";
+        let mut source_map = SourceMap::new();
+        source_map.add_file("mix.rf", source);
+        let listing = emit_asm(&built(source), OptLevel::O2, Syntax::Att, &source_map).unwrap();
+        let lines: Vec<_> = (listing.lines())
+            .filter(|line| line.starts_with("\t# "))
+            .collect();
+        assert_eq!(
+            lines,
+            ["\t# mix.rf:2: let c = a * b;", "\t# mix.rf:3: c + a"],
+            "{}",
+            listing
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Building executables with the tools on the system, for the backends that need them.
+
+use std::{
+    fmt::Display,
+    fs, io,
+    path::Path,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use cranelift_module::ModuleError;
+
+/// A way building an executable can fail.
+#[derive(Debug)]
+pub enum BuildError {
+    /// Cranelift can't compile for the machine the compiler is running on.
+    UnsupportedHost(String),
+    Module(Box<ModuleError>),
+    /// A tool that isn't installed, or isn't on the `PATH`.
+    ToolNotFound(String),
+    /// A tool that failed, with what it printed.
+    ToolFailed {
+        tool: String,
+        output: String,
+    },
+    Io(io::Error),
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::UnsupportedHost(message) => {
+                write!(f, "can't compile for this machine: {}", message)
+            }
+            BuildError::Module(error) => write!(f, "{}", error),
+            BuildError::ToolNotFound(tool) => write!(f, "`{}` wasn't found", tool),
+            BuildError::ToolFailed { tool, output } => {
+                write!(f, "`{}` failed:\n{}", tool, output)
+            }
+            BuildError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<Box<ModuleError>> for BuildError {
+    fn from(error: Box<ModuleError>) -> BuildError {
+        BuildError::Module(error)
+    }
+}
+
+impl From<io::Error> for BuildError {
+    fn from(error: io::Error) -> BuildError {
+        BuildError::Io(error)
+    }
+}
+
+pub fn run(tool: &str, args: &[&str]) -> Result<(), BuildError> {
+    let output = Command::new(tool).args(args).output().map_err(|error| {
+        if error.kind() == io::ErrorKind::NotFound {
+            BuildError::ToolNotFound(tool.to_string())
+        } else {
+            BuildError::Io(error)
+        }
+    })?;
+    if !output.status.success() {
+        return Err(BuildError::ToolFailed {
+            tool: tool.to_string(),
+            output: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// The system's C compiler, which is `$CC`, or `cc` by default.
+pub fn cc() -> String {
+    std::env::var("CC").unwrap_or_else(|_| "cc".to_string())
+}
+
+/// Compiles and links C sources and objects into an executable with the system's C compiler. It's
+/// linked with the C math library, which the runtime library uses, and with `libs`, which have
+/// the host functions the program declares with `extern fn`.
+pub fn link(
+    flags: &[&str],
+    inputs: &[&Path],
+    libs: &[String],
+    output: &Path,
+) -> Result<(), BuildError> {
+    let mut args: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+    args.extend(
+        inputs
+            .iter()
+            .map(|input| input.to_string_lossy().into_owned()),
+    );
+    args.extend(libs.iter().map(|lib| format!("-l{}", lib)));
+    args.extend(["-lm".to_string(), "-o".to_string()]);
+    args.push(output.to_string_lossy().into_owned());
+    run(&cc(), &args.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+/// Calls `build` with a new temporary directory, which is removed afterwards.
+pub fn in_temp_dir<T>(build: impl FnOnce(&Path) -> Result<T, BuildError>) -> Result<T, BuildError> {
+    // Builds running at the same time each get a directory of their own
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "ruffle-{}-{}",
+        std::process::id(),
+        BUILDS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir)?;
+    let result = build(&dir);
+    fs::remove_dir_all(&dir)?;
+    result
+}
@@ -0,0 +1,695 @@
+//! Lowers the MIR of a program to bytecode. Each value of a function is kept in a local slot,
+//! which it shares with values that aren't live at the same time, with the parameters first, and
+//! each instruction loads its operands, does its work, and stores its value. A jump to a block
+//! with parameters pushes the values it passes, then stores them in the parameters' slots, so that
+//! jumping back to a loop's header can pass its parameters to each other. A call whose value the
+//! block returns is a `tail_call`, which returns it without growing the stack.
+//!
+//! The code where each variable of the source is in a slot comes from the intervals the slots were
+//! allocated over, so that a debugger can show the variables of a call that's running.
+
+use std::collections::HashMap;
+
+use super::*;
+use crate::{
+    mir::{self, BlockCall, BlockId, Callee, InstKind, Intrinsic, Terminator, Value},
+    resolve::DefId,
+};
+
+/// Converts an index into the MIR to an operand.
+///
+/// # Panics
+///
+/// Panics if it doesn't fit, since the format can't refer to it.
+fn operand<T: TryFrom<usize>>(index: usize) -> T {
+    T::try_from(index)
+        .ok()
+        .expect("program is too big for bytecode")
+}
+
+pub fn emit(program: &mir::Program) -> Module {
+    let mut variants = Vec::new();
+    let mut variant_ids = HashMap::new();
+    for adt in &program.adts {
+        for (discriminant, variant) in adt.variants.iter().enumerate() {
+            variant_ids.insert(variant.def, operand(variants.len()));
+            variants.push(Variant {
+                name: variant.name.clone(),
+                discriminant: operand(discriminant),
+                fields: operand(variant.fields.len()),
+            });
+        }
+    }
+
+    let mut emitter = Emitter {
+        constants: Vec::new(),
+        constant_ids: HashMap::new(),
+        variant_ids,
+    };
+    let functions = program
+        .fns
+        .iter()
+        .map(|func| emitter.function(func))
+        .collect();
+    let globals = program
+        .globals
+        .iter()
+        .map(|global| Global {
+            name: global.name.clone(),
+            is_static: global.is_static,
+            init: operand(global.init.0),
+        })
+        .collect();
+    let main = program
+        .fns
+        .iter()
+        .position(|func| func.def.is_some() && func.name == "main")
+        .map(operand);
+    Module {
+        constants: emitter.constants,
+        functions,
+        globals,
+        variants,
+        main,
+    }
+}
+
+/// What's shared by the functions of a module.
+struct Emitter {
+    constants: Vec<Constant>,
+    constant_ids: HashMap<Constant, u16>,
+    variant_ids: HashMap<DefId, u16>,
+}
+
+impl Emitter {
+    fn constant(&mut self, constant: &Constant) -> u16 {
+        if let Some(&id) = self.constant_ids.get(constant) {
+            return id;
+        }
+        let id = operand(self.constants.len());
+        self.constants.push(constant.clone());
+        self.constant_ids.insert(constant.clone(), id);
+        id
+    }
+
+    fn function(&mut self, func: &mir::Function) -> Function {
+        let intervals = slots::intervals(func);
+        let (slots, locals) = slots::allocate(func, &intervals);
+        let slots = slots.into_iter().map(operand).collect();
+        // A call stores the parameters
+        let mut stored = vec![false; func.values.len()];
+        for param in func.params() {
+            stored[param.0] = true;
+        }
+
+        let mut used = vec![false; func.values.len()];
+        for block in &func.blocks {
+            for inst in &block.insts {
+                for value in inst.kind.operands() {
+                    used[value.0] = true;
+                }
+            }
+            let mut terminator = block.terminator.clone();
+            terminator.operands_mut(|value| used[value.0] = true);
+        }
+
+        let mut emitter = FnEmitter {
+            module: self,
+            func,
+            slots,
+            used,
+            stored,
+            in_place: in_place::in_place(func),
+            current: BlockId::ENTRY,
+            code: Vec::new(),
+            spans: Vec::new(),
+            positions: Vec::new(),
+            offsets: vec![0; func.blocks.len()],
+            patches: Vec::new(),
+        };
+        if func.blocks.is_empty() {
+            // Only declared, so it's never called
+            emitter.op(Op::Unreachable);
+        }
+        for id in func.block_ids() {
+            emitter.block(id);
+        }
+        for (patch, block) in std::mem::take(&mut emitter.patches) {
+            let offset = emitter.offsets[block.0];
+            emitter.patch(patch, offset);
+        }
+        let vars = emitter.vars(&intervals);
+
+        Function {
+            name: func.name.clone(),
+            params: operand(func.params().len()),
+            locals: operand(locals),
+            code: emitter.code,
+            spans: emitter.spans,
+            vars,
+        }
+    }
+}
+
+struct FnEmitter<'a> {
+    module: &'a mut Emitter,
+    func: &'a mir::Function,
+    /// The local slot of each value.
+    slots: Vec<u16>,
+    /// Whether each value is used, since the value of one that isn't can be dropped.
+    used: Vec<bool>,
+    /// Whether each value is stored in its slot, rather than dropped or never pushed.
+    stored: Vec<bool>,
+    /// Whether each value is given by an update that changes its operand in place.
+    in_place: Vec<bool>,
+    /// The block being emitted. Blocks are emitted in order.
+    current: BlockId,
+    code: Vec<u8>,
+    spans: Vec<(u32, Span)>,
+    /// The offset of the code at each position that the slots were allocated over.
+    positions: Vec<u32>,
+    /// The offset of each block's code.
+    offsets: Vec<u32>,
+    /// The jumps to blocks, which are filled in once every block has its offset.
+    patches: Vec<(usize, BlockId)>,
+}
+
+impl FnEmitter<'_> {
+    fn offset(&self) -> u32 {
+        operand(self.code.len())
+    }
+
+    /// Returns the variables of the function, with the code where their values are in their
+    /// slots, from the end of the code of the position a value is stored at up to the end of the
+    /// code of the last position it's live at.
+    fn vars(&self, intervals: &[(usize, usize)]) -> Vec<Var> {
+        let end = |position: usize| {
+            let next = self.positions.get(position + 1);
+            next.copied().unwrap_or_else(|| self.offset())
+        };
+        let params = self.func.params();
+        let var = |var: &mir::Var| {
+            let values = var.values.iter().filter(|value| self.stored[value.0]);
+            let ranges = values.map(|&value| {
+                let (start, last) = intervals[value.0];
+                // A call stores its arguments before the function's code starts
+                let start = if params.contains(&value) {
+                    0
+                } else {
+                    end(start)
+                };
+                (start, end(last), self.slots[value.0])
+            });
+            Var {
+                name: var.name.clone(),
+                ranges: ranges.filter(|(start, end, _)| start < end).collect(),
+            }
+        };
+        self.func.vars.iter().map(var).collect()
+    }
+
+    fn op(&mut self, op: Op) {
+        op.encode(&mut self.code);
+    }
+
+    fn load(&mut self, value: Value) {
+        self.op(Op::Load(self.slots[value.0]));
+    }
+
+    fn load_all(&mut self, values: &[Value]) {
+        for &value in values {
+            self.load(value);
+        }
+    }
+
+    /// Emits a jump whose target is filled in later, returning where it is.
+    fn jump(&mut self, op: Op) -> usize {
+        let patch = self.code.len();
+        self.op(op);
+        patch
+    }
+
+    fn patch(&mut self, patch: usize, target: u32) {
+        // The target is the last operand of every jump
+        let end = Op::decode(&self.code, patch).unwrap().1;
+        self.code[end - 4..end].copy_from_slice(&target.to_le_bytes());
+    }
+
+    /// Jumps to a block, unless it's the block that comes next.
+    fn jump_to(&mut self, block: BlockId) {
+        if block.0 != self.current.0 + 1 {
+            let patch = self.jump(Op::Jump(0));
+            self.patches.push((patch, block));
+        }
+    }
+
+    /// Passes the values of a jump to its block's parameters.
+    fn pass_args(&mut self, call: &BlockCall) {
+        self.load_all(&call.args);
+        let params = &self.func.block(call.block).params;
+        for &param in params.iter().rev() {
+            self.op(Op::Store(self.slots[param.0]));
+            self.stored[param.0] = true;
+        }
+    }
+
+    fn block(&mut self, id: BlockId) {
+        self.current = id;
+        self.offsets[id.0] = self.offset();
+        self.positions.push(self.offset());
+        let block = self.func.block(id);
+        for (i, inst) in block.insts.iter().enumerate() {
+            self.positions.push(self.offset());
+            if self.spans.last().map(|(_, span)| span) != Some(&inst.span) {
+                self.spans.push((self.offset(), inst.span.clone()));
+            }
+            if let Some((Callee::Direct(func), args)) = block.tail_call() {
+                if i == block.insts.len() - 1 {
+                    self.load_all(args);
+                    self.op(Op::TailCall {
+                        func: operand(func.0),
+                        args: operand(args.len()),
+                    });
+                    // There's no terminator, and nothing after it
+                    let offset = self.offset();
+                    self.positions.extend([offset, offset]);
+                    return;
+                }
+            }
+            if self.inst(inst.value, &inst.kind) {
+                let op = if self.used[inst.value.0] {
+                    self.stored[inst.value.0] = true;
+                    Op::Store(self.slots[inst.value.0])
+                } else {
+                    Op::Pop
+                };
+                self.op(op);
+            }
+        }
+        self.positions.push(self.offset());
+        match &block.terminator {
+            Terminator::Jump(target) => {
+                self.pass_args(target);
+                self.jump_to(target.block);
+            }
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                self.load(*cond);
+                if else_block.args.is_empty() {
+                    let patch = self.jump(Op::JumpIfFalse(0));
+                    self.patches.push((patch, else_block.block));
+                    self.pass_args(then_block);
+                    self.jump_to(then_block.block);
+                } else {
+                    // The else branch passes its values before it jumps to its block
+                    let patch = self.jump(Op::JumpIfFalse(0));
+                    self.pass_args(then_block);
+                    let then_patch = self.jump(Op::Jump(0));
+                    self.patches.push((then_patch, then_block.block));
+                    let offset = self.offset();
+                    self.patch(patch, offset);
+                    self.pass_args(else_block);
+                    self.jump_to(else_block.block);
+                }
+            }
+            Terminator::Return(value) => {
+                self.load(*value);
+                self.op(Op::Return);
+            }
+            Terminator::Unreachable => self.op(Op::Unreachable),
+        }
+        self.positions.push(self.offset());
+    }
+
+    /// Emits an instruction, returning whether it pushes its value. Instructions done for their
+    /// effect don't, and their unit value stays in its slot from the start of the call.
+    fn inst(&mut self, value: Value, kind: &InstKind) -> bool {
+        let kind_of = |value: Value| Kind::of(self.func.value_ty(value));
+        let op = match kind {
+            InstKind::Const(constant) => Op::Const(self.module.constant(constant)),
+            InstKind::Undef => return false,
+            InstKind::Unary { op, value } => {
+                self.load(*value);
+                Op::Unary {
+                    op: *op,
+                    kind: kind_of(*value),
+                }
+            }
+            InstKind::Binary { op, lhs, rhs } => {
+                self.load_all(&[*lhs, *rhs]);
+                Op::Binary {
+                    op: *op,
+                    kind: kind_of(*lhs),
+                }
+            }
+            InstKind::Cast(inner) => {
+                self.load(*inner);
+                Op::Cast {
+                    from: kind_of(*inner),
+                    to: kind_of(value),
+                }
+            }
+            InstKind::Tuple(elems) => {
+                self.load_all(elems);
+                Op::Tuple(operand(elems.len()))
+            }
+            InstKind::Array(elems) => {
+                self.load_all(elems);
+                Op::Array(operand(elems.len()))
+            }
+            InstKind::Construct { def, fields } => {
+                self.load_all(fields);
+                Op::Construct {
+                    variant: self.module.variant_ids[def],
+                    fields: operand(fields.len()),
+                }
+            }
+            InstKind::Field { base, index } => {
+                self.load(*base);
+                Op::Field(operand(*index))
+            }
+            InstKind::SetField { base, index, value } => {
+                self.load_all(&[*base, *value]);
+                Op::SetField(operand(*index))
+            }
+            InstKind::Discriminant(base) => {
+                self.load(*base);
+                Op::Discriminant
+            }
+            InstKind::VariantField { base, def, index } => {
+                self.load(*base);
+                Op::VariantField {
+                    variant: self.module.variant_ids[def],
+                    index: operand(*index),
+                }
+            }
+            InstKind::Index { base, index } => {
+                self.load_all(&[*base, *index]);
+                Op::Index
+            }
+            InstKind::SetIndex { base, index, value } => {
+                self.load_all(&[*base, *index, *value]);
+                Op::SetIndex
+            }
+            InstKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let bounds: Vec<_> = start.iter().chain(end).copied().collect();
+                self.load_all(&bounds);
+                Op::Range {
+                    start: start.is_some(),
+                    end: end.is_some(),
+                    inclusive: *inclusive,
+                }
+            }
+            InstKind::Global(global) => Op::Global(operand(global.0)),
+            InstKind::SetGlobal { global, value } => {
+                self.load(*value);
+                self.op(Op::SetGlobal(operand(global.0)));
+                return false;
+            }
+            InstKind::FnRef(func) => Op::Function(operand(func.0)),
+            InstKind::Closure { func, captures } => {
+                self.load_all(captures);
+                Op::Closure {
+                    func: operand(func.0),
+                    captures: operand(captures.len()),
+                }
+            }
+            InstKind::Generator { func, args } => {
+                self.load_all(args);
+                Op::Generator {
+                    func: operand(func.0),
+                    args: operand(args.len()),
+                }
+            }
+            InstKind::Call { callee, args } => match callee {
+                Callee::Direct(func) => {
+                    self.load_all(args);
+                    Op::Call {
+                        func: operand(func.0),
+                        args: operand(args.len()),
+                    }
+                }
+                Callee::Indirect(callee) => {
+                    self.load(*callee);
+                    self.load_all(args);
+                    Op::CallIndirect(operand(args.len()))
+                }
+            },
+            InstKind::Extern { name, args } => {
+                self.load_all(args);
+                Op::Extern {
+                    name: self.module.constant(&Constant::String(name.clone())),
+                    args: operand(args.len()),
+                    ret: kind_of(value),
+                }
+            }
+            InstKind::Intrinsic { intrinsic, args } => {
+                self.load_all(args);
+                match intrinsic {
+                    Intrinsic::Len => Op::Len,
+                    Intrinsic::Chars => Op::Chars,
+                    Intrinsic::Panic => {
+                        self.op(Op::Panic);
+                        return false;
+                    }
+                    Intrinsic::Print => {
+                        self.op(Op::Print);
+                        return false;
+                    }
+                    Intrinsic::Println => {
+                        self.op(Op::Println);
+                        return false;
+                    }
+                    Intrinsic::Input => Op::Input,
+                    Intrinsic::StringLen => Op::StringLen,
+                    Intrinsic::CharAt => Op::CharAt,
+                    Intrinsic::Slice => Op::Slice,
+                    Intrinsic::Contains => Op::Contains,
+                    Intrinsic::Split => Op::Split,
+                    Intrinsic::ToUpper => Op::ToUpper,
+                    Intrinsic::ToLower => Op::ToLower,
+                    Intrinsic::Push => Op::ArrayPush {
+                        in_place: self.in_place[value.0],
+                    },
+                    Intrinsic::Pop => Op::ArrayPop {
+                        in_place: self.in_place[value.0],
+                    },
+                    Intrinsic::NewMap => Op::NewMap,
+                    Intrinsic::Insert => Op::MapInsert {
+                        in_place: self.in_place[value.0],
+                    },
+                    Intrinsic::Find => Op::MapFind,
+                    Intrinsic::EntryKey => Op::EntryKey,
+                    Intrinsic::EntryValue => Op::EntryValue,
+                    Intrinsic::Remove => Op::MapRemove {
+                        in_place: self.in_place[value.0],
+                    },
+                    Intrinsic::Sqrt => Op::Sqrt,
+                    Intrinsic::Floor => Op::Floor,
+                    Intrinsic::Ceil => Op::Ceil,
+                    Intrinsic::Pow => Op::Pow,
+                    Intrinsic::ReadFile => Op::ReadFile,
+                    Intrinsic::WriteFile => {
+                        self.op(Op::WriteFile);
+                        return false;
+                    }
+                    Intrinsic::FileExists => Op::FileExists,
+                    Intrinsic::FsError => Op::FsError,
+                    Intrinsic::Yield => Op::Yield,
+                    Intrinsic::Next => Op::Resume {
+                        some: self.module.variant_ids[&DefId::SOME],
+                        none: self.module.variant_ids[&DefId::NONE],
+                    },
+                }
+            }
+        };
+        self.op(op);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+
+    fn emitted(source: &str) -> Module {
+        emit(&built(source))
+    }
+
+    #[test]
+    fn test_emit() {
+        let source = "
+static COUNT: int = 0;
+fn count(n: int) int {
+    let mut i = 0;
+    while i < n { i += 1; }
+    i
+}
+fn main() {
+    let x = if count(3) > 2 { Some(1) } else { None };
+    match x {
+        Some(v) => COUNT = v,
+        None => panic(\"none\"),
+    }
+}";
+        assert_eq!(
+            disassemble(&emitted(source)),
+            "static g0 COUNT = fn2
+
+fn0 count: 1 params, 3 locals
+    0000  const 0 (0)
+    0003  store 1
+    0006  load 1
+    0009  store 1
+    0012  load 1
+    0015  load 0
+    0018  binary < i32
+    0021  store 2
+    0024  load 2
+    0027  jump_if_false 0061
+    0032  const 1 (1)
+    0035  store 2
+    0038  load 1
+    0041  load 2
+    0044  binary + i32
+    0047  store 2
+    0050  load 2
+    0053  store 1
+    0056  jump 0012
+    0061  load 1
+    0064  return
+
+fn1 main (main): 0 params, 3 locals
+    0000  const 2 (3)
+    0003  store 0
+    0006  load 0
+    0009  call fn0 1
+    0013  store 0
+    0016  const 3 (2)
+    0019  store 1
+    0022  load 0
+    0025  load 1
+    0028  binary > i32
+    0031  store 0
+    0034  load 0
+    0037  jump_if_false 0070
+    0042  const 1 (1)
+    0045  store 0
+    0048  load 0
+    0051  construct Some 1
+    0056  store 0
+    0059  load 0
+    0062  store 0
+    0065  jump 0084
+    0070  construct None 0
+    0075  store 1
+    0078  load 1
+    0081  store 0
+    0084  load 0
+    0087  discriminant
+    0088  store 1
+    0091  const 0 (0)
+    0094  store 2
+    0097  load 1
+    0100  load 2
+    0103  binary == i32
+    0106  store 1
+    0109  load 1
+    0112  jump_if_false 0144
+    0117  load 0
+    0120  variant_field Some 0
+    0125  store 1
+    0128  load 1
+    0131  set_global g0
+    0134  const 4 (())
+    0137  store 1
+    0140  load 1
+    0143  return
+    0144  load 0
+    0147  discriminant
+    0148  store 0
+    0151  const 1 (1)
+    0154  store 1
+    0157  load 0
+    0160  load 1
+    0163  binary == i32
+    0166  store 0
+    0169  load 0
+    0172  jump_if_false 0188
+    0177  const 5 (\"none\")
+    0180  store 0
+    0183  load 0
+    0186  panic
+    0187  unreachable
+    0188  unreachable
+
+fn2 COUNT: 0 params, 1 locals
+    0000  const 0 (0)
+    0003  store 0
+    0006  load 0
+    0009  return
+"
+        );
+    }
+
+    #[test]
+    fn test_vars() {
+        let source = "fn fib(n: int) int {
+    let mut a = 0;
+    let mut b = 1;
+    let mut i = 0;
+    while i < n { let next = a + b; a = b; b = next; i += 1; }
+    a
+}";
+        let module = emitted(source);
+        let func = &module.functions[0];
+        let names = |offset: usize| {
+            let vars = func.vars_at(offset).into_iter();
+            vars.map(|(name, _)| name).collect::<Vec<_>>()
+        };
+        // The parameters are there from the start, before the code stores anything
+        assert_eq!(names(0), vec!["n"]);
+        let at = |text: &str| {
+            let start = source.find(text).unwrap();
+            let mut spans = func.spans.iter();
+            let (offset, _) = spans.find(|(_, span)| span.start == start).unwrap();
+            *offset as usize
+        };
+        assert_eq!(names(at("a + b")), vec!["n", "a", "b", "i"]);
+        assert_eq!(names(at("i += 1")), vec!["n", "a", "b", "i", "next"]);
+        // `n` isn't loaded after the loop, so its slot may hold something else
+        let (end, _) = func.ops().last().unwrap();
+        assert_eq!(names(end), vec!["a"]);
+        assert!(func.vars_at(func.code.len()).is_empty());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let source = "
+const NAME: string = \"ruffle\";
+fn add(a: float, b: float) float { a + b }
+fn double(a: float) float { add(a, a) }
+fn main() {
+    let f = |c: char| c == 'x';
+    let t = (double(1.5), f('y'), 1..=3);
+}";
+        let module = emitted(source);
+        assert!(disassemble(&module).contains("tail_call fn0 2"));
+        let bytes = module.encode();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(Module::decode(&bytes), Ok(module));
+        assert_eq!(Module::decode(b"RFBD"), Err(DecodeError::NotBytecode));
+        assert_eq!(
+            Module::decode(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+    }
+}
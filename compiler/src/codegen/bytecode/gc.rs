@@ -0,0 +1,334 @@
+//! The heap of the VM, where every value that isn't a number, `bool`, `char`, `()` or function
+//! lives, and the mark-and-sweep collector that frees what a program can't reach anymore.
+//!
+//! Objects are never changed once they're made, so that a value can be copied by copying its
+//! [`Ref`]: changing a field or element makes a new object, the same as native code does. Only
+//! generators change, as they're resumed, so every copy of one is the same generator, and the
+//! arrays and maps that an update changes in place, which nothing else refers to. The heap
+//! counts the bytes it has allocated, and once they pass its threshold, the VM collects before its
+//! next instruction, when every value it's using is on its stack, in a global, or a constant. Those
+//! are the roots: the collector marks every object they lead to, then frees the rest and sets the
+//! next threshold from how much is left.
+
+use std::mem::size_of;
+
+use super::Value;
+use crate::maps::Map;
+
+/// Refers to an object on the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ref(u32);
+
+/// Something a value refers to on the heap.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    String(String),
+    Tuple(Vec<Value>),
+    /// A struct, or a variant of an enum, with the index of the variant in the module.
+    Variant {
+        variant: u16,
+        fields: Vec<Value>,
+    },
+    Array(Vec<Value>),
+    Map(Map<Value>),
+    /// A range, with its start and end if it has them, which are its fields 0 and 1.
+    Range {
+        bounds: [Option<Value>; 2],
+        inclusive: bool,
+    },
+    /// A closure, whose function takes the captured values before its own parameters.
+    Closure {
+        func: u16,
+        captures: Vec<Value>,
+    },
+    /// A generator, which runs a function as it's resumed.
+    Generator {
+        func: u16,
+        state: GenState,
+    },
+}
+
+/// Where a generator is in running its function.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenState {
+    /// Waiting to continue from the instruction at `pc`, with the function's local slots and then
+    /// its operands, which are put back on the VM's stack when it's resumed.
+    Suspended { pc: usize, stack: Vec<Value> },
+    /// Resumed, with a frame on the VM's stack.
+    Running,
+    /// Returned from its function, so it doesn't yield anything else.
+    Finished,
+}
+
+impl Object {
+    /// Roughly how many bytes the object takes, which is what the threshold counts.
+    fn size(&self) -> usize {
+        size_of::<Object>()
+            + match self {
+                Object::String(string) => string.len(),
+                Object::Tuple(values)
+                | Object::Variant { fields: values, .. }
+                | Object::Array(values)
+                | Object::Closure {
+                    captures: values, ..
+                }
+                | Object::Generator {
+                    state: GenState::Suspended { stack: values, .. },
+                    ..
+                } => values.len() * size_of::<Value>(),
+                Object::Generator { .. } => 0,
+                // A key, a value and a hash for each entry, and its index in a bucket
+                Object::Map(map) => map.len() * (2 * size_of::<Value>() + 16),
+                Object::Range { .. } => 0,
+            }
+    }
+
+    /// Calls `f` with every value the object holds.
+    fn values(&self, f: impl FnMut(&Value)) {
+        match self {
+            Object::String(_) => {}
+            Object::Tuple(values)
+            | Object::Variant { fields: values, .. }
+            | Object::Array(values)
+            | Object::Closure {
+                captures: values, ..
+            }
+            | Object::Generator {
+                state: GenState::Suspended { stack: values, .. },
+                ..
+            } => values.iter().for_each(f),
+            Object::Generator { .. } => {}
+            Object::Map(map) => (map.entries().iter())
+                .flat_map(|entry| [&entry.key, &entry.value])
+                .for_each(f),
+            Object::Range { bounds, .. } => bounds.iter().flatten().for_each(f),
+        }
+    }
+}
+
+/// When the VM collects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcConfig {
+    /// How many bytes the heap allocates before its first collection, and the least it
+    /// allocates before any other.
+    pub threshold: usize,
+    /// How many times the bytes still in use after a collection the heap can grow to before the
+    /// next one.
+    pub growth: usize,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            threshold: 1 << 20,
+            growth: 2,
+        }
+    }
+}
+
+pub struct Heap {
+    /// The objects, where the slot of one that was freed is `None` until it's reused.
+    objects: Vec<Option<Object>>,
+    /// The slots that were freed.
+    free: Vec<u32>,
+    /// The bytes taken by the objects, whether they can be reached or not.
+    allocated: usize,
+    threshold: usize,
+    config: GcConfig,
+    collections: usize,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Heap {
+            objects: Vec::new(),
+            free: Vec::new(),
+            allocated: 0,
+            threshold: config.threshold,
+            config,
+            collections: 0,
+        }
+    }
+
+    /// Puts an object on the heap. Allocating never collects, so the values that aren't roots yet
+    /// stay alive until the next instruction.
+    pub fn alloc(&mut self, object: Object) -> Ref {
+        self.allocated += object.size();
+        match self.free.pop() {
+            Some(slot) => {
+                self.objects[slot as usize] = Some(object);
+                Ref(slot)
+            }
+            None => {
+                let slot = u32::try_from(self.objects.len()).expect("heap is full");
+                self.objects.push(Some(object));
+                Ref(slot)
+            }
+        }
+    }
+
+    /// Returns the object a value refers to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if it was freed, which only happens to a value that wasn't a root when the heap
+    /// was collected.
+    pub fn get(&self, object: Ref) -> &Object {
+        self.objects[object.0 as usize]
+            .as_ref()
+            .expect("object was freed")
+    }
+
+    /// Replaces an object with another, returning the one it was. Only generators are replaced,
+    /// as they're resumed and suspended, and arrays and maps as they're updated in place.
+    pub fn replace(&mut self, object: Ref, new: Object) -> Object {
+        self.allocated += new.size();
+        let slot = &mut self.objects[object.0 as usize];
+        let old = slot.replace(new).expect("object was freed");
+        self.allocated = self.allocated.saturating_sub(old.size());
+        old
+    }
+
+    /// Returns every object on the heap, for changing the functions that generators run when the
+    /// VM is patched, which doesn't change their sizes.
+    pub fn objects_mut(&mut self) -> impl Iterator<Item = &mut Object> + '_ {
+        self.objects.iter_mut().flatten()
+    }
+
+    /// Returns whether the heap has grown past its threshold, so that it's time to collect.
+    pub fn needs_collection(&self) -> bool {
+        self.allocated > self.threshold
+    }
+
+    /// Frees every object that can't be reached from the roots.
+    pub fn collect<'v>(&mut self, roots: impl IntoIterator<Item = &'v Value>) {
+        let mut marked = vec![false; self.objects.len()];
+        // The objects that are marked but whose values aren't yet, so that marking a long chain
+        // of objects doesn't recurse
+        let mut pending: Vec<Ref> = Vec::new();
+        let mut mark = |value: &Value, pending: &mut Vec<Ref>| {
+            if let Value::Object(object) = *value {
+                if !std::mem::replace(&mut marked[object.0 as usize], true) {
+                    pending.push(object);
+                }
+            }
+        };
+        for root in roots {
+            mark(root, &mut pending);
+        }
+        while let Some(object) = pending.pop() {
+            self.get(object).values(|value| mark(value, &mut pending));
+        }
+
+        self.allocated = 0;
+        for (slot, object) in self.objects.iter_mut().enumerate() {
+            if marked[slot] {
+                self.allocated += object.as_ref().unwrap().size();
+            } else if object.take().is_some() {
+                self.free.push(slot as u32);
+            }
+        }
+        self.threshold = (self.allocated * self.config.growth).max(self.config.threshold);
+        self.collections += 1;
+    }
+
+    /// The number of objects on the heap, whether they can be reached or not.
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The bytes taken by the objects, whether they can be reached or not.
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// How many times the heap has been collected.
+    pub fn collections(&self) -> usize {
+        self.collections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::bytecode::{emit, Vm},
+        console::Console,
+        ffi::Externs,
+        limits::Limits,
+        mir::built,
+    };
+
+    #[test]
+    fn test_collect() {
+        let config = GcConfig {
+            threshold: 256,
+            growth: 2,
+        };
+        let mut heap = Heap::new(config);
+        let string = |s: &str| Object::String(s.to_string());
+        let garbage = heap.alloc(string("garbage"));
+        let root = heap.alloc(string("root"));
+        let captured = heap.alloc(string("captured"));
+        let closure = heap.alloc(Object::Closure {
+            func: 0,
+            captures: vec![Value::Object(captured)],
+        });
+        // Two tuples that refer to each other, but that nothing else refers to
+        let a = heap.alloc(Object::Tuple(Vec::new()));
+        let b = heap.alloc(Object::Tuple(vec![Value::Object(a)]));
+        heap.replace(a, Object::Tuple(vec![Value::Object(b)]));
+        assert_eq!(heap.len(), 6);
+        assert!(heap.needs_collection());
+
+        heap.collect(&[Value::Object(root), Value::Int(1), Value::Object(closure)]);
+        assert_eq!(heap.len(), 3);
+        assert_eq!(heap.collections(), 1);
+        assert!(!heap.needs_collection());
+        assert_eq!(heap.get(root), &string("root"));
+        assert_eq!(heap.get(captured), &string("captured"));
+        // The freed slots are reused
+        let reused = heap.alloc(string("reused"));
+        assert!([garbage, a, b].contains(&reused));
+    }
+
+    #[test]
+    fn test_collect_in_vm() {
+        let source = "
+static NAMES: [string] = [\"ann\", \"bob\"];
+fn churn(n: int) string {
+    let kept = [\"kept\"];
+    let suffix = \"!\";
+    let exclaim = |s: string| s + suffix;
+    let mut i = 0;
+    while i < n {
+        let garbage = [i, i + 1];
+        i += garbage[1] - garbage[0];
+    }
+    exclaim(NAMES[1] + kept[0])
+}";
+        let program = built(source);
+        let module = emit(&program);
+        let config = GcConfig {
+            threshold: 1024,
+            growth: 2,
+        };
+        let mut output = Vec::new();
+        let host = Externs::default();
+        let console = Console::new(&b""[..], &mut output);
+        let mut vm = Vm::new(&module, config, Limits::default(), console, &host).unwrap();
+        let churn = module.functions.iter().position(|f| f.name == "churn");
+        let result = vm.call(churn.unwrap() as u16, vec![Value::Int(10_000)]);
+        // The global, the locals on the stack and the closure's capture outlive the garbage
+        assert_eq!(
+            vm.object(result.unwrap()),
+            &Object::String("bobkept!".into())
+        );
+        assert!(vm.heap().collections() > 0);
+        assert!(vm.heap().len() < 100);
+    }
+}
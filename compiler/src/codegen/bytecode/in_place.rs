@@ -0,0 +1,177 @@
+//! Finds the updates of arrays and maps that can change them in place. They're values, so an
+//! update such as `insert` gives a copy of the map, which takes as long as the map is big. But
+//! when the function made the map itself, and nothing can use it after the update but the value
+//! it gives, no one can tell the copy from the map, so the VM changes the map instead.
+//!
+//! The values that may refer to the same object are grouped together: the operand of an update
+//! with its value, and a value that's passed to a block with the block's parameter. A group is
+//! shared when one of its values came from somewhere else, such as a parameter or an element of
+//! another array, or went somewhere it can outlive the group, such as a tuple, a global or the
+//! arguments of a call. Returning a value doesn't share it, since the caller treats what a call
+//! gives as shared. An update of a group that isn't shared is done in place when no other value
+//! of the group is live after it.
+
+use std::collections::BTreeSet;
+
+use super::slots;
+use crate::mir::{BlockId, Cfg, Function, InstKind, Intrinsic, Value};
+
+/// Returns the array or map an instruction updates, if it's an update.
+fn updated(kind: &InstKind) -> Option<Value> {
+    match kind {
+        InstKind::Intrinsic {
+            intrinsic: Intrinsic::Push | Intrinsic::Pop | Intrinsic::Insert | Intrinsic::Remove,
+            args,
+        } => Some(args[0]),
+        _ => None,
+    }
+}
+
+/// Returns whether each value is given by an update that changes its operand in place.
+pub(super) fn in_place(func: &Function) -> Vec<bool> {
+    let len = func.values.len();
+    let mut groups: Vec<usize> = (0..len).collect();
+    let mut shared = vec![false; len];
+    for &param in func.params() {
+        shared[param.0] = true;
+    }
+    for block in &func.blocks {
+        for inst in &block.insts {
+            let operands = inst.kind.operands();
+            match &inst.kind {
+                kind if updated(kind).is_some() => {
+                    join(&mut groups, operands[0], inst.value);
+                    // The value put in the array or map can outlive it
+                    for value in &operands[1..] {
+                        shared[value.0] = true;
+                    }
+                }
+                // Made by the instruction, from values it doesn't keep
+                InstKind::Intrinsic {
+                    intrinsic: Intrinsic::NewMap | Intrinsic::Chars | Intrinsic::Split,
+                    ..
+                } => {}
+                // Only read, so their operands don't outlive them
+                InstKind::Index { .. }
+                | InstKind::Binary { .. }
+                | InstKind::Intrinsic {
+                    intrinsic:
+                        Intrinsic::Len | Intrinsic::Find | Intrinsic::EntryKey | Intrinsic::EntryValue,
+                    ..
+                } => shared[inst.value.0] = true,
+                InstKind::Array(_) => operands.iter().for_each(|value| shared[value.0] = true),
+                _ => {
+                    shared[inst.value.0] = true;
+                    operands.iter().for_each(|value| shared[value.0] = true);
+                }
+            }
+        }
+        for target in block.terminator.targets() {
+            let params = &func.block(target.block).params;
+            for (&arg, &param) in target.args.iter().zip(params) {
+                join(&mut groups, arg, param);
+            }
+        }
+    }
+    let groups: Vec<usize> = (0..len).map(|value| find(&groups, value)).collect();
+    let mut shared_groups = vec![false; len];
+    for value in (0..len).filter(|&value| shared[value]) {
+        shared_groups[groups[value]] = true;
+    }
+
+    let cfg = Cfg::new(func);
+    let live_in = slots::live_in(func, &cfg);
+    let mut in_place = vec![false; len];
+    for id in (0..func.blocks.len()).map(BlockId) {
+        let block = func.block(id);
+        let mut live: BTreeSet<Value> = (cfg.succs(id).iter())
+            .flat_map(|succ| live_in[succ.0].iter().copied())
+            .collect();
+        let mut terminator = block.terminator.clone();
+        terminator.operands_mut(|value| {
+            live.insert(*value);
+        });
+        for inst in block.insts.iter().rev() {
+            live.remove(&inst.value);
+            if let Some(operand) = updated(&inst.kind) {
+                let group = groups[operand.0];
+                in_place[inst.value.0] =
+                    !shared_groups[group] && live.iter().all(|value| groups[value.0] != group);
+            }
+            live.extend(inst.kind.operands());
+        }
+    }
+    in_place
+}
+
+/// Returns the first value of a value's group.
+fn find(groups: &[usize], mut value: usize) -> usize {
+    while groups[value] != value {
+        value = groups[value];
+    }
+    value
+}
+
+fn join(groups: &mut [usize], a: Value, b: Value) {
+    let (a, b) = (find(groups, a.0), find(groups, b.0));
+    groups[a.max(b)] = a.min(b);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+
+    #[test]
+    fn test_in_place() {
+        let source = "
+fn fill(n: int) Map<int, int> {
+    let mut squares = Map::new();
+    let mut i = 0;
+    while i < n {
+        squares.insert(i, i * i);
+        i += 1;
+    }
+    squares.remove(0);
+    squares
+}
+
+fn stack(n: int) [int] {
+    let mut xs = [n];
+    let mut i = 0;
+    while i < n {
+        xs.push(i);
+        i += 1;
+    }
+    xs.pop();
+    xs
+}
+
+fn copy(squares: Map<int, int>) Map<int, int> {
+    let mut copy = squares;
+    copy.insert(1, 2);
+    copy
+}
+
+fn keep() (Map<int, int>, Map<int, int>) {
+    let mut map = Map::new();
+    let before = map;
+    map.insert(1, 2);
+    (before, map)
+}";
+        let program = built(source);
+        let updates = |name: &str| {
+            let func = program.fns.iter().find(|f| f.name == name).unwrap();
+            let in_place = in_place(func);
+            let insts = func.blocks.iter().flat_map(|block| &block.insts);
+            (insts.filter(|inst| updated(&inst.kind).is_some()))
+                .map(|inst| in_place[inst.value.0])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(updates("fill"), [true, true]);
+        assert_eq!(updates("stack"), [true, true]);
+        // The parameter is the caller's map, and `before` is still used after the insert
+        assert_eq!(updates("copy"), [false]);
+        assert_eq!(updates("keep"), [false]);
+    }
+}
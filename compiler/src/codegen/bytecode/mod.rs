@@ -0,0 +1,1237 @@
+//! A compact stack-based bytecode, the first form a program takes that can be run.
+//!
+//! A [`Module`] has a pool of constants, a table of functions, the globals, and the variants of
+//! every struct and enum. Each function has a fixed number of local slots, where its parameters
+//! are the first, and its code is a sequence of [`Op`]s: each one is an opcode byte followed by
+//! its operands, which are little-endian. Instructions take their operands from the stack and
+//! push their result, and jumps go to byte offsets in the function's code.
+//!
+//! A module is stored as the bytes `RFBC`, then the version of the format, then its sections,
+//! which [`Module::encode`] writes and [`Module::decode`] reads back. Along with its code, each
+//! function has the spans of the source it was compiled from and the slots of its variables, which
+//! is how panics and the debugger find their way back to the source.
+//!
+//! A [`Vm`] runs a module's code, which is how `run` runs a program unless it's asked for another
+//! engine.
+
+mod emit;
+pub mod gc;
+mod in_place;
+mod peephole;
+pub mod profile;
+mod slots;
+mod vm;
+
+use std::fmt::Display;
+
+use crate::{
+    ast::{BinaryOp, UnaryOp},
+    lexer::Span,
+    mir::Constant,
+    typeck::{IntTy, Ty},
+};
+
+pub use emit::emit;
+pub use gc::GcConfig;
+pub use peephole::peephole;
+pub use profile::Profile;
+pub use vm::{run, CallFrame, Debugger, Reloader, Value, Vm, RELOAD_INTERVAL};
+
+/// The bytes a module starts with.
+pub const MAGIC: &[u8; 4] = b"RFBC";
+
+/// The version of the format, which changes whenever an existing module can't be read the same
+/// way anymore.
+pub const VERSION: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub constants: Vec<Constant>,
+    pub functions: Vec<Function>,
+    pub globals: Vec<Global>,
+    pub variants: Vec<Variant>,
+    /// The function that runs the program, if it has a `main`.
+    pub main: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    /// The number of parameters, which are in the first local slots when it's called.
+    pub params: u8,
+    /// The number of local slots, including the parameters.
+    pub locals: u16,
+    pub code: Vec<u8>,
+    /// The offsets where the code for each instruction of the source starts, with its span, in
+    /// order.
+    pub spans: Vec<(u32, Span)>,
+    /// The variables of the source, for debuggers.
+    pub vars: Vec<Var>,
+}
+
+/// A variable of the source, with the slots that hold its values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: String,
+    /// The offsets of the code where each slot holds a value of the variable, from after it's
+    /// stored up to the end of the instruction that last loads it, with the slot.
+    pub ranges: Vec<(u32, u32, u16)>,
+}
+
+impl Function {
+    /// Returns the instructions of the function with their offsets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the code isn't valid, which it always is in a module that was emitted or decoded.
+    pub fn ops(&self) -> impl Iterator<Item = (usize, Op)> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            if offset == self.code.len() {
+                return None;
+            }
+            let (op, next) = Op::decode(&self.code, offset).expect("invalid bytecode");
+            let start = std::mem::replace(&mut offset, next);
+            Some((start, op))
+        })
+    }
+
+    /// Returns the span of the source the code at `offset` was compiled from.
+    pub fn span_at(&self, offset: usize) -> Option<&Span> {
+        let index = self
+            .spans
+            .partition_point(|&(start, _)| start as usize <= offset);
+        index.checked_sub(1).map(|index| &self.spans[index].1)
+    }
+
+    /// Returns the variables that have a value when the code at `offset` runs, in the order
+    /// they're declared, with the slot of each. Where the value of a variable's last assignment
+    /// and the one before it are both kept, the last one's is the slot.
+    pub fn vars_at(&self, offset: usize) -> Vec<(&str, u16)> {
+        let offset = offset as u32;
+        (self.vars.iter())
+            .filter_map(|var| {
+                let ranges = var.ranges.iter();
+                let (_, _, slot) = ranges
+                    .filter(|&&(start, end, _)| start <= offset && offset < end)
+                    .max_by_key(|&&(start, ..)| start)?;
+                Some((var.name.as_str(), *slot))
+            })
+            .collect()
+    }
+}
+
+/// A `const` or `static`, whose value is what calling `init` returns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name: String,
+    pub is_static: bool,
+    pub init: u16,
+}
+
+/// A struct, or a variant of an enum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    /// The index of the variant in its enum, which is 0 for a struct.
+    pub discriminant: u16,
+    pub fields: u16,
+}
+
+/// The type of the operands of an arithmetic, comparison or cast instruction, which decides what
+/// it does with them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Int(IntTy),
+    Float,
+    Bool,
+    Char,
+    String,
+    /// Tuples, arrays, and the other types that can only be compared for equality.
+    Other,
+}
+
+const INT_TYS: [IntTy; 8] = [
+    IntTy::I8,
+    IntTy::I16,
+    IntTy::I32,
+    IntTy::I64,
+    IntTy::U8,
+    IntTy::U16,
+    IntTy::U32,
+    IntTy::U64,
+];
+
+impl Kind {
+    pub fn of(ty: &Ty) -> Kind {
+        match ty {
+            Ty::Int(int) => Kind::Int(*int),
+            Ty::IntVar(_) => Kind::Int(IntTy::DEFAULT),
+            Ty::Float => Kind::Float,
+            Ty::Bool => Kind::Bool,
+            Ty::Char => Kind::Char,
+            Ty::String => Kind::String,
+            _ => Kind::Other,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Kind::Int(int) => INT_TYS.iter().position(|&ty| ty == int).unwrap() as u8,
+            Kind::Float => 8,
+            Kind::Bool => 9,
+            Kind::Char => 10,
+            Kind::String => 11,
+            Kind::Other => 12,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Kind> {
+        Some(match byte {
+            0..=7 => Kind::Int(INT_TYS[byte as usize]),
+            8 => Kind::Float,
+            9 => Kind::Bool,
+            10 => Kind::Char,
+            11 => Kind::String,
+            12 => Kind::Other,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Int(int) => int.name(),
+            Kind::Float => "float",
+            Kind::Bool => "bool",
+            Kind::Char => "char",
+            Kind::String => "string",
+            Kind::Other => "other",
+        }
+    }
+}
+
+const BINARY_OPS: [BinaryOp; 20] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Rem,
+    BinaryOp::Eq,
+    BinaryOp::Ne,
+    BinaryOp::TripleEq,
+    BinaryOp::TripleNe,
+    BinaryOp::Lt,
+    BinaryOp::Le,
+    BinaryOp::Gt,
+    BinaryOp::Ge,
+    BinaryOp::And,
+    BinaryOp::Or,
+    BinaryOp::BitAnd,
+    BinaryOp::BitOr,
+    BinaryOp::BitXor,
+    BinaryOp::Shl,
+    BinaryOp::Shr,
+];
+
+const UNARY_OPS: [UnaryOp; 2] = [UnaryOp::Neg, UnaryOp::Not];
+
+/// An instruction. The stack effects are written as what it pops, then what it pushes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// `-- constant`, pushing a constant from the pool.
+    Const(u16),
+    /// `-- value`, pushing the value in a local slot.
+    Load(u16),
+    /// `value --`, storing a value in a local slot.
+    Store(u16),
+    /// `value --`
+    Pop,
+    /// `value -- result`
+    Unary {
+        op: UnaryOp,
+        kind: Kind,
+    },
+    /// `lhs rhs -- result`, where `kind` is the type of the operands. Integer arithmetic fails
+    /// when it overflows.
+    Binary {
+        op: BinaryOp,
+        kind: Kind,
+    },
+    /// `value -- result`
+    Cast {
+        from: Kind,
+        to: Kind,
+    },
+    /// `elems... -- tuple`
+    Tuple(u16),
+    /// `elems... -- array`
+    Array(u16),
+    /// `fields... -- value`, making a value of the variant at that index in the module.
+    Construct {
+        variant: u16,
+        fields: u16,
+    },
+    /// `base -- field`, a field of a struct or tuple, or a bound of a range.
+    Field(u16),
+    /// `base value -- base`, a copy with the field replaced.
+    SetField(u16),
+    /// `value -- discriminant`, the index of an enum value's variant as an `i32`.
+    Discriminant,
+    /// `value -- field`, a field of an enum value that has to be the variant.
+    VariantField {
+        variant: u16,
+        index: u16,
+    },
+    /// `array index -- element`, failing when the index is out of bounds.
+    Index,
+    /// `array index value -- array`, a copy with the element replaced.
+    SetIndex,
+    /// `start? end? -- range`, popping the bounds the range has.
+    Range {
+        start: bool,
+        end: bool,
+        inclusive: bool,
+    },
+    /// `-- value`
+    Global(u16),
+    /// `value --`
+    SetGlobal(u16),
+    /// `-- function`, a function as a value.
+    Function(u16),
+    /// `captures... -- closure`
+    Closure {
+        func: u16,
+        captures: u8,
+    },
+    /// `args... -- result`
+    Call {
+        func: u16,
+        args: u8,
+    },
+    /// `callee args... -- result`, calling a function or closure value.
+    CallIndirect(u8),
+    /// `args... -- result`, calling the host's function whose name is the string constant
+    /// `name`, which returns a value of `ret`, or the unit value for [`Kind::Other`].
+    Extern {
+        name: u16,
+        args: u8,
+        ret: Kind,
+    },
+    /// `args... --`, returning what a call returns, with the callee taking the caller's frame.
+    TailCall {
+        func: u16,
+        args: u8,
+    },
+    /// `args... -- generator`, a generator that calls the function with the arguments when it's
+    /// first resumed.
+    Generator {
+        func: u16,
+        args: u8,
+    },
+    /// `generator -- option`, resuming a generator until it yields a value, which is pushed in
+    /// the variant `some`, or until it returns, which pushes the variant `none`. It fails when the
+    /// generator is already running.
+    Resume {
+        some: u16,
+        none: u16,
+    },
+    /// `value -- ()`, handing a value to the code that resumed the generator whose function is
+    /// running, which continues from there. The `()` is pushed once the generator is resumed
+    /// again.
+    Yield,
+    /// `array -- length`, or the number of entries of a map.
+    Len,
+    /// `string -- chars`
+    Chars,
+    /// `message --`, stopping the program.
+    Panic,
+    /// `text --`, writing a string to the standard output.
+    Print,
+    /// `text --`, writing a string and a newline to the standard output.
+    Println,
+    /// `-- text`, reading a line from the standard input.
+    Input,
+    /// `string -- length`, the number of characters in a string.
+    StringLen,
+    /// `string index -- char`, failing when the index is out of bounds.
+    CharAt,
+    /// `string range -- string`, failing when the range is out of bounds.
+    Slice,
+    /// `string pattern -- bool`
+    Contains,
+    /// `string separator -- parts`, failing when the separator is empty.
+    Split,
+    /// `string -- string`, with its ASCII letters in upper case.
+    ToUpper,
+    /// `string -- string`, with its ASCII letters in lower case.
+    ToLower,
+    /// `array value -- array`, a copy with the value added to its end, or the array itself when
+    /// the update is `in_place`, since nothing else can use it anymore.
+    ArrayPush {
+        in_place: bool,
+    },
+    /// `array -- array`, a copy without its last element, if it has one, or the array itself when
+    /// the update is `in_place`.
+    ArrayPop {
+        in_place: bool,
+    },
+    /// `-- map`, an empty map.
+    NewMap,
+    /// `map key value -- map`, a copy with the value of the key added or replaced, or the map
+    /// itself when the update is `in_place`, since nothing else can use it anymore.
+    MapInsert {
+        in_place: bool,
+    },
+    /// `map key -- index`, the index of the key's entry, or -1 when it has none.
+    MapFind,
+    /// `map index -- key`, the key of an entry, failing when the index is out of bounds.
+    EntryKey,
+    /// `map index -- value`, the value of an entry, failing when the index is out of bounds.
+    EntryValue,
+    /// `map index -- map`, a copy without an entry, failing when the index is out of bounds, or
+    /// the map itself when the update is `in_place`.
+    MapRemove {
+        in_place: bool,
+    },
+    /// `x -- x`, the square root of a float, or the float rounded down or up to an integer.
+    Sqrt,
+    Floor,
+    Ceil,
+    /// `x y -- x`, a float raised to the power of another.
+    Pow,
+    /// `path -- contents`, or an empty string when the file can't be read.
+    ReadFile,
+    /// `path contents --`, replacing a file.
+    WriteFile,
+    /// `path -- bool`, whether a file or directory exists, or `false` when it can't be found out.
+    FileExists,
+    /// `-- message`, of the error that the last of the three before failed with, or an empty
+    /// string when it succeeded.
+    FsError,
+    /// Jumps to an offset in the function.
+    Jump(u32),
+    /// `cond --`, jumping to an offset when the condition is false.
+    JumpIfFalse(u32),
+    /// `value --`, returning from the function.
+    Return,
+    /// Fails, since the code can't be reached.
+    Unreachable,
+}
+
+/// The opcode of each instruction, the byte its encoding starts with.
+mod opcode {
+    pub const CONST: u8 = 0;
+    pub const LOAD: u8 = 1;
+    pub const STORE: u8 = 2;
+    pub const POP: u8 = 3;
+    pub const UNARY: u8 = 4;
+    pub const BINARY: u8 = 5;
+    pub const CAST: u8 = 6;
+    pub const TUPLE: u8 = 7;
+    pub const CONSTRUCT: u8 = 8;
+    pub const FIELD: u8 = 9;
+    pub const SET_FIELD: u8 = 10;
+    pub const DISCRIMINANT: u8 = 11;
+    pub const VARIANT_FIELD: u8 = 12;
+    pub const INDEX: u8 = 13;
+    pub const SET_INDEX: u8 = 14;
+    pub const RANGE: u8 = 15;
+    pub const GLOBAL: u8 = 16;
+    pub const SET_GLOBAL: u8 = 17;
+    pub const FUNCTION: u8 = 18;
+    pub const CLOSURE: u8 = 19;
+    pub const CALL: u8 = 20;
+    pub const CALL_INDIRECT: u8 = 21;
+    pub const LEN: u8 = 22;
+    pub const CHARS: u8 = 23;
+    pub const PANIC: u8 = 24;
+    pub const JUMP: u8 = 25;
+    pub const JUMP_IF_FALSE: u8 = 26;
+    pub const RETURN: u8 = 27;
+    pub const UNREACHABLE: u8 = 28;
+    pub const TAIL_CALL: u8 = 29;
+    pub const PRINT: u8 = 30;
+    pub const PRINTLN: u8 = 31;
+    pub const INPUT: u8 = 32;
+    pub const STRING_LEN: u8 = 33;
+    pub const CHAR_AT: u8 = 34;
+    pub const SLICE: u8 = 35;
+    pub const CONTAINS: u8 = 36;
+    pub const SPLIT: u8 = 37;
+    pub const TO_UPPER: u8 = 38;
+    pub const TO_LOWER: u8 = 39;
+    pub const ARRAY: u8 = 40;
+    pub const ARRAY_PUSH: u8 = 41;
+    pub const ARRAY_POP: u8 = 42;
+    pub const NEW_MAP: u8 = 43;
+    pub const MAP_INSERT: u8 = 44;
+    pub const MAP_FIND: u8 = 45;
+    pub const ENTRY_KEY: u8 = 46;
+    pub const ENTRY_VALUE: u8 = 47;
+    pub const MAP_REMOVE: u8 = 48;
+    pub const SQRT: u8 = 49;
+    pub const FLOOR: u8 = 50;
+    pub const CEIL: u8 = 51;
+    pub const POW: u8 = 52;
+    pub const READ_FILE: u8 = 53;
+    pub const WRITE_FILE: u8 = 54;
+    pub const FILE_EXISTS: u8 = 55;
+    pub const FS_ERROR: u8 = 56;
+    pub const EXTERN: u8 = 57;
+    pub const GENERATOR: u8 = 58;
+    pub const RESUME: u8 = 59;
+    pub const YIELD: u8 = 60;
+}
+
+impl Op {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let u16 = |out: &mut Vec<u8>, value: u16| out.extend(value.to_le_bytes());
+        match *self {
+            Op::Const(index) => {
+                out.push(opcode::CONST);
+                u16(out, index);
+            }
+            Op::Load(slot) => {
+                out.push(opcode::LOAD);
+                u16(out, slot);
+            }
+            Op::Store(slot) => {
+                out.push(opcode::STORE);
+                u16(out, slot);
+            }
+            Op::Pop => out.push(opcode::POP),
+            Op::Unary { op, kind } => {
+                let op = UNARY_OPS.iter().position(|&o| o == op).unwrap() as u8;
+                out.extend([opcode::UNARY, op, kind.to_byte()]);
+            }
+            Op::Binary { op, kind } => {
+                let op = BINARY_OPS.iter().position(|&o| o == op).unwrap() as u8;
+                out.extend([opcode::BINARY, op, kind.to_byte()]);
+            }
+            Op::Cast { from, to } => out.extend([opcode::CAST, from.to_byte(), to.to_byte()]),
+            Op::Tuple(len) => {
+                out.push(opcode::TUPLE);
+                u16(out, len);
+            }
+            Op::Array(len) => {
+                out.push(opcode::ARRAY);
+                u16(out, len);
+            }
+            Op::Construct { variant, fields } => {
+                out.push(opcode::CONSTRUCT);
+                u16(out, variant);
+                u16(out, fields);
+            }
+            Op::Field(index) => {
+                out.push(opcode::FIELD);
+                u16(out, index);
+            }
+            Op::SetField(index) => {
+                out.push(opcode::SET_FIELD);
+                u16(out, index);
+            }
+            Op::Discriminant => out.push(opcode::DISCRIMINANT),
+            Op::VariantField { variant, index } => {
+                out.push(opcode::VARIANT_FIELD);
+                u16(out, variant);
+                u16(out, index);
+            }
+            Op::Index => out.push(opcode::INDEX),
+            Op::SetIndex => out.push(opcode::SET_INDEX),
+            Op::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let flags = start as u8 | (end as u8) << 1 | (inclusive as u8) << 2;
+                out.extend([opcode::RANGE, flags]);
+            }
+            Op::Global(index) => {
+                out.push(opcode::GLOBAL);
+                u16(out, index);
+            }
+            Op::SetGlobal(index) => {
+                out.push(opcode::SET_GLOBAL);
+                u16(out, index);
+            }
+            Op::Function(index) => {
+                out.push(opcode::FUNCTION);
+                u16(out, index);
+            }
+            Op::Closure { func, captures } => {
+                out.push(opcode::CLOSURE);
+                u16(out, func);
+                out.push(captures);
+            }
+            Op::Call { func, args } => {
+                out.push(opcode::CALL);
+                u16(out, func);
+                out.push(args);
+            }
+            Op::CallIndirect(args) => out.extend([opcode::CALL_INDIRECT, args]),
+            Op::Extern { name, args, ret } => {
+                out.push(opcode::EXTERN);
+                u16(out, name);
+                out.extend([args, ret.to_byte()]);
+            }
+            Op::TailCall { func, args } => {
+                out.push(opcode::TAIL_CALL);
+                u16(out, func);
+                out.push(args);
+            }
+            Op::Generator { func, args } => {
+                out.push(opcode::GENERATOR);
+                u16(out, func);
+                out.push(args);
+            }
+            Op::Resume { some, none } => {
+                out.push(opcode::RESUME);
+                u16(out, some);
+                u16(out, none);
+            }
+            Op::Yield => out.push(opcode::YIELD),
+            Op::Len => out.push(opcode::LEN),
+            Op::Chars => out.push(opcode::CHARS),
+            Op::Panic => out.push(opcode::PANIC),
+            Op::Print => out.push(opcode::PRINT),
+            Op::Println => out.push(opcode::PRINTLN),
+            Op::Input => out.push(opcode::INPUT),
+            Op::StringLen => out.push(opcode::STRING_LEN),
+            Op::CharAt => out.push(opcode::CHAR_AT),
+            Op::Slice => out.push(opcode::SLICE),
+            Op::Contains => out.push(opcode::CONTAINS),
+            Op::Split => out.push(opcode::SPLIT),
+            Op::ToUpper => out.push(opcode::TO_UPPER),
+            Op::ToLower => out.push(opcode::TO_LOWER),
+            Op::ArrayPush { in_place } => out.extend([opcode::ARRAY_PUSH, in_place as u8]),
+            Op::ArrayPop { in_place } => out.extend([opcode::ARRAY_POP, in_place as u8]),
+            Op::NewMap => out.push(opcode::NEW_MAP),
+            Op::MapInsert { in_place } => out.extend([opcode::MAP_INSERT, in_place as u8]),
+            Op::MapFind => out.push(opcode::MAP_FIND),
+            Op::EntryKey => out.push(opcode::ENTRY_KEY),
+            Op::EntryValue => out.push(opcode::ENTRY_VALUE),
+            Op::MapRemove { in_place } => out.extend([opcode::MAP_REMOVE, in_place as u8]),
+            Op::Sqrt => out.push(opcode::SQRT),
+            Op::Floor => out.push(opcode::FLOOR),
+            Op::Ceil => out.push(opcode::CEIL),
+            Op::Pow => out.push(opcode::POW),
+            Op::ReadFile => out.push(opcode::READ_FILE),
+            Op::WriteFile => out.push(opcode::WRITE_FILE),
+            Op::FileExists => out.push(opcode::FILE_EXISTS),
+            Op::FsError => out.push(opcode::FS_ERROR),
+            Op::Jump(target) => {
+                out.push(opcode::JUMP);
+                out.extend(target.to_le_bytes());
+            }
+            Op::JumpIfFalse(target) => {
+                out.push(opcode::JUMP_IF_FALSE);
+                out.extend(target.to_le_bytes());
+            }
+            Op::Return => out.push(opcode::RETURN),
+            Op::Unreachable => out.push(opcode::UNREACHABLE),
+        }
+    }
+
+    /// Decodes the instruction at `offset` in some code, returning it with the offset of the
+    /// next one.
+    pub fn decode(code: &[u8], offset: usize) -> Result<(Op, usize), DecodeError> {
+        let mut reader = Reader {
+            bytes: code,
+            pos: offset,
+        };
+        let byte = reader.u8()?;
+        let kind = |reader: &mut Reader| {
+            let byte = reader.u8()?;
+            Kind::from_byte(byte).ok_or(DecodeError::InvalidKind(byte))
+        };
+        let op = match byte {
+            opcode::CONST => Op::Const(reader.u16()?),
+            opcode::LOAD => Op::Load(reader.u16()?),
+            opcode::STORE => Op::Store(reader.u16()?),
+            opcode::POP => Op::Pop,
+            opcode::UNARY => {
+                let op = reader.u8()?;
+                let op = *UNARY_OPS
+                    .get(op as usize)
+                    .ok_or(DecodeError::InvalidOperator(op))?;
+                Op::Unary {
+                    op,
+                    kind: kind(&mut reader)?,
+                }
+            }
+            opcode::BINARY => {
+                let op = reader.u8()?;
+                let op = *BINARY_OPS
+                    .get(op as usize)
+                    .ok_or(DecodeError::InvalidOperator(op))?;
+                Op::Binary {
+                    op,
+                    kind: kind(&mut reader)?,
+                }
+            }
+            opcode::CAST => Op::Cast {
+                from: kind(&mut reader)?,
+                to: kind(&mut reader)?,
+            },
+            opcode::TUPLE => Op::Tuple(reader.u16()?),
+            opcode::ARRAY => Op::Array(reader.u16()?),
+            opcode::CONSTRUCT => Op::Construct {
+                variant: reader.u16()?,
+                fields: reader.u16()?,
+            },
+            opcode::FIELD => Op::Field(reader.u16()?),
+            opcode::SET_FIELD => Op::SetField(reader.u16()?),
+            opcode::DISCRIMINANT => Op::Discriminant,
+            opcode::VARIANT_FIELD => Op::VariantField {
+                variant: reader.u16()?,
+                index: reader.u16()?,
+            },
+            opcode::INDEX => Op::Index,
+            opcode::SET_INDEX => Op::SetIndex,
+            opcode::RANGE => {
+                let flags = reader.u8()?;
+                Op::Range {
+                    start: flags & 1 != 0,
+                    end: flags & 2 != 0,
+                    inclusive: flags & 4 != 0,
+                }
+            }
+            opcode::GLOBAL => Op::Global(reader.u16()?),
+            opcode::SET_GLOBAL => Op::SetGlobal(reader.u16()?),
+            opcode::FUNCTION => Op::Function(reader.u16()?),
+            opcode::CLOSURE => Op::Closure {
+                func: reader.u16()?,
+                captures: reader.u8()?,
+            },
+            opcode::CALL => Op::Call {
+                func: reader.u16()?,
+                args: reader.u8()?,
+            },
+            opcode::CALL_INDIRECT => Op::CallIndirect(reader.u8()?),
+            opcode::EXTERN => Op::Extern {
+                name: reader.u16()?,
+                args: reader.u8()?,
+                ret: kind(&mut reader)?,
+            },
+            opcode::TAIL_CALL => Op::TailCall {
+                func: reader.u16()?,
+                args: reader.u8()?,
+            },
+            opcode::GENERATOR => Op::Generator {
+                func: reader.u16()?,
+                args: reader.u8()?,
+            },
+            opcode::RESUME => Op::Resume {
+                some: reader.u16()?,
+                none: reader.u16()?,
+            },
+            opcode::YIELD => Op::Yield,
+            opcode::LEN => Op::Len,
+            opcode::CHARS => Op::Chars,
+            opcode::PANIC => Op::Panic,
+            opcode::PRINT => Op::Print,
+            opcode::PRINTLN => Op::Println,
+            opcode::INPUT => Op::Input,
+            opcode::STRING_LEN => Op::StringLen,
+            opcode::CHAR_AT => Op::CharAt,
+            opcode::SLICE => Op::Slice,
+            opcode::CONTAINS => Op::Contains,
+            opcode::SPLIT => Op::Split,
+            opcode::TO_UPPER => Op::ToUpper,
+            opcode::TO_LOWER => Op::ToLower,
+            opcode::ARRAY_PUSH => Op::ArrayPush {
+                in_place: reader.u8()? != 0,
+            },
+            opcode::ARRAY_POP => Op::ArrayPop {
+                in_place: reader.u8()? != 0,
+            },
+            opcode::NEW_MAP => Op::NewMap,
+            opcode::MAP_INSERT => Op::MapInsert {
+                in_place: reader.u8()? != 0,
+            },
+            opcode::MAP_FIND => Op::MapFind,
+            opcode::ENTRY_KEY => Op::EntryKey,
+            opcode::ENTRY_VALUE => Op::EntryValue,
+            opcode::MAP_REMOVE => Op::MapRemove {
+                in_place: reader.u8()? != 0,
+            },
+            opcode::SQRT => Op::Sqrt,
+            opcode::FLOOR => Op::Floor,
+            opcode::CEIL => Op::Ceil,
+            opcode::POW => Op::Pow,
+            opcode::READ_FILE => Op::ReadFile,
+            opcode::WRITE_FILE => Op::WriteFile,
+            opcode::FILE_EXISTS => Op::FileExists,
+            opcode::FS_ERROR => Op::FsError,
+            opcode::JUMP => Op::Jump(reader.u32()?),
+            opcode::JUMP_IF_FALSE => Op::JumpIfFalse(reader.u32()?),
+            opcode::RETURN => Op::Return,
+            opcode::UNREACHABLE => Op::Unreachable,
+            _ => return Err(DecodeError::InvalidOpcode { offset, byte }),
+        };
+        Ok((op, reader.pos))
+    }
+}
+
+/// A way that bytes aren't a valid module.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The bytes don't start with [`MAGIC`].
+    NotBytecode,
+    UnsupportedVersion(u16),
+    /// The bytes end in the middle of something.
+    UnexpectedEnd,
+    InvalidOpcode {
+        offset: usize,
+        byte: u8,
+    },
+    InvalidOperator(u8),
+    InvalidKind(u8),
+    InvalidConstant(u8),
+    InvalidString,
+    /// An instruction that refers to a constant, function, global or variant the module doesn't
+    /// have, or jumps outside its function.
+    OutOfRange {
+        func: String,
+        offset: usize,
+    },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::NotBytecode => write!(f, "not a Ruffle bytecode module"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported bytecode version {}", version)
+            }
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of bytecode"),
+            DecodeError::InvalidOpcode { offset, byte } => {
+                write!(f, "invalid opcode {} at offset {}", byte, offset)
+            }
+            DecodeError::InvalidOperator(byte) => write!(f, "invalid operator {}", byte),
+            DecodeError::InvalidKind(byte) => write!(f, "invalid operand kind {}", byte),
+            DecodeError::InvalidConstant(byte) => write!(f, "invalid constant tag {}", byte),
+            DecodeError::InvalidString => write!(f, "string isn't valid UTF-8"),
+            DecodeError::OutOfRange { func, offset } => write!(
+                f,
+                "instruction at offset {} of `{}` refers to something that doesn't exist",
+                offset, func
+            ),
+        }
+    }
+}
+
+/// Reads the parts of a module from its bytes.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.u32()?;
+        self.take(len as usize)
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidString)
+    }
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend((bytes.len() as u32).to_le_bytes());
+    out.extend(bytes);
+}
+
+impl Module {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        out.extend(self.main.unwrap_or(u16::MAX).to_le_bytes());
+
+        out.extend((self.constants.len() as u32).to_le_bytes());
+        for constant in &self.constants {
+            match constant {
+                Constant::Int(value) => {
+                    out.push(0);
+                    out.extend(value.to_le_bytes());
+                }
+                Constant::Float(value) => {
+                    out.push(1);
+                    out.extend(value.to_le_bytes());
+                }
+                Constant::Bool(value) => out.extend([2, *value as u8]),
+                Constant::Char(value) => {
+                    out.push(3);
+                    out.extend((*value as u32).to_le_bytes());
+                }
+                Constant::String(value) => {
+                    out.push(4);
+                    put_bytes(&mut out, value.as_bytes());
+                }
+                Constant::Unit => out.push(5),
+            }
+        }
+
+        out.extend((self.functions.len() as u32).to_le_bytes());
+        for func in &self.functions {
+            put_bytes(&mut out, func.name.as_bytes());
+            out.push(func.params);
+            out.extend(func.locals.to_le_bytes());
+            put_bytes(&mut out, &func.code);
+            out.extend((func.spans.len() as u32).to_le_bytes());
+            for (offset, span) in &func.spans {
+                out.extend(offset.to_le_bytes());
+                out.extend((span.start as u32).to_le_bytes());
+                out.extend((span.end as u32).to_le_bytes());
+            }
+            out.extend((func.vars.len() as u32).to_le_bytes());
+            for var in &func.vars {
+                put_bytes(&mut out, var.name.as_bytes());
+                out.extend((var.ranges.len() as u32).to_le_bytes());
+                for (start, end, slot) in &var.ranges {
+                    out.extend(start.to_le_bytes());
+                    out.extend(end.to_le_bytes());
+                    out.extend(slot.to_le_bytes());
+                }
+            }
+        }
+
+        out.extend((self.globals.len() as u32).to_le_bytes());
+        for global in &self.globals {
+            put_bytes(&mut out, global.name.as_bytes());
+            out.push(global.is_static as u8);
+            out.extend(global.init.to_le_bytes());
+        }
+
+        out.extend((self.variants.len() as u32).to_le_bytes());
+        for variant in &self.variants {
+            put_bytes(&mut out, variant.name.as_bytes());
+            out.extend(variant.discriminant.to_le_bytes());
+            out.extend(variant.fields.to_le_bytes());
+        }
+        out
+    }
+
+    /// Reads a module from its bytes, checking that its code is valid.
+    pub fn decode(bytes: &[u8]) -> Result<Module, DecodeError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4).ok() != Some(MAGIC.as_slice()) {
+            return Err(DecodeError::NotBytecode);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let main = Some(reader.u16()?).filter(|&main| main != u16::MAX);
+
+        let mut constants = Vec::new();
+        for _ in 0..reader.u32()? {
+            let constant = match reader.u8()? {
+                0 => Constant::Int(i128::from_le_bytes(reader.array()?)),
+                1 => Constant::Float(f64::from_le_bytes(reader.array()?)),
+                2 => Constant::Bool(reader.u8()? != 0),
+                3 => {
+                    let value = reader.u32()?;
+                    Constant::Char(char::from_u32(value).ok_or(DecodeError::InvalidConstant(3))?)
+                }
+                4 => Constant::String(reader.string()?),
+                5 => Constant::Unit,
+                tag => return Err(DecodeError::InvalidConstant(tag)),
+            };
+            constants.push(constant);
+        }
+
+        let mut functions = Vec::new();
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let params = reader.u8()?;
+            let locals = reader.u16()?;
+            let code = reader.bytes()?.to_vec();
+            let mut spans = Vec::new();
+            for _ in 0..reader.u32()? {
+                let offset = reader.u32()?;
+                let start = reader.u32()? as usize;
+                let end = reader.u32()? as usize;
+                spans.push((offset, start..end));
+            }
+            let mut vars = Vec::new();
+            for _ in 0..reader.u32()? {
+                let name = reader.string()?;
+                let mut ranges = Vec::new();
+                for _ in 0..reader.u32()? {
+                    ranges.push((reader.u32()?, reader.u32()?, reader.u16()?));
+                }
+                vars.push(Var { name, ranges });
+            }
+            functions.push(Function {
+                name,
+                params,
+                locals,
+                code,
+                spans,
+                vars,
+            });
+        }
+
+        let mut globals = Vec::new();
+        for _ in 0..reader.u32()? {
+            globals.push(Global {
+                name: reader.string()?,
+                is_static: reader.u8()? != 0,
+                init: reader.u16()?,
+            });
+        }
+
+        let mut variants = Vec::new();
+        for _ in 0..reader.u32()? {
+            variants.push(Variant {
+                name: reader.string()?,
+                discriminant: reader.u16()?,
+                fields: reader.u16()?,
+            });
+        }
+
+        let module = Module {
+            constants,
+            functions,
+            globals,
+            variants,
+            main,
+        };
+        module.check()?;
+        Ok(module)
+    }
+
+    /// Checks that the code of every function decodes, and that its instructions only refer to
+    /// things that exist.
+    fn check(&self) -> Result<(), DecodeError> {
+        let functions = self.functions.len();
+        if self.main.is_some_and(|main| main as usize >= functions)
+            || self.globals.iter().any(|g| g.init as usize >= functions)
+        {
+            return Err(DecodeError::OutOfRange {
+                func: String::new(),
+                offset: 0,
+            });
+        }
+        for func in &self.functions {
+            let mut ranges = func.vars.iter().flat_map(|var| &var.ranges);
+            if let Some(&(start, ..)) = ranges.find(|&&(.., slot)| slot >= func.locals) {
+                return Err(DecodeError::OutOfRange {
+                    func: func.name.clone(),
+                    offset: start as usize,
+                });
+            }
+            let mut offset = 0;
+            let mut starts = Vec::new();
+            let mut jumps = Vec::new();
+            while offset < func.code.len() {
+                let (op, next) = Op::decode(&func.code, offset)?;
+                let valid = match op {
+                    Op::Const(index) => (index as usize) < self.constants.len(),
+                    Op::Extern { name, .. } => {
+                        matches!(self.constants.get(name as usize), Some(Constant::String(_)))
+                    }
+                    Op::Load(slot) | Op::Store(slot) => slot < func.locals,
+                    Op::Construct { variant, .. } | Op::VariantField { variant, .. } => {
+                        (variant as usize) < self.variants.len()
+                    }
+                    Op::Resume { some, none } => (some.max(none) as usize) < self.variants.len(),
+                    Op::Global(index) | Op::SetGlobal(index) => {
+                        (index as usize) < self.globals.len()
+                    }
+                    Op::Function(index)
+                    | Op::Closure { func: index, .. }
+                    | Op::Call { func: index, .. }
+                    | Op::TailCall { func: index, .. }
+                    | Op::Generator { func: index, .. } => (index as usize) < functions,
+                    Op::Jump(target) | Op::JumpIfFalse(target) => {
+                        jumps.push((offset, target as usize));
+                        true
+                    }
+                    _ => true,
+                };
+                if !valid {
+                    return Err(DecodeError::OutOfRange {
+                        func: func.name.clone(),
+                        offset,
+                    });
+                }
+                starts.push(offset);
+                offset = next;
+            }
+            // Jumps have to land on the start of an instruction
+            if let Some(&(offset, _)) = jumps
+                .iter()
+                .find(|(_, target)| starts.binary_search(target).is_err())
+            {
+                return Err(DecodeError::OutOfRange {
+                    func: func.name.clone(),
+                    offset,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints a module as text, with the offset of each instruction. Functions are named `fn0`,
+/// `fn1` and so on, and globals `g0`, `g1`, as in the MIR they're emitted from.
+pub fn disassemble(module: &Module) -> String {
+    let mut out = String::new();
+    for (i, global) in module.globals.iter().enumerate() {
+        let keyword = if global.is_static { "static" } else { "const" };
+        out.push_str(&format!(
+            "{} g{} {} = fn{}\n",
+            keyword, i, global.name, global.init
+        ));
+    }
+    for (i, func) in module.functions.iter().enumerate() {
+        if i > 0 || !module.globals.is_empty() {
+            out.push('\n');
+        }
+        let main = if module.main == Some(i as u16) {
+            " (main)"
+        } else {
+            ""
+        };
+        out.push_str(&format!(
+            "fn{} {}{}: {} params, {} locals\n",
+            i, func.name, main, func.params, func.locals
+        ));
+        for (offset, op) in func.ops() {
+            out.push_str(&format!("    {:04}  {}\n", offset, op_text(module, op)));
+        }
+    }
+    out
+}
+
+fn constant_text(constant: &Constant) -> String {
+    match constant {
+        Constant::Int(value) => value.to_string(),
+        Constant::Float(value) => format!("{:?}", value),
+        Constant::Bool(value) => value.to_string(),
+        Constant::Char(value) => format!("{:?}", value),
+        Constant::String(value) => format!("{:?}", value),
+        Constant::Unit => "()".to_string(),
+    }
+}
+
+fn op_text(module: &Module, op: Op) -> String {
+    let variant = |index: u16| &module.variants[index as usize].name;
+    let in_place_text = |in_place: bool| if in_place { " in_place" } else { "" };
+    match op {
+        Op::Const(index) => format!(
+            "const {} ({})",
+            index,
+            constant_text(&module.constants[index as usize])
+        ),
+        Op::Load(slot) => format!("load {}", slot),
+        Op::Store(slot) => format!("store {}", slot),
+        Op::Pop => "pop".to_string(),
+        Op::Unary { op, kind } => format!("unary {} {}", op.as_str(), kind.name()),
+        Op::Binary { op, kind } => format!("binary {} {}", op.as_str(), kind.name()),
+        Op::Cast { from, to } => format!("cast {} -> {}", from.name(), to.name()),
+        Op::Tuple(len) => format!("tuple {}", len),
+        Op::Array(len) => format!("array {}", len),
+        Op::Construct {
+            variant: index,
+            fields,
+        } => format!("construct {} {}", variant(index), fields),
+        Op::Field(index) => format!("field {}", index),
+        Op::SetField(index) => format!("set_field {}", index),
+        Op::Discriminant => "discriminant".to_string(),
+        Op::VariantField {
+            variant: index,
+            index: field,
+        } => format!("variant_field {} {}", variant(index), field),
+        Op::Index => "index".to_string(),
+        Op::SetIndex => "set_index".to_string(),
+        Op::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let bound = |has: bool| if has { "_" } else { "" };
+            let op = if inclusive { "..=" } else { ".." };
+            format!("range {}{}{}", bound(start), op, bound(end))
+        }
+        Op::Global(index) => format!("global g{}", index),
+        Op::SetGlobal(index) => format!("set_global g{}", index),
+        Op::Function(index) => format!("function fn{}", index),
+        Op::Closure { func, captures } => format!("closure fn{} {}", func, captures),
+        Op::Call { func, args } => format!("call fn{} {}", func, args),
+        Op::CallIndirect(args) => format!("call_indirect {}", args),
+        Op::Extern { name, args, ret } => format!(
+            "extern {} ({}) {} {}",
+            name,
+            constant_text(&module.constants[name as usize]),
+            args,
+            ret.name()
+        ),
+        Op::TailCall { func, args } => format!("tail_call fn{} {}", func, args),
+        Op::Generator { func, args } => format!("generator fn{} {}", func, args),
+        Op::Resume { some, none } => format!("resume {} {}", variant(some), variant(none)),
+        Op::Yield => "yield".to_string(),
+        Op::Len => "len".to_string(),
+        Op::Chars => "chars".to_string(),
+        Op::Panic => "panic".to_string(),
+        Op::Print => "print".to_string(),
+        Op::Println => "println".to_string(),
+        Op::Input => "input".to_string(),
+        Op::StringLen => "string_len".to_string(),
+        Op::CharAt => "char_at".to_string(),
+        Op::Slice => "slice".to_string(),
+        Op::Contains => "contains".to_string(),
+        Op::Split => "split".to_string(),
+        Op::ToUpper => "to_upper".to_string(),
+        Op::ToLower => "to_lower".to_string(),
+        Op::ArrayPush { in_place } => format!("array_push{}", in_place_text(in_place)),
+        Op::ArrayPop { in_place } => format!("array_pop{}", in_place_text(in_place)),
+        Op::NewMap => "new_map".to_string(),
+        Op::MapInsert { in_place } => format!("map_insert{}", in_place_text(in_place)),
+        Op::MapFind => "map_find".to_string(),
+        Op::EntryKey => "entry_key".to_string(),
+        Op::EntryValue => "entry_value".to_string(),
+        Op::MapRemove { in_place } => format!("map_remove{}", in_place_text(in_place)),
+        Op::Sqrt => "sqrt".to_string(),
+        Op::Floor => "floor".to_string(),
+        Op::Ceil => "ceil".to_string(),
+        Op::Pow => "pow".to_string(),
+        Op::ReadFile => "read_file".to_string(),
+        Op::WriteFile => "write_file".to_string(),
+        Op::FileExists => "file_exists".to_string(),
+        Op::FsError => "fs_error".to_string(),
+        Op::Jump(target) => format!("jump {:04}", target),
+        Op::JumpIfFalse(target) => format!("jump_if_false {:04}", target),
+        Op::Return => "return".to_string(),
+        Op::Unreachable => "unreachable".to_string(),
+    }
+}
@@ -0,0 +1,314 @@
+//! Rewrites short sequences of instructions in emitted bytecode into shorter ones, until there are
+//! none left to rewrite.
+//!
+//! A value that's stored and loaded right back stays on the stack instead, a store to a slot that's
+//! never loaded again becomes a `pop`, and a value pushed only to be popped isn't pushed.
+//! Comparisons of constants are folded, and so are jumps on them. Which slots are loaded again
+//! comes from the liveness of each slot over the function's code, so that a slot a loop loads
+//! again keeps its stores. A sequence is only rewritten if nothing jumps into the middle of it. The values of
+//! variables that stay on the stack aren't in their slots anymore, so only code that wasn't
+//! rewritten shows a debugger all of them.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use super::*;
+
+/// Rewrites the code of every function of a module.
+pub fn peephole(module: &mut Module) {
+    let mut constants = Constants {
+        ids: (module.constants.iter().enumerate())
+            .map(|(i, constant)| (constant.clone(), i as u16))
+            .collect(),
+        constants: &mut module.constants,
+    };
+    for func in &mut module.functions {
+        let mut ops: Vec<(usize, Op)> = func.ops().collect();
+        while rewrite(&mut ops, &mut constants) {}
+        let (code, offsets) = encode(&ops, func.code.len());
+        func.code = code;
+        // Slots whose stores and loads were all removed aren't needed
+        let slots = (ops.iter())
+            .filter_map(|(_, op)| match op {
+                Op::Load(slot) | Op::Store(slot) => Some(slot + 1),
+                _ => None,
+            })
+            .max();
+        func.locals = slots.unwrap_or(0).max(func.params.into());
+        let mut spans: Vec<(u32, Span)> = Vec::new();
+        for (start, span) in std::mem::take(&mut func.spans) {
+            let start = offsets[&(start as usize)] as u32;
+            // A span whose code was removed is replaced by the next one
+            if spans.last().is_some_and(|(last, _)| *last == start) {
+                spans.pop();
+            }
+            if (start as usize) < func.code.len() {
+                spans.push((start, span));
+            }
+        }
+        func.spans = spans;
+        let locals = func.locals;
+        for var in &mut func.vars {
+            let ranges = std::mem::take(&mut var.ranges).into_iter();
+            var.ranges = ranges
+                .map(|(start, end, slot)| {
+                    let start = offsets[&(start as usize)] as u32;
+                    (start, offsets[&(end as usize)] as u32, slot)
+                })
+                .filter(|&(start, end, slot)| start < end && slot < locals)
+                .collect();
+        }
+    }
+}
+
+/// The constants of the module, which folding adds to.
+struct Constants<'a> {
+    constants: &'a mut Vec<Constant>,
+    ids: HashMap<Constant, u16>,
+}
+
+impl Constants<'_> {
+    fn get(&self, id: u16) -> &Constant {
+        &self.constants[id as usize]
+    }
+
+    fn add(&mut self, constant: Constant) -> u16 {
+        if let Some(&id) = self.ids.get(&constant) {
+            return id;
+        }
+        let id = u16::try_from(self.constants.len()).expect("program is too big for bytecode");
+        self.constants.push(constant.clone());
+        self.ids.insert(constant, id);
+        id
+    }
+}
+
+/// Rewrites each sequence that can be once, returning whether any was. Each instruction keeps the
+/// offset it had in the original code, which jumps still refer to.
+fn rewrite(ops: &mut Vec<(usize, Op)>, constants: &mut Constants) -> bool {
+    let targets: HashSet<usize> = (ops.iter())
+        .filter_map(|(_, op)| match op {
+            Op::Jump(target) | Op::JumpIfFalse(target) => Some(*target as usize),
+            _ => None,
+        })
+        .collect();
+    let live = live_after(ops);
+    let is_live = |i: usize, slot: &u16| live[i].contains(slot);
+
+    let mut out = Vec::with_capacity(ops.len());
+    let mut changed = false;
+    let mut i = 0;
+    while i < ops.len() {
+        let (start, op) = ops[i];
+        // The instructions after this one in the same sequence, which nothing jumps to
+        let next = |n: usize| {
+            (1..=n)
+                .all(|k| {
+                    ops.get(i + k)
+                        .is_some_and(|(start, _)| !targets.contains(start))
+                })
+                .then(|| ops[i + n].1)
+        };
+        let (replacement, len) = match (op, next(1), next(2), next(3)) {
+            (Op::Store(slot), Some(Op::Load(load)), ..)
+                if slot == load && !is_live(i + 1, &slot) =>
+            {
+                (vec![], 2)
+            }
+            // The right operand of a binary instruction, stored before the left one is loaded
+            (
+                Op::Const(_) | Op::Load(_),
+                Some(Op::Store(slot)),
+                Some(Op::Load(lhs)),
+                Some(Op::Load(rhs)),
+            ) if slot == rhs && slot != lhs && op != Op::Load(slot) && !is_live(i + 3, &slot) => {
+                (vec![Op::Load(lhs), op], 4)
+            }
+            (Op::Store(slot), ..) if !is_live(i, &slot) => (vec![Op::Pop], 1),
+            (Op::Load(slot), Some(Op::Store(store)), ..) if slot == store => (vec![], 2),
+            (Op::Const(_) | Op::Load(_) | Op::Global(_) | Op::Function(_), Some(Op::Pop), ..) => {
+                (vec![], 2)
+            }
+            (Op::Const(lhs), Some(Op::Const(rhs)), Some(Op::Binary { op: binary, .. }), _) => {
+                match compare(binary, constants.get(lhs), constants.get(rhs)) {
+                    Some(result) => (vec![Op::Const(constants.add(Constant::Bool(result)))], 3),
+                    None => (vec![op], 1),
+                }
+            }
+            (Op::Const(cond), Some(Op::JumpIfFalse(target)), ..) => match constants.get(cond) {
+                Constant::Bool(true) => (vec![], 2),
+                Constant::Bool(false) => (vec![Op::Jump(target)], 2),
+                _ => (vec![op], 1),
+            },
+            // A jump to the next instruction
+            (Op::Jump(target), ..)
+                if ops.get(i + 1).map(|(start, _)| *start) == Some(target as usize) =>
+            {
+                (vec![], 1)
+            }
+            _ => (vec![op], 1),
+        };
+        changed |= replacement != [op] || len != 1;
+        // What replaces a sequence starts where it did
+        out.extend(replacement.into_iter().map(|op| (start, op)));
+        i += len;
+    }
+    // Jumps to instructions that were removed go to the next one left
+    let starts: Vec<usize> = out.iter().map(|(start, _)| *start).collect();
+    let retarget = |target: &mut u32| {
+        if let Some(&start) = starts.get(starts.partition_point(|&start| start < *target as usize))
+        {
+            *target = start as u32;
+        }
+    };
+    for (_, op) in &mut out {
+        if let Op::Jump(target) | Op::JumpIfFalse(target) = op {
+            retarget(target);
+        }
+    }
+    *ops = out;
+    changed
+}
+
+/// Compares two constants as a comparison instruction would, if it's one that can be folded.
+fn compare(op: BinaryOp, lhs: &Constant, rhs: &Constant) -> Option<bool> {
+    let ordering = match (lhs, rhs) {
+        (Constant::Int(lhs), Constant::Int(rhs)) => lhs.cmp(rhs),
+        (Constant::Bool(lhs), Constant::Bool(rhs)) => lhs.cmp(rhs),
+        (Constant::Char(lhs), Constant::Char(rhs)) => lhs.cmp(rhs),
+        _ => return None,
+    };
+    Some(match op {
+        BinaryOp::Eq => ordering.is_eq(),
+        BinaryOp::Ne => ordering.is_ne(),
+        BinaryOp::Lt => ordering.is_lt(),
+        BinaryOp::Le => ordering.is_le(),
+        BinaryOp::Gt => ordering.is_gt(),
+        BinaryOp::Ge => ordering.is_ge(),
+        _ => return None,
+    })
+}
+
+/// Returns the slots that may be loaded again after each instruction, before they're stored to.
+fn live_after(ops: &[(usize, Op)]) -> Vec<BTreeSet<u16>> {
+    // Where each offset's code starts, since what replaced a sequence shares its offset
+    let mut index = HashMap::new();
+    for (i, (start, _)) in ops.iter().enumerate() {
+        index.entry(*start).or_insert(i);
+    }
+    let succs = |i: usize| -> Vec<usize> {
+        match ops[i].1 {
+            Op::Jump(target) => vec![index[&(target as usize)]],
+            Op::JumpIfFalse(target) => vec![i + 1, index[&(target as usize)]],
+            Op::Return | Op::TailCall { .. } | Op::Unreachable => Vec::new(),
+            _ => vec![i + 1],
+        }
+    };
+    let mut live_before = vec![BTreeSet::new(); ops.len()];
+    let mut live_after = vec![BTreeSet::new(); ops.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..ops.len()).rev() {
+            let after: BTreeSet<u16> = (succs(i).into_iter())
+                .filter(|&succ| succ < ops.len())
+                .flat_map(|succ| live_before[succ].iter().copied())
+                .collect();
+            let mut before = after.clone();
+            match ops[i].1 {
+                Op::Store(slot) => {
+                    before.remove(&slot);
+                }
+                Op::Load(slot) => {
+                    before.insert(slot);
+                }
+                _ => {}
+            }
+            if before != live_before[i] {
+                live_before[i] = before;
+                changed = true;
+            }
+            live_after[i] = after;
+        }
+    }
+    live_after
+}
+
+/// Encodes rewritten instructions, returning the code with the new offset of each offset in the
+/// original code, up to its length.
+fn encode(ops: &[(usize, Op)], len: usize) -> (Vec<u8>, HashMap<usize, usize>) {
+    // An offset whose instruction was removed is now the offset of the next one left
+    let mut offsets = HashMap::new();
+    let mut code = Vec::new();
+    let mut old = 0;
+    for (start, op) in ops {
+        while old <= *start {
+            offsets.insert(old, code.len());
+            old += 1;
+        }
+        op.encode(&mut code);
+    }
+    while old <= len {
+        offsets.insert(old, code.len());
+        old += 1;
+    }
+    let mut out = Vec::with_capacity(code.len());
+    for (_, op) in ops {
+        let op = match *op {
+            Op::Jump(target) => Op::Jump(offsets[&(target as usize)] as u32),
+            Op::JumpIfFalse(target) => Op::JumpIfFalse(offsets[&(target as usize)] as u32),
+            op => op,
+        };
+        op.encode(&mut out);
+    }
+    (out, offsets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+
+    #[test]
+    fn test_peephole() {
+        let source = "
+fn count(n: int) int {
+    let mut i = 0;
+    while i < n { i += 1; }
+    i
+}
+fn main() {
+    if 1 < 2 {
+        count(3);
+    }
+}";
+        let mut module = emit(&built(source));
+        peephole(&mut module);
+        assert_eq!(
+            disassemble(&module),
+            "fn0 count: 1 params, 2 locals
+    0000  const 0 (0)
+    0003  store 1
+    0006  load 1
+    0009  load 0
+    0012  binary < i32
+    0015  jump_if_false 0037
+    0020  load 1
+    0023  const 1 (1)
+    0026  binary + i32
+    0029  store 1
+    0032  jump 0006
+    0037  load 1
+    0040  return
+
+fn1 main (main): 0 params, 0 locals
+    0000  const 3 (3)
+    0003  call fn0 1
+    0007  pop
+    0008  const 4 (())
+    0011  return
+"
+        );
+        let bytes = module.encode();
+        assert_eq!(Module::decode(&bytes), Ok(module));
+    }
+}
@@ -0,0 +1,84 @@
+//! Counts the instructions that the VM runs, by where they are in the code and by the chain of
+//! calls that ran them, so that a profiler can tell which functions and lines a program spends its
+//! time in. Counting instructions rather than sampling the time keeps a profile the same from one
+//! run to the next, at the cost of treating every instruction as equally slow.
+
+use std::collections::HashMap;
+
+use super::Module;
+
+/// The instructions a program ran.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// For each function, how many times the instruction at each offset of its code ran.
+    pub counts: Vec<Vec<u64>>,
+    /// Every chain of calls that ran an instruction, where each one's parent is the chain
+    /// without its innermost call.
+    pub calls: Vec<Call>,
+    /// The chain of calls made by each function from each chain.
+    children: HashMap<(Option<usize>, u16), usize>,
+    /// The chains of the calls that are running, outermost first.
+    path: Vec<usize>,
+}
+
+/// A chain of calls, with the instructions that ran in its innermost call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub parent: Option<usize>,
+    pub func: u16,
+    pub count: u64,
+}
+
+impl Profile {
+    pub fn new(module: &Module) -> Self {
+        Profile {
+            counts: (module.functions.iter())
+                .map(|func| vec![0; func.code.len()])
+                .collect(),
+            ..Profile::default()
+        }
+    }
+
+    /// Counts an instruction at `offset` of the innermost of `depth` calls that are running,
+    /// where `func(i)` is the function of call `i`, counted from the outermost.
+    pub(super) fn count(&mut self, depth: usize, func: impl Fn(usize) -> u16, offset: usize) {
+        // Since the last instruction, at most the innermost call changed
+        self.path.truncate(depth);
+        if let Some(&call) = self.path.last() {
+            if self.calls[call].func != func(self.path.len() - 1) {
+                self.path.pop();
+            }
+        }
+        while self.path.len() < depth {
+            let parent = self.path.last().copied();
+            let func = func(self.path.len());
+            let calls = &mut self.calls;
+            let call = *self.children.entry((parent, func)).or_insert_with(|| {
+                calls.push(Call {
+                    parent,
+                    func,
+                    count: 0,
+                });
+                calls.len() - 1
+            });
+            self.path.push(call);
+        }
+        let call = &mut self.calls[*self.path.last().unwrap()];
+        call.count += 1;
+        self.counts[call.func as usize][offset] += 1;
+    }
+
+    /// Returns how many instructions ran.
+    pub fn total(&self) -> u64 {
+        self.calls.iter().map(|call| call.count).sum()
+    }
+
+    /// Returns the functions of a chain of calls, outermost first.
+    pub fn stack(&self, call: usize) -> Vec<u16> {
+        let mut funcs: Vec<_> = std::iter::successors(Some(call), |&call| self.calls[call].parent)
+            .map(|call| self.calls[call].func)
+            .collect();
+        funcs.reverse();
+        funcs
+    }
+}
@@ -0,0 +1,182 @@
+//! Assigns the values of a function to local slots by linear scan, so that values that are never
+//! live at the same time share a slot, instead of each having its own.
+//!
+//! The code is numbered in the order it's emitted, with a position for the start of each block,
+//! one for each of its instructions, one for its terminator, and one for its end. A value's
+//! interval runs from where it's stored to where it's last loaded, over every block it's live
+//! into or out of. The parameters of a block are stored by the jumps to it after they load the
+//! values they pass, so a value can share its slot with a parameter it's passed to. There's a
+//! slot for every value that needs one, so nothing is ever spilled.
+
+use std::collections::BTreeSet;
+
+use crate::mir::{BlockId, Cfg, Function, InstKind, Intrinsic, Value};
+
+/// Whether the code of an instruction stores its value. `undef` and those done for their effect
+/// don't, and their unit value stays in its slot from the start of the call, where nothing else
+/// may have been stored before it.
+fn is_stored(kind: &InstKind) -> bool {
+    !matches!(
+        kind,
+        InstKind::Undef
+            | InstKind::SetGlobal { .. }
+            | InstKind::Intrinsic {
+                intrinsic: Intrinsic::Panic | Intrinsic::Print | Intrinsic::Println,
+                ..
+            }
+    )
+}
+
+/// Returns the slot of each value, given their intervals, and the number of slots. The parameters
+/// of the function are in the first slots, where a call puts its arguments.
+pub(super) fn allocate(func: &Function, intervals: &[(usize, usize)]) -> (Vec<usize>, usize) {
+    let params = func.params();
+    let mut slots = vec![0; func.values.len()];
+    // The end of the interval of the last value in each slot
+    let mut ends = Vec::new();
+    for (i, &param) in params.iter().enumerate() {
+        slots[param.0] = i;
+        ends.push(intervals[param.0].1);
+    }
+    let mut values: Vec<_> = (0..func.values.len())
+        .map(Value)
+        .filter(|value| !params.contains(value))
+        .collect();
+    values.sort_by_key(|value| intervals[value.0].0);
+    for value in values {
+        let (start, end) = intervals[value.0];
+        let slot = match ends.iter().position(|&slot_end| slot_end <= start) {
+            Some(slot) => slot,
+            None => {
+                ends.push(0);
+                ends.len() - 1
+            }
+        };
+        ends[slot] = end;
+        slots[value.0] = slot;
+    }
+    (slots, ends.len())
+}
+
+/// Returns the interval of each value, from the first position it's live at up to, but not
+/// including, the position after the last.
+pub(super) fn intervals(func: &Function) -> Vec<(usize, usize)> {
+    let cfg = Cfg::new(func);
+    let live_in = live_in(func, &cfg);
+    let mut starts = Vec::with_capacity(func.blocks.len());
+    let mut next = 0;
+    for block in &func.blocks {
+        starts.push(next);
+        next += block.insts.len() + 3;
+    }
+    let terminator_position = |id: BlockId| starts[id.0] + func.block(id).insts.len() + 1;
+
+    let mut positions: Vec<Option<(usize, usize)>> = vec![None; func.values.len()];
+    let mut live = |value: Value, position: usize| {
+        let interval = positions[value.0].get_or_insert((position, position));
+        interval.0 = interval.0.min(position);
+        interval.1 = interval.1.max(position);
+    };
+    for id in func.block_ids() {
+        let block = func.block(id);
+        for &value in &live_in[id.0] {
+            live(value, starts[id.0]);
+        }
+        for &succ in cfg.succs(id) {
+            for &value in &live_in[succ.0] {
+                live(value, terminator_position(id) + 1);
+            }
+        }
+        for &param in &block.params {
+            live(param, starts[id.0]);
+            for &pred in cfg.preds(id) {
+                live(param, terminator_position(pred));
+            }
+        }
+        for (i, inst) in block.insts.iter().enumerate() {
+            let position = starts[id.0] + 1 + i;
+            live(inst.value, if is_stored(&inst.kind) { position } else { 0 });
+            for value in inst.kind.operands() {
+                live(value, position);
+            }
+        }
+        let mut terminator = block.terminator.clone();
+        terminator.operands_mut(|value| live(*value, terminator_position(id)));
+    }
+    // A value that's stored and never loaded still needs its slot while it's stored, so that it
+    // doesn't overwrite the parameters stored by the same jump
+    (positions.into_iter())
+        .map(|interval| {
+            let (start, last) = interval.unwrap_or((0, 0));
+            (start, last.max(start + 1))
+        })
+        .collect()
+}
+
+/// Returns the values that are live at the start of each block, other than its parameters.
+pub(super) fn live_in(func: &Function, cfg: &Cfg) -> Vec<BTreeSet<Value>> {
+    let mut live_in = vec![BTreeSet::new(); func.blocks.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in (0..func.blocks.len()).rev().map(BlockId) {
+            let block = func.block(id);
+            let mut live: BTreeSet<Value> = (cfg.succs(id).iter())
+                .flat_map(|succ| live_in[succ.0].iter().copied())
+                .collect();
+            let mut terminator = block.terminator.clone();
+            terminator.operands_mut(|value| {
+                live.insert(*value);
+            });
+            for inst in block.insts.iter().rev() {
+                live.remove(&inst.value);
+                live.extend(inst.kind.operands());
+            }
+            for param in &block.params {
+                live.remove(param);
+            }
+            if live != live_in[id.0] {
+                live_in[id.0] = live;
+                changed = true;
+            }
+        }
+    }
+    live_in
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+
+    #[test]
+    fn test_allocate() {
+        let source = "
+fn fib(n: int) int {
+    let mut a = 0;
+    let mut b = 1;
+    let mut i = 0;
+    while i < n {
+        let next = a + b;
+        a = b;
+        b = next;
+        i += 1;
+    }
+    a
+}";
+        let program = built(source);
+        let func = &program.fns[0];
+        let intervals = intervals(func);
+        let (slots, count) = allocate(func, &intervals);
+        // `n`, the three variables of the loop, and the two values its body computes
+        assert_eq!(count, 6);
+        // Values that are live at the same time never share a slot
+        for (a, &(a_start, a_end)) in intervals.iter().enumerate() {
+            for (b, &(b_start, b_end)) in intervals.iter().enumerate().skip(a + 1) {
+                if a_start < b_end && b_start < a_end {
+                    assert_ne!(slots[a], slots[b], "v{} and v{}", a, b);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1751 @@
+//! A virtual machine that runs a module's bytecode, the fastest way to run a program without
+//! compiling it to native code.
+//!
+//! The code of each function is decoded once, before the program starts, with its jumps turned
+//! into the indices of the instructions they go to, and a dispatch loop runs it. Every call that's
+//! running has a frame on one stack of values, which holds its local slots and then the operands
+//! of its instructions. A call pushes a frame instead of recursing in the VM, so that how deep
+//! calls can nest only depends on the memory the stack takes, and a `tail_call` reuses the frame of
+//! the function that makes it. Resuming a generator puts its frame back on the stack, above the
+//! generator itself, and yielding takes it off again and keeps it in the generator.
+//!
+//! Every value that isn't a number, `bool`, `char`, `()` or function is an object on the VM's
+//! [`Heap`], which its garbage collector frees once nothing refers to it. The VM stops a program
+//! that runs more instructions, nests more calls or keeps more objects than its [`Limits`] allow.
+//!
+//! A [`Debugger`] can watch a program as it runs, which the VM pauses for before each instruction
+//! so that it can look at the calls that are running and the values in their slots, and a
+//! [`Profile`] counts the instructions it runs. A [`Reloader`] patches new versions of the module
+//! into it as it runs. The module has to be emitted from a checked program, since an instruction
+//! that gets an operand of the wrong type stops the VM.
+
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use super::{
+    gc::{GcConfig, GenState, Heap, Object, Ref},
+    profile::Profile,
+    *,
+};
+use crate::{
+    codegen::runtime::{DIVISION_BY_ZERO, OVERFLOW, SHIFT_OUT_OF_RANGE},
+    console::Console,
+    ffi::{self, Externs},
+    fs::Files,
+    interpreter::Panic,
+    limits::{Limit, Limits},
+    maps::Map,
+    strings,
+    typeck::eval_int,
+};
+
+/// How many calls can be running at once before the program is stopped.
+const MAX_FRAMES: usize = 100_000;
+
+/// A value of a running program, which is copied without copying the object it refers to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Unit,
+    Function(u16),
+    /// A string, tuple, struct, enum value, array, range or closure.
+    Object(Ref),
+}
+
+/// Runs a module: initializes its globals in order, then calls its `main`. Its `extern`
+/// functions call those of `host`.
+pub fn run<'a>(
+    module: &'a Module,
+    config: GcConfig,
+    limits: Limits,
+    console: Console<'a>,
+    host: &'a Externs,
+) -> Result<(), Panic> {
+    let mut vm = Vm::new(module, config, limits, console, host)?;
+    if let Some(main) = module.main {
+        vm.call(main, Vec::new())?;
+    }
+    Ok(())
+}
+
+/// The code of a function, decoded.
+struct Code {
+    ops: Vec<Op>,
+    /// The offset of each instruction in the encoded code, which spans refer to.
+    offsets: Vec<usize>,
+}
+
+/// A call that's running.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    func: usize,
+    /// The index of the next instruction.
+    pc: usize,
+    /// Where the call's local slots start on the stack.
+    base: usize,
+    /// For the frame of a generator, what it yields is wrapped in.
+    resumed: Option<Resumed>,
+}
+
+/// The variants of `Option` that a generator's frame gives the code that resumed it: `some` with
+/// a value it yields, and `none` when it returns.
+#[derive(Debug, Clone, Copy)]
+struct Resumed {
+    some: u16,
+    none: u16,
+}
+
+/// Watches a program as the VM runs it.
+pub trait Debugger {
+    /// Called before the VM runs the instruction at `offset` of a function, in the innermost call.
+    /// The program stops with the message the debugger fails with.
+    fn pause(&mut self, vm: &Vm, func: u16, offset: usize) -> Result<(), String>;
+}
+
+/// Patches new versions of its module into the VM as it runs.
+pub trait Reloader {
+    /// Called every [`RELOAD_INTERVAL`] instructions, to patch the VM with [`Vm::reload`]. The
+    /// program stops with the message the reloader fails with.
+    fn poll(&mut self, vm: &mut Vm) -> Result<(), String>;
+}
+
+/// How many instructions the VM runs between polls of its reloader.
+pub const RELOAD_INTERVAL: u32 = 1 << 16;
+
+/// A call that's running, as a debugger sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    pub func: u16,
+    /// The offset of the instruction the call is at: the one that runs next in the innermost
+    /// call, and the call of the next one in for the others.
+    pub offset: usize,
+    /// Where its slots start on the stack.
+    base: usize,
+}
+
+/// The state of a running module.
+pub struct Vm<'a> {
+    /// The module, which the VM has its own copy of once it's patched.
+    module: Cow<'a, Module>,
+    code: Vec<Code>,
+    /// The constants, whose strings are on the heap for as long as the VM runs.
+    constants: Vec<Value>,
+    /// The value of each global, once it's initialized.
+    globals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+    /// The calls that are running, innermost last.
+    frames: Vec<Frame>,
+    heap: Heap,
+    limits: Limits,
+    /// The instructions that the call from the host that's running can still run.
+    fuel: Option<u64>,
+    debugger: Option<&'a mut dyn Debugger>,
+    profile: Option<Profile>,
+    reloader: Option<&'a mut dyn Reloader>,
+    /// The instructions left to run before the reloader is polled.
+    until_reload: u32,
+    console: Console<'a>,
+    files: Files,
+    host: &'a Externs,
+}
+
+impl<'a> Vm<'a> {
+    /// Makes a VM for a module, decoding its code and initializing its globals in order.
+    pub fn new(
+        module: &'a Module,
+        config: GcConfig,
+        limits: Limits,
+        console: Console<'a>,
+        host: &'a Externs,
+    ) -> Result<Self, Panic> {
+        let mut heap = Heap::new(config);
+        let constants = (module.constants.iter())
+            .map(|constant| constant_value(&mut heap, constant))
+            .collect();
+        let mut vm = Vm {
+            module: Cow::Borrowed(module),
+            code: module.functions.iter().map(decode).collect(),
+            constants,
+            globals: vec![None; module.globals.len()],
+            stack: Vec::new(),
+            frames: Vec::new(),
+            heap,
+            limits,
+            fuel: limits.fuel,
+            debugger: None,
+            profile: None,
+            reloader: None,
+            until_reload: RELOAD_INTERVAL,
+            console,
+            files: Files::default(),
+            host,
+        };
+        for (i, global) in module.globals.iter().enumerate() {
+            vm.globals[i] = Some(vm.call(global.init, Vec::new())?);
+        }
+        Ok(vm)
+    }
+
+    /// Calls a function of the module with its arguments. The objects of the value it returns
+    /// are only kept until the next collection, unless they're passed to another call. A call
+    /// from the host starts with all of the fuel of the limits.
+    pub fn call(&mut self, func: u16, args: Vec<Value>) -> Result<Value, Panic> {
+        let depth = self.frames.len();
+        if depth == 0 {
+            self.fuel = self.limits.fuel;
+        }
+        let base = self.stack.len();
+        let len = args.len();
+        self.stack.extend(args);
+        let result = match self.enter(func as usize, len) {
+            Ok(_) => self.execute(depth),
+            Err(limit) => Err(Panic::exceeded(limit, Span::default())),
+        };
+        // A call that failed leaves its frames behind
+        self.frames.truncate(depth);
+        self.stack.truncate(base);
+        result
+    }
+
+    /// Puts an object on the heap, as a value that refers to it.
+    pub fn alloc(&mut self, object: Object) -> Value {
+        Value::Object(self.heap.alloc(object))
+    }
+
+    /// Returns the object a value refers to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value isn't on the heap.
+    pub fn object(&self, value: Value) -> &Object {
+        match value {
+            Value::Object(object) => self.heap.get(object),
+            _ => unreachable!("only the values of objects refer to them"),
+        }
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// Pauses every call from now on before each instruction, for a debugger to look at it.
+    pub fn set_debugger(&mut self, debugger: &'a mut dyn Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Counts every instruction from now on in a profile, which replaces any it was counting.
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::new(&self.module));
+    }
+
+    /// Stops counting instructions, returning the profile they were counted in.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Polls a reloader every so often from now on, which patches the VM as it runs.
+    pub fn set_reloader(&mut self, reloader: &'a mut dyn Reloader) {
+        self.reloader = Some(reloader);
+    }
+
+    /// Returns the module the VM runs, with the patches it's had.
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Patches a new version of the module into the VM, returning the functions that it changes
+    /// or adds. Functions are matched by name, in order for the ones that share one. The calls
+    /// that are running and the generators that have been made finish with the code they started
+    /// with, under another index, and every call from then on runs the new code.
+    ///
+    /// The globals keep their values, so the new module has to have the same globals, structs and
+    /// enums, and each function that it changes the same number of parameters, which is all the
+    /// VM can check: the types of the values are up to the caller.
+    pub fn reload(&mut self, new: &Module) -> Result<Vec<u16>, String> {
+        let module = &*self.module;
+        if new.variants != module.variants {
+            return Err("its structs and enums changed".to_string());
+        }
+        let globals = |module: &Module| -> Vec<(String, bool)> {
+            (module.globals.iter())
+                .map(|global| (global.name.clone(), global.is_static))
+                .collect()
+        };
+        if globals(new) != globals(module) {
+            return Err("its globals changed".to_string());
+        }
+        let mut named: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, func) in module.functions.iter().enumerate() {
+            named.entry(&func.name).or_default().push(i);
+        }
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        let mut funcs = Vec::new();
+        let mut len = module.functions.len();
+        for func in &new.functions {
+            let nth = seen.entry(&func.name).or_default();
+            let live = named
+                .get(func.name.as_str())
+                .and_then(|live| live.get(*nth));
+            *nth += 1;
+            match live {
+                Some(&live) if module.functions[live].params != func.params => {
+                    let message = format!("`{}` takes another number of parameters", func.name);
+                    return Err(message);
+                }
+                Some(&live) => funcs.push(live),
+                None => {
+                    funcs.push(len);
+                    len += 1;
+                }
+            }
+        }
+        // Each function it changes may need another index for the code that's running
+        if len + new.functions.len() > u16::MAX as usize + 1 {
+            return Err("it has too many functions".to_string());
+        }
+        let funcs: Vec<u16> = funcs.into_iter().map(|func| func as u16).collect();
+
+        // The constants that the VM already has are shared
+        let mut constants = Vec::new();
+        for constant in &new.constants {
+            let index = match self.module.constants.iter().position(|c| c == constant) {
+                Some(index) => index,
+                None => {
+                    let value = constant_value(&mut self.heap, constant);
+                    self.constants.push(value);
+                    self.module.to_mut().constants.push(constant.clone());
+                    self.constants.len() - 1
+                }
+            };
+            let index = u16::try_from(index).map_err(|_| "it has too many constants")?;
+            constants.push(index);
+        }
+
+        let relocated = new.functions.iter().zip(&funcs).map(|(func, &live)| {
+            let mut func = func.clone();
+            let mut code = Vec::with_capacity(func.code.len());
+            for (_, mut op) in func.ops() {
+                relocate(&mut op, &constants, &funcs);
+                op.encode(&mut code);
+            }
+            func.code = code;
+            (live as usize, func)
+        });
+        // The functions it adds take the indices after the VM's, before any code that's running
+        // is moved
+        let (added, changed): (Vec<_>, Vec<_>) =
+            relocated.partition(|&(live, _)| live >= self.module.functions.len());
+        let mut patched = Vec::new();
+        for (live, func) in added {
+            if let Some(profile) = &mut self.profile {
+                profile.counts.push(vec![0; func.code.len()]);
+            }
+            self.code.push(decode(&func));
+            self.module.to_mut().functions.push(func);
+            patched.push(live as u16);
+        }
+        for (live, func) in changed {
+            if self.module.functions[live] == func {
+                continue;
+            }
+            let started = |object: &Object| {
+                matches!(object, Object::Generator { func, state }
+                    if *func as usize == live && *state != GenState::Finished)
+            };
+            let code = std::mem::replace(&mut self.code[live], decode(&func));
+            let running = self.frames.iter().any(|frame| frame.func == live);
+            if running || self.heap.objects_mut().any(|object| started(object)) {
+                let old = self.module.functions.len();
+                let module = self.module.to_mut();
+                module.functions.push(module.functions[live].clone());
+                self.code.push(code);
+                for frame in self.frames.iter_mut().filter(|frame| frame.func == live) {
+                    frame.func = old;
+                }
+                for object in self.heap.objects_mut().filter(|object| started(object)) {
+                    if let Object::Generator { func, .. } = object {
+                        *func = old as u16;
+                    }
+                }
+                if let Some(profile) = &mut self.profile {
+                    let counts = std::mem::take(&mut profile.counts[live]);
+                    profile.counts.push(counts);
+                }
+            }
+            if let Some(profile) = &mut self.profile {
+                profile.counts[live] = vec![0; func.code.len()];
+            }
+            self.module.to_mut().functions[live] = func;
+            patched.push(live as u16);
+        }
+        let module = self.module.to_mut();
+        for (global, new) in module.globals.iter_mut().zip(&new.globals) {
+            global.init = funcs[new.init as usize];
+        }
+        module.main = new.main.map(|main| funcs[main as usize]);
+        Ok(patched)
+    }
+
+    /// Returns the calls that are running, innermost first.
+    pub fn call_frames(&self) -> Vec<CallFrame> {
+        let innermost = self.frames.len().saturating_sub(1);
+        (self.frames.iter().enumerate().rev())
+            .map(|(i, frame)| {
+                // The others have gone past the instructions that call the next ones in
+                let pc = if i == innermost {
+                    frame.pc
+                } else {
+                    frame.pc - 1
+                };
+                CallFrame {
+                    func: frame.func as u16,
+                    offset: self.code[frame.func].offsets[pc],
+                    base: frame.base,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the value in a slot of a call that's running.
+    pub fn slot(&self, frame: &CallFrame, slot: u16) -> Value {
+        self.stack[frame.base + slot as usize]
+    }
+
+    /// Describes a value the way it's written in the source, with the names of structs and
+    /// variants but not of their fields.
+    pub fn describe(&self, value: Value) -> String {
+        let list = |values: &mut dyn Iterator<Item = &Value>| {
+            let values: Vec<_> = values.map(|&value| self.describe(value)).collect();
+            values.join(", ")
+        };
+        let name = |func: u16| &self.module.functions[func as usize].name;
+        match value {
+            Value::Int(value) => value.to_string(),
+            Value::Float(value) => format!("{:?}", value),
+            Value::Bool(value) => value.to_string(),
+            Value::Char(value) => format!("{:?}", value),
+            Value::Unit => "()".to_string(),
+            Value::Function(func) => format!("fn {}", name(func)),
+            Value::Object(_) => match self.object(value) {
+                Object::String(string) => format!("{:?}", string),
+                Object::Tuple(values) if values.len() == 1 => {
+                    format!("({},)", list(&mut values.iter()))
+                }
+                Object::Tuple(values) => format!("({})", list(&mut values.iter())),
+                Object::Variant { variant, fields } => {
+                    let name = &self.module.variants[*variant as usize].name;
+                    match fields.is_empty() {
+                        true => name.clone(),
+                        false => format!("{}({})", name, list(&mut fields.iter())),
+                    }
+                }
+                Object::Array(values) => format!("[{}]", list(&mut values.iter())),
+                Object::Map(map) => {
+                    let entries: Vec<_> = (map.entries().iter())
+                        .map(|entry| {
+                            let key = self.describe(entry.key);
+                            format!("{}: {}", key, self.describe(entry.value))
+                        })
+                        .collect();
+                    format!("{{{}}}", entries.join(", "))
+                }
+                Object::Range { bounds, inclusive } => {
+                    let [start, end] = bounds.map(|bound| match bound {
+                        Some(bound) => self.describe(bound),
+                        None => String::new(),
+                    });
+                    let dots = if *inclusive { "..=" } else { ".." };
+                    format!("{}{}{}", start, dots, end)
+                }
+                Object::Closure { func, .. } => format!("<closure {}>", name(*func)),
+                Object::Generator { func, .. } => format!("<generator {}>", name(*func)),
+            },
+        }
+    }
+
+    /// Frees the objects that nothing running refers to.
+    fn collect(&mut self) {
+        let roots = (self.stack.iter())
+            .chain(&self.constants)
+            .chain(self.globals.iter().flatten());
+        self.heap.collect(roots);
+    }
+
+    /// Starts a call of a function whose arguments are on top of the stack, returning where its
+    /// slots start.
+    fn enter(&mut self, func: usize, args: usize) -> Result<usize, Limit> {
+        if self.frames.len() >= self.limits.depth(MAX_FRAMES) {
+            return Err(Limit::Depth);
+        }
+        let base = self.stack.len() - args;
+        let locals = self.module.functions[func].locals as usize;
+        self.stack.resize(base + locals, Value::Unit);
+        self.frames.push(Frame {
+            func,
+            pc: 0,
+            base,
+            resumed: None,
+        });
+        Ok(base)
+    }
+
+    /// Resumes the generator on top of the stack, which stays under its frame, returning where
+    /// its slots start. A generator that's finished is replaced by `None` instead. There has to
+    /// be room for another frame.
+    fn resume(&mut self, resumed: Resumed) -> Result<Option<(usize, usize, usize)>, &'static str> {
+        let Value::Object(generator) = *self.stack.last().unwrap() else {
+            unreachable!("only generators are resumed");
+        };
+        let Object::Generator { func, state } = self.heap.get(generator) else {
+            unreachable!("only generators are resumed");
+        };
+        match state {
+            GenState::Running => return Err("generator resumed while it's running"),
+            GenState::Finished => {
+                self.pop();
+                let none = Object::Variant {
+                    variant: resumed.none,
+                    fields: Vec::new(),
+                };
+                self.push_object(none);
+                return Ok(None);
+            }
+            GenState::Suspended { .. } => {}
+        }
+        let func = *func;
+        let running = Object::Generator {
+            func,
+            state: GenState::Running,
+        };
+        let Object::Generator {
+            state: GenState::Suspended { pc, stack },
+            ..
+        } = self.heap.replace(generator, running)
+        else {
+            unreachable!("the generator is suspended");
+        };
+        let base = self.stack.len();
+        self.stack.extend(stack);
+        let func = func as usize;
+        self.frames.push(Frame {
+            func,
+            pc,
+            base,
+            resumed: Some(resumed),
+        });
+        Ok(Some((func, pc, base)))
+    }
+
+    /// Leaves the frame of a generator, which has just been popped, replacing the generator under
+    /// it with what the code that resumed it gets: `Some(value)` when it yields a value, and
+    /// `None` when it returns.
+    fn leave(&mut self, resumed: Resumed, state: GenState, value: Option<Value>) {
+        let Value::Object(generator) = self.pop() else {
+            unreachable!("only generators are resumed");
+        };
+        let Object::Generator { func, .. } = *self.heap.get(generator) else {
+            unreachable!("only generators are resumed");
+        };
+        self.heap
+            .replace(generator, Object::Generator { func, state });
+        let option = match value {
+            Some(value) => Object::Variant {
+                variant: resumed.some,
+                fields: vec![value],
+            },
+            None => Object::Variant {
+                variant: resumed.none,
+                fields: Vec::new(),
+            },
+        };
+        self.push_object(option);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("instructions only pop what was pushed")
+    }
+
+    /// Pops the last `len` values, in the order they were pushed.
+    fn pop_all(&mut self, len: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - len)
+    }
+
+    /// Pops a value, returning the object it refers to.
+    fn pop_object(&mut self) -> &Object {
+        let value = self.pop();
+        self.object(value)
+    }
+
+    /// Returns the string a value refers to.
+    fn string(&self, value: Value) -> &str {
+        match self.object(value) {
+            Object::String(string) => string,
+            _ => unreachable!("the checker checks that strings are strings"),
+        }
+    }
+
+    fn push_object(&mut self, object: Object) {
+        let value = self.alloc(object);
+        self.stack.push(value);
+    }
+
+    /// Pushes an array or map changed by `f`, which is the object itself when the update is in
+    /// place, or else a copy of it.
+    fn update(&mut self, value: Value, in_place: bool, f: impl FnOnce(&mut Object, &Heap)) {
+        let Value::Object(object) = value else {
+            unreachable!("only arrays and maps are updated");
+        };
+        if in_place {
+            // Taken out while it changes, so that the heap counts the bytes it grows by
+            let mut updated = self.heap.replace(object, Object::Tuple(Vec::new()));
+            f(&mut updated, &self.heap);
+            self.heap.replace(object, updated);
+            self.stack.push(value);
+        } else {
+            let mut updated = self.heap.get(object).clone();
+            f(&mut updated, &self.heap);
+            self.push_object(updated);
+        }
+    }
+
+    /// Calls the host's function whose name is a constant, which returns a value of `ret`.
+    fn host_call(&mut self, name: u16, args: &[Value], ret: Kind) -> Result<Value, String> {
+        let Constant::String(name) = &self.module.constants[name as usize] else {
+            unreachable!("host functions are named by strings");
+        };
+        let args: Vec<_> = (args.iter())
+            .map(|&arg| self.to_host(arg))
+            .map(|arg| arg.expect("only numbers, `bool`s and `char`s cross to the host"))
+            .collect();
+        let ret = match ret {
+            Kind::Int(int) => Ty::Int(int),
+            Kind::Float => Ty::Float,
+            Kind::Bool => Ty::Bool,
+            Kind::Char => Ty::Char,
+            Kind::String | Kind::Other => Ty::unit(),
+        };
+        let value = self.host.call(name, &args, &ret)?;
+        Ok(self.from_host(value))
+    }
+
+    /// Converts a value for the host, if it's a number, `bool`, `char`, string or `()`.
+    pub fn to_host(&self, value: Value) -> Option<ffi::Value> {
+        Some(match value {
+            Value::Int(value) => ffi::Value::Int(value),
+            Value::Float(value) => ffi::Value::Float(value),
+            Value::Bool(value) => ffi::Value::Bool(value),
+            Value::Char(value) => ffi::Value::Char(value),
+            Value::Unit => ffi::Value::Unit,
+            Value::Object(_) => match self.object(value) {
+                Object::String(value) => ffi::Value::String(value.clone()),
+                _ => return None,
+            },
+            Value::Function(_) => return None,
+        })
+    }
+
+    /// Converts a value from the host, allocating it if it's a string.
+    pub fn from_host(&mut self, value: ffi::Value) -> Value {
+        match value {
+            ffi::Value::Int(value) => Value::Int(value),
+            ffi::Value::Float(value) => Value::Float(value),
+            ffi::Value::Bool(value) => Value::Bool(value),
+            ffi::Value::Char(value) => Value::Char(value),
+            ffi::Value::String(value) => self.alloc(Object::String(value)),
+            ffi::Value::Unit => Value::Unit,
+        }
+    }
+
+    /// Returns a failure of the instruction before `pc` in a function, which is running in the
+    /// innermost frame, with the calls that are running.
+    fn fail(&self, func: usize, pc: usize, message: &str) -> Panic {
+        let span = |func: usize, pc: usize| {
+            let offset = self.code[func].offsets[pc - 1];
+            let span = self.module.functions[func].span_at(offset);
+            span.cloned().unwrap_or_default()
+        };
+        let mut panic = panic(message, span(func, pc));
+        panic
+            .trace
+            .push((self.module.functions[func].name.clone(), panic.span.clone()));
+        // The others stopped at the instructions that call the next ones in
+        for frame in self.frames.iter().rev().skip(1) {
+            let name = self.module.functions[frame.func].name.clone();
+            panic.trace.push((name, span(frame.func, frame.pc)));
+        }
+        panic
+    }
+
+    /// Returns the failure of a program that went past a limit at the instruction before `pc`.
+    fn stop(&self, func: usize, pc: usize, limit: Limit) -> Panic {
+        Panic {
+            limit: Some(limit),
+            ..self.fail(func, pc, &limit.to_string())
+        }
+    }
+
+    /// Runs instructions until the call that's innermost when it starts returns, with `depth`
+    /// calls running outside of it.
+    fn execute(&mut self, depth: usize) -> Result<Value, Panic> {
+        let Frame {
+            mut func,
+            mut pc,
+            mut base,
+            ..
+        } = *self.frames.last().unwrap();
+        loop {
+            // Between instructions, every value in use is a root
+            let memory = self.limits.memory.unwrap_or(usize::MAX);
+            if self.heap.needs_collection() || self.heap.allocated() > memory {
+                self.collect();
+                if self.heap.allocated() > memory {
+                    return Err(self.stop(func, pc + 1, Limit::Memory));
+                }
+            }
+            if self.reloader.is_some() {
+                self.until_reload -= 1;
+                if self.until_reload == 0 {
+                    self.until_reload = RELOAD_INTERVAL;
+                    let reloader = self.reloader.take().unwrap();
+                    self.frames.last_mut().unwrap().pc = pc;
+                    let polled = reloader.poll(self);
+                    self.reloader = Some(reloader);
+                    polled.map_err(|message| self.fail(func, pc + 1, &message))?;
+                    // A patch moves the code that's running to another index
+                    func = self.frames.last().unwrap().func;
+                }
+            }
+            if let Some(profile) = &mut self.profile {
+                let frames = &self.frames;
+                let offset = self.code[func].offsets[pc];
+                profile.count(frames.len(), |i| frames[i].func as u16, offset);
+            }
+            if let Some(debugger) = self.debugger.take() {
+                self.frames.last_mut().unwrap().pc = pc;
+                let offset = self.code[func].offsets[pc];
+                let paused = debugger.pause(self, func as u16, offset);
+                self.debugger = Some(debugger);
+                paused.map_err(|message| self.fail(func, pc + 1, &message))?;
+            }
+            let op = self.code[func].ops[pc];
+            pc += 1;
+            if let Some(fuel) = &mut self.fuel {
+                if *fuel == 0 {
+                    return Err(self.stop(func, pc, Limit::Fuel));
+                }
+                *fuel -= 1;
+            }
+            match op {
+                Op::Const(index) => self.stack.push(self.constants[index as usize]),
+                Op::Load(slot) => self.stack.push(self.stack[base + slot as usize]),
+                Op::Store(slot) => {
+                    let value = self.pop();
+                    self.stack[base + slot as usize] = value;
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Unary { op, kind } => {
+                    let value = self.pop();
+                    let result = unary(op, kind, value).map_err(|m| self.fail(func, pc, m))?;
+                    self.stack.push(result);
+                }
+                Op::Binary { op, kind } => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let result = binary(&mut self.heap, op, kind, lhs, rhs)
+                        .map_err(|m| self.fail(func, pc, m))?;
+                    self.stack.push(result);
+                }
+                // The text of a value is a new string
+                Op::Cast {
+                    from,
+                    to: Kind::String,
+                } if from != Kind::String => {
+                    let text = match self.pop() {
+                        Value::Int(value) => value.to_string(),
+                        Value::Bool(value) => value.to_string(),
+                        Value::Char(value) => value.to_string(),
+                        _ => unreachable!("the checker checks what converts to a string"),
+                    };
+                    self.push_object(Object::String(text));
+                }
+                Op::Cast { to, .. } => {
+                    let value = self.pop();
+                    self.stack.push(cast(value, to));
+                }
+                Op::Tuple(0) => self.stack.push(Value::Unit),
+                Op::Tuple(len) => {
+                    let elems = self.pop_all(len as usize);
+                    self.push_object(Object::Tuple(elems));
+                }
+                Op::Array(len) => {
+                    let elems = self.pop_all(len as usize);
+                    self.push_object(Object::Array(elems));
+                }
+                Op::Construct { variant, fields } => {
+                    let fields = self.pop_all(fields as usize);
+                    self.push_object(Object::Variant { variant, fields });
+                }
+                Op::Field(index) => {
+                    let field = match self.pop_object() {
+                        Object::Tuple(elems) => elems[index as usize],
+                        Object::Variant { fields, .. } => fields[index as usize],
+                        Object::Range { bounds, .. } => {
+                            bounds[index as usize].expect("only the bounds a range has are read")
+                        }
+                        _ => unreachable!("only tuples, structs and ranges have fields"),
+                    };
+                    self.stack.push(field);
+                }
+                Op::SetField(index) => {
+                    let value = self.pop();
+                    let mut updated = self.pop_object().clone();
+                    match &mut updated {
+                        Object::Tuple(fields) | Object::Variant { fields, .. } => {
+                            fields[index as usize] = value;
+                        }
+                        _ => unreachable!("only the fields of tuples and structs are set"),
+                    }
+                    self.push_object(updated);
+                }
+                Op::Discriminant => match *self.pop_object() {
+                    Object::Variant { variant, .. } => {
+                        let variant = &self.module.variants[variant as usize];
+                        self.stack.push(Value::Int(variant.discriminant.into()));
+                    }
+                    _ => unreachable!("only enum values have a discriminant"),
+                },
+                Op::VariantField { index, .. } => match self.pop_object() {
+                    Object::Variant { fields, .. } => {
+                        let field = fields[index as usize];
+                        self.stack.push(field);
+                    }
+                    _ => unreachable!("only enum values have variants"),
+                },
+                Op::Index => {
+                    let index = self.pop();
+                    let array = self.pop();
+                    let elem = match (self.object(array), index) {
+                        (Object::Array(elems), Value::Int(index)) => in_bounds(elems, index)
+                            .map(|index| elems[index])
+                            .ok_or_else(|| self.fail(func, pc, "index out of bounds"))?,
+                        _ => unreachable!("only arrays are indexed"),
+                    };
+                    self.stack.push(elem);
+                }
+                Op::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let array = self.pop();
+                    let updated = match (self.object(array), index) {
+                        (Object::Array(elems), Value::Int(index)) => {
+                            let index = in_bounds(elems, index)
+                                .ok_or_else(|| self.fail(func, pc, "index out of bounds"))?;
+                            let mut elems = elems.clone();
+                            elems[index] = value;
+                            elems
+                        }
+                        _ => unreachable!("only arrays are indexed"),
+                    };
+                    self.push_object(Object::Array(updated));
+                }
+                Op::Range {
+                    start,
+                    end,
+                    inclusive,
+                } => {
+                    let end = end.then(|| self.pop());
+                    let start = start.then(|| self.pop());
+                    self.push_object(Object::Range {
+                        bounds: [start, end],
+                        inclusive,
+                    });
+                }
+                Op::Global(index) => {
+                    // Such as a global whose initializer calls a function that reads a later one
+                    let value = self.globals[index as usize].ok_or_else(|| {
+                        self.fail(func, pc, "global used before it's initialized")
+                    })?;
+                    self.stack.push(value);
+                }
+                Op::SetGlobal(index) => {
+                    let value = self.pop();
+                    self.globals[index as usize] = Some(value);
+                }
+                Op::Function(index) => self.stack.push(Value::Function(index)),
+                Op::Closure {
+                    func: index,
+                    captures,
+                } => {
+                    let captures = self.pop_all(captures as usize);
+                    self.push_object(Object::Closure {
+                        func: index,
+                        captures,
+                    });
+                }
+                Op::Call { func: callee, args } => {
+                    self.frames.last_mut().unwrap().pc = pc;
+                    let slots = (self.enter(callee as usize, args as usize))
+                        .map_err(|limit| self.stop(func, pc, limit))?;
+                    (func, pc, base) = (callee as usize, 0, slots);
+                }
+                Op::CallIndirect(args) => {
+                    let at = self.stack.len() - args as usize - 1;
+                    let (callee, args) = match self.stack[at] {
+                        Value::Function(callee) => {
+                            self.stack.remove(at);
+                            (callee, args as usize)
+                        }
+                        // The captured values go where the closure was, before the arguments
+                        closure => match self.object(closure) {
+                            Object::Closure { func, captures } => {
+                                let (callee, captures) = (*func, captures.clone());
+                                let len = captures.len();
+                                self.stack.splice(at..at + 1, captures);
+                                (callee, len + args as usize)
+                            }
+                            _ => unreachable!("only functions are called"),
+                        },
+                    };
+                    self.frames.last_mut().unwrap().pc = pc;
+                    let slots = (self.enter(callee as usize, args))
+                        .map_err(|limit| self.stop(func, pc, limit))?;
+                    (func, pc, base) = (callee as usize, 0, slots);
+                }
+                Op::Extern { name, args, ret } => {
+                    let args = self.pop_all(args as usize);
+                    let value = (self.host_call(name, &args, ret))
+                        .map_err(|message| self.fail(func, pc, &message))?;
+                    self.stack.push(value);
+                }
+                Op::TailCall { func: callee, args } => {
+                    // The arguments take the place of the caller's slots
+                    let start = self.stack.len() - args as usize;
+                    self.stack.drain(base..start);
+                    let locals = self.module.functions[callee as usize].locals as usize;
+                    self.stack.resize(base + locals, Value::Unit);
+                    (func, pc) = (callee as usize, 0);
+                    // A generator's frame stays one, so the callee finishes it when it returns
+                    let frame = self.frames.last_mut().unwrap();
+                    (frame.func, frame.pc) = (func, pc);
+                }
+                Op::Generator { func: callee, args } => {
+                    let mut stack = self.pop_all(args as usize);
+                    let locals = self.module.functions[callee as usize].locals as usize;
+                    stack.resize(locals, Value::Unit);
+                    self.push_object(Object::Generator {
+                        func: callee,
+                        state: GenState::Suspended { pc: 0, stack },
+                    });
+                }
+                Op::Resume { some, none } => {
+                    if self.frames.len() >= self.limits.depth(MAX_FRAMES) {
+                        return Err(self.stop(func, pc, Limit::Depth));
+                    }
+                    self.frames.last_mut().unwrap().pc = pc;
+                    let resumed = Resumed { some, none };
+                    if let Some(frame) =
+                        (self.resume(resumed)).map_err(|m| self.fail(func, pc, m))?
+                    {
+                        (func, pc, base) = frame;
+                    }
+                }
+                Op::Yield => {
+                    let value = self.pop();
+                    let mut stack = self.stack.split_off(base);
+                    // What `yield` gives once the generator is resumed
+                    stack.push(Value::Unit);
+                    let frame = self.frames.pop().unwrap();
+                    let resumed = frame.resumed.expect("only generators yield");
+                    self.leave(resumed, GenState::Suspended { pc, stack }, Some(value));
+                    Frame { func, pc, base, .. } = *self.frames.last().unwrap();
+                }
+                Op::Len => {
+                    let len = match self.pop_object() {
+                        Object::Array(elems) => elems.len(),
+                        Object::Map(map) => map.len(),
+                        _ => unreachable!("only arrays and maps have a length"),
+                    };
+                    self.stack.push(Value::Int(len as i128));
+                }
+                Op::ArrayPush { in_place } => {
+                    let value = self.pop();
+                    let array = self.pop();
+                    self.update(array, in_place, |array, _| match array {
+                        Object::Array(elems) => elems.push(value),
+                        _ => unreachable!("only arrays are pushed to"),
+                    });
+                }
+                Op::ArrayPop { in_place } => {
+                    let array = self.pop();
+                    self.update(array, in_place, |array, _| match array {
+                        Object::Array(elems) => {
+                            elems.pop();
+                        }
+                        _ => unreachable!("only arrays are popped from"),
+                    });
+                }
+                Op::NewMap => self.push_object(Object::Map(Map::default())),
+                Op::Sqrt | Op::Floor | Op::Ceil => {
+                    let Value::Float(x) = self.pop() else {
+                        unreachable!("only floats have square roots and are rounded");
+                    };
+                    self.stack.push(Value::Float(match op {
+                        Op::Sqrt => x.sqrt(),
+                        Op::Floor => x.floor(),
+                        _ => x.ceil(),
+                    }));
+                }
+                Op::Pow => {
+                    let (Value::Float(y), Value::Float(x)) = (self.pop(), self.pop()) else {
+                        unreachable!("only floats are raised to powers");
+                    };
+                    self.stack.push(Value::Float(x.powf(y)));
+                }
+                Op::ReadFile => {
+                    let path = self.pop();
+                    let path = self.string(path).to_string();
+                    let contents = self.files.read(&path);
+                    self.push_object(Object::String(contents));
+                }
+                Op::WriteFile => {
+                    let (contents, path) = (self.pop(), self.pop());
+                    let path = self.string(path).to_string();
+                    let contents = self.string(contents).to_string();
+                    self.files.write(&path, &contents);
+                }
+                Op::FileExists => {
+                    let path = self.pop();
+                    let path = self.string(path).to_string();
+                    let exists = self.files.exists(&path);
+                    self.stack.push(Value::Bool(exists));
+                }
+                Op::FsError => self.push_object(Object::String(self.files.error().to_string())),
+                Op::MapInsert { in_place } => {
+                    let value = self.pop();
+                    let key = self.pop();
+                    let map = self.pop();
+                    let hash = hash(&self.heap, key);
+                    self.update(map, in_place, |map, heap| match map {
+                        Object::Map(map) => {
+                            map.insert(hash, key, value, |other| equal(heap, key, *other))
+                        }
+                        _ => unreachable!("only maps are inserted into"),
+                    });
+                }
+                Op::MapFind => {
+                    let key = self.pop();
+                    let map = self.pop();
+                    let index = match self.object(map) {
+                        Object::Map(map) => map.find(hash(&self.heap, key), |other| {
+                            equal(&self.heap, key, *other)
+                        }),
+                        _ => unreachable!("only maps have keys"),
+                    };
+                    self.stack
+                        .push(Value::Int(index.map_or(-1, |index| index as i128)));
+                }
+                Op::EntryKey | Op::EntryValue | Op::MapRemove { .. } => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let (Object::Map(map), Value::Int(index)) = (self.object(object), index) else {
+                        unreachable!("only maps have entries");
+                    };
+                    let index = in_bounds(map.entries(), index)
+                        .ok_or_else(|| self.fail(func, pc, "index out of bounds"))?;
+                    match op {
+                        Op::EntryKey => self.stack.push(map.entries()[index].key),
+                        Op::EntryValue => self.stack.push(map.entries()[index].value),
+                        Op::MapRemove { in_place } => {
+                            self.update(object, in_place, |map, _| match map {
+                                Object::Map(map) => {
+                                    map.remove(index);
+                                }
+                                _ => unreachable!("only maps have entries"),
+                            })
+                        }
+                        _ => unreachable!("only entries are read and removed"),
+                    }
+                }
+                Op::Chars => match self.pop_object() {
+                    Object::String(string) => {
+                        let chars = string.chars().map(Value::Char).collect();
+                        self.push_object(Object::Array(chars));
+                    }
+                    _ => unreachable!("only strings have characters"),
+                },
+                Op::StringLen => {
+                    let string = self.pop();
+                    let len = strings::len(self.string(string));
+                    self.stack.push(Value::Int(len));
+                }
+                Op::CharAt => {
+                    let Value::Int(index) = self.pop() else {
+                        unreachable!("strings are indexed by integers")
+                    };
+                    let string = self.pop();
+                    let c = strings::char_at(self.string(string), index)
+                        .map_err(|message| self.fail(func, pc, message))?;
+                    self.stack.push(Value::Char(c));
+                }
+                Op::Slice => {
+                    let range = self.pop();
+                    let string = self.pop();
+                    let Object::Range { bounds, inclusive } = *self.object(range) else {
+                        unreachable!("strings are sliced by ranges")
+                    };
+                    let [start, end] = bounds.map(|bound| {
+                        bound.map(|bound| match bound {
+                            Value::Int(bound) => bound,
+                            _ => unreachable!("strings are sliced by ranges of integers"),
+                        })
+                    });
+                    let slice = strings::slice(self.string(string), start, end, inclusive)
+                        .map_err(|message| self.fail(func, pc, message))?
+                        .to_string();
+                    self.push_object(Object::String(slice));
+                }
+                Op::Contains => {
+                    let pattern = self.pop();
+                    let string = self.pop();
+                    let found = self.string(string).contains(self.string(pattern));
+                    self.stack.push(Value::Bool(found));
+                }
+                Op::Split => {
+                    let separator = self.pop();
+                    let string = self.pop();
+                    let parts: Vec<String> =
+                        strings::split(self.string(string), self.string(separator))
+                            .map_err(|message| self.fail(func, pc, message))?
+                            .into_iter()
+                            .map(str::to_string)
+                            .collect();
+                    let parts = (parts.into_iter())
+                        .map(|part| self.alloc(Object::String(part)))
+                        .collect();
+                    self.push_object(Object::Array(parts));
+                }
+                Op::ToUpper | Op::ToLower => {
+                    let string = self.pop();
+                    let string = self.string(string);
+                    let changed = match op {
+                        Op::ToUpper => strings::to_upper(string),
+                        _ => strings::to_lower(string),
+                    };
+                    self.push_object(Object::String(changed));
+                }
+                Op::Panic => match self.pop_object() {
+                    Object::String(message) => {
+                        let message = message.clone();
+                        return Err(self.fail(func, pc, &message));
+                    }
+                    _ => unreachable!("`panic` takes a message"),
+                },
+                Op::Print | Op::Println => {
+                    let Value::Object(text) = self.pop() else {
+                        unreachable!("`print` takes a string")
+                    };
+                    let written = match self.heap.get(text) {
+                        Object::String(text) if op == Op::Print => self.console.print(text),
+                        Object::String(text) => self.console.println(text),
+                        _ => unreachable!("`print` takes a string"),
+                    };
+                    written.map_err(|error| self.fail(func, pc, &error.to_string()))?;
+                }
+                Op::Input => {
+                    let line = (self.console.input())
+                        .map_err(|error| self.fail(func, pc, &error.to_string()))?;
+                    self.push_object(Object::String(line));
+                }
+                Op::Jump(target) => pc = target as usize,
+                Op::JumpIfFalse(target) => match self.pop() {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => pc = target as usize,
+                    _ => unreachable!("conditions are `bool`s"),
+                },
+                Op::Return => {
+                    let value = self.pop();
+                    self.stack.truncate(base);
+                    let frame = self.frames.pop().unwrap();
+                    if self.frames.len() == depth {
+                        return Ok(value);
+                    }
+                    match frame.resumed {
+                        Some(resumed) => self.leave(resumed, GenState::Finished, None),
+                        None => self.stack.push(value),
+                    }
+                    Frame { func, pc, base, .. } = *self.frames.last().unwrap();
+                }
+                Op::Unreachable => return Err(self.fail(func, pc, "entered unreachable code")),
+            }
+        }
+    }
+}
+
+/// Returns the value of a constant, putting a string on the heap.
+fn constant_value(heap: &mut Heap, constant: &Constant) -> Value {
+    match constant {
+        Constant::Int(value) => Value::Int(*value),
+        Constant::Float(value) => Value::Float(*value),
+        Constant::Bool(value) => Value::Bool(*value),
+        Constant::Char(value) => Value::Char(*value),
+        Constant::String(value) => Value::Object(heap.alloc(Object::String(value.clone()))),
+        Constant::Unit => Value::Unit,
+    }
+}
+
+/// Moves the constant and function operands of an instruction of a module that's patched into the
+/// VM from the module's indices to the VM's, which `constants` and `funcs` map them to.
+fn relocate(op: &mut Op, constants: &[u16], funcs: &[u16]) {
+    match op {
+        Op::Const(index) | Op::Extern { name: index, .. } => *index = constants[*index as usize],
+        Op::Function(func)
+        | Op::Closure { func, .. }
+        | Op::Call { func, .. }
+        | Op::TailCall { func, .. }
+        | Op::Generator { func, .. } => *func = funcs[*func as usize],
+        _ => {}
+    }
+}
+
+/// Decodes the code of a function, with jumps to the indices of instructions.
+fn decode(func: &Function) -> Code {
+    let (offsets, mut ops): (Vec<usize>, Vec<Op>) = func.ops().unzip();
+    let index = |target: &mut u32| {
+        *target = offsets.binary_search(&(*target as usize)).unwrap() as u32;
+    };
+    for op in &mut ops {
+        if let Op::Jump(target) | Op::JumpIfFalse(target) = op {
+            index(target);
+        }
+    }
+    Code { ops, offsets }
+}
+
+/// Returns an index into an array as a `usize`, if it's in bounds.
+fn in_bounds<T>(elems: &[T], index: i128) -> Option<usize> {
+    usize::try_from(index)
+        .ok()
+        .filter(|&index| index < elems.len())
+}
+
+/// Returns whether two values are equal, comparing strings, structs, tuples and arrays by their
+/// contents. Functions are never equal.
+fn equal(heap: &Heap, lhs: Value, rhs: Value) -> bool {
+    let all_equal = |a: &[Value], b: &[Value]| {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(heap, *a, *b))
+    };
+    match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Float(a), Value::Float(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Char(a), Value::Char(b)) => a == b,
+        (Value::Unit, Value::Unit) => true,
+        (Value::Object(a), Value::Object(b)) => match (heap.get(a), heap.get(b)) {
+            (Object::String(a), Object::String(b)) => a == b,
+            (Object::Tuple(a), Object::Tuple(b)) | (Object::Array(a), Object::Array(b)) => {
+                all_equal(a, b)
+            }
+            (
+                Object::Variant { variant, fields },
+                Object::Variant {
+                    variant: other,
+                    fields: other_fields,
+                },
+            ) => variant == other && all_equal(fields, other_fields),
+            (Object::Map(a), Object::Map(b)) => a.equal(b, |a, b| equal(heap, *a, *b)),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Hashes a map key, so that keys that are [`equal`] have the same hash, the same as the
+/// interpreter does.
+fn hash(heap: &Heap, key: Value) -> u64 {
+    fn add(heap: &Heap, value: Value, state: &mut DefaultHasher) {
+        match value {
+            Value::Int(value) => value.hash(state),
+            // `0.0 == -0.0`, so they have to hash the same
+            Value::Float(value) => (if value == 0.0 { 0.0 } else { value })
+                .to_bits()
+                .hash(state),
+            Value::Bool(value) => value.hash(state),
+            Value::Char(value) => value.hash(state),
+            Value::Object(object) => match heap.get(object) {
+                Object::String(string) => string.as_str().hash(state),
+                Object::Tuple(elems) | Object::Array(elems) => {
+                    elems.len().hash(state);
+                    elems.iter().for_each(|elem| add(heap, *elem, state));
+                }
+                Object::Variant { variant, fields } => {
+                    variant.hash(state);
+                    fields.iter().for_each(|field| add(heap, *field, state));
+                }
+                // The checker only lets types that can be compared be keys, and maps can't be
+                Object::Map(_)
+                | Object::Range { .. }
+                | Object::Closure { .. }
+                | Object::Generator { .. } => {}
+            },
+            Value::Unit | Value::Function(_) => {}
+        }
+    }
+    let mut state = DefaultHasher::new();
+    add(heap, key, &mut state);
+    state.finish()
+}
+
+/// Orders two numbers, characters or strings, which is `None` when either is NaN.
+fn compare(heap: &Heap, lhs: Value, rhs: Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(&b)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(&b),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(&b)),
+        (Value::Char(a), Value::Char(b)) => Some(a.cmp(&b)),
+        (Value::Object(a), Value::Object(b)) => match (heap.get(a), heap.get(b)) {
+            (Object::String(a), Object::String(b)) => Some(a.cmp(b)),
+            _ => unreachable!("the checker checks what can be ordered"),
+        },
+        _ => unreachable!("the checker checks what can be ordered"),
+    }
+}
+
+/// Evaluates `lhs op rhs`, returning the message of the failure when it fails.
+fn binary(
+    heap: &mut Heap,
+    op: BinaryOp,
+    kind: Kind,
+    lhs: Value,
+    rhs: Value,
+) -> Result<Value, &'static str> {
+    let ordering = || compare(heap, lhs, rhs);
+    Ok(Value::Bool(match op {
+        BinaryOp::Eq | BinaryOp::TripleEq => equal(heap, lhs, rhs),
+        BinaryOp::Ne | BinaryOp::TripleNe => !equal(heap, lhs, rhs),
+        BinaryOp::Lt => ordering().is_some_and(Ordering::is_lt),
+        BinaryOp::Le => ordering().is_some_and(Ordering::is_le),
+        BinaryOp::Gt => ordering().is_some_and(Ordering::is_gt),
+        BinaryOp::Ge => ordering().is_some_and(Ordering::is_ge),
+        _ => {
+            return Ok(match (lhs, rhs, kind) {
+                (Value::Int(a), Value::Int(b), Kind::Int(ty)) => {
+                    let result = eval_int(op, ty, a, b).ok_or(match op {
+                        BinaryOp::Div | BinaryOp::Rem if b == 0 => DIVISION_BY_ZERO,
+                        BinaryOp::Shl | BinaryOp::Shr => SHIFT_OUT_OF_RANGE,
+                        _ => OVERFLOW,
+                    })?;
+                    Value::Int(result)
+                }
+                (Value::Float(a), Value::Float(b), _) => Value::Float(match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    _ => a % b,
+                }),
+                (Value::Bool(a), Value::Bool(b), _) => Value::Bool(match op {
+                    BinaryOp::And | BinaryOp::BitAnd => a & b,
+                    BinaryOp::Or | BinaryOp::BitOr => a | b,
+                    _ => a ^ b,
+                }),
+                (Value::Object(a), Value::Object(b), Kind::String) => {
+                    let concat = match (heap.get(a), heap.get(b)) {
+                        (Object::String(a), Object::String(b)) => format!("{}{}", a, b),
+                        _ => unreachable!("the checker checks the operands of operators"),
+                    };
+                    Value::Object(heap.alloc(Object::String(concat)))
+                }
+                _ => unreachable!("the checker checks the operands of operators"),
+            })
+        }
+    }))
+}
+
+/// Evaluates `op value`.
+fn unary(op: UnaryOp, kind: Kind, value: Value) -> Result<Value, &'static str> {
+    Ok(match (op, value, kind) {
+        (UnaryOp::Neg, Value::Int(value), Kind::Int(ty)) => {
+            Value::Int(eval_int(BinaryOp::Sub, ty, 0, value).ok_or(OVERFLOW)?)
+        }
+        (UnaryOp::Neg, Value::Float(value), _) => Value::Float(-value),
+        // Flipping the bits of an integer wraps it into its type, the same as `as`
+        (UnaryOp::Not, Value::Int(value), Kind::Int(ty)) => Value::Int(ty.wrap(!value)),
+        (UnaryOp::Not, Value::Bool(value), _) => Value::Bool(!value),
+        _ => unreachable!("the checker checks the operands of operators"),
+    })
+}
+
+/// Converts a value with `as`.
+fn cast(value: Value, to: Kind) -> Value {
+    match (value, to) {
+        (Value::Int(value), Kind::Float) => Value::Float(value as f64),
+        (Value::Float(value), Kind::Int(ty)) => Value::Int(ty.from_float(value)),
+        (Value::Int(value), Kind::Int(ty)) => Value::Int(ty.wrap(value)),
+        (Value::Char(value), Kind::Int(ty)) => Value::Int(ty.wrap(value as i128)),
+        (Value::Bool(value), Kind::Int(_)) => Value::Int(value as i128),
+        // Native code keeps the number of an integer that isn't a character
+        (Value::Int(value), Kind::Char) => Value::Char(
+            char::from_u32(IntTy::U32.wrap(value) as u32).unwrap_or(char::REPLACEMENT_CHARACTER),
+        ),
+        (value, _) => value,
+    }
+}
+
+fn panic(message: &str, span: Span) -> Panic {
+    Panic {
+        message: message.to_string(),
+        span,
+        trace: Vec::new(),
+        limit: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::{self, built, opt::OptLevel};
+
+    const CONFIG: GcConfig = GcConfig {
+        threshold: 4096,
+        growth: 2,
+    };
+
+    /// Compiles a program the way `ruffle run` does.
+    fn compiled(source: &str) -> Module {
+        let mut program = built(source);
+        mir::opt::optimize(&mut program, OptLevel::O1);
+        let mut module = emit(&program);
+        peephole(&mut module);
+        module
+    }
+
+    /// Makes a VM for a module, with nothing to read on its console and nowhere to print.
+    fn vm<'a>(module: &'a Module, host: &'a Externs) -> Vm<'a> {
+        let console = Console::new(&b""[..], std::io::sink());
+        Vm::new(module, CONFIG, Limits::default(), console, host).unwrap()
+    }
+
+    fn func(module: &Module, name: &str) -> u16 {
+        let index = module.functions.iter().position(|func| func.name == name);
+        index.unwrap() as u16
+    }
+
+    #[test]
+    fn test_for_over_string() {
+        let source = "
+fn count(s: string, c: char) int {
+    let mut n = 0;
+    for x in s { if x == c { n += 1; } }
+    n
+}";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        let string = vm.alloc(Object::String("héllo".to_string()));
+        let args = vec![string, Value::Char('l')];
+        assert_eq!(vm.call(func(&module, "count"), args), Ok(Value::Int(2)));
+    }
+
+    #[test]
+    fn test_closure() {
+        let source = "fn adder(k: int) fn(int) -> int { |x: int| x + k }";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        let add_two = vm
+            .call(func(&module, "adder"), vec![Value::Int(2)])
+            .unwrap();
+        let Object::Closure { func: closure, .. } = *vm.object(add_two) else {
+            panic!("expected a closure, found {:?}", vm.object(add_two));
+        };
+        // The closure's captured value comes before its argument
+        assert_eq!(
+            vm.call(closure, vec![Value::Int(2), Value::Int(3)]),
+            Ok(Value::Int(5))
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let source =
+            "fn sum(n: i64, acc: i64) i64 { if n == 0 { acc } else { sum(n - 1, acc + n) } }";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        // Deeper than calls can nest, which calls in tail position don't
+        let args = vec![Value::Int(200_000), Value::Int(0)];
+        assert_eq!(
+            vm.call(func(&module, "sum"), args),
+            Ok(Value::Int(20_000_100_000))
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let source = "fn depth(n: int) int { if n == 0 { 0 } else { depth(n - 1) + 1 } }";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        let call_at = source.find("depth(n - 1)").unwrap();
+        let overflow = vm
+            .call(func(&module, "depth"), vec![Value::Int(200_000)])
+            .unwrap_err();
+        assert_eq!(
+            (overflow.message, overflow.span),
+            ("stack overflow".to_string(), call_at..call_at + 12)
+        );
+        assert_eq!(overflow.trace.len(), MAX_FRAMES);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let source = "fn add(a: u8, b: u8) u8 { a + b }";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        let sum_at = source.find("a + b").unwrap();
+        let sum_span = sum_at..sum_at + 5;
+        assert_eq!(
+            vm.call(func(&module, "add"), vec![Value::Int(250), Value::Int(10)]),
+            Err(Panic {
+                trace: vec![("add".to_string(), sum_span.clone())],
+                ..panic(OVERFLOW, sum_span)
+            })
+        );
+        // A failed call leaves nothing behind
+        assert!(vm.stack.is_empty() && vm.frames.is_empty());
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let source = "
+fn grow(n: int) int {
+    let mut s = \"\";
+    let mut i = 0;
+    while i < n { s = s + \"ab\"; i += 1; }
+    let mut len = 0;
+    for _ in s { len += 1; }
+    len
+}";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        // Each string but the last is garbage as soon as the next one is made
+        assert_eq!(
+            vm.call(func(&module, "grow"), vec![Value::Int(1000)]),
+            Ok(Value::Int(2000))
+        );
+        assert!(vm.heap().collections() > 0);
+        assert!(vm.heap().len() < 100, "{} objects", vm.heap().len());
+    }
+
+    #[test]
+    fn test_string_methods() {
+        let source = "
+fn shout(s: string) string {
+    let mut out = \"\";
+    for w in s.split(\" \") {
+        if w.contains(\"l\") { out = out + w[..1].to_upper() + w[1..]; } else { out = out + w.to_lower(); }
+        out = out + \"_\";
+    }
+    out
+}";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        let string = vm.alloc(Object::String("héllo WORLD Foo".to_string()));
+        let shouted = vm.call(func(&module, "shout"), vec![string]).unwrap();
+        assert_eq!(
+            *vm.object(shouted),
+            Object::String("Héllo_world_foo_".to_string())
+        );
+    }
+
+    #[test]
+    fn test_string_index() {
+        let source = "
+fn from_end(s: string, i: int) char { s[s.len() - 1 - i] }
+fn second(s: string) int { from_end(s, 5) as int + 1 }";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        let string = vm.alloc(Object::String("héllo".to_string()));
+        let args = vec![string, Value::Int(3)];
+        assert_eq!(
+            vm.call(func(&module, "from_end"), args),
+            Ok(Value::Char('é'))
+        );
+        let index_at = source.find("s[s.len() - 1 - i]").unwrap();
+        let index_span = index_at..index_at + 18;
+        assert_eq!(
+            vm.call(func(&module, "from_end"), vec![string, Value::Int(5)]),
+            Err(Panic {
+                trace: vec![("from_end".to_string(), index_span.clone())],
+                ..panic(strings::OUT_OF_BOUNDS, index_span.clone())
+            })
+        );
+        // The trace goes out from where it failed, through where each call was made
+        let call_at = source.find("from_end(s, 5)").unwrap();
+        assert_eq!(
+            vm.call(func(&module, "second"), vec![string])
+                .map_err(|panic| panic.trace),
+            Err(vec![
+                ("from_end".to_string(), index_span),
+                ("second".to_string(), call_at..call_at + 14),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_push_in_place() {
+        let source = "
+fn pushes(n: int) int {
+    let mut xs = [0];
+    let mut i = 1;
+    while i < n { xs.push(i); i += 1; }
+    let before = xs;
+    xs.pop();
+    if before.len() == n && xs.len() == n - 1 { xs[n - 2] } else { -1 }
+}";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        // Each push changes the array it's given, rather than copying it
+        assert_eq!(
+            vm.call(func(&module, "pushes"), vec![Value::Int(20_000)]),
+            Ok(Value::Int(19_998))
+        );
+    }
+
+    #[test]
+    fn test_insert_in_place() {
+        let source = "
+fn squares(n: int) int {
+    let mut squares = Map::new();
+    let mut i = 0;
+    while i < n { squares.insert(i, i * i); i += 1; }
+    let before = squares;
+    match squares.remove(3) { Some(9) => {}, _ => { return -1; } }
+    if before.contains(3) && !squares.contains(3) { squares.len() } else { -2 }
+}";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        // Each insert changes the map it's given, rather than copying it
+        assert_eq!(
+            vm.call(func(&module, "squares"), vec![Value::Int(20_000)]),
+            Ok(Value::Int(19_999))
+        );
+    }
+
+    #[test]
+    fn test_generators() {
+        let source = "
+fn upto(n: int) Gen<int> {
+    let mut i = 0;
+    while i < n { yield i; i += 1; }
+}
+fn odds(n: int) Gen<int> {
+    for x in upto(n) { if x % 2 == 1 { yield x; } }
+}
+fn drain(n: int) int {
+    let mut total = 0;
+    for x in odds(n) { total = total * 10 + x; }
+    let mut g = upto(1);
+    g.next();
+    match (g.next(), g.next()) { (None, None) => total * 10, _ => -1 }
+}";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut vm = vm(&module, &host);
+        // Generators nest, and a finished one stays finished
+        assert_eq!(
+            vm.call(func(&module, "drain"), vec![Value::Int(8)]),
+            Ok(Value::Int(13570))
+        );
+    }
+
+    #[test]
+    fn test_externs() {
+        let source = "
+extern fn twice(x: int) int;
+fn doubled(x: int) int {
+    twice(x) + 1
+}";
+        let module = compiled(source);
+        let mut host = Externs::default();
+        host.register("twice", |args| match args {
+            [ffi::Value::Int(x)] => Ok(ffi::Value::Int(x * 2)),
+            _ => Err("expected an integer".to_string()),
+        });
+        let mut vm = vm(&module, &host);
+        assert_eq!(
+            vm.call(func(&module, "doubled"), vec![Value::Int(20)]),
+            Ok(Value::Int(41))
+        );
+        let panic = vm
+            .call(func(&module, "doubled"), vec![Value::Int(1 << 30)])
+            .unwrap_err();
+        assert_eq!(
+            panic.message,
+            "host function `twice` returned Int(2147483648) instead of a `i32`"
+        );
+    }
+
+    #[test]
+    fn test_console() {
+        let source = "
+fn greet() {
+    print(\"name? \");
+    println(\"hi \" + input());
+}";
+        let module = compiled(source);
+        let host = Externs::default();
+        let mut output = Vec::new();
+        let console = Console::new(&b"ann\n"[..], &mut output);
+        let mut vm = Vm::new(&module, CONFIG, Limits::default(), console, &host).unwrap();
+        assert_eq!(vm.call(func(&module, "greet"), Vec::new()), Ok(Value::Unit));
+        drop(vm);
+        assert_eq!(String::from_utf8(output).unwrap(), "name? hi ann\n");
+    }
+
+    #[test]
+    fn test_run() {
+        let source = "
+struct Point { x: int, y: int }
+enum Shape { Circle(float), Rect(Point, Point) }
+static CALLS: int = 0;
+fn area(shape: Shape) float {
+    CALLS += 1;
+    match shape {
+        Shape::Circle(r) => 3.0 * r * r,
+        Shape::Rect(a, b) => ((b.x - a.x) * (b.y - a.y)) as float,
+    }
+}
+fn main() {
+    let shape = Shape::Rect(Point { x: 0, y: 0 }, Point { x: 2, y: 5 });
+    if area(shape) != 10.0 { panic(\"wrong area\"); }
+    panic(\"done\");
+}";
+        let module = compiled(source);
+        let host = Externs::default();
+        // The globals are initialized before anything is called
+        assert_eq!(vm(&module, &host).globals[0], Some(Value::Int(0)));
+        let console = Console::new(&b""[..], std::io::sink());
+        assert_eq!(
+            run(&module, CONFIG, Limits::default(), console, &host).map_err(|panic| panic.message),
+            Err("done".to_string())
+        );
+    }
+}
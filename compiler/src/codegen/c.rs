@@ -0,0 +1,1067 @@
+//! Lowers the MIR of a program to C99, which any C compiler can build into an executable with the
+//! runtime library, as `build --backend c` does, and which is easier to read than the other
+//! backends' output.
+//!
+//! Every value is an `rf_value`, a 64-bit integer represented as described in
+//! `runtime/runtime.c`. Each block is a label with its parameters as variables, which the jumps
+//! to it assign before their `goto`. The arithmetic that can overflow goes through checked helpers
+//! at the top of the file, which are written so that none of them relies on undefined behavior.
+//!
+//! C doesn't promise that a call in tail position reuses its caller's frame, so a program with
+//! such calls between functions goes through a trampoline: the call stores its callee and
+//! arguments and returns, and the nearest call that isn't in tail position makes it in its place.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+    fs,
+    path::Path,
+};
+
+use crate::{
+    ast::{BinaryOp, UnaryOp},
+    codegen::{
+        build::{in_temp_dir, link, BuildError},
+        bytecode::Kind,
+        runtime::{
+            slot_kind, text_kind, ARRAY, ENTRY, FUNCTION, GENERATORS, MAIN, MAP, RANGE, RUNTIME,
+            TUPLE, VARIANT,
+        },
+    },
+    mir::{
+        opt::OptLevel, BlockCall, BlockId, Callee, Constant, FuncId, Function, InstKind, Intrinsic,
+        Program, Terminator, Value,
+    },
+    resolve::DefId,
+    typeck::{IntTy, Ty},
+};
+
+/// The declarations of the runtime library, and the helpers the functions use.
+const PRELUDE: &str = r#"#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef int64_t rf_value;
+
+rf_value rf_alloc(int64_t kind, int64_t tag, int64_t len, const char *shape);
+rf_value rf_string(const char *data, int64_t len);
+rf_value rf_concat(rf_value lhs, rf_value rhs);
+int64_t rf_compare_strings(rf_value lhs, rf_value rhs);
+rf_value rf_float_rem(rf_value lhs, rf_value rhs);
+int64_t rf_equal(rf_value lhs, rf_value rhs, int64_t kind);
+rf_value rf_set_field(rf_value base, int64_t index, rf_value value);
+int64_t rf_len(rf_value array);
+rf_value rf_index(rf_value array, int64_t index);
+rf_value rf_set_index(rf_value array, int64_t index, rf_value value);
+rf_value rf_chars(rf_value string);
+void rf_panic(rf_value message);
+void rf_fail(const char *message);
+void rf_print(rf_value text);
+void rf_println(rf_value text);
+rf_value rf_input(void);
+int64_t rf_string_len(rf_value string);
+rf_value rf_char_at(rf_value string, int64_t index);
+rf_value rf_slice(rf_value string, rf_value range);
+int64_t rf_contains(rf_value string, rf_value pattern);
+rf_value rf_split(rf_value string, rf_value separator);
+rf_value rf_to_upper(rf_value string);
+rf_value rf_to_lower(rf_value string);
+rf_value rf_push(rf_value array, rf_value value);
+rf_value rf_pop(rf_value array);
+int64_t rf_map_find(rf_value map, rf_value key);
+rf_value rf_map_insert(rf_value map, rf_value key, rf_value value);
+rf_value rf_map_remove(rf_value map, int64_t index);
+rf_value rf_map_key(rf_value map, int64_t index);
+rf_value rf_map_value(rf_value map, int64_t index);
+rf_value rf_sqrt(rf_value x);
+rf_value rf_floor(rf_value x);
+rf_value rf_ceil(rf_value x);
+rf_value rf_pow(rf_value x, rf_value y);
+rf_value rf_read_file(rf_value path);
+rf_value rf_write_file(rf_value path, rf_value contents);
+int64_t rf_file_exists(rf_value path);
+rf_value rf_fs_error(void);
+rf_value rf_to_string(rf_value value, int64_t kind);
+
+static inline rf_value *rf_slots(rf_value object) { return (rf_value *)(intptr_t)(object + 24); }
+
+static inline rf_value rf_tag(rf_value object) { return ((uint32_t *)(intptr_t)object)[1]; }
+
+static inline double rf_float(rf_value bits) {
+    double result;
+    memcpy(&result, &bits, sizeof result);
+    return result;
+}
+
+static inline rf_value rf_bits(double value) {
+    rf_value result;
+    memcpy(&result, &value, sizeof result);
+    return result;
+}
+
+/* Arithmetic on integers narrower than 64 bits can't overflow an rf_value, so only the result
+   has to fit in the type. */
+static inline rf_value rf_check(rf_value value, rf_value min, rf_value max) {
+    if (value < min || value > max) {
+        rf_fail("arithmetic overflow");
+    }
+    return value;
+}
+
+static inline rf_value rf_check_unsigned(uint64_t value, uint64_t max) {
+    if (value > max) {
+        rf_fail("arithmetic overflow");
+    }
+    return (rf_value)value;
+}
+
+static inline rf_value rf_add(rf_value a, rf_value b) {
+    if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b)) {
+        rf_fail("arithmetic overflow");
+    }
+    return a + b;
+}
+
+static inline rf_value rf_sub(rf_value a, rf_value b) {
+    if ((b < 0 && a > INT64_MAX + b) || (b > 0 && a < INT64_MIN + b)) {
+        rf_fail("arithmetic overflow");
+    }
+    return a - b;
+}
+
+static inline rf_value rf_mul(rf_value a, rf_value b) {
+    if (a > 0 ? (b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a)
+              : (b > 0 ? a < INT64_MIN / b : a != 0 && b < INT64_MAX / a)) {
+        rf_fail("arithmetic overflow");
+    }
+    return a * b;
+}
+
+static inline rf_value rf_add_unsigned(rf_value a, rf_value b) {
+    if ((uint64_t)a + (uint64_t)b < (uint64_t)a) {
+        rf_fail("arithmetic overflow");
+    }
+    return (rf_value)((uint64_t)a + (uint64_t)b);
+}
+
+static inline rf_value rf_sub_unsigned(rf_value a, rf_value b) {
+    if ((uint64_t)a < (uint64_t)b) {
+        rf_fail("arithmetic overflow");
+    }
+    return (rf_value)((uint64_t)a - (uint64_t)b);
+}
+
+static inline rf_value rf_mul_unsigned(rf_value a, rf_value b) {
+    if (a != 0 && (uint64_t)b > UINT64_MAX / (uint64_t)a) {
+        rf_fail("arithmetic overflow");
+    }
+    return (rf_value)((uint64_t)a * (uint64_t)b);
+}
+
+static inline rf_value rf_divisor(rf_value a, rf_value b, rf_value min) {
+    if (b == 0) {
+        rf_fail("division by zero");
+    }
+    /* The quotient of the smallest value and -1 is one more than the largest */
+    if (a == min && b == -1) {
+        rf_fail("arithmetic overflow");
+    }
+    return b;
+}
+
+static inline uint64_t rf_divisor_unsigned(rf_value b) {
+    if (b == 0) {
+        rf_fail("division by zero");
+    }
+    return (uint64_t)b;
+}
+
+/* A negative amount is a large unsigned one. */
+static inline rf_value rf_shift_amount(rf_value amount, rf_value bits) {
+    if ((uint64_t)amount >= (uint64_t)bits) {
+        rf_fail("shift amount out of range");
+    }
+    return amount;
+}
+
+/* Shifting a negative number right is implementation-defined, so it's done on its complement. */
+static inline rf_value rf_shr(rf_value a, rf_value amount) {
+    return a < 0 ? ~(~a >> amount) : a >> amount;
+}
+
+/* Saturating, like casting a float to an integer in Ruffle. */
+static inline rf_value rf_float_to_int(rf_value bits, rf_value min, rf_value max) {
+    double value = rf_float(bits);
+    if (value != value) {
+        return 0;
+    }
+    if (value <= (double)min) {
+        return min;
+    }
+    if (value >= (double)max) {
+        return max;
+    }
+    return (rf_value)value;
+}
+
+static inline rf_value rf_float_to_unsigned(rf_value bits, uint64_t max) {
+    double value = rf_float(bits);
+    if (!(value > 0)) {
+        return 0;
+    }
+    if (value >= (double)max) {
+        return (rf_value)max;
+    }
+    return (rf_value)(uint64_t)value;
+}
+"#;
+
+/// The trampoline that makes the calls that calls in tail position leave, with its arguments
+/// stored to make room for the longest.
+const TRAMPOLINE: &str = "
+/* The next call to make in place of the one that returned, through the bounce for its number of
+   arguments. */
+static rf_value (*rf_bounce)(void);
+static void (*rf_tail)(void);
+static rf_value rf_tail_args[ARGS];
+
+static rf_value rf_trampoline(rf_value value) {
+    while (rf_bounce) {
+        rf_value (*bounce)(void) = rf_bounce;
+        rf_bounce = 0;
+        value = bounce();
+    }
+    return value;
+}
+";
+
+/// Lowers a program to a C source file, which defines `ruffle_main` to run the program.
+pub fn emit_c(program: &Program) -> String {
+    let tail_calls = program
+        .fns
+        .iter()
+        .any(|func| func.blocks.iter().any(|block| block.tail_call().is_some()));
+    let mut module = ModuleEmitter {
+        program,
+        thunks: BTreeMap::new(),
+        variants: HashMap::new(),
+        bounces: tail_calls.then(BTreeSet::new),
+    };
+    for adt in &program.adts {
+        for (discriminant, variant) in adt.variants.iter().enumerate() {
+            module.variants.insert(variant.def, discriminant);
+        }
+    }
+    let mut functions = String::new();
+    for (i, func) in program.fns.iter().enumerate() {
+        functions.push('\n');
+        functions.push_str(&module.function(FuncId(i), func));
+    }
+
+    let mut out = String::from(PRELUDE);
+    if !program.globals.is_empty() {
+        out.push('\n');
+    }
+    for (i, global) in program.globals.iter().enumerate() {
+        writeln!(out, "static rf_value g{}; /* {} */", i, global.name).unwrap();
+    }
+    let externs = program.externs();
+    if !externs.is_empty() {
+        out.push('\n');
+    }
+    for (name, params, ret) in externs {
+        let params: Vec<_> = params.iter().map(c_type).collect();
+        let params = match params.is_empty() {
+            true => "void".to_string(),
+            false => params.join(", "),
+        };
+        writeln!(out, "{} {}({});", c_type(&ret), name, params).unwrap();
+    }
+    if let Some(bounces) = &module.bounces {
+        let args = bounces.last().copied().unwrap_or(0).max(1);
+        out.push_str(&TRAMPOLINE.replace("ARGS", &args.to_string()));
+        for &count in bounces {
+            out.push('\n');
+            out.push_str(&bounce(count));
+        }
+    }
+    out.push('\n');
+    for (i, func) in program.fns.iter().enumerate() {
+        writeln!(out, "static rf_value {};", signature(FuncId(i), func)).unwrap();
+    }
+    for (&func, &captures) in &module.thunks {
+        let params = program.func(func).params().len() - captures;
+        writeln!(out, "static rf_value {};", thunk_signature(func, params)).unwrap();
+    }
+    out.push_str(&functions);
+    for (&func, &captures) in &module.thunks {
+        out.push('\n');
+        out.push_str(&module.thunk(func, captures));
+    }
+    out.push('\n');
+    out.push_str(&module.entry());
+    out
+}
+
+/// The parameters of a function are named after their values, so that jumps to the entry can
+/// assign them like the parameters of any other block.
+fn signature(id: FuncId, func: &Function) -> String {
+    let params: Vec<_> = func
+        .params()
+        .iter()
+        .map(|param| format!("rf_value v{}", param.0))
+        .collect();
+    match params.is_empty() {
+        true => format!("fn{}(void)", id.0),
+        false => format!("fn{}({})", id.0, params.join(", ")),
+    }
+}
+
+fn thunk_signature(func: FuncId, params: usize) -> String {
+    let mut all = vec!["rf_value env".to_string()];
+    all.extend((0..params).map(|i| format!("rf_value a{}", i)));
+    format!("fn{}_thunk({})", func.0, all.join(", "))
+}
+
+/// The bounce that makes a call with `count` arguments that the trampoline was left.
+fn bounce(count: usize) -> String {
+    let types = match count {
+        0 => "void".to_string(),
+        _ => vec!["rf_value"; count].join(", "),
+    };
+    let args: Vec<_> = (0..count).map(|i| format!("rf_tail_args[{}]", i)).collect();
+    format!(
+        "static rf_value rf_bounce{}(void) {{\n    return ((rf_value (*)({}))rf_tail)({});\n}}\n",
+        count,
+        types,
+        args.join(", ")
+    )
+}
+
+/// Returns the C type of a parameter or return type of an `extern` function.
+fn c_type(ty: &Ty) -> &'static str {
+    match ty {
+        Ty::Int(IntTy::I8) => "int8_t",
+        Ty::Int(IntTy::I16) => "int16_t",
+        Ty::Int(IntTy::I32) => "int32_t",
+        Ty::Int(IntTy::I64) => "int64_t",
+        Ty::Int(IntTy::U8) => "uint8_t",
+        Ty::Int(IntTy::U16) => "uint16_t",
+        Ty::Int(IntTy::U32) | Ty::Char => "uint32_t",
+        Ty::Int(IntTy::U64) => "uint64_t",
+        Ty::Float => "double",
+        Ty::Bool => "_Bool",
+        _ => "void",
+    }
+}
+
+/// Writes bytes as a C string literal.
+fn literal(bytes: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            }
+            b' '..=b'~' => out.push(byte as char),
+            // Always three digits, so that a digit after it isn't part of it
+            _ => write!(out, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Writes an integer constant, which can't be the smallest `int64_t` as a literal.
+fn int(value: i64) -> String {
+    match value {
+        i64::MIN => "INT64_MIN".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+struct ModuleEmitter<'a> {
+    program: &'a Program,
+    /// The functions used as values, with how many values they capture.
+    thunks: BTreeMap<FuncId, usize>,
+    /// The discriminant of each struct and variant.
+    variants: HashMap<DefId, usize>,
+    /// How many arguments the calls in tail position take, when the program has any, in which
+    /// case every other call goes through the trampoline.
+    bounces: Option<BTreeSet<usize>>,
+}
+
+impl ModuleEmitter<'_> {
+    fn function(&mut self, id: FuncId, func: &Function) -> String {
+        let mut out = format!("/* fn{} {} */\n", id.0, func.name);
+        writeln!(out, "static rf_value {} {{", signature(id, func)).unwrap();
+        if func.blocks.is_empty() {
+            // Only declared, so it's never called
+            out.push_str("    abort();\n}\n");
+            return out;
+        }
+        let params = func.params();
+        let locals: Vec<_> = (0..func.values.len())
+            .filter(|&value| !params.iter().any(|param| param.0 == value))
+            .map(|value| format!("v{}", value))
+            .collect();
+        if !locals.is_empty() {
+            writeln!(out, "    rf_value {};", locals.join(", ")).unwrap();
+        }
+        // Only the blocks that are jumped to need labels
+        let mut targets = vec![false; func.blocks.len()];
+        for block in &func.blocks {
+            for target in block.terminator.targets() {
+                targets[target.block.0] = true;
+            }
+        }
+        let mut emitter = FnEmitter {
+            module: self,
+            func,
+            out,
+        };
+        for id in func.block_ids() {
+            if targets[id.0] {
+                writeln!(emitter.out, "bb{}:", id.0).unwrap();
+            }
+            emitter.block(id);
+        }
+        emitter.out.push_str("}\n");
+        emitter.out
+    }
+
+    /// The thunk of a function used as a value, which takes the value and the function's own
+    /// arguments, and passes the values it captured to the function.
+    fn thunk(&self, func: FuncId, captures: usize) -> String {
+        let params = self.program.func(func).params().len() - captures;
+        let mut args: Vec<_> = (0..captures)
+            .map(|i| format!("rf_slots(env)[{}]", i + 1))
+            .collect();
+        args.extend((0..params).map(|i| format!("a{}", i)));
+        format!(
+            "static rf_value {} {{\n    return fn{}({});\n}}\n",
+            thunk_signature(func, params),
+            func.0,
+            args.join(", ")
+        )
+    }
+
+    /// The function that runs the program, which initializes the globals in order, then calls
+    /// `main`.
+    fn entry(&self) -> String {
+        let mut out = format!("int64_t {}(void) {{\n", ENTRY);
+        for (i, global) in self.program.globals.iter().enumerate() {
+            let call = self.land(format!("fn{}()", global.init.0));
+            writeln!(out, "    g{} = {};", i, call).unwrap();
+        }
+        let main = self
+            .program
+            .fns
+            .iter()
+            .position(|func| func.def.is_some() && func.name == "main");
+        if let Some(main) = main {
+            writeln!(out, "    {};", self.land(format!("fn{}()", main))).unwrap();
+        }
+        out.push_str("    return 0;\n}\n");
+        out
+    }
+
+    /// Makes a call that isn't in tail position go through the trampoline, when there is one.
+    fn land(&self, call: String) -> String {
+        match self.bounces {
+            Some(_) => format!("rf_trampoline({})", call),
+            None => call,
+        }
+    }
+}
+
+struct FnEmitter<'a, 'b> {
+    module: &'a mut ModuleEmitter<'b>,
+    func: &'a Function,
+    out: String,
+}
+
+fn v(value: Value) -> String {
+    format!("v{}", value.0)
+}
+
+/// Sign or zero extends an integer from the width of its type.
+fn wrap(ty: IntTy, value: String) -> String {
+    match ty.bits() {
+        64 => value,
+        bits => {
+            let prefix = if ty.is_signed() { "" } else { "u" };
+            format!("(rf_value)({}int{}_t)({})", prefix, bits, value)
+        }
+    }
+}
+
+impl FnEmitter<'_, '_> {
+    fn line(&mut self, line: String) {
+        self.out.push_str("    ");
+        self.out.push_str(&line);
+        self.out.push('\n');
+    }
+
+    fn block(&mut self, id: BlockId) {
+        let block = self.func.block(id);
+        if let Some((callee, args)) = block.tail_call() {
+            let (_, insts) = block.insts.split_last().unwrap();
+            for inst in insts {
+                self.inst(inst.value, &inst.kind);
+            }
+            self.tail_call(callee, args);
+            return;
+        }
+        for inst in &block.insts {
+            self.inst(inst.value, &inst.kind);
+        }
+        match &block.terminator {
+            Terminator::Jump(target) => {
+                self.assign(target, "");
+                self.line(format!("goto bb{};", target.block.0));
+            }
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } if then_block.args.is_empty() && else_block.args.is_empty() => {
+                self.line(format!("if ({}) goto bb{};", v(*cond), then_block.block.0));
+                self.line(format!("goto bb{};", else_block.block.0));
+            }
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                self.line(format!("if ({}) {{", v(*cond)));
+                self.assign(then_block, "    ");
+                self.line(format!("    goto bb{};", then_block.block.0));
+                self.line("}".to_string());
+                self.assign(else_block, "");
+                self.line(format!("goto bb{};", else_block.block.0));
+            }
+            Terminator::Return(value) => self.line(format!("return {};", v(*value))),
+            Terminator::Unreachable => self.line("abort();".to_string()),
+        }
+    }
+
+    /// Assigns the arguments of a jump to the parameters of its block, through temporary
+    /// variables when a parameter is also an argument, since they're assigned all at once.
+    fn assign(&mut self, target: &BlockCall, indent: &str) {
+        let params = &self.func.block(target.block).params;
+        let pairs: Vec<_> = params
+            .iter()
+            .zip(&target.args)
+            .filter(|(param, arg)| param != arg)
+            .collect();
+        let overlap = pairs.len() > 1 && pairs.iter().any(|(_, arg)| params.contains(arg));
+        if !overlap {
+            for (param, arg) in pairs {
+                self.line(format!("{}{} = {};", indent, v(*param), v(*arg)));
+            }
+            return;
+        }
+        let temps: Vec<_> = pairs
+            .iter()
+            .enumerate()
+            .map(|(i, (_, arg))| format!("t{} = {}", i, v(**arg)))
+            .collect();
+        self.line(format!("{}{{", indent));
+        self.line(format!("{}    rf_value {};", indent, temps.join(", ")));
+        for (i, (param, _)) in pairs.iter().enumerate() {
+            self.line(format!("{}    {} = t{};", indent, v(**param), i));
+        }
+        self.line(format!("{}}}", indent));
+    }
+
+    /// Allocates an object and sets its slots, which are `None` where they're left as 0.
+    fn object(&mut self, value: Value, kind: u32, tag: usize, slots: &[(u8, Option<String>)]) {
+        let shape: Vec<u8> = slots.iter().map(|(letter, _)| *letter).collect();
+        self.line(format!(
+            "{} = rf_alloc({}, {}, {}, {});",
+            v(value),
+            kind,
+            tag,
+            slots.len(),
+            literal(&shape)
+        ));
+        for (i, (_, slot)) in slots.iter().enumerate() {
+            if let Some(slot) = slot {
+                self.line(format!("rf_slots({})[{}] = {};", v(value), i, slot));
+            }
+        }
+    }
+
+    fn slots(&self, values: &[Value]) -> Vec<(u8, Option<String>)> {
+        values
+            .iter()
+            .map(|&value| (slot_kind(self.func.value_ty(value)), Some(v(value))))
+            .collect()
+    }
+
+    fn inst(&mut self, value: Value, kind: &InstKind) {
+        let kind_of = |value: Value| Kind::of(self.func.value_ty(value));
+        let expr = match kind {
+            InstKind::Const(constant) => match constant {
+                Constant::Int(value) => int(*value as i64),
+                Constant::Float(value) => {
+                    format!("{} /* {:?} */", int(value.to_bits() as i64), value)
+                }
+                Constant::Bool(value) => (*value as i64).to_string(),
+                Constant::Char(value) => (*value as i64).to_string(),
+                Constant::String(value) => {
+                    format!("rf_string({}, {})", literal(value.as_bytes()), value.len())
+                }
+                Constant::Unit => "0".to_string(),
+            },
+            InstKind::Undef => "0".to_string(),
+            InstKind::Unary { op, value } => unary(*op, kind_of(*value), v(*value)),
+            InstKind::Binary { op, lhs, rhs } => binary(*op, kind_of(*lhs), v(*lhs), v(*rhs)),
+            InstKind::Cast(inner) => cast(kind_of(*inner), kind_of(value), v(*inner)),
+            InstKind::Tuple(elems) if elems.is_empty() => "0".to_string(),
+            InstKind::Tuple(elems) => {
+                let slots = self.slots(elems);
+                return self.object(value, TUPLE, 0, &slots);
+            }
+            // An array's shape is a single letter, for all of its elements
+            InstKind::Array(elems) => {
+                let elem = match self.func.value_ty(value) {
+                    Ty::Array(elem) => slot_kind(elem),
+                    _ => b'i',
+                };
+                self.line(format!(
+                    "{} = rf_alloc({}, 0, {}, {});",
+                    v(value),
+                    ARRAY,
+                    elems.len(),
+                    literal(&[elem])
+                ));
+                for (i, &elem) in elems.iter().enumerate() {
+                    self.line(format!("rf_slots({})[{}] = {};", v(value), i, v(elem)));
+                }
+                return;
+            }
+            InstKind::Construct { def, fields } => {
+                let slots = self.slots(fields);
+                let discriminant = self.module.variants[def];
+                return self.object(value, VARIANT, discriminant, &slots);
+            }
+            InstKind::Field { base, index } | InstKind::VariantField { base, index, .. } => {
+                format!("rf_slots({})[{}]", v(*base), index)
+            }
+            InstKind::SetField { base, index, value } => {
+                format!("rf_set_field({}, {}, {})", v(*base), index, v(*value))
+            }
+            InstKind::Discriminant(base) => format!("rf_tag({})", v(*base)),
+            InstKind::Index { base, index } => format!("rf_index({}, {})", v(*base), v(*index)),
+            InstKind::SetIndex { base, index, value } => {
+                format!("rf_set_index({}, {}, {})", v(*base), v(*index), v(*value))
+            }
+            InstKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let flags = start.is_some() as usize
+                    | (end.is_some() as usize) << 1
+                    | (*inclusive as usize) << 2;
+                let elem = match self.func.value_ty(value) {
+                    Ty::Range(elem) => slot_kind(elem),
+                    _ => b'i',
+                };
+                let slots = [(elem, start.map(v)), (elem, end.map(v))];
+                return self.object(value, RANGE, flags, &slots);
+            }
+            InstKind::Global(global) => format!("g{}", global.0),
+            InstKind::SetGlobal { global, value: new } => {
+                self.line(format!("g{} = {};", global.0, v(*new)));
+                "0".to_string()
+            }
+            InstKind::FnRef(func) => return self.function_value(value, *func, &[]),
+            InstKind::Closure { func, captures } => {
+                return self.function_value(value, *func, captures)
+            }
+            InstKind::Call { callee, args } => {
+                let args: Vec<_> = args.iter().map(|&arg| v(arg)).collect();
+                let call = match callee {
+                    Callee::Direct(func) => format!("fn{}({})", func.0, args.join(", ")),
+                    Callee::Indirect(callee) => {
+                        let types = vec!["rf_value"; args.len() + 1].join(", ");
+                        let mut all = vec![v(*callee)];
+                        all.extend(args);
+                        format!(
+                            "((rf_value (*)({}))(intptr_t)rf_slots({})[0])({})",
+                            types,
+                            v(*callee),
+                            all.join(", ")
+                        )
+                    }
+                };
+                self.module.land(call)
+            }
+            InstKind::Extern { name, args } => {
+                let args: Vec<_> = (args.iter())
+                    .map(|&arg| match self.func.value_ty(arg) {
+                        Ty::Float => format!("rf_float({})", v(arg)),
+                        ty => format!("({}){}", c_type(ty), v(arg)),
+                    })
+                    .collect();
+                let call = format!("{}({})", name, args.join(", "));
+                match self.func.value_ty(value) {
+                    Ty::Float => format!("rf_bits({})", call),
+                    ty if ty.is_unit() => {
+                        self.line(format!("{};", call));
+                        "0".to_string()
+                    }
+                    _ => format!("(rf_value){}", call),
+                }
+            }
+            InstKind::Generator { .. }
+            | InstKind::Intrinsic {
+                intrinsic: Intrinsic::Yield | Intrinsic::Next,
+                ..
+            } => {
+                self.line(format!("rf_fail(\"{}\");", GENERATORS));
+                "0".to_string()
+            }
+            InstKind::Intrinsic { intrinsic, args } => {
+                let args: Vec<_> = args.iter().map(|&arg| v(arg)).collect();
+                match intrinsic {
+                    Intrinsic::Panic => {
+                        self.line(format!("rf_panic({});", args.join(", ")));
+                        "0".to_string()
+                    }
+                    Intrinsic::Print | Intrinsic::Println => {
+                        let name = intrinsic.name();
+                        self.line(format!("rf_{}({});", name, args.join(", ")));
+                        "0".to_string()
+                    }
+                    Intrinsic::NewMap => {
+                        let shape = match self.func.value_ty(value) {
+                            Ty::Map(key, value) => [slot_kind(key), slot_kind(value)],
+                            _ => [b'i', b'i'],
+                        };
+                        format!("rf_alloc({}, 0, 0, {})", MAP, literal(&shape))
+                    }
+                    // The others return what the library function of the same name does
+                    _ => format!("rf_{}({})", intrinsic.name(), args.join(", ")),
+                }
+            }
+        };
+        self.line(format!("{} = {};", v(value), expr));
+    }
+
+    /// Leaves a call in tail position to the trampoline, and returns.
+    fn tail_call(&mut self, callee: Callee, args: &[Value]) {
+        let mut args: Vec<_> = args.iter().map(|&arg| v(arg)).collect();
+        let function = match callee {
+            Callee::Direct(func) => format!("fn{}", func.0),
+            Callee::Indirect(callee) => {
+                // A function value is passed to its thunk before the arguments
+                args.insert(0, v(callee));
+                format!("(intptr_t)rf_slots({})[0]", v(callee))
+            }
+        };
+        for (i, arg) in args.iter().enumerate() {
+            self.line(format!("rf_tail_args[{}] = {};", i, arg));
+        }
+        self.module.bounces.as_mut().unwrap().insert(args.len());
+        self.line(format!("rf_tail = (void (*)(void)){};", function));
+        self.line(format!("rf_bounce = rf_bounce{};", args.len()));
+        self.line("return 0;".to_string());
+    }
+
+    /// Makes a function value, whose first slot points to the function's thunk and whose other
+    /// slots hold the values it captures.
+    fn function_value(&mut self, value: Value, func: FuncId, captures: &[Value]) {
+        self.module.thunks.insert(func, captures.len());
+        let mut slots = vec![(
+            b'i',
+            Some(format!("(rf_value)(intptr_t)fn{}_thunk", func.0)),
+        )];
+        slots.extend(self.slots(captures));
+        self.object(value, FUNCTION, 0, &slots);
+    }
+}
+
+fn unary(op: UnaryOp, kind: Kind, operand: String) -> String {
+    match (op, kind) {
+        (UnaryOp::Neg, Kind::Int(_)) => binary(BinaryOp::Sub, kind, "0".to_string(), operand),
+        (UnaryOp::Neg, _) => format!("rf_bits(-rf_float({}))", operand),
+        (UnaryOp::Not, Kind::Int(ty)) => wrap(ty, format!("~{}", operand)),
+        (UnaryOp::Not, _) => format!("{} ^ 1", operand),
+    }
+}
+
+/// Compares two values, which are signed when `signed`.
+fn compare(op: BinaryOp, signed: bool, lhs: String, rhs: String) -> String {
+    let op = match op {
+        BinaryOp::Eq | BinaryOp::TripleEq => "==",
+        BinaryOp::Ne | BinaryOp::TripleNe => "!=",
+        BinaryOp::Lt => "<",
+        BinaryOp::Le => "<=",
+        BinaryOp::Gt => ">",
+        _ => ">=",
+    };
+    match signed {
+        true => format!("{} {} {}", lhs, op, rhs),
+        false => format!("(uint64_t){} {} (uint64_t){}", lhs, op, rhs),
+    }
+}
+
+fn binary(op: BinaryOp, kind: Kind, lhs: String, rhs: String) -> String {
+    match kind {
+        Kind::Int(ty) => int_binary(op, ty, lhs, rhs),
+        Kind::Float => match op {
+            BinaryOp::Rem => format!("rf_float_rem({}, {})", lhs, rhs),
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => {
+                let op = match op {
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::Mul => "*",
+                    _ => "/",
+                };
+                format!("rf_bits(rf_float({}) {} rf_float({}))", lhs, op, rhs)
+            }
+            _ => compare(
+                op,
+                true,
+                format!("rf_float({})", lhs),
+                format!("rf_float({})", rhs),
+            ),
+        },
+        Kind::String if op == BinaryOp::Add => format!("rf_concat({}, {})", lhs, rhs),
+        Kind::String => compare(
+            op,
+            true,
+            format!("rf_compare_strings({}, {})", lhs, rhs),
+            "0".to_string(),
+        ),
+        Kind::Bool | Kind::Char => match op {
+            BinaryOp::And | BinaryOp::BitAnd => format!("{} & {}", lhs, rhs),
+            BinaryOp::Or | BinaryOp::BitOr => format!("{} | {}", lhs, rhs),
+            BinaryOp::BitXor => format!("{} ^ {}", lhs, rhs),
+            _ => compare(op, false, lhs, rhs),
+        },
+        Kind::Other => {
+            let equal = format!("rf_equal({}, {}, 'o')", lhs, rhs);
+            match op {
+                BinaryOp::Ne | BinaryOp::TripleNe => format!("!{}", equal),
+                _ => equal,
+            }
+        }
+    }
+}
+
+fn int_binary(op: BinaryOp, ty: IntTy, lhs: String, rhs: String) -> String {
+    let signed = ty.is_signed();
+    let (min, max) = (int(ty.min() as i64), ty.max());
+    match op {
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul if ty.bits() < 64 => {
+            let op = match op {
+                BinaryOp::Add => "+",
+                BinaryOp::Sub => "-",
+                _ => "*",
+            };
+            match signed {
+                true => format!("rf_check({} {} {}, {}, {})", lhs, op, rhs, min, max),
+                false => format!(
+                    "rf_check_unsigned((uint64_t){} {} (uint64_t){}, {})",
+                    lhs, op, rhs, max
+                ),
+            }
+        }
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => {
+            let name = match op {
+                BinaryOp::Add => "add",
+                BinaryOp::Sub => "sub",
+                _ => "mul",
+            };
+            let suffix = if signed { "" } else { "_unsigned" };
+            format!("rf_{}{}({}, {})", name, suffix, lhs, rhs)
+        }
+        BinaryOp::Div | BinaryOp::Rem => {
+            let op = if op == BinaryOp::Div { "/" } else { "%" };
+            // Integers extended from their type divide the same way as in it
+            match signed {
+                true => format!("{} {} rf_divisor({}, {}, {})", lhs, op, lhs, rhs, min),
+                false => format!(
+                    "(rf_value)((uint64_t){} {} rf_divisor_unsigned({}))",
+                    lhs, op, rhs
+                ),
+            }
+        }
+        BinaryOp::Shl => {
+            let amount = format!("rf_shift_amount({}, {})", rhs, ty.bits());
+            wrap(ty, format!("(rf_value)((uint64_t){} << {})", lhs, amount))
+        }
+        BinaryOp::Shr => {
+            let amount = format!("rf_shift_amount({}, {})", rhs, ty.bits());
+            match signed {
+                true => format!("rf_shr({}, {})", lhs, amount),
+                false => format!("(rf_value)((uint64_t){} >> {})", lhs, amount),
+            }
+        }
+        // Integers extended the same way combine bit by bit into one that's extended that way
+        BinaryOp::BitAnd => format!("{} & {}", lhs, rhs),
+        BinaryOp::BitOr => format!("{} | {}", lhs, rhs),
+        BinaryOp::BitXor => format!("{} ^ {}", lhs, rhs),
+        _ => compare(op, signed, lhs, rhs),
+    }
+}
+
+fn cast(from: Kind, to: Kind, value: String) -> String {
+    match (from, to) {
+        (Kind::Int(from), Kind::Float) if from.is_signed() => {
+            format!("rf_bits((double){})", value)
+        }
+        (Kind::Int(_), Kind::Float) => format!("rf_bits((double)(uint64_t){})", value),
+        (Kind::Float, Kind::Int(to)) if to.is_signed() => format!(
+            "rf_float_to_int({}, {}, {})",
+            value,
+            int(to.min() as i64),
+            to.max()
+        ),
+        (Kind::Float, Kind::Int(to)) => {
+            format!("rf_float_to_unsigned({}, {}u)", value, to.max())
+        }
+        (Kind::Int(_) | Kind::Char | Kind::Bool, Kind::Int(to)) => wrap(to, value),
+        (Kind::Int(_), Kind::Char) => wrap(IntTy::U32, value),
+        (Kind::Int(_) | Kind::Char | Kind::Bool, Kind::String) => {
+            format!("rf_to_string({}, '{}')", value, text_kind(from) as char)
+        }
+        _ => value,
+    }
+}
+
+/// Builds an executable from a program with the system's C compiler, which is `$CC`, or `cc` by
+/// default, linking it with `libs`.
+pub fn build_executable(
+    program: &Program,
+    output: &Path,
+    level: OptLevel,
+    libs: &[String],
+) -> Result<(), BuildError> {
+    in_temp_dir(|dir| {
+        let source = dir.join("program.c");
+        let runtime = dir.join("runtime.c");
+        let main = dir.join("main.c");
+        fs::write(&source, emit_c(program))?;
+        fs::write(&runtime, RUNTIME)?;
+        fs::write(&main, MAIN)?;
+        let flags = ["-std=c99", level.flag()];
+        link(&flags, &[&source, &runtime, &main], libs, output)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+    use std::process::Command;
+
+    #[test]
+    fn test_emit_c() {
+        let source = "
+fn count(n: u8) u8 {
+    let mut i = 0;
+    while i < n { i += 1; }
+    i
+}";
+        let c = emit_c(&built(source));
+        assert_eq!(
+            &c[PRELUDE.len()..],
+            "
+static rf_value fn0(rf_value v0);
+
+/* fn0 count */
+static rf_value fn0(rf_value v0) {
+    rf_value v1, v2, v3, v4, v5;
+    v1 = 0;
+    v2 = v1;
+    goto bb1;
+bb1:
+    v3 = (uint64_t)v2 < (uint64_t)v0;
+    if (v3) goto bb2;
+    goto bb3;
+bb2:
+    v4 = 1;
+    v5 = rf_check_unsigned((uint64_t)v2 + (uint64_t)v4, 255);
+    v2 = v5;
+    goto bb1;
+bb3:
+    return v2;
+}
+
+int64_t ruffle_main(void) {
+    return 0;
+}
+"
+        );
+    }
+
+    /// Builds a program and runs it, returning its exit code and what it printed to stderr. Each
+    /// test builds its executable under its own name.
+    fn run_program(name: &str, source: &str) -> (i32, String) {
+        let dir = std::env::temp_dir();
+        let exe = dir.join(format!("ruffle-c-test-{}-{}", name, std::process::id()));
+        build_executable(&built(source), &exe, OptLevel::O2, &[]).unwrap();
+        let output = Command::new(&exe).output().unwrap();
+        fs::remove_file(&exe).unwrap();
+        (
+            output.status.code().unwrap(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    }
+
+    #[test]
+    fn test_build_executable() {
+        let source = "
+enum Shape { Circle(float), Square(int) }
+static TOTAL: int = 0;
+fn area(shape: Shape) int {
+    match shape {
+        Shape::Circle(r) => (r * r * 3.0) as int,
+        Shape::Square(side) => side * side,
+    }
+}
+fn main() {
+    let (circle, square) = (Shape::Circle(2.0), Shape::Square(3));
+    let add = |n: int| TOTAL += n;
+    add(area(circle));
+    add(area(square));
+    let mut n = 0;
+    for c in \"héllo\" { if c != 'l' { n += 1; } }
+    if TOTAL != 21 || \"ab\" + \"c\" != \"abc\" || n != 3 {
+        panic(\"wrong\");
+    }
+}";
+        assert_eq!(run_program("build", source), (0, String::new()));
+    }
+
+    #[test]
+    fn test_narrow_integers() {
+        let source = "
+fn main() {
+    let m: i8 = -128;
+    if m >> 7 != -1 || (m as u8) != 128 {
+        panic(\"wrong\");
+    }
+}";
+        assert_eq!(run_program("narrow", source), (0, String::new()));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let source = "
+fn main() {
+    let x: u8 = 200;
+    let y = x + 100;
+}";
+        let (code, stderr) = run_program("overflow", source);
+        assert_eq!(code, 101);
+        assert_eq!(stderr, "panicked: arithmetic overflow\n");
+    }
+}
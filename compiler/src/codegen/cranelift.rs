@@ -0,0 +1,1249 @@
+//! Lowers the MIR of a program to machine code with Cranelift, which is written in Rust, so a
+//! program can be compiled into memory and run without any other tools.
+//!
+//! Values are represented the same way as in the LLVM backend, as described by the runtime
+//! library: every value is an `i64`, and functions take and return `i64`s. Blocks of the MIR map
+//! to blocks of Cranelift IR, which take parameters the same way.
+
+use std::{collections::HashMap, ffi::CString, fmt::Display, ops::Range, thread};
+
+use cranelift_codegen::{
+    ir::{
+        condcodes::{FloatCC, IntCC},
+        types, AbiParam, Block, FuncRef, GlobalValue, InstBuilder, MemFlags, Signature, SourceLoc,
+        TrapCode, Type, Value as ClValue, ValueLabel,
+    },
+    isa::{unwind::UnwindInfo, CallConv},
+    settings::{self, Configurable},
+    ValueLabelsRanges,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{
+    default_libcall_names, DataDescription, DataId, FuncId as ClFuncId, Linkage, Module,
+    ModuleError, ModuleReloc,
+};
+
+use super::{
+    bytecode::Kind,
+    runtime::{
+        self, slot_kind, text_kind, ARRAY, DIVISION_BY_ZERO, ENTRY, FUNCTION, GENERATORS, HEADER,
+        MAP, OVERFLOW, RANGE, SHIFT_OUT_OF_RANGE, TUPLE, VARIANT,
+    },
+};
+use crate::{
+    ast::{BinaryOp, UnaryOp},
+    mir::{
+        opt::OptLevel, BlockId, Callee, Constant, FuncId, Function, InstKind, Intrinsic, Program,
+        Terminator, Value,
+    },
+    resolve::DefId,
+    typeck::{IntTy, Ty},
+};
+
+const I64: Type = types::I64;
+
+/// The trap at the end of code that can't be reached, and of code that has already failed.
+const UNREACHABLE: TrapCode = TrapCode::unwrap_user(1);
+
+/// The functions of a program once they're defined in a module.
+pub struct Translated {
+    /// The function of each function of the MIR.
+    pub fns: Vec<ClFuncId>,
+    /// The function that runs the program.
+    pub entry: ClFuncId,
+    /// The machine code of every function, in the order they're defined.
+    pub code: Vec<Code>,
+}
+
+/// The machine code of a function, with the places where the addresses of other functions and
+/// data go once it's linked, and what debug info needs to know about it.
+pub struct Code {
+    pub func: ClFuncId,
+    pub bytes: Vec<u8>,
+    pub relocs: Vec<ModuleReloc>,
+    /// The ranges of the code that come from each offset into the source, in order.
+    pub srclocs: Vec<(Range<u32>, u32)>,
+    /// Where each variable of the function is kept while it's live, labeled with its index.
+    pub vars: ValueLabelsRanges,
+    pub unwind: Option<UnwindInfo>,
+}
+
+/// Defines the functions and globals of a program in a module, with the functions of the
+/// runtime library as imports.
+pub fn translate<M: Module>(
+    module: &mut M,
+    program: &Program,
+) -> Result<Translated, Box<ModuleError>> {
+    let mut translator = Translator {
+        module,
+        program,
+        fns: Vec::new(),
+        thunks: HashMap::new(),
+        runtime: HashMap::new(),
+        externs: HashMap::new(),
+        data: HashMap::new(),
+        globals: Vec::new(),
+        variants: HashMap::new(),
+        builder: FunctionBuilderContext::new(),
+        code: Vec::new(),
+    };
+    for adt in &program.adts {
+        for (discriminant, variant) in adt.variants.iter().enumerate() {
+            translator.variants.insert(variant.def, discriminant);
+        }
+    }
+    for (name, params, returns) in runtime::FUNCTIONS {
+        let signature = translator.signature(params, returns);
+        let id = translator
+            .module
+            .declare_function(name, Linkage::Import, &signature)?;
+        translator.runtime.insert(name, id);
+    }
+    for (name, params, ret) in program.externs() {
+        let mut signature = translator.module.make_signature();
+        signature.params = params.iter().filter_map(extern_param).collect();
+        signature.returns.extend(extern_param(&ret));
+        let id = translator
+            .module
+            .declare_function(name, Linkage::Import, &signature)?;
+        translator.externs.insert(name, id);
+    }
+    for (i, func) in program.fns.iter().enumerate() {
+        let signature = translator.fn_signature(func.params().len());
+        let id =
+            translator
+                .module
+                .declare_function(&format!("fn{}", i), Linkage::Local, &signature)?;
+        translator.fns.push(id);
+    }
+    for i in 0..program.globals.len() {
+        let id = translator
+            .module
+            .declare_data(&format!("g{}", i), Linkage::Local, true, false)?;
+        let mut data = DataDescription::new();
+        data.define_zeroinit(8);
+        data.set_align(8);
+        translator.module.define_data(id, &data)?;
+        translator.globals.push(id);
+    }
+
+    for (i, func) in program.fns.iter().enumerate() {
+        translator.function(FuncId(i), func)?;
+    }
+    let mut thunks: Vec<_> = translator.thunks.clone().into_iter().collect();
+    thunks.sort_by_key(|(func, _)| *func);
+    for (func, (id, captures)) in thunks {
+        translator.thunk(func, id, captures)?;
+    }
+    let entry = translator.entry()?;
+    Ok(Translated {
+        fns: translator.fns,
+        entry,
+        code: translator.code,
+    })
+}
+
+struct Translator<'a, M: Module> {
+    module: &'a mut M,
+    program: &'a Program,
+    fns: Vec<ClFuncId>,
+    /// The thunks of the functions used as values, with how many values they capture.
+    thunks: HashMap<FuncId, (ClFuncId, usize)>,
+    runtime: HashMap<&'static str, ClFuncId>,
+    /// The host functions the program declares with `extern fn`, which take and return C types.
+    externs: HashMap<&'a str, ClFuncId>,
+    /// The constant bytes of strings, shapes and messages.
+    data: HashMap<Vec<u8>, DataId>,
+    globals: Vec<DataId>,
+    /// The discriminant of each struct and variant.
+    variants: HashMap<DefId, usize>,
+    builder: FunctionBuilderContext,
+    code: Vec<Code>,
+}
+
+impl<M: Module> Translator<'_, M> {
+    fn signature(&self, params: usize, returns: bool) -> Signature {
+        let mut signature = self.module.make_signature();
+        signature.params = vec![AbiParam::new(I64); params];
+        if returns {
+            signature.returns.push(AbiParam::new(I64));
+        }
+        signature
+    }
+
+    /// The signature of a function of the program or a thunk, whose calling convention lets a call
+    /// in tail position reuse the caller's frame. The runtime and the entry, which are called from
+    /// C, keep the platform's.
+    fn fn_signature(&self, params: usize) -> Signature {
+        let mut signature = self.signature(params, true);
+        signature.call_conv = CallConv::Tail;
+        signature
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<DataId, Box<ModuleError>> {
+        if let Some(&id) = self.data.get(bytes) {
+            return Ok(id);
+        }
+        let id = self.module.declare_anonymous_data(false, false)?;
+        let mut data = DataDescription::new();
+        // The JIT doesn't allocate data without bytes, leaving it too far from the code to refer to
+        let padded = if bytes.is_empty() { &[0][..] } else { bytes };
+        data.define(padded.into());
+        self.module.define_data(id, &data)?;
+        self.data.insert(bytes.to_vec(), id);
+        Ok(id)
+    }
+
+    /// Defines a function, translating it with `body` into an empty function with its signature.
+    fn define(
+        &mut self,
+        id: ClFuncId,
+        signature: Signature,
+        body: impl FnOnce(&mut Self, &mut FunctionBuilder) -> Result<(), Box<ModuleError>>,
+    ) -> Result<(), Box<ModuleError>> {
+        let mut ctx = self.module.make_context();
+        ctx.func.signature = signature;
+        let mut builder_ctx = std::mem::take(&mut self.builder);
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+        body(self, &mut builder)?;
+        builder.seal_all_blocks();
+        builder.finalize();
+        self.builder = builder_ctx;
+        self.module.define_function(id, &mut ctx)?;
+        let compiled = ctx.compiled_code().unwrap();
+        let unwind =
+            (compiled.create_unwind_info(self.module.isa())).map_err(ModuleError::Compilation)?;
+        self.code.push(Code {
+            func: id,
+            bytes: compiled.code_buffer().to_vec(),
+            relocs: (compiled.buffer.relocs().iter())
+                .map(|reloc| ModuleReloc::from_mach_reloc(reloc, &ctx.func, id))
+                .collect(),
+            srclocs: (compiled.buffer.get_srclocs_sorted().iter())
+                .filter(|srcloc| !srcloc.loc.is_default())
+                .map(|srcloc| (srcloc.start..srcloc.end, srcloc.loc.bits()))
+                .collect(),
+            vars: compiled.value_labels_ranges.clone(),
+            unwind,
+        });
+        Ok(())
+    }
+
+    fn function(&mut self, id: FuncId, func: &Function) -> Result<(), Box<ModuleError>> {
+        let signature = self.fn_signature(func.params().len());
+        self.define(self.fns[id.0], signature, |translator, builder| {
+            if func.blocks.is_empty() {
+                // Only declared, so it's never called
+                let block = builder.create_block();
+                builder.append_block_params_for_function_params(block);
+                builder.switch_to_block(block);
+                builder.ins().trap(UNREACHABLE);
+                return Ok(());
+            }
+            builder.func.dfg.collect_debug_info();
+            builder.set_srcloc(SourceLoc::new(func.span.start as u32));
+            let blocks: Vec<_> = func.blocks.iter().map(|_| builder.create_block()).collect();
+            let mut values = vec![None; func.values.len()];
+            for (id, block) in func.block_ids().zip(&blocks) {
+                for &param in &func.block(id).params {
+                    values[param.0] = Some(builder.append_block_param(*block, I64));
+                }
+            }
+            let mut translator = FnTranslator {
+                translator,
+                builder,
+                func,
+                blocks,
+                values,
+                fails: HashMap::new(),
+                refs: HashMap::new(),
+                data: HashMap::new(),
+            };
+            for id in func.block_ids() {
+                translator.block(id)?;
+            }
+            for (i, var) in func.vars.iter().enumerate() {
+                for value in &var.values {
+                    let value = translator.value(*value);
+                    translator
+                        .builder
+                        .set_val_label(value, ValueLabel::from_u32(i as u32));
+                }
+            }
+            translator.fails()
+        })
+    }
+
+    /// Defines the thunk of a function that's used as a value, which takes the value and the
+    /// function's own arguments, and passes the values it captured to the function.
+    fn thunk(
+        &mut self,
+        func: FuncId,
+        id: ClFuncId,
+        captures: usize,
+    ) -> Result<(), Box<ModuleError>> {
+        let params = self.program.func(func).params().len() - captures;
+        let signature = self.fn_signature(params + 1);
+        let callee = self.fns[func.0];
+        self.define(id, signature, |translator, builder| {
+            let block = builder.create_block();
+            builder.append_block_params_for_function_params(block);
+            builder.switch_to_block(block);
+            let params = builder.block_params(block).to_vec();
+            let mut args = Vec::new();
+            for i in 0..captures {
+                let offset = HEADER + 8 * (i + 1);
+                args.push(
+                    builder
+                        .ins()
+                        .load(I64, MemFlags::trusted(), params[0], offset as i32),
+                );
+            }
+            args.extend(&params[1..]);
+            let callee = translator.module.declare_func_in_func(callee, builder.func);
+            builder.ins().return_call(callee, &args);
+            Ok(())
+        })
+    }
+
+    /// Defines the entry of the program, which initializes the globals in order, then calls
+    /// `main`.
+    fn entry(&mut self) -> Result<ClFuncId, Box<ModuleError>> {
+        let signature = self.signature(0, true);
+        let id = self
+            .module
+            .declare_function(ENTRY, Linkage::Export, &signature)?;
+        self.define(id, signature, |translator, builder| {
+            let block = builder.create_block();
+            builder.switch_to_block(block);
+            for (i, global) in translator.program.globals.iter().enumerate() {
+                let init = translator
+                    .module
+                    .declare_func_in_func(translator.fns[global.init.0], builder.func);
+                let call = builder.ins().call(init, &[]);
+                let value = builder.inst_results(call)[0];
+                let data = translator
+                    .module
+                    .declare_data_in_func(translator.globals[i], builder.func);
+                let address = builder.ins().symbol_value(I64, data);
+                builder.ins().store(MemFlags::trusted(), value, address, 0);
+            }
+            let main = translator
+                .program
+                .fns
+                .iter()
+                .position(|func| func.def.is_some() && func.name == "main");
+            if let Some(main) = main {
+                let main = translator
+                    .module
+                    .declare_func_in_func(translator.fns[main], builder.func);
+                builder.ins().call(main, &[]);
+            }
+            let zero = builder.ins().iconst(I64, 0);
+            builder.ins().return_(&[zero]);
+            Ok(())
+        })?;
+        Ok(id)
+    }
+}
+
+fn int_type(ty: IntTy) -> Type {
+    Type::int(ty.bits() as u16).unwrap()
+}
+
+/// The C type a value of a type is passed to or returned from a host function as, or nothing for
+/// `()`.
+fn extern_param(ty: &Ty) -> Option<AbiParam> {
+    match ty {
+        Ty::Int(int) if int.is_signed() => Some(AbiParam::new(int_type(*int)).sext()),
+        Ty::Int(int) => Some(AbiParam::new(int_type(*int)).uext()),
+        Ty::Float => Some(AbiParam::new(types::F64)),
+        Ty::Bool => Some(AbiParam::new(types::I8).uext()),
+        Ty::Char => Some(AbiParam::new(types::I32).uext()),
+        _ => None,
+    }
+}
+
+struct FnTranslator<'a, 'b, 'm, M: Module> {
+    translator: &'a mut Translator<'m, M>,
+    builder: &'a mut FunctionBuilder<'b>,
+    func: &'a Function,
+    blocks: Vec<Block>,
+    values: Vec<Option<ClValue>>,
+    /// The block that fails with each message.
+    fails: HashMap<&'static str, Block>,
+    /// The functions the function calls.
+    refs: HashMap<ClFuncId, FuncRef>,
+    /// The constant data the function uses.
+    data: HashMap<DataId, GlobalValue>,
+}
+
+impl<M: Module> FnTranslator<'_, '_, '_, M> {
+    fn value(&self, value: Value) -> ClValue {
+        self.values[value.0].expect("value used before it's defined")
+    }
+
+    fn values(&self, values: &[Value]) -> Vec<ClValue> {
+        values.iter().map(|&value| self.value(value)).collect()
+    }
+
+    fn iconst(&mut self, value: i64) -> ClValue {
+        self.builder.ins().iconst(I64, value)
+    }
+
+    fn func_ref(&mut self, id: ClFuncId) -> FuncRef {
+        if let Some(&func_ref) = self.refs.get(&id) {
+            return func_ref;
+        }
+        let func_ref = self
+            .translator
+            .module
+            .declare_func_in_func(id, self.builder.func);
+        self.refs.insert(id, func_ref);
+        func_ref
+    }
+
+    fn data_address(&mut self, id: DataId) -> ClValue {
+        let data = match self.data.get(&id) {
+            Some(&data) => data,
+            None => {
+                let data = self
+                    .translator
+                    .module
+                    .declare_data_in_func(id, self.builder.func);
+                self.data.insert(id, data);
+                data
+            }
+        };
+        self.builder.ins().symbol_value(I64, data)
+    }
+
+    /// Returns the address of constant bytes.
+    fn bytes(&mut self, bytes: &[u8]) -> Result<ClValue, Box<ModuleError>> {
+        let id = self.translator.bytes(bytes)?;
+        Ok(self.data_address(id))
+    }
+
+    /// Calls a function of the runtime library, returning its result if it has one.
+    fn call_runtime(&mut self, name: &str, args: &[ClValue]) -> Option<ClValue> {
+        let id = self.translator.runtime[name];
+        let func_ref = self.func_ref(id);
+        let call = self.builder.ins().call(func_ref, args);
+        self.builder.inst_results(call).first().copied()
+    }
+
+    fn call_runtime_value(&mut self, name: &str, args: &[ClValue]) -> ClValue {
+        self.call_runtime(name, args).unwrap()
+    }
+
+    /// Fails with the message when `cond` is true.
+    fn check(&mut self, cond: ClValue, message: &'static str) {
+        let fail = *self
+            .fails
+            .entry(message)
+            .or_insert_with(|| self.builder.create_block());
+        let ok = self.builder.create_block();
+        self.builder.ins().brif(cond, fail, &[], ok, &[]);
+        self.builder.switch_to_block(ok);
+    }
+
+    /// Fills in the blocks that fail.
+    fn fails(&mut self) -> Result<(), Box<ModuleError>> {
+        let mut fails: Vec<_> = self.fails.clone().into_iter().collect();
+        fails.sort_by_key(|(message, _)| *message);
+        for (message, block) in fails {
+            self.builder.switch_to_block(block);
+            let mut bytes = message.as_bytes().to_vec();
+            bytes.push(0);
+            let message = self.bytes(&bytes)?;
+            self.call_runtime("rf_fail", &[message]);
+            self.builder.ins().trap(UNREACHABLE);
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, id: BlockId) -> Result<(), Box<ModuleError>> {
+        self.builder.switch_to_block(self.blocks[id.0]);
+        let block = self.func.block(id);
+        let tail_call = block.tail_call();
+        for inst in &block.insts {
+            self.builder
+                .set_srcloc(SourceLoc::new(inst.span.start as u32));
+            if let (Some((callee, args)), InstKind::Call { .. }) = (tail_call, &inst.kind) {
+                if inst.value == block.insts.last().unwrap().value {
+                    self.call(callee, args, true);
+                    return Ok(());
+                }
+            }
+            let value = self.inst(inst.value, &inst.kind)?;
+            self.values[inst.value.0] = Some(value);
+        }
+        match &block.terminator {
+            Terminator::Jump(target) => {
+                let args = self.values(&target.args);
+                self.builder.ins().jump(self.blocks[target.block.0], &args);
+            }
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let cond = self.value(*cond);
+                let (then_args, else_args) =
+                    (self.values(&then_block.args), self.values(&else_block.args));
+                self.builder.ins().brif(
+                    cond,
+                    self.blocks[then_block.block.0],
+                    &then_args,
+                    self.blocks[else_block.block.0],
+                    &else_args,
+                );
+            }
+            Terminator::Return(value) => {
+                let value = self.value(*value);
+                self.builder.ins().return_(&[value]);
+            }
+            Terminator::Unreachable => {
+                self.builder.ins().trap(UNREACHABLE);
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocates an object with values in its slots.
+    fn object(
+        &mut self,
+        kind: u32,
+        tag: usize,
+        shape: &[u8],
+        slots: &[ClValue],
+    ) -> Result<ClValue, Box<ModuleError>> {
+        let mut shape = shape.to_vec();
+        shape.push(0);
+        let shape = self.bytes(&shape)?;
+        let args = [
+            self.iconst(kind as i64),
+            self.iconst(tag as i64),
+            self.iconst(slots.len() as i64),
+            shape,
+        ];
+        let object = self.call_runtime_value("rf_alloc", &args);
+        for (i, &value) in slots.iter().enumerate() {
+            let offset = HEADER + 8 * i;
+            self.builder
+                .ins()
+                .store(MemFlags::trusted(), value, object, offset as i32);
+        }
+        Ok(object)
+    }
+
+    fn shape(&self, values: &[Value]) -> Vec<u8> {
+        values
+            .iter()
+            .map(|&value| slot_kind(self.func.value_ty(value)))
+            .collect()
+    }
+
+    fn load_slot(&mut self, object: ClValue, index: usize) -> ClValue {
+        let offset = HEADER + 8 * index;
+        self.builder
+            .ins()
+            .load(I64, MemFlags::trusted(), object, offset as i32)
+    }
+
+    /// Translates an instruction, returning its value.
+    fn inst(&mut self, value: Value, kind: &InstKind) -> Result<ClValue, Box<ModuleError>> {
+        let kind_of = |value: Value| Kind::of(self.func.value_ty(value));
+        Ok(match kind {
+            InstKind::Const(constant) => match constant {
+                Constant::Int(value) => self.iconst(*value as i64),
+                Constant::Float(value) => self.iconst(value.to_bits() as i64),
+                Constant::Bool(value) => self.iconst(*value as i64),
+                Constant::Char(value) => self.iconst(*value as i64),
+                Constant::String(value) => {
+                    let bytes = self.bytes(value.as_bytes())?;
+                    let len = self.iconst(value.len() as i64);
+                    self.call_runtime_value("rf_string", &[bytes, len])
+                }
+                Constant::Unit => self.iconst(0),
+            },
+            InstKind::Undef => self.iconst(0),
+            InstKind::Unary { op, value } => {
+                let operand = self.value(*value);
+                self.unary(*op, kind_of(*value), operand)
+            }
+            InstKind::Binary { op, lhs, rhs } => {
+                let (a, b) = (self.value(*lhs), self.value(*rhs));
+                self.binary(*op, kind_of(*lhs), a, b)
+            }
+            InstKind::Cast(inner) => {
+                let operand = self.value(*inner);
+                self.cast(kind_of(*inner), kind_of(value), operand)
+            }
+            InstKind::Tuple(elems) if elems.is_empty() => self.iconst(0),
+            InstKind::Tuple(elems) => {
+                let values = self.values(elems);
+                let shape = self.shape(elems);
+                self.object(TUPLE, 0, &shape, &values)?
+            }
+            // An array's shape is a single letter, for all of its elements
+            InstKind::Array(elems) => {
+                let values = self.values(elems);
+                let shape = match self.func.value_ty(value) {
+                    Ty::Array(elem) => slot_kind(elem),
+                    _ => b'i',
+                };
+                self.object(ARRAY, 0, &[shape], &values)?
+            }
+            InstKind::Construct { def, fields } => {
+                let values = self.values(fields);
+                let shape = self.shape(fields);
+                let discriminant = self.translator.variants[def];
+                self.object(VARIANT, discriminant, &shape, &values)?
+            }
+            InstKind::Field { base, index } | InstKind::VariantField { base, index, .. } => {
+                let base = self.value(*base);
+                self.load_slot(base, *index)
+            }
+            InstKind::SetField { base, index, value } => {
+                let index = self.iconst(*index as i64);
+                let args = [self.value(*base), index, self.value(*value)];
+                self.call_runtime_value("rf_set_field", &args)
+            }
+            InstKind::Discriminant(base) => {
+                let base = self.value(*base);
+                self.builder.ins().uload32(MemFlags::trusted(), base, 4)
+            }
+            InstKind::Index { base, index } => {
+                let args = self.values(&[*base, *index]);
+                self.call_runtime_value("rf_index", &args)
+            }
+            InstKind::SetIndex { base, index, value } => {
+                let args = self.values(&[*base, *index, *value]);
+                self.call_runtime_value("rf_set_index", &args)
+            }
+            InstKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let flags = start.is_some() as usize
+                    | (end.is_some() as usize) << 1
+                    | (*inclusive as usize) << 2;
+                let mut bound = |bound: &Option<Value>| match bound {
+                    Some(bound) => self.value(*bound),
+                    None => self.iconst(0),
+                };
+                let values = [bound(start), bound(end)];
+                let elem = match self.func.value_ty(value) {
+                    Ty::Range(elem) => slot_kind(elem),
+                    _ => b'i',
+                };
+                self.object(RANGE, flags, &[elem, elem], &values)?
+            }
+            InstKind::Global(global) => {
+                let address = self.data_address(self.translator.globals[global.0]);
+                self.builder
+                    .ins()
+                    .load(I64, MemFlags::trusted(), address, 0)
+            }
+            InstKind::SetGlobal { global, value } => {
+                let address = self.data_address(self.translator.globals[global.0]);
+                let value = self.value(*value);
+                self.builder
+                    .ins()
+                    .store(MemFlags::trusted(), value, address, 0);
+                self.iconst(0)
+            }
+            InstKind::FnRef(func) => self.function_value(*func, &[])?,
+            InstKind::Closure { func, captures } => self.function_value(*func, captures)?,
+            InstKind::Call { callee, args } => self.call(*callee, args, false).unwrap(),
+            InstKind::Extern { name, args } => {
+                let args: Vec<_> = (args.iter())
+                    .map(|&arg| {
+                        let value = self.value(arg);
+                        match self.func.value_ty(arg) {
+                            Ty::Int(int) => self.narrow(value, *int),
+                            Ty::Float => self.bits_to_float(value),
+                            Ty::Bool => self.builder.ins().ireduce(types::I8, value),
+                            _ => self.builder.ins().ireduce(types::I32, value),
+                        }
+                    })
+                    .collect();
+                let func_ref = self.func_ref(self.translator.externs[name.as_str()]);
+                let call = self.builder.ins().call(func_ref, &args);
+                let result = self.builder.inst_results(call).first().copied();
+                match (self.func.value_ty(value), result) {
+                    (Ty::Int(int), Some(result)) => self.widen(result, *int),
+                    (Ty::Float, Some(result)) => self.float_to_bits(result),
+                    (_, Some(result)) => self.builder.ins().uextend(I64, result),
+                    (_, None) => self.iconst(0),
+                }
+            }
+            InstKind::Generator { .. }
+            | InstKind::Intrinsic {
+                intrinsic: Intrinsic::Yield | Intrinsic::Next,
+                ..
+            } => {
+                let fails = self.iconst(1);
+                self.check(fails, GENERATORS);
+                self.iconst(0)
+            }
+            InstKind::Intrinsic { intrinsic, args } => {
+                let args = self.values(args);
+                match intrinsic {
+                    Intrinsic::Panic => {
+                        self.call_runtime("rf_panic", &args);
+                        self.iconst(0)
+                    }
+                    Intrinsic::Print => {
+                        self.call_runtime("rf_print", &args);
+                        self.iconst(0)
+                    }
+                    Intrinsic::Println => {
+                        self.call_runtime("rf_println", &args);
+                        self.iconst(0)
+                    }
+                    Intrinsic::NewMap => {
+                        let shape = match self.func.value_ty(value) {
+                            Ty::Map(key, value) => [slot_kind(key), slot_kind(value)],
+                            _ => [b'i', b'i'],
+                        };
+                        self.object(MAP, 0, &shape, &[])?
+                    }
+                    // The others return what the library function of the same name does
+                    _ => self.call_runtime_value(&format!("rf_{}", intrinsic.name()), &args),
+                }
+            }
+        })
+    }
+
+    /// Calls a function, returning its value, or returns what it does when the call is in tail
+    /// position, so that it reuses the frame.
+    fn call(&mut self, callee: Callee, args: &[Value], tail: bool) -> Option<ClValue> {
+        let mut args = self.values(args);
+        let call = match callee {
+            Callee::Direct(func) => {
+                let func_ref = self.func_ref(self.translator.fns[func.0]);
+                if tail {
+                    self.builder.ins().return_call(func_ref, &args);
+                    return None;
+                }
+                self.builder.ins().call(func_ref, &args)
+            }
+            Callee::Indirect(callee) => {
+                let callee = self.value(callee);
+                let code = self.load_slot(callee, 0);
+                args.insert(0, callee);
+                let signature = self.translator.fn_signature(args.len());
+                let signature = self.builder.import_signature(signature);
+                if tail {
+                    self.builder
+                        .ins()
+                        .return_call_indirect(signature, code, &args);
+                    return None;
+                }
+                self.builder.ins().call_indirect(signature, code, &args)
+            }
+        };
+        Some(self.builder.inst_results(call)[0])
+    }
+
+    /// Makes a function value, whose first slot points to the function's thunk and whose other
+    /// slots hold the values it captures.
+    fn function_value(
+        &mut self,
+        func: FuncId,
+        captures: &[Value],
+    ) -> Result<ClValue, Box<ModuleError>> {
+        let thunk = match self.translator.thunks.get(&func) {
+            Some(&(thunk, _)) => thunk,
+            None => {
+                let params = self.translator.program.func(func).params().len() - captures.len();
+                let signature = self.translator.fn_signature(params + 1);
+                let thunk = self.translator.module.declare_function(
+                    &format!("fn{}.thunk", func.0),
+                    Linkage::Local,
+                    &signature,
+                )?;
+                self.translator.thunks.insert(func, (thunk, captures.len()));
+                thunk
+            }
+        };
+        let func_ref = self.func_ref(thunk);
+        let code = self.builder.ins().func_addr(I64, func_ref);
+        let mut values = vec![code];
+        values.extend(self.values(captures));
+        let mut shape = vec![b'i'];
+        shape.extend(self.shape(captures));
+        self.object(FUNCTION, 0, &shape, &values)
+    }
+
+    /// Truncates an integer to the width of its type.
+    fn narrow(&mut self, value: ClValue, ty: IntTy) -> ClValue {
+        match ty.bits() {
+            64 => value,
+            _ => self.builder.ins().ireduce(int_type(ty), value),
+        }
+    }
+
+    /// Extends an integer of the width of its type back to an `i64`.
+    fn widen(&mut self, value: ClValue, ty: IntTy) -> ClValue {
+        match ty.bits() {
+            64 => value,
+            _ if ty.is_signed() => self.builder.ins().sextend(I64, value),
+            _ => self.builder.ins().uextend(I64, value),
+        }
+    }
+
+    fn bool_to_int(&mut self, value: ClValue) -> ClValue {
+        self.builder.ins().uextend(I64, value)
+    }
+
+    fn bits_to_float(&mut self, value: ClValue) -> ClValue {
+        self.builder
+            .ins()
+            .bitcast(types::F64, MemFlags::new(), value)
+    }
+
+    fn float_to_bits(&mut self, value: ClValue) -> ClValue {
+        self.builder.ins().bitcast(I64, MemFlags::new(), value)
+    }
+
+    fn unary(&mut self, op: UnaryOp, kind: Kind, operand: ClValue) -> ClValue {
+        match (op, kind) {
+            (UnaryOp::Neg, Kind::Int(_)) => {
+                let zero = self.iconst(0);
+                self.binary(BinaryOp::Sub, kind, zero, operand)
+            }
+            (UnaryOp::Neg, _) => {
+                let float = self.bits_to_float(operand);
+                let result = self.builder.ins().fneg(float);
+                self.float_to_bits(result)
+            }
+            (UnaryOp::Not, Kind::Int(ty)) => {
+                let narrow = self.narrow(operand, ty);
+                let result = self.builder.ins().bnot(narrow);
+                self.widen(result, ty)
+            }
+            (UnaryOp::Not, _) => self.builder.ins().bxor_imm(operand, 1),
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, kind: Kind, lhs: ClValue, rhs: ClValue) -> ClValue {
+        match kind {
+            Kind::Int(ty) => self.int_binary(op, ty, lhs, rhs),
+            Kind::Float if op == BinaryOp::Rem => {
+                self.call_runtime_value("rf_float_rem", &[lhs, rhs])
+            }
+            Kind::Float => {
+                let (a, b) = (self.bits_to_float(lhs), self.bits_to_float(rhs));
+                let ins = self.builder.ins();
+                let result = match op {
+                    BinaryOp::Add => ins.fadd(a, b),
+                    BinaryOp::Sub => ins.fsub(a, b),
+                    BinaryOp::Mul => ins.fmul(a, b),
+                    BinaryOp::Div => ins.fdiv(a, b),
+                    _ => {
+                        let cond = match op {
+                            BinaryOp::Eq | BinaryOp::TripleEq => FloatCC::Equal,
+                            BinaryOp::Ne | BinaryOp::TripleNe => FloatCC::NotEqual,
+                            BinaryOp::Lt => FloatCC::LessThan,
+                            BinaryOp::Le => FloatCC::LessThanOrEqual,
+                            BinaryOp::Gt => FloatCC::GreaterThan,
+                            _ => FloatCC::GreaterThanOrEqual,
+                        };
+                        let result = ins.fcmp(cond, a, b);
+                        return self.bool_to_int(result);
+                    }
+                };
+                self.float_to_bits(result)
+            }
+            Kind::String if op == BinaryOp::Add => {
+                self.call_runtime_value("rf_concat", &[lhs, rhs])
+            }
+            Kind::String => {
+                let order = self.call_runtime_value("rf_compare_strings", &[lhs, rhs]);
+                let zero = self.iconst(0);
+                self.compare(op, true, order, zero)
+            }
+            Kind::Bool | Kind::Char => match op {
+                BinaryOp::And | BinaryOp::BitAnd => self.builder.ins().band(lhs, rhs),
+                BinaryOp::Or | BinaryOp::BitOr => self.builder.ins().bor(lhs, rhs),
+                BinaryOp::BitXor => self.builder.ins().bxor(lhs, rhs),
+                _ => self.compare(op, false, lhs, rhs),
+            },
+            Kind::Other => {
+                let kind = self.iconst(b'o' as i64);
+                let equal = self.call_runtime_value("rf_equal", &[lhs, rhs, kind]);
+                match op {
+                    BinaryOp::Ne | BinaryOp::TripleNe => self.builder.ins().bxor_imm(equal, 1),
+                    _ => equal,
+                }
+            }
+        }
+    }
+
+    /// Compares two `i64`s, which are signed when `signed`.
+    fn compare(&mut self, op: BinaryOp, signed: bool, lhs: ClValue, rhs: ClValue) -> ClValue {
+        let cond = match (op, signed) {
+            (BinaryOp::Eq | BinaryOp::TripleEq, _) => IntCC::Equal,
+            (BinaryOp::Ne | BinaryOp::TripleNe, _) => IntCC::NotEqual,
+            (BinaryOp::Lt, true) => IntCC::SignedLessThan,
+            (BinaryOp::Le, true) => IntCC::SignedLessThanOrEqual,
+            (BinaryOp::Gt, true) => IntCC::SignedGreaterThan,
+            (_, true) => IntCC::SignedGreaterThanOrEqual,
+            (BinaryOp::Lt, false) => IntCC::UnsignedLessThan,
+            (BinaryOp::Le, false) => IntCC::UnsignedLessThanOrEqual,
+            (BinaryOp::Gt, false) => IntCC::UnsignedGreaterThan,
+            (_, false) => IntCC::UnsignedGreaterThanOrEqual,
+        };
+        let result = self.builder.ins().icmp(cond, lhs, rhs);
+        self.bool_to_int(result)
+    }
+
+    fn int_binary(&mut self, op: BinaryOp, ty: IntTy, lhs: ClValue, rhs: ClValue) -> ClValue {
+        let signed = ty.is_signed();
+        match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => {
+                let (a, b) = (self.narrow(lhs, ty), self.narrow(rhs, ty));
+                let ins = self.builder.ins();
+                let (result, overflow) = match (op, signed) {
+                    (BinaryOp::Add, true) => ins.sadd_overflow(a, b),
+                    (BinaryOp::Add, false) => ins.uadd_overflow(a, b),
+                    (BinaryOp::Sub, true) => ins.ssub_overflow(a, b),
+                    (BinaryOp::Sub, false) => ins.usub_overflow(a, b),
+                    (_, true) => ins.smul_overflow(a, b),
+                    (_, false) => ins.umul_overflow(a, b),
+                };
+                self.check(overflow, OVERFLOW);
+                self.widen(result, ty)
+            }
+            BinaryOp::Div | BinaryOp::Rem => {
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, rhs, 0);
+                self.check(zero, DIVISION_BY_ZERO);
+                if signed {
+                    // The quotient of the smallest value and -1 is one more than the largest
+                    let ins = self.builder.ins();
+                    let min = ins.icmp_imm(IntCC::Equal, lhs, ty.min() as i64);
+                    let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+                    let overflow = self.builder.ins().band(min, minus_one);
+                    self.check(overflow, OVERFLOW);
+                }
+                // Integers extended from their type divide the same way as in it
+                let ins = self.builder.ins();
+                match (op, signed) {
+                    (BinaryOp::Div, true) => ins.sdiv(lhs, rhs),
+                    (BinaryOp::Div, false) => ins.udiv(lhs, rhs),
+                    (_, true) => ins.srem(lhs, rhs),
+                    (_, false) => ins.urem(lhs, rhs),
+                }
+            }
+            BinaryOp::Shl | BinaryOp::Shr => {
+                // A negative amount is a large unsigned one
+                let out_of_range = self.builder.ins().icmp_imm(
+                    IntCC::UnsignedGreaterThanOrEqual,
+                    rhs,
+                    ty.bits() as i64,
+                );
+                self.check(out_of_range, SHIFT_OUT_OF_RANGE);
+                match op {
+                    BinaryOp::Shl => {
+                        let result = self.builder.ins().ishl(lhs, rhs);
+                        let narrow = self.narrow(result, ty);
+                        self.widen(narrow, ty)
+                    }
+                    _ if signed => self.builder.ins().sshr(lhs, rhs),
+                    _ => self.builder.ins().ushr(lhs, rhs),
+                }
+            }
+            // Integers extended the same way combine bit by bit into one that's extended that way
+            BinaryOp::BitAnd => self.builder.ins().band(lhs, rhs),
+            BinaryOp::BitOr => self.builder.ins().bor(lhs, rhs),
+            BinaryOp::BitXor => self.builder.ins().bxor(lhs, rhs),
+            _ => self.compare(op, signed, lhs, rhs),
+        }
+    }
+
+    fn cast(&mut self, from: Kind, to: Kind, value: ClValue) -> ClValue {
+        match (from, to) {
+            (Kind::Int(from), Kind::Float) => {
+                let narrow = self.narrow(value, from);
+                let float = match from.is_signed() {
+                    true => self.builder.ins().fcvt_from_sint(types::F64, narrow),
+                    false => self.builder.ins().fcvt_from_uint(types::F64, narrow),
+                };
+                self.float_to_bits(float)
+            }
+            (Kind::Float, Kind::Int(to)) => {
+                // Saturating, like `IntTy::from_float`
+                let float = self.bits_to_float(value);
+                let result = match to.is_signed() {
+                    true => self.builder.ins().fcvt_to_sint_sat(int_type(to), float),
+                    false => self.builder.ins().fcvt_to_uint_sat(int_type(to), float),
+                };
+                self.widen(result, to)
+            }
+            (Kind::Int(_) | Kind::Char | Kind::Bool, Kind::Int(to)) => {
+                let narrow = self.narrow(value, to);
+                self.widen(narrow, to)
+            }
+            (Kind::Int(_), Kind::Char) => {
+                let narrow = self.narrow(value, IntTy::U32);
+                self.widen(narrow, IntTy::U32)
+            }
+            (Kind::Int(_) | Kind::Bool | Kind::Char, Kind::String) => {
+                let kind = self
+                    .builder
+                    .ins()
+                    .iconst(types::I64, text_kind(from) as i64);
+                self.call_runtime_value("rf_to_string", &[value, kind])
+            }
+            _ => value,
+        }
+    }
+}
+
+/// A way compiling a program into memory can fail.
+#[derive(Debug)]
+pub enum JitError {
+    /// Cranelift can't compile for the machine the compiler is running on.
+    UnsupportedHost(String),
+    /// A host function that the program calls but the running process doesn't have.
+    MissingExtern(String),
+    Module(Box<ModuleError>),
+}
+
+impl Display for JitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JitError::UnsupportedHost(message) => {
+                write!(f, "can't compile for this machine: {}", message)
+            }
+            JitError::MissingExtern(name) => write!(
+                f,
+                "the host function `{}` isn't in the compiler, so the JIT can't call it",
+                name
+            ),
+            JitError::Module(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<ModuleError> for JitError {
+    fn from(error: ModuleError) -> JitError {
+        JitError::Module(Box::new(error))
+    }
+}
+
+/// The settings shared by code compiled into memory and into objects, for an optimization level,
+/// which Cranelift only tells optimizing from not.
+pub(super) fn flags(level: OptLevel) -> settings::Builder {
+    let mut flags = settings::builder();
+    let opt_level = match level {
+        OptLevel::O0 => "none",
+        OptLevel::O1 | OptLevel::O2 | OptLevel::O3 => "speed",
+    };
+    flags.set("opt_level", opt_level).unwrap();
+    // Cranelift needs them to make calls in tail position
+    flags.set("preserve_frame_pointers", "true").unwrap();
+    flags
+}
+
+/// The stack of the thread that compiled code runs on, as big as the interpreter's, since the
+/// code doesn't limit how deeply calls nest.
+const STACK: usize = 1 << 30;
+
+/// Runs compiled code on a thread with a stack of [`STACK`] bytes.
+fn on_stack<T: Send>(code: impl FnOnce() -> T + Send) -> T {
+    thread::scope(|scope| {
+        (thread::Builder::new().stack_size(STACK))
+            .spawn_scoped(scope, code)
+            .unwrap()
+            .join()
+            .unwrap()
+    })
+}
+
+/// Returns whether the running process has a C function with a name, which only the functions of
+/// the libraries the compiler is linked with do.
+fn linked(name: &str) -> bool {
+    let Ok(name) = CString::new(name) else {
+        return false;
+    };
+    // SAFETY: the name is a C string, and the address is only compared
+    !unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) }.is_null()
+}
+
+/// A program compiled into memory, ready to run.
+pub struct Jit {
+    module: JITModule,
+    translated: Translated,
+    /// How many parameters each function has.
+    params: Vec<usize>,
+}
+
+impl Jit {
+    pub fn new(program: &Program, level: OptLevel) -> Result<Jit, JitError> {
+        // Cranelift panics on a symbol it can't find when it links the code
+        if let Some((name, ..)) = program
+            .externs()
+            .into_iter()
+            .find(|(name, ..)| !linked(name))
+        {
+            return Err(JitError::MissingExtern(name.to_string()));
+        }
+        let mut flags = flags(level);
+        // Code in memory calls the runtime library wherever it happens to be
+        flags.set("use_colocated_libcalls", "false").unwrap();
+        flags.set("is_pic", "false").unwrap();
+        let isa = cranelift_native::builder()
+            .map_err(|message| JitError::UnsupportedHost(message.to_string()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|error| JitError::UnsupportedHost(error.to_string()))?;
+
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        for ((name, ..), address) in runtime::FUNCTIONS.iter().zip(runtime::addresses()) {
+            builder.symbol(*name, address);
+        }
+        let mut module = JITModule::new(builder);
+        let translated = translate(&mut module, program).map_err(JitError::Module)?;
+        module.finalize_definitions()?;
+        Ok(Jit {
+            module,
+            translated,
+            params: program.fns.iter().map(|func| func.params().len()).collect(),
+        })
+    }
+
+    /// Runs the program, initializing its globals and then calling its `main`. A program that
+    /// panics exits the process.
+    pub fn run(&self) {
+        let entry = self.module.get_finalized_function(self.translated.entry);
+        // SAFETY: the entry takes nothing and returns an `i64`
+        let entry: extern "C" fn() -> i64 = unsafe { std::mem::transmute(entry) };
+        on_stack(|| entry());
+    }
+
+    /// Calls a function of the program with its arguments, which are represented as in the
+    /// runtime library, returning its result. Globals aren't initialized until [`Jit::run`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the function doesn't take that many arguments, or more than four.
+    pub fn call(&self, func: FuncId, args: &[i64]) -> i64 {
+        assert_eq!(args.len(), self.params[func.0], "wrong number of arguments");
+        let code = self
+            .module
+            .get_finalized_function(self.translated.fns[func.0]);
+        // The address of the code, which, unlike a pointer, can be sent to the thread it runs on
+        let code = code as usize;
+        // SAFETY: every function takes `i64`s and returns one, and it takes as many as there are
+        // arguments
+        on_stack(|| unsafe {
+            use std::mem::transmute;
+            let code = code as *const u8;
+            match *args {
+                [] => transmute::<*const u8, extern "C" fn() -> i64>(code)(),
+                [a] => transmute::<*const u8, extern "C" fn(i64) -> i64>(code)(a),
+                [a, b] => transmute::<*const u8, extern "C" fn(i64, i64) -> i64>(code)(a, b),
+                [a, b, c] => {
+                    transmute::<*const u8, extern "C" fn(i64, i64, i64) -> i64>(code)(a, b, c)
+                }
+                [a, b, c, d] => {
+                    type Code = extern "C" fn(i64, i64, i64, i64) -> i64;
+                    transmute::<*const u8, Code>(code)(a, b, c, d)
+                }
+                _ => panic!("too many arguments"),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+
+    fn jit(source: &str) -> (Program, Jit) {
+        let mir = built(source);
+        let jit = Jit::new(&mir, OptLevel::O2).unwrap();
+        (mir, jit)
+    }
+
+    fn func(program: &Program, name: &str) -> FuncId {
+        FuncId(program.fns.iter().position(|f| f.name == name).unwrap())
+    }
+
+    /// Compiles a program and calls one of its functions.
+    fn call(source: &str, name: &str, args: &[i64]) -> i64 {
+        let (program, jit) = jit(source);
+        jit.call(func(&program, name), args)
+    }
+
+    #[test]
+    fn test_recursion() {
+        let source = "fn fib(n: int) int { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }";
+        assert_eq!(call(source, "fib", &[20]), 6765);
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        let source = "fn depth(n: int) int { if n == 0 { 0 } else { depth(n - 1) + 1 } }";
+        // Deeper than the stack of the main thread
+        assert_eq!(call(source, "depth", &[1_000_000]), 1_000_000);
+    }
+
+    #[test]
+    fn test_closures_and_enums() {
+        let source = "
+enum Shape { Circle(float), Square(int) }
+fn area(shape: Shape) int {
+    match shape {
+        Shape::Circle(r) => (r * r * 3.0) as int,
+        Shape::Square(side) => side * side,
+    }
+}
+fn shapes(n: int) int {
+    let add = |shape: Shape| area(shape) + n;
+    add(Shape::Circle(2.0)) + add(Shape::Square(3))
+}";
+        assert_eq!(call(source, "shapes", &[1]), 23);
+    }
+
+    #[test]
+    fn test_bits() {
+        let source = "fn bits(x: u8) u8 { !x >> 1 }";
+        assert_eq!(call(source, "bits", &[0b1010]), 0b0111_1010);
+    }
+
+    #[test]
+    fn test_strings() {
+        let source = "
+fn letters() int {
+    let mut n = 0;
+    for c in \"héllo\" + \"!\" { if c != 'l' { n += 1; } }
+    n
+}
+fn blank() int { \"\".len() + 1 }";
+        assert_eq!(call(source, "letters", &[]), 4);
+        assert_eq!(call(source, "blank", &[]), 1);
+    }
+
+    #[test]
+    fn test_missing_externs() {
+        let source = "extern fn labs(n: int) int;
+fn main() int { labs(-3) }";
+        let (program, jit) = jit(source);
+        assert_eq!(jit.call(func(&program, "main"), &[]), 3);
+
+        let source = "extern fn ruffle_host(n: int) int;
+fn main() int { ruffle_host(-3) }";
+        let error = Jit::new(&built(source), OptLevel::O2).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "the host function `ruffle_host` isn't in the compiler, so the JIT can't call it"
+        );
+    }
+}
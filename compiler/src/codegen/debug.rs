@@ -0,0 +1,531 @@
+//! Describes the machine code the Cranelift backend compiles a program to in DWARF, with `-g`, so
+//! that gdb and lldb can step through its source and show its variables.
+//!
+//! The line table maps the code of each function back to the spans of the MIR instructions it was
+//! compiled from. Each function has an entry with its variables, whose locations are wherever
+//! register allocation put the values that hold them while they're live, and a frame table tells
+//! debuggers how to find the frames of its callers. Addresses are relocations against the symbols
+//! of the functions, so the sections are added to the object before it's written.
+
+use std::{collections::HashMap, env};
+
+use cranelift_codegen::{
+    ir::ValueLabel,
+    isa::{unwind::UnwindInfo, TargetIsa},
+    LabelValueLoc,
+};
+use cranelift_module::{FuncId as ClFuncId, ModuleDeclarations};
+use cranelift_object::ObjectProduct;
+use gimli::{
+    write::{
+        Address, AttributeValue, DwarfUnit, EndianVec, Expression, FileId as LineFileId,
+        FrameTable, LineProgram, LineString, Location, LocationList, Range, RangeList, Sections,
+        UnitEntryId, Writer,
+    },
+    Encoding, Format, LineEncoding, Register, RunTimeEndian, SectionId,
+};
+use object::{
+    write::{Relocation, SectionId as ObjectSectionId},
+    RelocationEncoding, RelocationFlags, RelocationKind, SectionKind,
+};
+
+use super::{
+    bytecode::Kind,
+    cranelift::{Code, Translated},
+};
+use crate::{
+    mir::{Function, Program},
+    source_map::SourceMap,
+    typeck::Ty,
+};
+
+/// The DWARF sections of a compiled program, ready to be added to its object.
+pub struct Debug {
+    /// The function each [`Address::Symbol`] refers to, by its index.
+    symbols: Vec<ClFuncId>,
+    sections: Vec<(SectionId, Relocating)>,
+}
+
+/// Describes the code of a program that was translated into a module, before the module is
+/// finished.
+pub fn describe(
+    isa: &dyn TargetIsa,
+    decls: &ModuleDeclarations,
+    program: &Program,
+    translated: &Translated,
+    source_map: &SourceMap,
+) -> Debug {
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 4,
+        address_size: 8,
+    };
+    let comp_dir = env::current_dir().map_or_else(
+        |_| ".".to_string(),
+        |dir| dir.to_string_lossy().into_owned(),
+    );
+    let name = (source_map.files().first()).map_or_else(
+        || "main.rf".to_string(),
+        |file| file.path.display().to_string(),
+    );
+    let mut dwarf = DwarfUnit::new(encoding);
+    let mut lines = LineProgram::new(
+        encoding,
+        LineEncoding::default(),
+        LineString::String(comp_dir.clone().into_bytes()),
+        LineString::String(name.clone().into_bytes()),
+        None,
+    );
+    let files: Vec<LineFileId> = (source_map.files().iter())
+        .map(|file| {
+            let dir = match file.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => lines.add_directory(
+                    LineString::String(dir.to_string_lossy().as_bytes().to_vec()),
+                ),
+                _ => lines.default_directory(),
+            };
+            let name = file.path.file_name().unwrap_or(file.path.as_os_str());
+            lines.add_file(
+                LineString::String(name.to_string_lossy().as_bytes().to_vec()),
+                dir,
+                None,
+            )
+        })
+        .collect();
+    // The file index and row of an offset into the source
+    let position = |offset: usize| {
+        let file = source_map.file_at(offset)?;
+        let location = source_map.location(offset)?;
+        Some((files[file.0], location.row as u64, location.col as u64))
+    };
+
+    let root = dwarf.unit.root();
+    let strings = &mut dwarf.strings;
+    let entry = dwarf.unit.get_mut(root);
+    entry.set(
+        gimli::DW_AT_producer,
+        AttributeValue::String(b"ruffle".to_vec()),
+    );
+    // DWARF has no code for Ruffle, and debuggers need a language to print values in, so it
+    // passes for C, which has all of its scalar types
+    entry.set(
+        gimli::DW_AT_language,
+        AttributeValue::Language(gimli::DW_LANG_C99),
+    );
+    entry.set(
+        gimli::DW_AT_name,
+        AttributeValue::StringRef(strings.add(name)),
+    );
+    entry.set(
+        gimli::DW_AT_comp_dir,
+        AttributeValue::StringRef(strings.add(comp_dir)),
+    );
+    entry.set(
+        gimli::DW_AT_low_pc,
+        AttributeValue::Address(Address::Constant(0)),
+    );
+
+    let mut describer = Describer {
+        isa,
+        dwarf: &mut dwarf,
+        types: HashMap::new(),
+    };
+    let funcs: HashMap<ClFuncId, &Function> =
+        (translated.fns.iter().copied()).zip(&program.fns).collect();
+    let mut ranges = Vec::new();
+    let mut frames = FrameTable::default();
+    let cie = isa.create_systemv_cie().map(|cie| frames.add_cie(cie));
+    for (symbol, code) in translated.code.iter().enumerate() {
+        let address = |offset: u32| Address::Symbol {
+            symbol,
+            addend: offset as i64,
+        };
+        let len = code.bytes.len() as u32;
+        ranges.push(Range::StartLength {
+            begin: address(0),
+            length: len as u64,
+        });
+
+        let func = funcs.get(&code.func);
+        if !code.srclocs.is_empty() {
+            lines.begin_sequence(Some(address(0)));
+            // The prologue belongs to the function's definition, where debuggers stop on entering
+            // it before they skip to the next row
+            let prologue = (func.filter(|_| code.srclocs[0].0.start > 0))
+                .map(|func| (0, func.span.start as u32));
+            let srclocs = (code.srclocs.iter()).map(|(range, offset)| (range.start, *offset));
+            for (start, offset) in prologue.into_iter().chain(srclocs) {
+                let Some((file, line, column)) = position(offset as usize) else {
+                    continue;
+                };
+                let row = lines.row();
+                row.address_offset = start as u64;
+                row.file = file;
+                row.line = line;
+                row.column = column;
+                lines.generate_row();
+            }
+            lines.end_sequence(len as u64);
+        }
+
+        let entry = describer.dwarf.unit.add(root, gimli::DW_TAG_subprogram);
+        let name = match func {
+            Some(func) => func.name.clone(),
+            None => decls
+                .get_function_decl(code.func)
+                .linkage_name(code.func)
+                .into_owned(),
+        };
+        let name = describer.dwarf.strings.add(name);
+        let subprogram = describer.dwarf.unit.get_mut(entry);
+        subprogram.set(gimli::DW_AT_name, AttributeValue::StringRef(name));
+        subprogram.set(gimli::DW_AT_low_pc, AttributeValue::Address(address(0)));
+        subprogram.set(gimli::DW_AT_high_pc, AttributeValue::Udata(len as u64));
+        let mut frame_base = Expression::new();
+        frame_base.op(gimli::DW_OP_call_frame_cfa);
+        subprogram.set(gimli::DW_AT_frame_base, AttributeValue::Exprloc(frame_base));
+        match func {
+            Some(func) => {
+                if let Some((file, line, _)) = position(func.span.start) {
+                    subprogram.set(
+                        gimli::DW_AT_decl_file,
+                        AttributeValue::FileIndex(Some(file)),
+                    );
+                    subprogram.set(gimli::DW_AT_decl_line, AttributeValue::Udata(line));
+                }
+                if !func.ret.is_unit() {
+                    let ty = describer.base_type(&func.ret);
+                    let subprogram = describer.dwarf.unit.get_mut(entry);
+                    subprogram.set(gimli::DW_AT_type, AttributeValue::UnitRef(ty));
+                }
+                describer.vars(entry, func, code, len, &position, address);
+            }
+            // Thunks and the entry, which aren't in the source
+            None => subprogram.set(gimli::DW_AT_artificial, AttributeValue::Flag(true)),
+        }
+
+        if let (Some(cie), Some(UnwindInfo::SystemV(info))) = (cie, &code.unwind) {
+            frames.add_fde(cie, info.to_fde(address(0)));
+        }
+    }
+    let ranges = dwarf.unit.ranges.add(RangeList(ranges));
+    let entry = dwarf.unit.get_mut(root);
+    entry.set(gimli::DW_AT_ranges, AttributeValue::RangeListRef(ranges));
+    dwarf.unit.line_program = lines;
+
+    // Writing only fails for entries that don't make sense, which would be a bug here
+    let mut sections = Sections::new(Relocating::default());
+    dwarf.write(&mut sections).unwrap();
+    frames.write_debug_frame(&mut sections.debug_frame).unwrap();
+    let mut written = Vec::new();
+    (sections.for_each(|id, section| {
+        if section.data.len() > 0 {
+            written.push((id, section.clone()));
+        }
+        Ok::<_, gimli::write::Error>(())
+    }))
+    .unwrap();
+    Debug {
+        symbols: translated.code.iter().map(|code| code.func).collect(),
+        sections: written,
+    }
+}
+
+impl Debug {
+    /// Adds the sections to the object of the module they describe.
+    pub fn add_to(self, product: &mut ObjectProduct) {
+        let ids: HashMap<SectionId, ObjectSectionId> = (self.sections.iter())
+            .map(|(id, section)| {
+                let object_id = product.object.add_section(
+                    Vec::new(),
+                    id.name().as_bytes().to_vec(),
+                    SectionKind::Debug,
+                );
+                (product.object).set_section_data(object_id, section.data.slice().to_vec(), 1);
+                (*id, object_id)
+            })
+            .collect();
+        for (id, section) in &self.sections {
+            for reloc in &section.relocs {
+                let symbol = match reloc.target {
+                    Target::Symbol(symbol) => product.function_symbol(self.symbols[symbol]),
+                    Target::Section(section) => product.object.section_symbol(ids[&section]),
+                };
+                let relocation = Relocation {
+                    offset: reloc.offset as u64,
+                    symbol,
+                    addend: reloc.addend,
+                    flags: RelocationFlags::Generic {
+                        kind: RelocationKind::Absolute,
+                        encoding: RelocationEncoding::Generic,
+                        size: reloc.size * 8,
+                    },
+                };
+                // Absolute relocations of 4 and 8 bytes are supported by every format
+                (product.object.add_relocation(ids[id], relocation)).unwrap();
+            }
+        }
+    }
+}
+
+struct Describer<'a> {
+    isa: &'a dyn TargetIsa,
+    dwarf: &'a mut DwarfUnit,
+    /// The entry of each type, by its name.
+    types: HashMap<String, UnitEntryId>,
+}
+
+impl Describer<'_> {
+    /// Adds an entry for each variable of a function, with where it's kept while it's live.
+    fn vars(
+        &mut self,
+        parent: UnitEntryId,
+        func: &Function,
+        code: &Code,
+        len: u32,
+        position: &dyn Fn(usize) -> Option<(LineFileId, u64, u64)>,
+        address: impl Fn(u32) -> Address,
+    ) {
+        for (label, var) in func.vars.iter().enumerate() {
+            let param = var.values.iter().any(|value| func.params().contains(value));
+            let tag = if param {
+                gimli::DW_TAG_formal_parameter
+            } else {
+                gimli::DW_TAG_variable
+            };
+            let ty = self.base_type(func.value_ty(var.values[0]));
+            let locations: Vec<_> = (code.vars.get(&ValueLabel::from_u32(label as u32)))
+                .into_iter()
+                .flatten()
+                .filter(|range| range.start < range.end.min(len))
+                .filter_map(|range| {
+                    let mut expression = Expression::new();
+                    match range.loc {
+                        LabelValueLoc::Reg(reg) => {
+                            let reg = self.isa.map_regalloc_reg_to_dwarf(reg).ok()?;
+                            expression.op_reg(Register(reg));
+                        }
+                        LabelValueLoc::CFAOffset(offset) => expression.op_fbreg(offset),
+                    }
+                    Some(Location::StartEnd {
+                        begin: address(range.start),
+                        end: address(range.end.min(len)),
+                        data: expression,
+                    })
+                })
+                .collect();
+
+            let name = self.dwarf.strings.add(var.name.as_str());
+            let id = self.dwarf.unit.add(parent, tag);
+            // A variable without locations was optimized out
+            if !locations.is_empty() {
+                let locations = self.dwarf.unit.locations.add(LocationList(locations));
+                let entry = self.dwarf.unit.get_mut(id);
+                entry.set(
+                    gimli::DW_AT_location,
+                    AttributeValue::LocationListRef(locations),
+                );
+            }
+            let entry = self.dwarf.unit.get_mut(id);
+            entry.set(gimli::DW_AT_name, AttributeValue::StringRef(name));
+            entry.set(gimli::DW_AT_type, AttributeValue::UnitRef(ty));
+            if let Some((file, line, _)) = position(var.span.start) {
+                entry.set(
+                    gimli::DW_AT_decl_file,
+                    AttributeValue::FileIndex(Some(file)),
+                );
+                entry.set(gimli::DW_AT_decl_line, AttributeValue::Udata(line));
+            }
+        }
+    }
+
+    /// The entry of a type, which is a base type of the size values of it have. Strings and the
+    /// types of other objects are the addresses of the objects.
+    fn base_type(&mut self, ty: &Ty) -> UnitEntryId {
+        let name = ty.to_string();
+        if let Some(&id) = self.types.get(&name) {
+            return id;
+        }
+        let (encoding, size) = match Kind::of(ty) {
+            Kind::Int(int) if int.is_signed() => (gimli::DW_ATE_signed, int.bits() / 8),
+            Kind::Int(int) => (gimli::DW_ATE_unsigned, int.bits() / 8),
+            Kind::Float => (gimli::DW_ATE_float, 8),
+            Kind::Bool => (gimli::DW_ATE_boolean, 1),
+            Kind::Char => (gimli::DW_ATE_UTF, 4),
+            Kind::String | Kind::Other => (gimli::DW_ATE_address, 8),
+        };
+        let root = self.dwarf.unit.root();
+        let id = self.dwarf.unit.add(root, gimli::DW_TAG_base_type);
+        let string = self.dwarf.strings.add(name.as_str());
+        let entry = self.dwarf.unit.get_mut(id);
+        entry.set(gimli::DW_AT_name, AttributeValue::StringRef(string));
+        entry.set(gimli::DW_AT_encoding, AttributeValue::Encoding(encoding));
+        entry.set(gimli::DW_AT_byte_size, AttributeValue::Data1(size as u8));
+        self.types.insert(name, id);
+        id
+    }
+}
+
+/// A section being written, with the relocations of the addresses and offsets in it.
+#[derive(Clone)]
+struct Relocating {
+    data: EndianVec<RunTimeEndian>,
+    relocs: Vec<Reloc>,
+}
+
+#[derive(Clone)]
+struct Reloc {
+    offset: usize,
+    size: u8,
+    target: Target,
+    addend: i64,
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    /// A function, by its index in [`Debug::symbols`].
+    Symbol(usize),
+    Section(SectionId),
+}
+
+impl Default for Relocating {
+    fn default() -> Self {
+        Relocating {
+            data: EndianVec::new(RunTimeEndian::Little),
+            relocs: Vec::new(),
+        }
+    }
+}
+
+impl Relocating {
+    /// Records a relocation at `offset`, which is written with zeros for the linker to fill in.
+    fn reloc(&mut self, offset: usize, size: u8, target: Target, addend: i64) {
+        self.relocs.push(Reloc {
+            offset,
+            size,
+            target,
+            addend,
+        });
+    }
+}
+
+impl Writer for Relocating {
+    type Endian = RunTimeEndian;
+
+    fn endian(&self) -> RunTimeEndian {
+        self.data.endian()
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> gimli::write::Result<()> {
+        self.data.write(bytes)
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) -> gimli::write::Result<()> {
+        self.data.write_at(offset, bytes)
+    }
+
+    fn write_address(&mut self, address: Address, size: u8) -> gimli::write::Result<()> {
+        match address {
+            Address::Constant(value) => self.write_udata(value, size),
+            Address::Symbol { symbol, addend } => {
+                self.reloc(self.len(), size, Target::Symbol(symbol), addend);
+                self.write_udata(0, size)
+            }
+        }
+    }
+
+    fn write_offset(
+        &mut self,
+        value: usize,
+        section: SectionId,
+        size: u8,
+    ) -> gimli::write::Result<()> {
+        self.reloc(self.len(), size, Target::Section(section), value as i64);
+        self.write_udata(0, size)
+    }
+
+    fn write_offset_at(
+        &mut self,
+        offset: usize,
+        value: usize,
+        section: SectionId,
+        size: u8,
+    ) -> gimli::write::Result<()> {
+        self.reloc(offset, size, Target::Section(section), value as i64);
+        self.write_udata_at(offset, 0, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use gimli::{Dwarf, EndianSlice, LittleEndian};
+    use object::{Object, ObjectSection};
+
+    use super::*;
+    use crate::{
+        codegen::object::build_executable,
+        mir::{built, opt::OptLevel},
+    };
+
+    #[test]
+    fn test_debug_info() {
+        let source = "fn add(a: int, b: int) int {
+    let sum = a + b;
+    sum * 2
+}
+fn main() { add(1, 2); }";
+        let mut source_map = SourceMap::new();
+        source_map.add_file("main.rf", source);
+        // Linking applies the relocations, which reading the object as it is wouldn't
+        let exe = std::env::temp_dir().join(format!("ruffle-debug-test-{}", std::process::id()));
+        build_executable(&built(source), &exe, OptLevel::O2, Some(&source_map), &[]).unwrap();
+        let data = fs::read(&exe).unwrap();
+        fs::remove_file(&exe).unwrap();
+        let file = object::File::parse(&*data).unwrap();
+        let dwarf = Dwarf::load(|id| {
+            let section = file.section_by_name(id.name());
+            let data = section.map_or(Ok(&[][..]), |section| section.data());
+            data.map(|data| EndianSlice::new(data, LittleEndian))
+        })
+        .unwrap();
+        let header = dwarf.units().next().unwrap().unwrap();
+        let unit = dwarf.unit(header).unwrap();
+
+        let mut entries = unit.entries();
+        let mut names = Vec::new();
+        while let Some((_, entry)) = entries.next_dfs().unwrap() {
+            if let Some(name) = entry.attr_value(gimli::DW_AT_name).unwrap() {
+                let name = dwarf.attr_string(&unit, name).unwrap();
+                names.push(format!("{} {}", entry.tag(), name.to_string_lossy()));
+            }
+        }
+        assert_eq!(
+            names,
+            [
+                "DW_TAG_compile_unit main.rf",
+                "DW_TAG_base_type i32",
+                "DW_TAG_subprogram add",
+                "DW_TAG_formal_parameter a",
+                "DW_TAG_formal_parameter b",
+                "DW_TAG_variable sum",
+                "DW_TAG_subprogram main",
+                "DW_TAG_subprogram ruffle_main",
+            ]
+        );
+
+        let mut rows = unit.line_program.clone().unwrap().rows();
+        let mut lines = Vec::new();
+        while let Some((_, row)) = rows.next_row().unwrap() {
+            if !row.end_sequence() {
+                lines.push(row.line().unwrap().get());
+            }
+        }
+        assert_eq!(lines, [1, 2, 3, 3, 3, 5, 5, 5, 5, 5]);
+    }
+}
@@ -0,0 +1,1108 @@
+//! Lowers the MIR of a program to LLVM IR, and builds an executable from it with LLVM's `opt`
+//! and `llc` and the system's C compiler, which also compiles the runtime library, as `build
+//! --backend llvm` does.
+//!
+//! The IR is written as text rather than built through LLVM's C API, so that the compiler doesn't
+//! link against LLVM; the tools only have to be on the `PATH` when an executable is built. Every
+//! value is an `i64`, as in the runtime library (`runtime/runtime.c`), which describes how each
+//! type is represented. Integers are kept sign or zero extended from their type, so they're only
+//! truncated for the arithmetic that depends on their width. Each function takes and returns
+//! `i64`s, and function and closure values point to a thunk that takes the value itself before
+//! the arguments, and passes the captured values to the function.
+//!
+//! The program's functions and thunks use the `tailcc` calling convention, under which LLVM turns
+//! every call marked `tail` into a jump, at any optimization level, so a call whose value is
+//! returned doesn't grow the stack even when it's to another function.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    ast::{BinaryOp, UnaryOp},
+    codegen::{
+        build::{in_temp_dir, link, run, BuildError},
+        bytecode::Kind,
+        runtime::{
+            slot_kind, text_kind, ARRAY, DIVISION_BY_ZERO, ENTRY, FUNCTION, GENERATORS, HEADER,
+            MAIN, MAP, OVERFLOW, RANGE, RUNTIME, SHIFT_OUT_OF_RANGE, TUPLE, VARIANT,
+        },
+    },
+    mir::{
+        opt::OptLevel, BlockCall, BlockId, Callee, Constant, FuncId, Function, InstKind, Intrinsic,
+        Program, Terminator, Value,
+    },
+    resolve::DefId,
+    typeck::{IntTy, Ty},
+};
+
+const RUNTIME_DECLS: &str = "declare i64 @rf_alloc(i64, i64, i64, i8*)
+declare i64 @rf_string(i8*, i64)
+declare i64 @rf_concat(i64, i64)
+declare i64 @rf_compare_strings(i64, i64)
+declare i64 @rf_equal(i64, i64, i64)
+declare i64 @rf_set_field(i64, i64, i64)
+declare i64 @rf_len(i64)
+declare i64 @rf_index(i64, i64)
+declare i64 @rf_set_index(i64, i64, i64)
+declare i64 @rf_chars(i64)
+declare void @rf_panic(i64) noreturn
+declare void @rf_fail(i8*) noreturn
+declare void @rf_print(i64)
+declare void @rf_println(i64)
+declare i64 @rf_input()
+declare i64 @rf_string_len(i64)
+declare i64 @rf_char_at(i64, i64)
+declare i64 @rf_slice(i64, i64)
+declare i64 @rf_contains(i64, i64)
+declare i64 @rf_split(i64, i64)
+declare i64 @rf_to_upper(i64)
+declare i64 @rf_to_lower(i64)
+declare i64 @rf_push(i64, i64)
+declare i64 @rf_pop(i64)
+declare i64 @rf_map_find(i64, i64)
+declare i64 @rf_map_insert(i64, i64, i64)
+declare i64 @rf_map_remove(i64, i64)
+declare i64 @rf_map_key(i64, i64)
+declare i64 @rf_map_value(i64, i64)
+declare i64 @rf_sqrt(i64)
+declare i64 @rf_floor(i64)
+declare i64 @rf_ceil(i64)
+declare i64 @rf_pow(i64, i64)
+declare i64 @rf_read_file(i64)
+declare i64 @rf_write_file(i64, i64)
+declare i64 @rf_file_exists(i64)
+declare i64 @rf_fs_error()
+declare i64 @rf_to_string(i64, i64)
+";
+
+pub fn emit_ir(program: &Program) -> String {
+    let mut module = ModuleEmitter {
+        program,
+        strings: Vec::new(),
+        string_ids: HashMap::new(),
+        intrinsics: BTreeSet::new(),
+        thunks: BTreeMap::new(),
+        variants: HashMap::new(),
+    };
+    for adt in &program.adts {
+        for (discriminant, variant) in adt.variants.iter().enumerate() {
+            module.variants.insert(variant.def, discriminant);
+        }
+    }
+
+    let mut functions = String::new();
+    for (i, func) in program.fns.iter().enumerate() {
+        functions.push('\n');
+        module.function(FuncId(i), func, &mut functions);
+    }
+    for (&func, &captures) in &module.thunks.clone() {
+        functions.push('\n');
+        module.thunk(func, captures, &mut functions);
+    }
+    functions.push('\n');
+    module.main(&mut functions);
+
+    let mut out = String::from("; ModuleID = 'ruffle'\nsource_filename = \"ruffle\"\n\n");
+    for i in 0..program.globals.len() {
+        out.push_str(&format!("@g{} = internal global i64 0\n", i));
+    }
+    for (i, bytes) in module.strings.iter().enumerate() {
+        out.push_str(&format!(
+            "@str.{} = private unnamed_addr constant [{} x i8] c\"{}\"\n",
+            i,
+            bytes.len(),
+            escape(bytes)
+        ));
+    }
+    out.push('\n');
+    out.push_str(RUNTIME_DECLS);
+    for (name, params, ret) in program.externs() {
+        let params: Vec<_> = (params.iter())
+            .filter_map(extern_type)
+            .map(|(ty, attr)| format!("{}{}", ty, attr))
+            .collect();
+        let ret = extern_type(&ret).map_or("void".to_string(), |(ty, attr)| {
+            format!("{} {}", attr, ty).trim_start().to_string()
+        });
+        out.push_str(&format!(
+            "declare {} @{}({})\n",
+            ret,
+            name,
+            params.join(", ")
+        ));
+    }
+    for intrinsic in &module.intrinsics {
+        out.push_str(intrinsic);
+        out.push('\n');
+    }
+    out.push_str(&functions);
+    out
+}
+
+/// The C type a value of a type is passed to or returned from a host function as, with the
+/// attribute that extends it to a register, or nothing for `()`.
+fn extern_type(ty: &Ty) -> Option<(&'static str, &'static str)> {
+    let ext = |int: &IntTy| {
+        if int.is_signed() {
+            " signext"
+        } else {
+            " zeroext"
+        }
+    };
+    match ty {
+        Ty::Int(int @ (IntTy::I8 | IntTy::U8)) => Some(("i8", ext(int))),
+        Ty::Int(int @ (IntTy::I16 | IntTy::U16)) => Some(("i16", ext(int))),
+        Ty::Int(int @ (IntTy::I32 | IntTy::U32)) => Some(("i32", ext(int))),
+        Ty::Int(_) => Some(("i64", "")),
+        Ty::Float => Some(("double", "")),
+        Ty::Bool => Some(("i1", " zeroext")),
+        Ty::Char => Some(("i32", " zeroext")),
+        _ => None,
+    }
+}
+
+/// Escapes bytes for a string constant in LLVM IR.
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b' '..=b'~' if byte != b'"' && byte != b'\\' => (byte as char).to_string(),
+            _ => format!("\\{:02X}", byte),
+        })
+        .collect()
+}
+
+struct ModuleEmitter<'a> {
+    program: &'a Program,
+    strings: Vec<Vec<u8>>,
+    string_ids: HashMap<Vec<u8>, usize>,
+    /// The declarations of the LLVM intrinsics the functions use.
+    intrinsics: BTreeSet<String>,
+    /// The functions used as values, with how many values they capture.
+    thunks: BTreeMap<FuncId, usize>,
+    /// The discriminant of each struct and variant.
+    variants: HashMap<DefId, usize>,
+}
+
+impl ModuleEmitter<'_> {
+    /// Returns a pointer to a constant with the bytes.
+    fn bytes(&mut self, bytes: &[u8]) -> String {
+        let id = match self.string_ids.get(bytes) {
+            Some(&id) => id,
+            None => {
+                self.strings.push(bytes.to_vec());
+                self.string_ids
+                    .insert(bytes.to_vec(), self.strings.len() - 1);
+                self.strings.len() - 1
+            }
+        };
+        let len = self.strings[id].len();
+        format!(
+            "getelementptr inbounds ([{} x i8], [{} x i8]* @str.{}, i64 0, i64 0)",
+            len, len, id
+        )
+    }
+
+    /// Returns a pointer to a constant C string, which ends in a 0.
+    fn c_string(&mut self, text: &str) -> String {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.push(0);
+        self.bytes(&bytes)
+    }
+
+    fn intrinsic(&mut self, decl: String) {
+        self.intrinsics.insert(decl);
+    }
+
+    fn function(&mut self, id: FuncId, func: &Function, out: &mut String) {
+        let params: Vec<_> = func
+            .params()
+            .iter()
+            .map(|param| format!("i64 %v{}", param.0))
+            .collect();
+        out.push_str(&format!("; fn{} {}\n", id.0, func.name));
+        out.push_str(&format!(
+            "define internal tailcc i64 @fn{}({}) {{\n",
+            id.0,
+            params.join(", ")
+        ));
+        if func.blocks.is_empty() {
+            // Only declared, so it's never called
+            out.push_str("  unreachable\n}\n");
+            return;
+        }
+
+        let mut emitter = FnEmitter {
+            module: self,
+            func,
+            operands: (0..func.values.len()).map(|v| format!("%v{}", v)).collect(),
+            bodies: vec![Vec::new(); func.blocks.len()],
+            incoming: vec![Vec::new(); func.blocks.len()],
+            current: BlockId::ENTRY,
+            label: String::new(),
+            temps: 0,
+            labels: 0,
+            fails: BTreeMap::new(),
+        };
+        for block in func.block_ids() {
+            emitter.block(block);
+        }
+
+        for block in func.block_ids() {
+            out.push_str(&format!("bb{}:\n", block.0));
+            // The parameters of the entry are the function's
+            let params = match block {
+                BlockId::ENTRY => &[],
+                _ => func.block(block).params.as_slice(),
+            };
+            for (i, param) in params.iter().enumerate() {
+                let incoming: Vec<_> = emitter.incoming[block.0]
+                    .iter()
+                    .map(|(label, args)| format!("[ {}, %{} ]", args[i], label))
+                    .collect();
+                out.push_str(&format!(
+                    "  %v{} = phi i64 {}\n",
+                    param.0,
+                    incoming.join(", ")
+                ));
+            }
+            for line in &emitter.bodies[block.0] {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        for (message, label) in std::mem::take(&mut emitter.fails) {
+            let message = emitter.module.c_string(message);
+            out.push_str(&format!("{}:\n", label));
+            out.push_str(&format!("  call void @rf_fail(i8* {})\n", message));
+            out.push_str("  unreachable\n");
+        }
+        out.push_str("}\n");
+    }
+
+    /// Defines the thunk of a function that's used as a value, which takes the value and the
+    /// function's own arguments.
+    fn thunk(&mut self, id: FuncId, captures: usize, out: &mut String) {
+        let func = self.program.func(id);
+        let params = func.params().len() - captures;
+        let mut decl = vec!["i64 %env".to_string()];
+        decl.extend((0..params).map(|i| format!("i64 %a{}", i)));
+        out.push_str(&format!(
+            "define internal tailcc i64 @fn{}.thunk({}) {{\n",
+            id.0,
+            decl.join(", ")
+        ));
+        let mut args = Vec::new();
+        for i in 0..captures {
+            let offset = HEADER + 8 * (i + 1);
+            out.push_str(&format!("  %p{} = add i64 %env, {}\n", i, offset));
+            out.push_str(&format!("  %q{} = inttoptr i64 %p{} to i64*\n", i, i));
+            out.push_str(&format!("  %c{} = load i64, i64* %q{}\n", i, i));
+            args.push(format!("i64 %c{}", i));
+        }
+        args.extend((0..params).map(|i| format!("i64 %a{}", i)));
+        out.push_str(&format!(
+            "  %r = tail call tailcc i64 @fn{}({})\n  ret i64 %r\n}}\n",
+            id.0,
+            args.join(", ")
+        ));
+    }
+
+    /// Defines the entry of the program, which initializes the globals in order, then calls
+    /// `main`.
+    fn main(&mut self, out: &mut String) {
+        out.push_str(&format!("define i64 @{}() {{\n", ENTRY));
+        for (i, global) in self.program.globals.iter().enumerate() {
+            out.push_str(&format!(
+                "  %g{} = call tailcc i64 @fn{}()\n",
+                i, global.init.0
+            ));
+            out.push_str(&format!("  store i64 %g{}, i64* @g{}\n", i, i));
+        }
+        let main = self
+            .program
+            .fns
+            .iter()
+            .position(|func| func.def.is_some() && func.name == "main");
+        if let Some(main) = main {
+            out.push_str(&format!("  call tailcc i64 @fn{}()\n", main));
+        }
+        out.push_str("  ret i64 0\n}\n");
+    }
+}
+
+struct FnEmitter<'a, 'm> {
+    module: &'a mut ModuleEmitter<'m>,
+    func: &'a Function,
+    /// The operand that holds each value, which is a temporary or a constant for the values of
+    /// instructions.
+    operands: Vec<String>,
+    /// The instructions of each block, after its phis.
+    bodies: Vec<Vec<String>>,
+    /// The label of each jump to a block, with the values it passes.
+    incoming: Vec<Vec<(String, Vec<String>)>>,
+    current: BlockId,
+    /// The label of the LLVM block being emitted, which a block of the MIR is split into when it
+    /// checks for failures.
+    label: String,
+    temps: usize,
+    labels: usize,
+    /// The block that fails with each message.
+    fails: BTreeMap<&'static str, String>,
+}
+
+impl FnEmitter<'_, '_> {
+    fn line(&mut self, line: String) {
+        self.bodies[self.current.0].push(format!("  {}", line));
+    }
+
+    /// Emits an instruction into a new temporary, returning it.
+    fn temp(&mut self, inst: String) -> String {
+        let temp = format!("%t{}", self.temps);
+        self.temps += 1;
+        self.line(format!("{} = {}", temp, inst));
+        temp
+    }
+
+    fn start(&mut self, label: String) {
+        self.bodies[self.current.0].push(format!("{}:", label));
+        self.label = label;
+    }
+
+    fn new_label(&mut self, prefix: &str) -> String {
+        self.labels += 1;
+        format!("{}.{}", prefix, self.labels)
+    }
+
+    /// Fails with the message when `cond`, an `i1`, is true.
+    fn check(&mut self, cond: String, message: &'static str) {
+        let next = self.fails.len();
+        let fail = self
+            .fails
+            .entry(message)
+            .or_insert_with(|| format!("fail.{}", next))
+            .clone();
+        let ok = self.new_label("ok");
+        self.line(format!("br i1 {}, label %{}, label %{}", cond, fail, ok));
+        self.start(ok);
+    }
+
+    fn value(&self, value: Value) -> String {
+        self.operands[value.0].clone()
+    }
+
+    fn call(&mut self, name: &str, args: &[String]) -> String {
+        let args: Vec<_> = args.iter().map(|arg| format!("i64 {}", arg)).collect();
+        self.temp(format!("call i64 @{}({})", name, args.join(", ")))
+    }
+
+    /// Calls one of the program's functions, marking the call `tail` when the block returns its
+    /// value, so that the callee takes over the caller's frame.
+    fn call_function(&mut self, callee: Callee, args: &[Value], tail: bool) -> String {
+        let mut args: Vec<_> = args.iter().map(|&arg| self.value(arg)).collect();
+        let call = if tail {
+            "tail call tailcc"
+        } else {
+            "call tailcc"
+        };
+        let pointer = match callee {
+            Callee::Direct(func) => format!("@fn{}", func.0),
+            Callee::Indirect(callee) => {
+                let callee = self.value(callee);
+                let code = self.load_slot(callee.clone(), 0);
+                let params = vec!["i64"; args.len() + 1].join(", ");
+                args.insert(0, callee);
+                self.temp(format!("inttoptr i64 {} to i64 ({})*", code, params))
+            }
+        };
+        let args: Vec<_> = args.iter().map(|arg| format!("i64 {}", arg)).collect();
+        self.temp(format!("{} i64 {}({})", call, pointer, args.join(", ")))
+    }
+
+    /// Truncates an integer to the width of its type.
+    fn narrow(&mut self, value: String, ty: IntTy) -> String {
+        if ty.bits() == 64 {
+            return value;
+        }
+        self.temp(format!("trunc i64 {} to i{}", value, ty.bits()))
+    }
+
+    /// Extends an integer of the width of its type back to an `i64`.
+    fn widen(&mut self, value: String, ty: IntTy) -> String {
+        if ty.bits() == 64 {
+            return value;
+        }
+        let ext = if ty.is_signed() { "sext" } else { "zext" };
+        self.temp(format!("{} i{} {} to i64", ext, ty.bits(), value))
+    }
+
+    fn bool_to_int(&mut self, value: String) -> String {
+        self.temp(format!("zext i1 {} to i64", value))
+    }
+
+    fn bits_to_float(&mut self, value: String) -> String {
+        self.temp(format!("bitcast i64 {} to double", value))
+    }
+
+    fn float_to_bits(&mut self, value: String) -> String {
+        self.temp(format!("bitcast double {} to i64", value))
+    }
+
+    /// Returns a pointer to a slot of an object.
+    fn slot(&mut self, object: String, index: usize) -> String {
+        let address = self.temp(format!("add i64 {}, {}", object, HEADER + 8 * index));
+        self.temp(format!("inttoptr i64 {} to i64*", address))
+    }
+
+    fn load_slot(&mut self, object: String, index: usize) -> String {
+        let slot = self.slot(object, index);
+        self.temp(format!("load i64, i64* {}", slot))
+    }
+
+    /// Allocates an object with values in its slots.
+    fn object(&mut self, kind: u32, tag: usize, shape: &[u8], slots: &[String]) -> String {
+        let mut shape = shape.to_vec();
+        shape.push(0);
+        let shape = self.module.bytes(&shape);
+        let object = self.temp(format!(
+            "call i64 @rf_alloc(i64 {}, i64 {}, i64 {}, i8* {})",
+            kind,
+            tag,
+            slots.len(),
+            shape
+        ));
+        for (i, value) in slots.iter().enumerate() {
+            let slot = self.slot(object.clone(), i);
+            self.line(format!("store i64 {}, i64* {}", value, slot));
+        }
+        object
+    }
+
+    fn shape(&self, values: &[Value]) -> Vec<u8> {
+        values
+            .iter()
+            .map(|&value| slot_kind(self.func.value_ty(value)))
+            .collect()
+    }
+
+    fn block(&mut self, id: BlockId) {
+        self.current = id;
+        self.label = format!("bb{}", id.0);
+        let block = self.func.block(id);
+        let tail_call = block.tail_call();
+        for (i, inst) in block.insts.iter().enumerate() {
+            let operand = match tail_call {
+                Some((callee, args)) if i == block.insts.len() - 1 => {
+                    self.call_function(callee, args, true)
+                }
+                _ => self.inst(inst.value, &inst.kind),
+            };
+            self.operands[inst.value.0] = operand;
+        }
+        match &block.terminator {
+            Terminator::Jump(target) => self.jump(target),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let cond = self.value(*cond);
+                let cond = self.temp(format!("trunc i64 {} to i1", cond));
+                // A jump that passes values goes through a block of its own, so that the phis
+                // can tell it apart from the other
+                let targets = [then_block, else_block].map(|target| {
+                    if target.args.is_empty() {
+                        (format!("bb{}", target.block.0), None)
+                    } else {
+                        (self.new_label("edge"), Some(target))
+                    }
+                });
+                self.line(format!(
+                    "br i1 {}, label %{}, label %{}",
+                    cond, targets[0].0, targets[1].0
+                ));
+                for (label, target) in targets {
+                    if let Some(target) = target {
+                        self.start(label);
+                        self.jump(target);
+                    }
+                }
+            }
+            Terminator::Return(value) => {
+                let value = self.value(*value);
+                self.line(format!("ret i64 {}", value));
+            }
+            Terminator::Unreachable => self.line("unreachable".to_string()),
+        }
+    }
+
+    fn jump(&mut self, target: &BlockCall) {
+        let args = target.args.iter().map(|&arg| self.value(arg)).collect();
+        self.incoming[target.block.0].push((self.label.clone(), args));
+        self.line(format!("br label %bb{}", target.block.0));
+    }
+
+    /// Emits an instruction, returning the operand that holds its value.
+    fn inst(&mut self, value: Value, kind: &InstKind) -> String {
+        let kind_of = |value: Value| Kind::of(self.func.value_ty(value));
+        match kind {
+            InstKind::Const(constant) => match constant {
+                Constant::Int(value) => (*value as i64).to_string(),
+                Constant::Float(value) => (value.to_bits() as i64).to_string(),
+                Constant::Bool(value) => (*value as i64).to_string(),
+                Constant::Char(value) => (*value as i64).to_string(),
+                Constant::String(value) => {
+                    let bytes = self.module.bytes(value.as_bytes());
+                    self.temp(format!(
+                        "call i64 @rf_string(i8* {}, i64 {})",
+                        bytes,
+                        value.len()
+                    ))
+                }
+                Constant::Unit => "0".to_string(),
+            },
+            InstKind::Undef => "0".to_string(),
+            InstKind::Unary { op, value } => {
+                let operand = self.value(*value);
+                self.unary(*op, kind_of(*value), operand)
+            }
+            InstKind::Binary { op, lhs, rhs } => {
+                let (a, b) = (self.value(*lhs), self.value(*rhs));
+                self.binary(*op, kind_of(*lhs), a, b)
+            }
+            InstKind::Cast(inner) => {
+                let operand = self.value(*inner);
+                self.cast(kind_of(*inner), kind_of(value), operand)
+            }
+            InstKind::Tuple(elems) if elems.is_empty() => "0".to_string(),
+            InstKind::Tuple(elems) => {
+                let values: Vec<_> = elems.iter().map(|&elem| self.value(elem)).collect();
+                let shape = self.shape(elems);
+                self.object(TUPLE, 0, &shape, &values)
+            }
+            // An array's shape is a single letter, for all of its elements
+            InstKind::Array(elems) => {
+                let values: Vec<_> = elems.iter().map(|&elem| self.value(elem)).collect();
+                let shape = match self.func.value_ty(value) {
+                    Ty::Array(elem) => slot_kind(elem),
+                    _ => b'i',
+                };
+                self.object(ARRAY, 0, &[shape], &values)
+            }
+            InstKind::Construct { def, fields } => {
+                let values: Vec<_> = fields.iter().map(|&field| self.value(field)).collect();
+                let shape = self.shape(fields);
+                let discriminant = self.module.variants[def];
+                self.object(VARIANT, discriminant, &shape, &values)
+            }
+            InstKind::Field { base, index } | InstKind::VariantField { base, index, .. } => {
+                let base = self.value(*base);
+                self.load_slot(base, *index)
+            }
+            InstKind::SetField { base, index, value } => {
+                let (base, value) = (self.value(*base), self.value(*value));
+                self.call("rf_set_field", &[base, index.to_string(), value])
+            }
+            InstKind::Discriminant(base) => {
+                let base = self.value(*base);
+                let address = self.temp(format!("add i64 {}, 4", base));
+                let pointer = self.temp(format!("inttoptr i64 {} to i32*", address));
+                let tag = self.temp(format!("load i32, i32* {}", pointer));
+                self.temp(format!("zext i32 {} to i64", tag))
+            }
+            InstKind::Index { base, index } => {
+                let (base, index) = (self.value(*base), self.value(*index));
+                self.call("rf_index", &[base, index])
+            }
+            InstKind::SetIndex { base, index, value } => {
+                let args = [*base, *index, *value].map(|value| self.value(value));
+                self.call("rf_set_index", &args)
+            }
+            InstKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let flags = start.is_some() as usize
+                    | (end.is_some() as usize) << 1
+                    | (*inclusive as usize) << 2;
+                let bound =
+                    |bound: &Option<Value>| bound.map_or("0".to_string(), |b| self.value(b));
+                let values = [bound(start), bound(end)];
+                let elem = match self.func.value_ty(value) {
+                    Ty::Range(elem) => slot_kind(elem),
+                    _ => b'i',
+                };
+                self.object(RANGE, flags, &[elem, elem], &values)
+            }
+            InstKind::Global(global) => self.temp(format!("load i64, i64* @g{}", global.0)),
+            InstKind::SetGlobal { global, value } => {
+                let value = self.value(*value);
+                self.line(format!("store i64 {}, i64* @g{}", value, global.0));
+                "0".to_string()
+            }
+            InstKind::FnRef(func) => self.function_value(*func, &[]),
+            InstKind::Closure { func, captures } => self.function_value(*func, captures),
+            InstKind::Call { callee, args } => self.call_function(*callee, args, false),
+            InstKind::Extern { name, args } => {
+                let mut operands = Vec::new();
+                for &arg in args {
+                    let operand = self.value(arg);
+                    let ty = self.func.value_ty(arg);
+                    let operand = match ty {
+                        Ty::Int(int) => self.narrow(operand, *int),
+                        Ty::Float => self.bits_to_float(operand),
+                        Ty::Bool => self.temp(format!("trunc i64 {} to i1", operand)),
+                        _ => self.temp(format!("trunc i64 {} to i32", operand)),
+                    };
+                    if let Some((ty, attr)) = extern_type(ty) {
+                        operands.push(format!("{}{} {}", ty, attr, operand));
+                    }
+                }
+                let ty = self.func.value_ty(value);
+                let Some((ret, attr)) = extern_type(ty) else {
+                    self.line(format!("call void @{}({})", name, operands.join(", ")));
+                    return "0".to_string();
+                };
+                let result = self.temp(format!(
+                    "call {} @{}({})",
+                    format!("{} {}", attr, ret).trim_start(),
+                    name,
+                    operands.join(", ")
+                ));
+                match ty {
+                    Ty::Int(int) => self.widen(result, *int),
+                    Ty::Float => self.float_to_bits(result),
+                    _ => self.temp(format!("zext {} {} to i64", ret, result)),
+                }
+            }
+            InstKind::Generator { .. }
+            | InstKind::Intrinsic {
+                intrinsic: Intrinsic::Yield | Intrinsic::Next,
+                ..
+            } => {
+                self.check("true".to_string(), GENERATORS);
+                "0".to_string()
+            }
+            InstKind::Intrinsic { intrinsic, args } => {
+                let args: Vec<_> = args.iter().map(|&arg| self.value(arg)).collect();
+                match intrinsic {
+                    Intrinsic::Panic => {
+                        self.line(format!("call void @rf_panic(i64 {})", args[0]));
+                        "0".to_string()
+                    }
+                    Intrinsic::Print | Intrinsic::Println => {
+                        let name = format!("rf_{}", intrinsic.name());
+                        self.line(format!("call void @{}(i64 {})", name, args[0]));
+                        "0".to_string()
+                    }
+                    Intrinsic::NewMap => {
+                        let shape = match self.func.value_ty(value) {
+                            Ty::Map(key, value) => [slot_kind(key), slot_kind(value)],
+                            _ => [b'i', b'i'],
+                        };
+                        self.object(MAP, 0, &shape, &[])
+                    }
+                    // The others return what the library function of the same name does
+                    _ => self.call(&format!("rf_{}", intrinsic.name()), &args),
+                }
+            }
+        }
+    }
+
+    /// Makes a function value, whose first slot points to the function's thunk and whose other
+    /// slots hold the values it captures.
+    fn function_value(&mut self, func: FuncId, captures: &[Value]) -> String {
+        self.module.thunks.insert(func, captures.len());
+        let params =
+            vec!["i64"; self.module.program.func(func).params().len() - captures.len() + 1];
+        let code = self.temp(format!(
+            "ptrtoint i64 ({})* @fn{}.thunk to i64",
+            params.join(", "),
+            func.0
+        ));
+        let mut values = vec![code];
+        values.extend(captures.iter().map(|&capture| self.value(capture)));
+        let mut shape = vec![b'i'];
+        shape.extend(self.shape(captures));
+        self.object(FUNCTION, 0, &shape, &values)
+    }
+
+    fn unary(&mut self, op: UnaryOp, kind: Kind, operand: String) -> String {
+        match (op, kind) {
+            (UnaryOp::Neg, Kind::Int(_)) => {
+                self.binary(BinaryOp::Sub, kind, "0".to_string(), operand)
+            }
+            (UnaryOp::Neg, _) => {
+                let float = self.bits_to_float(operand);
+                let result = self.temp(format!("fneg double {}", float));
+                self.float_to_bits(result)
+            }
+            (UnaryOp::Not, Kind::Int(ty)) => {
+                let narrow = self.narrow(operand, ty);
+                let result = self.temp(format!("xor i{} {}, -1", ty.bits(), narrow));
+                self.widen(result, ty)
+            }
+            (UnaryOp::Not, _) => self.temp(format!("xor i64 {}, 1", operand)),
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, kind: Kind, lhs: String, rhs: String) -> String {
+        match kind {
+            Kind::Int(ty) => self.int_binary(op, ty, lhs, rhs),
+            Kind::Float => {
+                let (a, b) = (self.bits_to_float(lhs), self.bits_to_float(rhs));
+                let inst = match op {
+                    BinaryOp::Add => "fadd",
+                    BinaryOp::Sub => "fsub",
+                    BinaryOp::Mul => "fmul",
+                    BinaryOp::Div => "fdiv",
+                    BinaryOp::Rem => "frem",
+                    _ => {
+                        let cond = match op {
+                            BinaryOp::Eq | BinaryOp::TripleEq => "oeq",
+                            BinaryOp::Ne | BinaryOp::TripleNe => "une",
+                            BinaryOp::Lt => "olt",
+                            BinaryOp::Le => "ole",
+                            BinaryOp::Gt => "ogt",
+                            _ => "oge",
+                        };
+                        let result = self.temp(format!("fcmp {} double {}, {}", cond, a, b));
+                        return self.bool_to_int(result);
+                    }
+                };
+                let result = self.temp(format!("{} double {}, {}", inst, a, b));
+                self.float_to_bits(result)
+            }
+            Kind::String if op == BinaryOp::Add => self.call("rf_concat", &[lhs, rhs]),
+            Kind::String => {
+                let order = self.call("rf_compare_strings", &[lhs, rhs]);
+                self.compare(op, true, order, "0".to_string())
+            }
+            Kind::Bool | Kind::Char => match op {
+                BinaryOp::And | BinaryOp::BitAnd => self.temp(format!("and i64 {}, {}", lhs, rhs)),
+                BinaryOp::Or | BinaryOp::BitOr => self.temp(format!("or i64 {}, {}", lhs, rhs)),
+                BinaryOp::BitXor => self.temp(format!("xor i64 {}, {}", lhs, rhs)),
+                _ => self.compare(op, false, lhs, rhs),
+            },
+            Kind::Other => {
+                let equal = self.call("rf_equal", &[lhs, rhs, (b'o' as i64).to_string()]);
+                match op {
+                    BinaryOp::Ne | BinaryOp::TripleNe => self.temp(format!("xor i64 {}, 1", equal)),
+                    _ => equal,
+                }
+            }
+        }
+    }
+
+    /// Compares two `i64`s, which are signed when `signed`.
+    fn compare(&mut self, op: BinaryOp, signed: bool, lhs: String, rhs: String) -> String {
+        let sign = if signed { "s" } else { "u" };
+        let cond = match op {
+            BinaryOp::Eq | BinaryOp::TripleEq => "eq".to_string(),
+            BinaryOp::Ne | BinaryOp::TripleNe => "ne".to_string(),
+            BinaryOp::Lt => format!("{}lt", sign),
+            BinaryOp::Le => format!("{}le", sign),
+            BinaryOp::Gt => format!("{}gt", sign),
+            _ => format!("{}ge", sign),
+        };
+        let result = self.temp(format!("icmp {} i64 {}, {}", cond, lhs, rhs));
+        self.bool_to_int(result)
+    }
+
+    fn int_binary(&mut self, op: BinaryOp, ty: IntTy, lhs: String, rhs: String) -> String {
+        let bits = ty.bits();
+        let sign = if ty.is_signed() { "s" } else { "u" };
+        match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => {
+                let name = match op {
+                    BinaryOp::Add => "add",
+                    BinaryOp::Sub => "sub",
+                    _ => "mul",
+                };
+                let intrinsic = format!("llvm.{}{}.with.overflow.i{}", sign, name, bits);
+                self.module.intrinsic(format!(
+                    "declare {{i{b}, i1}} @{}(i{b}, i{b})",
+                    intrinsic,
+                    b = bits
+                ));
+                let (a, b) = (self.narrow(lhs, ty), self.narrow(rhs, ty));
+                let pair = self.temp(format!(
+                    "call {{i{b}, i1}} @{}(i{b} {}, i{b} {})",
+                    intrinsic,
+                    a,
+                    b,
+                    b = bits
+                ));
+                let overflow = self.temp(format!("extractvalue {{i{}, i1}} {}, 1", bits, pair));
+                self.check(overflow, OVERFLOW);
+                let result = self.temp(format!("extractvalue {{i{}, i1}} {}, 0", bits, pair));
+                self.widen(result, ty)
+            }
+            BinaryOp::Div | BinaryOp::Rem => {
+                let zero = self.temp(format!("icmp eq i64 {}, 0", rhs));
+                self.check(zero, DIVISION_BY_ZERO);
+                if ty.is_signed() {
+                    // The quotient of the smallest value and -1 is one more than the largest
+                    let min = self.temp(format!("icmp eq i64 {}, {}", lhs, ty.min()));
+                    let minus_one = self.temp(format!("icmp eq i64 {}, -1", rhs));
+                    let overflow = self.temp(format!("and i1 {}, {}", min, minus_one));
+                    self.check(overflow, OVERFLOW);
+                }
+                let inst = if op == BinaryOp::Div { "div" } else { "rem" };
+                let (a, b) = (self.narrow(lhs, ty), self.narrow(rhs, ty));
+                let result = self.temp(format!("{}{} i{} {}, {}", sign, inst, bits, a, b));
+                self.widen(result, ty)
+            }
+            BinaryOp::Shl | BinaryOp::Shr => {
+                // A negative amount is a large unsigned one
+                let out_of_range = self.temp(format!("icmp uge i64 {}, {}", rhs, bits));
+                self.check(out_of_range, SHIFT_OUT_OF_RANGE);
+                let (a, b) = (self.narrow(lhs, ty), self.narrow(rhs, ty));
+                let inst = match op {
+                    BinaryOp::Shl => "shl",
+                    _ if ty.is_signed() => "ashr",
+                    _ => "lshr",
+                };
+                let result = self.temp(format!("{} i{} {}, {}", inst, bits, a, b));
+                self.widen(result, ty)
+            }
+            // Integers extended the same way combine bit by bit into one that's extended that way
+            BinaryOp::BitAnd => self.temp(format!("and i64 {}, {}", lhs, rhs)),
+            BinaryOp::BitOr => self.temp(format!("or i64 {}, {}", lhs, rhs)),
+            BinaryOp::BitXor => self.temp(format!("xor i64 {}, {}", lhs, rhs)),
+            _ => self.compare(op, ty.is_signed(), lhs, rhs),
+        }
+    }
+
+    fn cast(&mut self, from: Kind, to: Kind, value: String) -> String {
+        match (from, to) {
+            (Kind::Int(from), Kind::Float) => {
+                let narrow = self.narrow(value, from);
+                let inst = if from.is_signed() { "sitofp" } else { "uitofp" };
+                let float = self.temp(format!("{} i{} {} to double", inst, from.bits(), narrow));
+                self.float_to_bits(float)
+            }
+            (Kind::Float, Kind::Int(to)) => {
+                // Saturating, like `IntTy::from_float`
+                let sign = if to.is_signed() { "si" } else { "ui" };
+                let intrinsic = format!("llvm.fpto{}.sat.i{}.f64", sign, to.bits());
+                self.module
+                    .intrinsic(format!("declare i{} @{}(double)", to.bits(), intrinsic));
+                let float = self.bits_to_float(value);
+                let result = self.temp(format!(
+                    "call i{} @{}(double {})",
+                    to.bits(),
+                    intrinsic,
+                    float
+                ));
+                self.widen(result, to)
+            }
+            (Kind::Int(_) | Kind::Char | Kind::Bool, Kind::Int(to)) => {
+                let narrow = self.narrow(value, to);
+                self.widen(narrow, to)
+            }
+            (Kind::Int(_), Kind::Char) => {
+                let narrow = self.narrow(value, IntTy::U32);
+                self.widen(narrow, IntTy::U32)
+            }
+            (Kind::Int(_) | Kind::Char | Kind::Bool, Kind::String) => {
+                self.call("rf_to_string", &[value, text_kind(from).to_string()])
+            }
+            _ => value,
+        }
+    }
+}
+
+/// Builds an executable from a program, optimizing it with LLVM and linking it with `libs`. The C
+/// compiler is `$CC`, or `cc` by default.
+pub fn build_executable(
+    program: &Program,
+    output: &Path,
+    level: OptLevel,
+    libs: &[String],
+) -> Result<(), BuildError> {
+    in_temp_dir(|dir| build_in(program, output, level, libs, dir))
+}
+
+fn build_in(
+    program: &Program,
+    output: &Path,
+    level: OptLevel,
+    libs: &[String],
+    dir: &Path,
+) -> Result<(), BuildError> {
+    let ir = dir.join("program.ll");
+    let bitcode = dir.join("program.bc");
+    let object = dir.join("program.o");
+    let runtime = dir.join("runtime.c");
+    let main = dir.join("main.c");
+    fs::write(&ir, emit_ir(program))?;
+    fs::write(&runtime, RUNTIME)?;
+    fs::write(&main, MAIN)?;
+
+    let path = |path: &Path| path.to_string_lossy().into_owned();
+    run("opt", &[level.flag(), &path(&ir), "-o", &path(&bitcode)])?;
+    run(
+        "llc",
+        &[
+            level.flag(),
+            "-filetype=obj",
+            "-relocation-model=pic",
+            &path(&bitcode),
+            "-o",
+            &path(&object),
+        ],
+    )?;
+    link(&[level.flag()], &[&object, &runtime, &main], libs, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+    use std::process::Command;
+
+    #[test]
+    fn test_emit_ir() {
+        let source = "
+fn count(n: u8) u8 {
+    let mut i = 0;
+    while i < n { i += 1; }
+    i
+}";
+        assert_eq!(
+            emit_ir(&built(source)),
+            "; ModuleID = 'ruffle'
+source_filename = \"ruffle\"
+
+@str.0 = private unnamed_addr constant [20 x i8] c\"arithmetic overflow\\00\"
+
+declare i64 @rf_alloc(i64, i64, i64, i8*)
+declare i64 @rf_string(i8*, i64)
+declare i64 @rf_concat(i64, i64)
+declare i64 @rf_compare_strings(i64, i64)
+declare i64 @rf_equal(i64, i64, i64)
+declare i64 @rf_set_field(i64, i64, i64)
+declare i64 @rf_len(i64)
+declare i64 @rf_index(i64, i64)
+declare i64 @rf_set_index(i64, i64, i64)
+declare i64 @rf_chars(i64)
+declare void @rf_panic(i64) noreturn
+declare void @rf_fail(i8*) noreturn
+declare void @rf_print(i64)
+declare void @rf_println(i64)
+declare i64 @rf_input()
+declare i64 @rf_string_len(i64)
+declare i64 @rf_char_at(i64, i64)
+declare i64 @rf_slice(i64, i64)
+declare i64 @rf_contains(i64, i64)
+declare i64 @rf_split(i64, i64)
+declare i64 @rf_to_upper(i64)
+declare i64 @rf_to_lower(i64)
+declare i64 @rf_push(i64, i64)
+declare i64 @rf_pop(i64)
+declare i64 @rf_map_find(i64, i64)
+declare i64 @rf_map_insert(i64, i64, i64)
+declare i64 @rf_map_remove(i64, i64)
+declare i64 @rf_map_key(i64, i64)
+declare i64 @rf_map_value(i64, i64)
+declare i64 @rf_sqrt(i64)
+declare i64 @rf_floor(i64)
+declare i64 @rf_ceil(i64)
+declare i64 @rf_pow(i64, i64)
+declare i64 @rf_read_file(i64)
+declare i64 @rf_write_file(i64, i64)
+declare i64 @rf_file_exists(i64)
+declare i64 @rf_fs_error()
+declare i64 @rf_to_string(i64, i64)
+declare {i8, i1} @llvm.uadd.with.overflow.i8(i8, i8)
+
+; fn0 count
+define internal tailcc i64 @fn0(i64 %v0) {
+bb0:
+  br label %bb1
+bb1:
+  %v2 = phi i64 [ 0, %bb0 ], [ %t8, %ok.1 ]
+  %t0 = icmp ult i64 %v2, %v0
+  %t1 = zext i1 %t0 to i64
+  %t2 = trunc i64 %t1 to i1
+  br i1 %t2, label %bb2, label %bb3
+bb2:
+  %t3 = trunc i64 %v2 to i8
+  %t4 = trunc i64 1 to i8
+  %t5 = call {i8, i1} @llvm.uadd.with.overflow.i8(i8 %t3, i8 %t4)
+  %t6 = extractvalue {i8, i1} %t5, 1
+  br i1 %t6, label %fail.0, label %ok.1
+ok.1:
+  %t7 = extractvalue {i8, i1} %t5, 0
+  %t8 = zext i8 %t7 to i64
+  br label %bb1
+bb3:
+  ret i64 %v2
+fail.0:
+  call void @rf_fail(i8* getelementptr inbounds ([20 x i8], [20 x i8]* @str.0, i64 0, i64 0))
+  unreachable
+}
+
+define i64 @ruffle_main() {
+  ret i64 0
+}
+"
+        );
+    }
+
+    /// Builds a program and runs it, returning its exit code and what it printed to stderr. Each
+    /// test builds its executable under its own name.
+    fn run_program(name: &str, source: &str) -> (i32, String) {
+        let dir = std::env::temp_dir();
+        let exe = dir.join(format!("ruffle-test-{}-{}", name, std::process::id()));
+        build_executable(&built(source), &exe, OptLevel::O2, &[]).unwrap();
+        let output = Command::new(&exe).output().unwrap();
+        fs::remove_file(&exe).unwrap();
+        (
+            output.status.code().unwrap(),
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        )
+    }
+
+    #[test]
+    fn test_build_executable() {
+        let source = "
+enum Shape { Circle(float), Square(int) }
+static TOTAL: int = 0;
+fn area(shape: Shape) int {
+    match shape {
+        Shape::Circle(r) => (r * r * 3.0) as int,
+        Shape::Square(side) => side * side,
+    }
+}
+fn main() {
+    let (circle, square) = (Shape::Circle(2.0), Shape::Square(3));
+    let add = |n: int| TOTAL += n;
+    add(area(circle));
+    add(area(square));
+    let mut n = 0;
+    for c in \"héllo\" { if c != 'l' { n += 1; } }
+    if TOTAL != 21 || \"ab\" + \"c\" != \"abc\" || n != 3 {
+        panic(\"wrong\");
+    }
+}";
+        assert_eq!(run_program("build", source), (0, String::new()));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let source = "
+fn main() {
+    let x: u8 = 200;
+    let y = x + 100;
+}";
+        let (code, stderr) = run_program("overflow", source);
+        assert_eq!(code, 101);
+        assert_eq!(stderr, "panicked: arithmetic overflow\n");
+    }
+}
@@ -0,0 +1,15 @@
+//! Backends, which turn the MIR of a program into something that runs.
+
+pub mod asm;
+mod build;
+pub mod bytecode;
+pub mod c;
+pub mod cranelift;
+mod debug;
+#[cfg(feature = "backend-llvm")]
+pub mod llvm;
+pub mod object;
+pub(crate) mod runtime;
+pub mod wasm;
+
+pub use build::BuildError;
@@ -0,0 +1,110 @@
+//! Compiles a program to a relocatable object file with Cranelift, and links it with the runtime
+//! library into an executable, which doesn't need any tools but a C compiler to drive the linker.
+//!
+//! The object defines `ruffle_main`, which the `main` in `runtime/main.c` calls, and imports the
+//! functions of the runtime library, which is compiled from its source with each executable.
+
+use std::{fs, io, path::Path};
+
+use cranelift_codegen::{
+    isa,
+    settings::{self, Configurable},
+};
+use cranelift_module::{default_libcall_names, Module, ModuleError};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+
+use super::{
+    build::{in_temp_dir, link, BuildError},
+    cranelift::{flags, translate},
+    debug,
+    runtime::{MAIN, RUNTIME},
+};
+use crate::{
+    mir::{opt::OptLevel, Program},
+    source_map::SourceMap,
+};
+
+/// Makes a module whose code can be put anywhere in an executable, compiled for an ISA.
+pub(super) fn module(isa: isa::Builder, level: OptLevel) -> Result<ObjectModule, Box<ModuleError>> {
+    let mut flags = flags(level);
+    flags.set("is_pic", "true").unwrap();
+    let isa = isa
+        .finish(settings::Flags::new(flags))
+        .map_err(ModuleError::Compilation)?;
+    let builder = ObjectBuilder::new(isa, "ruffle", default_libcall_names())?;
+    Ok(ObjectModule::new(builder))
+}
+
+/// Compiles a program to an object file for the machine the compiler is running on, with DWARF
+/// debug info if there's a source map to map its code back to.
+pub fn emit_object(
+    program: &Program,
+    level: OptLevel,
+    debug: Option<&SourceMap>,
+) -> Result<Vec<u8>, BuildError> {
+    let isa = cranelift_native::builder()
+        .map_err(|message| BuildError::UnsupportedHost(message.to_string()))?;
+    let mut module = module(isa, level)?;
+    let translated = translate(&mut module, program)?;
+    let debug = debug.map(|source_map| {
+        let decls = module.declarations();
+        debug::describe(module.isa(), decls, program, &translated, source_map)
+    });
+    let mut product = module.finish();
+    if let Some(debug) = debug {
+        debug.add_to(&mut product);
+    }
+    let object = product.emit().map_err(io::Error::other)?;
+    Ok(object)
+}
+
+/// Builds an executable from a program, linking it with the system's C compiler, which is `$CC`,
+/// or `cc` by default, and with `libs`.
+pub fn build_executable(
+    program: &Program,
+    output: &Path,
+    level: OptLevel,
+    debug: Option<&SourceMap>,
+    libs: &[String],
+) -> Result<(), BuildError> {
+    let object = emit_object(program, level, debug)?;
+    in_temp_dir(|dir| {
+        let program = dir.join("program.o");
+        let runtime = dir.join("runtime.c");
+        let main = dir.join("main.c");
+        fs::write(&program, &object)?;
+        fs::write(&runtime, RUNTIME)?;
+        fs::write(&main, MAIN)?;
+        link(&[level.flag()], &[&program, &runtime, &main], libs, output)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+    use std::process::Command;
+
+    #[test]
+    fn test_build_executable() {
+        let source = "
+struct Point { x: int, y: int }
+static ORIGIN: Point = Point { x: 0, y: 0 };
+fn dist(p: Point) int { p.x - ORIGIN.x + p.y - ORIGIN.y }
+fn main() {
+    let points = (Point { x: 1, y: 2 }, Point { x: 3, y: 4 });
+    let (a, b) = points;
+    let f = |p: Point| dist(p) * 2;
+    if f(a) + f(b) != 20 || \"ab\" + \"c\" != \"abc\" {
+        panic(\"wrong\");
+    }
+    panic(\"right\");
+}";
+        let exe = std::env::temp_dir().join(format!("ruffle-object-test-{}", std::process::id()));
+        build_executable(&built(source), &exe, OptLevel::O2, None, &[]).unwrap();
+        let output = Command::new(&exe).output().unwrap();
+        fs::remove_file(&exe).unwrap();
+        assert_eq!(output.status.code(), Some(101));
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "panicked: right\n");
+    }
+}
@@ -0,0 +1,190 @@
+//! The runtime library that native code calls, which is written in C in `runtime/`. It's compiled
+//! into the compiler for the code it runs in memory, and its source is compiled with each
+//! executable. `runtime/runtime.c` describes how values are represented.
+
+use super::bytecode::Kind;
+use crate::typeck::Ty;
+
+/// The source of the library.
+pub const RUNTIME: &str = include_str!("../../runtime/runtime.c");
+
+/// The source of an executable's `main`, which calls the program's.
+pub const MAIN: &str = include_str!("../../runtime/main.c");
+
+/// The kinds of objects.
+pub const TUPLE: u32 = 0;
+pub const VARIANT: u32 = 1;
+pub const ARRAY: u32 = 2;
+pub const STRING: u32 = 3;
+pub const RANGE: u32 = 4;
+pub const FUNCTION: u32 = 5;
+pub const MAP: u32 = 6;
+
+/// The size of an object's header, before its slots.
+pub const HEADER: usize = 24;
+
+/// The messages of the failures that compiled code checks for itself.
+pub const OVERFLOW: &str = "arithmetic overflow";
+pub const DIVISION_BY_ZERO: &str = "division by zero";
+pub const SHIFT_OUT_OF_RANGE: &str = "shift amount out of range";
+
+/// The message that compiled code fails with when it makes a generator, or resumes or yields
+/// from one, which only the VM can do.
+pub const GENERATORS: &str = "generators only run in the VM";
+
+/// The name of the function that a compiled program defines to run it, which initializes its
+/// globals and then calls its `main`.
+pub const ENTRY: &str = "ruffle_main";
+
+/// The functions of the library with how many arguments they take and whether they return a
+/// value. Every argument and result is 64 bits.
+pub const FUNCTIONS: [(&str, usize, bool); 39] = [
+    ("rf_alloc", 4, true),
+    ("rf_string", 2, true),
+    ("rf_concat", 2, true),
+    ("rf_compare_strings", 2, true),
+    ("rf_float_rem", 2, true),
+    ("rf_equal", 3, true),
+    ("rf_set_field", 3, true),
+    ("rf_len", 1, true),
+    ("rf_index", 2, true),
+    ("rf_set_index", 3, true),
+    ("rf_chars", 1, true),
+    ("rf_panic", 1, false),
+    ("rf_fail", 1, false),
+    ("rf_print", 1, false),
+    ("rf_println", 1, false),
+    ("rf_input", 0, true),
+    ("rf_string_len", 1, true),
+    ("rf_char_at", 2, true),
+    ("rf_slice", 2, true),
+    ("rf_contains", 2, true),
+    ("rf_split", 2, true),
+    ("rf_to_upper", 1, true),
+    ("rf_to_lower", 1, true),
+    ("rf_push", 2, true),
+    ("rf_pop", 1, true),
+    ("rf_map_find", 2, true),
+    ("rf_map_insert", 3, true),
+    ("rf_map_remove", 2, true),
+    ("rf_map_key", 2, true),
+    ("rf_map_value", 2, true),
+    ("rf_sqrt", 1, true),
+    ("rf_floor", 1, true),
+    ("rf_ceil", 1, true),
+    ("rf_pow", 2, true),
+    ("rf_read_file", 1, true),
+    ("rf_write_file", 2, true),
+    ("rf_file_exists", 1, true),
+    ("rf_fs_error", 0, true),
+    ("rf_to_string", 2, true),
+];
+
+/// Returns the letter that `rf_to_string` takes for the kind of value it converts to a string.
+pub fn text_kind(kind: Kind) -> u8 {
+    match kind {
+        Kind::Int(ty) if ty.is_signed() => b'i',
+        Kind::Int(_) => b'u',
+        Kind::Bool => b'b',
+        _ => b'c',
+    }
+}
+
+/// Returns the letter the library uses for what a slot of the type holds.
+pub fn slot_kind(ty: &Ty) -> u8 {
+    match ty {
+        Ty::Float => b'f',
+        // The values of a type parameter are compared as they are, since their type isn't known
+        Ty::Int(_) | Ty::IntVar(_) | Ty::Bool | Ty::Char | Ty::Param { .. } => b'i',
+        _ => b'o',
+    }
+}
+
+// Only their addresses are taken, so their parameters don't matter
+extern "C" {
+    fn rf_alloc();
+    fn rf_string();
+    fn rf_concat();
+    fn rf_compare_strings();
+    fn rf_float_rem();
+    fn rf_equal();
+    fn rf_set_field();
+    fn rf_len();
+    fn rf_index();
+    fn rf_set_index();
+    fn rf_chars();
+    fn rf_panic();
+    fn rf_fail();
+    fn rf_print();
+    fn rf_println();
+    fn rf_input();
+    fn rf_string_len();
+    fn rf_char_at();
+    fn rf_slice();
+    fn rf_contains();
+    fn rf_split();
+    fn rf_to_upper();
+    fn rf_to_lower();
+    fn rf_push();
+    fn rf_pop();
+    fn rf_map_find();
+    fn rf_map_insert();
+    fn rf_map_remove();
+    fn rf_map_key();
+    fn rf_map_value();
+    fn rf_sqrt();
+    fn rf_floor();
+    fn rf_ceil();
+    fn rf_pow();
+    fn rf_read_file();
+    fn rf_write_file();
+    fn rf_file_exists();
+    fn rf_fs_error();
+    fn rf_to_string();
+}
+
+/// Returns the address of each function of the library that's compiled into the compiler, in the
+/// order of [`FUNCTIONS`].
+pub fn addresses() -> [*const u8; 39] {
+    [
+        rf_alloc as *const u8,
+        rf_string as *const u8,
+        rf_concat as *const u8,
+        rf_compare_strings as *const u8,
+        rf_float_rem as *const u8,
+        rf_equal as *const u8,
+        rf_set_field as *const u8,
+        rf_len as *const u8,
+        rf_index as *const u8,
+        rf_set_index as *const u8,
+        rf_chars as *const u8,
+        rf_panic as *const u8,
+        rf_fail as *const u8,
+        rf_print as *const u8,
+        rf_println as *const u8,
+        rf_input as *const u8,
+        rf_string_len as *const u8,
+        rf_char_at as *const u8,
+        rf_slice as *const u8,
+        rf_contains as *const u8,
+        rf_split as *const u8,
+        rf_to_upper as *const u8,
+        rf_to_lower as *const u8,
+        rf_push as *const u8,
+        rf_pop as *const u8,
+        rf_map_find as *const u8,
+        rf_map_insert as *const u8,
+        rf_map_remove as *const u8,
+        rf_map_key as *const u8,
+        rf_map_value as *const u8,
+        rf_sqrt as *const u8,
+        rf_floor as *const u8,
+        rf_ceil as *const u8,
+        rf_pow as *const u8,
+        rf_read_file as *const u8,
+        rf_write_file as *const u8,
+        rf_file_exists as *const u8,
+        rf_fs_error as *const u8,
+        rf_to_string as *const u8,
+    ]
+}
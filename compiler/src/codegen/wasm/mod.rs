@@ -0,0 +1,1231 @@
+//! Lowers the MIR of a program to a WebAssembly module, which runs in WASI runtimes such as
+//! wasmtime, and in browsers with `runtime/ruffle.js`.
+//!
+//! Values are represented as described in `runtime/runtime.c`, with objects in the module's
+//! memory at 32-bit addresses. The module exports its memory, every function with a name, and
+//! `_start`, which runs the program. Only WASI is imported, so calling an `extern fn` panics.
+//!
+//! WebAssembly only has structured control flow, so the blocks of a function are laid out in
+//! order, each after the end of a `block` that the blocks before it can break out of to jump to
+//! it. Jumping back to a block goes through a loop around all of them, which dispatches on a
+//! local holding the block to go to. A call whose value is returned is a `return_call`, from the
+//! tail call proposal, so the runtime has to support it.
+
+mod runtime;
+#[cfg(test)]
+mod wasi;
+
+use std::collections::{HashMap, HashSet};
+
+use wasm_encoder::{
+    BlockType, CodeSection, ConstExpr, DataSection, ElementSection, Elements, EntityType,
+    ExportKind, ExportSection, Function, FunctionSection, GlobalSection, GlobalType, ImportSection,
+    InstructionSink, MemArg, MemorySection, MemoryType, RefType, TableSection, TableType,
+    TypeSection, ValType,
+};
+
+use super::{
+    bytecode::Kind,
+    runtime::{
+        slot_kind, text_kind, ARRAY, DIVISION_BY_ZERO, FUNCTION, GENERATORS, HEADER, MAP, OVERFLOW,
+        RANGE, SHIFT_OUT_OF_RANGE, STRING, TUPLE, VARIANT,
+    },
+};
+use crate::{
+    ast::{BinaryOp, UnaryOp},
+    mir::{
+        BlockCall, BlockId, Callee, Constant, FuncId, Function as MirFunction, InstKind, Intrinsic,
+        Program, Terminator, Value,
+    },
+    resolve::DefId,
+    typeck::{IntTy, Ty},
+};
+use runtime::{
+    ALLOC, CHARS, CHAR_AT, COMPARE_STRINGS, CONCAT, CONTAINS, EQUAL, FILE_EXISTS, FLOAT_REM,
+    FS_ERROR, INDEX, INPUT, LEN, MAP_FIND, MAP_INSERT, MAP_KEY, MAP_REMOVE, MAP_VALUE, PANIC, POP,
+    POW, PRINT, PRINTLN, PUSH, READ_FILE, SET_FIELD, SET_INDEX, SLICE, SPLIT, STRING_LEN, TO_LOWER,
+    TO_STRING, TO_UPPER, WRITE_FILE,
+};
+
+const I64: ValType = ValType::I64;
+
+/// The address of the constant data, after the address of `()`.
+const DATA: usize = 8;
+
+/// The types of the functions of a module, each defined once.
+#[derive(Default)]
+struct Types {
+    section: TypeSection,
+    indices: HashMap<(Vec<ValType>, Vec<ValType>), u32>,
+}
+
+impl Types {
+    fn func(&mut self, params: &[ValType], results: &[ValType]) -> u32 {
+        let key = (params.to_vec(), results.to_vec());
+        if let Some(&index) = self.indices.get(&key) {
+            return index;
+        }
+        let index = self.section.len();
+        self.section
+            .ty()
+            .function(params.iter().copied(), results.iter().copied());
+        self.indices.insert(key, index);
+        index
+    }
+
+    /// The type of a function that takes values and returns one.
+    fn values(&mut self, params: usize) -> u32 {
+        self.func(&vec![I64; params], &[I64])
+    }
+}
+
+/// The memory a module starts with, which holds its constant strings and shapes.
+#[derive(Default)]
+struct Data {
+    bytes: Vec<u8>,
+    constants: HashMap<Vec<u8>, i64>,
+    strings: HashMap<String, i64>,
+}
+
+impl Data {
+    fn push(&mut self, bytes: &[u8]) -> i64 {
+        let address = DATA + self.bytes.len();
+        self.bytes.extend(bytes);
+        // So that the next object starts at a multiple of 8
+        self.bytes.resize(self.bytes.len().next_multiple_of(8), 0);
+        address as i64
+    }
+
+    /// Returns the address of constant bytes.
+    fn bytes(&mut self, bytes: &[u8]) -> i64 {
+        if let Some(&address) = self.constants.get(bytes) {
+            return address;
+        }
+        let address = self.push(bytes);
+        self.constants.insert(bytes.to_vec(), address);
+        address
+    }
+
+    /// Returns the address of bytes that the code changes.
+    fn scratch(&mut self, bytes: &[u8]) -> i64 {
+        self.push(bytes)
+    }
+
+    /// Returns the address of a constant string.
+    fn string(&mut self, string: &str) -> i64 {
+        if let Some(&address) = self.strings.get(string) {
+            return address;
+        }
+        let mut object = Vec::new();
+        object.extend(STRING.to_le_bytes());
+        object.extend(0u32.to_le_bytes());
+        object.extend((string.len() as i64).to_le_bytes());
+        object.extend(0i64.to_le_bytes());
+        object.extend(string.as_bytes());
+        let address = self.push(&object);
+        self.strings.insert(string.to_string(), address);
+        address
+    }
+
+    /// The address of the end of the data, where the heap starts.
+    fn end(&self) -> usize {
+        DATA + self.bytes.len()
+    }
+}
+
+/// Lowers a program to the bytes of a WebAssembly module.
+pub fn emit(program: &Program) -> Vec<u8> {
+    let mut module = ModuleEmitter {
+        program,
+        types: Types::default(),
+        data: Data::default(),
+        variants: HashMap::new(),
+        thunks: Vec::new(),
+    };
+    for adt in &program.adts {
+        for (discriminant, variant) in adt.variants.iter().enumerate() {
+            module.variants.insert(variant.def, discriminant);
+        }
+    }
+    let imports = runtime::imports(&mut module.types);
+    let mut code = runtime::functions(&mut module.types, &mut module.data);
+    for func in &program.fns {
+        let ty = module.types.values(func.params().len());
+        code.push((ty, module.function(func)));
+    }
+    let entry = module.entry();
+    code.push((module.types.func(&[], &[]), entry));
+    // Thunks can't make more thunks, so they can be made after everything else
+    for i in 0..module.thunks.len() {
+        let (func, captures) = module.thunks[i];
+        let params = program.func(func).params().len() - captures;
+        let ty = module.types.values(params + 1);
+        code.push((ty, module.thunk(func, captures)));
+    }
+    module.finish(&imports, code)
+}
+
+struct ModuleEmitter<'a> {
+    program: &'a Program,
+    types: Types,
+    data: Data,
+    /// The discriminant of each struct and variant.
+    variants: HashMap<DefId, usize>,
+    /// The functions used as values, with how many values they capture. The thunk of each is in
+    /// the table at its index here.
+    thunks: Vec<(FuncId, usize)>,
+}
+
+impl ModuleEmitter<'_> {
+    /// The index of a function of the program.
+    fn func_index(&self, func: FuncId) -> u32 {
+        runtime::END + func.0 as u32
+    }
+
+    /// The index of the thunk of a function used as a value.
+    fn thunk_index(&mut self, func: FuncId, captures: usize) -> u32 {
+        let index = match self.thunks.iter().position(|&(f, _)| f == func) {
+            Some(index) => index,
+            None => {
+                self.thunks.push((func, captures));
+                self.thunks.len() - 1
+            }
+        };
+        index as u32
+    }
+
+    fn function(&mut self, func: &MirFunction) -> Function {
+        let params = func.params();
+        if func.blocks.is_empty() {
+            // Only declared, so it's never called
+            let mut f = Function::new([]);
+            f.instructions().unreachable().end();
+            return f;
+        }
+        // The parameters are the first locals, then every other value
+        let mut locals = vec![0; func.values.len()];
+        for (i, param) in params.iter().enumerate() {
+            locals[param.0] = i as u32;
+        }
+        let mut next = params.len() as u32;
+        for (value, local) in locals.iter_mut().enumerate() {
+            if !params.iter().any(|param| param.0 == value) {
+                *local = next;
+                next += 1;
+            }
+        }
+        let others = next - params.len() as u32;
+        let (temp, label) = (next, next + 1);
+        let mut emitter = FnEmitter {
+            module: self,
+            func,
+            locals,
+            temp,
+            label,
+            code: Vec::new(),
+        };
+        emitter.body();
+        let mut f = Function::new([(others + 1, I64), (1, ValType::I32)]);
+        f.raw(emitter.code);
+        f
+    }
+
+    /// The thunk of a function used as a value, which takes the value and the function's own
+    /// arguments, and passes the values it captured to the function.
+    fn thunk(&mut self, func: FuncId, captures: usize) -> Function {
+        let params = self.program.func(func).params().len() - captures;
+        let mut f = Function::new([]);
+        let mut ins = f.instructions();
+        for i in 0..captures {
+            ins.local_get(0).i32_wrap_i64().i64_load(slot(i + 1));
+        }
+        for i in 0..params {
+            ins.local_get(i as u32 + 1);
+        }
+        ins.call(self.func_index(func)).end();
+        f
+    }
+
+    /// The function that runs the program, which initializes the globals in order, then calls
+    /// `main`.
+    fn entry(&mut self) -> Function {
+        let mut f = Function::new([]);
+        let mut ins = f.instructions();
+        for (i, global) in self.program.globals.iter().enumerate() {
+            ins.call(self.func_index(global.init))
+                .global_set(i as u32 + 1);
+        }
+        let main = self
+            .program
+            .fns
+            .iter()
+            .position(|func| func.def.is_some() && func.name == "main");
+        if let Some(main) = main {
+            ins.call(self.func_index(FuncId(main))).drop();
+        }
+        ins.end();
+        f
+    }
+
+    fn finish(mut self, imports: &[(&str, u32)], code: Vec<(u32, Function)>) -> Vec<u8> {
+        let mut import_section = ImportSection::new();
+        for &(name, ty) in imports {
+            import_section.import("wasi_snapshot_preview1", name, EntityType::Function(ty));
+        }
+        let mut functions = FunctionSection::new();
+        let mut codes = CodeSection::new();
+        for (ty, f) in &code {
+            functions.function(*ty);
+            codes.function(f);
+        }
+
+        let thunks = self.thunks.len();
+        let first_thunk = code.len() - thunks + imports.len();
+        let mut tables = TableSection::new();
+        tables.table(TableType {
+            element_type: RefType::FUNCREF,
+            table64: false,
+            minimum: thunks as u64,
+            maximum: Some(thunks as u64),
+            shared: false,
+        });
+        let mut elements = ElementSection::new();
+        let indices: Vec<u32> = (first_thunk..first_thunk + thunks)
+            .map(|index| index as u32)
+            .collect();
+        elements.active(
+            None,
+            &ConstExpr::i32_const(0),
+            Elements::Functions(indices.into()),
+        );
+
+        let heap = self.data.end().next_multiple_of(8);
+        let mut memories = MemorySection::new();
+        memories.memory(MemoryType {
+            minimum: heap.div_ceil(0x10000).max(1) as u64,
+            maximum: None,
+            memory64: false,
+            shared: false,
+            page_size_log2: None,
+        });
+        let mut globals = GlobalSection::new();
+        globals.global(
+            GlobalType {
+                val_type: ValType::I32,
+                mutable: true,
+                shared: false,
+            },
+            &ConstExpr::i32_const(heap as i32),
+        );
+        for _ in &self.program.globals {
+            globals.global(
+                GlobalType {
+                    val_type: I64,
+                    mutable: true,
+                    shared: false,
+                },
+                &ConstExpr::i64_const(0),
+            );
+        }
+
+        let mut exports = ExportSection::new();
+        exports.export("memory", ExportKind::Memory, 0);
+        let entry = runtime::END + self.program.fns.len() as u32;
+        exports.export("_start", ExportKind::Func, entry);
+        let mut names = HashSet::new();
+        for (i, func) in self.program.fns.iter().enumerate() {
+            if func.def.is_some() && names.insert(&func.name) {
+                exports.export(&func.name, ExportKind::Func, self.func_index(FuncId(i)));
+            }
+        }
+
+        let mut data = DataSection::new();
+        data.active(
+            0,
+            &ConstExpr::i32_const(DATA as i32),
+            std::mem::take(&mut self.data.bytes),
+        );
+
+        let mut module = wasm_encoder::Module::new();
+        module
+            .section(&self.types.section)
+            .section(&import_section)
+            .section(&functions)
+            .section(&tables)
+            .section(&memories)
+            .section(&globals)
+            .section(&exports)
+            .section(&elements)
+            .section(&codes)
+            .section(&data);
+        module.finish()
+    }
+}
+
+fn mem(offset: usize, align: u32) -> MemArg {
+    MemArg {
+        offset: offset as u64,
+        align,
+        memory_index: 0,
+    }
+}
+
+/// The offset of a slot of an object.
+fn slot(index: usize) -> MemArg {
+    mem(HEADER + 8 * index, 3)
+}
+
+struct FnEmitter<'a, 'b> {
+    module: &'a mut ModuleEmitter<'b>,
+    func: &'a MirFunction,
+    /// The local of each value.
+    locals: Vec<u32>,
+    /// A local for intermediate values.
+    temp: u32,
+    /// The local holding the block to jump back to.
+    label: u32,
+    code: Vec<u8>,
+}
+
+impl FnEmitter<'_, '_> {
+    fn ins(&mut self) -> InstructionSink<'_> {
+        InstructionSink::new(&mut self.code)
+    }
+
+    fn get(&mut self, value: Value) -> &mut Self {
+        let local = self.locals[value.0];
+        self.ins().local_get(local);
+        self
+    }
+
+    fn body(&mut self) {
+        let blocks = self.func.blocks.len();
+        let loops = self.func.block_ids().any(|block| {
+            let targets = self.func.block(block).terminator.targets();
+            targets.iter().any(|target| target.block.0 <= block.0)
+        });
+        // Without a loop, the entry block goes first without having to be dispatched to
+        let first = if loops { 0 } else { 1 };
+        if loops {
+            self.ins().loop_(BlockType::Empty);
+        }
+        for _ in first..blocks {
+            self.ins().block(BlockType::Empty);
+        }
+        if loops {
+            let label = self.label;
+            self.ins().local_get(label).br_table(0..blocks as u32, 0);
+        }
+        for block in self.func.block_ids() {
+            if block.0 >= first {
+                self.ins().end();
+            }
+            self.block(block, blocks);
+        }
+        if loops {
+            self.ins().end();
+        }
+        self.ins().unreachable().end();
+    }
+
+    fn block(&mut self, id: BlockId, blocks: usize) {
+        let block = self.func.block(id);
+        if let Some((callee, args)) = block.tail_call() {
+            // The call returns in the caller's place, so the last instruction is all there is
+            let (_, insts) = block.insts.split_last().unwrap();
+            for inst in insts {
+                self.inst(inst.value, &inst.kind);
+                let local = self.locals[inst.value.0];
+                self.ins().local_set(local);
+            }
+            self.call(callee, args, true);
+            return;
+        }
+        for inst in &block.insts {
+            self.inst(inst.value, &inst.kind);
+            let local = self.locals[inst.value.0];
+            self.ins().local_set(local);
+        }
+        match &block.terminator {
+            Terminator::Jump(target) => self.jump(id, target, blocks, 0),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                self.get(*cond).ins().i32_wrap_i64().if_(BlockType::Empty);
+                self.jump(id, then_block, blocks, 1);
+                self.ins().else_();
+                self.jump(id, else_block, blocks, 1);
+                self.ins().end();
+            }
+            Terminator::Return(value) => {
+                self.get(*value).ins().return_();
+            }
+            Terminator::Unreachable => {
+                self.ins().unreachable();
+            }
+        }
+    }
+
+    /// Jumps from the end of a block, inside `depth` more blocks than it.
+    fn jump(&mut self, from: BlockId, target: &BlockCall, blocks: usize, depth: u32) {
+        for &arg in &target.args {
+            self.get(arg);
+        }
+        for param in self.func.block(target.block).params.iter().rev() {
+            let local = self.locals[param.0];
+            self.ins().local_set(local);
+        }
+        let (from, to) = (from.0 as u32, target.block.0 as u32);
+        if to > from {
+            // The next block is right after the end of this one
+            if to > from + 1 || depth > 0 {
+                self.ins().br(to - from - 1 + depth);
+            }
+        } else {
+            let label = self.label;
+            self.ins()
+                .i32_const(to as i32)
+                .local_set(label)
+                .br(blocks as u32 - 1 - from + depth);
+        }
+    }
+
+    /// Calls a function, pushing its value, or returning it with `tail`, in which case the callee
+    /// takes over the caller's frame.
+    fn call(&mut self, callee: Callee, args: &[Value], tail: bool) {
+        match callee {
+            Callee::Direct(func) => {
+                for &arg in args {
+                    self.get(arg);
+                }
+                let index = self.module.func_index(func);
+                if tail {
+                    self.ins().return_call(index);
+                } else {
+                    self.ins().call(index);
+                }
+            }
+            Callee::Indirect(callee) => {
+                self.get(callee);
+                for &arg in args {
+                    self.get(arg);
+                }
+                let ty = self.module.types.values(args.len() + 1);
+                self.get(callee)
+                    .ins()
+                    .i32_wrap_i64()
+                    .i64_load(slot(0))
+                    .i32_wrap_i64();
+                if tail {
+                    self.ins().return_call_indirect(0, ty);
+                } else {
+                    self.ins().call_indirect(0, ty);
+                }
+            }
+        }
+    }
+
+    /// Panics with the message if the `i32` on the stack isn't 0.
+    fn check(&mut self, message: &str) {
+        let message = self.module.data.string(message);
+        self.ins()
+            .if_(BlockType::Empty)
+            .i64_const(message)
+            .call(PANIC)
+            .end();
+    }
+
+    /// Allocates an object with `len` slots, and leaves it in the temporary local.
+    fn alloc(&mut self, kind: u32, tag: usize, len: usize, shape: &[u8]) {
+        let shape = self.module.data.bytes(shape);
+        let temp = self.temp;
+        self.ins()
+            .i64_const(kind as i64)
+            .i64_const(tag as i64)
+            .i64_const(len as i64)
+            .i64_const(shape)
+            .call(ALLOC)
+            .local_set(temp);
+    }
+
+    /// Allocates an object with values in its slots, and pushes it.
+    fn object(&mut self, kind: u32, tag: usize, values: &[Value]) {
+        let mut shape: Vec<u8> = values
+            .iter()
+            .map(|&value| slot_kind(self.func.value_ty(value)))
+            .collect();
+        shape.push(0);
+        self.alloc(kind, tag, values.len(), &shape);
+        for (i, &value) in values.iter().enumerate() {
+            self.set_slot(i, value);
+        }
+        let temp = self.temp;
+        self.ins().local_get(temp);
+    }
+
+    /// Sets a slot of the object in the temporary local.
+    fn set_slot(&mut self, index: usize, value: Value) {
+        let temp = self.temp;
+        self.ins().local_get(temp).i32_wrap_i64();
+        self.get(value).ins().i64_store(slot(index));
+    }
+
+    /// Pushes the value of an instruction.
+    fn inst(&mut self, value: Value, kind: &InstKind) {
+        let kind_of = |value: Value| Kind::of(self.func.value_ty(value));
+        match kind {
+            InstKind::Const(constant) => match constant {
+                Constant::Int(value) => {
+                    self.ins().i64_const(*value as i64);
+                }
+                Constant::Float(value) => {
+                    self.ins().i64_const(value.to_bits() as i64);
+                }
+                Constant::Bool(value) => {
+                    self.ins().i64_const(*value as i64);
+                }
+                Constant::Char(value) => {
+                    self.ins().i64_const(*value as i64);
+                }
+                Constant::String(value) => {
+                    let address = self.module.data.string(value);
+                    self.ins().i64_const(address);
+                }
+                Constant::Unit => {
+                    self.ins().i64_const(0);
+                }
+            },
+            InstKind::Undef => {
+                self.ins().i64_const(0);
+            }
+            InstKind::Unary { op, value } => self.unary(*op, kind_of(*value), *value),
+            InstKind::Binary { op, lhs, rhs } => self.binary(*op, kind_of(*lhs), *lhs, *rhs),
+            InstKind::Cast(inner) => self.cast(kind_of(*inner), kind_of(value), *inner),
+            InstKind::Tuple(elems) if elems.is_empty() => {
+                self.ins().i64_const(0);
+            }
+            InstKind::Tuple(elems) => self.object(TUPLE, 0, elems),
+            // An array's shape is a single letter, for all of its elements
+            InstKind::Array(elems) => {
+                let elem = match self.func.value_ty(value) {
+                    Ty::Array(elem) => slot_kind(elem),
+                    _ => b'i',
+                };
+                self.alloc(ARRAY, 0, elems.len(), &[elem, 0]);
+                for (i, &elem) in elems.iter().enumerate() {
+                    self.set_slot(i, elem);
+                }
+                let temp = self.temp;
+                self.ins().local_get(temp);
+            }
+            InstKind::Construct { def, fields } => {
+                let discriminant = self.module.variants[def];
+                self.object(VARIANT, discriminant, fields);
+            }
+            InstKind::Field { base, index } | InstKind::VariantField { base, index, .. } => {
+                self.get(*base).ins().i32_wrap_i64().i64_load(slot(*index));
+            }
+            InstKind::SetField { base, index, value } => {
+                self.get(*base).ins().i64_const(*index as i64);
+                self.get(*value).ins().call(SET_FIELD);
+            }
+            InstKind::Discriminant(base) => {
+                self.get(*base).ins().i32_wrap_i64().i64_load32_u(mem(4, 2));
+            }
+            InstKind::Index { base, index } => {
+                self.get(*base).get(*index).ins().call(INDEX);
+            }
+            InstKind::SetIndex { base, index, value } => {
+                self.get(*base)
+                    .get(*index)
+                    .get(*value)
+                    .ins()
+                    .call(SET_INDEX);
+            }
+            InstKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let flags = start.is_some() as usize
+                    | (end.is_some() as usize) << 1
+                    | (*inclusive as usize) << 2;
+                let elem = match self.func.value_ty(value) {
+                    Ty::Range(elem) => slot_kind(elem),
+                    _ => b'i',
+                };
+                self.alloc(RANGE, flags, 2, &[elem, elem, 0]);
+                // A missing bound is left as 0
+                for (i, bound) in [start, end].into_iter().enumerate() {
+                    if let Some(bound) = bound {
+                        self.set_slot(i, *bound);
+                    }
+                }
+                let temp = self.temp;
+                self.ins().local_get(temp);
+            }
+            InstKind::Global(global) => {
+                self.ins().global_get(global.0 as u32 + 1);
+            }
+            InstKind::SetGlobal { global, value } => {
+                self.get(*value)
+                    .ins()
+                    .global_set(global.0 as u32 + 1)
+                    .i64_const(0);
+            }
+            InstKind::FnRef(func) => self.function_value(*func, &[]),
+            InstKind::Closure { func, captures } => self.function_value(*func, captures),
+            InstKind::Call { callee, args } => self.call(*callee, args, false),
+            InstKind::Extern { name, .. } => {
+                // Only WASI is imported, so there's no host to call
+                let message = format!(
+                    "`{}` is a host function, which WebAssembly can't call",
+                    name
+                );
+                let message = self.module.data.string(&message);
+                self.ins().i64_const(message).call(PANIC).i64_const(0);
+            }
+            InstKind::Generator { .. } => {
+                let message = self.module.data.string(GENERATORS);
+                self.ins().i64_const(message).call(PANIC).i64_const(0);
+            }
+            InstKind::Intrinsic { intrinsic, args } => {
+                for &arg in args {
+                    self.get(arg);
+                }
+                match intrinsic {
+                    Intrinsic::Len => self.ins().call(LEN),
+                    Intrinsic::Chars => self.ins().call(CHARS),
+                    Intrinsic::Panic => self.ins().call(PANIC).i64_const(0),
+                    Intrinsic::Print => self.ins().call(PRINT).i64_const(0),
+                    Intrinsic::Println => self.ins().call(PRINTLN).i64_const(0),
+                    Intrinsic::Input => self.ins().call(INPUT),
+                    Intrinsic::StringLen => self.ins().call(STRING_LEN),
+                    Intrinsic::CharAt => self.ins().call(CHAR_AT),
+                    Intrinsic::Slice => self.ins().call(SLICE),
+                    Intrinsic::Contains => self.ins().call(CONTAINS),
+                    Intrinsic::Split => self.ins().call(SPLIT),
+                    Intrinsic::ToUpper => self.ins().call(TO_UPPER),
+                    Intrinsic::ToLower => self.ins().call(TO_LOWER),
+                    Intrinsic::Push => self.ins().call(PUSH),
+                    Intrinsic::Pop => self.ins().call(POP),
+                    Intrinsic::NewMap => {
+                        let shape = match self.func.value_ty(value) {
+                            Ty::Map(key, value) => [slot_kind(key), slot_kind(value), 0],
+                            _ => [b'i', b'i', 0],
+                        };
+                        self.alloc(MAP, 0, 0, &shape);
+                        let temp = self.temp;
+                        self.ins().local_get(temp)
+                    }
+                    Intrinsic::Insert => self.ins().call(MAP_INSERT),
+                    Intrinsic::Find => self.ins().call(MAP_FIND),
+                    Intrinsic::EntryKey => self.ins().call(MAP_KEY),
+                    Intrinsic::EntryValue => self.ins().call(MAP_VALUE),
+                    Intrinsic::Remove => self.ins().call(MAP_REMOVE),
+                    Intrinsic::Sqrt => self
+                        .ins()
+                        .f64_reinterpret_i64()
+                        .f64_sqrt()
+                        .i64_reinterpret_f64(),
+                    Intrinsic::Floor => self
+                        .ins()
+                        .f64_reinterpret_i64()
+                        .f64_floor()
+                        .i64_reinterpret_f64(),
+                    Intrinsic::Ceil => self
+                        .ins()
+                        .f64_reinterpret_i64()
+                        .f64_ceil()
+                        .i64_reinterpret_f64(),
+                    Intrinsic::Pow => self.ins().call(POW),
+                    Intrinsic::ReadFile => self.ins().call(READ_FILE),
+                    Intrinsic::WriteFile => self.ins().call(WRITE_FILE),
+                    Intrinsic::FileExists => self.ins().call(FILE_EXISTS),
+                    Intrinsic::FsError => self.ins().call(FS_ERROR),
+                    Intrinsic::Yield | Intrinsic::Next => {
+                        let message = self.module.data.string(GENERATORS);
+                        self.ins()
+                            .drop()
+                            .i64_const(message)
+                            .call(PANIC)
+                            .i64_const(0)
+                    }
+                };
+            }
+        }
+    }
+
+    /// Pushes a function value, whose first slot is the index of the function's thunk in the
+    /// table, and whose other slots hold the values it captures.
+    fn function_value(&mut self, func: FuncId, captures: &[Value]) {
+        let thunk = self.module.thunk_index(func, captures.len());
+        let mut shape = vec![b'i'];
+        for &capture in captures {
+            shape.push(slot_kind(self.func.value_ty(capture)));
+        }
+        shape.push(0);
+        self.alloc(FUNCTION, 0, captures.len() + 1, &shape);
+        let temp = self.temp;
+        self.ins()
+            .local_get(temp)
+            .i32_wrap_i64()
+            .i64_const(thunk as i64)
+            .i64_store(slot(0));
+        for (i, &capture) in captures.iter().enumerate() {
+            self.set_slot(i + 1, capture);
+        }
+        self.ins().local_get(temp);
+    }
+
+    /// Sign or zero extends the integer on the stack from the width of its type.
+    fn wrap(&mut self, ty: IntTy) {
+        match (ty.bits(), ty.is_signed()) {
+            (64, _) => {}
+            (8, true) => {
+                self.ins().i64_extend8_s();
+            }
+            (16, true) => {
+                self.ins().i64_extend16_s();
+            }
+            (32, true) => {
+                self.ins().i64_extend32_s();
+            }
+            (bits, _) => {
+                self.ins().i64_const((1 << bits) - 1).i64_and();
+            }
+        }
+    }
+
+    fn unary(&mut self, op: UnaryOp, kind: Kind, operand: Value) {
+        match (op, kind) {
+            (UnaryOp::Neg, Kind::Int(ty)) if ty.is_signed() => {
+                self.get(operand).ins().i64_const(ty.min() as i64).i64_eq();
+                self.check(OVERFLOW);
+                self.ins().i64_const(0);
+                self.get(operand).ins().i64_sub();
+            }
+            (UnaryOp::Neg, Kind::Int(_)) => {
+                // Only 0 has an unsigned negation
+                self.get(operand).ins().i64_const(0).i64_ne();
+                self.check(OVERFLOW);
+                self.ins().i64_const(0);
+            }
+            (UnaryOp::Neg, _) => {
+                self.get(operand)
+                    .ins()
+                    .f64_reinterpret_i64()
+                    .f64_neg()
+                    .i64_reinterpret_f64();
+            }
+            (UnaryOp::Not, Kind::Int(ty)) => {
+                self.get(operand).ins().i64_const(-1).i64_xor();
+                self.wrap(ty);
+            }
+            (UnaryOp::Not, _) => {
+                self.get(operand).ins().i64_const(1).i64_xor();
+            }
+        }
+    }
+
+    fn binary(&mut self, op: BinaryOp, kind: Kind, lhs: Value, rhs: Value) {
+        match kind {
+            Kind::Int(ty) => self.int_binary(op, ty, lhs, rhs),
+            Kind::Float if op == BinaryOp::Rem => {
+                self.get(lhs).get(rhs).ins().call(FLOAT_REM);
+            }
+            Kind::Float => {
+                self.get(lhs).ins().f64_reinterpret_i64();
+                self.get(rhs).ins().f64_reinterpret_i64();
+                let mut ins = self.ins();
+                match op {
+                    BinaryOp::Add => ins.f64_add().i64_reinterpret_f64(),
+                    BinaryOp::Sub => ins.f64_sub().i64_reinterpret_f64(),
+                    BinaryOp::Mul => ins.f64_mul().i64_reinterpret_f64(),
+                    BinaryOp::Div => ins.f64_div().i64_reinterpret_f64(),
+                    BinaryOp::Eq | BinaryOp::TripleEq => ins.f64_eq().i64_extend_i32_u(),
+                    BinaryOp::Ne | BinaryOp::TripleNe => ins.f64_ne().i64_extend_i32_u(),
+                    BinaryOp::Lt => ins.f64_lt().i64_extend_i32_u(),
+                    BinaryOp::Le => ins.f64_le().i64_extend_i32_u(),
+                    BinaryOp::Gt => ins.f64_gt().i64_extend_i32_u(),
+                    _ => ins.f64_ge().i64_extend_i32_u(),
+                };
+            }
+            Kind::String if op == BinaryOp::Add => {
+                self.get(lhs).get(rhs).ins().call(CONCAT);
+            }
+            Kind::String => {
+                self.get(lhs)
+                    .get(rhs)
+                    .ins()
+                    .call(COMPARE_STRINGS)
+                    .i64_const(0);
+                self.compare(op, true);
+            }
+            Kind::Bool | Kind::Char => {
+                self.get(lhs).get(rhs);
+                match op {
+                    BinaryOp::And | BinaryOp::BitAnd => {
+                        self.ins().i64_and();
+                    }
+                    BinaryOp::Or | BinaryOp::BitOr => {
+                        self.ins().i64_or();
+                    }
+                    BinaryOp::BitXor => {
+                        self.ins().i64_xor();
+                    }
+                    _ => self.compare(op, false),
+                }
+            }
+            Kind::Other => {
+                self.get(lhs)
+                    .get(rhs)
+                    .ins()
+                    .i64_const(b'o' as i64)
+                    .call(EQUAL);
+                if matches!(op, BinaryOp::Ne | BinaryOp::TripleNe) {
+                    self.ins().i64_const(1).i64_xor();
+                }
+            }
+        }
+    }
+
+    /// Compares the two `i64`s on the stack, which are signed when `signed`.
+    fn compare(&mut self, op: BinaryOp, signed: bool) {
+        let mut ins = self.ins();
+        match (op, signed) {
+            (BinaryOp::Eq | BinaryOp::TripleEq, _) => ins.i64_eq(),
+            (BinaryOp::Ne | BinaryOp::TripleNe, _) => ins.i64_ne(),
+            (BinaryOp::Lt, true) => ins.i64_lt_s(),
+            (BinaryOp::Le, true) => ins.i64_le_s(),
+            (BinaryOp::Gt, true) => ins.i64_gt_s(),
+            (_, true) => ins.i64_ge_s(),
+            (BinaryOp::Lt, false) => ins.i64_lt_u(),
+            (BinaryOp::Le, false) => ins.i64_le_u(),
+            (BinaryOp::Gt, false) => ins.i64_gt_u(),
+            (_, false) => ins.i64_ge_u(),
+        };
+        ins.i64_extend_i32_u();
+    }
+
+    fn int_binary(&mut self, op: BinaryOp, ty: IntTy, lhs: Value, rhs: Value) {
+        let signed = ty.is_signed();
+        let temp = self.temp;
+        match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul if ty.bits() < 64 => {
+                // The result of integers this narrow always fits, so it overflowed if it doesn't
+                // fit in their type
+                self.get(lhs).get(rhs);
+                match op {
+                    BinaryOp::Add => self.ins().i64_add(),
+                    BinaryOp::Sub => self.ins().i64_sub(),
+                    _ => self.ins().i64_mul(),
+                };
+                self.ins().local_tee(temp);
+                self.wrap(ty);
+                self.ins().local_get(temp).i64_ne();
+                self.check(OVERFLOW);
+            }
+            BinaryOp::Add if signed => {
+                // The sum of numbers with the same sign has that sign, unless it overflowed
+                self.get(lhs).get(rhs).ins().i64_add().local_set(temp);
+                self.get(lhs).ins().local_get(temp).i64_xor();
+                self.get(rhs).ins().local_get(temp).i64_xor();
+                self.ins().i64_and().i64_const(0).i64_lt_s();
+                self.check(OVERFLOW);
+            }
+            BinaryOp::Sub if signed => {
+                self.get(lhs).get(rhs).ins().i64_sub().local_set(temp);
+                self.get(lhs).get(rhs).ins().i64_xor();
+                self.get(lhs).ins().local_get(temp).i64_xor();
+                self.ins().i64_and().i64_const(0).i64_lt_s();
+                self.check(OVERFLOW);
+            }
+            BinaryOp::Add => {
+                self.get(lhs).get(rhs).ins().i64_add().local_tee(temp);
+                self.get(lhs).ins().i64_lt_u();
+                self.check(OVERFLOW);
+            }
+            BinaryOp::Sub => {
+                self.get(lhs).get(rhs).ins().i64_lt_u();
+                self.check(OVERFLOW);
+                self.get(lhs).get(rhs).ins().i64_sub().local_set(temp);
+            }
+            BinaryOp::Mul => {
+                // The product overflowed if dividing it by one factor doesn't give the other
+                self.get(lhs).get(rhs).ins().i64_mul().local_set(temp);
+                self.get(lhs)
+                    .ins()
+                    .i64_eqz()
+                    .if_(BlockType::Result(ValType::I32))
+                    .i32_const(0)
+                    .else_();
+                if signed {
+                    // Where dividing would overflow too
+                    self.get(lhs)
+                        .ins()
+                        .i64_const(-1)
+                        .i64_eq()
+                        .if_(BlockType::Result(ValType::I32));
+                    self.get(rhs)
+                        .ins()
+                        .i64_const(i64::MIN)
+                        .i64_eq()
+                        .else_()
+                        .local_get(temp);
+                    self.get(lhs).ins().i64_div_s();
+                    self.get(rhs).ins().i64_ne().end();
+                } else {
+                    self.ins().local_get(temp);
+                    self.get(lhs).ins().i64_div_u();
+                    self.get(rhs).ins().i64_ne();
+                }
+                self.ins().end();
+                self.check(OVERFLOW);
+            }
+            BinaryOp::Div | BinaryOp::Rem => {
+                self.get(rhs).ins().i64_eqz();
+                self.check(DIVISION_BY_ZERO);
+                if signed {
+                    // The quotient of the smallest value and -1 is one more than the largest
+                    self.get(lhs).ins().i64_const(ty.min() as i64).i64_eq();
+                    self.get(rhs).ins().i64_const(-1).i64_eq().i32_and();
+                    self.check(OVERFLOW);
+                }
+                // Integers extended from their type divide the same way as in it
+                self.get(lhs).get(rhs);
+                match (op, signed) {
+                    (BinaryOp::Div, true) => self.ins().i64_div_s(),
+                    (BinaryOp::Div, false) => self.ins().i64_div_u(),
+                    (_, true) => self.ins().i64_rem_s(),
+                    (_, false) => self.ins().i64_rem_u(),
+                };
+                return;
+            }
+            BinaryOp::Shl | BinaryOp::Shr => {
+                // A negative amount is a large unsigned one
+                self.get(rhs).ins().i64_const(ty.bits() as i64).i64_ge_u();
+                self.check(SHIFT_OUT_OF_RANGE);
+                self.get(lhs).get(rhs);
+                match op {
+                    BinaryOp::Shl => {
+                        self.ins().i64_shl();
+                        self.wrap(ty);
+                    }
+                    _ if signed => {
+                        self.ins().i64_shr_s();
+                    }
+                    _ => {
+                        self.ins().i64_shr_u();
+                    }
+                }
+                return;
+            }
+            // Integers extended the same way combine bit by bit into one that's extended that way
+            BinaryOp::BitAnd => {
+                self.get(lhs).get(rhs).ins().i64_and();
+                return;
+            }
+            BinaryOp::BitOr => {
+                self.get(lhs).get(rhs).ins().i64_or();
+                return;
+            }
+            BinaryOp::BitXor => {
+                self.get(lhs).get(rhs).ins().i64_xor();
+                return;
+            }
+            _ => {
+                self.get(lhs).get(rhs);
+                self.compare(op, signed);
+                return;
+            }
+        }
+        // The result of arithmetic is left in the temporary local
+        self.ins().local_get(temp);
+    }
+
+    fn cast(&mut self, from: Kind, to: Kind, value: Value) {
+        let temp = self.temp;
+        match (from, to) {
+            (Kind::Int(from), Kind::Float) => {
+                self.get(value);
+                if from.is_signed() {
+                    self.ins().f64_convert_i64_s();
+                } else {
+                    self.ins().f64_convert_i64_u();
+                }
+                self.ins().i64_reinterpret_f64();
+            }
+            (Kind::Float, Kind::Int(to)) => {
+                // Saturating, like `IntTy::from_float`
+                self.get(value).ins().f64_reinterpret_i64();
+                if to.is_signed() {
+                    self.ins().i64_trunc_sat_f64_s();
+                } else {
+                    self.ins().i64_trunc_sat_f64_u();
+                }
+                if to.bits() < 64 {
+                    let (min, max) = (to.min() as i64, to.max() as i64);
+                    self.ins()
+                        .local_tee(temp)
+                        .i64_const(max)
+                        .local_get(temp)
+                        .i64_const(max)
+                        .i64_lt_s()
+                        .select();
+                    if to.is_signed() {
+                        self.ins()
+                            .local_tee(temp)
+                            .i64_const(min)
+                            .local_get(temp)
+                            .i64_const(min)
+                            .i64_gt_s()
+                            .select();
+                    }
+                }
+            }
+            (Kind::Int(_) | Kind::Char | Kind::Bool, Kind::Int(to)) => {
+                self.get(value);
+                self.wrap(to);
+            }
+            (Kind::Int(_), Kind::Char) => {
+                self.get(value);
+                self.wrap(IntTy::U32);
+            }
+            (Kind::Int(_) | Kind::Char | Kind::Bool, Kind::String) => {
+                self.get(value)
+                    .ins()
+                    .i64_const(text_kind(from) as i64)
+                    .call(TO_STRING);
+            }
+            _ => {
+                self.get(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+    use wasi::Host;
+    use wasmtime::{Instance, Store};
+
+    fn instantiate(source: &str) -> (Store<Host>, Instance) {
+        wasi::instantiate(&emit(&built(source)))
+    }
+
+    /// Calls a function the module exports, which returns one value.
+    fn call(store: &mut Store<Host>, instance: &Instance, name: &str, args: &[i64]) -> i64 {
+        let func = instance.get_func(&mut *store, name).unwrap();
+        let args: Vec<_> = args.iter().map(|&arg| arg.into()).collect();
+        let mut results = [0i64.into()];
+        func.call(store, &args, &mut results).unwrap();
+        results[0].unwrap_i64()
+    }
+
+    /// Compiles a program and calls one of its functions.
+    fn run(source: &str, name: &str, args: &[i64]) -> i64 {
+        let (mut store, instance) = instantiate(source);
+        call(&mut store, &instance, name, args)
+    }
+
+    #[test]
+    fn test_recursion() {
+        let source = "fn fib(n: int) int { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }";
+        assert_eq!(run(source, "fib", &[20]), 6765);
+    }
+
+    #[test]
+    fn test_closures_and_enums() {
+        let source = "
+enum Shape { Circle(float), Square(int) }
+fn area(shape: Shape) int {
+    match shape {
+        Shape::Circle(r) => (r * r * 3.0) as int,
+        Shape::Square(side) => side * side,
+    }
+}
+fn shapes(n: int) int {
+    let add = |shape: Shape| area(shape) + n;
+    add(Shape::Circle(2.0)) + add(Shape::Square(3))
+}";
+        assert_eq!(run(source, "shapes", &[1]), 23);
+    }
+
+    #[test]
+    fn test_bits() {
+        let source = "fn bits(x: u8) u8 { !x >> 1 }";
+        assert_eq!(run(source, "bits", &[0b1010]), 0b0111_1010);
+    }
+
+    #[test]
+    fn test_strings() {
+        let source = "
+fn letters() int {
+    let mut n = 0;
+    for c in \"héllo\" + \"!\" { if c != 'l' { n += 1; } }
+    n
+}
+fn same() bool { (1, \"a\" + \"b\") == (1, \"ab\") }";
+        assert_eq!(run(source, "letters", &[]), 4);
+        assert_eq!(run(source, "same", &[]), 1);
+    }
+
+    #[test]
+    fn test_string_methods() {
+        let source = "
+fn words() int {
+    let s = \"Héllo, wörld, foo\";
+    let mut n = 0;
+    for w in s.split(\", \") { if w.to_upper().contains(\"WöR\") { n += w.len(); } }
+    if s[1] == 'é' && s[7..=11] == \"wörld\" && s[12..].to_upper() == \", FOO\" { n + 100 } else { n }
+}";
+        assert_eq!(run(source, "words", &[]), 105);
+    }
+
+    #[test]
+    fn test_input() {
+        let source = "
+fn echo() int {
+    let line = input();
+    print(\"got \");
+    println(line);
+    let mut n = 0;
+    for _ in line { n += 1; }
+    n
+}";
+        let (mut store, instance) = instantiate(source);
+        store.data_mut().stdin = "héllo wörld\nrest".bytes().collect();
+        assert_eq!(call(&mut store, &instance, "echo", &[]), 11);
+        assert_eq!(call(&mut store, &instance, "echo", &[]), 4);
+        assert_eq!(call(&mut store, &instance, "echo", &[]), 0);
+        assert_eq!(
+            String::from_utf8_lossy(&store.data().stdout),
+            "got héllo wörld\ngot rest\ngot \n"
+        );
+    }
+
+    #[test]
+    fn test_panic() {
+        let source = "
+static LIMIT: u8 = 200;
+fn main() {
+    let mut i: u8 = 0;
+    while true { i = i + LIMIT; }
+}";
+        let (mut store, instance) = instantiate(source);
+        let start = instance
+            .get_typed_func::<(), ()>(&mut store, "_start")
+            .unwrap();
+        assert!(start.call(&mut store, ()).is_err());
+        assert_eq!(store.data().exit, Some(101));
+        assert_eq!(
+            String::from_utf8_lossy(&store.data().stderr),
+            "panicked: arithmetic overflow\n"
+        );
+    }
+}
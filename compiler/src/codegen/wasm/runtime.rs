@@ -0,0 +1,2333 @@
+//! The runtime library of a WebAssembly module, written in WebAssembly so that the module only
+//! has to import what it needs from WASI to read and write lines and files and to report a panic.
+//! It works like `runtime/runtime.c`, except that objects are never freed, so memory past the heap
+//! is always zeroed, that a map has no table of hashes, so finding a key compares it with each key
+//! in turn, that `pow` is computed from multiplications and square roots, so it can be off in the
+//! last digits, and that a file that's read isn't checked to be UTF-8. Files are found from the
+//! first directory the host preopens.
+
+use wasm_encoder::{BlockType, Function, Ieee64, MemArg, ValType};
+
+use super::{Data, Types};
+use crate::codegen::runtime::{ARRAY, FUNCTION, HEADER, MAP, STRING};
+
+const I32: ValType = ValType::I32;
+const I64: ValType = ValType::I64;
+const F64: ValType = ValType::F64;
+
+/// The functions imported from WASI.
+pub const FD_WRITE: u32 = 0;
+pub const PROC_EXIT: u32 = 1;
+pub const FD_READ: u32 = 2;
+pub const PATH_OPEN: u32 = 3;
+pub const PATH_FILESTAT_GET: u32 = 4;
+pub const FD_CLOSE: u32 = 5;
+
+/// The functions of the library, which come after the imports.
+pub const ALLOCATE: u32 = 6;
+pub const ALLOC: u32 = 7;
+pub const CONCAT: u32 = 8;
+pub const COMPARE_STRINGS: u32 = 9;
+pub const FLOAT_REM: u32 = 10;
+pub const EQUAL: u32 = 11;
+pub const SET_FIELD: u32 = 12;
+pub const LEN: u32 = 13;
+pub const INDEX: u32 = 14;
+pub const SET_INDEX: u32 = 15;
+pub const CHARS: u32 = 16;
+pub const PANIC: u32 = 17;
+pub const PRINT: u32 = 18;
+pub const PRINTLN: u32 = 19;
+pub const INPUT: u32 = 20;
+pub const STRING_LEN: u32 = 21;
+pub const CHAR_OFFSET: u32 = 22;
+pub const SUBSTRING: u32 = 23;
+pub const CHAR_AT: u32 = 24;
+pub const SLICE: u32 = 25;
+pub const OCCURS_AT: u32 = 26;
+pub const CONTAINS: u32 = 27;
+pub const SPLIT: u32 = 28;
+pub const TO_UPPER: u32 = 29;
+pub const TO_LOWER: u32 = 30;
+pub const PUSH: u32 = 31;
+pub const POP: u32 = 32;
+pub const MAP_FIND: u32 = 33;
+pub const MAP_INSERT: u32 = 34;
+pub const MAP_REMOVE: u32 = 35;
+pub const MAP_KEY: u32 = 36;
+pub const MAP_VALUE: u32 = 37;
+pub const MAP_EQUAL: u32 = 38;
+pub const POW: u32 = 39;
+pub const READ_FILE: u32 = 40;
+pub const WRITE_FILE: u32 = 41;
+pub const FILE_EXISTS: u32 = 42;
+pub const FS_ERROR: u32 = 43;
+pub const SET_FS_ERROR: u32 = 44;
+pub const TO_STRING: u32 = 45;
+
+/// The index of the first function after the library.
+pub const END: u32 = 46;
+
+/// The global holding the address of the end of the heap, where the next object goes.
+pub const HEAP: u32 = 0;
+
+/// Returns the types of the imports, in order.
+pub fn imports(types: &mut Types) -> [(&'static str, u32); 6] {
+    let path_open = [I32, I32, I32, I32, I32, I64, I64, I32, I32];
+    [
+        ("fd_write", types.func(&[I32; 4], &[I32])),
+        ("proc_exit", types.func(&[I32], &[])),
+        ("fd_read", types.func(&[I32; 4], &[I32])),
+        ("path_open", types.func(&path_open, &[I32])),
+        ("path_filestat_get", types.func(&[I32; 5], &[I32])),
+        ("fd_close", types.func(&[I32], &[I32])),
+    ]
+}
+
+/// Returns the type and the code of each function of the library, in order.
+pub fn functions(types: &mut Types, data: &mut Data) -> Vec<(u32, Function)> {
+    // Where the message of the error that the last file operation failed with is kept
+    let empty = data.string("");
+    let error = data.scratch(&empty.to_le_bytes()) as i32;
+    vec![
+        (types.values(1), allocate(data)),
+        (types.values(4), alloc()),
+        (types.values(2), concat()),
+        (types.values(2), compare_strings()),
+        (types.values(2), float_rem()),
+        (types.values(3), equal()),
+        (types.values(3), set_field()),
+        (types.values(1), len()),
+        (types.values(2), index(data)),
+        (types.values(3), set_index(data)),
+        (types.values(1), chars(data)),
+        (types.func(&[I64], &[]), panic(data)),
+        (types.func(&[I64], &[]), print(data, false)),
+        (types.func(&[I64], &[]), print(data, true)),
+        (types.values(0), input(data)),
+        (types.values(1), string_len()),
+        (types.values(2), char_offset(data)),
+        (types.values(3), substring()),
+        (types.values(2), char_at(data)),
+        (types.values(2), slice(data)),
+        (types.values(3), occurs_at()),
+        (types.values(2), contains()),
+        (types.values(2), split(data)),
+        (types.values(1), change_case(true)),
+        (types.values(1), change_case(false)),
+        (types.values(2), push()),
+        (types.values(1), pop()),
+        (types.values(2), map_find()),
+        (types.values(3), map_insert()),
+        (types.values(2), map_remove(data)),
+        (types.values(2), map_entry(data, false)),
+        (types.values(2), map_entry(data, true)),
+        (types.values(2), map_equal()),
+        (types.values(2), pow()),
+        (types.values(1), read_file(data)),
+        (types.values(2), write_file(data)),
+        (types.values(1), file_exists(data)),
+        (types.values(0), fs_error(error)),
+        (types.func(&[I32], &[]), set_fs_error(data, error)),
+        (types.values(2), to_string(data)),
+    ]
+}
+
+fn mem(offset: usize, align: u32) -> MemArg {
+    MemArg {
+        offset: offset as u64,
+        align,
+        memory_index: 0,
+    }
+}
+
+/// The offset of a slot of an object.
+fn slot(index: usize) -> MemArg {
+    mem(HEADER + 8 * index, 3)
+}
+
+/// `allocate(size)` returns the address of `size` zeroed bytes, growing the memory if it has to.
+fn allocate(data: &mut Data) -> Function {
+    let (size, result, end) = (0, 1, 2);
+    let out_of_memory = data.string("out of memory");
+    let mut f = Function::new([(2, I64)]);
+    f.instructions()
+        .global_get(HEAP)
+        .i64_extend_i32_u()
+        .local_tee(result)
+        .local_get(size)
+        .i64_add()
+        // Every object starts at a multiple of 8
+        .i64_const(7)
+        .i64_add()
+        .i64_const(-8)
+        .i64_and()
+        .local_tee(end)
+        .i64_const(1 << 32)
+        .i64_ge_u()
+        .if_(BlockType::Empty)
+        .i64_const(out_of_memory)
+        .call(PANIC)
+        .end()
+        .local_get(end)
+        .memory_size(0)
+        .i64_extend_i32_u()
+        .i64_const(16)
+        .i64_shl()
+        .i64_gt_u()
+        .if_(BlockType::Empty)
+        // Enough 64 KiB pages for the rest
+        .local_get(end)
+        .memory_size(0)
+        .i64_extend_i32_u()
+        .i64_const(16)
+        .i64_shl()
+        .i64_sub()
+        .i64_const(0xffff)
+        .i64_add()
+        .i64_const(16)
+        .i64_shr_u()
+        .i32_wrap_i64()
+        .memory_grow(0)
+        .i32_const(-1)
+        .i32_eq()
+        .if_(BlockType::Empty)
+        .i64_const(out_of_memory)
+        .call(PANIC)
+        .end()
+        .end()
+        .local_get(end)
+        .i32_wrap_i64()
+        .global_set(HEAP)
+        .local_get(result)
+        .end();
+    f
+}
+
+/// `alloc(kind, tag, len, shape)` returns a new object with `len` slots.
+fn alloc() -> Function {
+    let (kind, tag, len, shape, object) = (0, 1, 2, 3, 4);
+    let mut f = Function::new([(1, I64)]);
+    f.instructions()
+        .i64_const(HEADER as i64)
+        .local_get(len)
+        .i64_const(3)
+        .i64_shl()
+        .i64_add()
+        .call(ALLOCATE)
+        .local_tee(object)
+        .i32_wrap_i64()
+        .local_get(kind)
+        .i64_store32(mem(0, 2))
+        .local_get(object)
+        .i32_wrap_i64()
+        .local_get(tag)
+        .i64_store32(mem(4, 2))
+        .local_get(object)
+        .i32_wrap_i64()
+        .local_get(len)
+        .i64_store(mem(8, 3))
+        .local_get(object)
+        .i32_wrap_i64()
+        .local_get(shape)
+        .i64_store(mem(16, 3))
+        .local_get(object)
+        .end();
+    f
+}
+
+/// `concat(lhs, rhs)` returns a new string of the bytes of both.
+fn concat() -> Function {
+    let (lhs, rhs, lhs_len, rhs_len, result) = (0, 1, 2, 3, 4);
+    let mut f = Function::new([(3, I64)]);
+    f.instructions()
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(lhs_len)
+        .local_get(rhs)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(rhs_len)
+        .i64_const(HEADER as i64)
+        .local_get(lhs_len)
+        .i64_add()
+        .local_get(rhs_len)
+        .i64_add()
+        .call(ALLOCATE)
+        .local_tee(result)
+        .i32_wrap_i64()
+        .i64_const(STRING as i64)
+        .i64_store32(mem(0, 2))
+        .local_get(result)
+        .i32_wrap_i64()
+        .local_get(lhs_len)
+        .local_get(rhs_len)
+        .i64_add()
+        .i64_store(mem(8, 3))
+        .local_get(result)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(lhs)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(lhs_len)
+        .i32_wrap_i64()
+        .memory_copy(0, 0)
+        .local_get(result)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .local_get(lhs_len)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(rhs)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(rhs_len)
+        .i32_wrap_i64()
+        .memory_copy(0, 0)
+        .local_get(result)
+        .end();
+    f
+}
+
+/// `compare_strings(lhs, rhs)` compares strings by their bytes, returning -1, 0 or 1.
+fn compare_strings() -> Function {
+    let (lhs, rhs, lhs_len, rhs_len, i, a, b) = (0, 1, 2, 3, 4, 5, 6);
+    let mut f = Function::new([(5, I64)]);
+    f.instructions()
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(lhs_len)
+        .local_get(rhs)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(rhs_len)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(i)
+        .local_get(lhs_len)
+        .i64_ge_u()
+        .br_if(1)
+        .local_get(i)
+        .local_get(rhs_len)
+        .i64_ge_u()
+        .br_if(1)
+        .local_get(lhs)
+        .local_get(i)
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load8_u(mem(HEADER, 0))
+        .local_set(a)
+        .local_get(rhs)
+        .local_get(i)
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load8_u(mem(HEADER, 0))
+        .local_set(b)
+        .local_get(a)
+        .local_get(b)
+        .i64_ne()
+        .if_(BlockType::Empty)
+        .i64_const(-1)
+        .i64_const(1)
+        .local_get(a)
+        .local_get(b)
+        .i64_lt_u()
+        .select()
+        .return_()
+        .end()
+        .local_get(i)
+        .i64_const(1)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        // A string that starts with another comes after it
+        .local_get(lhs_len)
+        .local_get(rhs_len)
+        .i64_gt_s()
+        .local_get(lhs_len)
+        .local_get(rhs_len)
+        .i64_lt_s()
+        .i32_sub()
+        .i64_extend_i32_s()
+        .end();
+    f
+}
+
+/// `float_rem(lhs, rhs)` returns the remainder of dividing floats, from the truncated quotient,
+/// which can be off when the quotient is too large for a float to hold exactly.
+fn float_rem() -> Function {
+    let (lhs, rhs) = (0, 1);
+    let mut f = Function::new([]);
+    f.instructions()
+        // The remainder of a finite number and an infinity is the number
+        .local_get(rhs)
+        .f64_reinterpret_i64()
+        .f64_abs()
+        .f64_const(Ieee64::from(f64::INFINITY))
+        .f64_eq()
+        .local_get(lhs)
+        .f64_reinterpret_i64()
+        .f64_abs()
+        .f64_const(Ieee64::from(f64::INFINITY))
+        .f64_lt()
+        .i32_and()
+        .if_(BlockType::Result(I64))
+        .local_get(lhs)
+        .else_()
+        .local_get(lhs)
+        .f64_reinterpret_i64()
+        .local_get(lhs)
+        .f64_reinterpret_i64()
+        .local_get(rhs)
+        .f64_reinterpret_i64()
+        .f64_div()
+        .f64_trunc()
+        .local_get(rhs)
+        .f64_reinterpret_i64()
+        .f64_mul()
+        .f64_sub()
+        // The remainder has the sign of the dividend, even when it's 0
+        .local_get(lhs)
+        .f64_reinterpret_i64()
+        .f64_copysign()
+        .i64_reinterpret_f64()
+        .end()
+        .end();
+    f
+}
+
+/// `equal(lhs, rhs, kind)` returns whether two values are equal, where `kind` is the letter of
+/// their shape.
+fn equal() -> Function {
+    let (lhs, rhs, kind, i, len, letter) = (0, 1, 2, 3, 4, 5);
+    let mut f = Function::new([(3, I64)]);
+    f.instructions()
+        .local_get(kind)
+        .i64_const(b'f' as i64)
+        .i64_eq()
+        .if_(BlockType::Empty)
+        .local_get(lhs)
+        .f64_reinterpret_i64()
+        .local_get(rhs)
+        .f64_reinterpret_i64()
+        .f64_eq()
+        .i64_extend_i32_u()
+        .return_()
+        .end()
+        .local_get(kind)
+        .i64_const(b'o' as i64)
+        .i64_ne()
+        .local_get(lhs)
+        .local_get(rhs)
+        .i64_eq()
+        .i32_or()
+        .if_(BlockType::Empty)
+        .local_get(lhs)
+        .local_get(rhs)
+        .i64_eq()
+        .i64_extend_i32_u()
+        .return_()
+        .end()
+        // Objects of different kinds, tags or lengths, or `()` and something else
+        .local_get(lhs)
+        .i64_eqz()
+        .local_get(rhs)
+        .i64_eqz()
+        .i32_or()
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i64_load(mem(0, 3))
+        .local_get(rhs)
+        .i32_wrap_i64()
+        .i64_load(mem(0, 3))
+        .i64_ne()
+        .i32_or()
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_tee(len)
+        .local_get(rhs)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_ne()
+        .i32_or()
+        .if_(BlockType::Empty)
+        .i64_const(0)
+        .return_()
+        .end()
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i32_load(mem(0, 2))
+        .i32_const(STRING as i32)
+        .i32_eq()
+        .if_(BlockType::Empty)
+        .local_get(lhs)
+        .local_get(rhs)
+        .call(COMPARE_STRINGS)
+        .i64_eqz()
+        .i64_extend_i32_u()
+        .return_()
+        .end()
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i32_load(mem(0, 2))
+        .i32_const(FUNCTION as i32)
+        .i32_eq()
+        .if_(BlockType::Empty)
+        .i64_const(0)
+        .return_()
+        .end()
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i32_load(mem(0, 2))
+        .i32_const(MAP as i32)
+        .i32_eq()
+        .if_(BlockType::Empty)
+        .local_get(lhs)
+        .local_get(rhs)
+        .call(MAP_EQUAL)
+        .return_()
+        .end()
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(i)
+        .local_get(len)
+        .i64_ge_u()
+        .br_if(1)
+        // An array has one letter for all of its elements
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i64_load(mem(16, 3))
+        .i64_const(0)
+        .local_get(i)
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i32_load(mem(0, 2))
+        .i32_const(ARRAY as i32)
+        .i32_eq()
+        .select()
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load8_u(mem(0, 0))
+        .local_set(letter)
+        .local_get(lhs)
+        .local_get(i)
+        .i64_const(3)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load(slot(0))
+        .local_get(rhs)
+        .local_get(i)
+        .i64_const(3)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load(slot(0))
+        .local_get(letter)
+        .call(EQUAL)
+        .i64_eqz()
+        .if_(BlockType::Empty)
+        .i64_const(0)
+        .return_()
+        .end()
+        .local_get(i)
+        .i64_const(1)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        .i64_const(1)
+        .end();
+    f
+}
+
+/// `set_field(base, index, value)` returns a copy of an object with a slot set.
+fn set_field() -> Function {
+    let (base, index, value, size, result) = (0, 1, 2, 3, 4);
+    let mut f = Function::new([(2, I64)]);
+    f.instructions()
+        .i64_const(HEADER as i64)
+        .local_get(base)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_const(3)
+        .i64_shl()
+        .i64_add()
+        .local_tee(size)
+        .call(ALLOCATE)
+        .local_tee(result)
+        .i32_wrap_i64()
+        .local_get(base)
+        .i32_wrap_i64()
+        .local_get(size)
+        .i32_wrap_i64()
+        .memory_copy(0, 0)
+        .local_get(result)
+        .local_get(index)
+        .i64_const(3)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(value)
+        .i64_store(slot(0))
+        .local_get(result)
+        .end();
+    f
+}
+
+/// `len(array)` returns the length of an array.
+fn len() -> Function {
+    let mut f = Function::new([]);
+    f.instructions()
+        .local_get(0)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .end();
+    f
+}
+
+/// Panics unless the local `index` is an index of the local `array`.
+fn check_index(f: &mut Function, data: &mut Data, array: u32, index: u32) {
+    let out_of_bounds = data.string("index out of bounds");
+    // A negative index is a large unsigned one
+    f.instructions()
+        .local_get(index)
+        .local_get(array)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_ge_u()
+        .if_(BlockType::Empty)
+        .i64_const(out_of_bounds)
+        .call(PANIC)
+        .end();
+}
+
+/// `index(array, index)` returns an element of an array.
+fn index(data: &mut Data) -> Function {
+    let (array, index) = (0, 1);
+    let mut f = Function::new([]);
+    check_index(&mut f, data, array, index);
+    f.instructions()
+        .local_get(array)
+        .local_get(index)
+        .i64_const(3)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load(slot(0))
+        .end();
+    f
+}
+
+/// `set_index(array, index, value)` returns a copy of an array with an element set.
+fn set_index(data: &mut Data) -> Function {
+    let (array, index, value) = (0, 1, 2);
+    let mut f = Function::new([]);
+    check_index(&mut f, data, array, index);
+    f.instructions()
+        .local_get(array)
+        .local_get(index)
+        .local_get(value)
+        .call(SET_FIELD)
+        .end();
+    f
+}
+
+/// `chars(string)` decodes the UTF-8 of a string into an array of its characters.
+fn chars(data: &mut Data) -> Function {
+    let (string, len, i, result, byte, c, width, j, k) = (0, 1, 2, 3, 4, 5, 6, 7, 8);
+    let shape = data.bytes(b"i\0");
+    let mut f = Function::new([(8, I64)]);
+    f.instructions()
+        .local_get(string)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(len)
+        .i64_const(ARRAY as i64)
+        .i64_const(0)
+        .local_get(string)
+        .call(STRING_LEN)
+        .i64_const(shape)
+        .call(ALLOC)
+        .local_set(result)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(i)
+        .local_get(len)
+        .i64_ge_u()
+        .br_if(1);
+    decode(&mut f, string, i, [byte, width, c, j]);
+    f.instructions()
+        .local_get(result)
+        .local_get(k)
+        .i64_const(3)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(c)
+        .i64_store(slot(0))
+        .local_get(k)
+        .i64_const(1)
+        .i64_add()
+        .local_set(k)
+        .local_get(i)
+        .local_get(width)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        .local_get(result)
+        .end();
+    f
+}
+
+/// Decodes the character that starts at the offset in the local `i` of the local `string`, into
+/// the local `c`, with the number of bytes it takes in the local `width`. The locals `byte` and
+/// `j` are its own.
+fn decode(f: &mut Function, string: u32, i: u32, [byte, width, c, j]: [u32; 4]) {
+    f.instructions()
+        .local_get(string)
+        .local_get(i)
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load8_u(mem(HEADER, 0))
+        .local_set(byte)
+        // How many bytes the character takes, from its first
+        .i64_const(1)
+        .i64_const(2)
+        .i64_const(3)
+        .i64_const(4)
+        .local_get(byte)
+        .i64_const(0xf0)
+        .i64_lt_u()
+        .select()
+        .local_get(byte)
+        .i64_const(0xe0)
+        .i64_lt_u()
+        .select()
+        .local_get(byte)
+        .i64_const(0x80)
+        .i64_lt_u()
+        .select()
+        .local_set(width)
+        .local_get(byte)
+        .local_get(byte)
+        .i64_const(0x7f)
+        .local_get(width)
+        .i64_shr_u()
+        .i64_and()
+        .local_get(width)
+        .i64_const(1)
+        .i64_eq()
+        .select()
+        .local_set(c)
+        .i64_const(1)
+        .local_set(j)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(j)
+        .local_get(width)
+        .i64_ge_u()
+        .br_if(1)
+        .local_get(c)
+        .i64_const(6)
+        .i64_shl()
+        .local_get(string)
+        .local_get(i)
+        .i64_add()
+        .local_get(j)
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load8_u(mem(HEADER, 0))
+        .i64_const(0x3f)
+        .i64_and()
+        .i64_or()
+        .local_set(c)
+        .local_get(j)
+        .i64_const(1)
+        .i64_add()
+        .local_set(j)
+        .br(0)
+        .end()
+        .end();
+}
+
+/// `panic(message)` prints a string to stderr after `panicked: ` and exits with code 101.
+fn panic(data: &mut Data) -> Function {
+    let message = 0;
+    let prefix = data.bytes(b"panicked: ") as u32;
+    let newline = data.bytes(b"\n") as u32;
+    // The pieces to write, as WASI's `ciovec`s, with the message's filled in when it's known
+    let mut pieces = Vec::new();
+    for word in [prefix, 10, 0, 0, newline, 1] {
+        pieces.extend(word.to_le_bytes());
+    }
+    let pieces = data.scratch(&pieces) as i32;
+    let written = data.scratch(&[0; 4]) as i32;
+    let mut f = Function::new([]);
+    f.instructions()
+        .i32_const(pieces)
+        .local_get(message)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .i32_store(mem(8, 2))
+        .i32_const(pieces)
+        .local_get(message)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i32_wrap_i64()
+        .i32_store(mem(12, 2))
+        .i32_const(2)
+        .i32_const(pieces)
+        .i32_const(3)
+        .i32_const(written)
+        .call(FD_WRITE)
+        .drop()
+        .i32_const(101)
+        .call(PROC_EXIT)
+        .unreachable()
+        .end();
+    f
+}
+
+/// `print(text)` writes a string to the standard output, followed by a newline for `println`.
+fn print(data: &mut Data, newline: bool) -> Function {
+    let text = 0;
+    let end = data.bytes(b"\n") as u32;
+    // The string's piece is filled in when it's known
+    let mut pieces = Vec::new();
+    for word in [0, 0, end, 1] {
+        pieces.extend(word.to_le_bytes());
+    }
+    let pieces = data.scratch(&pieces) as i32;
+    let written = data.scratch(&[0; 4]) as i32;
+    let mut f = Function::new([]);
+    f.instructions()
+        .i32_const(pieces)
+        .local_get(text)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .i32_store(mem(0, 2))
+        .i32_const(pieces)
+        .local_get(text)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i32_wrap_i64()
+        .i32_store(mem(4, 2))
+        .i32_const(1)
+        .i32_const(pieces)
+        .i32_const(1 + newline as i32)
+        .i32_const(written)
+        .call(FD_WRITE)
+        .drop()
+        .end();
+    f
+}
+
+/// `input()` reads a line from the standard input, without its newline, which is empty at the end
+/// of the input. Its bytes are read one at a time into the string, which the heap grows under.
+fn input(data: &mut Data) -> Function {
+    let (result, len) = (0, 1);
+    let piece = data.scratch(&[0; 8]) as i32;
+    let read = data.scratch(&[0; 4]) as i32;
+    let mut f = Function::new([(2, I64)]);
+    f.instructions()
+        .i64_const(HEADER as i64)
+        .call(ALLOCATE)
+        .local_tee(result)
+        .i32_wrap_i64()
+        .i64_const(STRING as i64)
+        .i64_store32(mem(0, 2))
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        // The next byte goes at the end of the heap once the string fills what it has
+        .local_get(result)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .local_get(len)
+        .i64_add()
+        .global_get(HEAP)
+        .i64_extend_i32_u()
+        .i64_eq()
+        .if_(BlockType::Empty)
+        .i64_const(8)
+        .call(ALLOCATE)
+        .drop()
+        .end()
+        .i32_const(piece)
+        .local_get(result)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .local_get(len)
+        .i64_add()
+        .i32_wrap_i64()
+        .i32_store(mem(0, 2))
+        .i32_const(piece)
+        .i32_const(1)
+        .i32_store(mem(4, 2))
+        .i32_const(0)
+        .i32_const(piece)
+        .i32_const(1)
+        .i32_const(read)
+        .call(FD_READ)
+        .br_if(1)
+        .i32_const(read)
+        .i32_load(mem(0, 2))
+        .i32_eqz()
+        .br_if(1)
+        .local_get(result)
+        .local_get(len)
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load8_u(mem(HEADER, 0))
+        .i64_const(b'\n' as i64)
+        .i64_eq()
+        .br_if(1)
+        .local_get(len)
+        .i64_const(1)
+        .i64_add()
+        .local_set(len)
+        .br(0)
+        .end()
+        .end()
+        .local_get(result)
+        .i32_wrap_i64()
+        .local_get(len)
+        .i64_store(mem(8, 3))
+        .local_get(result)
+        .end();
+    f
+}
+
+/// Pushes the byte at the offset in the local `i` of the bytes of the string in the local
+/// `string`.
+fn load_byte(f: &mut Function, string: u32, i: u32) {
+    f.instructions()
+        .local_get(string)
+        .local_get(i)
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load8_u(mem(HEADER, 0));
+}
+
+/// `string_len(string)` returns the number of characters of a string, which is how strings are
+/// indexed and sliced.
+fn string_len() -> Function {
+    let (string, len, i, count) = (0, 1, 2, 3);
+    let mut f = Function::new([(3, I64)]);
+    f.instructions()
+        .local_get(string)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(len)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(i)
+        .local_get(len)
+        .i64_ge_u()
+        .br_if(1);
+    // Every byte but the ones that continue a character starts one
+    load_byte(&mut f, string, i);
+    f.instructions()
+        .i64_const(0xc0)
+        .i64_and()
+        .i64_const(0x80)
+        .i64_ne()
+        .i64_extend_i32_u()
+        .local_get(count)
+        .i64_add()
+        .local_set(count)
+        .local_get(i)
+        .i64_const(1)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        .local_get(count)
+        .end();
+    f
+}
+
+/// `char_offset(string, index)` returns the offset of the byte that starts a character of a
+/// string, or of its end for the index after its last character.
+fn char_offset(data: &mut Data) -> Function {
+    let (string, index, len, i, count) = (0, 1, 2, 3, 4);
+    let out_of_bounds = data.string("index out of bounds");
+    let mut f = Function::new([(3, I64)]);
+    f.instructions()
+        .local_get(string)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(len)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(i)
+        .local_get(len)
+        .i64_ge_u()
+        .br_if(1);
+    load_byte(&mut f, string, i);
+    f.instructions()
+        .i64_const(0xc0)
+        .i64_and()
+        .i64_const(0x80)
+        .i64_ne()
+        .if_(BlockType::Empty)
+        .local_get(count)
+        .local_get(index)
+        .i64_eq()
+        .if_(BlockType::Empty)
+        .local_get(i)
+        .return_()
+        .end()
+        .local_get(count)
+        .i64_const(1)
+        .i64_add()
+        .local_set(count)
+        .end()
+        .local_get(i)
+        .i64_const(1)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        .local_get(count)
+        .local_get(index)
+        .i64_eq()
+        .if_(BlockType::Empty)
+        .local_get(len)
+        .return_()
+        .end()
+        .i64_const(out_of_bounds)
+        .call(PANIC)
+        .unreachable()
+        .end();
+    f
+}
+
+/// `substring(string, from, to)` returns a new string of the bytes of a string from one offset up
+/// to another.
+fn substring() -> Function {
+    let (string, from, to, result) = (0, 1, 2, 3);
+    let mut f = Function::new([(1, I64)]);
+    f.instructions()
+        .i64_const(HEADER as i64)
+        .local_get(to)
+        .i64_add()
+        .local_get(from)
+        .i64_sub()
+        .call(ALLOCATE)
+        .local_tee(result)
+        .i32_wrap_i64()
+        .i64_const(STRING as i64)
+        .i64_store32(mem(0, 2))
+        .local_get(result)
+        .i32_wrap_i64()
+        .local_get(to)
+        .local_get(from)
+        .i64_sub()
+        .i64_store(mem(8, 3))
+        .local_get(result)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(string)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .local_get(from)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(to)
+        .local_get(from)
+        .i64_sub()
+        .i32_wrap_i64()
+        .memory_copy(0, 0)
+        .local_get(result)
+        .end();
+    f
+}
+
+/// `char_at(string, index)` returns a character of a string.
+fn char_at(data: &mut Data) -> Function {
+    let (string, index, i, byte, width, c, j) = (0, 1, 2, 3, 4, 5, 6);
+    let out_of_bounds = data.string("index out of bounds");
+    let mut f = Function::new([(5, I64)]);
+    f.instructions()
+        .local_get(string)
+        .local_get(index)
+        .call(CHAR_OFFSET)
+        .local_tee(i)
+        // The index after the last character has an offset, but no character
+        .local_get(string)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_eq()
+        .if_(BlockType::Empty)
+        .i64_const(out_of_bounds)
+        .call(PANIC)
+        .end();
+    decode(&mut f, string, i, [byte, width, c, j]);
+    f.instructions().local_get(c).end();
+    f
+}
+
+/// `slice(string, range)` returns the characters of a string in a range, whose tag says which
+/// bounds it has.
+fn slice(data: &mut Data) -> Function {
+    let (string, range, tag, start, end) = (0, 1, 2, 3, 4);
+    let out_of_bounds = data.string("index out of bounds");
+    let mut f = Function::new([(3, I64)]);
+    f.instructions()
+        .local_get(range)
+        .i32_wrap_i64()
+        .i64_load32_u(mem(4, 2))
+        .local_set(tag)
+        // A missing start is 0 in the range too
+        .local_get(range)
+        .i32_wrap_i64()
+        .i64_load(slot(0))
+        .local_set(start)
+        .local_get(tag)
+        .i64_const(2)
+        .i64_and()
+        .i32_wrap_i64()
+        .if_(BlockType::Result(I64))
+        .local_get(range)
+        .i32_wrap_i64()
+        .i64_load(slot(1))
+        .local_get(tag)
+        .i64_const(2)
+        .i64_shr_u()
+        .i64_add()
+        .else_()
+        .local_get(string)
+        .call(STRING_LEN)
+        .end()
+        .local_tee(end)
+        .local_get(start)
+        .i64_lt_s()
+        .if_(BlockType::Empty)
+        .i64_const(out_of_bounds)
+        .call(PANIC)
+        .end()
+        .local_get(string)
+        .local_get(string)
+        .local_get(start)
+        .call(CHAR_OFFSET)
+        .local_get(string)
+        .local_get(end)
+        .call(CHAR_OFFSET)
+        .call(SUBSTRING)
+        .end();
+    f
+}
+
+/// `occurs_at(string, pattern, at)` returns whether a pattern occurs in a string at an offset.
+fn occurs_at() -> Function {
+    let (string, pattern, at, len, j) = (0, 1, 2, 3, 4);
+    let mut f = Function::new([(2, I64)]);
+    f.instructions()
+        .local_get(pattern)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_tee(len)
+        .local_get(at)
+        .i64_add()
+        .local_get(string)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_gt_u()
+        .if_(BlockType::Empty)
+        .i64_const(0)
+        .return_()
+        .end()
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(j)
+        .local_get(len)
+        .i64_ge_u()
+        .br_if(1)
+        .local_get(string)
+        .local_get(at)
+        .i64_add()
+        .local_get(j)
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load8_u(mem(HEADER, 0));
+    load_byte(&mut f, pattern, j);
+    f.instructions()
+        .i64_ne()
+        .if_(BlockType::Empty)
+        .i64_const(0)
+        .return_()
+        .end()
+        .local_get(j)
+        .i64_const(1)
+        .i64_add()
+        .local_set(j)
+        .br(0)
+        .end()
+        .end()
+        .i64_const(1)
+        .end();
+    f
+}
+
+/// `contains(string, pattern)` returns whether a pattern occurs anywhere in a string.
+fn contains() -> Function {
+    let (string, pattern, i) = (0, 1, 2);
+    let mut f = Function::new([(1, I64)]);
+    f.instructions()
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(i)
+        .local_get(string)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_gt_u()
+        .br_if(1)
+        .local_get(string)
+        .local_get(pattern)
+        .local_get(i)
+        .call(OCCURS_AT)
+        .i32_wrap_i64()
+        .if_(BlockType::Empty)
+        .i64_const(1)
+        .return_()
+        .end()
+        .local_get(i)
+        .i64_const(1)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        .i64_const(0)
+        .end();
+    f
+}
+
+/// `split(string, separator)` returns the parts of a string between each occurrence of a
+/// separator, which can't be empty.
+fn split(data: &mut Data) -> Function {
+    let (string, separator, len, count, i, start, result) = (0, 1, 2, 3, 4, 5, 6);
+    let empty_separator = data.string("empty separator");
+    let shape = data.bytes(b"o\0");
+    let mut f = Function::new([(5, I64)]);
+    f.instructions()
+        .local_get(separator)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_eqz()
+        .if_(BlockType::Empty)
+        .i64_const(empty_separator)
+        .call(PANIC)
+        .end()
+        .local_get(string)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(len);
+    // The parts are counted, then made, finding the separators the same way both times. Each part
+    // is added at `count` when they're made.
+    for making in [false, true] {
+        f.instructions()
+            .i64_const(0)
+            .local_set(i)
+            .block(BlockType::Empty)
+            .loop_(BlockType::Empty)
+            .local_get(i)
+            .local_get(len)
+            .i64_ge_u()
+            .br_if(1)
+            .local_get(string)
+            .local_get(separator)
+            .local_get(i)
+            .call(OCCURS_AT)
+            .i32_wrap_i64()
+            .if_(BlockType::Empty);
+        if making {
+            add_part(&mut f, [string, result, count, start], i);
+            f.instructions()
+                .local_get(i)
+                .local_get(separator)
+                .i32_wrap_i64()
+                .i64_load(mem(8, 3))
+                .i64_add()
+                .local_tee(i)
+                .local_set(start);
+        } else {
+            f.instructions()
+                .local_get(count)
+                .i64_const(1)
+                .i64_add()
+                .local_set(count)
+                .local_get(i)
+                .local_get(separator)
+                .i32_wrap_i64()
+                .i64_load(mem(8, 3))
+                .i64_add()
+                .local_set(i);
+        }
+        f.instructions()
+            .else_()
+            .local_get(i)
+            .i64_const(1)
+            .i64_add()
+            .local_set(i)
+            .end()
+            .br(0)
+            .end()
+            .end();
+        if !making {
+            // The part after the last separator
+            f.instructions()
+                .i64_const(ARRAY as i64)
+                .i64_const(0)
+                .local_get(count)
+                .i64_const(1)
+                .i64_add()
+                .i64_const(shape)
+                .call(ALLOC)
+                .local_set(result)
+                .i64_const(0)
+                .local_set(count);
+        }
+    }
+    add_part(&mut f, [string, result, count, start], len);
+    f.instructions().local_get(result).end();
+    f
+}
+
+/// Sets the element at the local `count` of the array in the local `result` to the part of the
+/// local `string` from the offset in the local `start` up to the one in the local `end`, then
+/// adds 1 to `count`.
+fn add_part(f: &mut Function, [string, result, count, start]: [u32; 4], end: u32) {
+    f.instructions()
+        .local_get(result)
+        .local_get(count)
+        .i64_const(3)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(string)
+        .local_get(start)
+        .local_get(end)
+        .call(SUBSTRING)
+        .i64_store(slot(0))
+        .local_get(count)
+        .i64_const(1)
+        .i64_add()
+        .local_set(count);
+}
+
+/// `to_upper(string)` and `to_lower(string)` return a copy of a string with its ASCII letters in
+/// one case.
+fn change_case(upper: bool) -> Function {
+    let (string, len, result, i, byte) = (0, 1, 2, 3, 4);
+    let first = if upper { b'a' } else { b'A' };
+    let mut f = Function::new([(4, I64)]);
+    f.instructions()
+        .local_get(string)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(len)
+        .local_get(string)
+        .i64_const(0)
+        .local_get(len)
+        .call(SUBSTRING)
+        .local_set(result)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(i)
+        .local_get(len)
+        .i64_ge_u()
+        .br_if(1);
+    load_byte(&mut f, result, i);
+    f.instructions()
+        .local_tee(byte)
+        .i64_const(first as i64)
+        .i64_sub()
+        .i64_const(26)
+        .i64_lt_u()
+        // The cases of an ASCII letter differ in one bit
+        .if_(BlockType::Empty)
+        .local_get(result)
+        .local_get(i)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(byte)
+        .i64_const(0x20)
+        .i64_xor()
+        .i64_store8(mem(HEADER, 0))
+        .end()
+        .local_get(i)
+        .i64_const(1)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        .local_get(result)
+        .end();
+    f
+}
+
+/// Allocates an array of the length in the local `len` with the shape of the local `array`, into
+/// the local `result`, and copies the elements of `array` that fit.
+fn copy_array(f: &mut Function, array: u32, len: u32, result: u32) {
+    f.instructions()
+        .i64_const(ARRAY as i64)
+        .i64_const(0)
+        .local_get(len)
+        .local_get(array)
+        .i32_wrap_i64()
+        .i64_load(mem(16, 3))
+        .call(ALLOC)
+        .local_tee(result)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(array)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(array)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_get(len)
+        .local_get(array)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_get(len)
+        .i64_lt_u()
+        .select()
+        .i64_const(3)
+        .i64_shl()
+        .i32_wrap_i64()
+        .memory_copy(0, 0);
+}
+
+/// `push(array, value)` returns a copy of an array with a value added to its end.
+fn push() -> Function {
+    let (array, value, len, result) = (0, 1, 2, 3);
+    let mut f = Function::new([(2, I64)]);
+    f.instructions()
+        .local_get(array)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_const(1)
+        .i64_add()
+        .local_set(len);
+    copy_array(&mut f, array, len, result);
+    f.instructions()
+        .local_get(result)
+        .local_get(len)
+        .i64_const(3)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(value)
+        // The slot before the one at `len`
+        .i64_store(mem(HEADER - 8, 3))
+        .local_get(result)
+        .end();
+    f
+}
+
+/// `pop(array)` returns a copy of an array without its last element, if it has one.
+fn pop() -> Function {
+    let (array, len, result) = (0, 1, 2);
+    let mut f = Function::new([(2, I64)]);
+    f.instructions()
+        .local_get(array)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_tee(len)
+        .i64_eqz()
+        .if_(BlockType::Empty)
+        .local_get(array)
+        .return_()
+        .end()
+        .local_get(len)
+        .i64_const(1)
+        .i64_sub()
+        .local_set(len);
+    copy_array(&mut f, array, len, result);
+    f.instructions().local_get(result).end();
+    f
+}
+
+/// Allocates a map with the number of entries in the local `len` and the shape of the local
+/// `map`, into the local `result`, and copies the entries of `map` that fit. Its two slots for
+/// each entry hold the key and then the value.
+fn copy_map(f: &mut Function, map: u32, len: u32, result: u32) {
+    f.instructions()
+        .i64_const(MAP as i64)
+        .i64_const(0)
+        .local_get(len)
+        .i64_const(1)
+        .i64_shl()
+        .local_get(map)
+        .i32_wrap_i64()
+        .i64_load(mem(16, 3))
+        .call(ALLOC)
+        .local_tee(result)
+        .i32_wrap_i64()
+        // Its length is the number of entries, not of slots
+        .local_get(len)
+        .i64_store(mem(8, 3))
+        .local_get(result)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(map)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(map)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_get(len)
+        .local_get(map)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_get(len)
+        .i64_lt_u()
+        .select()
+        .i64_const(4)
+        .i64_shl()
+        .i32_wrap_i64()
+        .memory_copy(0, 0);
+}
+
+/// `map_find(map, key)` returns the index of the entry of a map whose key is `key`, or -1.
+fn map_find() -> Function {
+    let (map, key, i, len) = (0, 1, 2, 3);
+    let mut f = Function::new([(2, I64)]);
+    f.instructions()
+        .local_get(map)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(len)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(i)
+        .local_get(len)
+        .i64_ge_u()
+        .br_if(1)
+        .local_get(map)
+        .local_get(i)
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load(slot(0))
+        .local_get(key)
+        // The first letter of the shape is for the keys
+        .local_get(map)
+        .i32_wrap_i64()
+        .i64_load(mem(16, 3))
+        .i32_wrap_i64()
+        .i64_load8_u(mem(0, 0))
+        .call(EQUAL)
+        .i32_wrap_i64()
+        .if_(BlockType::Empty)
+        .local_get(i)
+        .return_()
+        .end()
+        .local_get(i)
+        .i64_const(1)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        .i64_const(-1)
+        .end();
+    f
+}
+
+/// `map_insert(map, key, value)` returns a copy of a map with the value of a key set, adding an
+/// entry for the key at the end when it doesn't have one.
+fn map_insert() -> Function {
+    let (map, key, value, index, len, result) = (0, 1, 2, 3, 4, 5);
+    let mut f = Function::new([(3, I64)]);
+    f.instructions()
+        .local_get(map)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(len)
+        .local_get(map)
+        .local_get(key)
+        .call(MAP_FIND)
+        .local_tee(index)
+        .i64_const(0)
+        .i64_ge_s()
+        .if_(BlockType::Empty);
+    copy_map(&mut f, map, len, result);
+    f.instructions()
+        .local_get(result)
+        .local_get(index)
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(value)
+        .i64_store(slot(1))
+        .local_get(result)
+        .return_()
+        .end()
+        .local_get(len)
+        .i64_const(1)
+        .i64_add()
+        .local_set(len);
+    copy_map(&mut f, map, len, result);
+    f.instructions()
+        .local_get(result)
+        .local_get(len)
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(key)
+        // The slots of the entry before the one at `len`
+        .i64_store(mem(HEADER - 16, 3))
+        .local_get(result)
+        .local_get(len)
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(value)
+        .i64_store(mem(HEADER - 8, 3))
+        .local_get(result)
+        .end();
+    f
+}
+
+/// `map_remove(map, index)` returns a copy of a map without an entry, keeping the order of the
+/// others.
+fn map_remove(data: &mut Data) -> Function {
+    let (map, index, len, result) = (0, 1, 2, 3);
+    let mut f = Function::new([(2, I64)]);
+    check_index(&mut f, data, map, index);
+    f.instructions()
+        .local_get(map)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_const(1)
+        .i64_sub()
+        .local_set(len);
+    copy_map(&mut f, map, len, result);
+    // The entries after the removed one move down over it
+    f.instructions()
+        .local_get(result)
+        .local_get(index)
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(map)
+        .local_get(index)
+        .i64_const(1)
+        .i64_add()
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(len)
+        .local_get(index)
+        .i64_sub()
+        .i64_const(4)
+        .i64_shl()
+        .i32_wrap_i64()
+        .memory_copy(0, 0)
+        .local_get(result)
+        .end();
+    f
+}
+
+/// `map_key(map, index)` and `map_value(map, index)` return the key or the value of an entry of
+/// a map.
+fn map_entry(data: &mut Data, value: bool) -> Function {
+    let (map, index) = (0, 1);
+    let mut f = Function::new([]);
+    check_index(&mut f, data, map, index);
+    f.instructions()
+        .local_get(map)
+        .local_get(index)
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load(slot(value as usize))
+        .end();
+    f
+}
+
+/// `map_equal(lhs, rhs)` returns whether two maps of the same length have the same entries, in
+/// any order.
+fn map_equal() -> Function {
+    let (lhs, rhs, i, len, j) = (0, 1, 2, 3, 4);
+    let mut f = Function::new([(3, I64)]);
+    f.instructions()
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_set(len)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(i)
+        .local_get(len)
+        .i64_ge_u()
+        .br_if(1)
+        .local_get(rhs)
+        .local_get(lhs)
+        .local_get(i)
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load(slot(0))
+        .call(MAP_FIND)
+        .local_tee(j)
+        .i64_const(0)
+        .i64_lt_s()
+        .if_(BlockType::Empty)
+        .i64_const(0)
+        .return_()
+        .end()
+        .local_get(lhs)
+        .local_get(i)
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load(slot(1))
+        .local_get(rhs)
+        .local_get(j)
+        .i64_const(4)
+        .i64_shl()
+        .i64_add()
+        .i32_wrap_i64()
+        .i64_load(slot(1))
+        // The second letter of the shape is for the values
+        .local_get(lhs)
+        .i32_wrap_i64()
+        .i64_load(mem(16, 3))
+        .i32_wrap_i64()
+        .i64_load8_u(mem(1, 0))
+        .call(EQUAL)
+        .i64_eqz()
+        .if_(BlockType::Empty)
+        .i64_const(0)
+        .return_()
+        .end()
+        .local_get(i)
+        .i64_const(1)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        .i64_const(1)
+        .end();
+    f
+}
+
+/// `pow(x, y)` returns a float raised to the power of another. The whole part of the power is
+/// found by squaring `x`, and the fraction bit by bit from its square roots, since `x` to the
+/// power of a half is its square root, of a quarter that root's square root, and so on.
+fn pow() -> Function {
+    let (x, y, result, base, fraction, n, i) = (0, 1, 2, 3, 4, 5, 6);
+    let mut f = Function::new([(3, F64), (2, I64)]);
+    f.instructions()
+        // NaN to any power is NaN, except to the power of 0
+        .local_get(y)
+        .f64_reinterpret_i64()
+        .local_get(y)
+        .f64_reinterpret_i64()
+        .f64_ne()
+        .if_(BlockType::Empty)
+        .local_get(y)
+        .return_()
+        .end()
+        .f64_const(Ieee64::from(1.0))
+        .local_set(result)
+        .local_get(x)
+        .f64_reinterpret_i64()
+        .local_set(base)
+        .local_get(y)
+        .f64_reinterpret_i64()
+        .f64_abs()
+        .local_tee(fraction)
+        .f64_floor()
+        .i64_trunc_sat_f64_u()
+        .local_set(n)
+        .local_get(fraction)
+        .local_get(fraction)
+        .f64_floor()
+        .f64_sub()
+        .local_set(fraction)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(n)
+        .i64_eqz()
+        .br_if(1)
+        .local_get(n)
+        .i64_const(1)
+        .i64_and()
+        .i32_wrap_i64()
+        .if_(BlockType::Empty)
+        .local_get(result)
+        .local_get(base)
+        .f64_mul()
+        .local_set(result)
+        .end()
+        .local_get(base)
+        .local_get(base)
+        .f64_mul()
+        .local_set(base)
+        .local_get(n)
+        .i64_const(1)
+        .i64_shr_u()
+        .local_set(n)
+        .br(0)
+        .end()
+        .end()
+        // A negative number to a fractional power is NaN, which is the square root's
+        .local_get(x)
+        .f64_reinterpret_i64()
+        .local_set(base)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(fraction)
+        .f64_const(Ieee64::from(0.0))
+        .f64_eq()
+        .local_get(i)
+        .i64_const(f64::MANTISSA_DIGITS as i64)
+        .i64_ge_u()
+        .i32_or()
+        .br_if(1)
+        .local_get(base)
+        .f64_sqrt()
+        .local_set(base)
+        .local_get(fraction)
+        .f64_const(Ieee64::from(2.0))
+        .f64_mul()
+        .local_tee(fraction)
+        .f64_const(Ieee64::from(1.0))
+        .f64_ge()
+        .if_(BlockType::Empty)
+        .local_get(result)
+        .local_get(base)
+        .f64_mul()
+        .local_set(result)
+        .local_get(fraction)
+        .f64_const(Ieee64::from(1.0))
+        .f64_sub()
+        .local_set(fraction)
+        .end()
+        .local_get(i)
+        .i64_const(1)
+        .i64_add()
+        .local_set(i)
+        .br(0)
+        .end()
+        .end()
+        .f64_const(Ieee64::from(1.0))
+        .local_get(result)
+        .f64_div()
+        .local_get(result)
+        .local_get(y)
+        .f64_reinterpret_i64()
+        .f64_const(Ieee64::from(0.0))
+        .f64_lt()
+        .select()
+        .i64_reinterpret_f64()
+        .end();
+    f
+}
+
+/// The file descriptor of the directory that paths start from, the first one the host preopens.
+const DIR: i32 = 3;
+
+/// The WASI flag that makes a path's symbolic links be followed.
+const FOLLOW: i32 = 1;
+
+/// The WASI error numbers that have messages of their own.
+const EACCES: i32 = 2;
+const EISDIR: i32 = 31;
+const ENOENT: i32 = 44;
+const ENOSYS: i32 = 52;
+const ENOTDIR: i32 = 54;
+const ENOTSUP: i32 = 58;
+const EPERM: i32 = 63;
+const ENOTCAPABLE: i32 = 76;
+
+/// Pushes the address and the length of the path in the local `path`, for a WASI function.
+fn path_args(f: &mut Function, path: u32) {
+    f.instructions()
+        .local_get(path)
+        .i32_wrap_i64()
+        .i32_const(HEADER as i32)
+        .i32_add()
+        .local_get(path)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i32_wrap_i64();
+}
+
+/// `read_file(path)` returns the contents of a file, or an empty string when it can't be read.
+/// The string is as long as the file's size, and is filled by reading until the end of the file.
+fn read_file(data: &mut Data) -> Function {
+    let (path, result, size, total, errno) = (0, 1, 2, 3, 4);
+    let empty = data.string("");
+    let stat = data.scratch(&[0; 64]) as i32;
+    let fd = data.scratch(&[0; 4]) as i32;
+    let piece = data.scratch(&[0; 8]) as i32;
+    let read = data.scratch(&[0; 4]) as i32;
+    let mut f = Function::new([(3, I64), (1, I32)]);
+    f.instructions().i32_const(DIR).i32_const(FOLLOW);
+    path_args(&mut f, path);
+    f.instructions()
+        .i32_const(stat)
+        .call(PATH_FILESTAT_GET)
+        .local_tee(errno)
+        .i32_eqz()
+        .if_(BlockType::Empty)
+        // Opening a directory succeeds, but it's not a file that can be read
+        .i32_const(stat)
+        .i32_load8_u(mem(16, 0))
+        .i32_const(3)
+        .i32_eq()
+        .if_(BlockType::Empty)
+        .i32_const(EISDIR)
+        .local_set(errno)
+        .end()
+        .end()
+        .local_get(errno)
+        .if_(BlockType::Empty)
+        .local_get(errno)
+        .call(SET_FS_ERROR)
+        .i64_const(empty)
+        .return_()
+        .end()
+        .i32_const(DIR)
+        .i32_const(FOLLOW);
+    path_args(&mut f, path);
+    f.instructions()
+        // No flags, and only the right to read
+        .i32_const(0)
+        .i64_const(1 << 1)
+        .i64_const(0)
+        .i32_const(0)
+        .i32_const(fd)
+        .call(PATH_OPEN)
+        .local_tee(errno)
+        .if_(BlockType::Empty)
+        .local_get(errno)
+        .call(SET_FS_ERROR)
+        .i64_const(empty)
+        .return_()
+        .end()
+        .i32_const(stat)
+        .i64_load(mem(32, 3))
+        .local_tee(size)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .call(ALLOCATE)
+        .local_tee(result)
+        .i32_wrap_i64()
+        .i64_const(STRING as i64)
+        .i64_store32(mem(0, 2))
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(total)
+        .local_get(size)
+        .i64_ge_u()
+        .br_if(1)
+        .i32_const(piece)
+        .local_get(result)
+        .local_get(total)
+        .i64_add()
+        .i32_wrap_i64()
+        .i32_const(HEADER as i32)
+        .i32_add()
+        .i32_store(mem(0, 2))
+        .i32_const(piece)
+        .local_get(size)
+        .local_get(total)
+        .i64_sub()
+        .i32_wrap_i64()
+        .i32_store(mem(4, 2))
+        .i32_const(fd)
+        .i32_load(mem(0, 2))
+        .i32_const(piece)
+        .i32_const(1)
+        .i32_const(read)
+        .call(FD_READ)
+        .local_tee(errno)
+        .br_if(1)
+        .i32_const(read)
+        .i32_load(mem(0, 2))
+        .i32_eqz()
+        .br_if(1)
+        .local_get(total)
+        .i32_const(read)
+        .i32_load(mem(0, 2))
+        .i64_extend_i32_u()
+        .i64_add()
+        .local_set(total)
+        .br(0)
+        .end()
+        .end()
+        .i32_const(fd)
+        .i32_load(mem(0, 2))
+        .call(FD_CLOSE)
+        .drop()
+        .local_get(errno)
+        .call(SET_FS_ERROR)
+        .local_get(errno)
+        .if_(BlockType::Empty)
+        .i64_const(empty)
+        .return_()
+        .end()
+        .local_get(result)
+        .i32_wrap_i64()
+        .local_get(total)
+        .i64_store(mem(8, 3))
+        .local_get(result)
+        .end();
+    f
+}
+
+/// `write_file(path, contents)` replaces a file with a string, creating it when it doesn't exist.
+fn write_file(data: &mut Data) -> Function {
+    let (path, contents, written, errno) = (0, 1, 2, 3);
+    let fd = data.scratch(&[0; 4]) as i32;
+    let piece = data.scratch(&[0; 8]) as i32;
+    let count = data.scratch(&[0; 4]) as i32;
+    let mut f = Function::new([(1, I64), (1, I32)]);
+    f.instructions().i32_const(DIR).i32_const(FOLLOW);
+    path_args(&mut f, path);
+    f.instructions()
+        // Created and truncated, with only the right to write
+        .i32_const(1 | 8)
+        .i64_const(1 << 6)
+        .i64_const(0)
+        .i32_const(0)
+        .i32_const(fd)
+        .call(PATH_OPEN)
+        .local_tee(errno)
+        .if_(BlockType::Empty)
+        .local_get(errno)
+        .call(SET_FS_ERROR)
+        .i64_const(0)
+        .return_()
+        .end()
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(written)
+        .local_get(contents)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .i64_ge_u()
+        .br_if(1)
+        .i32_const(piece)
+        .local_get(contents)
+        .local_get(written)
+        .i64_add()
+        .i32_wrap_i64()
+        .i32_const(HEADER as i32)
+        .i32_add()
+        .i32_store(mem(0, 2))
+        .i32_const(piece)
+        .local_get(contents)
+        .i32_wrap_i64()
+        .i64_load(mem(8, 3))
+        .local_get(written)
+        .i64_sub()
+        .i32_wrap_i64()
+        .i32_store(mem(4, 2))
+        .i32_const(fd)
+        .i32_load(mem(0, 2))
+        .i32_const(piece)
+        .i32_const(1)
+        .i32_const(count)
+        .call(FD_WRITE)
+        .local_tee(errno)
+        .br_if(1)
+        .local_get(written)
+        .i32_const(count)
+        .i32_load(mem(0, 2))
+        .i64_extend_i32_u()
+        .i64_add()
+        .local_set(written)
+        .br(0)
+        .end()
+        .end()
+        .i32_const(fd)
+        .i32_load(mem(0, 2))
+        .call(FD_CLOSE)
+        .drop()
+        .local_get(errno)
+        .call(SET_FS_ERROR)
+        .i64_const(0)
+        .end();
+    f
+}
+
+/// `file_exists(path)` returns whether a file or directory exists, which is `false` without an
+/// error when it isn't found, and with one when it can't be found out.
+fn file_exists(data: &mut Data) -> Function {
+    let (path, errno) = (0, 1);
+    let stat = data.scratch(&[0; 64]) as i32;
+    let mut f = Function::new([(1, I32)]);
+    f.instructions().i32_const(DIR).i32_const(FOLLOW);
+    path_args(&mut f, path);
+    f.instructions()
+        .i32_const(stat)
+        .call(PATH_FILESTAT_GET)
+        .local_tee(errno)
+        .i32_eqz()
+        .i64_extend_i32_u()
+        .i32_const(0)
+        .local_get(errno)
+        .local_get(errno)
+        .i32_const(ENOENT)
+        .i32_eq()
+        .select()
+        .call(SET_FS_ERROR)
+        .end();
+    f
+}
+
+/// `fs_error()` returns the message of the error that the last of the functions before failed
+/// with, or an empty string when it succeeded.
+fn fs_error(error: i32) -> Function {
+    let mut f = Function::new([]);
+    f.instructions().i32_const(error).i64_load(mem(0, 3)).end();
+    f
+}
+
+/// `set_fs_error(errno)` keeps the message of a WASI error number for `fs_error()`, which is empty
+/// for 0, and the same as the runtime library of native code gives for the errors programs
+/// usually meet.
+fn set_fs_error(data: &mut Data, error: i32) -> Function {
+    let errno = 0;
+    let messages = [
+        (&[0][..], ""),
+        (&[ENOENT], "no such file or directory"),
+        (&[EACCES, EPERM, ENOTCAPABLE], "permission denied"),
+        (&[EISDIR], "is a directory"),
+        (&[ENOTDIR], "not a directory"),
+        (&[ENOSYS, ENOTSUP], "unsupported"),
+    ];
+    let other = data.string("other error");
+    let mut f = Function::new([]);
+    f.instructions()
+        .i32_const(error)
+        .i64_const(other)
+        .i64_store(mem(0, 3));
+    for (codes, message) in messages {
+        let message = data.string(message);
+        for &code in codes {
+            f.instructions()
+                .local_get(errno)
+                .i32_const(code)
+                .i32_eq()
+                .if_(BlockType::Empty)
+                .i32_const(error)
+                .i64_const(message)
+                .i64_store(mem(0, 3))
+                .end();
+        }
+    }
+    f.instructions().end();
+    f
+}
+
+/// `to_string(value, kind)` returns the text of a value that `as string` converts, whose kind is
+/// the letter that `rf_to_string` takes in the runtime library of native code.
+fn to_string(data: &mut Data) -> Function {
+    let (value, kind, result, len, n, at) = (0, 1, 2, 3, 4, 5);
+    let (yes, no) = (data.string("true"), data.string("false"));
+    let mut f = Function::new([(4, I64)]);
+    f.instructions()
+        .local_get(kind)
+        .i64_const(b'b' as i64)
+        .i64_eq()
+        .if_(BlockType::Empty)
+        .i64_const(yes)
+        .i64_const(no)
+        .local_get(value)
+        .i64_const(0)
+        .i64_ne()
+        .select()
+        .return_()
+        .end()
+        // Room for the longest, which is 20 digits and a sign
+        .i64_const(HEADER as i64 + 24)
+        .call(ALLOCATE)
+        .local_tee(result)
+        .i32_wrap_i64()
+        .i64_const(STRING as i64)
+        .i64_store32(mem(0, 2))
+        .local_get(result)
+        .i64_const(HEADER as i64)
+        .i64_add()
+        .local_set(at)
+        .local_get(kind)
+        .i64_const(b'c' as i64)
+        .i64_eq()
+        .if_(BlockType::Empty)
+        // A character is encoded as UTF-8, whose bytes after the first hold 6 bits each
+        .i64_const(1)
+        .local_get(value)
+        .i64_const(0x80)
+        .i64_ge_u()
+        .i64_extend_i32_u()
+        .i64_add()
+        .local_get(value)
+        .i64_const(0x800)
+        .i64_ge_u()
+        .i64_extend_i32_u()
+        .i64_add()
+        .local_get(value)
+        .i64_const(0x10000)
+        .i64_ge_u()
+        .i64_extend_i32_u()
+        .i64_add()
+        .local_tee(len)
+        .i64_const(1)
+        .i64_sub()
+        .local_set(n)
+        .block(BlockType::Empty)
+        .loop_(BlockType::Empty)
+        .local_get(n)
+        .i64_eqz()
+        .br_if(1)
+        .local_get(at)
+        .local_get(n)
+        .i64_add()
+        .i32_wrap_i64()
+        .local_get(value)
+        .i64_const(0x3f)
+        .i64_and()
+        .i64_const(0x80)
+        .i64_or()
+        .i64_store8(mem(0, 0))
+        .local_get(value)
+        .i64_const(6)
+        .i64_shr_u()
+        .local_set(value)
+        .local_get(n)
+        .i64_const(1)
+        .i64_sub()
+        .local_set(n)
+        .br(0)
+        .end()
+        .end()
+        // The first byte starts with as many 1 bits as there are bytes, unless it's the only one
+        .local_get(at)
+        .i32_wrap_i64()
+        .local_get(value)
+        .i64_const(0xf00)
+        .local_get(len)
+        .i64_shr_u()
+        .i64_const(0)
+        .local_get(len)
+        .i64_const(1)
+        .i64_gt_u()
+        .select()
+        .i64_or()
+        .i64_store8(mem(0, 0))
+        .else_()
+        // A negative integer is a sign, then the digits of its magnitude
+        .local_get(kind)
+        .i64_const(b'i' as i64)
+        .i64_eq()
+        .local_get(value)
+        .i64_const(0)
+        .i64_lt_s()
+        .i32_and()
+        .if_(BlockType::Empty)
+        .local_get(at)
+        .i32_wrap_i64()
+        .i64_const(b'-' as i64)
+        .i64_store8(mem(0, 0))
+        .i64_const(1)
+        .local_set(len)
+        .i64_const(0)
+        .local_get(value)
+        .i64_sub()
+        .local_set(value)
+        .end()
+        .local_get(value)
+        .local_set(n)
+        .loop_(BlockType::Empty)
+        .local_get(len)
+        .i64_const(1)
+        .i64_add()
+        .local_set(len)
+        .local_get(n)
+        .i64_const(10)
+        .i64_div_u()
+        .local_tee(n)
+        .i64_const(0)
+        .i64_ne()
+        .br_if(0)
+        .end()
+        // The digits are written from the last
+        .local_get(at)
+        .local_get(len)
+        .i64_add()
+        .local_set(n)
+        .loop_(BlockType::Empty)
+        .local_get(n)
+        .i64_const(1)
+        .i64_sub()
+        .local_tee(n)
+        .i32_wrap_i64()
+        .local_get(value)
+        .i64_const(10)
+        .i64_rem_u()
+        .i64_const(b'0' as i64)
+        .i64_add()
+        .i64_store8(mem(0, 0))
+        .local_get(value)
+        .i64_const(10)
+        .i64_div_u()
+        .local_tee(value)
+        .i64_const(0)
+        .i64_ne()
+        .br_if(0)
+        .end()
+        .end()
+        .local_get(result)
+        .i32_wrap_i64()
+        .local_get(len)
+        .i64_store(mem(8, 3))
+        .local_get(result)
+        .end();
+    f
+}
@@ -0,0 +1,186 @@
+//! A WASI host for the modules that tests run in wasmtime, which implements the few functions
+//! the runtime library imports. Modules read stdin from a buffer and write stdout and stderr to
+//! buffers, and their files are kept in memory, so tests can check everything they do. It's also
+//! part of the tests in `tests/engines.rs`, which is why it only uses wasmtime.
+
+use std::collections::{HashMap, VecDeque};
+
+use wasmtime::{Caller, Engine, Extern, Instance, Linker, Module, Store};
+
+/// The file descriptor of the first file a module opens, after stdio and the preopened
+/// directory.
+const FIRST_FILE: i32 = 4;
+
+const ENOENT: i32 = 44;
+
+/// What a module reads from stdin, what it wrote to stdout and stderr, the code it exited
+/// with, and the files it has, with the ones it opened and how far it read each of them.
+#[derive(Default)]
+pub struct Host {
+    pub stdin: VecDeque<u8>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit: Option<i32>,
+    pub files: HashMap<String, Vec<u8>>,
+    open: Vec<(String, usize)>,
+}
+
+fn memory(caller: &mut Caller<'_, Host>) -> wasmtime::Memory {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => memory,
+        _ => panic!("the module doesn't export its memory"),
+    }
+}
+
+pub fn instantiate(module: &[u8]) -> (Store<Host>, Instance) {
+    let engine = Engine::default();
+    let module = Module::new(&engine, module).unwrap();
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "fd_write",
+            |mut caller: Caller<'_, Host>, fd: i32, iovs: i32, count: i32, written: i32| {
+                let memory = memory(&mut caller);
+                let (data, host) = memory.data_and_store_mut(&mut caller);
+                let out = match fd {
+                    1 => &mut host.stdout,
+                    2 => &mut host.stderr,
+                    _ => {
+                        let path = &host.open[(fd - FIRST_FILE) as usize].0;
+                        host.files.get_mut(path).unwrap()
+                    }
+                };
+                let mut total = 0;
+                for i in 0..count as usize {
+                    let iov = iovs as usize + 8 * i;
+                    let word = |at: usize| {
+                        u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize
+                    };
+                    let (ptr, len) = (word(iov), word(iov + 4));
+                    out.extend(&data[ptr..ptr + len]);
+                    total += len;
+                }
+                let written = written as usize;
+                data[written..written + 4].copy_from_slice(&(total as u32).to_le_bytes());
+                0
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "fd_read",
+            |mut caller: Caller<'_, Host>, fd: i32, iovs: i32, count: i32, read: i32| {
+                let memory = memory(&mut caller);
+                let (data, host) = memory.data_and_store_mut(&mut caller);
+                let mut total = 0;
+                for i in 0..count as usize {
+                    let iov = iovs as usize + 8 * i;
+                    let word = |at: usize| {
+                        u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize
+                    };
+                    let (ptr, len) = (word(iov), word(iov + 4));
+                    for byte in &mut data[ptr..ptr + len] {
+                        let next = if fd == 0 {
+                            host.stdin.pop_front()
+                        } else {
+                            let (path, at) = &mut host.open[(fd - FIRST_FILE) as usize];
+                            let next = host.files[path.as_str()].get(*at).copied();
+                            *at += next.is_some() as usize;
+                            next
+                        };
+                        match next {
+                            Some(next) => *byte = next,
+                            None => break,
+                        }
+                        total += 1;
+                    }
+                }
+                let read = read as usize;
+                data[read..read + 4].copy_from_slice(&(total as u32).to_le_bytes());
+                0
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "proc_exit",
+            |mut caller: Caller<'_, Host>, code: i32| -> wasmtime::Result<()> {
+                caller.data_mut().exit = Some(code);
+                Err(wasmtime::Error::msg("exited"))
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "path_open",
+            |mut caller: Caller<'_, Host>,
+             _dir: i32,
+             _flags: i32,
+             path: i32,
+             len: i32,
+             oflags: i32,
+             _rights: i64,
+             _inheriting: i64,
+             _fdflags: i32,
+             opened: i32| {
+                let memory = memory(&mut caller);
+                let (data, host) = memory.data_and_store_mut(&mut caller);
+                let path = &data[path as usize..(path + len) as usize];
+                let path = String::from_utf8(path.to_vec()).unwrap();
+                // Created, and truncated
+                if oflags & 1 != 0 {
+                    let file = host.files.entry(path.clone()).or_default();
+                    if oflags & 8 != 0 {
+                        file.clear();
+                    }
+                } else if !host.files.contains_key(&path) {
+                    return ENOENT;
+                }
+                host.open.push((path, 0));
+                let fd = FIRST_FILE + host.open.len() as i32 - 1;
+                let opened = opened as usize;
+                data[opened..opened + 4].copy_from_slice(&fd.to_le_bytes());
+                0
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "path_filestat_get",
+            |mut caller: Caller<'_, Host>,
+             _dir: i32,
+             _flags: i32,
+             path: i32,
+             len: i32,
+             stat: i32| {
+                let memory = memory(&mut caller);
+                let (data, host) = memory.data_and_store_mut(&mut caller);
+                let path = &data[path as usize..(path + len) as usize];
+                let Some(file) = host.files.get(&*String::from_utf8_lossy(path)) else {
+                    return ENOENT;
+                };
+                // A regular file, of its size
+                let stat = stat as usize;
+                data[stat + 16] = 4;
+                let size = file.len() as u64;
+                data[stat + 32..stat + 40].copy_from_slice(&size.to_le_bytes());
+                0
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "wasi_snapshot_preview1",
+            "fd_close",
+            |_: Caller<'_, Host>, _fd: i32| 0,
+        )
+        .unwrap();
+    let mut store = Store::new(&engine, Host::default());
+    let instance = linker.instantiate(&mut store, &module).unwrap();
+    (store, instance)
+}
@@ -0,0 +1,117 @@
+//! Where a running program's `print`, `println` and `input` read and write, which is the standard
+//! input and output when it's run from the command line, and anything else when it's run from a
+//! test or embedded.
+
+use std::io::{self, BufRead, Read, Write};
+
+/// The input a program reads lines from and the output it writes to.
+pub struct Console<'a> {
+    input: Box<dyn BufRead + 'a>,
+    output: Box<dyn Write + 'a>,
+}
+
+impl<'a> Console<'a> {
+    pub fn new(input: impl BufRead + 'a, output: impl Write + 'a) -> Self {
+        Console {
+            input: Box::new(input),
+            output: Box::new(output),
+        }
+    }
+
+    /// A console for the standard input and output of the process, which shares them with any
+    /// other, such as the debugger's.
+    pub fn stdio() -> Console<'static> {
+        Console::new(StdinLines::default(), io::stdout())
+    }
+
+    pub fn print(&mut self, text: &str) -> io::Result<()> {
+        self.output.write_all(text.as_bytes())
+    }
+
+    pub fn println(&mut self, text: &str) -> io::Result<()> {
+        self.print(text)?;
+        self.output.write_all(b"\n")
+    }
+
+    /// Reads a line, without its newline, which is empty at the end of the input. What was
+    /// printed before is flushed first, so that a prompt shows before the program waits.
+    pub fn input(&mut self) -> io::Result<String> {
+        Ok(self.read_line()?.unwrap_or_default())
+    }
+
+    /// Reads a line like [`Console::input`], but returns `None` at the end of the input.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        self.output.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+}
+
+/// The standard input, read a line at a time, so that every console that reads it only takes the
+/// lines it asks for.
+#[derive(Default)]
+struct StdinLines {
+    line: String,
+    consumed: usize,
+}
+
+impl Read for StdinLines {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for StdinLines {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.consumed == self.line.len() {
+            self.line.clear();
+            self.consumed = 0;
+            io::stdin().read_line(&mut self.line)?;
+        }
+        Ok(&self.line.as_bytes()[self.consumed..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.consumed += amount;
+    }
+}
+
+impl Drop for Console<'_> {
+    fn drop(&mut self) {
+        // Nothing can be done about output that can't be written anymore
+        let _ = self.output.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_console() {
+        let mut output = Vec::new();
+        let mut console = Console::new(&b"first\r\nsecond\nlast"[..], &mut output);
+        console.print("name? ").unwrap();
+        assert_eq!(console.input().unwrap(), "first");
+        assert_eq!(console.input().unwrap(), "second");
+        console.println("hi").unwrap();
+        assert_eq!(console.input().unwrap(), "last");
+        assert_eq!(console.read_line().unwrap(), None);
+        assert_eq!(console.input().unwrap(), "");
+        drop(console);
+        assert_eq!(output, b"name? hi\n");
+    }
+}
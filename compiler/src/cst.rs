@@ -0,0 +1,704 @@
+//! Contains the lossless concrete syntax tree, which keeps every token of the source file
+//! including whitespace and comments, so the exact source text can be recovered from it.
+//!
+//! The tree is built from the AST that the [`Parser`] produces, so it has the same structure and
+//! the grammar only lives in one place: each node of the AST that has a span becomes a node of the
+//! tree, around the tokens in its span. Whitespace and comments between the tokens of a node
+//! belong to it, those around it belong to its parent, and the doc comments before an item belong
+//! to the item. What the parser recovers from is kept in [`SyntaxKind::Error`] nodes. Typed
+//! wrappers in [`ast`] give a structured view over the untyped tree.
+
+use rowan::{GreenNode, GreenNodeBuilder, Language};
+
+use crate::{
+    ast::{Block, Expr, Item, ItemKind, Pattern, Stmt, StmtKind, TypeExpr, VariantKind},
+    lexer::{Lexer, Span, Token},
+    parser::Parser,
+    visit::{self, Visit},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u16)]
+pub enum SyntaxKind {
+    // Trivia
+    Whitespace,
+    Comment,
+    DocComment,
+
+    // Tokens
+    Ident,
+    Literal,
+    LParen,
+    RParen,
+    LSquare,
+    RSquare,
+    LBrace,
+    RBrace,
+    Semi,
+    Comma,
+    Colon,
+    Less,
+    Greater,
+    Shr,
+    /// Any other operator or symbol.
+    Punct,
+    FnKw,
+    ConstKw,
+    StaticKw,
+    StructKw,
+    EnumKw,
+    ModKw,
+    UseKw,
+    TraitKw,
+    ImplKw,
+    ElseKw,
+    PubKw,
+    ExternKw,
+    /// Any other keyword.
+    Keyword,
+    /// A token that couldn't be lexed.
+    ErrorToken,
+
+    // Nodes
+    SourceFile,
+    Fn,
+    Extern,
+    Const,
+    Static,
+    Struct,
+    Enum,
+    Mod,
+    Use,
+    Trait,
+    Impl,
+    GenericParam,
+    Param,
+    Field,
+    Variant,
+    Block,
+    Stmt,
+    Expr,
+    Pattern,
+    Type,
+    /// An item or statement that the parser recovered from.
+    Error,
+}
+
+impl SyntaxKind {
+    /// Returns whether the kind is whitespace or a comment.
+    pub fn is_trivia(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::Whitespace | SyntaxKind::Comment | SyntaxKind::DocComment
+        )
+    }
+
+    /// Returns whether the kind is that of an item, which its doc comments belong to.
+    pub fn is_item(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::Fn
+                | SyntaxKind::Extern
+                | SyntaxKind::Const
+                | SyntaxKind::Static
+                | SyntaxKind::Struct
+                | SyntaxKind::Enum
+                | SyntaxKind::Mod
+                | SyntaxKind::Use
+                | SyntaxKind::Trait
+                | SyntaxKind::Impl
+        )
+    }
+}
+
+impl From<&Token> for SyntaxKind {
+    fn from(token: &Token) -> Self {
+        match token {
+            Token::Whitespace => SyntaxKind::Whitespace,
+            Token::Comment => SyntaxKind::Comment,
+            Token::DocComment(_) => SyntaxKind::DocComment,
+            Token::Ident(_) => SyntaxKind::Ident,
+            Token::Integer(_)
+            | Token::OversizedInteger(_)
+            | Token::Float(_)
+            | Token::String(_)
+            | Token::Char(_)
+            | Token::Bool(_) => SyntaxKind::Literal,
+            Token::LParen => SyntaxKind::LParen,
+            Token::RParen => SyntaxKind::RParen,
+            Token::LSquare => SyntaxKind::LSquare,
+            Token::RSquare => SyntaxKind::RSquare,
+            Token::LBrace => SyntaxKind::LBrace,
+            Token::RBrace => SyntaxKind::RBrace,
+            Token::Semi => SyntaxKind::Semi,
+            Token::Comma => SyntaxKind::Comma,
+            Token::Colon => SyntaxKind::Colon,
+            Token::Less => SyntaxKind::Less,
+            Token::Greater => SyntaxKind::Greater,
+            Token::Shr => SyntaxKind::Shr,
+            Token::Fn => SyntaxKind::FnKw,
+            Token::Const => SyntaxKind::ConstKw,
+            Token::Static => SyntaxKind::StaticKw,
+            Token::Struct => SyntaxKind::StructKw,
+            Token::Enum => SyntaxKind::EnumKw,
+            Token::Mod => SyntaxKind::ModKw,
+            Token::Use => SyntaxKind::UseKw,
+            Token::Trait => SyntaxKind::TraitKw,
+            Token::Impl => SyntaxKind::ImplKw,
+            Token::Else => SyntaxKind::ElseKw,
+            Token::Pub => SyntaxKind::PubKw,
+            Token::Extern => SyntaxKind::ExternKw,
+            Token::Let
+            | Token::If
+            | Token::While
+            | Token::For
+            | Token::Return
+            | Token::Yield
+            | Token::Class
+            | Token::SelfValue
+            | Token::Super
+            | Token::Match
+            | Token::Loop
+            | Token::Break
+            | Token::Continue
+            | Token::Mut
+            | Token::Type
+            | Token::In
+            | Token::As => SyntaxKind::Keyword,
+            Token::Error(_) => SyntaxKind::ErrorToken,
+            _ => SyntaxKind::Punct,
+        }
+    }
+}
+
+impl From<SyntaxKind> for rowan::SyntaxKind {
+    fn from(kind: SyntaxKind) -> Self {
+        Self(kind as u16)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RuffleLanguage {}
+
+impl Language for RuffleLanguage {
+    type Kind = SyntaxKind;
+
+    fn kind_from_raw(raw: rowan::SyntaxKind) -> SyntaxKind {
+        assert!(raw.0 <= SyntaxKind::Error as u16);
+        // SAFETY: `SyntaxKind` is `repr(u16)` and the value was checked to be in range
+        unsafe { std::mem::transmute::<u16, SyntaxKind>(raw.0) }
+    }
+
+    fn kind_to_raw(kind: SyntaxKind) -> rowan::SyntaxKind {
+        kind.into()
+    }
+}
+
+pub type SyntaxNode = rowan::SyntaxNode<RuffleLanguage>;
+pub type SyntaxToken = rowan::SyntaxToken<RuffleLanguage>;
+pub type SyntaxElement = rowan::SyntaxElement<RuffleLanguage>;
+
+/// Parses a source file into a lossless syntax tree. This never fails, as whatever the parser
+/// recovers from is kept in [`SyntaxKind::Error`] nodes.
+pub fn parse_cst(source: &str) -> SyntaxNode {
+    let tokens: Vec<_> = Lexer::new(source)
+        .with_trivia()
+        .with_recovery()
+        .map(|token| {
+            let token = token.expect("recovering lexer yielded an error");
+            (SyntaxKind::from(&token.token), token.span)
+        })
+        .collect();
+
+    let program = Parser::new(source).parse_program();
+    let mut nodes = Nodes::default();
+    nodes.visit_program(&program);
+    let mut nodes = nodes.0;
+    for (kind, span) in &mut nodes {
+        if kind.is_item() {
+            span.start = docs_start(&tokens, span.start);
+        }
+    }
+    // Outer nodes first, which the visitor already puts before the nodes with the same span
+    nodes.sort_by_key(|(_, span)| (span.start, std::cmp::Reverse(span.end)));
+
+    SyntaxNode::new_root(build(source, &tokens, &nodes))
+}
+
+/// Returns where the doc comments before the token at `start` start, or `start` if there are
+/// none.
+fn docs_start(tokens: &[(SyntaxKind, Span)], start: usize) -> usize {
+    let index = tokens.partition_point(|(_, span)| span.start < start);
+    let mut docs = start;
+    for (kind, span) in tokens[..index].iter().rev() {
+        match kind {
+            SyntaxKind::DocComment => docs = span.start,
+            SyntaxKind::Whitespace => {}
+            _ => break,
+        }
+    }
+    docs
+}
+
+/// Builds the green tree of the tokens, with each node around the tokens in its span. A node that
+/// doesn't start at a token or doesn't fit in the node around it is left out, as it didn't come
+/// from the source text as it's written.
+fn build(source: &str, tokens: &[(SyntaxKind, Span)], nodes: &[(SyntaxKind, Span)]) -> GreenNode {
+    let mut builder = GreenNodeBuilder::new();
+    builder.start_node(SyntaxKind::SourceFile.into());
+    // The end of each node that's open, innermost last
+    let mut open: Vec<usize> = Vec::new();
+    let mut nodes = nodes.iter().peekable();
+    for (kind, span) in tokens {
+        while open.last().is_some_and(|&end| end <= span.start) {
+            builder.finish_node();
+            open.pop();
+        }
+        while let Some((node, node_span)) = nodes.next_if(|(_, node)| node.start <= span.start) {
+            let fits = open.last().is_none_or(|&end| node_span.end <= end);
+            if node_span.start == span.start && !node_span.is_empty() && fits {
+                builder.start_node((*node).into());
+                open.push(node_span.end);
+            }
+        }
+        builder.token((*kind).into(), &source[span.clone()]);
+    }
+    for _ in open {
+        builder.finish_node();
+    }
+    builder.finish_node();
+    builder.finish()
+}
+
+/// Collects the kind and span of each node of the AST, outer nodes first.
+#[derive(Default)]
+struct Nodes(Vec<(SyntaxKind, Span)>);
+
+impl<'ast> Visit<'ast> for Nodes {
+    fn visit_item(&mut self, item: &'ast Item) {
+        let kind = match &item.kind {
+            ItemKind::Const(_) => SyntaxKind::Const,
+            ItemKind::Static(_) => SyntaxKind::Static,
+            ItemKind::Fn(_) => SyntaxKind::Fn,
+            ItemKind::Extern(_) => SyntaxKind::Extern,
+            ItemKind::Struct(_) => SyntaxKind::Struct,
+            ItemKind::Enum(_) => SyntaxKind::Enum,
+            ItemKind::Trait(_) => SyntaxKind::Trait,
+            ItemKind::Impl(_) => SyntaxKind::Impl,
+            ItemKind::Mod(_) => SyntaxKind::Mod,
+            ItemKind::Use(_) => SyntaxKind::Use,
+            ItemKind::Error => SyntaxKind::Error,
+        };
+        self.0.push((kind, item.span.clone()));
+        match &item.kind {
+            ItemKind::Fn(decl) => {
+                let generics = decl.generics.iter().map(|generic| &generic.span);
+                self.push_all(SyntaxKind::GenericParam, generics);
+                self.push_all(
+                    SyntaxKind::Param,
+                    decl.params.iter().map(|param| &param.span),
+                );
+            }
+            ItemKind::Extern(decl) => {
+                self.push_all(
+                    SyntaxKind::Param,
+                    decl.params.iter().map(|param| &param.span),
+                );
+            }
+            ItemKind::Struct(decl) => {
+                self.push_all(
+                    SyntaxKind::Field,
+                    decl.fields.iter().map(|field| &field.span),
+                );
+            }
+            ItemKind::Enum(decl) => {
+                for variant in &decl.variants {
+                    self.0.push((SyntaxKind::Variant, variant.span.clone()));
+                    if let VariantKind::Struct(fields) = &variant.kind {
+                        self.push_all(SyntaxKind::Field, fields.iter().map(|field| &field.span));
+                    }
+                }
+            }
+            ItemKind::Trait(decl) => {
+                for method in &decl.methods {
+                    self.0.push((SyntaxKind::Fn, method.span.clone()));
+                    let generics = method.generics.iter().map(|generic| &generic.span);
+                    self.push_all(SyntaxKind::GenericParam, generics);
+                    let params = method.params.iter().map(|param| &param.span);
+                    self.push_all(SyntaxKind::Param, params);
+                }
+            }
+            _ => {}
+        }
+        visit::walk_item(self, item);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        self.0.push((SyntaxKind::Block, block.span.clone()));
+        visit::walk_block(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        match stmt.kind {
+            // The item is a node of its own
+            StmtKind::Item(_) => {}
+            StmtKind::Error => self.0.push((SyntaxKind::Error, stmt.span.clone())),
+            _ => self.0.push((SyntaxKind::Stmt, stmt.span.clone())),
+        }
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        self.0.push((SyntaxKind::Expr, expr.span.clone()));
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+        self.0.push((SyntaxKind::Pattern, pattern.span.clone()));
+        visit::walk_pattern(self, pattern);
+    }
+
+    fn visit_type(&mut self, ty: &'ast TypeExpr) {
+        // The type of a `self` parameter isn't written, and has the span of its pattern
+        let written =
+            !matches!(self.0.last(), Some((SyntaxKind::Pattern, span)) if *span == ty.span);
+        if written {
+            self.0.push((SyntaxKind::Type, ty.span.clone()));
+        }
+        visit::walk_type(self, ty);
+    }
+}
+
+impl Nodes {
+    fn push_all<'a>(&mut self, kind: SyntaxKind, spans: impl Iterator<Item = &'a Span>) {
+        self.0.extend(spans.map(|span| (kind, span.clone())));
+    }
+}
+
+/// Typed views over the syntax tree.
+pub mod ast {
+    use super::{SyntaxKind, SyntaxNode, SyntaxToken};
+
+    /// A typed wrapper around a syntax node of a specific kind.
+    pub trait AstNode: Sized {
+        fn cast(node: SyntaxNode) -> Option<Self>;
+        fn syntax(&self) -> &SyntaxNode;
+    }
+
+    macro_rules! ast_node {
+        ($name:ident, $kind:ident) => {
+            #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+            pub struct $name(SyntaxNode);
+
+            impl AstNode for $name {
+                fn cast(node: SyntaxNode) -> Option<Self> {
+                    (node.kind() == SyntaxKind::$kind).then_some(Self(node))
+                }
+
+                fn syntax(&self) -> &SyntaxNode {
+                    &self.0
+                }
+            }
+        };
+    }
+
+    ast_node!(SourceFile, SourceFile);
+    ast_node!(GenericParam, GenericParam);
+    ast_node!(Param, Param);
+    ast_node!(Field, Field);
+    ast_node!(Variant, Variant);
+    ast_node!(Block, Block);
+    ast_node!(Stmt, Stmt);
+    ast_node!(Expr, Expr);
+    ast_node!(Pattern, Pattern);
+    ast_node!(Type, Type);
+
+    /// Returns the children of `node` that are `N` nodes.
+    fn children<N: AstNode>(node: &SyntaxNode) -> impl Iterator<Item = N> {
+        node.children().filter_map(N::cast)
+    }
+
+    /// Returns the first identifier token that's a direct child of `node`.
+    fn first_ident(node: &SyntaxNode) -> Option<SyntaxToken> {
+        node.children_with_tokens()
+            .filter_map(|element| element.into_token())
+            .find(|token| token.kind() == SyntaxKind::Ident)
+    }
+
+    /// A declaration, at the top level or in a module, trait, `impl` block or block.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct Item(SyntaxNode);
+
+    impl AstNode for Item {
+        fn cast(node: SyntaxNode) -> Option<Self> {
+            node.kind().is_item().then_some(Self(node))
+        }
+
+        fn syntax(&self) -> &SyntaxNode {
+            &self.0
+        }
+    }
+
+    impl Item {
+        pub fn kind(&self) -> SyntaxKind {
+            self.0.kind()
+        }
+
+        /// Returns the declared name, which is `None` for `use` declarations and `impl` blocks.
+        pub fn name(&self) -> Option<SyntaxToken> {
+            match self.kind() {
+                SyntaxKind::Use | SyntaxKind::Impl => None,
+                _ => first_ident(&self.0),
+            }
+        }
+
+        /// Returns whether the item is declared with `pub`.
+        pub fn is_pub(&self) -> bool {
+            self.0
+                .children_with_tokens()
+                .filter_map(|element| element.into_token())
+                .find(|token| !token.kind().is_trivia())
+                .is_some_and(|token| token.kind() == SyntaxKind::PubKw)
+        }
+
+        /// Returns the doc comment tokens written before the item.
+        pub fn doc_comments(&self) -> impl Iterator<Item = SyntaxToken> {
+            self.0
+                .children_with_tokens()
+                .filter_map(|element| element.into_token())
+                .take_while(|token| token.kind().is_trivia())
+                .filter(|token| token.kind() == SyntaxKind::DocComment)
+        }
+
+        /// Returns the type parameters of a function.
+        pub fn generics(&self) -> impl Iterator<Item = GenericParam> {
+            children(&self.0)
+        }
+
+        /// Returns the parameters of a function.
+        pub fn params(&self) -> impl Iterator<Item = Param> {
+            children(&self.0)
+        }
+
+        /// Returns the fields of a struct.
+        pub fn fields(&self) -> impl Iterator<Item = Field> {
+            children(&self.0)
+        }
+
+        /// Returns the variants of an enum.
+        pub fn variants(&self) -> impl Iterator<Item = Variant> {
+            children(&self.0)
+        }
+
+        /// Returns the items of a module declared inline, or the methods of a trait or `impl`
+        /// block.
+        pub fn items(&self) -> impl Iterator<Item = Item> {
+            children(&self.0)
+        }
+
+        /// Returns the body of a function.
+        pub fn body(&self) -> Option<Block> {
+            children(&self.0).next()
+        }
+    }
+
+    impl SourceFile {
+        pub fn items(&self) -> impl Iterator<Item = Item> {
+            children(&self.0)
+        }
+    }
+
+    impl GenericParam {
+        pub fn name(&self) -> Option<SyntaxToken> {
+            first_ident(&self.0)
+        }
+    }
+
+    impl Param {
+        pub fn pattern(&self) -> Option<Pattern> {
+            children(&self.0).next()
+        }
+
+        pub fn ty(&self) -> Option<Type> {
+            children(&self.0).next()
+        }
+    }
+
+    impl Field {
+        pub fn name(&self) -> Option<SyntaxToken> {
+            first_ident(&self.0)
+        }
+
+        pub fn ty(&self) -> Option<Type> {
+            children(&self.0).next()
+        }
+    }
+
+    impl Variant {
+        pub fn name(&self) -> Option<SyntaxToken> {
+            first_ident(&self.0)
+        }
+
+        /// Returns the fields of a struct variant.
+        pub fn fields(&self) -> impl Iterator<Item = Field> {
+            children(&self.0)
+        }
+    }
+
+    impl Block {
+        pub fn stmts(&self) -> impl Iterator<Item = Stmt> {
+            children(&self.0)
+        }
+
+        pub fn items(&self) -> impl Iterator<Item = Item> {
+            children(&self.0)
+        }
+
+        /// Returns the final expression without a `;`, which gives the block its value.
+        pub fn tail(&self) -> Option<Expr> {
+            children(&self.0).next()
+        }
+    }
+
+    impl Stmt {
+        /// Returns the expression of an expression statement, or the value of a `let`,
+        /// `return` or `yield`.
+        pub fn expr(&self) -> Option<Expr> {
+            children(&self.0).next()
+        }
+    }
+
+    impl Expr {
+        /// Returns the expressions directly inside this one.
+        pub fn exprs(&self) -> impl Iterator<Item = Expr> {
+            children(&self.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ast::*, *};
+
+    const SOURCE: &str = "// A comment\n/// Adds numbers\nfn add<T: Num>(x: Map<int, int>, self) i32 {\n    let z = x + y; // Trailing\n    if z > 0 { z } else { 0 }\n    fn inner() {}\n    z\n}\n\nstruct Point { x: int, y: int }\nenum Shape { Circle(float), Rect { w: float }, Empty }\nmod math { use a::b; }\n@ const X = 1;\n";
+
+    #[test]
+    fn test_cst_is_lossless() {
+        let root = parse_cst(SOURCE);
+        assert_eq!(root.text().to_string(), SOURCE);
+
+        let messy = "fn (( { ]] } \n /* unterminated";
+        assert_eq!(parse_cst(messy).text().to_string(), messy);
+    }
+
+    #[test]
+    fn test_cst_typed_view() {
+        let file = SourceFile::cast(parse_cst(SOURCE)).unwrap();
+        let items: Vec<_> = file.items().collect();
+        let names: Vec<_> = items
+            .iter()
+            .map(|item| item.name().map(|name| name.text().to_string()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                Some("add".to_string()),
+                Some("Point".to_string()),
+                Some("Shape".to_string()),
+                Some("math".to_string()),
+                Some("X".to_string()),
+            ]
+        );
+
+        let add = &items[0];
+        assert_eq!(add.doc_comments().count(), 1);
+        let generics: Vec<_> = add
+            .generics()
+            .map(|generic| generic.syntax().text().to_string())
+            .collect();
+        assert_eq!(generics, vec!["T: Num"]);
+        let params: Vec<_> = add
+            .params()
+            .map(|param| param.syntax().text().to_string())
+            .collect();
+        assert_eq!(params, vec!["x: Map<int, int>", "self"]);
+        // The type of `self` isn't written
+        assert!(add.params().nth(1).unwrap().ty().is_none());
+        let ty = add.params().next().unwrap().ty().unwrap();
+        assert_eq!(ty.syntax().text().to_string(), "Map<int, int>");
+
+        let body = add.body().unwrap();
+        let stmts: Vec<_> = body
+            .stmts()
+            .map(|stmt| stmt.syntax().text().to_string())
+            .collect();
+        assert_eq!(stmts, vec!["let z = x + y;", "if z > 0 { z } else { 0 }"]);
+        assert_eq!(body.items().count(), 1);
+        assert_eq!(body.tail().unwrap().syntax().text().to_string(), "z");
+        // Expressions are nodes too, around the expressions in them
+        let value = body.stmts().next().unwrap().expr().unwrap();
+        let operands: Vec<_> = value
+            .exprs()
+            .map(|expr| expr.syntax().text().to_string())
+            .collect();
+        assert_eq!(operands, vec!["x", "y"]);
+
+        assert_eq!(items[1].fields().count(), 2);
+        let variants: Vec<_> = items[2]
+            .variants()
+            .filter_map(|variant| variant.name())
+            .map(|name| name.text().to_string())
+            .collect();
+        assert_eq!(variants, vec!["Circle", "Rect", "Empty"]);
+        let rect = items[2].variants().nth(1).unwrap();
+        assert_eq!(rect.fields().count(), 1);
+        assert_eq!(items[3].items().count(), 1);
+    }
+
+    #[test]
+    fn test_cst_pub_items() {
+        let source = "pub fn f() {}\n/// Docs\npub struct P { pub x: int }\nenum E {}\npub @";
+        let root = parse_cst(source);
+        assert_eq!(root.text().to_string(), source);
+
+        let file = SourceFile::cast(root).unwrap();
+        let items: Vec<_> = file
+            .items()
+            .map(|item| (item.name().unwrap().text().to_string(), item.is_pub()))
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                ("f".to_string(), true),
+                ("P".to_string(), true),
+                ("E".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cst_error_nodes() {
+        let root = parse_cst(SOURCE);
+        let errors: Vec<_> = root
+            .children()
+            .filter(|node| node.kind() == SyntaxKind::Error)
+            .map(|node| node.text().to_string())
+            .collect();
+        assert_eq!(errors, vec!["@"]);
+
+        let source = "fn f() {\n    let = 1;\n    g();\n}";
+        let root = parse_cst(source);
+        assert_eq!(root.text().to_string(), source);
+        let body = SourceFile::cast(root)
+            .unwrap()
+            .items()
+            .next()
+            .unwrap()
+            .body();
+        let errors: Vec<_> = (body.unwrap().syntax().children())
+            .filter(|node| node.kind() == SyntaxKind::Error)
+            .map(|node| node.text().to_string())
+            .collect();
+        assert_eq!(errors, vec!["let = 1;"]);
+    }
+}
@@ -0,0 +1,477 @@
+//! An interactive debugger for programs that the VM runs, which `debug` starts. It stops before the
+//! first line of `main`, then runs the program as it's told, with commands read from a console:
+//!
+//! - `break [file:]line` stops the program whenever the code of a line starts running.
+//! - `step` runs to the start of the next line, in whatever call it's in, and `next` to the start
+//!   of the next line of the same call, or of one it returns to. `continue` runs to a breakpoint.
+//! - `backtrace` lists the calls that are running, and `frame n` picks the one that `locals` and
+//!   `print name` show the variables of.
+//! - `quit` stops the program.
+//!
+//! The code of a line starts wherever the span of an instruction is on another line than the span
+//! of the one before it, and where a `for` loop over a generator jumps back to the code of its
+//! header that resumes the generator, which `next` steps over like a call. A variable is in the
+//! slot that the function's debug info gives it where the code is. The program should be built without the peephole optimizer, which keeps
+//! values on the stack instead of in their slots.
+
+use std::{collections::HashMap, io, path::Path};
+
+use crate::{
+    codegen::bytecode::{CallFrame, Debugger, GcConfig, Module, Op, Vm},
+    console::Console,
+    ffi::Externs,
+    interpreter::Panic,
+    limits::Limits,
+    source_map::{FileId, SourceMap},
+};
+
+const HELP: &str = "break [file:]line  stop whenever the line starts running
+step               run to the start of the next line
+next               run to the start of the next line of this call, or one it returns to
+continue           run to a breakpoint
+backtrace          list the calls that are running
+frame n            look at the variables of the call #n of the backtrace
+locals             show the variables that have a value
+print name         show one of them
+quit               stop the program";
+
+/// Runs a module's `main` in the VM with a debugger, which reads its commands from `commands` and
+/// writes what it shows to it. A program that the debugger is told to quit stops without failing.
+pub fn run<'a>(
+    module: &'a Module,
+    source_map: &'a SourceMap,
+    config: GcConfig,
+    limits: Limits,
+    console: Console<'a>,
+    commands: Console<'a>,
+    host: &'a Externs,
+) -> Result<(), Panic> {
+    let mut session = Session::new(module, source_map, commands);
+    let result = {
+        let mut vm = Vm::new(module, config, limits, console, host)?;
+        vm.set_debugger(&mut session);
+        match module.main {
+            Some(main) => vm.call(main, Vec::new()).map(|_| ()),
+            None => Ok(()),
+        }
+    };
+    match result {
+        Err(_) if session.quit => Ok(()),
+        result => result,
+    }
+}
+
+/// A line of one of the program's files.
+type Line = (FileId, usize);
+
+/// How far the program runs before it stops again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Step,
+    /// To the start of a line of a call that's at most as deep as this.
+    Next(usize),
+    Continue,
+}
+
+/// What the debugger does after a command.
+enum Then {
+    Wait,
+    Run(Mode),
+    Quit,
+}
+
+/// The state of the debugger of a running program.
+pub struct Session<'a> {
+    module: &'a Module,
+    source_map: &'a SourceMap,
+    /// For each function, the offsets where the code of a line starts, with the file and line.
+    lines: Vec<HashMap<usize, Line>>,
+    /// For each function, the jumps that take a `for` loop over a generator back to its header,
+    /// with where they go and the line of the header.
+    loops: Vec<HashMap<usize, (usize, Line)>>,
+    /// Where the jump that just ran took a loop back to its header, in which function.
+    looped: Option<(u16, usize, Line)>,
+    breakpoints: Vec<Line>,
+    mode: Mode,
+    /// The call that `locals` and `print` look at, counted out from the innermost.
+    frame: usize,
+    console: Console<'a>,
+    quit: bool,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(module: &'a Module, source_map: &'a SourceMap, console: Console<'a>) -> Self {
+        let mut lines = Vec::new();
+        let mut loops = Vec::new();
+        for func in &module.functions {
+            let mut starts = HashMap::new();
+            let mut last = None;
+            // Code that lowering made up has no span, and belongs to the line before it
+            for (offset, span) in func.spans.iter().filter(|(_, span)| !span.is_empty()) {
+                let Some(file) = source_map.file_at(span.start) else {
+                    continue;
+                };
+                let line = source_map.location(span.start).unwrap().row;
+                if last != Some((file, line)) {
+                    starts.insert(*offset as usize, (file, line));
+                    last = Some((file, line));
+                }
+            }
+            let mut changes: Vec<_> = starts
+                .iter()
+                .map(|(&offset, &line)| (offset, line))
+                .collect();
+            changes.sort();
+            let line_at = |offset: usize| {
+                let index = changes.partition_point(|&(start, _)| start <= offset);
+                index.checked_sub(1).map(|index| changes[index].1)
+            };
+
+            // A loop over a generator goes back to its header in the middle of the header's line,
+            // where the code that resumes the generator is
+            let ops: Vec<(usize, Op)> = func.ops().collect();
+            let mut jumps = HashMap::new();
+            for &(offset, op) in &ops {
+                let Op::Jump(target) = op else {
+                    continue;
+                };
+                let target = target as usize;
+                let resume = (ops.iter())
+                    .skip_while(|&&(at, _)| at < target)
+                    .take_while(|(_, op)| {
+                        matches!(op, Op::Load(_) | Op::Store(_) | Op::Resume { .. })
+                    })
+                    .find(|(_, op)| matches!(op, Op::Resume { .. }));
+                if let Some(header) = resume.and_then(|&(at, _)| line_at(at)) {
+                    if line_at(offset) != Some(header) {
+                        jumps.insert(offset, (target, header));
+                    }
+                }
+            }
+            lines.push(starts);
+            loops.push(jumps);
+        }
+        Session {
+            module,
+            source_map,
+            lines,
+            loops,
+            looped: None,
+            breakpoints: Vec::new(),
+            mode: Mode::Step,
+            frame: 0,
+            console,
+            quit: false,
+        }
+    }
+
+    /// Describes where a call is, the way the trace of a panic does.
+    fn location(&self, frame: &CallFrame) -> String {
+        let func = &self.module.functions[frame.func as usize];
+        let location =
+            (func.span_at(frame.offset)).and_then(|span| self.source_map.location(span.start));
+        match location {
+            Some(location) => format!("{} ({})", func.name, location),
+            None => func.name.clone(),
+        }
+    }
+
+    /// Shows where a call is, with the line of the source it's at.
+    fn show(&mut self, frame: &CallFrame) -> io::Result<()> {
+        let location = self.location(frame);
+        self.console.println(&format!("at {}", location))?;
+        let func = &self.module.functions[frame.func as usize];
+        let Some(span) = func.span_at(frame.offset) else {
+            return Ok(());
+        };
+        let (Some(file), Some(location)) = (
+            self.source_map.file_at(span.start),
+            self.source_map.location(span.start),
+        ) else {
+            return Ok(());
+        };
+        let text = self.source_map.source(file).lines().nth(location.row - 1);
+        let text = format!("{:>4} | {}", location.row, text.unwrap_or_default());
+        self.console.println(&text)
+    }
+
+    /// Runs a command, with the calls that are running.
+    fn command(&mut self, vm: &Vm, frames: &[CallFrame], command: &str) -> io::Result<Then> {
+        let (name, arg) = match command.trim().split_once(' ') {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (command.trim(), ""),
+        };
+        match name {
+            "" => {}
+            "break" | "b" => {
+                let message = self.set_breakpoint(arg);
+                self.console.println(&message)?;
+            }
+            "step" | "s" => return Ok(Then::Run(Mode::Step)),
+            "next" | "n" => return Ok(Then::Run(Mode::Next(frames.len()))),
+            "continue" | "c" => return Ok(Then::Run(Mode::Continue)),
+            "backtrace" | "bt" => {
+                for (i, frame) in frames.iter().enumerate() {
+                    let location = self.location(frame);
+                    self.console.println(&format!("#{} {}", i, location))?;
+                }
+            }
+            "frame" | "f" => match arg.parse().ok().filter(|&i: &usize| i < frames.len()) {
+                Some(i) => {
+                    self.frame = i;
+                    self.show(&frames[i])?;
+                }
+                None => self.console.println(&format!("no frame `{}`", arg))?,
+            },
+            "locals" | "l" => {
+                let vars = self.vars(vm, &frames[self.frame]);
+                if vars.is_empty() {
+                    self.console.println("no variables")?;
+                }
+                for (name, value) in vars {
+                    self.console.println(&format!("{} = {}", name, value))?;
+                }
+            }
+            "print" | "p" => {
+                let vars = self.vars(vm, &frames[self.frame]);
+                match vars.iter().find(|(name, _)| *name == arg) {
+                    Some((name, value)) => {
+                        self.console.println(&format!("{} = {}", name, value))?;
+                    }
+                    None => {
+                        let message = format!("no variable named `{}` has a value here", arg);
+                        self.console.println(&message)?;
+                    }
+                }
+            }
+            "help" | "h" => self.console.println(HELP)?,
+            "quit" | "q" => return Ok(Then::Quit),
+            _ => {
+                let message = format!("unknown command `{}`, which `help` lists", name);
+                self.console.println(&message)?;
+            }
+        }
+        Ok(Then::Wait)
+    }
+
+    /// Sets a breakpoint at `[file:]line`, where the file is the program's first one if it's left
+    /// out, returning what to tell the user.
+    fn set_breakpoint(&mut self, arg: &str) -> String {
+        let (file, line) = match arg.rsplit_once(':') {
+            Some((path, line)) => {
+                let mut files = self.source_map.files().iter();
+                match files.position(|file| file.path.ends_with(Path::new(path))) {
+                    Some(file) => (FileId(file), line),
+                    None => return format!("no file named `{}`", path),
+                }
+            }
+            None => (FileId(0), arg),
+        };
+        let Ok(line) = line.parse() else {
+            return "usage: break [file:]line".to_string();
+        };
+        let path = self.source_map.file(file).path.display();
+        if !self
+            .lines
+            .iter()
+            .any(|lines| lines.values().any(|&at| at == (file, line)))
+        {
+            return format!("no code at {}:{}", path, line);
+        }
+        let message = format!("breakpoint at {}:{}", path, line);
+        if !self.breakpoints.contains(&(file, line)) {
+            self.breakpoints.push((file, line));
+        }
+        message
+    }
+
+    /// Returns the variables of a call that have a value, described.
+    fn vars(&self, vm: &Vm, frame: &CallFrame) -> Vec<(&'a str, String)> {
+        let module: &'a Module = self.module;
+        let func = &module.functions[frame.func as usize];
+        (func.vars_at(frame.offset).into_iter())
+            .map(|(name, slot)| (name, vm.describe(vm.slot(frame, slot))))
+            .collect()
+    }
+}
+
+impl Debugger for Session<'_> {
+    fn pause(&mut self, vm: &Vm, func: u16, offset: usize) -> Result<(), String> {
+        let looped = (self.looped.take()).filter(|&(at, target, _)| (at, target) == (func, offset));
+        if let Some(&(target, header)) = self.loops[func as usize].get(&offset) {
+            self.looped = Some((func, target, header));
+        }
+        let line = looped.map(|(_, _, header)| header);
+        let Some(line) = line.or_else(|| self.lines[func as usize].get(&offset).copied()) else {
+            return Ok(());
+        };
+        let stops = match self.mode {
+            Mode::Step => true,
+            Mode::Next(depth) => vm.call_frames().len() <= depth,
+            Mode::Continue => false,
+        };
+        if !stops && !self.breakpoints.contains(&line) {
+            return Ok(());
+        }
+        let frames = vm.call_frames();
+        self.frame = 0;
+        self.show(&frames[0]).map_err(|error| error.to_string())?;
+        loop {
+            self.console
+                .print("(debug) ")
+                .map_err(|error| error.to_string())?;
+            let command = self
+                .console
+                .read_line()
+                .map_err(|error| error.to_string())?;
+            // The end of the input is as good as `quit`
+            let then = match command {
+                Some(command) => self.command(vm, &frames, &command),
+                None => Ok(Then::Quit),
+            };
+            match then.map_err(|error| error.to_string())? {
+                Then::Wait => {}
+                Then::Run(mode) => {
+                    self.mode = mode;
+                    return Ok(());
+                }
+                Then::Quit => {
+                    self.quit = true;
+                    return Err("stopped by the debugger".to_string());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::bytecode,
+        hir,
+        loader::load_source,
+        mir::{self, opt::OptLevel},
+        resolve, typeck,
+    };
+
+    /// Runs a program in the debugger with `commands`, returning what the debugger showed and what
+    /// the program printed.
+    fn debugged(source: &str, commands: &str) -> (String, Vec<u8>) {
+        let loaded = load_source("main.rf", source);
+        let res = resolve::resolve(&loaded.program);
+        let types = typeck::check(&loaded.program, &res);
+        assert!(types.errors.is_empty(), "{:?}", types.errors);
+        let mut program = mir::build(&hir::lower(&loaded.program, &res, &types), &res);
+        mir::opt::optimize(&mut program, OptLevel::O0);
+        let module = bytecode::emit(&program);
+
+        let mut program_output = Vec::new();
+        let mut output = Vec::new();
+        let console = Console::new(&b""[..], &mut program_output);
+        let commands = Console::new(commands.as_bytes(), &mut output);
+        let ran = run(
+            &module,
+            &loaded.source_map,
+            GcConfig::default(),
+            Limits::default(),
+            console,
+            commands,
+            &Externs::default(),
+        );
+        assert_eq!(ran, Ok(()));
+        (String::from_utf8(output).unwrap(), program_output)
+    }
+
+    #[test]
+    fn test_debugger() {
+        let source = "fn total(xs: [int]) int {
+    let mut sum = 0;
+    for x in xs {
+        sum += x;
+    }
+    sum
+}
+fn main() {
+    let name = \"ruffle\";
+    let t = total([1, 2]);
+    println(name);
+}";
+        let commands = "next\nlocals\nbreak 4\nbreak 20\nbreak lib.rf:1\ncontinue\nbacktrace
+print x\nframe 1\nprint x\nlocals\ncontinue\nstep\nnext\nwhat\nquit\n";
+        let (output, program_output) = debugged(source, commands);
+        assert_eq!(
+            output,
+            "at main (main.rf:9:16)
+   9 |     let name = \"ruffle\";
+(debug) at main (main.rf:10:20)
+  10 |     let t = total([1, 2]);
+(debug) name = \"ruffle\"
+(debug) breakpoint at main.rf:4
+(debug) no code at main.rf:20
+(debug) no file named `lib.rf`
+(debug) at total (main.rf:4:9)
+   4 |         sum += x;
+(debug) #0 total (main.rf:4:9)
+#1 main (main.rf:10:13)
+(debug) x = 1
+(debug) at main (main.rf:10:13)
+  10 |     let t = total([1, 2]);
+(debug) no variable named `x` has a value here
+(debug) name = \"ruffle\"
+(debug) at total (main.rf:4:9)
+   4 |         sum += x;
+(debug) at main (main.rf:11:5)
+  11 |     println(name);
+(debug) at main (main.rf:8:11)
+   8 | fn main() {
+(debug) unknown command `what`, which `help` lists
+(debug) "
+        );
+        // It was quit after it printed the name, but before it returned
+        assert_eq!(program_output, b"ruffle\n");
+    }
+
+    #[test]
+    fn test_next_over_generator() {
+        let source = "fn count(n: int) Gen<int> {
+    let mut i = 0;
+    while i < n {
+        yield i;
+        i += 1;
+    }
+}
+fn total() int {
+    let mut sum = 0;
+    for x in count(2) {
+        sum += x;
+    }
+    sum
+}
+fn main() {
+    println(total());
+}";
+        // The loop's header resumes the generator, which `next` steps over, like a `while` loop's
+        // condition, so the body's last line goes back to it rather than to the caller
+        let commands = "break 11\ncontinue\nnext\nnext\nnext\nnext\n";
+        let (output, program_output) = debugged(source, commands);
+        assert_eq!(
+            output,
+            "at main (main.rf:16:13)
+  16 |     println(total());
+(debug) breakpoint at main.rf:11
+(debug) at total (main.rf:11:9)
+  11 |         sum += x;
+(debug) at total (main.rf:10:14)
+  10 |     for x in count(2) {
+(debug) at total (main.rf:11:9)
+  11 |         sum += x;
+(debug) at total (main.rf:10:14)
+  10 |     for x in count(2) {
+(debug) at main (main.rf:15:11)
+  15 | fn main() {
+(debug) "
+        );
+        assert_eq!(program_output, b"1\n");
+    }
+}
@@ -0,0 +1,179 @@
+//! Errors and warnings from the passes after parsing, which can point at more than one place in
+//! the source code.
+
+use std::fmt::Display;
+
+use crate::{lexer::Span, source_map::SourceMap, utils::rows_cols_index};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Extra information attached to a diagnostic, optionally pointing at another span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+/// A message about a span of the source code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message, span)
+        }
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>, span: Option<Span>) -> Self {
+        self.notes.push(Note {
+            message: message.into(),
+            span,
+        });
+        self
+    }
+
+    /// Pairs the diagnostic with the source code it refers to, so it can be displayed.
+    pub fn with_source<'a>(&'a self, source: &'a str) -> SourceDiagnostic<'a> {
+        SourceDiagnostic {
+            diagnostic: self,
+            source,
+        }
+    }
+
+    /// Pairs the diagnostic with the files of a program, so it can be displayed with the path of
+    /// the file it's in.
+    pub fn with_source_map<'a>(&'a self, source_map: &'a SourceMap) -> MappedDiagnostic<'a> {
+        MappedDiagnostic {
+            diagnostic: self,
+            source_map,
+        }
+    }
+}
+
+/// A diagnostic along with the source code it refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceDiagnostic<'a> {
+    pub diagnostic: &'a Diagnostic,
+    pub source: &'a str,
+}
+
+impl<'a> Display for SourceDiagnostic<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let diagnostic = self.diagnostic;
+        let (rows, cols) = rows_cols_index(self.source, diagnostic.span.start);
+        writeln!(f, "{} at {}:{}:", diagnostic.severity, rows, cols)?;
+        writeln!(f, "{}", &self.source[diagnostic.span.clone()])?;
+        writeln!(f, "^ {}", diagnostic.message)?;
+        for note in &diagnostic.notes {
+            match &note.span {
+                Some(span) => {
+                    let (rows, cols) = rows_cols_index(self.source, span.start);
+                    writeln!(f, "note at {}:{}: {}", rows, cols, note.message)?;
+                }
+                None => writeln!(f, "note: {}", note.message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A diagnostic along with the files of the program it refers to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedDiagnostic<'a> {
+    pub diagnostic: &'a Diagnostic,
+    pub source_map: &'a SourceMap,
+}
+
+impl<'a> MappedDiagnostic<'a> {
+    /// Formats the location of `offset` as `path:row:col`.
+    fn location(&self, offset: usize) -> String {
+        match self.source_map.location(offset) {
+            Some(location) => location.to_string(),
+            None => "<unknown>".to_string(),
+        }
+    }
+}
+
+impl<'a> Display for MappedDiagnostic<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let diagnostic = self.diagnostic;
+        writeln!(
+            f,
+            "{} at {}:",
+            diagnostic.severity,
+            self.location(diagnostic.span.start)
+        )?;
+        writeln!(f, "{}", &self.source_map.text()[diagnostic.span.clone()])?;
+        writeln!(f, "^ {}", diagnostic.message)?;
+        for note in &diagnostic.notes {
+            match &note.span {
+                Some(span) => {
+                    writeln!(f, "note at {}: {}", self.location(span.start), note.message)?
+                }
+                None => writeln!(f, "note: {}", note.message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_diagnostic() {
+        let source = "let value = 1;\nprint(valu);";
+        let diagnostic = Diagnostic::error("cannot find value `valu` in this scope", 21..25)
+            .with_note("a similar name exists: `value`", Some(4..9))
+            .with_note("names are case sensitive", None);
+
+        assert_eq!(
+            diagnostic.with_source(source).to_string(),
+            "error at 2:7:\nvalu\n^ cannot find value `valu` in this scope\nnote at 1:5: a similar name exists: `value`\nnote: names are case sensitive\n"
+        );
+    }
+
+    #[test]
+    fn test_display_mapped_diagnostic() {
+        let mut source_map = SourceMap::new();
+        source_map.add_file("main.rf", "mod util;\n");
+        source_map.add_file("util.rf", "fn f() {\n    x\n}");
+        let diagnostic = Diagnostic::error("cannot find value `x` in this scope", 23..24)
+            .with_note("the module is declared here", Some(0..9));
+
+        assert_eq!(
+            diagnostic.with_source_map(&source_map).to_string(),
+            "error at util.rf:2:5:\nx\n^ cannot find value `x` in this scope\nnote at main.rf:1:1: the module is declared here\n"
+        );
+    }
+}
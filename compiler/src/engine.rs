@@ -0,0 +1,387 @@
+//! Embeds programs in a Rust application as scripts. An [`Engine`] compiles a script to bytecode,
+//! and an [`Instance`] of it runs in the VM, where the host calls its functions by path. Scripts
+//! call the host's functions through `extern fn` declarations, and read the host's values the
+//! same way, as functions without parameters.
+//!
+//! Values cross in both directions as [`Value`]s, which convert to and from the Rust types they
+//! hold with `into()` and `try_into()`. A script that can't be trusted runs within [`Limits`],
+//! which stop it with [`Error::LimitExceeded`] instead of letting it run forever or take all of
+//! the host's memory.
+
+use std::{collections::HashMap, error, fmt::Display, path::Path};
+
+use crate::{
+    codegen::bytecode::{self, GcConfig, Module, Vm},
+    console::Console,
+    ffi::Externs,
+    flow, hir,
+    interpreter::Panic,
+    loader::load_source,
+    mir::{self, opt::OptLevel},
+    resolve,
+    source_map::SourceMap,
+    typeck::{self, Ty},
+};
+
+pub use crate::{
+    ffi::Value,
+    limits::{Limit, Limits},
+};
+
+/// Why a script couldn't be compiled or run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The errors of a script that doesn't compile, each shown with the source it points at.
+    Compile(Vec<String>),
+    /// The message of a script that panicked, with the calls that were running.
+    Panic(String),
+    /// A call to a function that the script doesn't have, or that doesn't take the arguments.
+    Call(String),
+    /// A script that went past one of the engine's limits, which stopped it.
+    LimitExceeded(Limit),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Compile(errors) => write!(f, "{}", errors.join("\n")),
+            Error::Panic(message) | Error::Call(message) => write!(f, "{}", message),
+            Error::LimitExceeded(limit) => write!(f, "script stopped: {}", limit),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Compiles scripts and runs them with the host functions registered with it.
+#[derive(Debug, Clone, Default)]
+pub struct Engine {
+    host: Externs,
+    gc: GcConfig,
+    limits: Limits,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a function that scripts call through `extern fn name(...)`, replacing any that
+    /// has the name already.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        func: impl Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    ) {
+        self.host.register(name, func);
+    }
+
+    /// Registers a value that scripts read through `extern fn name() ty`.
+    pub fn define(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        let value = value.into();
+        self.host.register(name, move |_| Ok(value.clone()));
+    }
+
+    /// Sets how much each call of a script's functions can do, and how much memory its instances
+    /// can keep.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Compiles a script, as if it was read from the file at `path`, which is where the modules it
+    /// declares are loaded from and what its errors point at.
+    pub fn compile(&self, path: impl AsRef<Path>, source: &str) -> Result<Script, Error> {
+        let loaded = load_source(path, source);
+        let program = &loaded.program;
+        let res = resolve::resolve(program);
+        let types = typeck::check(program, &res);
+        let flow = flow::check(program, &res, &types);
+        let mut diagnostics = loaded.errors.clone();
+        diagnostics.extend(res.errors.iter().map(|error| error.to_diagnostic()));
+        diagnostics.extend(types.errors.iter().map(|error| error.to_diagnostic()));
+        diagnostics.extend(flow.errors.iter().map(|error| error.to_diagnostic()));
+        if !diagnostics.is_empty() {
+            let errors = (diagnostics.iter())
+                .map(|diagnostic| diagnostic.with_source_map(&loaded.source_map).to_string());
+            return Err(Error::Compile(errors.collect()));
+        }
+
+        let mut program = mir::build(&hir::lower(program, &res, &types), &res);
+        mir::opt::optimize(&mut program, OptLevel::O1);
+        let mut module = bytecode::emit(&program);
+        bytecode::peephole(&mut module);
+        // Functions in different modules can share a name, so they're found by their path
+        let mut fns = HashMap::new();
+        for (i, func) in program.fns.iter().enumerate() {
+            let Some(path) = func.def.and_then(|def| res.path(def)) else {
+                continue;
+            };
+            let index = u16::try_from(i).map_err(|_| {
+                Error::Compile(vec![format!(
+                    "the script has {} functions, but the VM can only call {}",
+                    program.fns.len(),
+                    u16::MAX as usize + 1
+                )])
+            })?;
+            let params = func.params().iter().map(|&param| func.value_ty(param));
+            let signature = Signature {
+                index,
+                params: params.cloned().collect(),
+                ret: func.ret.clone(),
+            };
+            fns.insert(path, signature);
+        }
+        Ok(Script {
+            module,
+            source_map: loaded.source_map,
+            fns,
+        })
+    }
+
+    /// Starts running a script, initializing its globals in order, with `console` as the input
+    /// and output of `print`, `println` and `input`.
+    pub fn instantiate<'a>(
+        &'a self,
+        script: &'a Script,
+        console: Console<'a>,
+    ) -> Result<Instance<'a>, Error> {
+        let vm = Vm::new(&script.module, self.gc, self.limits, console, &self.host)
+            .map_err(|panic| script.error(panic))?;
+        Ok(Instance { vm, script })
+    }
+}
+
+/// A compiled script, which can be instantiated any number of times.
+#[derive(Debug, Clone)]
+pub struct Script {
+    module: Module,
+    source_map: SourceMap,
+    /// The functions the host can call, by their path from the root module.
+    fns: HashMap<String, Signature>,
+}
+
+impl Script {
+    /// Returns the error of a call that failed, with where it was in the script if it panicked.
+    fn error(&self, panic: Panic) -> Error {
+        match panic.limit {
+            Some(limit) => Error::LimitExceeded(limit),
+            None => Error::Panic(panic.with_source_map(&self.source_map).to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Signature {
+    /// The function of the module.
+    index: u16,
+    params: Vec<Ty>,
+    ret: Ty,
+}
+
+/// A running script, whose globals are initialized once for all of the calls to it.
+pub struct Instance<'a> {
+    vm: Vm<'a>,
+    script: &'a Script,
+}
+
+impl Instance<'_> {
+    /// Calls a function of the script by its path, such as `area` or `shapes::Circle::new`. It has
+    /// to take and return values that cross to the host: numbers, `bool`s, `char`s, strings and
+    /// `()`.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, Error> {
+        let signature = (self.script.fns.get(name))
+            .ok_or_else(|| Error::Call(format!("the script has no function named `{}`", name)))?;
+        if !crosses(&signature.ret) {
+            return Err(Error::Call(format!(
+                "`{}` returns a `{}`, which can't cross to the host",
+                name, signature.ret
+            )));
+        }
+        if args.len() != signature.params.len() {
+            return Err(Error::Call(format!(
+                "`{}` takes {} arguments, but {} were given",
+                name,
+                signature.params.len(),
+                args.len()
+            )));
+        }
+        if let Some((arg, param)) = (args.iter().zip(&signature.params)).find(|(a, p)| !a.is(p)) {
+            return Err(Error::Call(format!(
+                "`{}` takes a `{}`, but {:?} was given",
+                name, param, arg
+            )));
+        }
+
+        let args = (args.iter())
+            .map(|arg| self.vm.from_host(arg.clone()))
+            .collect();
+        let value =
+            (self.vm.call(signature.index, args)).map_err(|panic| self.script.error(panic))?;
+        Ok(self
+            .vm
+            .to_host(value)
+            .expect("the return type crosses to the host"))
+    }
+}
+
+/// Returns whether values of a type cross between a script and its host.
+fn crosses(ty: &Ty) -> bool {
+    matches!(
+        ty,
+        Ty::Int(_) | Ty::Float | Ty::Bool | Ty::Char | Ty::String
+    ) || ty.is_unit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine() {
+        let mut engine = Engine::new();
+        engine.register("scale", |args| match args {
+            [Value::Int(x)] => Ok(Value::Int(x * 10)),
+            _ => Err("expected an integer".to_string()),
+        });
+        engine.define("bonus", 5);
+        let source = "extern fn scale(x: int) int;
+extern fn bonus() int;
+static SIDES = 4;
+fn area(w: int, h: int) int {
+    scale(w * h) + bonus()
+}
+fn sides() int { SIDES }
+fn greet(name: string) string { \"hi \" + name }
+fn first(xs: [int]) int { xs[0] }
+fn pair() (int, int) { (1, 2) }
+fn fail() { panic(\"no\"); }";
+        let script = engine.compile("script.rf", source).unwrap();
+        let mut output = Vec::new();
+        let console = Console::new(&b""[..], &mut output);
+        let mut instance = engine.instantiate(&script, console).unwrap();
+
+        assert_eq!(
+            instance.call("area", &[2.into(), 3.into()]),
+            Ok(Value::Int(65))
+        );
+        assert_eq!(
+            instance
+                .call("sides", &[])
+                .map(|n| i32::try_from(n).unwrap()),
+            Ok(4)
+        );
+        assert_eq!(
+            instance.call("greet", &["ann".into()]),
+            Ok(Value::String("hi ann".to_string()))
+        );
+        assert_eq!(
+            instance.call("area", &[1.5.into(), 1.into()]),
+            Err(Error::Call(
+                "`area` takes a `i32`, but Float(1.5) was given".to_string()
+            ))
+        );
+        assert_eq!(
+            instance.call("area", &[1.into()]),
+            Err(Error::Call(
+                "`area` takes 2 arguments, but 1 were given".to_string()
+            ))
+        );
+        assert_eq!(
+            instance.call("first", &[1.into()]),
+            Err(Error::Call(
+                "`first` takes a `[i32]`, but Int(1) was given".to_string()
+            ))
+        );
+        assert_eq!(
+            instance.call("pair", &[]),
+            Err(Error::Call(
+                "`pair` returns a `(i32, i32)`, which can't cross to the host".to_string()
+            ))
+        );
+        assert_eq!(
+            instance.call("volume", &[]),
+            Err(Error::Call(
+                "the script has no function named `volume`".to_string()
+            ))
+        );
+        let Err(Error::Panic(message)) = instance.call("fail", &[]) else {
+            panic!("expected a panic");
+        };
+        assert!(message.starts_with("panicked: no"), "{}", message);
+
+        let Err(Error::Compile(errors)) = engine.compile("bad.rf", "fn f() int { true }") else {
+            panic!("expected errors");
+        };
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_paths() {
+        let engine = Engine::new();
+        let source = "mod shapes {
+    pub struct Square { side: int }
+    impl Square {
+        pub fn area(side: int) int { side * side }
+    }
+    pub fn sides() int { 4 }
+}
+fn sides() int { 3 }
+fn main() { println(shapes::sides() + sides()); }";
+        let script = engine.compile("script.rf", source).unwrap();
+        let console = Console::new(&b""[..], std::io::sink());
+        let mut instance = engine.instantiate(&script, console).unwrap();
+
+        assert_eq!(instance.call("sides", &[]), Ok(Value::Int(3)));
+        assert_eq!(instance.call("shapes::sides", &[]), Ok(Value::Int(4)));
+        assert_eq!(
+            instance.call("shapes::Square::area", &[3.into()]),
+            Ok(Value::Int(9))
+        );
+        assert_eq!(
+            instance.call("area", &[3.into()]),
+            Err(Error::Call(
+                "the script has no function named `area`".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut engine = Engine::new();
+        engine.set_limits(Limits {
+            fuel: Some(1_000_000),
+            depth: Some(50),
+            memory: Some(1 << 16),
+        });
+        let source = "fn spin() { while true {} }
+fn depth(n: int) int { if n == 0 { 0 } else { depth(n - 1) + 1 } }
+fn hoard(n: int) int {
+    let mut xs = [0];
+    let mut i = 0;
+    while i < n { xs.push(i); i += 1; }
+    xs.len()
+}";
+        let script = engine.compile("script.rf", source).unwrap();
+        let console = Console::new(&b""[..], std::io::sink());
+        let mut instance = engine.instantiate(&script, console).unwrap();
+
+        assert_eq!(
+            instance.call("spin", &[]),
+            Err(Error::LimitExceeded(Limit::Fuel))
+        );
+        assert_eq!(instance.call("depth", &[40.into()]), Ok(Value::Int(40)));
+        assert_eq!(
+            instance.call("depth", &[60.into()]),
+            Err(Error::LimitExceeded(Limit::Depth))
+        );
+        assert_eq!(instance.call("hoard", &[100.into()]), Ok(Value::Int(101)));
+        assert_eq!(
+            instance.call("hoard", &[100_000.into()]),
+            Err(Error::LimitExceeded(Limit::Memory))
+        );
+        assert_eq!(
+            Error::LimitExceeded(Limit::Fuel).to_string(),
+            "script stopped: out of fuel"
+        );
+    }
+}
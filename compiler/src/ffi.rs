@@ -0,0 +1,184 @@
+//! The functions of the host that programs declare with `extern fn` and call like their own.
+//!
+//! The interpreter and the VM call the Rust functions registered in [`Externs`] by the names of
+//! the declarations. Compiled code calls C functions with those names instead, which the linker
+//! finds in the libraries given with `--link` or the manifest's `link`, or the JIT looks up in
+//! the running process, refusing to compile a program that calls one it doesn't have.
+//! WebAssembly modules only import WASI, so they panic when they call one. Only integers, floats,
+//! `bool`s and `char`s cross between the two, so that they mean the same to both sides whatever
+//! runs the program.
+
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+use crate::typeck::Ty;
+
+/// An argument of a host function, or what it returns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    /// A string, which only crosses to and from the functions of a program that an
+    /// [`Engine`](crate::engine::Engine) calls, never to a host function.
+    String(String),
+    /// What a function that returns nothing returns.
+    Unit,
+}
+
+impl Value {
+    /// Returns whether the value is one of a type that crosses to the host, or for `()`, the
+    /// unit value.
+    pub fn is(&self, ty: &Ty) -> bool {
+        match (self, ty) {
+            (Value::Int(value), Ty::Int(int)) => int.min() <= *value && *value <= int.max(),
+            (Value::Float(_), Ty::Float)
+            | (Value::Bool(_), Ty::Bool)
+            | (Value::Char(_), Ty::Char)
+            | (Value::String(_), Ty::String) => true,
+            (Value::Unit, ty) => ty.is_unit(),
+            _ => false,
+        }
+    }
+}
+
+macro_rules! from_int {
+    ($($int:ty),*) => {$(
+        impl From<$int> for Value {
+            fn from(value: $int) -> Self {
+                Value::Int(value.into())
+            }
+        }
+
+        impl TryFrom<Value> for $int {
+            type Error = Value;
+
+            fn try_from(value: Value) -> Result<Self, Value> {
+                match value {
+                    Value::Int(int) => int.try_into().map_err(|_| value),
+                    _ => Err(value),
+                }
+            }
+        }
+    )*};
+}
+
+from_int!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+macro_rules! from_value {
+    ($($variant:ident($ty:ty)),*) => {$(
+        impl From<$ty> for Value {
+            fn from(value: $ty) -> Self {
+                Value::$variant(value)
+            }
+        }
+
+        impl TryFrom<Value> for $ty {
+            type Error = Value;
+
+            fn try_from(value: Value) -> Result<Self, Value> {
+                match value {
+                    Value::$variant(value) => Ok(value),
+                    _ => Err(value),
+                }
+            }
+        }
+    )*};
+}
+
+from_value!(Float(f64), Bool(bool), Char(char), String(String));
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Unit
+    }
+}
+
+/// A host function, which fails with a message that the program panics with.
+pub type ExternFn = Arc<dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync>;
+
+/// The host functions that programs can call, by name.
+#[derive(Clone, Default)]
+pub struct Externs {
+    fns: HashMap<String, ExternFn>,
+}
+
+impl Externs {
+    /// Registers a function for `extern fn` declarations with the name to call, replacing any
+    /// that has the name already.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        func: impl Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    ) {
+        self.fns.insert(name.into(), Arc::new(func));
+    }
+
+    /// Calls the function registered with a name, which has to return a value of `ret`.
+    pub fn call(&self, name: &str, args: &[Value], ret: &Ty) -> Result<Value, String> {
+        let func = (self.fns.get(name))
+            .ok_or_else(|| format!("no host function named `{}` is registered", name))?;
+        let value = func(args)?;
+        if !value.is(ret) {
+            return Err(format!(
+                "host function `{}` returned {:?} instead of a `{}`",
+                name, value, ret
+            ));
+        }
+        Ok(value)
+    }
+}
+
+impl Debug for Externs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<_> = self.fns.keys().collect();
+        names.sort();
+        f.debug_set().entries(names).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typeck::IntTy;
+
+    #[test]
+    fn test_externs() {
+        let mut externs = Externs::default();
+        externs.register("twice", |args| match args {
+            [Value::Int(x)] => Ok(Value::Int(x * 2)),
+            _ => Err("expected an integer".to_string()),
+        });
+        let u8 = Ty::Int(IntTy::U8);
+
+        assert_eq!(
+            externs.call("twice", &[Value::Int(100)], &u8),
+            Ok(Value::Int(200))
+        );
+        assert_eq!(
+            externs.call("twice", &[Value::Int(200)], &u8),
+            Err("host function `twice` returned Int(400) instead of a `u8`".to_string())
+        );
+        assert_eq!(
+            externs.call("twice", &[Value::Bool(true)], &u8),
+            Err("expected an integer".to_string())
+        );
+        assert_eq!(
+            externs.call("half", &[Value::Int(1)], &u8),
+            Err("no host function named `half` is registered".to_string())
+        );
+        assert!(Value::Unit.is(&Ty::unit()));
+        assert_eq!(Value::from(-3i8), Value::Int(-3));
+        assert_eq!(u8::try_from(Value::Int(255)), Ok(255));
+        assert_eq!(u8::try_from(Value::Int(256)), Err(Value::Int(256)));
+        assert_eq!(String::try_from(Value::from("hi")), Ok("hi".to_string()));
+        assert_eq!(bool::try_from(Value::Unit), Err(Value::Unit));
+        assert_eq!(format!("{:?}", externs), "{\"twice\"}");
+    }
+}
@@ -0,0 +1,870 @@
+//! Control-flow checks on function bodies, such as finding the paths through a function that
+//! reach its end without producing the value it returns, variables read before they're assigned,
+//! jumps that have nowhere to go, and code that can never run.
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+};
+
+use crate::{
+    ast::*,
+    diagnostic::Diagnostic,
+    lexer::Span,
+    pretty::print_type,
+    resolve::{DefId, Resolution},
+    typeck::{Ty, TypeckResults},
+    visit::{self, Visit},
+};
+
+/// How a path reaches the end of a function without a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallOff {
+    /// A block without a final expression.
+    BlockEnd,
+    /// An `if` without an `else`, which does nothing when its condition is false.
+    MissingElse,
+    /// A loop, which can finish without producing a value.
+    LoopEnd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlowError {
+    /// A path through a function with a return type that ends without a value. `span` is the
+    /// branch where the path falls off, and `ret` is the declared return type.
+    MissingReturn {
+        name: String,
+        ty: String,
+        ret: Span,
+        span: Span,
+        fall_off: FallOff,
+    },
+    /// A `break` or `continue`, named by `keyword`, that isn't inside a loop. Loops outside of a
+    /// closure don't count, as the closure can be called after they've finished.
+    OutsideLoop { keyword: &'static str, span: Span },
+    /// A `return` that isn't inside a function or closure, such as in a constant's value.
+    ReturnOutsideFn { span: Span },
+    /// A read of a variable that was declared without a value, on a path where it may not have
+    /// been assigned yet. `declared` is where the variable is declared.
+    Unassigned {
+        name: String,
+        declared: Span,
+        span: Span,
+    },
+}
+
+impl FlowError {
+    pub fn span(&self) -> &Span {
+        match self {
+            FlowError::MissingReturn { span, .. }
+            | FlowError::OutsideLoop { span, .. }
+            | FlowError::ReturnOutsideFn { span }
+            | FlowError::Unassigned { span, .. } => span,
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.to_string(), self.span().clone());
+        match self {
+            FlowError::MissingReturn { name, ret, .. } => diagnostic.with_note(
+                format!("the return type of `{}` is declared here", name),
+                Some(ret.clone()),
+            ),
+            FlowError::Unassigned { name, declared, .. } => diagnostic.with_note(
+                format!("`{}` is declared here without a value", name),
+                Some(declared.clone()),
+            ),
+            _ => diagnostic,
+        }
+    }
+}
+
+impl Error for FlowError {}
+
+impl Display for FlowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FlowError::MissingReturn {
+                name, ty, fall_off, ..
+            } => {
+                write!(f, "`{}` must return `{}`, but ", name, ty)?;
+                match fall_off {
+                    FallOff::BlockEnd => write!(f, "this block ends without a value"),
+                    FallOff::MissingElse => write!(f, "this `if` has no `else`"),
+                    FallOff::LoopEnd => write!(f, "this loop can end without a value"),
+                }
+            }
+            FlowError::OutsideLoop { keyword, .. } => write!(f, "`{}` outside of a loop", keyword),
+            FlowError::ReturnOutsideFn { .. } => write!(f, "`return` outside of a function"),
+            FlowError::Unassigned { name, .. } => {
+                write!(f, "use of possibly-unassigned variable `{}`", name)
+            }
+        }
+    }
+}
+
+/// A warning about code that can never run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lint {
+    /// A statement or a block's final expression, named by `kind`, that comes after one that
+    /// never lets control continue. `cause` is the statement that diverges.
+    Unreachable {
+        kind: &'static str,
+        span: Span,
+        cause: Span,
+    },
+}
+
+impl Lint {
+    pub fn span(&self) -> &Span {
+        match self {
+            Lint::Unreachable { span, .. } => span,
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::warning(self.to_string(), self.span().clone());
+        match self {
+            Lint::Unreachable { cause, .. } => {
+                diagnostic.with_note("any code after this is unreachable", Some(cause.clone()))
+            }
+        }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lint::Unreachable { kind, .. } => write!(f, "unreachable {}", kind),
+        }
+    }
+}
+
+/// The errors and warnings found by checking the control flow of a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlowResults {
+    pub errors: Vec<FlowError>,
+    pub lints: Vec<Lint>,
+}
+
+/// Checks the control flow of every function in a program whose types have been checked. Calls
+/// to functions that return `!`, such as `panic`, are known to never return.
+pub fn check(program: &Program, res: &Resolution, typeck: &TypeckResults) -> FlowResults {
+    let mut checker = Checker {
+        res,
+        types: &typeck.types,
+        errors: Vec::new(),
+        lints: Vec::new(),
+        loops: 0,
+        in_fn: false,
+    };
+    checker.visit_program(program);
+    FlowResults {
+        errors: checker.errors,
+        lints: checker.lints,
+    }
+}
+
+struct Checker<'a> {
+    res: &'a Resolution,
+    types: &'a HashMap<Span, Ty>,
+    errors: Vec<FlowError>,
+    lints: Vec<Lint>,
+    /// The number of loops around the current node, inside the current function or closure.
+    loops: usize,
+    /// Whether the current node is inside a function or closure, which `return` leaves.
+    in_fn: bool,
+}
+
+impl Checker<'_> {
+    /// Reports the first path through a function's body that doesn't produce its return value, and
+    /// the variables it reads before they're assigned.
+    fn check_fn(&mut self, name: &Ident, ret: &Option<TypeExpr>, body: &Block) {
+        let mut assignments = Assignments {
+            res: self.res,
+            types: self.types,
+            unassigned: Some(HashSet::new()),
+            breaks: Vec::new(),
+            errors: Vec::new(),
+        };
+        assignments.visit_block(body);
+        self.errors.append(&mut assignments.errors);
+
+        let Some(ret) = ret else {
+            return;
+        };
+        // A generator's body runs as it's resumed, so it never produces the generator
+        if matches!(&ret.kind, TypeExprKind::Tuple(elems) if elems.is_empty()) || yields(body) {
+            return;
+        }
+
+        if let Some((span, fall_off)) = block_missing_value(body, self.types) {
+            self.errors.push(FlowError::MissingReturn {
+                name: name.name.clone(),
+                ty: print_type(ret),
+                ret: ret.span.clone(),
+                span,
+                fall_off,
+            });
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Checker<'_> {
+    fn visit_item(&mut self, item: &'ast Item) {
+        let outer = (self.loops, self.in_fn);
+        self.loops = 0;
+        self.in_fn = false;
+        match &item.kind {
+            ItemKind::Fn(decl) => {
+                self.check_fn(&decl.name, &decl.ret, &decl.body);
+                self.in_fn = true;
+            }
+            ItemKind::Trait(decl) => {
+                for method in &decl.methods {
+                    if let Some(body) = &method.body {
+                        self.check_fn(&method.name, &method.ret, body);
+                    }
+                }
+                self.in_fn = true;
+            }
+            _ => {}
+        }
+        visit::walk_item(self, item);
+        (self.loops, self.in_fn) = outer;
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        // Only the first unreachable statement is reported, as the rest follow from it
+        let cause = block
+            .stmts
+            .iter()
+            .position(|stmt| stmt_diverges(stmt, self.types));
+        if let Some(cause) = cause {
+            let unreachable = block.stmts[cause + 1..]
+                .iter()
+                .find(|stmt| !matches!(stmt.kind, StmtKind::Item(_)))
+                .map(|stmt| ("statement", stmt.span.clone()))
+                .or_else(|| {
+                    block
+                        .tail
+                        .as_ref()
+                        .map(|tail| ("expression", tail.span.clone()))
+                });
+            if let Some((kind, span)) = unreachable {
+                self.lints.push(Lint::Unreachable {
+                    kind,
+                    span,
+                    cause: block.stmts[cause].span.clone(),
+                });
+            }
+        }
+        visit::walk_block(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        let keyword = match stmt.kind {
+            StmtKind::Break => "break",
+            StmtKind::Continue => "continue",
+            StmtKind::Return(_) if !self.in_fn => {
+                self.errors.push(FlowError::ReturnOutsideFn {
+                    span: stmt.span.clone(),
+                });
+                return visit::walk_stmt(self, stmt);
+            }
+            _ => return visit::walk_stmt(self, stmt),
+        };
+        if self.loops == 0 {
+            self.errors.push(FlowError::OutsideLoop {
+                keyword,
+                span: stmt.span.clone(),
+            });
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        match &expr.kind {
+            ExprKind::While { cond, body } => {
+                self.visit_expr(cond);
+                self.loops += 1;
+                self.visit_block(body);
+                self.loops -= 1;
+            }
+            ExprKind::Loop(body) => {
+                self.loops += 1;
+                self.visit_block(body);
+                self.loops -= 1;
+            }
+            ExprKind::For(for_loop) => {
+                self.visit_pattern(&for_loop.pattern);
+                self.visit_expr(&for_loop.iter);
+                self.loops += 1;
+                self.visit_block(&for_loop.body);
+                self.loops -= 1;
+            }
+            ExprKind::Closure(_) => {
+                let outer = (self.loops, self.in_fn);
+                (self.loops, self.in_fn) = (0, true);
+                visit::walk_expr(self, expr);
+                (self.loops, self.in_fn) = outer;
+            }
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+}
+
+/// Follows the paths through a function's body in the order it runs, reporting the reads of
+/// variables that were declared without a value and may not have been assigned on the way.
+/// Nested functions are checked on their own.
+struct Assignments<'a> {
+    res: &'a Resolution,
+    types: &'a HashMap<Span, Ty>,
+    /// The variables declared without a value that may still be unassigned, or `None` where the
+    /// code can't be reached.
+    unassigned: Option<HashSet<DefId>>,
+    /// What's unassigned at the `break`s out of each loop around the current node, innermost
+    /// last.
+    breaks: Vec<Option<HashSet<DefId>>>,
+    errors: Vec<FlowError>,
+}
+
+/// Joins what's unassigned on two paths that meet: a variable is unassigned if it is on either.
+fn join(a: Option<HashSet<DefId>>, b: Option<HashSet<DefId>>) -> Option<HashSet<DefId>> {
+    match (a, b) {
+        (Some(mut a), Some(b)) => {
+            a.extend(b);
+            Some(a)
+        }
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+impl Assignments<'_> {
+    /// Returns the local variable a path refers to, if it does.
+    fn local(&self, path: &Path) -> Option<DefId> {
+        let [segment] = &path.segments[..] else {
+            return None;
+        };
+        self.res.lookup(&segment.span)
+    }
+}
+
+impl<'ast> Visit<'ast> for Assignments<'_> {
+    fn visit_item(&mut self, _item: &'ast Item) {}
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        match &stmt.kind {
+            StmtKind::Let {
+                pattern,
+                value: None,
+                ..
+            } => {
+                struct Bindings<'a, 'r>(&'r Resolution, &'a mut Option<HashSet<DefId>>);
+
+                impl<'ast> Visit<'ast> for Bindings<'_, '_> {
+                    fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+                        if let PatternKind::Ident(name) | PatternKind::Mut(name) = &pattern.kind {
+                            let def = self.0.lookup(&name.span);
+                            if let (Some(def), Some(unassigned)) = (def, self.1.as_mut()) {
+                                unassigned.insert(def);
+                            }
+                        }
+                        visit::walk_pattern(self, pattern);
+                    }
+                }
+
+                Bindings(self.res, &mut self.unassigned).visit_pattern(pattern);
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+                self.unassigned = None;
+            }
+            StmtKind::Break => {
+                if let Some(breaks) = self.breaks.last_mut() {
+                    *breaks = join(breaks.take(), self.unassigned.take());
+                }
+                self.unassigned = None;
+            }
+            StmtKind::Continue => self.unassigned = None,
+            _ => visit::walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        match &expr.kind {
+            ExprKind::Path(path) => {
+                let def = self.local(path);
+                if let (Some(def), Some(unassigned)) = (def, self.unassigned.as_mut()) {
+                    // Each variable is only reported once, at its first read
+                    if unassigned.remove(&def) {
+                        let def = self.res.def(def);
+                        self.errors.push(FlowError::Unassigned {
+                            name: def.name.clone(),
+                            declared: def.span.clone(),
+                            span: expr.span.clone(),
+                        });
+                    }
+                }
+            }
+            ExprKind::Assign { target, value } => {
+                self.visit_expr(value);
+                let assigned = match &target.kind {
+                    ExprKind::Path(path) => self.local(path),
+                    _ => None,
+                };
+                match (assigned, self.unassigned.as_mut()) {
+                    (Some(def), Some(unassigned)) => {
+                        unassigned.remove(&def);
+                    }
+                    (Some(_), None) => {}
+                    // Assigning to a field or an element reads the rest of the variable
+                    (None, _) => self.visit_expr(target),
+                }
+            }
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.visit_expr(cond);
+                let start = self.unassigned.clone();
+                self.visit_block(then_branch);
+                let then = std::mem::replace(&mut self.unassigned, start);
+                if let Some(else_branch) = else_branch {
+                    self.visit_expr(else_branch);
+                }
+                self.unassigned = join(then, self.unassigned.take());
+            }
+            ExprKind::Match(match_expr) => {
+                self.visit_expr(&match_expr.scrutinee);
+                let start = self.unassigned.clone();
+                let mut end = None;
+                for arm in &match_expr.arms {
+                    self.unassigned = start.clone();
+                    self.visit_expr(&arm.body);
+                    end = join(end, self.unassigned.take());
+                }
+                self.unassigned = if match_expr.arms.is_empty() {
+                    start
+                } else {
+                    end
+                };
+            }
+            // The body of a `while` or `for` loop may not run at all, so what it assigns only
+            // counts inside it
+            ExprKind::While { cond, body } => {
+                self.visit_expr(cond);
+                let start = self.unassigned.clone();
+                self.breaks.push(None);
+                self.visit_block(body);
+                self.breaks.pop();
+                self.unassigned = start;
+            }
+            ExprKind::For(for_loop) => {
+                self.visit_expr(&for_loop.iter);
+                let start = self.unassigned.clone();
+                self.breaks.push(None);
+                self.visit_block(&for_loop.body);
+                self.breaks.pop();
+                self.unassigned = start;
+            }
+            // A `loop` is only left by a `break`
+            ExprKind::Loop(body) => {
+                self.breaks.push(None);
+                self.visit_block(body);
+                self.unassigned = self.breaks.pop().unwrap();
+            }
+            // A closure reads the variables it captures when it's made, and whatever it assigns
+            // is its own copy
+            ExprKind::Closure(closure) => {
+                let start = self.unassigned.clone();
+                let breaks = std::mem::take(&mut self.breaks);
+                self.visit_expr(&closure.body);
+                (self.unassigned, self.breaks) = (start, breaks);
+            }
+            ExprKind::Binary {
+                op: BinaryOp::And | BinaryOp::Or,
+                lhs,
+                rhs,
+            } => {
+                self.visit_expr(lhs);
+                let start = self.unassigned.clone();
+                self.visit_expr(rhs);
+                self.unassigned = join(start, self.unassigned.take());
+            }
+            ExprKind::Call { .. } | ExprKind::MethodCall { .. } => {
+                visit::walk_expr(self, expr);
+                if self.types.get(&expr.span) == Some(&Ty::Never) {
+                    self.unassigned = None;
+                }
+            }
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+}
+
+/// Returns the branch where a path through a block ends without a value, if there is one.
+/// `types` are the types of the expressions, which say which calls never return.
+pub(crate) fn block_missing_value(
+    block: &Block,
+    types: &HashMap<Span, Ty>,
+) -> Option<(Span, FallOff)> {
+    if block.stmts.iter().any(|stmt| stmt_diverges(stmt, types)) {
+        return None;
+    }
+    match &block.tail {
+        Some(tail) => expr_missing_value(tail, types),
+        // Point at the closing brace
+        None => Some((block.span.end - 1..block.span.end, FallOff::BlockEnd)),
+    }
+}
+
+/// Returns the branch where a path through an expression ends without a value, if there is one.
+/// Only expressions that contain branches can fail to produce a value.
+fn expr_missing_value(expr: &Expr, types: &HashMap<Span, Ty>) -> Option<(Span, FallOff)> {
+    if diverges(expr, types) {
+        return None;
+    }
+    match &expr.kind {
+        ExprKind::Paren(inner) => expr_missing_value(inner, types),
+        ExprKind::Block(block) => block_missing_value(block, types),
+        ExprKind::If {
+            then_branch,
+            else_branch,
+            ..
+        } => block_missing_value(then_branch, types).or_else(|| match else_branch {
+            Some(else_branch) => expr_missing_value(else_branch, types),
+            None => Some((expr.span.clone(), FallOff::MissingElse)),
+        }),
+        ExprKind::Match(match_expr) => match_expr
+            .arms
+            .iter()
+            .find_map(|arm| expr_missing_value(&arm.body, types)),
+        ExprKind::While { .. } | ExprKind::Loop(_) | ExprKind::For(_) => {
+            Some((expr.span.clone(), FallOff::LoopEnd))
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether a statement never lets control continue to the next one.
+fn stmt_diverges(stmt: &Stmt, types: &HashMap<Span, Ty>) -> bool {
+    match &stmt.kind {
+        StmtKind::Return(_) | StmtKind::Break | StmtKind::Continue => true,
+        StmtKind::Expr(expr)
+        | StmtKind::Let {
+            value: Some(expr), ..
+        } => diverges(expr, types),
+        _ => false,
+    }
+}
+
+fn block_diverges(block: &Block, types: &HashMap<Span, Ty>) -> bool {
+    block.stmts.iter().any(|stmt| stmt_diverges(stmt, types))
+        || block
+            .tail
+            .as_deref()
+            .is_some_and(|tail| diverges(tail, types))
+}
+
+/// Returns whether every path through an expression leaves it with `return`, `break` or
+/// `continue`, calls a function that never returns, or loops forever.
+pub(crate) fn diverges(expr: &Expr, types: &HashMap<Span, Ty>) -> bool {
+    match &expr.kind {
+        ExprKind::Paren(inner) => diverges(inner, types),
+        ExprKind::Block(block) => block_diverges(block, types),
+        ExprKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => block_diverges(then_branch, types) && diverges(else_branch, types),
+        ExprKind::Match(match_expr) => {
+            let arms = &match_expr.arms;
+            diverges(&match_expr.scrutinee, types)
+                || (!arms.is_empty() && arms.iter().all(|arm| diverges(&arm.body, types)))
+        }
+        ExprKind::Call { .. } | ExprKind::MethodCall { .. } => {
+            types.get(&expr.span) == Some(&Ty::Never)
+        }
+        ExprKind::While { cond, body } => {
+            matches!(cond.kind, ExprKind::Literal(Literal::Bool(true))) && !breaks(body)
+        }
+        ExprKind::Loop(body) => !breaks(body),
+        _ => false,
+    }
+}
+
+/// Returns whether a loop body contains a `break` out of the loop, as opposed to one out of a
+/// loop nested inside it.
+fn breaks(body: &Block) -> bool {
+    struct FindBreak(bool);
+
+    impl<'ast> Visit<'ast> for FindBreak {
+        fn visit_item(&mut self, _item: &'ast Item) {}
+
+        fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+            self.0 |= matches!(stmt.kind, StmtKind::Break);
+            visit::walk_stmt(self, stmt);
+        }
+
+        fn visit_expr(&mut self, expr: &'ast Expr) {
+            if !matches!(
+                expr.kind,
+                ExprKind::While { .. }
+                    | ExprKind::Loop(_)
+                    | ExprKind::For(_)
+                    | ExprKind::Closure(_)
+            ) {
+                visit::walk_expr(self, expr);
+            }
+        }
+    }
+
+    let mut finder = FindBreak(false);
+    finder.visit_block(body);
+    finder.0
+}
+
+/// Returns whether a function's body contains a `yield`, which makes the function a generator.
+/// A `yield` in a closure or an item nested in the body doesn't count.
+pub(crate) fn yields(body: &Block) -> bool {
+    struct FindYield(bool);
+
+    impl<'ast> Visit<'ast> for FindYield {
+        fn visit_item(&mut self, _item: &'ast Item) {}
+
+        fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+            self.0 |= matches!(stmt.kind, StmtKind::Yield(_));
+            visit::walk_stmt(self, stmt);
+        }
+
+        fn visit_expr(&mut self, expr: &'ast Expr) {
+            if !matches!(expr.kind, ExprKind::Closure(_)) {
+                visit::walk_expr(self, expr);
+            }
+        }
+    }
+
+    let mut finder = FindYield(false);
+    finder.visit_block(body);
+    finder.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse_source, resolve::resolve, typeck};
+
+    fn check_source(source: &str) -> FlowResults {
+        let program = parse_source(source).unwrap();
+        let res = resolve(&program);
+        check(&program, &res, &typeck::check(&program, &res))
+    }
+
+    /// Returns the message and source text of each error in the program.
+    fn errors(source: &str) -> Vec<(String, &str)> {
+        check_source(source)
+            .errors
+            .iter()
+            .map(|error| (error.to_string(), &source[error.span().clone()]))
+            .collect()
+    }
+
+    #[test]
+    fn test_check_returns() {
+        let source = "
+fn tail() int { 1 }
+fn early(a: bool) int { if a { return 1; } return 2; }
+fn branches(a: bool) int { if a { 1 } else if !a { 2 } else { return 3; } }
+fn arms(a: Option<int>) int { match a { Some(x) => x, None => { return 0; } } }
+fn forever() int { while true { } }
+fn fails(a: bool) int { if a { 1 } else { panic(\"not a\"); } }
+fn unit() { }
+fn empty_unit() () { }
+trait Shape { fn sides() int; fn area() float { 1.0 } }";
+        assert_eq!(errors(source), vec![]);
+    }
+
+    #[test]
+    fn test_check_jumps() {
+        let source = "
+const X: int = { return 1; };
+fn f() {
+    break;
+    while true { continue; for i in xs { break; } let g = || { break; }; }
+    let h = |x| { return x; };
+    return;
+}
+trait T { fn t() { continue; } }";
+        assert_eq!(
+            errors(source),
+            vec![
+                ("`return` outside of a function".to_string(), "return 1;"),
+                ("`break` outside of a loop".to_string(), "break;"),
+                ("`break` outside of a loop".to_string(), "break;"),
+                ("`continue` outside of a loop".to_string(), "continue;"),
+            ]
+        );
+        let positions: Vec<_> = check_source(source)
+            .errors
+            .iter()
+            .map(|error| error.span().start)
+            .collect();
+        let breaks: Vec<_> = source.match_indices("break").map(|(i, _)| i).collect();
+        assert_eq!(positions[1..3], [breaks[0], breaks[2]]);
+    }
+
+    #[test]
+    fn test_check_missing_returns() {
+        let source = "
+fn empty() int { }
+fn no_else(a: bool) int { if a { 1 } }
+fn nested(a: bool) int { if a { 1 } else { let b = 2; } }
+fn arm(a: Option<int>) int { match a { Some(x) => x, None => { } } }
+fn loops() int { while true { break; } }
+fn breaks() int { loop { break; } }
+fn statement(a: bool) string { if a { return \"a\"; } else { 1; } }
+impl Point { fn area() float { for p in points { return 1.0; } } }
+fn outer() { fn inner() int { } }";
+        let expected = vec![
+            (
+                "`empty` must return `int`, but this block ends without a value",
+                "}",
+            ),
+            (
+                "`no_else` must return `int`, but this `if` has no `else`",
+                "if a { 1 }",
+            ),
+            (
+                "`nested` must return `int`, but this block ends without a value",
+                "}",
+            ),
+            (
+                "`arm` must return `int`, but this block ends without a value",
+                "}",
+            ),
+            (
+                "`loops` must return `int`, but this loop can end without a value",
+                "while true { break; }",
+            ),
+            (
+                "`breaks` must return `int`, but this loop can end without a value",
+                "loop { break; }",
+            ),
+            (
+                "`statement` must return `string`, but this block ends without a value",
+                "}",
+            ),
+            (
+                "`area` must return `float`, but this loop can end without a value",
+                "for p in points { return 1.0; }",
+            ),
+            (
+                "`inner` must return `int`, but this block ends without a value",
+                "}",
+            ),
+        ];
+        let found = errors(source);
+        assert_eq!(
+            found
+                .iter()
+                .map(|(message, text)| (message.as_str(), *text))
+                .collect::<Vec<_>>(),
+            expected
+        );
+
+        let error = &check_source(source).errors[2];
+        let diagnostic = error.to_diagnostic();
+        // The `let` block of the `else` branch is the one that falls off
+        assert_eq!(diagnostic.span.start, source.find("; } }").unwrap() + 2);
+        assert_eq!(
+            diagnostic.notes[0].message,
+            "the return type of `nested` is declared here"
+        );
+        assert_eq!(&source[diagnostic.notes[0].span.clone().unwrap()], "int");
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let source = "
+fn f(a: bool) int {
+    if a { return 1; }
+    return 2;
+    let b = 3;
+    b
+}
+fn g() int {
+    panic(\"g\");
+    fn inner() { }
+    1
+}
+fn h() { while true { break; } let c = 4; }";
+        let lints = check_source(source).lints;
+        let found: Vec<_> = lints
+            .iter()
+            .map(|lint| {
+                let Lint::Unreachable { cause, .. } = lint;
+                (
+                    lint.to_string(),
+                    &source[lint.span().clone()],
+                    &source[cause.clone()],
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "unreachable statement".to_string(),
+                    "let b = 3;",
+                    "return 2;"
+                ),
+                ("unreachable expression".to_string(), "1", "panic(\"g\");"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_unassigned() {
+        let source = "
+fn assigned(c: bool) int {
+    let mut a: int;
+    if c { a = 1; } else { return 0; }
+    let mut b: int;
+    loop { b = 2; break; }
+    let mut c: int;
+    match a { 1 => { c = 1; }, _ => panic(\"no\") }
+    a + b + c
+}
+fn unassigned(c: bool) int {
+    let x: int;
+    let y = x + x;
+    let mut z: int;
+    while c { z = 1; }
+    let mut w: int;
+    if c { w = 1; }
+    let f = |n: int| n + w;
+    z
+}";
+        let expected = vec![
+            ("use of possibly-unassigned variable `x`", "x"),
+            ("use of possibly-unassigned variable `w`", "w"),
+            ("use of possibly-unassigned variable `z`", "z"),
+        ];
+        let found = errors(source);
+        assert_eq!(
+            found
+                .iter()
+                .map(|(message, text)| (message.as_str(), *text))
+                .collect::<Vec<_>>(),
+            expected
+        );
+        let diagnostic = check_source(source).errors[0].to_diagnostic();
+        assert_eq!(diagnostic.span.start, source.find("x + x").unwrap());
+        assert_eq!(&source[diagnostic.notes[0].span.clone().unwrap()], "x");
+    }
+}
@@ -0,0 +1,94 @@
+//! The files that programs read and write with the `fs` module, which the interpreter and the VM
+//! share so that they behave the same, and the same as the runtime libraries of compiled code.
+//!
+//! Like those libraries, an operation doesn't return its error. It keeps the message of the error
+//! instead, or an empty one when it succeeds, for `fs_error()` to return, and the call of the
+//! `fs` function makes the `Result` from the two.
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+/// The message of the error that the last operation failed with, which is empty when it
+/// succeeded.
+#[derive(Debug, Default)]
+pub struct Files {
+    error: String,
+}
+
+impl Files {
+    /// Reads a file, which has to be UTF-8, returning an empty string when it can't be read.
+    pub fn read(&mut self, path: &str) -> String {
+        let read = fs::read(path).and_then(|bytes| {
+            String::from_utf8(bytes).map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+        });
+        self.keep(read).unwrap_or_default()
+    }
+
+    /// Writes a file, replacing it when it exists.
+    pub fn write(&mut self, path: &str, contents: &str) {
+        self.keep(fs::write(path, contents));
+    }
+
+    /// Returns whether a file or directory exists, which is `false` when it can't be found out.
+    pub fn exists(&mut self, path: &str) -> bool {
+        self.keep(Path::new(path).try_exists()).unwrap_or(false)
+    }
+
+    pub fn error(&self) -> &str {
+        &self.error
+    }
+
+    fn keep<T>(&mut self, result: io::Result<T>) -> Option<T> {
+        self.error = match &result {
+            Ok(_) => String::new(),
+            Err(error) => message(error),
+        };
+        result.ok()
+    }
+}
+
+/// Returns the message of an error, which is the same as the runtime libraries give for the errors
+/// programs usually meet.
+fn message(error: &io::Error) -> String {
+    match error.kind() {
+        ErrorKind::NotFound => "no such file or directory".to_string(),
+        ErrorKind::PermissionDenied => "permission denied".to_string(),
+        ErrorKind::IsADirectory => "is a directory".to_string(),
+        ErrorKind::NotADirectory => "not a directory".to_string(),
+        ErrorKind::InvalidData => "not valid UTF-8".to_string(),
+        kind => kind.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("ruffle-fs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        let path = path.to_str().unwrap();
+        let mut files = Files::default();
+
+        assert!(!files.exists(path));
+        assert_eq!(files.error(), "");
+        assert_eq!(files.read(path), "");
+        assert_eq!(files.error(), "no such file or directory");
+        files.write(path, "héllo\n");
+        assert_eq!(files.error(), "");
+        assert!(files.exists(path));
+        assert_eq!(files.read(path), "héllo\n");
+        files.write(dir.to_str().unwrap(), "");
+        assert_eq!(files.error(), "is a directory");
+
+        fs::write(path, [0xff, b'!']).unwrap();
+        assert_eq!(files.read(path), "");
+        assert_eq!(files.error(), "not valid UTF-8");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
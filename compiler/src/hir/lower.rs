@@ -0,0 +1,1923 @@
+//! Lowers a checked AST to the HIR.
+
+use std::collections::HashMap;
+
+use super::*;
+use crate::{
+    ast,
+    resolve::{DefKind, Resolution},
+    typeck::{array_method_ty, map_method_ty, Instance, IntTy, TypeckResults},
+    visit::{self, Visit},
+};
+
+/// Lowers a program that has been resolved and type checked. Expressions with errors become
+/// [`ExprKind::Error`], so only a program without errors lowers to something that can run.
+pub fn lower(program: &ast::Program, res: &Resolution, typeck: &TypeckResults) -> Program {
+    let mut collector = Collector {
+        res,
+        typeck,
+        adts: builtin_adts(),
+        fns: Vec::new(),
+        globals: Vec::new(),
+        externs: Vec::new(),
+    };
+    collector.visit_program(program);
+
+    let fields = collector
+        .adts
+        .iter()
+        .flat_map(|adt| &adt.variants)
+        .map(|variant| {
+            let names = variant.fields.iter().map(|field| field.name.clone());
+            (variant.def, names.collect())
+        })
+        .collect();
+    let lowerer = |ret, instance| Lowerer {
+        res,
+        typeck,
+        instance,
+        fields: &fields,
+        locals: Vec::new(),
+        local_ids: HashMap::new(),
+        ret,
+    };
+
+    let fns = collector
+        .fns
+        .into_iter()
+        .map(|source| {
+            let ty = typeck.defs.get(&source.def).cloned().unwrap_or(Ty::Error);
+            let (params, ret) = match &ty {
+                Ty::Fn { params, ret } => (params.clone(), (**ret).clone()),
+                _ => (Vec::new(), Ty::Error),
+            };
+            // `return` in a generator just finishes it
+            let generator = crate::flow::yields(source.body);
+            let ret = if generator { Ty::unit() } else { ret };
+            let mut lowerer = lowerer(ret, typeck.instances.get(&source.def));
+            let body = lowerer.fn_body(source.params, &params, source.body);
+            Fn {
+                def: source.def,
+                name: source.name.name.clone(),
+                ty,
+                generator,
+                body,
+                span: source.span,
+            }
+        })
+        .collect();
+    let globals = collector
+        .globals
+        .into_iter()
+        .map(|(def, decl, is_static, span)| {
+            let ty = typeck.defs.get(&def).cloned().unwrap_or(Ty::Error);
+            let mut lowerer = lowerer(Ty::Error, None);
+            let value = lowerer.expr(&decl.value);
+            Global {
+                def,
+                name: decl.name.name.clone(),
+                ty,
+                is_static,
+                body: Body {
+                    params: Vec::new(),
+                    locals: lowerer.locals,
+                    value,
+                },
+                span,
+            }
+        })
+        .collect();
+    Program {
+        adts: collector.adts,
+        fns,
+        globals,
+        externs: collector.externs,
+    }
+}
+
+/// The built in `Option` and `Result` enums, whose variants have one unnamed field or none.
+fn builtin_adts() -> Vec<Adt> {
+    let variant = |def, name: &str, fields: usize| Variant {
+        def,
+        name: name.to_string(),
+        fields: (0..fields)
+            .map(|i| Field {
+                name: i.to_string(),
+                ty: Ty::Error,
+            })
+            .collect(),
+    };
+    vec![
+        Adt {
+            def: DefId::OPTION,
+            name: "Option".to_string(),
+            is_enum: true,
+            variants: vec![
+                variant(DefId::SOME, "Some", 1),
+                variant(DefId::NONE, "None", 0),
+            ],
+        },
+        Adt {
+            def: DefId::RESULT,
+            name: "Result".to_string(),
+            is_enum: true,
+            variants: vec![variant(DefId::OK, "Ok", 1), variant(DefId::ERR, "Err", 1)],
+        },
+    ]
+}
+
+/// A function or trait method with a body, waiting to be lowered.
+struct FnSource<'ast> {
+    def: DefId,
+    name: &'ast ast::Ident,
+    params: &'ast [ast::Param],
+    body: &'ast ast::Block,
+    span: Span,
+}
+
+/// Finds the structs and enums, and the functions and globals to lower, wherever they're declared.
+struct Collector<'a, 'ast> {
+    res: &'a Resolution,
+    typeck: &'a TypeckResults,
+    adts: Vec<Adt>,
+    fns: Vec<FnSource<'ast>>,
+    globals: Vec<(DefId, &'ast ast::GlobalDecl, bool, Span)>,
+    externs: Vec<Extern>,
+}
+
+impl Collector<'_, '_> {
+    /// Returns the fields of a struct or variant, given their names.
+    fn fields(&self, def: DefId, names: impl Iterator<Item = String>) -> Vec<Field> {
+        let tys = self.typeck.fields.get(&def);
+        names
+            .enumerate()
+            .map(|(i, name)| Field {
+                name,
+                ty: tys.and_then(|tys| tys.get(i)).cloned().unwrap_or(Ty::Error),
+            })
+            .collect()
+    }
+}
+
+impl<'ast> Visit<'ast> for Collector<'_, 'ast> {
+    fn visit_item(&mut self, item: &'ast ast::Item) {
+        let span = item.span.clone();
+        match &item.kind {
+            ast::ItemKind::Fn(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    self.fns.push(FnSource {
+                        def,
+                        name: &decl.name,
+                        params: &decl.params,
+                        body: &decl.body,
+                        span,
+                    });
+                }
+            }
+            ast::ItemKind::Extern(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    self.externs.push(Extern {
+                        def,
+                        name: decl.name.name.clone(),
+                        ty: self.typeck.defs.get(&def).cloned().unwrap_or(Ty::Error),
+                        span,
+                    });
+                }
+            }
+            ast::ItemKind::Trait(decl) => {
+                for method in &decl.methods {
+                    let (Some(def), Some(body)) =
+                        (self.res.lookup(&method.name.span), &method.body)
+                    else {
+                        continue;
+                    };
+                    // The impls that take the method lower its body again as their own
+                    let provided = self.res.provided.iter().filter(|p| p.method == def);
+                    for def in std::iter::once(def).chain(provided.map(|provided| provided.def)) {
+                        self.fns.push(FnSource {
+                            def,
+                            name: &method.name,
+                            params: &method.params,
+                            body,
+                            span: method.span.clone(),
+                        });
+                    }
+                }
+            }
+            ast::ItemKind::Const(decl) | ast::ItemKind::Static(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    let is_static = matches!(item.kind, ast::ItemKind::Static(_));
+                    self.globals.push((def, decl, is_static, span));
+                }
+            }
+            ast::ItemKind::Struct(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    let names = decl.fields.iter().map(|field| field.name.name.clone());
+                    self.adts.push(Adt {
+                        def,
+                        name: decl.name.name.clone(),
+                        is_enum: false,
+                        variants: vec![Variant {
+                            def,
+                            name: decl.name.name.clone(),
+                            fields: self.fields(def, names),
+                        }],
+                    });
+                }
+            }
+            ast::ItemKind::Enum(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    let variants = decl.variants.iter().filter_map(|variant| {
+                        let names: Vec<_> = match &variant.kind {
+                            ast::VariantKind::Unit => Vec::new(),
+                            ast::VariantKind::Tuple(types) => {
+                                (0..types.len()).map(|i| i.to_string()).collect()
+                            }
+                            ast::VariantKind::Struct(fields) => {
+                                fields.iter().map(|field| field.name.name.clone()).collect()
+                            }
+                        };
+                        let def = self.res.lookup(&variant.name.span)?;
+                        Some(Variant {
+                            def,
+                            name: variant.name.name.clone(),
+                            fields: self.fields(def, names.into_iter()),
+                        })
+                    });
+                    self.adts.push(Adt {
+                        def,
+                        name: decl.name.name.clone(),
+                        is_enum: true,
+                        variants: variants.collect(),
+                    });
+                }
+            }
+            _ => {}
+        }
+        visit::walk_item(self, item);
+    }
+}
+
+/// Lowers the body of one function or global.
+struct Lowerer<'a> {
+    res: &'a Resolution,
+    typeck: &'a TypeckResults,
+    /// The types of the body when it's the copy of a trait's method that an impl takes, which
+    /// are looked up before those of the program.
+    instance: Option<&'a Instance>,
+    /// The names of the fields of every struct and variant.
+    fields: &'a HashMap<DefId, Vec<String>>,
+    locals: Vec<Local>,
+    /// The variables of the body, by the definitions the resolver made for them.
+    local_ids: HashMap<DefId, LocalId>,
+    /// The return type of the function or closure being lowered, which `?` returns from.
+    ret: Ty,
+}
+
+impl Lowerer<'_> {
+    fn fn_body(&mut self, params: &[ast::Param], tys: &[Ty], body: &ast::Block) -> Body {
+        let mut stmts = Vec::new();
+        let params = params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| self.param(&param.pattern, tys.get(i), &mut stmts))
+            .collect();
+        let mut block = self.block(body);
+        stmts.append(&mut block.stmts);
+        block.stmts = stmts;
+        let value = block_expr(block, body.span.clone());
+        Body {
+            params,
+            locals: std::mem::take(&mut self.locals),
+            value,
+        }
+    }
+
+    /// Declares a parameter. A pattern other than a name binds a new variable, which is matched
+    /// against the pattern by a `let` added to `stmts`.
+    fn param(&mut self, pattern: &ast::Pattern, ty: Option<&Ty>, stmts: &mut Vec<Stmt>) -> LocalId {
+        let lowered = self.pattern(pattern);
+        if let PatternKind::Binding(id) = lowered.kind {
+            return id;
+        }
+        let ty = ty.cloned().unwrap_or(Ty::Error);
+        let id = self.new_local("$param", ty.clone(), false, &pattern.span);
+        stmts.push(Stmt::Let {
+            pattern: lowered,
+            value: Some(local(id, &ty, &pattern.span)),
+        });
+        id
+    }
+
+    fn new_local(&mut self, name: &str, ty: Ty, mutable: bool, span: &Span) -> LocalId {
+        self.locals.push(Local {
+            name: name.to_string(),
+            ty,
+            mutable,
+            span: span.clone(),
+        });
+        LocalId(self.locals.len() - 1)
+    }
+
+    /// Adds `let name = value;` to `stmts` for a new variable, returning the variable.
+    fn temp(&mut self, name: &str, mutable: bool, value: Expr, stmts: &mut Vec<Stmt>) -> Expr {
+        let id = self.new_local(name, value.ty.clone(), mutable, &value.span);
+        let expr = local(id, &value.ty, &value.span);
+        stmts.push(Stmt::Let {
+            pattern: Pattern {
+                kind: PatternKind::Binding(id),
+                span: value.span.clone(),
+            },
+            value: Some(value),
+        });
+        expr
+    }
+
+    /// Returns the type the checker found for an expression.
+    fn ty(&self, expr: &ast::Expr) -> Ty {
+        (self
+            .instance
+            .and_then(|instance| instance.types.get(&expr.span)))
+        .or_else(|| self.typeck.type_of(expr))
+        .cloned()
+        .unwrap_or(Ty::Error)
+    }
+
+    /// Returns the type the checker found for a variable or item.
+    fn def_ty(&self, def: DefId) -> Option<Ty> {
+        (self.instance.and_then(|instance| instance.defs.get(&def)))
+            .or_else(|| self.typeck.defs.get(&def))
+            .cloned()
+    }
+
+    fn block(&mut self, block: &ast::Block) -> Block {
+        let mut stmts = Vec::new();
+        for stmt in &block.stmts {
+            let span = &stmt.span;
+            let stmt = match &stmt.kind {
+                ast::StmtKind::Let { pattern, value, .. } => Stmt::Let {
+                    pattern: self.pattern(pattern),
+                    value: value.as_ref().map(|value| self.expr(value)),
+                },
+                ast::StmtKind::Expr(expr) => Stmt::Expr(self.expr(expr)),
+                ast::StmtKind::Return(value) => {
+                    let value = value.as_ref().map(|value| Box::new(self.expr(value)));
+                    Stmt::Expr(never(ExprKind::Return(value), span))
+                }
+                ast::StmtKind::Yield(value) => Stmt::Expr(Expr {
+                    kind: ExprKind::Yield(Box::new(self.expr(value))),
+                    ty: Ty::unit(),
+                    span: span.clone(),
+                }),
+                ast::StmtKind::Break => Stmt::Expr(never(ExprKind::Break, span)),
+                ast::StmtKind::Continue => Stmt::Expr(never(ExprKind::Continue, span)),
+                // Items are lowered on their own
+                ast::StmtKind::Item(_) | ast::StmtKind::Error => continue,
+            };
+            stmts.push(stmt);
+        }
+        Block {
+            stmts,
+            tail: block.tail.as_ref().map(|tail| Box::new(self.expr(tail))),
+        }
+    }
+
+    fn expr(&mut self, expr: &ast::Expr) -> Expr {
+        let ty = self.ty(expr);
+        let span = &expr.span;
+        let kind = match &expr.kind {
+            ast::ExprKind::Literal(literal) => ExprKind::Literal(literal.clone()),
+            ast::ExprKind::Path(path) => return self.path(path, ty, span),
+            ast::ExprKind::Paren(inner) => return self.expr(inner),
+            ast::ExprKind::Tuple(elems) => {
+                ExprKind::Tuple(elems.iter().map(|elem| self.expr(elem)).collect())
+            }
+            ast::ExprKind::Array(elems) => {
+                ExprKind::Array(elems.iter().map(|elem| self.expr(elem)).collect())
+            }
+            ast::ExprKind::Block(block) => ExprKind::Block(self.block(block)),
+            ast::ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => ExprKind::If {
+                cond: Box::new(self.expr(cond)),
+                then_branch: Box::new(block_expr(
+                    self.block(then_branch),
+                    then_branch.span.clone(),
+                )),
+                else_branch: else_branch
+                    .as_ref()
+                    .map(|branch| Box::new(self.expr(branch))),
+            },
+            ast::ExprKind::While { cond, body } => ExprKind::While {
+                cond: Box::new(self.expr(cond)),
+                body: self.block(body),
+            },
+            // A `loop` is a `while` whose condition is always true
+            ast::ExprKind::Loop(body) => ExprKind::While {
+                cond: Box::new(literal(Literal::Bool(true), &Ty::Bool, span)),
+                body: self.block(body),
+            },
+            ast::ExprKind::For(for_loop) => return self.for_loop(for_loop, ty, span),
+            ast::ExprKind::Closure(closure) => {
+                let (tys, ret) = match &ty {
+                    Ty::Fn { params, ret } => (params.clone(), (**ret).clone()),
+                    _ => (Vec::new(), Ty::Error),
+                };
+                let outer = std::mem::replace(&mut self.ret, ret);
+                let mut stmts = Vec::new();
+                let params = closure
+                    .params
+                    .iter()
+                    .enumerate()
+                    .map(|(i, param)| self.param(&param.pattern, tys.get(i), &mut stmts))
+                    .collect();
+                let mut body = self.expr(&closure.body);
+                if !stmts.is_empty() {
+                    let span = body.span.clone();
+                    let block = Block {
+                        stmts,
+                        tail: Some(Box::new(body)),
+                    };
+                    body = block_expr(block, span);
+                }
+                self.ret = outer;
+                ExprKind::Closure {
+                    params,
+                    body: Box::new(body),
+                }
+            }
+            ast::ExprKind::Match(match_expr) => ExprKind::Match {
+                scrutinee: Box::new(self.expr(&match_expr.scrutinee)),
+                arms: match_expr
+                    .arms
+                    .iter()
+                    .map(|arm| Arm {
+                        pattern: self.pattern(&arm.pattern),
+                        body: self.expr(&arm.body),
+                    })
+                    .collect(),
+            },
+            ast::ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => ExprKind::Range {
+                start: start.as_ref().map(|start| Box::new(self.expr(start))),
+                end: end.as_ref().map(|end| Box::new(self.expr(end))),
+                inclusive: *inclusive,
+            },
+            ast::ExprKind::Call { callee, args } => {
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                if let Some(def) = self.inline_def(callee) {
+                    return self.inline_call(def, args, span);
+                }
+                match self.variant_def(callee) {
+                    Some(def) => ExprKind::Construct { def, fields: args },
+                    None => ExprKind::Call {
+                        callee: Box::new(self.expr(callee)),
+                        args,
+                    },
+                }
+            }
+            ast::ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => match (self.instance.map_or(&self.typeck.methods, |i| &i.methods))
+                .get(&method.span)
+            {
+                Some(&def @ (DefId::ARRAY_PUSH | DefId::ARRAY_POP)) => {
+                    return self.array_update(def, receiver, args, ty, span);
+                }
+                Some(
+                    &def @ (DefId::MAP_INSERT
+                    | DefId::MAP_GET
+                    | DefId::MAP_REMOVE
+                    | DefId::MAP_CONTAINS),
+                ) => return self.map_method(def, receiver, args, ty, span),
+                Some(&DefId::GEN_NEXT) => {
+                    return intrinsic(Intrinsic::Next, vec![self.expr(receiver)], ty);
+                }
+                Some(&def) => {
+                    let callee_ty = match self.ty(receiver) {
+                        Ty::Array(elem) if def == DefId::ARRAY_LEN => {
+                            Some(array_method_ty(def, &elem))
+                        }
+                        Ty::Map(key, value) if def == DefId::MAP_LEN => {
+                            Some(map_method_ty(def, &key, &value))
+                        }
+                        _ => self.def_ty(def),
+                    };
+                    let callee = Expr {
+                        kind: ExprKind::Fn(def),
+                        ty: callee_ty.unwrap_or(Ty::Error),
+                        span: method.span.clone(),
+                    };
+                    let args = std::iter::once(receiver.as_ref()).chain(args);
+                    ExprKind::Call {
+                        callee: Box::new(callee),
+                        args: args.map(|arg| self.expr(arg)).collect(),
+                    }
+                }
+                None => ExprKind::Error,
+            },
+            ast::ExprKind::Field { base, field } => match self.field_index(base, field) {
+                Some(index) => ExprKind::Field {
+                    base: Box::new(self.expr(base)),
+                    index,
+                },
+                None => ExprKind::Error,
+            },
+            ast::ExprKind::Index { base, index } => ExprKind::Index {
+                base: Box::new(self.expr(base)),
+                index: Box::new(self.expr(index)),
+            },
+            ast::ExprKind::Try(inner) => return self.try_expr(inner, ty, span),
+            ast::ExprKind::StructLit { path, fields } => {
+                return self.struct_lit(path, fields, ty, span);
+            }
+            ast::ExprKind::Cast { expr, .. } => ExprKind::Cast(Box::new(self.expr(expr))),
+            ast::ExprKind::Unary { op, expr } => ExprKind::Unary {
+                op: *op,
+                expr: Box::new(self.expr(expr)),
+            },
+            // `a && b` is `if a { b } else { false }`, and `a || b` is `if a { true } else { b }`
+            ast::ExprKind::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+            } => {
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                let constant = Expr {
+                    kind: ExprKind::Literal(Literal::Bool(*op == BinaryOp::Or)),
+                    ty: Ty::Bool,
+                    span: span.clone(),
+                };
+                let (then_branch, else_branch) = match op {
+                    BinaryOp::And => (rhs, constant),
+                    _ => (constant, rhs),
+                };
+                ExprKind::If {
+                    cond: Box::new(lhs),
+                    then_branch: Box::new(then_branch),
+                    else_branch: Some(Box::new(else_branch)),
+                }
+            }
+            ast::ExprKind::Binary { op, lhs, rhs } => ExprKind::Binary {
+                op: *op,
+                lhs: Box::new(self.expr(lhs)),
+                rhs: Box::new(self.expr(rhs)),
+            },
+            ast::ExprKind::Assign { target, value } => ExprKind::Assign {
+                target: Box::new(self.expr(target)),
+                value: Box::new(self.expr(value)),
+            },
+            // `a[i] += b` is `{ let $index = i; a[$index] = a[$index] + b }`, so that `i` is only
+            // evaluated once
+            ast::ExprKind::CompoundAssign { op, target, value } => {
+                let mut stmts = Vec::new();
+                let target = self.place(target, &mut stmts);
+                let value = self.expr(value);
+                let current = Expr {
+                    kind: ExprKind::Binary {
+                        op: *op,
+                        lhs: Box::new(target.clone()),
+                        rhs: Box::new(value),
+                    },
+                    ty: target.ty.clone(),
+                    span: span.clone(),
+                };
+                let assign = Expr {
+                    kind: ExprKind::Assign {
+                        target: Box::new(target),
+                        value: Box::new(current),
+                    },
+                    ty: ty.clone(),
+                    span: span.clone(),
+                };
+                if stmts.is_empty() {
+                    return assign;
+                }
+                ExprKind::Block(Block {
+                    stmts,
+                    tail: Some(Box::new(assign)),
+                })
+            }
+        };
+        Expr {
+            kind,
+            ty,
+            span: span.clone(),
+        }
+    }
+
+    /// Lowers `array.push(value)` to `array = push(array, value)`, and `array.pop()` to
+    /// `{ let $array = array; if len($array) == 0 { None } else { let $last = $array[len($array) -
+    /// 1]; array = pop($array); Some($last) } }`, where the built in `push` and `pop` return a copy
+    /// of the array with its last element added or removed. An array that isn't a place is moved
+    /// into a variable first, which the copy is assigned to, and `$array` is only a copy of an
+    /// array that isn't in a variable already.
+    fn array_update(
+        &mut self,
+        def: DefId,
+        receiver: &ast::Expr,
+        args: &[ast::Expr],
+        ty: Ty,
+        span: &Span,
+    ) -> Expr {
+        let mut stmts = Vec::new();
+        let mut array = self.place(receiver, &mut stmts);
+        if !is_place(&array) {
+            array = self.temp("$array", true, array, &mut stmts);
+        }
+        let Ty::Array(elem) = array.ty.clone() else {
+            return error(ty, span);
+        };
+        let call = |args: Vec<Expr>, array_ty: Ty| {
+            let params = args.iter().map(|arg| arg.ty.clone()).collect();
+            let callee = Expr {
+                kind: ExprKind::Fn(def),
+                ty: Ty::Fn {
+                    params,
+                    ret: Box::new(array_ty.clone()),
+                },
+                span: span.clone(),
+            };
+            Expr {
+                kind: ExprKind::Call {
+                    callee: Box::new(callee),
+                    args,
+                },
+                ty: array_ty,
+                span: span.clone(),
+            }
+        };
+
+        let tail = match args {
+            [value] => {
+                let value = self.expr(value);
+                let pushed = call(vec![array.clone(), value], array.ty.clone());
+                assign(array, pushed)
+            }
+            _ => {
+                let current = match array.kind {
+                    // Read again as it is, so that nothing else refers to the array when it's
+                    // updated
+                    ExprKind::Local(_) => array.clone(),
+                    _ => self.temp("$array", false, array.clone(), &mut stmts),
+                };
+                let int = Ty::Int(IntTy::DEFAULT);
+                let len = intrinsic(Intrinsic::Len, vec![current.clone()], int.clone());
+                let is_empty = binary(
+                    BinaryOp::Eq,
+                    len.clone(),
+                    literal(Literal::Integer(0), &int, span),
+                );
+                let last = Expr {
+                    kind: ExprKind::Index {
+                        base: Box::new(current.clone()),
+                        index: Box::new(binary(
+                            BinaryOp::Sub,
+                            len,
+                            literal(Literal::Integer(1), &int, span),
+                        )),
+                    },
+                    ty: *elem,
+                    span: span.clone(),
+                };
+                // Read before the element is removed, so that the array can be changed in place
+                let mut removed = Vec::new();
+                let last = self.temp("$last", false, last, &mut removed);
+                let popped = call(vec![current], array.ty.clone());
+                removed.push(Stmt::Expr(assign(array, popped)));
+                let none = Expr {
+                    kind: ExprKind::Construct {
+                        def: DefId::NONE,
+                        fields: Vec::new(),
+                    },
+                    ty: ty.clone(),
+                    span: span.clone(),
+                };
+                let some = Expr {
+                    kind: ExprKind::Construct {
+                        def: DefId::SOME,
+                        fields: vec![last],
+                    },
+                    ty: ty.clone(),
+                    span: span.clone(),
+                };
+                let removed = Block {
+                    stmts: removed,
+                    tail: Some(Box::new(some)),
+                };
+                Expr {
+                    kind: ExprKind::If {
+                        cond: Box::new(is_empty),
+                        then_branch: Box::new(none),
+                        else_branch: Some(Box::new(block_expr(removed, span.clone()))),
+                    },
+                    ty: ty.clone(),
+                    span: span.clone(),
+                }
+            }
+        };
+        block_expr(
+            Block {
+                stmts,
+                tail: Some(Box::new(tail)),
+            },
+            span.clone(),
+        )
+    }
+
+    /// Lowers the methods of maps that take a key, which find its entry with `find(map, key)`, the
+    /// index of the entry or -1:
+    ///
+    /// - `map.insert(key, value)` is `map = insert(map, key, value)`, where the built in `insert`
+    ///   returns a copy of the map with the entry added or replaced.
+    /// - `map.contains(key)` is `find(map, key) >= 0`.
+    /// - `map.get(key)` is `{ let $map = map; let $index = find($map, key); if $index < 0 { None }
+    ///   else { Some(value($map, $index)) } }`.
+    /// - `map.remove(key)` is the same as `get`, also assigning `remove($map, $index)`, a copy of
+    ///   the map without the entry, to the map once the value is read, when it's found.
+    ///
+    /// A map that `insert` or `remove` is called on is moved into a variable first when it isn't
+    /// a place, like the receiver of [`Lowerer::array_update`], and `$map` is only a copy of a map
+    /// that isn't in a variable already.
+    fn map_method(
+        &mut self,
+        def: DefId,
+        receiver: &ast::Expr,
+        args: &[ast::Expr],
+        ty: Ty,
+        span: &Span,
+    ) -> Expr {
+        let mut stmts = Vec::new();
+        let map = if matches!(def, DefId::MAP_INSERT | DefId::MAP_REMOVE) {
+            let map = self.place(receiver, &mut stmts);
+            if is_place(&map) {
+                map
+            } else {
+                self.temp("$map", true, map, &mut stmts)
+            }
+        } else {
+            self.expr(receiver)
+        };
+        let Ty::Map(_, value_ty) = map.ty.clone() else {
+            return error(ty, span);
+        };
+        let args: Vec<Expr> = args.iter().map(|arg| self.expr(arg)).collect();
+        let int = Ty::Int(IntTy::DEFAULT);
+        let zero = literal(Literal::Integer(0), &int, span);
+
+        let tail = match (def, args.as_slice()) {
+            (DefId::MAP_INSERT, [_, _]) => {
+                let args: Vec<Expr> = std::iter::once(map.clone()).chain(args).collect();
+                let callee = Expr {
+                    kind: ExprKind::Fn(def),
+                    ty: Ty::Fn {
+                        params: args.iter().map(|arg| arg.ty.clone()).collect(),
+                        ret: Box::new(map.ty.clone()),
+                    },
+                    span: span.clone(),
+                };
+                let inserted = Expr {
+                    kind: ExprKind::Call {
+                        callee: Box::new(callee),
+                        args,
+                    },
+                    ty: map.ty.clone(),
+                    span: span.clone(),
+                };
+                assign(map, inserted)
+            }
+            (DefId::MAP_CONTAINS, [key]) => {
+                let index = intrinsic(Intrinsic::Find, vec![map, key.clone()], int);
+                binary(BinaryOp::Ge, index, zero)
+            }
+            (_, [key]) => {
+                let current = match map.kind {
+                    // Read again as it is, so that nothing else refers to the map when it's updated
+                    ExprKind::Local(_) => map.clone(),
+                    _ => self.temp("$map", false, map.clone(), &mut stmts),
+                };
+                let index = intrinsic(Intrinsic::Find, vec![current.clone(), key.clone()], int);
+                let index = self.temp("$index", false, index, &mut stmts);
+                let is_missing = binary(BinaryOp::Lt, index.clone(), zero);
+                let mut value = intrinsic(
+                    Intrinsic::EntryValue,
+                    vec![current.clone(), index.clone()],
+                    *value_ty,
+                );
+                let mut removed = Vec::new();
+                if def == DefId::MAP_REMOVE {
+                    // Read before the entry is removed, so that the map can be changed in place
+                    value = self.temp("$value", false, value, &mut removed);
+                    let map_ty = map.ty.clone();
+                    let remove = intrinsic(Intrinsic::Remove, vec![current, index.clone()], map_ty);
+                    removed.push(Stmt::Expr(assign(map, remove)));
+                }
+                let none = Expr {
+                    kind: ExprKind::Construct {
+                        def: DefId::NONE,
+                        fields: Vec::new(),
+                    },
+                    ty: ty.clone(),
+                    span: span.clone(),
+                };
+                let mut found = Expr {
+                    kind: ExprKind::Construct {
+                        def: DefId::SOME,
+                        fields: vec![value],
+                    },
+                    ty: ty.clone(),
+                    span: span.clone(),
+                };
+                if !removed.is_empty() {
+                    let block = Block {
+                        stmts: removed,
+                        tail: Some(Box::new(found)),
+                    };
+                    found = block_expr(block, span.clone());
+                }
+                Expr {
+                    kind: ExprKind::If {
+                        cond: Box::new(is_missing),
+                        then_branch: Box::new(none),
+                        else_branch: Some(Box::new(found)),
+                    },
+                    ty: ty.clone(),
+                    span: span.clone(),
+                }
+            }
+            // The wrong number of arguments was reported by the checker
+            _ => return error(ty, span),
+        };
+        block_expr(
+            Block {
+                stmts,
+                tail: Some(Box::new(tail)),
+            },
+            span.clone(),
+        )
+    }
+
+    /// Lowers the target of a compound assignment, moving the indexes it uses into variables
+    /// declared by `stmts` so that the target can be both read and written.
+    fn place(&mut self, expr: &ast::Expr, stmts: &mut Vec<Stmt>) -> Expr {
+        let kind = match &expr.kind {
+            ast::ExprKind::Paren(inner) => return self.place(inner, stmts),
+            ast::ExprKind::Index { base, index } => {
+                let base = self.place(base, stmts);
+                let mut index = self.expr(index);
+                if !matches!(index.kind, ExprKind::Literal(_) | ExprKind::Local(_)) {
+                    index = self.temp("$index", false, index, stmts);
+                }
+                ExprKind::Index {
+                    base: Box::new(base),
+                    index: Box::new(index),
+                }
+            }
+            ast::ExprKind::Field { base, field } => match self.field_index(base, field) {
+                Some(index) => ExprKind::Field {
+                    base: Box::new(self.place(base, stmts)),
+                    index,
+                },
+                None => ExprKind::Error,
+            },
+            _ => return self.expr(expr),
+        };
+        Expr {
+            kind,
+            ty: self.ty(expr),
+            span: expr.span.clone(),
+        }
+    }
+
+    /// Returns the index of a field of a struct in its declaration.
+    fn field_index(&self, base: &ast::Expr, field: &ast::Ident) -> Option<usize> {
+        let Ty::Adt { def, .. } = self.ty(base) else {
+            return None;
+        };
+        let fields = self.fields.get(&def)?;
+        fields.iter().position(|name| *name == field.name)
+    }
+
+    fn path(&mut self, path: &ast::Path, ty: Ty, span: &Span) -> Expr {
+        let def = self.res.lookup(&path.segments.last().unwrap().span);
+        let kind = match def.map(|def| (def, self.res.def(def).kind)) {
+            Some((def, DefKind::Local | DefKind::Param)) => match self.local_ids.get(&def) {
+                Some(&id) => ExprKind::Local(id),
+                None => ExprKind::Error,
+            },
+            // The functions whose calls are lowered in place are closures that call them when
+            // they're used as values
+            Some((def, _)) if is_inline(def) => {
+                let Ty::Fn { params, .. } = ty.clone() else {
+                    return error(ty, span);
+                };
+                let args: Vec<_> = params
+                    .iter()
+                    .map(|param| {
+                        let id = self.new_local("$arg", param.clone(), false, span);
+                        local(id, param, span)
+                    })
+                    .collect();
+                let params = args
+                    .iter()
+                    .map(|arg| match arg.kind {
+                        ExprKind::Local(id) => id,
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                ExprKind::Closure {
+                    params,
+                    body: Box::new(self.inline_value(def, &args, span)),
+                }
+            }
+            Some((def, DefKind::Fn | DefKind::Method)) => ExprKind::Fn(def),
+            Some((DefId::MATH_PI, _)) => ExprKind::Literal(Literal::Float(std::f64::consts::PI)),
+            Some((def, DefKind::Const | DefKind::Static)) => ExprKind::Global(def),
+            // A tuple variant used as a value is a function that constructs it
+            Some((def, DefKind::Variant)) => match ty.clone() {
+                Ty::Fn { params, ret } => {
+                    let fields: Vec<_> = params
+                        .into_iter()
+                        .map(|param| {
+                            let id = self.new_local("$field", param.clone(), false, span);
+                            local(id, &param, span)
+                        })
+                        .collect();
+                    let params = fields
+                        .iter()
+                        .map(|field| match field.kind {
+                            ExprKind::Local(id) => id,
+                            _ => unreachable!(),
+                        })
+                        .collect();
+                    let body = Expr {
+                        kind: ExprKind::Construct { def, fields },
+                        ty: *ret,
+                        span: span.clone(),
+                    };
+                    ExprKind::Closure {
+                        params,
+                        body: Box::new(body),
+                    }
+                }
+                _ => ExprKind::Construct {
+                    def,
+                    fields: Vec::new(),
+                },
+            },
+            _ => ExprKind::Error,
+        };
+        Expr {
+            kind,
+            ty,
+            span: span.clone(),
+        }
+    }
+
+    /// Returns the function a call's callee names when it's one whose calls are lowered in place.
+    fn inline_def(&self, callee: &ast::Expr) -> Option<DefId> {
+        let ast::ExprKind::Path(path) = &strip_parens(callee).kind else {
+            return None;
+        };
+        let def = self.res.lookup(&path.segments.last().unwrap().span)?;
+        is_inline(def).then_some(def)
+    }
+
+    /// Lowers a call of a function that [`is_inline`] accepts to the expression that
+    /// [`Lowerer::inline_value`] makes, with the arguments evaluated into variables first.
+    fn inline_call(&mut self, def: DefId, args: Vec<Expr>, span: &Span) -> Expr {
+        let mut stmts = Vec::new();
+        let args: Vec<_> = (args.into_iter())
+            .map(|arg| self.temp("$arg", false, arg, &mut stmts))
+            .collect();
+        let tail = self.inline_value(def, &args, span);
+        let block = Block {
+            stmts,
+            tail: Some(Box::new(tail)),
+        };
+        block_expr(block, span.clone())
+    }
+
+    /// Returns the value of a call of a function that [`is_inline`] accepts, whose arguments are
+    /// variables.
+    fn inline_value(&mut self, def: DefId, args: &[Expr], span: &Span) -> Expr {
+        match def {
+            DefId::FS_READ_TO_STRING | DefId::FS_WRITE | DefId::FS_EXISTS => {
+                self.fs_value(def, args, span)
+            }
+            DefId::PRINT | DefId::PRINTLN => print_value(def, args, span),
+            _ => math_value(def, args, span),
+        }
+    }
+
+    /// Returns the value of a call of a function of `fs`, which is `{ let $value = op(args); let
+    /// $error = fs_error(); if $error == "" { Ok($value) } else { Err($error) } }`, where `op` is
+    /// the intrinsic that does what the function does.
+    fn fs_value(&mut self, def: DefId, args: &[Expr], span: &Span) -> Expr {
+        let (op, value_ty) = match def {
+            DefId::FS_READ_TO_STRING => (Intrinsic::ReadFile, Ty::String),
+            DefId::FS_WRITE => (Intrinsic::WriteFile, Ty::unit()),
+            _ => (Intrinsic::FileExists, Ty::Bool),
+        };
+        let ty = Ty::Result(Box::new(value_ty.clone()), Box::new(Ty::String));
+        // The wrong number of arguments was reported by the checker
+        if args.is_empty() {
+            return error(ty, span);
+        }
+        let mut stmts = Vec::new();
+        let value = intrinsic(op, args.to_vec(), value_ty);
+        let value = self.temp("$value", false, value, &mut stmts);
+        let error = Expr {
+            kind: ExprKind::Intrinsic {
+                intrinsic: Intrinsic::FsError,
+                args: Vec::new(),
+            },
+            ty: Ty::String,
+            span: span.clone(),
+        };
+        let error = self.temp("$error", false, error, &mut stmts);
+        let empty = literal(Literal::String(String::new()), &Ty::String, span);
+        let construct = |def, field| Expr {
+            kind: ExprKind::Construct {
+                def,
+                fields: vec![field],
+            },
+            ty: ty.clone(),
+            span: span.clone(),
+        };
+        let tail = Expr {
+            kind: ExprKind::If {
+                cond: Box::new(binary(BinaryOp::Eq, error.clone(), empty)),
+                then_branch: Box::new(construct(DefId::OK, value)),
+                else_branch: Some(Box::new(construct(DefId::ERR, error))),
+            },
+            ty: ty.clone(),
+            span: span.clone(),
+        };
+        let block = Block {
+            stmts,
+            tail: Some(Box::new(tail)),
+        };
+        block_expr(block, span.clone())
+    }
+
+    /// Returns the variant that a call constructs, if its callee names one.
+    fn variant_def(&self, callee: &ast::Expr) -> Option<DefId> {
+        let ast::ExprKind::Path(path) = &callee.kind else {
+            return None;
+        };
+        let def = self.res.lookup(&path.segments.last().unwrap().span)?;
+        (self.res.def(def).kind == DefKind::Variant).then_some(def)
+    }
+
+    /// Lowers a struct literal. The fields are evaluated in the order they're written, so when
+    /// that isn't the order they're declared in, they're evaluated into variables first.
+    fn struct_lit(
+        &mut self,
+        path: &ast::Path,
+        inits: &[ast::FieldInit],
+        ty: Ty,
+        span: &Span,
+    ) -> Expr {
+        let def = self.res.lookup(&path.segments.last().unwrap().span);
+        let Some((def, names)) = def.and_then(|def| Some((def, self.fields.get(&def)?))) else {
+            return error(ty, span);
+        };
+        let written: Vec<_> = inits.iter().map(|init| &init.name.name).collect();
+        let in_order = written.iter().copied().eq(names.iter());
+
+        let mut stmts = Vec::new();
+        let mut values = HashMap::new();
+        for init in inits {
+            let mut value = self.expr(&init.value);
+            if !in_order {
+                let name = format!("${}", init.name.name);
+                value = self.temp(&name, false, value, &mut stmts);
+            }
+            values.insert(init.name.name.as_str(), value);
+        }
+        let fields = names
+            .iter()
+            .map(|name| {
+                values
+                    .remove(name.as_str())
+                    .unwrap_or_else(|| error(Ty::Error, span))
+            })
+            .collect();
+        let construct = Expr {
+            kind: ExprKind::Construct { def, fields },
+            ty: ty.clone(),
+            span: span.clone(),
+        };
+        if stmts.is_empty() {
+            return construct;
+        }
+        Expr {
+            kind: ExprKind::Block(Block {
+                stmts,
+                tail: Some(Box::new(construct)),
+            }),
+            ty,
+            span: span.clone(),
+        }
+    }
+
+    /// Lowers `inner?` to `match inner { Some($value) => $value, None => return None }`, or for a
+    /// `Result`, `match inner { Ok($value) => $value, Err($error) => return Err($error) }`.
+    fn try_expr(&mut self, inner: &ast::Expr, ty: Ty, span: &Span) -> Expr {
+        let scrutinee = self.expr(inner);
+        let (success, failure, error_ty) = match &scrutinee.ty {
+            Ty::Option(_) => (DefId::SOME, DefId::NONE, None),
+            Ty::Result(_, error) => (DefId::OK, DefId::ERR, Some((**error).clone())),
+            _ => return error(ty, span),
+        };
+
+        let value = self.new_local("$value", ty.clone(), false, span);
+        let success_arm = Arm {
+            pattern: variant_pattern(success, vec![binding(value, span)], span),
+            body: local(value, &ty, span),
+        };
+        let (failure_pattern, failure_fields) = match error_ty {
+            Some(error_ty) => {
+                let error = self.new_local("$error", error_ty.clone(), false, span);
+                (
+                    vec![binding(error, span)],
+                    vec![local(error, &error_ty, span)],
+                )
+            }
+            None => (Vec::new(), Vec::new()),
+        };
+        let returned = Expr {
+            kind: ExprKind::Construct {
+                def: failure,
+                fields: failure_fields,
+            },
+            ty: self.ret.clone(),
+            span: span.clone(),
+        };
+        let failure_arm = Arm {
+            pattern: variant_pattern(failure, failure_pattern, span),
+            body: never(ExprKind::Return(Some(Box::new(returned))), span),
+        };
+        Expr {
+            kind: ExprKind::Match {
+                scrutinee: Box::new(scrutinee),
+                arms: vec![success_arm, failure_arm],
+            },
+            ty,
+            span: span.clone(),
+        }
+    }
+
+    /// Lowers a `for` loop to a `while` loop. The next value is found before the body runs, so
+    /// that `continue` doesn't skip over moving to it.
+    fn for_loop(&mut self, for_loop: &ast::ForLoop, ty: Ty, span: &Span) -> Expr {
+        let mut stmts = Vec::new();
+        let iter = strip_parens(&for_loop.iter);
+        let (cond, current, mut body) = match self.ty(iter) {
+            Ty::Range(elem) => self.range_loop(iter, &elem, &mut stmts),
+            Ty::Array(elem) => {
+                let array = self.expr(iter);
+                self.array_loop(array, &elem, &mut stmts)
+            }
+            Ty::Map(key, value) => {
+                let map = self.expr(iter);
+                self.map_loop(map, &key, &value, &mut stmts)
+            }
+            Ty::String => {
+                let string = self.expr(iter);
+                let array = intrinsic(
+                    Intrinsic::Chars,
+                    vec![string],
+                    Ty::Array(Box::new(Ty::Char)),
+                );
+                self.array_loop(array, &Ty::Char, &mut stmts)
+            }
+            Ty::Gen(elem) => {
+                let gen = self.expr(iter);
+                self.gen_loop(gen, &elem, &mut stmts)
+            }
+            _ => return error(ty, span),
+        };
+
+        let pattern = self.pattern(&for_loop.pattern);
+        let lowered = self.block(&for_loop.body);
+        body.insert(
+            0,
+            Stmt::Let {
+                pattern,
+                value: Some(current),
+            },
+        );
+        body.extend(lowered.stmts);
+        let while_loop = Expr {
+            kind: ExprKind::While {
+                cond: Box::new(cond),
+                body: Block {
+                    stmts: body,
+                    tail: lowered.tail,
+                },
+            },
+            ty: ty.clone(),
+            span: span.clone(),
+        };
+        Expr {
+            kind: ExprKind::Block(Block {
+                stmts,
+                tail: Some(Box::new(while_loop)),
+            }),
+            ty,
+            span: span.clone(),
+        }
+    }
+
+    /// Returns the condition of a loop over a range, the current value, and the statements that
+    /// move to the next value at the start of the body. The statements that come before the loop
+    /// are added to `stmts`.
+    ///
+    /// `start..end` loops with `while $next < $end`. `start..=end` keeps whether there's a next
+    /// value in `$more`, so that it doesn't go past the end of the type when `end` is its largest
+    /// value. `start..` loops until it overflows.
+    fn range_loop(
+        &mut self,
+        range: &ast::Expr,
+        elem: &Ty,
+        stmts: &mut Vec<Stmt>,
+    ) -> (Expr, Expr, Vec<Stmt>) {
+        let span = &range.span;
+        let (start, end, inclusive) = match &range.kind {
+            ast::ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = match start {
+                    Some(start) => self.expr(start),
+                    None => literal(Literal::Integer(0), elem, span),
+                };
+                let end = end.as_ref().map(|end| self.expr(end));
+                (start, end, *inclusive)
+            }
+            _ => {
+                let range = self.expr(range);
+                let range = self.temp("$range", false, range, stmts);
+                let bound = |index| Expr {
+                    kind: ExprKind::Field {
+                        base: Box::new(range.clone()),
+                        index,
+                    },
+                    ty: elem.clone(),
+                    span: span.clone(),
+                };
+                (bound(0), Some(bound(1)), false)
+            }
+        };
+
+        let next = self.temp("$next", true, start, stmts);
+        let current = next.clone();
+        let step = assign(
+            next.clone(),
+            binary(
+                BinaryOp::Add,
+                next.clone(),
+                literal(Literal::Integer(1), elem, span),
+            ),
+        );
+        let Some(end) = end else {
+            let cond = literal(Literal::Bool(true), &Ty::Bool, span);
+            return (cond, current, vec![Stmt::Expr(step)]);
+        };
+        let end = self.temp("$end", false, end, stmts);
+        if !inclusive {
+            let cond = binary(BinaryOp::Lt, next, end);
+            return (cond, current, vec![Stmt::Expr(step)]);
+        }
+
+        let more = binary(BinaryOp::Le, next.clone(), end.clone());
+        let more = self.temp("$more", true, more, stmts);
+        let check = assign(more.clone(), binary(BinaryOp::Lt, next, end));
+        let step = Expr {
+            kind: ExprKind::If {
+                cond: Box::new(more.clone()),
+                then_branch: Box::new(step),
+                else_branch: None,
+            },
+            ty: Ty::unit(),
+            span: span.clone(),
+        };
+        (more, current, vec![Stmt::Expr(check), Stmt::Expr(step)])
+    }
+
+    /// Like [`Lowerer::range_loop`], for a loop over the elements of an array, with
+    /// `while $index < len($array)`.
+    fn array_loop(
+        &mut self,
+        array: Expr,
+        elem: &Ty,
+        stmts: &mut Vec<Stmt>,
+    ) -> (Expr, Expr, Vec<Stmt>) {
+        let span = array.span.clone();
+        let int = Ty::Int(IntTy::DEFAULT);
+        let array = self.temp("$array", false, array, stmts);
+        let zero = literal(Literal::Integer(0), &int, &span);
+        let index = self.temp("$index", true, zero, stmts);
+        let len = intrinsic(Intrinsic::Len, vec![array.clone()], int.clone());
+        let cond = binary(BinaryOp::Lt, index.clone(), len);
+        let current = Expr {
+            kind: ExprKind::Index {
+                base: Box::new(array),
+                index: Box::new(index.clone()),
+            },
+            ty: elem.clone(),
+            span: span.clone(),
+        };
+        let step = assign(
+            index.clone(),
+            binary(
+                BinaryOp::Add,
+                index,
+                literal(Literal::Integer(1), &int, &span),
+            ),
+        );
+        (cond, current, vec![Stmt::Expr(step)])
+    }
+
+    /// Like [`Lowerer::array_loop`], for a loop over the entries of a map, in the order they were
+    /// inserted, where the current value is `(key($map, $index), value($map, $index))`.
+    fn map_loop(
+        &mut self,
+        map: Expr,
+        key: &Ty,
+        value: &Ty,
+        stmts: &mut Vec<Stmt>,
+    ) -> (Expr, Expr, Vec<Stmt>) {
+        let span = map.span.clone();
+        let int = Ty::Int(IntTy::DEFAULT);
+        let map = self.temp("$map", false, map, stmts);
+        let zero = literal(Literal::Integer(0), &int, &span);
+        let index = self.temp("$index", true, zero, stmts);
+        let len = intrinsic(Intrinsic::Len, vec![map.clone()], int.clone());
+        let cond = binary(BinaryOp::Lt, index.clone(), len);
+        let entry = vec![map, index.clone()];
+        let current = Expr {
+            kind: ExprKind::Tuple(vec![
+                intrinsic(Intrinsic::EntryKey, entry.clone(), key.clone()),
+                intrinsic(Intrinsic::EntryValue, entry, value.clone()),
+            ]),
+            ty: Ty::Tuple(vec![key.clone(), value.clone()]),
+            span: span.clone(),
+        };
+        let step = assign(
+            index.clone(),
+            binary(
+                BinaryOp::Add,
+                index,
+                literal(Literal::Integer(1), &int, &span),
+            ),
+        );
+        (cond, current, vec![Stmt::Expr(step)])
+    }
+
+    /// Like [`Lowerer::range_loop`], for a loop over the values a generator yields. The condition
+    /// resumes the generator, and keeps what it yields in `$current`:
+    /// `match next($gen) { Some($value) => { $current = $value; true }, None => false }`.
+    fn gen_loop(&mut self, gen: Expr, elem: &Ty, stmts: &mut Vec<Stmt>) -> (Expr, Expr, Vec<Stmt>) {
+        let span = gen.span.clone();
+        let gen = self.temp("$gen", false, gen, stmts);
+        let current = self.new_local("$current", elem.clone(), true, &span);
+        stmts.push(Stmt::Let {
+            pattern: binding(current, &span),
+            value: None,
+        });
+        let current = local(current, elem, &span);
+        let value = self.new_local("$value", elem.clone(), false, &span);
+        let next = intrinsic(
+            Intrinsic::Next,
+            vec![gen],
+            Ty::Option(Box::new(elem.clone())),
+        );
+        let yielded = Block {
+            stmts: vec![Stmt::Expr(assign(
+                current.clone(),
+                local(value, elem, &span),
+            ))],
+            tail: Some(Box::new(literal(Literal::Bool(true), &Ty::Bool, &span))),
+        };
+        let cond = Expr {
+            kind: ExprKind::Match {
+                scrutinee: Box::new(next),
+                arms: vec![
+                    Arm {
+                        pattern: variant_pattern(DefId::SOME, vec![binding(value, &span)], &span),
+                        body: block_expr(yielded, span.clone()),
+                    },
+                    Arm {
+                        pattern: variant_pattern(DefId::NONE, Vec::new(), &span),
+                        body: literal(Literal::Bool(false), &Ty::Bool, &span),
+                    },
+                ],
+            },
+            ty: Ty::Bool,
+            span: span.clone(),
+        };
+        (cond, current, Vec::new())
+    }
+
+    fn pattern(&mut self, pattern: &ast::Pattern) -> Pattern {
+        let span = &pattern.span;
+        let kind = match &pattern.kind {
+            ast::PatternKind::Wildcard => PatternKind::Wildcard,
+            ast::PatternKind::Literal(literal) => PatternKind::Literal(literal.clone()),
+            ast::PatternKind::Ident(ident) | ast::PatternKind::Mut(ident) => {
+                let Some(def) = self.res.lookup(&ident.span) else {
+                    return Pattern {
+                        kind: PatternKind::Wildcard,
+                        span: span.clone(),
+                    };
+                };
+                let definition = self.res.def(def);
+                match definition.kind {
+                    DefKind::Variant => PatternKind::Variant {
+                        def,
+                        fields: Vec::new(),
+                    },
+                    DefKind::Const | DefKind::Static => PatternKind::Global(def),
+                    _ => {
+                        let ty = self.def_ty(def).unwrap_or(Ty::Error);
+                        let id = self.new_local(&ident.name, ty, definition.mutable, &ident.span);
+                        self.local_ids.insert(def, id);
+                        PatternKind::Binding(id)
+                    }
+                }
+            }
+            ast::PatternKind::Tuple(elems) => {
+                PatternKind::Tuple(elems.iter().map(|elem| self.pattern(elem)).collect())
+            }
+            ast::PatternKind::Path(path) => {
+                match self.res.lookup(&path.segments.last().unwrap().span) {
+                    Some(def) if self.res.def(def).kind == DefKind::Variant => {
+                        PatternKind::Variant {
+                            def,
+                            fields: Vec::new(),
+                        }
+                    }
+                    Some(def) => PatternKind::Global(def),
+                    None => PatternKind::Wildcard,
+                }
+            }
+            ast::PatternKind::TupleVariant { path, fields } => {
+                match self.res.lookup(&path.segments.last().unwrap().span) {
+                    Some(def) => PatternKind::Variant {
+                        def,
+                        fields: fields.iter().map(|field| self.pattern(field)).collect(),
+                    },
+                    None => PatternKind::Wildcard,
+                }
+            }
+            ast::PatternKind::StructVariant { path, fields } => {
+                let def = self.res.lookup(&path.segments.last().unwrap().span);
+                let Some((def, names)) = def.and_then(|def| Some((def, self.fields.get(&def)?)))
+                else {
+                    return Pattern {
+                        kind: PatternKind::Wildcard,
+                        span: span.clone(),
+                    };
+                };
+                let mut patterns: HashMap<_, _> = fields
+                    .iter()
+                    .map(|field| (field.name.name.as_str(), self.pattern(&field.pattern)))
+                    .collect();
+                let fields = names
+                    .iter()
+                    .map(|name| {
+                        patterns.remove(name.as_str()).unwrap_or(Pattern {
+                            kind: PatternKind::Wildcard,
+                            span: span.clone(),
+                        })
+                    })
+                    .collect();
+                PatternKind::Variant { def, fields }
+            }
+        };
+        Pattern {
+            kind,
+            span: span.clone(),
+        }
+    }
+}
+
+fn strip_parens(expr: &ast::Expr) -> &ast::Expr {
+    match &expr.kind {
+        ast::ExprKind::Paren(inner) => strip_parens(inner),
+        _ => expr,
+    }
+}
+
+/// Returns whether calls of a built in function are lowered in place rather than calling it:
+/// `math::abs`, `math::min` and `math::max`, which take any kind of number, `print` and `println`,
+/// which take anything that converts to a string, and the functions of `fs`, which make a
+/// `Result`.
+fn is_inline(def: DefId) -> bool {
+    matches!(
+        def,
+        DefId::MATH_ABS
+            | DefId::MATH_MIN
+            | DefId::MATH_MAX
+            | DefId::PRINT
+            | DefId::PRINTLN
+            | DefId::FS_READ_TO_STRING
+            | DefId::FS_WRITE
+            | DefId::FS_EXISTS
+    )
+}
+
+/// Returns the value of `math::abs(a)`, `math::min(a, b)` or `math::max(a, b)`, which take any
+/// kind of number, for arguments that are variables: `if a < 0 { -a } else { a }`,
+/// `if b < a { b } else { a }` and `if b > a { b } else { a }`. A float's absolute value is
+/// `0.0 - a` when `a <= 0.0` instead, so that it's never `-0.0`, and an unsigned integer's is
+/// itself.
+fn math_value(def: DefId, args: &[Expr], span: &Span) -> Expr {
+    let Some(a) = args.first().cloned() else {
+        return error(Ty::Error, span);
+    };
+    let ty = a.ty.clone();
+    let (cond, then_branch) = match (def, &args[1..]) {
+        (DefId::MATH_ABS, []) => match ty {
+            Ty::Float => {
+                let zero = literal(Literal::Float(0.0), &ty, span);
+                let cond = binary(BinaryOp::Le, a.clone(), zero.clone());
+                (cond, binary(BinaryOp::Sub, zero, a.clone()))
+            }
+            Ty::Int(int) if !int.is_signed() => return a,
+            _ => {
+                let zero = literal(Literal::Integer(0), &ty, span);
+                let negated = Expr {
+                    kind: ExprKind::Unary {
+                        op: UnaryOp::Neg,
+                        expr: Box::new(a.clone()),
+                    },
+                    ty: ty.clone(),
+                    span: span.clone(),
+                };
+                (binary(BinaryOp::Lt, a.clone(), zero), negated)
+            }
+        },
+        (DefId::MATH_MIN, [b]) => (binary(BinaryOp::Lt, b.clone(), a.clone()), b.clone()),
+        (DefId::MATH_MAX, [b]) => (binary(BinaryOp::Gt, b.clone(), a.clone()), b.clone()),
+        _ => return error(ty, span),
+    };
+    Expr {
+        kind: ExprKind::If {
+            cond: Box::new(cond),
+            then_branch: Box::new(then_branch),
+            else_branch: Some(Box::new(a)),
+        },
+        ty,
+        span: span.clone(),
+    }
+}
+
+/// Returns the value of `print(a)` or `println(a)`, which call the built in function with `a`
+/// converted to a string when it isn't one.
+fn print_value(def: DefId, args: &[Expr], span: &Span) -> Expr {
+    let args = args
+        .iter()
+        .map(|arg| match arg.ty {
+            Ty::String => arg.clone(),
+            _ => Expr {
+                kind: ExprKind::Cast(Box::new(arg.clone())),
+                ty: Ty::String,
+                span: arg.span.clone(),
+            },
+        })
+        .collect();
+    let callee = Expr {
+        kind: ExprKind::Fn(def),
+        ty: Ty::Fn {
+            params: vec![Ty::String],
+            ret: Box::new(Ty::unit()),
+        },
+        span: span.clone(),
+    };
+    Expr {
+        kind: ExprKind::Call {
+            callee: Box::new(callee),
+            args,
+        },
+        ty: Ty::unit(),
+        span: span.clone(),
+    }
+}
+
+/// Returns whether an expression can be assigned to.
+fn is_place(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Local(_) | ExprKind::Global(_) => true,
+        ExprKind::Field { base, .. } | ExprKind::Index { base, .. } => is_place(base),
+        _ => false,
+    }
+}
+
+/// Wraps a block in an expression, giving it the type of its value.
+fn block_expr(block: Block, span: Span) -> Expr {
+    let diverges = block.stmts.iter().any(|stmt| match stmt {
+        Stmt::Expr(expr)
+        | Stmt::Let {
+            value: Some(expr), ..
+        } => expr.ty == Ty::Never,
+        Stmt::Let { value: None, .. } => false,
+    });
+    let ty = match &block.tail {
+        _ if diverges => Ty::Never,
+        Some(tail) => tail.ty.clone(),
+        None => Ty::unit(),
+    };
+    Expr {
+        kind: ExprKind::Block(block),
+        ty,
+        span,
+    }
+}
+
+fn local(id: LocalId, ty: &Ty, span: &Span) -> Expr {
+    Expr {
+        kind: ExprKind::Local(id),
+        ty: ty.clone(),
+        span: span.clone(),
+    }
+}
+
+fn literal(literal: Literal, ty: &Ty, span: &Span) -> Expr {
+    Expr {
+        kind: ExprKind::Literal(literal),
+        ty: ty.clone(),
+        span: span.clone(),
+    }
+}
+
+fn never(kind: ExprKind, span: &Span) -> Expr {
+    Expr {
+        kind,
+        ty: Ty::Never,
+        span: span.clone(),
+    }
+}
+
+fn error(ty: Ty, span: &Span) -> Expr {
+    Expr {
+        kind: ExprKind::Error,
+        ty,
+        span: span.clone(),
+    }
+}
+
+fn intrinsic(intrinsic: Intrinsic, args: Vec<Expr>, ty: Ty) -> Expr {
+    let span = args[0].span.clone();
+    Expr {
+        kind: ExprKind::Intrinsic { intrinsic, args },
+        ty,
+        span,
+    }
+}
+
+/// Returns `lhs op rhs`, which has the type of `lhs` or is a `bool` for a comparison.
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    let ty = if op.is_comparison() {
+        Ty::Bool
+    } else {
+        lhs.ty.clone()
+    };
+    let span = lhs.span.clone();
+    Expr {
+        kind: ExprKind::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        },
+        ty,
+        span,
+    }
+}
+
+fn assign(target: Expr, value: Expr) -> Expr {
+    let span = target.span.clone();
+    Expr {
+        kind: ExprKind::Assign {
+            target: Box::new(target),
+            value: Box::new(value),
+        },
+        ty: Ty::unit(),
+        span,
+    }
+}
+
+fn binding(id: LocalId, span: &Span) -> Pattern {
+    Pattern {
+        kind: PatternKind::Binding(id),
+        span: span.clone(),
+    }
+}
+
+fn variant_pattern(def: DefId, fields: Vec<Pattern>, span: &Span) -> Pattern {
+    Pattern {
+        kind: PatternKind::Variant { def, fields },
+        span: span.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hir::{lowered, print_program};
+
+    /// Lowers a program without errors and prints it.
+    fn printed(source: &str) -> String {
+        let (res, hir) = lowered(source);
+        print_program(&hir, &res)
+    }
+
+    #[test]
+    fn test_lower_for_loops() {
+        let source = "
+fn loops(xs: [int], n: int) {
+    for x in xs { }
+    for i in 0..n { continue; }
+    for j in 1..=3 { }
+    for c in \"abc\" { }
+}";
+        assert_eq!(
+            printed(source),
+            "fn loops(xs: [i32], n: i32) {
+    {
+        let $array = xs;
+        let mut $index = 0;
+        while $index < @len($array) {
+            let x = $array[$index];
+            $index = $index + 1;
+        }
+    }
+    {
+        let mut $next = 0;
+        let $end = n;
+        while $next < $end {
+            let i = $next;
+            $next = $next + 1;
+            continue;
+        }
+    }
+    {
+        let mut $next = 1;
+        let $end = 3;
+        let mut $more = $next <= $end;
+        while $more {
+            let j = $next;
+            $more = $next < $end;
+            if $more { $next = $next + 1 }
+        }
+    }
+    {
+        let $array = @chars(\"abc\");
+        let mut $index = 0;
+        while $index < @len($array) {
+            let c = $array[$index];
+            $index = $index + 1;
+        }
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_lower_sugar() {
+        let source = "
+struct Point { x: int, y: int }
+impl Point { fn sum(self) int { self.x + self.y } }
+fn f(mut xs: [int], (a, b): (int, bool)) Option<int> {
+    let mut total = a;
+    total += 1;
+    xs[total * 2] -= a;
+    let p = Point { y: 1, x: total };
+    let q = Point { x: 1, y: 2 };
+    let c = b && p.sum() > 1 || q.x == 1;
+    let first = Some(xs[0])?;
+    let wrap = Some;
+    wrap(first)
+}";
+        assert_eq!(
+            printed(source),
+            "fn sum(self: Point) i32 {
+    self.x + self.y
+}
+
+fn f(mut xs: [i32], $param: (i32, bool)) Option<i32> {
+    let (a, b) = $param;
+    let mut total = a;
+    total = total + 1;
+    {
+        let $index = total * 2;
+        xs[$index] = xs[$index] - a
+    }
+    let p = {
+        let $y = 1;
+        let $x = total;
+        Point { x: $x, y: $y }
+    };
+    let q = Point { x: 1, y: 2 };
+    let c = if (if b { sum(p) > 1 } else { false }) { true } else { q.x == 1 };
+    let first = match Some(xs[0]) {
+        Some($value) => $value,
+        None => return None,
+    };
+    let wrap = |$field| Some($field);
+    wrap(first)
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_lower_array_methods() {
+        let source = "
+fn f(mut xs: [[int]], i: int) Option<int> {
+    xs[i + 1].push([1, 2].len());
+    let mut ys = [3];
+    ys.pop()
+}";
+        assert_eq!(
+            printed(source),
+            "fn f(mut xs: [[i32]], i: i32) Option<i32> {
+    {
+        let $index = i + 1;
+        xs[$index] = push(xs[$index], len([1, 2]))
+    }
+    let mut ys = [3];
+    {
+        if @len(ys) == 0 { None } else {
+            let $last = ys[@len(ys) - 1];
+            ys = pop(ys);
+            Some($last)
+        }
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_lower_try_result() {
+        let source = "
+enum Shape { Circle(float), Rect { w: float, h: float } }
+const UNIT: float = 1.0;
+fn area(shape: Shape, scale: Result<float, string>) Result<float, string> {
+    let scale = scale?;
+    match shape { Shape::Circle(UNIT) => Ok(scale), Shape::Rect { h } => Ok(h * scale), _ => Err(\"no\") }
+}";
+        let (res, hir) = lowered(source);
+        assert_eq!(
+            print_program(&hir, &res),
+            "const UNIT: float = 1.0;
+
+fn area(shape: Shape, scale: Result<float, string>) Result<float, string> {
+    let scale = match scale {
+        Ok($value) => $value,
+        Err($error) => return Err($error),
+    };
+    match shape {
+        Circle(UNIT) => Ok(scale),
+        Rect(_, h) => Ok(h * scale),
+        _ => Err(\"no\"),
+    }
+}
+"
+        );
+
+        let rect = res.lookup(&span_of(source, "Rect")).unwrap();
+        let (adt, index) = hir.variant(rect).unwrap();
+        assert_eq!((adt.name.as_str(), index), ("Shape", 1));
+        let fields = &adt.variants[index].fields;
+        assert_eq!(fields[1].name, "h");
+        assert_eq!(hir.field_ty(&Ty::Error, rect, 1), Ty::Float);
+        let result = Ty::Result(Box::new(Ty::Float), Box::new(Ty::String));
+        assert_eq!(hir.field_ty(&result, DefId::ERR, 0), Ty::String);
+        assert_eq!(hir.variant(DefId::NONE).unwrap().1, 1);
+        let Ty::Fn { ret, .. } = &hir.fns[0].ty else {
+            panic!("`area` isn't a function");
+        };
+        assert_eq!(hir.fns[0].body.value.ty, **ret);
+    }
+
+    /// Returns the span of the first time `text` is written in the source.
+    fn span_of(source: &str, text: &str) -> Span {
+        let start = source.find(text).unwrap();
+        start..start + text.len()
+    }
+}
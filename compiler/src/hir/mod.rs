@@ -0,0 +1,350 @@
+//! The high-level IR, a typed tree that sits between the AST and the later stages of the compiler.
+//!
+//! It's lowered from a program that has been resolved and type checked, and keeps its shape, but
+//! it's a smaller language: names are replaced by what they refer to, every expression has its
+//! type, and the sugar is gone. `for` loops become `while` loops, `a += b` becomes `a = a + b`,
+//! `&&` and `||` become `if`s, `?` becomes a `match` that returns early, method calls become calls
+//! that pass the receiver first, struct literals and variant constructors become
+//! [`ExprKind::Construct`], and patterns in parameters become `let`s at the start of the body.
+
+mod lower;
+mod print;
+
+use crate::{
+    ast::{BinaryOp, Literal, UnaryOp},
+    lexer::Span,
+    resolve::DefId,
+    typeck::Ty,
+};
+
+pub use lower::lower;
+pub use print::print_program;
+
+/// A lowered program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// The structs and enums, including the built in `Option` and `Result`.
+    pub adts: Vec<Adt>,
+    /// Every function with a body, including methods and functions declared inside blocks.
+    pub fns: Vec<Fn>,
+    /// The constants and statics.
+    pub globals: Vec<Global>,
+    /// The functions declared with `extern fn`, which the host provides.
+    pub externs: Vec<Extern>,
+}
+
+impl Program {
+    /// Returns the type of a field of `base`, which is a value of the struct or variant `def`.
+    pub fn field_ty(&self, base: &Ty, def: DefId, index: usize) -> Ty {
+        match base {
+            Ty::Option(value) => (**value).clone(),
+            Ty::Result(value, _) if def == DefId::OK => (**value).clone(),
+            Ty::Result(_, error) => (**error).clone(),
+            _ => self
+                .variant(def)
+                .and_then(|(adt, variant)| adt.variants[variant].fields.get(index))
+                .map_or(Ty::Error, |field| field.ty.clone()),
+        }
+    }
+
+    /// Returns the struct or enum a struct or variant belongs to, and the index of the variant.
+    pub fn variant(&self, def: DefId) -> Option<(&Adt, usize)> {
+        self.adts.iter().find_map(|adt| {
+            let index = adt.variants.iter().position(|variant| variant.def == def)?;
+            Some((adt, index))
+        })
+    }
+}
+
+/// A struct or an enum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adt {
+    pub def: DefId,
+    pub name: String,
+    pub is_enum: bool,
+    /// The variants of an enum, in the order they're declared. A struct has one variant, which
+    /// has the struct's [`DefId`].
+    pub variants: Vec<Variant>,
+}
+
+/// A struct, or a variant of an enum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub def: DefId,
+    pub name: String,
+    /// The fields in the order they're declared, which is the order of the values in
+    /// [`ExprKind::Construct`].
+    pub fields: Vec<Field>,
+}
+
+/// A field of a struct or variant. The fields of a tuple variant are named `0`, `1` and so on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    /// The field's type, which is [`Ty::Error`] for the fields of `Option` and `Result`, as they
+    /// depend on the type of the value. [`Program::field_ty`] finds those.
+    pub ty: Ty,
+}
+
+/// A function or method.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fn {
+    pub def: DefId,
+    pub name: String,
+    /// The function's signature, a [`Ty::Fn`].
+    pub ty: Ty,
+    /// Whether the function is a generator, whose call makes a [`Ty::Gen`] that runs the body as
+    /// it's resumed. The body returns nothing.
+    pub generator: bool,
+    pub body: Body,
+    pub span: Span,
+}
+
+/// A function declared with `extern fn`, which is called by its name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extern {
+    pub def: DefId,
+    pub name: String,
+    /// The function's signature, a [`Ty::Fn`].
+    pub ty: Ty,
+    pub span: Span,
+}
+
+/// A `const` or `static`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub def: DefId,
+    pub name: String,
+    pub ty: Ty,
+    pub is_static: bool,
+    pub body: Body,
+    pub span: Span,
+}
+
+/// The code of a function or global, along with every variable declared in it, including those
+/// inside its closures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Body {
+    pub params: Vec<LocalId>,
+    pub locals: Vec<Local>,
+    pub value: Expr,
+}
+
+impl Body {
+    pub fn local(&self, id: LocalId) -> &Local {
+        &self.locals[id.0]
+    }
+}
+
+/// Identifies a variable in a [`Body`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LocalId(pub usize);
+
+/// A parameter or variable. The variables added by lowering have names starting with `$`, which
+/// can't clash with a name in the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Local {
+    pub name: String,
+    pub ty: Ty,
+    pub mutable: bool,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub tail: Option<Box<Expr>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// `let pattern = value;`
+    Let {
+        pattern: Pattern,
+        value: Option<Expr>,
+    },
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub ty: Ty,
+    /// The span of the source code that the expression was lowered from.
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Literal(Literal),
+    Local(LocalId),
+    Fn(DefId),
+    /// A `const` or `static`.
+    Global(DefId),
+    Tuple(Vec<Expr>),
+    Array(Vec<Expr>),
+    /// A struct or enum value, with the values of the fields of `def`, a struct or variant, in the
+    /// order they're declared.
+    Construct {
+        def: DefId,
+        fields: Vec<Expr>,
+    },
+    Block(Block),
+    /// `if cond then_branch else else_branch`
+    If {
+        cond: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Option<Box<Expr>>,
+    },
+    /// The only loop.
+    While {
+        cond: Box<Expr>,
+        body: Block,
+    },
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<Arm>,
+    },
+    Closure {
+        params: Vec<LocalId>,
+        body: Box<Expr>,
+    },
+    /// `start..end`. The bounds of a range value are read with [`ExprKind::Field`], as fields 0
+    /// and 1.
+    Range {
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+        inclusive: bool,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Intrinsic {
+        intrinsic: Intrinsic,
+        args: Vec<Expr>,
+    },
+    /// A field of a struct, by its index in the declaration.
+    Field {
+        base: Box<Expr>,
+        index: usize,
+    },
+    Index {
+        base: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `expr as ty`, where the type converted to is the type of the cast.
+    Cast(Box<Expr>),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    /// `lhs op rhs`, where `op` isn't `&&` or `||`.
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    Assign {
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Return(Option<Box<Expr>>),
+    /// `yield value`, which hands the value to the code that resumed the generator, and waits to
+    /// be resumed again.
+    Yield(Box<Expr>),
+    Break,
+    Continue,
+    /// An expression that had an error, which isn't lowered.
+    Error,
+}
+
+/// An operation the language provides that can't be written in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Intrinsic {
+    /// `len(array)`, the number of elements in an array, or of entries in a map.
+    Len,
+    /// `chars(string)`, the characters of a string as an array.
+    Chars,
+    /// `map_find(map, key)`, the index of the map's entry with the key, or -1 when it has none.
+    Find,
+    /// `map_key(map, index)` and `map_value(map, index)`, the key and the value of an entry of a
+    /// map, failing when the index is out of bounds.
+    EntryKey,
+    EntryValue,
+    /// `map_remove(map, index)`, a copy of a map without one of its entries, failing when the index
+    /// is out of bounds.
+    Remove,
+    /// `read_file(path)`, the contents of a file, or an empty string when it can't be read.
+    ReadFile,
+    /// `write_file(path, contents)`, replacing a file.
+    WriteFile,
+    /// `file_exists(path)`, whether a file or directory exists, or `false` when it can't be found
+    /// out.
+    FileExists,
+    /// `fs_error()`, the message of the error that the last of `read_file`, `write_file` and
+    /// `file_exists` failed with, or an empty string when it succeeded.
+    FsError,
+    /// `next(gen)`, which resumes a generator until it yields a value, returned in a `Some`, or
+    /// `None` once the generator is finished.
+    Next,
+}
+
+impl Intrinsic {
+    pub fn name(self) -> &'static str {
+        match self {
+            Intrinsic::Len => "len",
+            Intrinsic::Chars => "chars",
+            Intrinsic::Find => "map_find",
+            Intrinsic::EntryKey => "map_key",
+            Intrinsic::EntryValue => "map_value",
+            Intrinsic::Remove => "map_remove",
+            Intrinsic::ReadFile => "read_file",
+            Intrinsic::WriteFile => "write_file",
+            Intrinsic::FileExists => "file_exists",
+            Intrinsic::FsError => "fs_error",
+            Intrinsic::Next => "next",
+        }
+    }
+}
+
+/// A `pattern => body` arm of a `match`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternKind {
+    Wildcard,
+    Literal(Literal),
+    Binding(LocalId),
+    /// A `const` or `static`, which matches values equal to it.
+    Global(DefId),
+    Tuple(Vec<Pattern>),
+    /// A struct variant or tuple variant, with a pattern for each of the fields of `def` in the
+    /// order they're declared. Fields left out of the pattern are [`PatternKind::Wildcard`]s.
+    Variant {
+        def: DefId,
+        fields: Vec<Pattern>,
+    },
+}
+
+/// Parses, resolves, checks and lowers a program for a test, which expects it to have no errors.
+#[cfg(test)]
+pub(crate) fn lowered(source: &str) -> (crate::resolve::Resolution, Program) {
+    let program = crate::parser::parse_source(source).unwrap();
+    let res = crate::resolve::resolve(&program);
+    assert!(res.errors.is_empty(), "{:?}", res.errors);
+    let typeck = crate::typeck::check(&program, &res);
+    assert!(typeck.errors.is_empty(), "{:?}", typeck.errors);
+    let lowered = lower(&program, &res, &typeck);
+    (res, lowered)
+}
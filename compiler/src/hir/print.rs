@@ -0,0 +1,395 @@
+//! Prints the HIR as Ruffle-like source code, for looking at what a program lowers to. Variables
+//! added by lowering start with `$`, and intrinsics with `@`.
+
+use super::*;
+use crate::resolve::Resolution;
+
+/// Prints the functions and globals of a lowered program. `res` names the definitions it uses.
+pub fn print_program(program: &Program, res: &Resolution) -> String {
+    let mut printer = Printer {
+        program,
+        res,
+        body: None,
+        out: String::new(),
+        indent: 0,
+    };
+    for func in &program.externs {
+        printer
+            .out
+            .push_str(&format!("extern fn {}: {};\n", func.name, func.ty));
+    }
+    for global in &program.globals {
+        printer.global(global);
+        printer.out.push('\n');
+    }
+    for (i, func) in program.fns.iter().enumerate() {
+        if i > 0 || !program.globals.is_empty() || !program.externs.is_empty() {
+            printer.out.push('\n');
+        }
+        printer.func(func);
+        printer.out.push('\n');
+    }
+    printer.out
+}
+
+struct Printer<'a> {
+    program: &'a Program,
+    res: &'a Resolution,
+    /// The body being printed, which has the names of its variables.
+    body: Option<&'a Body>,
+    out: String,
+    indent: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+
+    fn comma_separated<T>(&mut self, elems: &[T], mut print: impl FnMut(&mut Self, &T)) {
+        for (i, elem) in elems.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            print(self, elem);
+        }
+    }
+
+    fn local(&mut self, id: LocalId) {
+        let local = self.body.unwrap().local(id);
+        self.out.push_str(&local.name);
+    }
+
+    fn def(&mut self, def: DefId) {
+        self.out.push_str(&self.res.def(def).name);
+    }
+
+    fn global(&mut self, global: &'a Global) {
+        self.body = Some(&global.body);
+        let keyword = if global.is_static { "static" } else { "const" };
+        self.out
+            .push_str(&format!("{} {}: {} = ", keyword, global.name, global.ty));
+        self.expr(&global.body.value);
+        self.out.push(';');
+    }
+
+    fn func(&mut self, func: &'a Fn) {
+        self.body = Some(&func.body);
+        self.out.push_str("fn ");
+        self.out.push_str(&func.name);
+        self.out.push('(');
+        self.comma_separated(&func.body.params, |p, &param| {
+            let local = p.body.unwrap().local(param);
+            if local.mutable {
+                p.out.push_str("mut ");
+            }
+            p.out.push_str(&format!("{}: {}", local.name, local.ty));
+        });
+        self.out.push(')');
+        if let Ty::Fn { ret, .. } = &func.ty {
+            if !ret.is_unit() {
+                self.out.push_str(&format!(" {}", ret));
+            }
+        }
+        self.out.push(' ');
+        self.expr(&func.body.value);
+    }
+
+    fn block(&mut self, block: &Block) {
+        if block.stmts.is_empty() && block.tail.is_none() {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push('{');
+        self.indent += 1;
+        for stmt in &block.stmts {
+            self.newline();
+            match stmt {
+                Stmt::Let { pattern, value } => {
+                    self.out.push_str("let ");
+                    self.pattern(pattern);
+                    if let Some(value) = value {
+                        self.out.push_str(" = ");
+                        self.expr(value);
+                    }
+                    self.out.push(';');
+                }
+                Stmt::Expr(expr) => {
+                    self.expr(expr);
+                    if !matches!(
+                        expr.kind,
+                        ExprKind::Block(_)
+                            | ExprKind::If { .. }
+                            | ExprKind::While { .. }
+                            | ExprKind::Match { .. }
+                    ) {
+                        self.out.push(';');
+                    }
+                }
+            }
+        }
+        if let Some(tail) = &block.tail {
+            self.newline();
+            self.expr(tail);
+        }
+        self.indent -= 1;
+        self.newline();
+        self.out.push('}');
+    }
+
+    /// Prints a branch of an `if`, which is only a block when lowering didn't make it something
+    /// else.
+    fn branch(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Block(block) => self.block(block),
+            _ => {
+                self.out.push_str("{ ");
+                self.expr(expr);
+                self.out.push_str(" }");
+            }
+        }
+    }
+
+    /// Prints an operand of an operator, in parentheses when it has operators of its own.
+    fn operand(&mut self, expr: &Expr) {
+        let parens = matches!(
+            expr.kind,
+            ExprKind::Binary { .. }
+                | ExprKind::Cast(_)
+                | ExprKind::Assign { .. }
+                | ExprKind::If { .. }
+                | ExprKind::Closure { .. }
+                | ExprKind::Range { .. }
+        );
+        if parens {
+            self.out.push('(');
+        }
+        self.expr(expr);
+        if parens {
+            self.out.push(')');
+        }
+    }
+
+    /// Prints the condition of an `if` or `while`, in parentheses when it's an `if` itself.
+    fn cond(&mut self, cond: &Expr) {
+        if let ExprKind::If { .. } = cond.kind {
+            self.operand(cond);
+        } else {
+            self.expr(cond);
+        }
+    }
+
+    fn construct(&mut self, def: DefId, fields: &[Expr]) {
+        self.def(def);
+        let names = self
+            .program
+            .variant(def)
+            .map(|(adt, index)| &adt.variants[index].fields);
+        let Some(names) = names.filter(|_| !fields.is_empty()) else {
+            return;
+        };
+        if names[0].name == "0" {
+            self.out.push('(');
+            self.comma_separated(fields, |p, field| p.expr(field));
+            self.out.push(')');
+        } else {
+            self.out.push_str(" { ");
+            let named: Vec<_> = names.iter().zip(fields).collect();
+            self.comma_separated(&named, |p, (name, field)| {
+                p.out.push_str(&name.name);
+                p.out.push_str(": ");
+                p.expr(field);
+            });
+            self.out.push_str(" }");
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.literal(literal),
+            ExprKind::Local(id) => self.local(*id),
+            ExprKind::Fn(def) | ExprKind::Global(def) => self.def(*def),
+            ExprKind::Tuple(elems) => {
+                self.out.push('(');
+                self.comma_separated(elems, |p, elem| p.expr(elem));
+                if elems.len() == 1 {
+                    self.out.push(',');
+                }
+                self.out.push(')');
+            }
+            ExprKind::Array(elems) => {
+                self.out.push('[');
+                self.comma_separated(elems, |p, elem| p.expr(elem));
+                self.out.push(']');
+            }
+            ExprKind::Construct { def, fields } => self.construct(*def, fields),
+            ExprKind::Block(block) => self.block(block),
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.out.push_str("if ");
+                self.cond(cond);
+                self.out.push(' ');
+                self.branch(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.out.push_str(" else ");
+                    match else_branch.kind {
+                        ExprKind::If { .. } => self.expr(else_branch),
+                        _ => self.branch(else_branch),
+                    }
+                }
+            }
+            ExprKind::While { cond, body } => {
+                self.out.push_str("while ");
+                self.cond(cond);
+                self.out.push(' ');
+                self.block(body);
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.out.push_str("match ");
+                self.expr(scrutinee);
+                self.out.push_str(" {");
+                self.indent += 1;
+                for arm in arms {
+                    self.newline();
+                    self.pattern(&arm.pattern);
+                    self.out.push_str(" => ");
+                    self.expr(&arm.body);
+                    self.out.push(',');
+                }
+                self.indent -= 1;
+                self.newline();
+                self.out.push('}');
+            }
+            ExprKind::Closure { params, body } => {
+                self.out.push('|');
+                self.comma_separated(params, |p, &param| p.local(param));
+                self.out.push_str("| ");
+                self.expr(body);
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                if let Some(start) = start {
+                    self.operand(start);
+                }
+                self.out.push_str(if *inclusive { "..=" } else { ".." });
+                if let Some(end) = end {
+                    self.operand(end);
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                self.operand(callee);
+                self.out.push('(');
+                self.comma_separated(args, |p, arg| p.expr(arg));
+                self.out.push(')');
+            }
+            ExprKind::Intrinsic { intrinsic, args } => {
+                self.out.push('@');
+                self.out.push_str(intrinsic.name());
+                self.out.push('(');
+                self.comma_separated(args, |p, arg| p.expr(arg));
+                self.out.push(')');
+            }
+            ExprKind::Field { base, index } => {
+                self.operand(base);
+                let name = match &base.ty {
+                    Ty::Adt { def, .. } => self
+                        .program
+                        .variant(*def)
+                        .map(|(adt, variant)| adt.variants[variant].fields[*index].name.clone()),
+                    _ => None,
+                };
+                self.out.push('.');
+                self.out
+                    .push_str(&name.unwrap_or_else(|| index.to_string()));
+            }
+            ExprKind::Index { base, index } => {
+                self.operand(base);
+                self.out.push('[');
+                self.expr(index);
+                self.out.push(']');
+            }
+            ExprKind::Cast(inner) => {
+                self.operand(inner);
+                self.out.push_str(&format!(" as {}", expr.ty));
+            }
+            ExprKind::Unary { op, expr } => {
+                self.out.push_str(op.as_str());
+                self.operand(expr);
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.operand(lhs);
+                self.out.push_str(&format!(" {} ", op.as_str()));
+                self.operand(rhs);
+            }
+            ExprKind::Assign { target, value } => {
+                self.expr(target);
+                self.out.push_str(" = ");
+                self.expr(value);
+            }
+            ExprKind::Return(value) => {
+                self.out.push_str("return");
+                if let Some(value) = value {
+                    self.out.push(' ');
+                    self.expr(value);
+                }
+            }
+            ExprKind::Yield(value) => {
+                self.out.push_str("yield ");
+                self.expr(value);
+            }
+            ExprKind::Break => self.out.push_str("break"),
+            ExprKind::Continue => self.out.push_str("continue"),
+            ExprKind::Error => self.out.push_str("<error>"),
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        match literal {
+            Literal::Integer(value) => self.out.push_str(&value.to_string()),
+            Literal::OversizedInteger(digits) => self.out.push_str(digits),
+            Literal::Float(value) => self.out.push_str(&format!("{:?}", value)),
+            Literal::String(value) => self.out.push_str(&format!("{:?}", value)),
+            Literal::Char(value) => self.out.push_str(&format!("{:?}", value)),
+            Literal::Bool(value) => self.out.push_str(&value.to_string()),
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match &pattern.kind {
+            PatternKind::Wildcard => self.out.push('_'),
+            PatternKind::Literal(literal) => self.literal(literal),
+            PatternKind::Binding(id) => {
+                if self.body.unwrap().local(*id).mutable {
+                    self.out.push_str("mut ");
+                }
+                self.local(*id);
+            }
+            PatternKind::Global(def) => self.def(*def),
+            PatternKind::Tuple(elems) => {
+                self.out.push('(');
+                self.comma_separated(elems, |p, elem| p.pattern(elem));
+                if elems.len() == 1 {
+                    self.out.push(',');
+                }
+                self.out.push(')');
+            }
+            PatternKind::Variant { def, fields } => {
+                self.def(*def);
+                if !fields.is_empty() {
+                    self.out.push('(');
+                    self.comma_separated(fields, |p, field| p.pattern(field));
+                    self.out.push(')');
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1341 @@
+//! A tree-walking interpreter, which runs a program by evaluating its HIR directly, without
+//! building the MIR or generating any code.
+//!
+//! Values are dynamically typed, but the operations on them come from the types the checker gave
+//! the expressions, such as which integer type an addition overflows. Values are never shared:
+//! assigning to a field or an element of a variable replaces the variable with a copy that has the
+//! part replaced, and closures capture the values of variables when they're made. The copies share
+//! what they didn't change, so that copying an array only copies its elements when one of the
+//! copies is changed.
+//!
+//! The program has to have been checked without errors. Running it fails the same way native code
+//! does, when it calls `panic`, when integer arithmetic overflows, and when an index is out of
+//! bounds, and stops when it goes past its [`Limits`]. Generators need frames that outlive their
+//! calls, so only the VM runs them, and calling one here panics.
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::Display,
+    hash::{DefaultHasher, Hash, Hasher},
+    rc::Rc,
+};
+
+use crate::{
+    ast::{BinaryOp, Literal, UnaryOp},
+    codegen::runtime::{DIVISION_BY_ZERO, OVERFLOW, SHIFT_OUT_OF_RANGE},
+    console::Console,
+    ffi::{self, Externs},
+    fs::Files,
+    hir::{self, Body, Expr, ExprKind, LocalId, Pattern, PatternKind, Stmt},
+    lexer::Span,
+    limits::{Limit, Limits},
+    maps::Map,
+    resolve::DefId,
+    source_map::SourceMap,
+    strings,
+    typeck::{eval_int, IntTy, Ty},
+};
+
+/// How deep calls can nest before the program is stopped, rather than the interpreter running out
+/// of stack.
+const MAX_DEPTH: usize = 10_000;
+
+/// How many calls of a panic's trace are displayed.
+const MAX_TRACE: usize = 20;
+
+/// A value of a running program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value<'a> {
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    String(Rc<str>),
+    /// A tuple, where `()` is the one without elements.
+    Tuple(Rc<Vec<Value<'a>>>),
+    /// A struct, or a variant of an enum, with its fields in the order they're declared.
+    Variant {
+        def: DefId,
+        fields: Rc<Vec<Value<'a>>>,
+    },
+    Array(Rc<Vec<Value<'a>>>),
+    Map(Rc<Map<Value<'a>>>),
+    /// A range, with its start and end if it has them, which are its fields 0 and 1.
+    Range {
+        bounds: Rc<[Option<Value<'a>>; 2]>,
+        inclusive: bool,
+    },
+    Fn(DefId),
+    Closure(Rc<Closure<'a>>),
+}
+
+impl Value<'_> {
+    pub fn unit() -> Self {
+        Value::Tuple(Rc::new(Vec::new()))
+    }
+}
+
+/// A closure, with the variables of the body it's in as they were when it was made.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure<'a> {
+    body: &'a Body,
+    params: &'a [LocalId],
+    value: &'a Expr,
+    locals: Vec<Option<Value<'a>>>,
+}
+
+/// A failure that stops a running program, such as a call to `panic` or arithmetic that overflows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Panic {
+    pub message: String,
+    /// The expression that failed.
+    pub span: Span,
+    /// The calls that were running, innermost first, with the name of each one's function and
+    /// where it was, which is `span` for the innermost and the call of the next one in for the
+    /// others.
+    pub trace: Vec<(String, Span)>,
+    /// The limit the program went past, if that's what stopped it.
+    pub limit: Option<Limit>,
+}
+
+impl Panic {
+    /// Returns the failure of a program that went past a limit at `span`.
+    pub fn exceeded(limit: Limit, span: Span) -> Self {
+        Panic {
+            limit: Some(limit),
+            ..panic(&limit.to_string(), &span)
+        }
+    }
+
+    /// Pairs the panic with the files of the program, so it can be displayed with where each call
+    /// of its trace was.
+    pub fn with_source_map<'a>(&'a self, source_map: &'a SourceMap) -> MappedPanic<'a> {
+        MappedPanic {
+            panic: self,
+            source_map,
+        }
+    }
+}
+
+impl Display for Panic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "panicked: {}", self.message)
+    }
+}
+
+/// A panic along with the files of the program it happened in.
+pub struct MappedPanic<'a> {
+    pub panic: &'a Panic,
+    pub source_map: &'a SourceMap,
+}
+
+impl Display for MappedPanic<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.panic)?;
+        let trace = &self.panic.trace;
+        for (name, span) in trace.iter().take(MAX_TRACE) {
+            match self.source_map.location(span.start) {
+                Some(location) => write!(f, "\n    at {} ({})", name, location)?,
+                None => write!(f, "\n    at {}", name)?,
+            }
+        }
+        // Such as the calls of a stack overflow, which all look alike
+        if trace.len() > MAX_TRACE {
+            write!(f, "\n    ... {} more calls", trace.len() - MAX_TRACE)?;
+        }
+        Ok(())
+    }
+}
+
+/// Why evaluating an expression stopped before it had a value.
+enum Unwind<'a> {
+    Break,
+    Continue,
+    Return(Value<'a>),
+    /// A call whose value the function returns, which is made in place of the function's own, so
+    /// that recursion in tail position runs in constant space like it does when compiled.
+    TailCall {
+        callee: Value<'a>,
+        args: Vec<Value<'a>>,
+        span: Span,
+    },
+    Panic(Panic),
+}
+
+impl From<Panic> for Unwind<'_> {
+    fn from(panic: Panic) -> Self {
+        Unwind::Panic(panic)
+    }
+}
+
+type Eval<'a> = Result<Value<'a>, Unwind<'a>>;
+
+/// Runs a checked program: initializes its globals in order, then calls its `main`. Its `extern`
+/// functions call those of `host`.
+pub fn run<'a>(
+    program: &'a hir::Program,
+    limits: Limits,
+    console: Console<'a>,
+    host: &'a Externs,
+) -> Result<(), Panic> {
+    let mut interpreter = Interpreter::new(program, limits, console, host)?;
+    if let Some(main) = program.fns.iter().find(|func| func.name == "main") {
+        interpreter.call(Value::Fn(main.def), Vec::new(), &main.span)?;
+    }
+    Ok(())
+}
+
+/// The state of a running program.
+pub struct Interpreter<'a> {
+    fns: HashMap<DefId, &'a hir::Fn>,
+    externs: HashMap<DefId, &'a hir::Extern>,
+    /// The index of each global, and its value once it's initialized.
+    global_ids: HashMap<DefId, usize>,
+    globals: Vec<Option<Value<'a>>>,
+    /// The calls that are running, innermost last.
+    frames: Vec<Frame<'a>>,
+    limits: Limits,
+    /// The expressions that the call from the host that's running can still evaluate.
+    fuel: Option<u64>,
+    console: Console<'a>,
+    files: Files,
+    host: &'a Externs,
+}
+
+/// The variables of a call that's running, which are those of the body of its function.
+struct Frame<'a> {
+    /// The name of the function, for the traces of panics.
+    name: &'a str,
+    /// Where it was called from, which calls in tail position don't change.
+    call: Span,
+    body: &'a Body,
+    locals: Vec<Option<Value<'a>>>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Makes an interpreter for a checked program, initializing its globals in order.
+    pub fn new(
+        program: &'a hir::Program,
+        limits: Limits,
+        console: Console<'a>,
+        host: &'a Externs,
+    ) -> Result<Self, Panic> {
+        let mut interpreter = Interpreter {
+            fns: (program.fns.iter()).map(|func| (func.def, func)).collect(),
+            externs: (program.externs.iter())
+                .map(|func| (func.def, func))
+                .collect(),
+            global_ids: (program.globals.iter().enumerate())
+                .map(|(i, global)| (global.def, i))
+                .collect(),
+            globals: vec![None; program.globals.len()],
+            frames: Vec::new(),
+            limits,
+            fuel: limits.fuel,
+            console,
+            files: Files::default(),
+            host,
+        };
+        for (i, global) in program.globals.iter().enumerate() {
+            let body = &global.body;
+            let locals = vec![None; body.locals.len()];
+            interpreter.fuel = limits.fuel;
+            interpreter.frames.push(Frame {
+                name: &global.name,
+                call: global.span.clone(),
+                body,
+                locals,
+            });
+            let mut result = interpreter.expr(&body.value);
+            interpreter.trace(&mut result);
+            interpreter.frames.pop();
+            interpreter.globals[i] = Some(returned(result)?);
+        }
+        Ok(interpreter)
+    }
+
+    /// Calls a function or closure value with its arguments. A call from the host starts with all
+    /// of the fuel of the limits.
+    pub fn call(
+        &mut self,
+        mut callee: Value<'a>,
+        mut args: Vec<Value<'a>>,
+        span: &Span,
+    ) -> Result<Value<'a>, Panic> {
+        if self.frames.is_empty() {
+            self.fuel = self.limits.fuel;
+        }
+        if self.frames.len() >= self.limits.depth(MAX_DEPTH) {
+            return Err(Panic::exceeded(Limit::Depth, span.clone()));
+        }
+        let call = span.clone();
+        let mut span = span.clone();
+        loop {
+            let (name, body, mut locals, params, value) = match callee {
+                Value::Fn(def) => match self.fns.get(&def) {
+                    Some(func) if func.generator => {
+                        let message =
+                            format!("`{}` is a generator, which only the VM runs", func.name);
+                        return Err(panic(&message, &span));
+                    }
+                    Some(func) => {
+                        let body = &func.body;
+                        let locals = vec![None; body.locals.len()];
+                        (&func.name[..], body, locals, &body.params[..], &body.value)
+                    }
+                    None => match self.externs.get(&def) {
+                        Some(func) => return self.host_call(func, &args, &span),
+                        None => return self.builtin(def, args, &span),
+                    },
+                },
+                Value::Closure(closure) => {
+                    let locals = closure.locals.clone();
+                    let (body, params, value) = (closure.body, closure.params, closure.value);
+                    ("{closure}", body, locals, params, value)
+                }
+                _ => unreachable!("only functions are called"),
+            };
+            for (param, arg) in params.iter().zip(args) {
+                locals[param.0] = Some(arg);
+            }
+            self.frames.push(Frame {
+                name,
+                call: call.clone(),
+                body,
+                locals,
+            });
+            let mut result = self.eval(value, true);
+            self.trace(&mut result);
+            self.frames.pop();
+            match result {
+                Err(Unwind::TailCall {
+                    callee: next,
+                    args: next_args,
+                    span: next_span,
+                }) => (callee, args, span) = (next, next_args, next_span),
+                result => return returned(result),
+            }
+        }
+    }
+
+    /// Calls a function without a body, which is either built in, or a method that a trait
+    /// requires, which only the trait's implementations define.
+    fn builtin(
+        &mut self,
+        def: DefId,
+        mut args: Vec<Value<'a>>,
+        span: &Span,
+    ) -> Result<Value<'a>, Panic> {
+        let failed = |message: &str| panic(message, span);
+        let io_failed = |error: std::io::Error| panic(&error.to_string(), span);
+        Ok(match (def, &mut args[..]) {
+            (DefId::PANIC, [Value::String(message)]) => return Err(failed(message)),
+            (DefId::PRINT, [Value::String(text)]) => {
+                self.console.print(text).map_err(io_failed)?;
+                Value::unit()
+            }
+            (DefId::PRINTLN, [Value::String(text)]) => {
+                self.console.println(text).map_err(io_failed)?;
+                Value::unit()
+            }
+            (DefId::INPUT, []) => Value::String(self.console.input().map_err(io_failed)?.into()),
+            (DefId::STRING_LEN, [Value::String(string)]) => Value::Int(strings::len(string)),
+            (DefId::STRING_CONTAINS, [Value::String(string), Value::String(pattern)]) => {
+                Value::Bool(string.contains(&**pattern))
+            }
+            (DefId::STRING_SPLIT, [Value::String(string), Value::String(separator)]) => {
+                let parts = strings::split(string, separator).map_err(failed)?;
+                let parts = parts.into_iter().map(|part| Value::String(part.into()));
+                Value::Array(Rc::new(parts.collect()))
+            }
+            (DefId::STRING_TO_UPPER, [Value::String(string)]) => {
+                Value::String(strings::to_upper(string).into())
+            }
+            (DefId::STRING_TO_LOWER, [Value::String(string)]) => {
+                Value::String(strings::to_lower(string).into())
+            }
+            (DefId::ARRAY_LEN, [Value::Array(elems)]) => Value::Int(elems.len() as i128),
+            // Changing the array a method was called on is left to the caller, and the array is
+            // changed in place when nothing else refers to it
+            (DefId::ARRAY_PUSH, [Value::Array(elems), value]) => {
+                Rc::make_mut(elems).push(value.clone());
+                args.swap_remove(0)
+            }
+            (DefId::ARRAY_POP, [Value::Array(elems)]) => {
+                Rc::make_mut(elems).pop();
+                args.swap_remove(0)
+            }
+            (DefId::MAP_NEW, []) => Value::Map(Rc::new(Map::default())),
+            (DefId::MATH_SQRT, [Value::Float(x)]) => Value::Float(x.sqrt()),
+            (DefId::MATH_FLOOR, [Value::Float(x)]) => Value::Float(x.floor()),
+            (DefId::MATH_CEIL, [Value::Float(x)]) => Value::Float(x.ceil()),
+            (DefId::MATH_POW, [Value::Float(x), Value::Float(y)]) => Value::Float(x.powf(*y)),
+            (DefId::MAP_LEN, [Value::Map(map)]) => Value::Int(map.len() as i128),
+            // Changed in place when nothing else refers to the map
+            (DefId::MAP_INSERT, [Value::Map(map), key, value]) => {
+                let equal_key = |other: &Value| equal(key, other);
+                Rc::make_mut(map).insert(hash(key), key.clone(), value.clone(), equal_key);
+                args.swap_remove(0)
+            }
+            _ => return Err(failed("called a function without a body")),
+        })
+    }
+
+    /// Evaluates an intrinsic. `remove` changes the map in place when nothing else refers to it.
+    fn intrinsic(
+        &mut self,
+        intrinsic: hir::Intrinsic,
+        mut args: Vec<Value<'a>>,
+        span: &Span,
+    ) -> Result<Value<'a>, Panic> {
+        Ok(match (intrinsic, &mut args[..]) {
+            (hir::Intrinsic::Len, [Value::Array(elems)]) => Value::Int(elems.len() as i128),
+            (hir::Intrinsic::Len, [Value::Map(map)]) => Value::Int(map.len() as i128),
+            (hir::Intrinsic::Chars, [Value::String(string)]) => {
+                Value::Array(Rc::new(string.chars().map(Value::Char).collect()))
+            }
+            (hir::Intrinsic::Find, [Value::Map(map), key]) => {
+                let index = map.find(hash(key), |other| equal(key, other));
+                Value::Int(index.map_or(-1, |index| index as i128))
+            }
+            (hir::Intrinsic::EntryKey, [Value::Map(map), Value::Int(index)]) => map.entries()
+                [in_bounds(map.entries(), *index, span)?]
+            .key
+            .clone(),
+            (hir::Intrinsic::EntryValue, [Value::Map(map), Value::Int(index)]) => map.entries()
+                [in_bounds(map.entries(), *index, span)?]
+            .value
+            .clone(),
+            (hir::Intrinsic::Remove, [Value::Map(map), Value::Int(index)]) => {
+                let index = in_bounds(map.entries(), *index, span)?;
+                Rc::make_mut(map).remove(index);
+                args.swap_remove(0)
+            }
+            (hir::Intrinsic::ReadFile, [Value::String(path)]) => {
+                Value::String(self.files.read(path).into())
+            }
+            (hir::Intrinsic::WriteFile, [Value::String(path), Value::String(contents)]) => {
+                self.files.write(path, contents);
+                Value::unit()
+            }
+            (hir::Intrinsic::FileExists, [Value::String(path)]) => {
+                Value::Bool(self.files.exists(path))
+            }
+            (hir::Intrinsic::FsError, []) => Value::String(self.files.error().into()),
+            _ => unreachable!("the checker checks the arguments of intrinsics"),
+        })
+    }
+
+    /// Evaluates the value of an assignment. An update of the array or map in the variable it's
+    /// assigned to, like `array = push(array, value)`, takes it out of the variable once its
+    /// arguments are evaluated, so that the update can change it in place.
+    fn assigned(&mut self, target: &'a Expr, value: &'a Expr) -> Eval<'a> {
+        let ExprKind::Local(id) = target.kind else {
+            return self.expr(value);
+        };
+        let updates =
+            |args: &[Expr]| matches!(args.first(), Some(arg) if arg.kind == ExprKind::Local(id));
+        match &value.kind {
+            ExprKind::Call { callee, args } if updates(args) => {
+                let ExprKind::Fn(def @ (DefId::ARRAY_PUSH | DefId::ARRAY_POP | DefId::MAP_INSERT)) =
+                    callee.kind
+                else {
+                    return self.expr(value);
+                };
+                // The fuel of the call, which isn't evaluated as an expression
+                self.burn(&value.span)?;
+                self.expr(callee)?;
+                let args = self.exprs(args)?;
+                self.frame().locals[id.0] = None;
+                Ok(self.builtin(def, args, &value.span)?)
+            }
+            ExprKind::Intrinsic {
+                intrinsic: intrinsic @ hir::Intrinsic::Remove,
+                args,
+            } if updates(args) => {
+                self.burn(&value.span)?;
+                let args = self.exprs(args)?;
+                self.frame().locals[id.0] = None;
+                Ok(self.intrinsic(*intrinsic, args, &value.span)?)
+            }
+            _ => self.expr(value),
+        }
+    }
+
+    /// Uses up the fuel of evaluating an expression.
+    fn burn(&mut self, span: &Span) -> Result<(), Panic> {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(Panic::exceeded(Limit::Fuel, span.clone()));
+            }
+            *fuel -= 1;
+        }
+        Ok(())
+    }
+
+    /// Calls the host's function for an `extern` function.
+    fn host_call(
+        &self,
+        func: &hir::Extern,
+        args: &[Value<'a>],
+        span: &Span,
+    ) -> Result<Value<'a>, Panic> {
+        let Ty::Fn { ret, .. } = &func.ty else {
+            unreachable!("`extern` functions are functions");
+        };
+        let args: Vec<_> = (args.iter())
+            .map(|arg| match arg {
+                Value::Int(value) => ffi::Value::Int(*value),
+                Value::Float(value) => ffi::Value::Float(*value),
+                Value::Bool(value) => ffi::Value::Bool(*value),
+                Value::Char(value) => ffi::Value::Char(*value),
+                Value::String(value) => ffi::Value::String(value.to_string()),
+                _ => unreachable!("only numbers, `bool`s and `char`s cross to the host"),
+            })
+            .collect();
+        let value =
+            (self.host.call(&func.name, &args, ret)).map_err(|message| panic(&message, span))?;
+        Ok(match value {
+            ffi::Value::Int(value) => Value::Int(value),
+            ffi::Value::Float(value) => Value::Float(value),
+            ffi::Value::Bool(value) => Value::Bool(value),
+            ffi::Value::Char(value) => Value::Char(value),
+            ffi::Value::String(value) => Value::String(value.into()),
+            ffi::Value::Unit => Value::unit(),
+        })
+    }
+
+    fn frame(&mut self) -> &mut Frame<'a> {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Evaluates a block, whose value the function returns when `tail` is set.
+    fn block(&mut self, block: &'a hir::Block, tail: bool) -> Eval<'a> {
+        for stmt in &block.stmts {
+            match stmt {
+                Stmt::Let { pattern, value } => {
+                    if let Some(value) = value {
+                        let value = self.expr(value)?;
+                        self.pattern(pattern, &value)?;
+                    }
+                }
+                Stmt::Expr(expr) => {
+                    self.expr(expr)?;
+                }
+            }
+        }
+        match &block.tail {
+            Some(value) => self.eval(value, tail),
+            None => Ok(Value::unit()),
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) -> Eval<'a> {
+        self.eval(expr, false)
+    }
+
+    /// Evaluates an expression, whose value the function returns when `tail` is set, so that a
+    /// call that gives its value is left to the caller.
+    fn eval(&mut self, expr: &'a Expr, tail: bool) -> Eval<'a> {
+        let span = &expr.span;
+        self.burn(span)?;
+        Ok(match &expr.kind {
+            ExprKind::Literal(literal) => match literal {
+                Literal::Integer(value) => Value::Int(*value as i128),
+                Literal::Float(value) => Value::Float(*value),
+                Literal::String(value) => Value::String(value.as_str().into()),
+                Literal::Char(value) => Value::Char(*value),
+                Literal::Bool(value) => Value::Bool(*value),
+                Literal::OversizedInteger(_) => unreachable!("the checker reports it"),
+            },
+            ExprKind::Local(id) => self.frame().locals[id.0]
+                .clone()
+                .expect("variables are assigned before they're read"),
+            ExprKind::Fn(def) => Value::Fn(*def),
+            ExprKind::Global(def) => self.global(*def, span)?,
+            ExprKind::Tuple(elems) => Value::Tuple(Rc::new(self.exprs(elems)?)),
+            ExprKind::Array(elems) => Value::Array(Rc::new(self.exprs(elems)?)),
+            ExprKind::Construct { def, fields } => Value::Variant {
+                def: *def,
+                fields: Rc::new(self.exprs(fields)?),
+            },
+            ExprKind::Block(block) => self.block(block, tail)?,
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                if self.cond(cond)? {
+                    self.eval(then_branch, tail)?
+                } else if let Some(else_branch) = else_branch {
+                    self.eval(else_branch, tail)?
+                } else {
+                    Value::unit()
+                }
+            }
+            ExprKind::While { cond, body } => {
+                while self.cond(cond)? {
+                    match self.block(body, false) {
+                        Ok(_) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
+                }
+                Value::unit()
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let scrutinee = self.expr(scrutinee)?;
+                for arm in arms {
+                    if self.pattern(&arm.pattern, &scrutinee)? {
+                        return self.eval(&arm.body, tail);
+                    }
+                }
+                unreachable!("the checker makes sure that matches are exhaustive")
+            }
+            ExprKind::Closure { params, body } => {
+                let frame = self.frame();
+                Value::Closure(Rc::new(Closure {
+                    body: frame.body,
+                    params,
+                    value: body,
+                    locals: frame.locals.clone(),
+                }))
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = start.as_ref().map(|start| self.expr(start)).transpose()?;
+                let end = end.as_ref().map(|end| self.expr(end)).transpose()?;
+                Value::Range {
+                    bounds: Rc::new([start, end]),
+                    inclusive: *inclusive,
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                let callee = self.expr(callee)?;
+                let args = self.exprs(args)?;
+                if tail {
+                    let span = span.clone();
+                    return Err(Unwind::TailCall { callee, args, span });
+                }
+                self.call(callee, args, span)?
+            }
+            ExprKind::Intrinsic { intrinsic, args } => {
+                let args = self.exprs(args)?;
+                self.intrinsic(*intrinsic, args, span)?
+            }
+            ExprKind::Field { base, index } => match self.expr(base)? {
+                Value::Tuple(elems) => elems[*index].clone(),
+                Value::Variant { fields, .. } => fields[*index].clone(),
+                Value::Range { bounds, .. } => bounds[*index]
+                    .clone()
+                    .expect("only the bounds a range has are read"),
+                _ => unreachable!("only tuples, structs and ranges have fields"),
+            },
+            ExprKind::Index { base, index } => {
+                let base = self.expr(base)?;
+                let index = self.expr(index)?;
+                match (base, index) {
+                    (Value::Array(elems), Value::Int(index)) => {
+                        elems[in_bounds(&elems, index, span)?].clone()
+                    }
+                    (Value::String(string), Value::Int(index)) => {
+                        let c = strings::char_at(&string, index);
+                        Value::Char(c.map_err(|message| panic(message, span))?)
+                    }
+                    (Value::String(string), Value::Range { bounds, inclusive }) => {
+                        let [start, end] = (*bounds).clone().map(|bound| {
+                            bound.map(|bound| match bound {
+                                Value::Int(bound) => bound,
+                                _ => unreachable!("strings are sliced by ranges of integers"),
+                            })
+                        });
+                        let slice = strings::slice(&string, start, end, inclusive);
+                        Value::String(slice.map_err(|message| panic(message, span))?.into())
+                    }
+                    _ => unreachable!("only arrays and strings are indexed"),
+                }
+            }
+            ExprKind::Cast(inner) => {
+                let value = self.expr(inner)?;
+                cast(value, &expr.ty)
+            }
+            ExprKind::Unary { op, expr: operand } => {
+                let value = self.expr(operand)?;
+                unary(*op, &operand.ty, value).map_err(|message| panic(message, span))?
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let (ty, lhs) = (&lhs.ty, self.expr(lhs)?);
+                let rhs = self.expr(rhs)?;
+                binary(*op, ty, lhs, rhs).map_err(|message| panic(message, span))?
+            }
+            ExprKind::Assign { target, value } => {
+                let value = self.assigned(target, value)?;
+                self.assign(target, value)?;
+                Value::unit()
+            }
+            ExprKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value, true)?,
+                    None => Value::unit(),
+                };
+                return Err(Unwind::Return(value));
+            }
+            ExprKind::Yield(_) => unreachable!("generators aren't called"),
+            ExprKind::Break => return Err(Unwind::Break),
+            ExprKind::Continue => return Err(Unwind::Continue),
+            ExprKind::Error => unreachable!("the checker reports it"),
+        })
+    }
+
+    fn exprs(&mut self, exprs: &'a [Expr]) -> Result<Vec<Value<'a>>, Unwind<'a>> {
+        exprs.iter().map(|expr| self.expr(expr)).collect()
+    }
+
+    /// Gives a panic the calls that are running, unless a call further in already gave it those
+    /// that were.
+    fn trace(&self, result: &mut Eval<'a>) {
+        let Err(Unwind::Panic(panic)) = result else {
+            return;
+        };
+        if !panic.trace.is_empty() {
+            return;
+        }
+        let mut span = &panic.span;
+        for frame in self.frames.iter().rev() {
+            panic.trace.push((frame.name.to_string(), span.clone()));
+            span = &frame.call;
+        }
+    }
+
+    fn cond(&mut self, cond: &'a Expr) -> Result<bool, Unwind<'a>> {
+        match self.expr(cond)? {
+            Value::Bool(value) => Ok(value),
+            _ => unreachable!("conditions are `bool`s"),
+        }
+    }
+
+    fn global(&self, def: DefId, span: &Span) -> Result<Value<'a>, Panic> {
+        let global = self.global_ids[&def];
+        // Such as a global whose initializer calls a function that reads a later one
+        (self.globals[global].clone())
+            .ok_or_else(|| panic("global used before it's initialized", span))
+    }
+
+    /// Assigns a value to a place. Assigning to a field or element assigns a copy of the whole
+    /// struct or array with that part replaced.
+    fn assign(&mut self, target: &'a Expr, value: Value<'a>) -> Result<(), Unwind<'a>> {
+        let span = &target.span;
+        match &target.kind {
+            ExprKind::Local(id) => self.frame().locals[id.0] = Some(value),
+            ExprKind::Global(def) => self.globals[self.global_ids[def]] = Some(value),
+            ExprKind::Field { base, index } => {
+                let updated = match self.expr(base)? {
+                    Value::Tuple(mut elems) => {
+                        Rc::make_mut(&mut elems)[*index] = value;
+                        Value::Tuple(elems)
+                    }
+                    Value::Variant { def, mut fields } => {
+                        Rc::make_mut(&mut fields)[*index] = value;
+                        Value::Variant { def, fields }
+                    }
+                    _ => unreachable!("only the fields of tuples and structs are assigned to"),
+                };
+                self.assign(base, updated)?;
+            }
+            ExprKind::Index { base, index } => {
+                let whole = self.expr(base)?;
+                let index = self.expr(index)?;
+                let updated = match (whole, index) {
+                    (Value::Array(mut elems), Value::Int(index)) => {
+                        let index = in_bounds(&elems, index, span)?;
+                        Rc::make_mut(&mut elems)[index] = value;
+                        Value::Array(elems)
+                    }
+                    _ => unreachable!("only arrays are indexed"),
+                };
+                self.assign(base, updated)?;
+            }
+            _ => unreachable!("only places are assigned to"),
+        }
+        Ok(())
+    }
+
+    /// Matches a value against a pattern, assigning the variables it binds, and returns whether
+    /// it matched.
+    fn pattern(&mut self, pattern: &'a Pattern, value: &Value<'a>) -> Result<bool, Unwind<'a>> {
+        Ok(match (&pattern.kind, value) {
+            (PatternKind::Wildcard, _) => true,
+            (PatternKind::Binding(id), _) => {
+                self.frame().locals[id.0] = Some(value.clone());
+                true
+            }
+            (PatternKind::Literal(literal), _) => {
+                let expected = match literal {
+                    Literal::Integer(value) => Value::Int(*value as i128),
+                    Literal::Float(value) => Value::Float(*value),
+                    Literal::String(value) => Value::String(value.as_str().into()),
+                    Literal::Char(value) => Value::Char(*value),
+                    Literal::Bool(value) => Value::Bool(*value),
+                    Literal::OversizedInteger(_) => unreachable!("the checker reports it"),
+                };
+                equal(value, &expected)
+            }
+            (PatternKind::Global(def), _) => equal(value, &self.global(*def, &pattern.span)?),
+            (PatternKind::Tuple(patterns), Value::Tuple(elems)) => {
+                self.patterns(patterns, elems)?
+            }
+            (
+                PatternKind::Variant {
+                    def,
+                    fields: patterns,
+                },
+                Value::Variant { def: found, fields },
+            ) => def == found && self.patterns(patterns, fields)?,
+            _ => unreachable!("the checker checks the types of patterns"),
+        })
+    }
+
+    fn patterns(
+        &mut self,
+        patterns: &'a [Pattern],
+        values: &[Value<'a>],
+    ) -> Result<bool, Unwind<'a>> {
+        for (pattern, value) in patterns.iter().zip(values) {
+            if !self.pattern(pattern, value)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Returns an index into an array as a `usize`, failing when it's out of bounds.
+fn in_bounds<T>(elems: &[T], index: i128, span: &Span) -> Result<usize, Panic> {
+    usize::try_from(index)
+        .ok()
+        .filter(|&index| index < elems.len())
+        .ok_or_else(|| panic("index out of bounds", span))
+}
+
+/// The integer type of an operand, for the operators that depend on it.
+fn int_ty(ty: &Ty) -> IntTy {
+    match ty {
+        Ty::Int(int) => *int,
+        _ => IntTy::DEFAULT,
+    }
+}
+
+/// Returns whether two values are equal, comparing structs, tuples and arrays by their contents.
+/// Functions are never equal.
+fn equal(lhs: &Value, rhs: &Value) -> bool {
+    let all_equal =
+        |a: &[Value], b: &[Value]| a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b));
+    match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Float(a), Value::Float(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Char(a), Value::Char(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Tuple(a), Value::Tuple(b)) | (Value::Array(a), Value::Array(b)) => all_equal(a, b),
+        (
+            Value::Variant { def, fields },
+            Value::Variant {
+                def: other,
+                fields: other_fields,
+            },
+        ) => def == other && all_equal(fields, other_fields),
+        (Value::Map(a), Value::Map(b)) => a.equal(b, equal),
+        _ => false,
+    }
+}
+
+/// Hashes a map key, so that keys that are [`equal`] have the same hash.
+fn hash(key: &Value) -> u64 {
+    fn add(value: &Value, state: &mut DefaultHasher) {
+        match value {
+            Value::Int(value) => value.hash(state),
+            // `0.0 == -0.0`, so they have to hash the same
+            Value::Float(value) => (if *value == 0.0 { 0.0 } else { *value })
+                .to_bits()
+                .hash(state),
+            Value::Bool(value) => value.hash(state),
+            Value::Char(value) => value.hash(state),
+            Value::String(value) => value.hash(state),
+            Value::Tuple(elems) | Value::Array(elems) => {
+                elems.len().hash(state);
+                elems.iter().for_each(|elem| add(elem, state));
+            }
+            Value::Variant { def, fields } => {
+                def.hash(state);
+                fields.iter().for_each(|field| add(field, state));
+            }
+            // The checker only lets types that can be compared be keys, and maps can't be
+            Value::Map(_) | Value::Range { .. } | Value::Fn(_) | Value::Closure(_) => {}
+        }
+    }
+    let mut state = DefaultHasher::new();
+    add(key, &mut state);
+    state.finish()
+}
+
+/// Orders two numbers, characters or strings, which is `None` when either is NaN.
+fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Char(a), Value::Char(b)) => Some(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => unreachable!("the checker checks what can be ordered"),
+    }
+}
+
+/// Evaluates `lhs op rhs`, where `ty` is the type of `lhs`, returning the message of the failure
+/// when it fails.
+fn binary<'a>(
+    op: BinaryOp,
+    ty: &Ty,
+    lhs: Value<'a>,
+    rhs: Value<'a>,
+) -> Result<Value<'a>, &'static str> {
+    let ordering = || compare(&lhs, &rhs);
+    Ok(Value::Bool(match op {
+        BinaryOp::Eq | BinaryOp::TripleEq => equal(&lhs, &rhs),
+        BinaryOp::Ne | BinaryOp::TripleNe => !equal(&lhs, &rhs),
+        BinaryOp::Lt => ordering().is_some_and(Ordering::is_lt),
+        BinaryOp::Le => ordering().is_some_and(Ordering::is_le),
+        BinaryOp::Gt => ordering().is_some_and(Ordering::is_gt),
+        BinaryOp::Ge => ordering().is_some_and(Ordering::is_ge),
+        _ => {
+            return Ok(match (lhs, rhs) {
+                (Value::Int(a), Value::Int(b)) => {
+                    let result = eval_int(op, int_ty(ty), a, b).ok_or(match op {
+                        BinaryOp::Div | BinaryOp::Rem if b == 0 => DIVISION_BY_ZERO,
+                        BinaryOp::Shl | BinaryOp::Shr => SHIFT_OUT_OF_RANGE,
+                        _ => OVERFLOW,
+                    })?;
+                    Value::Int(result)
+                }
+                (Value::Float(a), Value::Float(b)) => Value::Float(match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Sub => a - b,
+                    BinaryOp::Mul => a * b,
+                    BinaryOp::Div => a / b,
+                    _ => a % b,
+                }),
+                (Value::Bool(a), Value::Bool(b)) => Value::Bool(match op {
+                    BinaryOp::And | BinaryOp::BitAnd => a & b,
+                    BinaryOp::Or | BinaryOp::BitOr => a | b,
+                    _ => a ^ b,
+                }),
+                (Value::String(a), Value::String(b)) => Value::String(format!("{}{}", a, b).into()),
+                _ => unreachable!("the checker checks the operands of operators"),
+            })
+        }
+    }))
+}
+
+/// Evaluates `op value`, where `ty` is the type of `value`.
+fn unary<'a>(op: UnaryOp, ty: &Ty, value: Value<'a>) -> Result<Value<'a>, &'static str> {
+    Ok(match (op, value) {
+        (UnaryOp::Neg, Value::Int(value)) => {
+            Value::Int(eval_int(BinaryOp::Sub, int_ty(ty), 0, value).ok_or(OVERFLOW)?)
+        }
+        (UnaryOp::Neg, Value::Float(value)) => Value::Float(-value),
+        // Flipping the bits of an integer wraps it into its type, the same as `as`
+        (UnaryOp::Not, Value::Int(value)) => Value::Int(int_ty(ty).wrap(!value)),
+        (UnaryOp::Not, Value::Bool(value)) => Value::Bool(!value),
+        _ => unreachable!("the checker checks the operands of operators"),
+    })
+}
+
+/// Converts a value to a type with `as`.
+fn cast<'a>(value: Value<'a>, to: &Ty) -> Value<'a> {
+    match (value, to) {
+        (Value::Int(value), Ty::Float) => Value::Float(value as f64),
+        (Value::Float(value), Ty::Int(_) | Ty::IntVar(_)) => {
+            Value::Int(int_ty(to).from_float(value))
+        }
+        (Value::Int(value), Ty::Int(_) | Ty::IntVar(_)) => Value::Int(int_ty(to).wrap(value)),
+        (Value::Char(value), Ty::Int(_) | Ty::IntVar(_)) => {
+            Value::Int(int_ty(to).wrap(value as i128))
+        }
+        (Value::Bool(value), Ty::Int(_) | Ty::IntVar(_)) => Value::Int(value as i128),
+        // Native code keeps the number of an integer that isn't a character
+        (Value::Int(value), Ty::Char) => Value::Char(
+            char::from_u32(IntTy::U32.wrap(value) as u32).unwrap_or(char::REPLACEMENT_CHARACTER),
+        ),
+        (Value::Int(value), Ty::String) => Value::String(value.to_string().into()),
+        (Value::Bool(value), Ty::String) => Value::String(value.to_string().into()),
+        (Value::Char(value), Ty::String) => Value::String(value.to_string().into()),
+        (value, _) => value,
+    }
+}
+
+/// Returns the value a function returns, or why it failed.
+fn returned<'a>(result: Eval<'a>) -> Result<Value<'a>, Panic> {
+    match result {
+        Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+        Err(Unwind::Panic(panic)) => Err(panic),
+        Err(Unwind::TailCall { .. } | Unwind::Break | Unwind::Continue) => {
+            unreachable!("calls are made and loops are left before the function returns")
+        }
+    }
+}
+
+fn panic(message: &str, span: &Span) -> Panic {
+    Panic {
+        message: message.to_string(),
+        span: span.clone(),
+        trace: Vec::new(),
+        limit: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Makes an interpreter for a program, with nothing to read on its console and nowhere to
+    /// print.
+    fn interpreter<'a>(program: &'a hir::Program, host: &'a Externs) -> Interpreter<'a> {
+        let console = Console::new(&b""[..], std::io::sink());
+        Interpreter::new(program, Limits::default(), console, host).unwrap()
+    }
+
+    fn func<'a>(program: &'a hir::Program, name: &str) -> Value<'a> {
+        let func = program.fns.iter().find(|func| func.name == name).unwrap();
+        Value::Fn(func.def)
+    }
+
+    const POINTS: &str = "
+struct Point { x: int, y: int }
+enum Shape { Circle(float), Rect(Point, Point) }
+static CALLS: int = 0;
+fn area(shape: Shape) float {
+    CALLS += 1;
+    match shape {
+        Shape::Circle(r) => 3.0 * r * r,
+        Shape::Rect(a, b) => ((b.x - a.x) * (b.y - a.y)) as float,
+    }
+}
+fn moved(p: Point) (Point, Point) {
+    let mut q = p;
+    q.x = 5;
+    (p, q)
+}";
+
+    #[test]
+    fn test_adts() {
+        let (_, program) = hir::lowered(POINTS);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        let point = |x: i128, y: i128| Value::Variant {
+            def: program.adts[2].variants[0].def,
+            fields: Rc::new(vec![Value::Int(x), Value::Int(y)]),
+        };
+        let rect = Value::Variant {
+            def: program.adts[3].variants[1].def,
+            fields: Rc::new(vec![point(0, 0), point(2, 5)]),
+        };
+        assert_eq!(
+            interpreter.call(func(&program, "area"), vec![rect], &(0..0)),
+            Ok(Value::Float(10.0))
+        );
+        assert_eq!(interpreter.globals[0], Some(Value::Int(1)));
+        // Assigning a field of a copy leaves the original as it was
+        assert_eq!(
+            interpreter.call(func(&program, "moved"), vec![point(1, 2)], &(0..0)),
+            Ok(Value::Tuple(Rc::new(vec![point(1, 2), point(5, 2)])))
+        );
+    }
+
+    #[test]
+    fn test_for_over_string() {
+        let source = "
+fn count(s: string, c: char) int {
+    let mut n = 0;
+    for x in s { if x == c { n += 1; } }
+    n
+}";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        let args = vec![Value::String("héllo".into()), Value::Char('l')];
+        assert_eq!(
+            interpreter.call(func(&program, "count"), args, &(0..0)),
+            Ok(Value::Int(2))
+        );
+    }
+
+    #[test]
+    fn test_closure() {
+        let source = "fn adder(k: int) fn(int) -> int { |x: int| x + k }";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        let add_two = interpreter.call(func(&program, "adder"), vec![Value::Int(2)], &(0..0));
+        assert_eq!(
+            interpreter.call(add_two.unwrap(), vec![Value::Int(3)], &(0..0)),
+            Ok(Value::Int(5))
+        );
+    }
+
+    const SUM: &str =
+        "fn sum(n: int, acc: int) int { if n == 0 { acc } else { sum(n - 1, acc + n) } }
+fn depth(n: int) int { if n == 0 { 0 } else { depth(n - 1) + 1 } }";
+
+    #[test]
+    fn test_tail_calls() {
+        let (_, program) = hir::lowered(SUM);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        // Deeper than calls can nest, which calls in tail position don't
+        let args = vec![Value::Int(50_000), Value::Int(0)];
+        assert_eq!(
+            interpreter.call(func(&program, "sum"), args, &(0..0)),
+            Ok(Value::Int(1_250_025_000))
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let (_, program) = hir::lowered(SUM);
+        let host = Externs::default();
+        // Each call from the host gets all of the fuel, and calls nest no deeper than the limit
+        let limits = Limits {
+            fuel: Some(10_000),
+            depth: Some(20),
+            memory: None,
+        };
+        let console = Console::new(&b""[..], std::io::sink());
+        let mut limited = Interpreter::new(&program, limits, console, &host).unwrap();
+        for _ in 0..3 {
+            let args = vec![Value::Int(100), Value::Int(0)];
+            assert_eq!(
+                limited.call(func(&program, "sum"), args, &(0..0)),
+                Ok(Value::Int(5050))
+            );
+        }
+        let args = vec![Value::Int(50_000), Value::Int(0)];
+        let stopped = limited
+            .call(func(&program, "sum"), args, &(0..0))
+            .unwrap_err();
+        assert_eq!(
+            (stopped.message.as_str(), stopped.limit),
+            ("out of fuel", Some(Limit::Fuel))
+        );
+        let call_at = SUM.find("depth(n - 1)").unwrap();
+        let stopped =
+            (limited.call(func(&program, "depth"), vec![Value::Int(30)], &(0..0))).unwrap_err();
+        assert_eq!(
+            (stopped.span, stopped.limit, stopped.trace.len()),
+            (call_at..call_at + 12, Some(Limit::Depth), 20)
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        let source = "fn add(a: u8, b: u8) u8 { a + b }";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        let args = vec![Value::Int(250), Value::Int(10)];
+        let sum_at = source.find("a + b").unwrap();
+        let sum_span = sum_at..sum_at + 5;
+        assert_eq!(
+            interpreter.call(func(&program, "add"), args, &(0..0)),
+            Err(Panic {
+                trace: vec![("add".to_string(), sum_span.clone())],
+                ..panic("arithmetic overflow", &sum_span)
+            })
+        );
+    }
+
+    #[test]
+    fn test_array_index() {
+        let source = "fn at(xs: [char], i: int) char { xs[i] }";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        let args = vec![Value::Array(Rc::new(vec![Value::Char('a')])), Value::Int(1)];
+        assert_eq!(
+            interpreter
+                .call(func(&program, "at"), args, &(0..0))
+                .map_err(|panic| panic.message),
+            Err("index out of bounds".to_string())
+        );
+    }
+
+    #[test]
+    fn test_string_methods() {
+        let source = "
+fn shout(s: string) string {
+    let mut out = \"\";
+    for w in s.split(\" \") {
+        if w.contains(\"l\") { out = out + w[..1].to_upper() + w[1..]; } else { out = out + w.to_lower(); }
+        out = out + \"_\";
+    }
+    out
+}";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        let args = vec![Value::String("héllo WORLD Foo".into())];
+        assert_eq!(
+            interpreter.call(func(&program, "shout"), args, &(0..0)),
+            Ok(Value::String("Héllo_world_foo_".into()))
+        );
+    }
+
+    #[test]
+    fn test_string_index() {
+        let source = "
+fn from_end(s: string, i: int) char { s[s.len() - 1 - i] }
+fn second(s: string) int { from_end(s, 5) as int + 1 }";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        let args = vec![Value::String("héllo".into()), Value::Int(3)];
+        assert_eq!(
+            interpreter.call(func(&program, "from_end"), args, &(0..0)),
+            Ok(Value::Char('é'))
+        );
+        let args = vec![Value::String("héllo".into()), Value::Int(5)];
+        assert_eq!(
+            interpreter
+                .call(func(&program, "from_end"), args, &(0..0))
+                .map_err(|panic| panic.message),
+            Err("index out of bounds".to_string())
+        );
+        // The trace goes out from where it failed, through where each call was made
+        let index_at = source.find("s[s.len() - 1 - i]").unwrap();
+        let call_at = source.find("from_end(s, 5)").unwrap();
+        let args = vec![Value::String("héllo".into())];
+        assert_eq!(
+            interpreter
+                .call(func(&program, "second"), args, &(0..0))
+                .map_err(|panic| panic.trace),
+            Err(vec![
+                ("from_end".to_string(), index_at..index_at + 18),
+                ("second".to_string(), call_at..call_at + 14),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_push_in_place() {
+        let source = "
+fn pushes(n: int) int {
+    let mut xs = [0];
+    let mut i = 1;
+    while i < n { xs.push(i); i += 1; }
+    let before = xs;
+    xs.pop();
+    if before.len() == n && xs.len() == n - 1 { xs[n - 2] } else { -1 }
+}";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        // Each push changes the array it's given, rather than copying it
+        assert_eq!(
+            interpreter.call(func(&program, "pushes"), vec![Value::Int(20_000)], &(0..0)),
+            Ok(Value::Int(19_998))
+        );
+    }
+
+    #[test]
+    fn test_insert_in_place() {
+        let source = "
+fn squares(n: int) int {
+    let mut squares = Map::new();
+    let mut i = 0;
+    while i < n { squares.insert(i, i * i); i += 1; }
+    let before = squares;
+    match squares.remove(3) { Some(9) => {}, _ => { return -1; } }
+    if before.contains(3) && !squares.contains(3) { squares.len() } else { -2 }
+}";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let mut interpreter = interpreter(&program, &host);
+        // Each insert changes the map it's given, rather than copying it
+        assert_eq!(
+            interpreter.call(func(&program, "squares"), vec![Value::Int(20_000)], &(0..0)),
+            Ok(Value::Int(19_999))
+        );
+    }
+
+    #[test]
+    fn test_externs() {
+        let source = "
+extern fn twice(x: int) int;
+fn doubled(x: int) int {
+    twice(x) + 1
+}";
+        let (_, program) = hir::lowered(source);
+        let mut host = Externs::default();
+        host.register("twice", |args| match args {
+            [ffi::Value::Int(x)] => Ok(ffi::Value::Int(x * 2)),
+            _ => Err("expected an integer".to_string()),
+        });
+        let mut interpreter = interpreter(&program, &host);
+        assert_eq!(
+            interpreter.call(func(&program, "doubled"), vec![Value::Int(20)], &(0..0)),
+            Ok(Value::Int(41))
+        );
+        let panic = (interpreter.call(
+            func(&program, "doubled"),
+            vec![Value::Int(1 << 30)],
+            &(0..0),
+        ))
+        .unwrap_err();
+        assert_eq!(
+            panic.message,
+            "host function `twice` returned Int(2147483648) instead of a `i32`"
+        );
+    }
+
+    #[test]
+    fn test_console() {
+        let source = "
+fn greet() {
+    print(\"name? \");
+    println(\"hi \" + input());
+}";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let mut output = Vec::new();
+        let console = Console::new(&b"ann\n"[..], &mut output);
+        let mut interpreter =
+            Interpreter::new(&program, Limits::default(), console, &host).unwrap();
+        assert_eq!(
+            interpreter.call(func(&program, "greet"), Vec::new(), &(0..0)),
+            Ok(Value::unit())
+        );
+        drop(interpreter);
+        assert_eq!(String::from_utf8(output).unwrap(), "name? hi ann\n");
+    }
+
+    #[test]
+    fn test_run() {
+        let source = "
+fn first(s: string) char {
+    for c in s { return c; }
+    panic(\"empty string\")
+}
+fn main() {
+    first(\"\");
+}";
+        let (_, program) = hir::lowered(source);
+        let host = Externs::default();
+        let console = Console::new(&b""[..], std::io::sink());
+        assert_eq!(
+            run(&program, Limits::default(), console, &host).map_err(|panic| panic.message),
+            Err("empty string".to_string())
+        );
+    }
+}
@@ -1,7 +1,8 @@
 //! Contains utils for lexing a Ruffle source file into a string of tokens.
 
-use logos::Logos;
+use logos::{FilterResult, Logos};
 use std::{
+    collections::VecDeque,
     error::Error,
     fmt::{Debug, Display},
     num::{ParseFloatError, ParseIntError},
@@ -57,12 +58,39 @@ impl<'a> Display for SlicedError<'a> {
 }
 
 /// Error type returned from lexing.
-#[derive(Default, Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LexingError {
-    #[default]
-    NonAsciiCharacter,
+    UnexpectedCharacter(char),
     InvalidInteger(&'static str),
+    /// A `0x`, `0o` or `0b` prefix with no digits after it.
+    MissingDigits,
     InvalidFloat,
+    InvalidEscape(Span),
+    InvalidChar,
+    InvalidSeparator(Span),
+    UnterminatedString(Span),
+    UnterminatedComment(Span),
+}
+
+/// Required by logos, though errors are always created by `unexpected_character` or the token
+/// callbacks.
+impl Default for LexingError {
+    fn default() -> Self {
+        LexingError::UnexpectedCharacter(char::REPLACEMENT_CHARACTER)
+    }
+}
+
+impl LexingError {
+    /// Returns a span that is more precise than the token's span, if the error has one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            LexingError::InvalidEscape(span)
+            | LexingError::InvalidSeparator(span)
+            | LexingError::UnterminatedString(span)
+            | LexingError::UnterminatedComment(span) => Some(span.clone()),
+            _ => None,
+        }
+    }
 }
 
 impl Error for LexingError {}
@@ -70,14 +98,22 @@ impl Error for LexingError {}
 impl Display for LexingError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            LexingError::NonAsciiCharacter => write!(f, "non ascii character"),
+            LexingError::UnexpectedCharacter(char) => write!(f, "unexpected character `{}`", char),
             LexingError::InvalidInteger(err) => write!(f, "invalid integer: {}", err),
+            LexingError::MissingDigits => write!(f, "missing digits after radix prefix"),
             LexingError::InvalidFloat => write!(f, "invalid float"),
+            LexingError::InvalidEscape(_) => write!(f, "invalid escape sequence"),
+            LexingError::InvalidSeparator(_) => write!(f, "misplaced digit separator"),
+            LexingError::UnterminatedString(_) => write!(f, "unterminated string literal"),
+            LexingError::UnterminatedComment(_) => write!(f, "unterminated block comment"),
+            LexingError::InvalidChar => {
+                write!(f, "character literal must contain exactly one character")
+            }
         }
     }
 }
 
-/// Error type returned by calling `lex.slice().parse()` to i32.
+/// Error type returned by calling `lex.slice().parse()` to u64.
 impl From<ParseIntError> for LexingError {
     fn from(err: ParseIntError) -> Self {
         use std::num::IntErrorKind::*;
@@ -88,16 +124,166 @@ impl From<ParseIntError> for LexingError {
     }
 }
 
-/// Error type returned by calling `lex.slice().parse()` to f32.
+/// Error type returned by calling `lex.slice().parse()` to f64.
 impl From<ParseFloatError> for LexingError {
     fn from(_err: ParseFloatError) -> Self {
         Self::InvalidFloat
     }
 }
 
+/// Replaces the escape sequences in the contents of a string literal with the characters they
+/// represent. `offset` is the position of `body` in the source, used to span invalid escapes.
+fn unescape(body: &str, offset: usize) -> Result<String, LexingError> {
+    let mut value = String::with_capacity(body.len());
+    let mut chars = body.char_indices().peekable();
+
+    while let Some((start, char)) = chars.next() {
+        if char != '\\' {
+            value.push(char);
+            continue;
+        }
+
+        let invalid = |end: usize| LexingError::InvalidEscape(offset + start..offset + end);
+        let Some((i, escape)) = chars.next() else {
+            return Err(invalid(start + 1));
+        };
+
+        value.push(match escape {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            '\'' => '\'',
+            // A line continuation, skips the newline and the next line's indentation.
+            '\n' | '\r' => {
+                while chars.next_if(|&(_, c)| c.is_whitespace()).is_some() {}
+                continue;
+            }
+            'u' => {
+                if chars.next_if(|&(_, c)| c == '{').is_none() {
+                    return Err(invalid(i + 1));
+                }
+
+                let mut code = String::new();
+                let end = loop {
+                    match chars.next() {
+                        Some((j, '}')) => break j + 1,
+                        Some((_, c)) if c.is_ascii_hexdigit() && code.len() < 6 => code.push(c),
+                        Some((j, c)) => return Err(invalid(j + c.len_utf8())),
+                        None => return Err(invalid(body.len())),
+                    }
+                };
+
+                u32::from_str_radix(&code, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| invalid(end))?
+            }
+            _ => return Err(invalid(i + escape.len_utf8())),
+        });
+    }
+
+    Ok(value)
+}
+
+/// Callback for string literals, called on the opening quote. Consumes everything up to the
+/// closing quote, which may be on a later line, and processes escape sequences.
+fn lex_string(lex: &mut logos::Lexer<Token>) -> Result<String, LexingError> {
+    let start = lex.span().start;
+    let remainder = lex.remainder();
+    let mut chars = remainder.char_indices();
+
+    let end = loop {
+        match chars.next() {
+            Some((_, '\\')) => {
+                chars.next();
+            }
+            Some((i, '"')) => break i,
+            Some(_) => {}
+            None => {
+                lex.bump(remainder.len());
+                return Err(LexingError::UnterminatedString(start..start + 1));
+            }
+        }
+    };
+
+    lex.bump(end + 1);
+    unescape(&remainder[..end], start + 1)
+}
+
+/// Callback for character literals, which must contain exactly one character after escapes.
+fn lex_char(lex: &mut logos::Lexer<Token>) -> Result<char, LexingError> {
+    let slice = lex.slice();
+    let value = unescape(&slice[1..slice.len() - 1], lex.span().start + 1)?;
+    let mut chars = value.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(char), None) => Ok(char),
+        _ => Err(LexingError::InvalidChar),
+    }
+}
+
+/// Removes `_` digit separators from a numeric literal. Separators may only appear between two
+/// digits (or other separators), so they can't start or end the literal or touch the `.`.
+fn strip_separators(digits: &str, offset: usize) -> Result<String, LexingError> {
+    for (i, _) in digits.match_indices('_') {
+        let prev = digits[..i].chars().next_back();
+        let next = digits[i + 1..].chars().next();
+
+        if matches!(prev, None | Some('.')) || matches!(next, None | Some('.')) {
+            return Err(LexingError::InvalidSeparator(offset + i..offset + i + 1));
+        }
+    }
+
+    Ok(digits.replace('_', ""))
+}
+
+/// Callback for integer literals, handles the `0x`, `0o` and `0b` radix prefixes.
+fn lex_integer(lex: &mut logos::Lexer<Token>) -> Result<u64, LexingError> {
+    let slice = lex.slice();
+    let (prefix, radix) = match slice.get(..2) {
+        Some("0x") => (2, 16),
+        Some("0o") => (2, 8),
+        Some("0b") => (2, 2),
+        _ => (0, 10),
+    };
+
+    let digits = strip_separators(&slice[prefix..], lex.span().start + prefix)?;
+    Ok(u64::from_str_radix(&digits, radix)?)
+}
+
+/// Callback for float literals.
+fn lex_float(lex: &mut logos::Lexer<Token>) -> Result<f64, LexingError> {
+    Ok(strip_separators(lex.slice(), lex.span().start)?.parse()?)
+}
+
+/// Error callback for input that doesn't match any token.
+fn unexpected_character(lex: &mut logos::Lexer<Token>) -> LexingError {
+    LexingError::UnexpectedCharacter(lex.slice().chars().next().unwrap_or_default())
+}
+
+/// Callback for block comments, skips everything up to and including the closing `*/`. A comment
+/// that's never closed is an error at its `/*`.
+fn skip_block_comment(lex: &mut logos::Lexer<Token>) -> FilterResult<(), LexingError> {
+    let remainder = lex.remainder();
+    match remainder.find("*/") {
+        Some(i) => {
+            lex.bump(i + 2);
+            FilterResult::Skip
+        }
+        None => {
+            let start = lex.span().start;
+            lex.bump(remainder.len());
+            FilterResult::Error(LexingError::UnterminatedComment(start..start + 2))
+        }
+    }
+}
+
 #[derive(Logos, Debug, PartialEq, Clone)]
-#[logos(skip r"[ \t\n\f]+|//.*|/\*([^*]|\*+[^*/])*\*+/")] // Comments
-#[logos(error = LexingError)]
+#[logos(skip r"[ \t\n\f]+|//.*")] // Whitespace and line comments
+#[logos(error(LexingError, unexpected_character))]
 pub enum Token {
     // Symbols
     #[token(".")]
@@ -130,6 +316,12 @@ pub enum Token {
     StraightArrow,
     #[token("=>")]
     EqArrow,
+    #[token("#")]
+    Pound,
+    #[token("..")]
+    DotDot,
+    #[token("..=")]
+    DotDotEq,
 
     // Arithmetic Operators
     #[token("+")]
@@ -167,6 +359,18 @@ pub enum Token {
     #[token("||")]
     OrOr,
 
+    // Bitwise Operators
+    #[token("&")]
+    And,
+    #[token("|")]
+    Or,
+    #[token("^")]
+    Caret,
+    #[token("<<")]
+    Shl,
+    #[token(">>")]
+    Shr,
+
     // Assignment Operators
     #[token("=")]
     Eq,
@@ -178,6 +382,18 @@ pub enum Token {
     StarEq,
     #[token("/=")]
     SlashEq,
+    #[token("%=")]
+    ModulusEq,
+    #[token("&=")]
+    AndEq,
+    #[token("|=")]
+    OrEq,
+    #[token("^=")]
+    CaretEq,
+    #[token("<<=")]
+    ShlEq,
+    #[token(">>=")]
+    ShrEq,
 
     // Keywords
     #[token("let")]
@@ -194,6 +410,8 @@ pub enum Token {
     For,
     #[token("return")]
     Return,
+    #[token("yield")]
+    Yield,
     #[token("class")]
     Class,
     #[token("impl")]
@@ -214,16 +432,69 @@ pub enum Token {
     Const,
     #[token("static")]
     Static,
+    #[token("extern")]
+    Extern,
+    #[token("match")]
+    Match,
+    #[token("loop")]
+    Loop,
+    #[token("break")]
+    Break,
+    #[token("continue")]
+    Continue,
+    #[token("pub")]
+    Pub,
+    #[token("mut")]
+    Mut,
+    #[token("trait")]
+    Trait,
+    #[token("type")]
+    Type,
+    #[token("in")]
+    In,
+    #[token("as")]
+    As,
 
     // Literals
-    #[regex(r"[0-9]+", |lex| lex.slice().parse())]
-    Integer(i32),
-    #[regex(r"[0-9]+\.[0-9]+", |lex| lex.slice().parse())]
-    Float(f32),
-    #[regex(r#""([^"\\]|\\.)*""#, |lex| lex.slice()[1..lex.slice().len() - 1].to_owned())]
+    #[regex(r"[0-9][0-9_]*", lex_integer)]
+    #[regex(r"0x[0-9a-fA-F_]+", lex_integer)]
+    #[regex(r"0o[0-7_]+", lex_integer)]
+    #[regex(r"0b[01_]+", lex_integer)]
+    #[regex(r"0[xob]", |_| Err(LexingError::MissingDigits))]
+    /// An integer literal, which is unsigned as `-` is an operator. Narrowing to the literal's
+    /// actual type happens during type checking.
+    Integer(u64),
+    /// An integer literal too large to store, kept as written.
+    OversizedInteger(String),
+    #[regex(r"[0-9][0-9_]*\.[0-9_]+", lex_float)]
+    Float(f64),
+    #[regex(r"///([^/\n][^\n]*)?", |lex| lex.slice()[3..].to_owned())]
+    #[regex(r"//![^\n]*", |lex| lex.slice()[3..].to_owned())]
+    DocComment(String),
+    #[token("\"", lex_string)]
     String(String),
+    #[regex(r"'([^'\\\n]|\\.)*'", lex_char)]
+    Char(char),
+    #[token("true", |_| true)]
+    #[token("false", |_| false)]
+    Bool(bool),
     #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice().to_owned())]
     Ident(String),
+
+    // Trivia, only produced by `lex_source_with_trivia`
+    Whitespace,
+    #[token("/*", skip_block_comment)]
+    Comment,
+
+    /// Stands in for invalid input, only produced by a lexer with [`Lexer::with_recovery`].
+    Error(LexingError),
+}
+
+impl Token {
+    /// Returns true for tokens that don't affect the meaning of the program.
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, Token::Whitespace | Token::Comment)
+    }
 }
 
 impl Display for Token {
@@ -248,6 +519,9 @@ impl Display for Token {
                 Token::RBrace => "}",
                 Token::StraightArrow => "->",
                 Token::EqArrow => "=>",
+                Token::Pound => "#",
+                Token::DotDot => "..",
+                Token::DotDotEq => "..=",
 
                 // Arithmetic Operators
                 Token::Plus => "+",
@@ -270,12 +544,25 @@ impl Display for Token {
                 Token::AndAnd => "&&",
                 Token::OrOr => "||",
 
+                // Bitwise Operators
+                Token::And => "&",
+                Token::Or => "|",
+                Token::Caret => "^",
+                Token::Shl => "<<",
+                Token::Shr => ">>",
+
                 // Assignment Operators
                 Token::Eq => "=",
                 Token::PlusEq => "+=",
                 Token::MinusEq => "-=",
                 Token::StarEq => "*=",
                 Token::SlashEq => "/=",
+                Token::ModulusEq => "%=",
+                Token::AndEq => "&=",
+                Token::OrEq => "|=",
+                Token::CaretEq => "^=",
+                Token::ShlEq => "<<=",
+                Token::ShrEq => ">>=",
 
                 // Keywords
                 Token::Let => "let",
@@ -285,6 +572,7 @@ impl Display for Token {
                 Token::While => "while",
                 Token::For => "for",
                 Token::Return => "return",
+                Token::Yield => "yield",
                 Token::Class => "class",
                 Token::Impl => "impl",
                 Token::Struct => "struct",
@@ -295,40 +583,238 @@ impl Display for Token {
                 Token::Mod => "mod",
                 Token::Const => "const",
                 Token::Static => "static",
+                Token::Extern => "extern",
+                Token::Match => "match",
+                Token::Loop => "loop",
+                Token::Break => "break",
+                Token::Continue => "continue",
+                Token::Pub => "pub",
+                Token::Mut => "mut",
+                Token::Trait => "trait",
+                Token::Type => "type",
+                Token::In => "in",
+                Token::As => "as",
 
                 // Literals
                 Token::Integer(value) => return write!(f, "{}", value),
+                Token::OversizedInteger(value) => return write!(f, "{}", value),
                 Token::Float(value) => return write!(f, "{}", value),
                 Token::String(value) => return write!(f, "str(\"{}\")", value),
+                Token::Char(value) => return write!(f, "char('{}')", value),
+                Token::Bool(value) => return write!(f, "{}", value),
+                Token::DocComment(value) => return write!(f, "doc(\"{}\")", value),
                 Token::Ident(value) => return write!(f, "ident({})", value),
+
+                // Trivia
+                Token::Whitespace => "whitespace",
+                Token::Comment => "comment",
+
+                Token::Error(_) => "error",
             }
         )
     }
 }
 
-/// Lexes a source file into tokens with span information.
-pub fn lex_source(source: &str) -> Vec<Result<SlicedToken, SlicedError>> {
-    let mut lexer = Token::lexer(source);
-    let mut tokens = Vec::new();
+/// The result of lexing a single token.
+pub type LexResult<'a> = Result<SlicedToken<'a>, SlicedError<'a>>;
+
+/// Lazily lexes a source file into tokens with span information.
+pub struct Lexer<'a> {
+    inner: logos::Lexer<'a, Token>,
+    source: &'a str,
+    /// Whether whitespace and comments are emitted as tokens.
+    trivia: bool,
+    /// Whether errors are emitted as [`Token::Error`] instead of `Err` results.
+    recover: bool,
+    /// End of the last token pulled from `inner`.
+    end: usize,
+    /// Tokens lexed ahead of time, the trivia before a token is queued along with the token.
+    pending: VecDeque<LexResult<'a>>,
+}
 
-    while let Some(token) = lexer.next() {
-        let span = lexer.span();
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            inner: Token::lexer(source),
+            source,
+            trivia: false,
+            recover: false,
+            end: 0,
+            pending: VecDeque::new(),
+        }
+    }
 
-        tokens.push(match token {
-            Ok(t) => Ok(SlicedToken {
-                token: t,
+    /// Makes the lexer also emit the whitespace and comments between tokens as
+    /// [`Token::Whitespace`] and [`Token::Comment`], so the source can be reconstructed exactly
+    /// from the token slices.
+    pub fn with_trivia(mut self) -> Self {
+        self.trivia = true;
+        self
+    }
+
+    /// Starts lexing at byte `offset` of the source, so spans are relative to the whole source
+    /// even when only the end of it is lexed.
+    pub fn starting_at(mut self, offset: usize) -> Self {
+        self.inner.bump(offset);
+        self.end = offset;
+        self
+    }
+
+    /// Makes the lexer emit errors as [`Token::Error`] tokens, so consumers get an uninterrupted
+    /// token stream and can report every error in one pass.
+    pub fn with_recovery(mut self) -> Self {
+        self.recover = true;
+        self
+    }
+
+    /// Splits text skipped by the lexer into whitespace and comment tokens.
+    fn push_trivia(&mut self, span: Span) {
+        let mut start = span.start;
+
+        while start < span.end {
+            let rest = &self.source[start..span.end];
+            let (token, len) = if rest.starts_with("//") {
+                (Token::Comment, rest.find('\n').unwrap_or(rest.len()))
+            } else if rest.starts_with("/*") {
+                (
+                    Token::Comment,
+                    rest.find("*/").map_or(rest.len(), |i| i + 2),
+                )
+            } else {
+                match rest.find(|c: char| !c.is_whitespace()) {
+                    // Anything else is skipped a character at a time, so the loop always advances
+                    Some(0) => {
+                        let char = rest.chars().next().unwrap();
+                        let error = LexingError::UnexpectedCharacter(char);
+                        let span = start..start + char.len_utf8();
+                        self.pending.push_back(self.error(error, span));
+                        start += char.len_utf8();
+                        continue;
+                    }
+                    len => (Token::Whitespace, len.unwrap_or(rest.len())),
+                }
+            };
+
+            self.pending.push_back(Ok(SlicedToken {
+                token,
+                span: start..start + len,
+                source: self.source,
+            }));
+            start += len;
+        }
+    }
+
+    /// Reports an error as a [`Token::Error`] if the lexer recovers from errors, and as an `Err`
+    /// otherwise.
+    fn error(&self, error: LexingError, span: Span) -> LexResult<'a> {
+        let source = self.source;
+        if self.recover {
+            Ok(SlicedToken {
                 span,
+                token: Token::Error(error),
                 source,
-            }),
-            Err(e) => Err(SlicedError {
-                error: e,
+            })
+        } else {
+            Err(SlicedError {
                 span,
+                error,
+                source,
+            })
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = LexResult<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.pending.pop_front() {
+            return Some(token);
+        }
+
+        let Some(mut token) = self.inner.next() else {
+            if self.trivia && self.end < self.source.len() {
+                self.push_trivia(self.end..self.source.len());
+                self.end = self.source.len();
+            }
+            return self.pending.pop_front();
+        };
+
+        // Whether an integer overflows depends on the type it ends up with, which isn't known yet
+        if token == Err(LexingError::InvalidInteger("overflow")) {
+            token = Ok(Token::OversizedInteger(self.inner.slice().to_owned()));
+        }
+
+        let span = self.inner.span();
+        let source = self.source;
+        let token = match token {
+            Ok(t) => Ok(SlicedToken {
+                token: t,
+                span: span.clone(),
                 source,
             }),
-        });
+            Err(e) => {
+                let span = e.span().unwrap_or(span.clone());
+                self.error(e, span)
+            }
+        };
+
+        if !self.trivia {
+            return Some(token);
+        }
+
+        self.push_trivia(self.end..span.start);
+        self.end = span.end;
+        // The error of an unterminated comment is at its `/*`, and the rest of it is still comment
+        let opened = match &token {
+            Ok(SlicedToken {
+                token: Token::Error(LexingError::UnterminatedComment(opened)),
+                ..
+            })
+            | Err(SlicedError {
+                error: LexingError::UnterminatedComment(opened),
+                ..
+            }) => Some(opened.clone()),
+            _ => None,
+        };
+        self.pending.push_back(token);
+        if let Some(opened) = opened {
+            self.pending.push_back(Ok(SlicedToken {
+                token: Token::Comment,
+                span: opened.end..span.end,
+                source,
+            }));
+        }
+        self.pending.pop_front()
     }
+}
+
+/// Lexes a source file into tokens with span information.
+pub fn lex_source(source: &str) -> Vec<LexResult<'_>> {
+    Lexer::new(source).collect()
+}
 
-    tokens
+/// Lexes a source file into tokens, including whitespace and comments. See
+/// [`Lexer::with_trivia`].
+pub fn lex_source_with_trivia(source: &str) -> Vec<LexResult<'_>> {
+    Lexer::new(source).with_trivia().collect()
+}
+
+/// Prints the tokens of a source file separated by spaces, the way `ruffle tokenize` shows them.
+/// Invalid input is printed as `error` and returned with why it's invalid.
+pub fn print_tokens(source: &str) -> (String, Vec<SlicedError<'_>>) {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    for token in Lexer::new(source) {
+        match token {
+            Ok(token) => tokens.push(token.to_string()),
+            Err(error) => {
+                tokens.push("error".to_string());
+                errors.push(error);
+            }
+        }
+    }
+    (tokens.join(" "), errors)
 }
 
 #[cfg(test)]
@@ -336,6 +822,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_lex_valid_tokens() {
         let source = r#"let x = 42; let y = 3.14; return x + y;"#;
         let tokens = lex_source(source);
@@ -366,22 +853,36 @@ mod tests {
         }
     }
 
-    // #[test]
-    // fn test_lex_invalid_integer() {
-    //     let source = "let x = 1000000000000000000000000000;";
-    //     let tokens = lex_source(source);
+    #[test]
+    fn test_lex_64_bit_literals() {
+        let source = "9223372036854775808 18446744073709551615 1.7976931348623157";
+        let tokens = lex_source(source);
 
-    //     assert_eq!(tokens.len(), 5);
+        let expected = vec![
+            Token::Integer(1 << 63),
+            Token::Integer(u64::MAX),
+            Token::Float(1.797_693_134_862_315_7),
+        ];
 
-    //     assert_eq!(tokens[0].as_ref().unwrap().token, Token::Let);
-    //     assert!(matches!(
-    //         tokens[3],
-    //         Err(SlicedError {
-    //             error: LexingError::InvalidInteger(_),
-    //             ..
-    //         })
-    //     ));
-    // }
+        assert_eq!(tokens.len(), expected.len());
+        for (token, expected) in tokens.into_iter().map(Result::unwrap).zip(expected) {
+            assert_eq!(token.token, expected);
+        }
+    }
+
+    #[test]
+    fn test_lex_oversized_integer() {
+        let source = "let x = 1000000000000000000000000000;";
+        let tokens = lex_source(source);
+
+        assert_eq!(tokens.len(), 5);
+
+        assert_eq!(tokens[0].as_ref().unwrap().token, Token::Let);
+        assert_eq!(
+            tokens[3].as_ref().unwrap().token,
+            Token::OversizedInteger("1000000000000000000000000000".to_string())
+        );
+    }
 
     // #[test]
     // fn test_lex_invalid_float() {
@@ -411,12 +912,22 @@ mod tests {
         assert!(matches!(
             tokens[3],
             Err(SlicedError {
-                error: LexingError::NonAsciiCharacter,
+                error: LexingError::UnexpectedCharacter('@'),
                 ..
             })
         ));
     }
 
+    #[test]
+    fn test_lex_unexpected_unicode_character() {
+        let tokens = lex_source("let é = 1;");
+
+        let error = tokens[1].as_ref().unwrap_err();
+        assert_eq!(error.error, LexingError::UnexpectedCharacter('é'));
+        assert_eq!(error.slice(), "é");
+        assert_eq!(error.error.to_string(), "unexpected character `é`");
+    }
+
     #[test]
     fn test_lex_keywords_and_identifiers() {
         let source = "fn foo() { let bar = 42; }";
@@ -444,6 +955,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_lex_extended_keywords() {
+        let source = "match loop break continue pub mut trait type in as extern yield matches";
+        let tokens = lex_source(source);
+
+        let expected = vec![
+            Token::Match,
+            Token::Loop,
+            Token::Break,
+            Token::Continue,
+            Token::Pub,
+            Token::Mut,
+            Token::Trait,
+            Token::Type,
+            Token::In,
+            Token::As,
+            Token::Extern,
+            Token::Yield,
+            Token::Ident("matches".to_string()),
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, expected) in tokens.into_iter().map(Result::unwrap).zip(expected) {
+            assert_eq!(token.token, expected);
+        }
+    }
+
     #[test]
     fn test_lex_strings() {
         let source = r#"let greeting = "Hello, World!";"#;
@@ -465,6 +1003,346 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_lex_string_escapes() {
+        let source = r#""line\n\t\"quoted\" \\ \u{1F600}""#;
+        let tokens = lex_source(source);
+
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(
+            tokens[0].as_ref().unwrap().token,
+            Token::String("line\n\t\"quoted\" \\ \u{1F600}".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lex_invalid_escape() {
+        let source = r#"let s = "bad \q escape";"#;
+        let tokens = lex_source(source);
+
+        assert_eq!(tokens.len(), 5);
+
+        let error = tokens[3].as_ref().unwrap_err();
+        assert!(matches!(error.error, LexingError::InvalidEscape(_)));
+        assert_eq!(error.slice(), "\\q");
+    }
+
+    #[test]
+    fn test_lex_chars() {
+        let source = r"'a' '\n' '\'' '\u{e9}'";
+        let tokens = lex_source(source);
+
+        let expected = vec![
+            Token::Char('a'),
+            Token::Char('\n'),
+            Token::Char('\''),
+            Token::Char('é'),
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, expected) in tokens.into_iter().map(Result::unwrap).zip(expected) {
+            assert_eq!(token.token, expected);
+        }
+    }
+
+    #[test]
+    fn test_lex_invalid_chars() {
+        for source in ["''", "'ab'", r"'\n\t'"] {
+            let tokens = lex_source(source);
+
+            assert_eq!(tokens.len(), 1, "{}", source);
+            assert!(matches!(
+                tokens[0],
+                Err(SlicedError {
+                    error: LexingError::InvalidChar,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_lex_radix_integers() {
+        let source = "0xFF 0o77 0b1010 0xffffffffffffffff";
+        let tokens = lex_source(source);
+
+        let expected = vec![
+            Token::Integer(255),
+            Token::Integer(63),
+            Token::Integer(10),
+            Token::Integer(u64::MAX),
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, expected) in tokens.into_iter().map(Result::unwrap).zip(expected) {
+            assert_eq!(token.token, expected);
+        }
+    }
+
+    #[test]
+    fn test_lex_missing_radix_digits() {
+        for source in ["0x", "0o;", "0b2", "0xg"] {
+            let tokens = lex_source(source);
+            let error = tokens[0].as_ref().unwrap_err();
+
+            assert_eq!(error.error, LexingError::MissingDigits, "{}", source);
+            assert_eq!(error.span, 0..2, "{}", source);
+            assert_eq!(error.error.to_string(), "missing digits after radix prefix");
+        }
+    }
+
+    #[test]
+    fn test_lex_radix_integer_overflow() {
+        let tokens = lex_source("0x1_0000_0000_0000_0000");
+
+        assert_eq!(
+            tokens[0].as_ref().unwrap().token,
+            Token::OversizedInteger("0x1_0000_0000_0000_0000".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lex_digit_separators() {
+        let source = "1_000_000 1.234_5 0xFF_FF 0b1010__1010";
+        let tokens = lex_source(source);
+
+        let expected = vec![
+            Token::Integer(1_000_000),
+            Token::Float(1.234_5),
+            Token::Integer(0xFFFF),
+            Token::Integer(0b1010_1010),
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, expected) in tokens.into_iter().map(Result::unwrap).zip(expected) {
+            assert_eq!(token.token, expected);
+        }
+    }
+
+    #[test]
+    fn test_lex_misplaced_digit_separators() {
+        for (source, at) in [("100_", 3), ("0x_FF", 2), ("1_.5", 1), ("1._5", 2)] {
+            let tokens = lex_source(source);
+            let error = tokens[0].as_ref().unwrap_err();
+
+            assert!(
+                matches!(error.error, LexingError::InvalidSeparator(_)),
+                "{}",
+                source
+            );
+            assert_eq!(error.span, at..at + 1, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_lex_multi_line_strings() {
+        let source = "let s = \"one\ntwo\"; let t = \"three \\\n    four\";";
+        let tokens = lex_source(source);
+
+        assert_eq!(tokens.len(), 10);
+        assert_eq!(
+            tokens[3].as_ref().unwrap().token,
+            Token::String("one\ntwo".to_string())
+        );
+        assert_eq!(
+            tokens[8].as_ref().unwrap().token,
+            Token::String("three four".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lex_unterminated_string() {
+        let source = "let s = \"never\nends;";
+        let tokens = lex_source(source);
+
+        assert_eq!(tokens.len(), 4);
+
+        let error = tokens[3].as_ref().unwrap_err();
+        assert!(matches!(error.error, LexingError::UnterminatedString(_)));
+        assert_eq!(error.span, 8..9);
+    }
+
+    #[test]
+    fn test_lex_doc_comments() {
+        let source = "//! Module docs\n/// Item docs\n//// Not docs\nfn f() {}";
+        let tokens = lex_source(source);
+
+        assert_eq!(tokens.len(), 8);
+        assert_eq!(
+            tokens[0].as_ref().unwrap().token,
+            Token::DocComment(" Module docs".to_string())
+        );
+        assert_eq!(
+            tokens[1].as_ref().unwrap().token,
+            Token::DocComment(" Item docs".to_string())
+        );
+        assert_eq!(tokens[2].as_ref().unwrap().token, Token::Fn);
+    }
+
+    #[test]
+    fn test_lex_with_trivia() {
+        let source = "let x = 1; // one\n/* two */ x";
+        let tokens = lex_source_with_trivia(source);
+
+        let expected = vec![
+            Token::Let,
+            Token::Whitespace,
+            Token::Ident("x".to_string()),
+            Token::Whitespace,
+            Token::Eq,
+            Token::Whitespace,
+            Token::Integer(1),
+            Token::Semi,
+            Token::Whitespace,
+            Token::Comment,
+            Token::Whitespace,
+            Token::Comment,
+            Token::Whitespace,
+            Token::Ident("x".to_string()),
+        ];
+
+        let tokens: Vec<SlicedToken> = tokens.into_iter().map(Result::unwrap).collect();
+        assert_eq!(tokens.len(), expected.len());
+
+        let reconstructed: String = tokens.iter().map(SlicedToken::slice).collect();
+        assert_eq!(reconstructed, source);
+
+        for (token, expected) in tokens.into_iter().zip(expected) {
+            assert_eq!(token.token, expected);
+        }
+    }
+
+    #[test]
+    fn test_lex_compound_assignment_operators() {
+        let source = "%= &= |= ^= <<= >>= & && | || ^ << >> < <= > >=";
+        let tokens = lex_source(source);
+
+        let expected = vec![
+            Token::ModulusEq,
+            Token::AndEq,
+            Token::OrEq,
+            Token::CaretEq,
+            Token::ShlEq,
+            Token::ShrEq,
+            Token::And,
+            Token::AndAnd,
+            Token::Or,
+            Token::OrOr,
+            Token::Caret,
+            Token::Shl,
+            Token::Shr,
+            Token::Less,
+            Token::LessEq,
+            Token::Greater,
+            Token::GreaterEq,
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, expected) in tokens.into_iter().map(Result::unwrap).zip(expected) {
+            assert_eq!(token.token, expected);
+        }
+    }
+
+    #[test]
+    fn test_lex_starting_at() {
+        let source = "skipped; let x";
+        let tokens: Vec<_> = Lexer::new(source)
+            .with_trivia()
+            .starting_at(9)
+            .map(|token| token.unwrap().span)
+            .collect();
+        assert_eq!(tokens, vec![9..12, 12..13, 13..14]);
+    }
+
+    #[test]
+    fn test_lex_ranges() {
+        let source = "0..10 a..=b 1.5.. x.y";
+        let tokens = lex_source(source);
+
+        let expected = vec![
+            Token::Integer(0),
+            Token::DotDot,
+            Token::Integer(10),
+            Token::Ident("a".to_string()),
+            Token::DotDotEq,
+            Token::Ident("b".to_string()),
+            Token::Float(1.5),
+            Token::DotDot,
+            Token::Ident("x".to_string()),
+            Token::Period,
+            Token::Ident("y".to_string()),
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, expected) in tokens.into_iter().map(Result::unwrap).zip(expected) {
+            assert_eq!(token.token, expected);
+        }
+    }
+
+    #[test]
+    fn test_lex_attributes() {
+        let source = "#[cfg(test)] #![allow(unused)]";
+        let tokens = lex_source(source);
+
+        let expected = vec![
+            Token::Pound,
+            Token::LSquare,
+            Token::Ident("cfg".to_string()),
+            Token::LParen,
+            Token::Ident("test".to_string()),
+            Token::RParen,
+            Token::RSquare,
+            Token::Pound,
+            Token::Bang,
+            Token::LSquare,
+            Token::Ident("allow".to_string()),
+            Token::LParen,
+            Token::Ident("unused".to_string()),
+            Token::RParen,
+            Token::RSquare,
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, expected) in tokens.into_iter().map(Result::unwrap).zip(expected) {
+            assert_eq!(token.token, expected);
+        }
+    }
+
+    #[test]
+    fn test_lexer_is_lazy() {
+        let mut lexer = Lexer::new("let x = @; let");
+
+        assert_eq!(lexer.next().unwrap().unwrap().token, Token::Let);
+        assert_eq!(lexer.by_ref().filter(Result::is_err).count(), 1);
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn test_lexer_recovery() {
+        let source = "let x = @ + 0x; let y = \"\\q\";";
+        let tokens: Vec<Token> = Lexer::new(source)
+            .with_recovery()
+            .map(|t| t.unwrap().token)
+            .collect();
+
+        let expected = vec![
+            Token::Let,
+            Token::Ident("x".to_string()),
+            Token::Eq,
+            Token::Error(LexingError::UnexpectedCharacter('@')),
+            Token::Plus,
+            Token::Error(LexingError::MissingDigits),
+            Token::Semi,
+            Token::Let,
+            Token::Ident("y".to_string()),
+            Token::Eq,
+            Token::Error(LexingError::InvalidEscape(25..27)),
+            Token::Semi,
+        ];
+
+        assert_eq!(tokens, expected);
+    }
+
     #[test]
     fn test_lex_nested_expressions() {
         let source = "let result = (1 + 2) * (3 - 4);";
@@ -535,4 +1413,30 @@ mod tests {
             assert_eq!(token.token, expected);
         }
     }
+
+    #[test]
+    fn test_block_comments() {
+        let source = "/* This is a\n   multi-line comment */ let /* inline */ x";
+        let tokens: Vec<_> = lex_source(source).into_iter().map(Result::unwrap).collect();
+        assert_eq!(tokens[0].token, Token::Let);
+        assert_eq!(tokens[1].token, Token::Ident("x".to_string()));
+        assert_eq!(tokens.len(), 2);
+
+        let tokens = lex_source("let x; /* never closed\n let y;");
+        let error = tokens.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(error.error, LexingError::UnterminatedComment(7..9));
+        assert_eq!(
+            error.to_string(),
+            "error at 1:8:\n/*\n^ unterminated block comment\n"
+        );
+        assert_eq!(tokens.len(), 4);
+    }
+
+    #[test]
+    fn test_print_tokens() {
+        let (tokens, errors) = print_tokens("let x = ` 1;");
+        assert_eq!(tokens, "let ident(x) = error 1 ;");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error, LexingError::UnexpectedCharacter('`'));
+    }
 }
@@ -1,3 +1,31 @@
+pub mod ast;
+pub mod check;
+pub mod codegen;
+pub mod console;
+pub mod cst;
+pub mod debugger;
+pub mod diagnostic;
+pub mod engine;
+pub mod ffi;
+pub mod flow;
+pub mod fs;
+pub mod hir;
+pub mod interpreter;
 pub mod lexer;
+pub mod limits;
+pub mod loader;
+pub mod manifest;
+pub mod maps;
+pub mod mir;
 pub mod parser;
+pub mod pretty;
+pub mod profiler;
+pub mod resolve;
+pub mod source_map;
+pub mod strings;
+pub mod typeck;
 mod utils;
+pub mod visit;
+pub mod watch;
+
+pub use engine::Engine;
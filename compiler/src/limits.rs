@@ -0,0 +1,45 @@
+//! Limits on how much a running program can do, so that a host can run programs it doesn't trust
+//! and stop them cleanly, rather than have one run forever or take all of its memory.
+//!
+//! Both the interpreter and the VM count fuel and how deep calls nest, but only the VM knows how
+//! much memory its objects take, since the interpreter's values are shared Rust values.
+
+use std::fmt::Display;
+
+/// How much a program can do before it's stopped, where `None` is no more than the engine that
+/// runs it allows anyway.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// How many steps each call from the host can take, including the one that runs `main`:
+    /// instructions in the VM, and expressions evaluated in the interpreter.
+    pub fuel: Option<u64>,
+    /// How many calls can be running at once, up to the most that the engine can run.
+    pub depth: Option<usize>,
+    /// How many bytes the VM's objects can take once it's collected the ones it can't reach.
+    pub memory: Option<usize>,
+}
+
+impl Limits {
+    /// Returns how many calls can be running at once in an engine that can run `max`.
+    pub fn depth(&self, max: usize) -> usize {
+        self.depth.map_or(max, |depth| depth.min(max))
+    }
+}
+
+/// A limit that a program went past, which stopped it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Fuel,
+    Depth,
+    Memory,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Fuel => write!(f, "out of fuel"),
+            Limit::Depth => write!(f, "stack overflow"),
+            Limit::Memory => write!(f, "out of memory"),
+        }
+    }
+}
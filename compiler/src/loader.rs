@@ -0,0 +1,519 @@
+//! Loads a program from disk, following `mod name;` declarations to the files that hold the
+//! modules. A program can also be loaded from the directory of a project, whose manifest says
+//! where its root module is, or without one, whose root module is in `main.rf` or `src/main.rf`.
+
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    ast::{Ident, Item, ItemKind, ModDecl, Program},
+    diagnostic::Diagnostic,
+    manifest::{self, Manifest},
+    parser::Parser,
+    source_map::SourceMap,
+};
+
+/// A program loaded from one or more files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedProgram {
+    /// The program, where every module declared with `mod name;` has its items filled in.
+    pub program: Program,
+    pub source_map: SourceMap,
+    /// Syntax errors, modules whose file couldn't be loaded and problems with manifests.
+    pub errors: Vec<Diagnostic>,
+    /// The manifest of the project the program was loaded from, if it has a valid one.
+    pub manifest: Option<Manifest>,
+}
+
+/// Loads the program whose root module is the file at `path`, or in the project directory at
+/// `path`. The module `name` is loaded from `name.rf` or `name/mod.rf`, next to the file that
+/// declares it for the root module, or in a directory named after the declaring module otherwise.
+/// Only failing to read the root file, or a project's manifest, is returned as an error, other
+/// problems are collected in [`LoadedProgram::errors`].
+///
+/// The manifest is part of the source map, so its problems point into it, and each of the
+/// project's dependencies is loaded as a module of the root module.
+pub fn load_program(path: impl AsRef<Path>) -> io::Result<LoadedProgram> {
+    let path = path.as_ref();
+    if path.join(manifest::FILE).is_file() {
+        let mut loader = Loader::new();
+        let (items, manifest) = loader.load_project(path)?;
+        return Ok(LoadedProgram {
+            program: Program { items },
+            source_map: loader.source_map,
+            errors: loader.errors,
+            manifest,
+        });
+    }
+    let path = root_file(path)?;
+    let source = fs::read_to_string(&path)?;
+    Ok(load_source(path, &source))
+}
+
+/// Returns the file of the root module of the program at `path`: `path` itself, unless it's a
+/// directory, whose manifest says where its root module is. Without a manifest, it's in
+/// `main.rf`, or in `src/main.rf` for a project that keeps its sources apart.
+pub fn root_file(path: &Path) -> io::Result<PathBuf> {
+    if !path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    if path.join(manifest::FILE).is_file() {
+        return Ok(Manifest::read(path)?.root(path));
+    }
+    let candidates = [path.join("main.rf"), path.join("src").join("main.rf")];
+    let found: Vec<&PathBuf> = candidates.iter().filter(|path| path.is_file()).collect();
+    match found[..] {
+        [root] => Ok(root.clone()),
+        [] => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "it has no `main.rf` or `src/main.rf`",
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "it has both `main.rf` and `src/main.rf`, so its root module is ambiguous",
+        )),
+    }
+}
+
+/// Loads a program whose root module is `source`, as if it was read from the file at `path`, so
+/// its modules are loaded the same way as with [`load_program`].
+pub fn load_source(path: impl AsRef<Path>, source: &str) -> LoadedProgram {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+
+    let mut loader = Loader::new();
+    let program = loader.parse_file(path, source, &dir);
+
+    LoadedProgram {
+        program,
+        source_map: loader.source_map,
+        errors: loader.errors,
+        manifest: None,
+    }
+}
+
+/// Joins a relative path onto a directory, dropping the `..`s that it can, so that diagnostics
+/// show `geometry/main.rf` rather than `app/../geometry/main.rf`.
+fn join(dir: &Path, path: &Path) -> PathBuf {
+    let mut joined = PathBuf::new();
+    for component in dir.components().chain(path.components()) {
+        match component {
+            Component::ParentDir
+                if matches!(joined.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                joined.pop();
+            }
+            Component::CurDir => {}
+            component => joined.push(component),
+        }
+    }
+    joined
+}
+
+struct Loader {
+    source_map: SourceMap,
+    errors: Vec<Diagnostic>,
+    /// The files being loaded, each declaring a module in the next, as given and canonicalized.
+    stack: Vec<(PathBuf, PathBuf)>,
+    /// The projects being loaded, each depending on the next, canonicalized.
+    projects: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn new() -> Self {
+        Loader {
+            source_map: SourceMap::new(),
+            errors: Vec::new(),
+            stack: Vec::new(),
+            projects: Vec::new(),
+        }
+    }
+
+    /// Loads the project in `dir`, returning the items of its root module, with a module for each
+    /// of its dependencies, and its manifest if it's valid. A package that two others depend on
+    /// is loaded for each of them, as separate modules.
+    fn load_project(&mut self, dir: &Path) -> io::Result<(Vec<Item>, Option<Manifest>)> {
+        let path = dir.join(manifest::FILE);
+        let source = fs::read_to_string(&path)?;
+        let file = self.source_map.add_file(&path, &source);
+        let start = self.source_map.file(file).span.start;
+        let manifest = match Manifest::parse(&source) {
+            Ok(manifest) => manifest,
+            Err(mut error) => {
+                error.span = start + error.span.start..start + error.span.end;
+                self.errors.push(error);
+                return Ok((Vec::new(), None));
+            }
+        };
+        let at = |span: std::ops::Range<usize>| start + span.start..start + span.end;
+
+        let root = manifest.root(dir);
+        let mut items = match fs::read_to_string(&root) {
+            Ok(source) => {
+                let root_dir = root.parent().unwrap_or(Path::new("")).to_path_buf();
+                self.parse_file(&root, &source, &root_dir).items
+            }
+            Err(error) => {
+                self.errors.push(Diagnostic::error(
+                    format!("can't read the root module `{}`: {}", root.display(), error),
+                    at(manifest.package.name.span()),
+                ));
+                Vec::new()
+            }
+        };
+
+        let canonical = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        self.projects.push(canonical);
+        for (name, dependency) in &manifest.dependencies {
+            let span = at(dependency.path.span());
+            let dir = join(dir, dependency.path.get_ref());
+            let canonical = fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone());
+            if self.projects.contains(&canonical) {
+                self.errors.push(Diagnostic::error(
+                    format!("the dependency `{}` depends on this package", name),
+                    span,
+                ));
+                continue;
+            }
+            if !dir.join(manifest::FILE).is_file() {
+                self.errors.push(Diagnostic::error(
+                    format!("the dependency `{}` has no `{}`", name, manifest::FILE),
+                    span,
+                ));
+                continue;
+            }
+            match self.load_project(&dir) {
+                Ok((dependency, _)) => items.push(Item {
+                    kind: ItemKind::Mod(ModDecl {
+                        name: Ident {
+                            name: name.clone(),
+                            span: span.clone(),
+                        },
+                        items: Some(dependency),
+                    }),
+                    public: false,
+                    span,
+                    docs: Vec::new(),
+                }),
+                Err(error) => self.errors.push(Diagnostic::error(
+                    format!("can't read the dependency `{}`: {}", name, error),
+                    span,
+                )),
+            }
+        }
+        self.projects.pop();
+        Ok((items, Some(manifest)))
+    }
+
+    /// Parses a file and loads the modules it declares, which are looked for in `dir`.
+    fn parse_file(&mut self, path: &Path, source: &str, dir: &Path) -> Program {
+        let file = self.source_map.add_file(path, source);
+        let span = self.source_map.file(file).span.clone();
+
+        let mut parser = Parser::new(&self.source_map.text()[..span.end]).starting_at(span.start);
+        let mut program = parser.parse_program();
+        self.errors.extend(
+            parser
+                .errors()
+                .iter()
+                .map(|error| Diagnostic::error(error.error.to_string(), error.span.clone())),
+        );
+
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.stack.push((path.to_path_buf(), canonical));
+        self.load_modules(&mut program.items, dir);
+        self.stack.pop();
+        program
+    }
+
+    /// Fills in the items of the `mod name;` declarations among `items` from their files.
+    fn load_modules(&mut self, items: &mut [Item], dir: &Path) {
+        for item in items {
+            let ItemKind::Mod(decl) = &mut item.kind else {
+                continue;
+            };
+            let name = &decl.name.name;
+            let module_dir = dir.join(name);
+            if let Some(items) = &mut decl.items {
+                self.load_modules(items, &module_dir);
+                continue;
+            }
+
+            let candidates = [dir.join(format!("{}.rf", name)), module_dir.join("mod.rf")];
+            let found: Vec<&PathBuf> = candidates.iter().filter(|path| path.is_file()).collect();
+            let path = match found[..] {
+                [path] => path,
+                [] => {
+                    self.errors.push(
+                        Diagnostic::error(
+                            format!("file not found for module `{}`", name),
+                            decl.name.span.clone(),
+                        )
+                        .with_note(
+                            format!(
+                                "expected `{}` or `{}`",
+                                candidates[0].display(),
+                                candidates[1].display()
+                            ),
+                            None,
+                        ),
+                    );
+                    continue;
+                }
+                _ => {
+                    self.errors.push(Diagnostic::error(
+                        format!(
+                            "module `{}` is in both `{}` and `{}`",
+                            name,
+                            candidates[0].display(),
+                            candidates[1].display()
+                        ),
+                        decl.name.span.clone(),
+                    ));
+                    continue;
+                }
+            };
+
+            // A file can be reached again through a symlink, which would load it forever
+            let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            if let Some(start) = self.stack.iter().position(|(_, file)| *file == canonical) {
+                let cycle: Vec<String> = self.stack[start..]
+                    .iter()
+                    .map(|(file, _)| format!("`{}`", file.display()))
+                    .chain([format!("`{}`", path.display())])
+                    .collect();
+                self.errors.push(
+                    Diagnostic::error(
+                        format!("module `{}` is in a file that's already being loaded", name),
+                        decl.name.span.clone(),
+                    )
+                    .with_note(format!("cycle: {}", cycle.join(" -> ")), None),
+                );
+                continue;
+            }
+
+            match fs::read_to_string(path) {
+                Ok(source) => {
+                    let program = self.parse_file(path, &source, &module_dir);
+                    decl.items = Some(program.items);
+                }
+                Err(error) => self.errors.push(Diagnostic::error(
+                    format!("couldn't read `{}`: {}", path.display(), error),
+                    decl.name.span.clone(),
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ast::ModDecl, resolve::resolve};
+
+    /// Creates a directory of source files for a test, which is removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir = std::env::temp_dir().join(format!("ruffle-{}-{}", name, std::process::id()));
+            for (path, source) in files {
+                let path = dir.join(path);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, source).unwrap();
+            }
+            Self(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn module<'a>(items: &'a [Item], name: &str) -> &'a ModDecl {
+        items
+            .iter()
+            .find_map(|item| match &item.kind {
+                ItemKind::Mod(decl) if decl.name.name == name => Some(decl),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_load_module_files() {
+        let dir = TestDir::new(
+            "load-modules",
+            &[
+                (
+                    "main.rf",
+                    "mod shapes;\nmod util;\nfn main() { shapes::circle::area(util::PI) }",
+                ),
+                ("shapes.rf", "pub mod circle;"),
+                ("shapes/circle.rf", "pub fn area(r: float) float { r * r }"),
+                ("util/mod.rf", "pub const PI: float = 3.14;"),
+            ],
+        );
+        let loaded = load_program(dir.0.join("main.rf")).unwrap();
+        assert!(loaded.errors.is_empty(), "{:?}", loaded.errors);
+        assert_eq!(loaded.source_map.files().len(), 4);
+
+        let shapes = module(&loaded.program.items, "shapes");
+        let circle = module(shapes.items.as_ref().unwrap(), "circle");
+        let area = &circle.items.as_ref().unwrap()[0];
+        let location = loaded.source_map.location(area.span.start).unwrap();
+        assert_eq!(location.path, dir.0.join("shapes/circle.rf"));
+        assert_eq!(
+            &loaded.source_map.text()[area.span.clone()],
+            "pub fn area(r: float) float { r * r }"
+        );
+
+        // Spans from different files don't overlap, so names resolve across them
+        let res = resolve(&loaded.program);
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+    }
+
+    #[test]
+    fn test_load_missing_module() {
+        let dir = TestDir::new(
+            "load-missing",
+            &[
+                ("main.rf", "mod gone;\nmod broken;"),
+                ("broken.rf", "fn f( {}"),
+            ],
+        );
+        let loaded = load_program(dir.0.join("main.rf")).unwrap();
+
+        let messages: Vec<_> = loaded.errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "file not found for module `gone`",
+                "expected pattern, found `{`"
+            ]
+        );
+        let location = loaded
+            .source_map
+            .location(loaded.errors[1].span.start)
+            .unwrap();
+        assert_eq!(location.path, dir.0.join("broken.rf"));
+        assert_eq!((location.row, location.col), (1, 7));
+
+        assert!(load_program(dir.0.join("nope.rf")).is_err());
+    }
+
+    #[test]
+    fn test_load_directory() {
+        let dir = TestDir::new(
+            "load-directory",
+            &[
+                ("src/main.rf", "mod util;\nfn main() { util::twice(1) }"),
+                ("src/util.rf", "pub fn twice(x: int) int { x + }"),
+            ],
+        );
+        let loaded = load_program(&dir.0).unwrap();
+        assert_eq!(loaded.source_map.files().len(), 2);
+        assert_eq!(loaded.source_map.files()[0].path, dir.0.join("src/main.rf"));
+
+        // Errors point into the file they're in
+        let [error] = &loaded.errors[..] else {
+            panic!("expected one error, found {:?}", loaded.errors);
+        };
+        let location = loaded.source_map.location(error.span.start).unwrap();
+        assert_eq!(location.path, dir.0.join("src/util.rf"));
+
+        fs::write(dir.0.join("main.rf"), "fn main() {}").unwrap();
+        let error = load_program(&dir.0).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        fs::remove_dir_all(dir.0.join("src")).unwrap();
+        assert!(load_program(&dir.0).unwrap().errors.is_empty());
+        fs::remove_file(dir.0.join("main.rf")).unwrap();
+        assert_eq!(
+            load_program(&dir.0).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_load_project() {
+        let dir = TestDir::new(
+            "load-project",
+            &[
+                (
+                    "app/ruffle.toml",
+                    "[package]\nname = \"app\"\nentry = \"app.rf\"\n\n[dependencies]\n\
+                     geometry = { path = \"../geometry\" }\n",
+                ),
+                ("app/src/app.rf", "fn main() { geometry::area(2.0); }"),
+                (
+                    "geometry/ruffle.toml",
+                    "[package]\nname = \"geometry\"\n[dependencies]\nmore = { path = \"../more\" }",
+                ),
+                (
+                    "geometry/src/main.rf",
+                    "pub fn area(r: float) float { r * r }",
+                ),
+            ],
+        );
+        let loaded = load_program(dir.0.join("app")).unwrap();
+        assert_eq!(loaded.manifest.unwrap().package.name.get_ref(), "app");
+        let paths: Vec<_> = (loaded.source_map.files().iter())
+            .map(|file| file.path.strip_prefix(&dir.0).unwrap().to_path_buf())
+            .collect();
+        let expected = [
+            "app/ruffle.toml",
+            "app/src/app.rf",
+            "geometry/ruffle.toml",
+            "geometry/src/main.rf",
+        ];
+        assert_eq!(paths, expected.map(PathBuf::from));
+
+        // A dependency's problems point at where it's depended on
+        let [error] = &loaded.errors[..] else {
+            panic!("expected one error, found {:?}", loaded.errors);
+        };
+        assert_eq!(error.message, "the dependency `more` has no `ruffle.toml`");
+        let location = loaded.source_map.location(error.span.start).unwrap();
+        assert_eq!(location.path, dir.0.join("geometry/ruffle.toml"));
+        assert_eq!(location.row, 4);
+
+        let geometry = module(&loaded.program.items, "geometry");
+        assert_eq!(geometry.items.as_ref().unwrap().len(), 1);
+        let res = resolve(&loaded.program);
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_load_module_cycle() {
+        let dir = TestDir::new(
+            "load-cycle",
+            &[("main.rf", "mod a;"), ("a.rf", "mod b;\nfn f() {}")],
+        );
+        // `a/b.rf` is `main.rf`, so loading it would declare `a` again
+        fs::create_dir_all(dir.0.join("a")).unwrap();
+        std::os::unix::fs::symlink(dir.0.join("main.rf"), dir.0.join("a/b.rf")).unwrap();
+        let loaded = load_program(dir.0.join("main.rf")).unwrap();
+
+        let [error] = &loaded.errors[..] else {
+            panic!("expected one error, found {:?}", loaded.errors);
+        };
+        assert_eq!(
+            error.message,
+            "module `b` is in a file that's already being loaded"
+        );
+        assert_eq!(
+            error.notes[0].message,
+            format!(
+                "cycle: `{}` -> `{}` -> `{}`",
+                dir.0.join("main.rf").display(),
+                dir.0.join("a.rf").display(),
+                dir.0.join("a/b.rf").display(),
+            )
+        );
+    }
+}
@@ -1,18 +1,829 @@
-use std::fs;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process, thread,
+};
 
-use compiler::lexer::{lex_source, SlicedToken};
+use clap::{Args, Parser, Subcommand};
+#[cfg(feature = "backend-llvm")]
+use compiler::codegen::llvm;
+use compiler::{
+    check,
+    codegen::{
+        asm::{self, Syntax},
+        bytecode::{self, GcConfig, Module},
+        c,
+        cranelift::Jit,
+        object, wasm,
+    },
+    console::Console,
+    debugger,
+    diagnostic::Diagnostic,
+    ffi::Externs,
+    hir, interpreter,
+    lexer::{print_tokens, SlicedError},
+    limits::Limits,
+    loader::{self, load_program, LoadedProgram},
+    manifest::Manifest,
+    mir::{self, opt::OptLevel},
+    pretty::print_program,
+    profiler,
+    resolve::{LintOptions, Resolution},
+    watch,
+};
 
-fn main() {
-    let source = fs::read_to_string("examples/test.rf").unwrap();
-    let lexer = lex_source(&source);
-    let tokens: Vec<SlicedToken> = lexer
+/// The compiler for Ruffle, a small language like Rust.
+#[derive(Parser, Debug)]
+#[command(name = "ruffle", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Compile a program to an executable, or to the outputs that `--emit` asks for
+    Build(BuildArgs),
+    /// Run a program
+    Run(RunArgs),
+    /// Check a program for errors without compiling it
+    Check(CheckArgs),
+    /// Print the tokens of a file
+    Tokenize(Sources),
+    /// Run a program in the VM, stopping it in a debugger
+    Debug(DebugArgs),
+    /// Run a program in the VM, then report the functions and lines it ran the most instructions
+    /// of
+    Profile(DebugArgs),
+}
+
+#[derive(Args, Debug)]
+struct Source {
+    /// The file of the program's root module, which its other modules are loaded next to, or the
+    /// directory of a project, whose `ruffle.toml` says where its root module is, or without one,
+    /// whose root module is in `main.rf` or `src/main.rf`
+    #[arg(default_value = ".")]
+    path: PathBuf,
+    /// Accept a file whatever its extension, rather than only `.rf` files
+    #[arg(long)]
+    any_extension: bool,
+}
+
+impl Source {
+    /// Returns the path, exiting if it isn't a source file.
+    fn path(&self) -> &Path {
+        if let Err(error) = validate(&self.path, self.any_extension) {
+            fail(error);
+        }
+        &self.path
+    }
+}
+
+/// Several programs, each compiled on its own.
+#[derive(Args, Debug)]
+struct Sources {
+    /// The files of the programs' root modules, which their other modules are loaded next to, or
+    /// the directories of projects, whose `ruffle.toml` says where their root modules are, or
+    /// without one, whose root modules are in `main.rf` or `src/main.rf`
+    #[arg(default_value = ".")]
+    paths: Vec<PathBuf>,
+    /// Accept files whatever their extension, rather than only `.rf` files
+    #[arg(long)]
+    any_extension: bool,
+}
+
+impl Sources {
+    /// Returns the paths, exiting if any of them isn't a source file.
+    fn paths(&self) -> &[PathBuf] {
+        let errors: Vec<_> = (self.paths.iter())
+            .filter_map(|path| validate(path, self.any_extension).err())
+            .collect();
+        for error in &errors {
+            eprintln!("error: {}", error);
+        }
+        if !errors.is_empty() {
+            process::exit(1);
+        }
+        &self.paths
+    }
+}
+
+/// Returns why a path can't be compiled, if it can't: it has to be a directory, or a file that
+/// ends in `.rf` unless `--any-extension` says otherwise.
+fn validate(path: &Path, any_extension: bool) -> Result<(), String> {
+    if !path.exists() {
+        return Err(format!("file not found: `{}`", path.display()));
+    }
+    if path.is_dir() {
+        return Ok(());
+    }
+    if !path.is_file() {
+        return Err(format!("`{}` isn't a file or directory", path.display()));
+    }
+    if !any_extension && path.extension().is_none_or(|extension| extension != "rf") {
+        return Err(format!(
+            "`{}` isn't a Ruffle source file, which ends in `.rf`; pass `--any-extension` to \
+             compile it anyway",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+struct CheckArgs {
+    #[command(flatten)]
+    sources: Sources,
+    #[command(flatten)]
+    lints: Lints,
+}
+
+#[derive(Args, Debug)]
+struct BuildArgs {
+    #[command(flatten)]
+    sources: Sources,
+    #[command(flatten)]
+    lints: Lints,
+    /// What to output, separated by commas, in files named after the source when there are
+    /// several or they're binary: tokens, ast, ast-json, hir, mir, cfg, bytecode, asm, c, wasm,
+    /// obj, llvm-ir or bin
+    #[arg(long, value_delimiter = ',', value_parser = Emit::parse, default_value = "bin")]
+    emit: Vec<Emit>,
+    #[command(flatten)]
+    opt: Opt,
+    /// What compiles the executable: native, with Cranelift, c, with the system's C compiler, or
+    /// llvm, when the compiler is built with it
+    #[arg(long, value_parser = Backend::parse, default_value = "native")]
+    backend: Backend,
+    /// Add DWARF debug info to native code
+    #[arg(short = 'g')]
+    debug: bool,
+    /// The syntax of `--emit=asm`
+    #[arg(long, value_parser = parse_syntax, default_value = "att")]
+    asm_syntax: Syntax,
+    /// Link the executable with a library, for the functions the program declares with
+    /// `extern fn`, as well as the libraries in the manifest
+    #[arg(short = 'l', long = "link", value_name = "LIB")]
+    libs: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    #[command(flatten)]
+    source: Source,
+    #[command(flatten)]
+    lints: Lints,
+    /// What runs the program: vm, interpreter or jit
+    #[arg(long, value_parser = Engine::parse, default_value = "vm")]
+    engine: Engine,
+    #[command(flatten)]
+    opt: Opt,
+    #[command(flatten)]
+    runtime: Runtime,
+    /// Patch the program as its files change, and run it again when they do, in the VM
+    #[arg(long)]
+    watch: bool,
+}
+
+#[derive(Args, Debug)]
+struct DebugArgs {
+    #[command(flatten)]
+    source: Source,
+    #[command(flatten)]
+    lints: Lints,
+    #[command(flatten)]
+    opt: Opt,
+    #[command(flatten)]
+    runtime: Runtime,
+}
+
+#[derive(Args, Debug)]
+struct Opt {
+    /// How much to optimize: 0, 1, 2 or 3, by default the `opt-level` of the project's
+    /// `ruffle.toml`, or 0
+    #[arg(short = 'O', value_parser = parse_level)]
+    level: Option<OptLevel>,
+    /// Optimize as much as `-O3`
+    #[arg(long)]
+    release: bool,
+}
+
+impl Opt {
+    fn level(&self, manifest: Option<&Manifest>) -> OptLevel {
+        if self.release {
+            return OptLevel::O3;
+        }
+        let level = self
+            .level
+            .or_else(|| manifest.and_then(Manifest::opt_level));
+        level.unwrap_or(OptLevel::O0)
+    }
+}
+
+/// The opt-in lints that checking a program warns about.
+#[derive(Args, Debug)]
+struct Lints {
+    /// Warn about a lint that's off by default, as well as those in the manifest: shadowing, for
+    /// a `let` that shadows a variable of an enclosing scope
+    #[arg(long = "warn", value_name = "LINT", value_parser = parse_lint)]
+    warn: Vec<String>,
+}
+
+impl Lints {
+    fn options(&self, manifest: Option<&Manifest>) -> LintOptions {
+        let mut options = manifest.map_or_else(LintOptions::default, Manifest::lints);
+        for lint in &self.warn {
+            options.warn(lint);
+        }
+        options
+    }
+}
+
+/// How the VM and the interpreter run a program.
+#[derive(Args, Debug)]
+struct Runtime {
+    /// The bytes the VM's objects can take before it first collects them
+    #[arg(long)]
+    gc_threshold: Option<usize>,
+    /// The instructions of the VM, or expressions of the interpreter, that the program can run
+    #[arg(long)]
+    fuel: Option<u64>,
+    /// How many calls can be running at once
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// The bytes the VM's objects can take once it's collected the ones it can't reach
+    #[arg(long)]
+    max_memory: Option<usize>,
+}
+
+impl Runtime {
+    fn gc(&self) -> GcConfig {
+        let mut gc = GcConfig::default();
+        if let Some(threshold) = self.gc_threshold {
+            gc.threshold = threshold;
+        }
+        gc
+    }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            fuel: self.fuel,
+            depth: self.max_depth,
+            memory: self.max_memory,
+        }
+    }
+
+    /// The first of the flags that was given, for an engine that takes none of them.
+    fn given(&self) -> Option<&'static str> {
+        [
+            ("--gc-threshold", self.gc_threshold.is_some()),
+            ("--fuel", self.fuel.is_some()),
+            ("--max-depth", self.max_depth.is_some()),
+            ("--max-memory", self.max_memory.is_some()),
+        ]
         .into_iter()
-        .map(|token| match token {
-            Ok(t) => t,
-            Err(e) => panic!("{}", e),
+        .find_map(|(flag, given)| given.then_some(flag))
+    }
+}
+
+fn parse_level(level: &str) -> Result<OptLevel, String> {
+    match level {
+        "0" => Ok(OptLevel::O0),
+        "1" => Ok(OptLevel::O1),
+        "2" => Ok(OptLevel::O2),
+        "3" => Ok(OptLevel::O3),
+        _ => Err(format!("unknown optimization level `{}`", level)),
+    }
+}
+
+fn parse_lint(lint: &str) -> Result<String, String> {
+    match LintOptions::default().warn(lint) {
+        true => Ok(lint.to_string()),
+        false => Err(format!("unknown lint `{}`", lint)),
+    }
+}
+
+fn parse_syntax(syntax: &str) -> Result<Syntax, String> {
+    match syntax {
+        "att" => Ok(Syntax::Att),
+        "intel" => Ok(Syntax::Intel),
+        _ => Err(format!("unknown assembly syntax `{}`", syntax)),
+    }
+}
+
+/// Something the compiler outputs, chosen with `--emit=<kind>,<kind>...`, in the order of the
+/// stages of the pipeline that produce them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Emit {
+    Tokens,
+    Ast,
+    AstJson,
+    Hir,
+    Mir,
+    Cfg,
+    Bytecode,
+    Asm,
+    C,
+    Wasm,
+    Object,
+    #[cfg(feature = "backend-llvm")]
+    LlvmIr,
+    /// An executable, which `build` outputs unless it's asked for something else.
+    Bin,
+}
+
+impl Emit {
+    fn parse(kind: &str) -> Result<Emit, String> {
+        Ok(match kind {
+            "tokens" => Emit::Tokens,
+            "ast" => Emit::Ast,
+            "ast-json" => Emit::AstJson,
+            "hir" => Emit::Hir,
+            "mir" => Emit::Mir,
+            "cfg" => Emit::Cfg,
+            "bytecode" => Emit::Bytecode,
+            "asm" => Emit::Asm,
+            "c" => Emit::C,
+            "wasm" => Emit::Wasm,
+            "obj" => Emit::Object,
+            #[cfg(feature = "backend-llvm")]
+            "llvm-ir" => Emit::LlvmIr,
+            "bin" => Emit::Bin,
+            _ => return Err(format!("unknown kind of output `{}`", kind)),
         })
-        .collect();
-    for token in tokens {
-        print!("{} ", token)
+    }
+
+    /// Returns whether the output is binary, which always goes to a file rather than stdout.
+    fn is_binary(self) -> bool {
+        matches!(self, Emit::Wasm | Emit::Object | Emit::Bin)
+    }
+
+    /// The extension of the file the output is written to when there are several.
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::Tokens => "tokens",
+            Emit::Ast => "ast",
+            Emit::AstJson => "ast.json",
+            Emit::Hir => "hir",
+            Emit::Mir => "mir",
+            Emit::Cfg => "dot",
+            Emit::Bytecode => "bytecode",
+            Emit::Asm => "s",
+            Emit::C => "c",
+            Emit::Wasm => "wasm",
+            Emit::Object => "o",
+            #[cfg(feature = "backend-llvm")]
+            Emit::LlvmIr => "ll",
+            Emit::Bin => "",
+        }
+    }
+}
+
+/// What compiles a program to an executable for `build`, chosen with `--backend=<name>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    /// Cranelift, which is built into the compiler.
+    Native,
+    /// The system's C compiler, which is given the program as C with the runtime library.
+    C,
+    /// LLVM, whose `opt` and `llc` optimize and compile the program's IR.
+    #[cfg(feature = "backend-llvm")]
+    Llvm,
+}
+
+impl Backend {
+    fn parse(name: &str) -> Result<Backend, String> {
+        match name {
+            "native" => Ok(Backend::Native),
+            "c" => Ok(Backend::C),
+            #[cfg(feature = "backend-llvm")]
+            "llvm" => Ok(Backend::Llvm),
+            _ => Err(format!("unknown backend `{}`", name)),
+        }
+    }
+}
+
+/// The stack of the thread the interpreter runs on, which programs that recurse deeply need.
+const INTERPRETER_STACK: usize = 1 << 30;
+
+/// What runs a program for `run`, chosen with `--engine=<name>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Engine {
+    /// Compiling it to bytecode and running that in a VM.
+    Vm,
+    /// Evaluating its HIR, which is checked but never compiled.
+    Interpreter,
+    /// Compiling it into memory with Cranelift.
+    Jit,
+}
+
+impl Engine {
+    fn parse(name: &str) -> Result<Engine, String> {
+        match name {
+            "vm" => Ok(Engine::Vm),
+            "interpreter" => Ok(Engine::Interpreter),
+            "jit" => Ok(Engine::Jit),
+            _ => Err(format!("unknown engine `{}`", name)),
+        }
+    }
+}
+
+/// Where the outputs go: to stdout if there's only one, of one source, and otherwise to files
+/// named after each source, like `main.mir` for `main.rf`. A binary output, like a wasm module or
+/// an executable, is always a file named after the source, like `main.wasm` or `main`, in the
+/// current directory.
+struct Outputs<'a> {
+    stem: &'a str,
+    to_files: bool,
+}
+
+impl Outputs<'_> {
+    fn write(&self, emit: Emit, output: &[u8]) {
+        if !self.to_files && !emit.is_binary() {
+            // Such as when it's piped to a program that's exited
+            if let Err(error) = io::stdout().write_all(output) {
+                fail(format!("can't write to stdout: {}", error));
+            }
+            return;
+        }
+        let path = format!("{}.{}", self.stem, emit.extension());
+        if let Err(error) = fs::write(&path, output) {
+            fail(format!("can't write `{}`: {}", path, error));
+        }
+    }
+}
+
+fn fail(error: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", error);
+    process::exit(1);
+}
+
+/// A program that's been checked, and lowered to HIR.
+struct Checked {
+    loaded: LoadedProgram,
+    res: Resolution,
+    lowered: hir::Program,
+}
+
+impl Checked {
+    /// Compiles the program to MIR, optimized at a level.
+    fn mir(&self, level: OptLevel) -> mir::Program {
+        let mut program = mir::build(&self.lowered, &self.res);
+        mir::opt::optimize(&mut program, level);
+        program
+    }
+
+    /// Prints an error for each generator, for the engines and backends other than the VM.
+    /// Returns whether there were none.
+    fn no_generators(&self) -> bool {
+        let errors = check::generators(&self.lowered);
+        for error in &errors {
+            eprintln!("{}", error.with_source_map(&self.loaded.source_map));
+        }
+        errors.is_empty()
+    }
+}
+
+/// Loads a program and checks it, printing its errors and warnings, with the opt-in lints that
+/// the command line and the manifest turn on. Returns `None` if it has errors.
+fn check(path: &Path, lints: &Lints) -> Option<Checked> {
+    // Modules declared with `mod name;` are loaded from their own files
+    let loaded = match load_program(path) {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            eprintln!("error: can't read `{}`: {}", path.display(), error);
+            return None;
+        }
+    };
+    let options = lints.options(Manifest::find(path).as_ref());
+    let (diagnostics, checked) = check::check_with(&loaded, options);
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.with_source_map(&loaded.source_map));
+    }
+    // The IR of a program with errors isn't worth looking at
+    let (res, lowered) = checked?;
+    Some(Checked {
+        loaded,
+        res,
+        lowered,
+    })
+}
+
+/// Checks a program, exiting if it has errors.
+fn checked(path: &Path, lints: &Lints) -> Checked {
+    check(path, lints).unwrap_or_else(|| process::exit(1))
+}
+
+/// Compiles a program to bytecode, which the peephole optimizer only rewrites from `-O1` on.
+fn bytecode(program: &mir::Program, level: OptLevel, peephole: bool) -> Module {
+    let mut module = bytecode::emit(program);
+    if level >= OptLevel::O1 && peephole {
+        bytecode::peephole(&mut module);
+    }
+    module
+}
+
+fn main() {
+    match Cli::parse().command {
+        Command::Build(args) => build(args),
+        Command::Run(args) => run(args),
+        Command::Check(args) => {
+            let paths = args.sources.paths();
+            // Every program is checked, even once one has errors
+            if paths
+                .iter()
+                .filter(|path| check(path, &args.lints).is_none())
+                .count()
+                > 0
+            {
+                process::exit(1);
+            }
+        }
+        Command::Tokenize(args) => {
+            let paths = args.paths();
+            if paths.iter().filter(|path| !tokenize(path)).count() > 0 {
+                process::exit(1);
+            }
+        }
+        Command::Debug(args) => {
+            let path = args.source.path();
+            let checked = checked(path, &args.lints);
+            let level = args.opt.level(Manifest::find(path).as_ref());
+            // The debugger finds variables in their slots, where the peephole optimizer may not
+            // leave them
+            let module = bytecode(&checked.mir(level), level, false);
+            let source_map = &checked.loaded.source_map;
+            let (gc, limits) = (args.runtime.gc(), args.runtime.limits());
+            let commands = Console::stdio();
+            let host = Externs::default();
+            let ran = debugger::run(
+                &module,
+                source_map,
+                gc,
+                limits,
+                Console::stdio(),
+                commands,
+                &host,
+            );
+            if let Err(panic) = ran {
+                eprintln!("{}", panic.with_source_map(source_map));
+                process::exit(101);
+            }
+        }
+        Command::Profile(args) => {
+            let path = args.source.path();
+            let checked = checked(path, &args.lints);
+            let manifest = Manifest::find(path);
+            let level = args.opt.level(manifest.as_ref());
+            let module = bytecode(&checked.mir(level), level, true);
+            let (gc, limits) = (args.runtime.gc(), args.runtime.limits());
+            let host = Externs::default();
+            let (profile, ran) = profiler::run(&module, gc, limits, Console::stdio(), &host);
+            let source_map = &checked.loaded.source_map;
+            eprint!("\n{}", profiler::report(&profile, &module, source_map));
+            let path = format!("{}.folded", stem(path, manifest.as_ref()));
+            if let Err(error) = fs::write(&path, profiler::folded(&profile, &module)) {
+                fail(format!("can't write `{}`: {}", path, error));
+            }
+            eprintln!("\nthe stacks for flame graphs are in `{}`", path);
+            if let Err(panic) = ran {
+                eprintln!("{}", panic.with_source_map(source_map));
+                process::exit(101);
+            }
+        }
+    }
+}
+
+/// Returns the name of a source file without its extension, or of the package of a project
+/// directory, or of the directory without a manifest, which outputs are named after.
+fn stem(path: &Path, manifest: Option<&Manifest>) -> String {
+    if let Some(manifest) = manifest {
+        return manifest.package.name.get_ref().clone();
+    }
+    // `.` and `..` are named after the directories they stand for
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let stem = if path.is_dir() {
+        path.file_name()
+    } else {
+        path.file_stem()
+    };
+    stem.map_or_else(|| "main".into(), |stem| stem.to_string_lossy().into_owned())
+}
+
+/// Reads the root file of a program, printing why it can't be read if it can't.
+fn read(path: &Path) -> Option<String> {
+    match loader::root_file(path).and_then(fs::read_to_string) {
+        Ok(source) => Some(source),
+        Err(error) => {
+            eprintln!("error: can't read `{}`: {}", path.display(), error);
+            None
+        }
+    }
+}
+
+/// Prints the tokens of a file, returning whether it had no errors.
+fn tokenize(path: &Path) -> bool {
+    let Some(source) = read(path) else {
+        return false;
+    };
+    let (tokens, errors) = print_tokens(&source);
+    println!("{}", tokens);
+    lexed(&source, &errors)
+}
+
+/// Prints the errors from lexing a file, returning whether there were none.
+fn lexed(source: &str, errors: &[SlicedError]) -> bool {
+    for error in errors {
+        let diagnostic = Diagnostic::error(error.error.to_string(), error.span.clone());
+        eprintln!("{}", diagnostic.with_source(source));
+    }
+    errors.is_empty()
+}
+
+fn build(mut args: BuildArgs) {
+    args.emit.sort();
+    args.emit.dedup();
+    if args.debug && args.backend != Backend::Native {
+        fail("`-g` only adds debug info to `--backend native`");
+    }
+    let paths = args.sources.paths();
+    // Every program is built, even once one has errors
+    let several = paths.len() > 1;
+    if paths
+        .iter()
+        .filter(|path| !build_file(path, &args, several))
+        .count()
+        > 0
+    {
+        process::exit(1);
+    }
+}
+
+/// Builds the outputs of one program, returning whether it had no errors. There are several
+/// programs when `several` is set, and their outputs go to files.
+fn build_file(path: &Path, args: &BuildArgs, several: bool) -> bool {
+    let manifest = Manifest::find(path);
+    let level = args.opt.level(manifest.as_ref());
+    let emits = &args.emit;
+    let stem = stem(path, manifest.as_ref());
+    let outputs = Outputs {
+        stem: &stem,
+        to_files: several || emits.iter().filter(|&&emit| emit != Emit::Bin).count() > 1,
+    };
+    // Each stage only runs if an output needs it
+    let needs = |stage: Emit| emits.last().is_some_and(|&last| last >= stage);
+
+    if emits.contains(&Emit::Tokens) {
+        let Some(source) = read(path) else {
+            return false;
+        };
+        let (tokens, errors) = print_tokens(&source);
+        outputs.write(Emit::Tokens, format!("{}\n", tokens).as_bytes());
+        if !lexed(&source, &errors) {
+            return false;
+        }
+    }
+    if !needs(Emit::Ast) {
+        return true;
+    }
+    if !needs(Emit::Hir) {
+        let loaded = match load_program(path) {
+            Ok(loaded) => loaded,
+            Err(error) => {
+                eprintln!("error: can't read `{}`: {}", path.display(), error);
+                return false;
+            }
+        };
+        for error in &loaded.errors {
+            eprintln!("{}", error.with_source_map(&loaded.source_map));
+        }
+        if emits.contains(&Emit::Ast) {
+            outputs.write(Emit::Ast, print_program(&loaded.program).as_bytes());
+        }
+        if emits.contains(&Emit::AstJson) {
+            let json = serde_json::to_string_pretty(&loaded.program).unwrap() + "\n";
+            outputs.write(Emit::AstJson, json.as_bytes());
+        }
+        return loaded.errors.is_empty();
+    }
+
+    let Some(checked) = check(path, &args.lints) else {
+        return false;
+    };
+    let program = &checked.loaded.program;
+    if emits.contains(&Emit::Ast) {
+        outputs.write(Emit::Ast, print_program(program).as_bytes());
+    }
+    if emits.contains(&Emit::AstJson) {
+        let json = serde_json::to_string_pretty(program).unwrap() + "\n";
+        outputs.write(Emit::AstJson, json.as_bytes());
+    }
+    if emits.contains(&Emit::Hir) {
+        let hir = hir::print_program(&checked.lowered, &checked.res);
+        outputs.write(Emit::Hir, hir.as_bytes());
+    }
+    if !needs(Emit::Mir) {
+        return true;
+    }
+    // Everything from assembly on is compiled for a machine, where generators don't run
+    if needs(Emit::Asm) && !checked.no_generators() {
+        return false;
+    }
+    let mut libs = args.libs.clone();
+    if let Some(manifest) = &manifest {
+        libs.extend(manifest.build.link.iter().cloned());
+    }
+    let debug = args.debug || manifest.is_some_and(|manifest| manifest.build.debug);
+    let source_map = debug.then_some(&checked.loaded.source_map);
+    let program = checked.mir(level);
+    for &emit in emits {
+        match emit {
+            Emit::Mir => outputs.write(emit, mir::print_program(&program).as_bytes()),
+            Emit::Cfg => outputs.write(emit, mir::print_dot(&program).as_bytes()),
+            Emit::Bytecode => {
+                let module = bytecode(&program, level, true);
+                outputs.write(emit, bytecode::disassemble(&module).as_bytes());
+            }
+            Emit::Asm => {
+                match asm::emit_asm(&program, level, args.asm_syntax, &checked.loaded.source_map) {
+                    Ok(listing) => outputs.write(emit, listing.as_bytes()),
+                    Err(error) => fail(error),
+                }
+            }
+            Emit::C => outputs.write(emit, c::emit_c(&program).as_bytes()),
+            Emit::Wasm => outputs.write(emit, &wasm::emit(&program)),
+            Emit::Object => match object::emit_object(&program, level, source_map) {
+                Ok(object) => outputs.write(emit, &object),
+                Err(error) => fail(error),
+            },
+            #[cfg(feature = "backend-llvm")]
+            Emit::LlvmIr => outputs.write(emit, llvm::emit_ir(&program).as_bytes()),
+            Emit::Bin => {
+                let output = Path::new(&stem);
+                let built = match args.backend {
+                    Backend::Native => {
+                        object::build_executable(&program, output, level, source_map, &libs)
+                    }
+                    Backend::C => c::build_executable(&program, output, level, &libs),
+                    #[cfg(feature = "backend-llvm")]
+                    Backend::Llvm => llvm::build_executable(&program, output, level, &libs),
+                };
+                if let Err(error) = built {
+                    fail(error);
+                }
+            }
+            Emit::Tokens | Emit::Ast | Emit::AstJson | Emit::Hir => {}
+        }
+    }
+    true
+}
+
+fn run(args: RunArgs) {
+    let path = args.source.path();
+    let level = args.opt.level(Manifest::find(path).as_ref());
+    let (gc, limits) = (args.runtime.gc(), args.runtime.limits());
+    let host = Externs::default();
+    if args.watch {
+        if args.engine != Engine::Vm {
+            fail("`--watch` only runs programs in the VM");
+        }
+        watch::run(path, level, gc, limits, &host);
+    }
+    if let (Engine::Jit, Some(flag)) = (args.engine, args.runtime.given()) {
+        fail(format!("`{}` doesn't apply to `--engine jit`", flag));
+    }
+
+    let checked = checked(path, &args.lints);
+    if args.engine != Engine::Vm && !checked.no_generators() {
+        process::exit(1);
+    }
+    let source_map = &checked.loaded.source_map;
+    match args.engine {
+        Engine::Vm => {
+            let module = bytecode(&checked.mir(level), level, true);
+            if let Err(panic) = bytecode::run(&module, gc, limits, Console::stdio(), &host) {
+                eprintln!("{}", panic.with_source_map(source_map));
+                process::exit(101);
+            }
+        }
+        Engine::Jit => match Jit::new(&checked.mir(level), level) {
+            Ok(jit) => jit.run(),
+            Err(error) => fail(error),
+        },
+        Engine::Interpreter => {
+            let lowered = checked.lowered;
+            // Each call the program makes nests calls in the interpreter
+            let interpreted = thread::Builder::new()
+                .stack_size(INTERPRETER_STACK)
+                .spawn(move || interpreter::run(&lowered, limits, Console::stdio(), &host))
+                .unwrap()
+                .join()
+                .unwrap();
+            if let Err(panic) = interpreted {
+                eprintln!("{}", panic.with_source_map(source_map));
+                process::exit(101);
+            }
+        }
     }
 }
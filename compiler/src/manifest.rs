@@ -0,0 +1,259 @@
+//! Reads `ruffle.toml`, the manifest of a project, which names its package, says where its
+//! sources are, and lists the packages it depends on and how to build it:
+//!
+//! ```toml
+//! [package]
+//! name = "shapes"
+//! version = "0.1.0"
+//! edition = "2025"
+//! src = "src"
+//! entry = "main.rf"
+//!
+//! [dependencies]
+//! geometry = { path = "../geometry" }
+//!
+//! [build]
+//! opt-level = 2
+//! debug = true
+//! link = ["sqlite3"]
+//!
+//! [lints]
+//! warn = ["shadowing"]
+//! ```
+//!
+//! Only `name` is required. A dependency is another project on disk, which is loaded as a module
+//! of the root module named after it, so `geometry::area` calls the `pub fn area` of its root
+//! module. The libraries in `link` are linked into executables for the `extern fn`s of the
+//! program, and the lints in `warn` are the opt-in ones that checking it warns about, as with
+//! `--warn`.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{diagnostic::Diagnostic, mir::opt::OptLevel, resolve::LintOptions};
+
+/// The name of the manifest in the directory of a project.
+pub const FILE: &str = "ruffle.toml";
+
+/// The editions of the language, oldest first. A manifest without one is on the latest.
+pub const EDITIONS: &[&str] = &["2025"];
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub package: Package,
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Dependency>,
+    #[serde(default)]
+    pub build: Build,
+    #[serde(default)]
+    pub lints: Lints,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Package {
+    /// The name of the package, which its executable is named after.
+    pub name: Spanned<String>,
+    pub version: Option<String>,
+    pub edition: Option<Spanned<String>>,
+    /// The directory of the sources, `src` by default.
+    pub src: Option<PathBuf>,
+    /// The file of the root module in the directory of the sources, `main.rf` by default.
+    pub entry: Option<PathBuf>,
+}
+
+/// A package that a project depends on.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dependency {
+    /// The directory of its project, relative to the one that depends on it.
+    pub path: Spanned<PathBuf>,
+}
+
+/// How the project is built, unless the command line says otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Build {
+    pub opt_level: Option<Spanned<u8>>,
+    /// Whether to add debug info to native code.
+    #[serde(default)]
+    pub debug: bool,
+    /// The libraries that executables are linked with, by the names the linker's `-l` takes.
+    #[serde(default)]
+    pub link: Vec<String>,
+}
+
+/// The lints that checking the project reports, besides those it always does.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Lints {
+    /// The opt-in lints to warn about.
+    #[serde(default)]
+    pub warn: Vec<Spanned<String>>,
+}
+
+impl Manifest {
+    /// Parses a manifest, or returns the first problem with it, spanning its source.
+    pub fn parse(source: &str) -> Result<Manifest, Diagnostic> {
+        let manifest: Manifest = toml::from_str(source).map_err(|error| {
+            let span = error.span().unwrap_or(0..0);
+            Diagnostic::error(format!("invalid manifest: {}", error.message()), span)
+        })?;
+        if manifest.package.name.get_ref().is_empty() {
+            let span = manifest.package.name.span();
+            return Err(Diagnostic::error("the package has no name", span));
+        }
+        if let Some(edition) = &manifest.package.edition {
+            if !EDITIONS.contains(&edition.get_ref().as_str()) {
+                let message = format!("unknown edition `{}`", edition.get_ref());
+                let editions: Vec<_> = EDITIONS.iter().map(|e| format!("`{}`", e)).collect();
+                return Err(Diagnostic::error(message, edition.span())
+                    .with_note(format!("the editions are {}", editions.join(", ")), None));
+            }
+        }
+        for (name, dependency) in &manifest.dependencies {
+            // Dependencies are modules, so they're named like them
+            let mut chars = name.chars();
+            let is_ident = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+                && chars.all(|c| c.is_alphanumeric() || c == '_');
+            if !is_ident {
+                let message = format!("the dependency `{}` isn't named like a module", name);
+                return Err(Diagnostic::error(message, dependency.path.span()));
+            }
+        }
+        if let Some(level) = &manifest.build.opt_level {
+            if *level.get_ref() > 3 {
+                let message = format!("unknown optimization level `{}`", level.get_ref());
+                return Err(Diagnostic::error(message, level.span()));
+            }
+        }
+        for lint in &manifest.lints.warn {
+            if !LintOptions::default().warn(lint.get_ref()) {
+                let message = format!("unknown lint `{}`", lint.get_ref());
+                return Err(Diagnostic::error(message, lint.span()));
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Reads the manifest of the project in `dir`. A manifest that can't be parsed is invalid
+    /// data.
+    pub fn read(dir: &Path) -> io::Result<Manifest> {
+        let source = fs::read_to_string(dir.join(FILE))?;
+        Manifest::parse(&source).map_err(|error| {
+            let message = format!("`{}`: {}", FILE, error.message);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })
+    }
+
+    /// Returns the manifest of the project in `dir`, if it has one that can be read.
+    pub fn find(dir: &Path) -> Option<Manifest> {
+        dir.is_dir().then(|| Manifest::read(dir).ok()).flatten()
+    }
+
+    /// Returns the file of the root module of the project in `dir`.
+    pub fn root(&self, dir: &Path) -> PathBuf {
+        let src = self.package.src.as_deref().unwrap_or(Path::new("src"));
+        let entry = (self.package.entry.as_deref()).unwrap_or(Path::new("main.rf"));
+        dir.join(src).join(entry)
+    }
+
+    pub fn edition(&self) -> &str {
+        (self.package.edition.as_ref()).map_or(EDITIONS[EDITIONS.len() - 1], |e| e.get_ref())
+    }
+
+    /// Returns the opt-in lints that the manifest turns on.
+    pub fn lints(&self) -> LintOptions {
+        let mut options = LintOptions::default();
+        for lint in &self.lints.warn {
+            options.warn(lint.get_ref());
+        }
+        options
+    }
+
+    pub fn opt_level(&self) -> Option<OptLevel> {
+        (self.build.opt_level.as_ref()).map(|level| match level.get_ref() {
+            0 => OptLevel::O0,
+            1 => OptLevel::O1,
+            2 => OptLevel::O2,
+            _ => OptLevel::O3,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let source = "[package]
+name = \"shapes\"
+src = \"lib\"
+
+[dependencies]
+geometry = { path = \"../geometry\" }
+
+[build]
+opt-level = 2
+link = [\"sqlite3\"]
+
+[lints]
+warn = [\"shadowing\"]
+";
+        let manifest = Manifest::parse(source).unwrap();
+        assert_eq!(manifest.package.name.get_ref(), "shapes");
+        assert_eq!(manifest.edition(), "2025");
+        assert_eq!(manifest.opt_level(), Some(OptLevel::O2));
+        assert!(!manifest.build.debug);
+        assert_eq!(manifest.build.link, ["sqlite3"]);
+        assert!(manifest.lints().shadowing);
+        assert_eq!(manifest.root(Path::new("p")), Path::new("p/lib/main.rf"));
+        let geometry = &manifest.dependencies["geometry"];
+        assert_eq!(geometry.path.get_ref(), Path::new("../geometry"));
+        assert_eq!(&source[geometry.path.span()], "\"../geometry\"");
+
+        let error = |source: &str| {
+            let error = Manifest::parse(source).unwrap_err();
+            (error.message, source[error.span].to_string())
+        };
+        assert_eq!(
+            error("[package]\nname = \"a\"\nedition = \"2015\""),
+            ("unknown edition `2015`".to_string(), "\"2015\"".to_string())
+        );
+        assert_eq!(
+            error("[package]\nname = \"a\"\n[build]\nopt-level = 4"),
+            (
+                "unknown optimization level `4`".to_string(),
+                "4".to_string()
+            )
+        );
+        assert_eq!(
+            error("[package]\nname = \"a\"\n[dependencies]\nmy-lib = { path = \"x\" }"),
+            (
+                "the dependency `my-lib` isn't named like a module".to_string(),
+                "\"x\"".to_string()
+            )
+        );
+        assert_eq!(
+            error("[package]\nname = \"a\"\n[lints]\nwarn = [\"shadows\"]"),
+            (
+                "unknown lint `shadows`".to_string(),
+                "\"shadows\"".to_string()
+            )
+        );
+        let (message, _) = error("[package]\nname = \"a\"\nentyr = \"b.rf\"");
+        assert!(
+            message.starts_with("invalid manifest: unknown field `entyr`"),
+            "{}",
+            message
+        );
+    }
+}
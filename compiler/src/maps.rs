@@ -0,0 +1,145 @@
+//! The maps that programs make with `Map::new()`, which the interpreter and the VM share so that
+//! they behave the same, and the same as the runtime libraries of compiled code.
+//!
+//! A map keeps its entries in the order their keys were first inserted, which is the order a
+//! `for` loop visits them in, and finds them by the hashes of their keys. It doesn't know how to
+//! hash or compare its keys, as the VM needs its heap for that, so looking one up takes the key's
+//! hash and a function that says whether another key is equal to it.
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Map<V> {
+    entries: Vec<Entry<V>>,
+    /// The indexes of the entries whose keys have each hash.
+    buckets: HashMap<u64, Vec<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry<V> {
+    /// The hash of the key.
+    pub hash: u64,
+    pub key: V,
+    pub value: V,
+}
+
+impl<V> Default for Map<V> {
+    fn default() -> Self {
+        Map {
+            entries: Vec::new(),
+            buckets: HashMap::new(),
+        }
+    }
+}
+
+impl<V> Map<V> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries, in the order they were inserted.
+    pub fn entries(&self) -> &[Entry<V>] {
+        &self.entries
+    }
+
+    /// Returns the index of the entry whose key has the hash `hash` and is one that `equal`
+    /// accepts.
+    pub fn find(&self, hash: u64, mut equal: impl FnMut(&V) -> bool) -> Option<usize> {
+        let bucket = self.buckets.get(&hash)?;
+        bucket
+            .iter()
+            .copied()
+            .find(|&index| equal(&self.entries[index].key))
+    }
+
+    /// Sets the value of a key, adding an entry for the key at the end when it doesn't have one.
+    /// `equal` says whether another key is equal to this one.
+    pub fn insert(&mut self, hash: u64, key: V, value: V, equal: impl FnMut(&V) -> bool) {
+        match self.find(hash, equal) {
+            Some(index) => self.entries[index].value = value,
+            None => {
+                self.buckets
+                    .entry(hash)
+                    .or_default()
+                    .push(self.entries.len());
+                self.entries.push(Entry { hash, key, value });
+            }
+        }
+    }
+
+    /// Removes the entry at an index, keeping the order of the others.
+    pub fn remove(&mut self, index: usize) -> Entry<V> {
+        let entry = self.entries.remove(index);
+        self.buckets.retain(|_, bucket| {
+            bucket.retain(|&other| other != index);
+            for other in bucket.iter_mut() {
+                if *other > index {
+                    *other -= 1;
+                }
+            }
+            !bucket.is_empty()
+        });
+        entry
+    }
+
+    /// Returns whether two maps have the same entries, in any order, where `equal` compares keys
+    /// and values.
+    pub fn equal(&self, other: &Map<V>, mut equal: impl FnMut(&V, &V) -> bool) -> bool {
+        self.len() == other.len()
+            && self.entries.iter().all(|entry| {
+                other
+                    .find(entry.hash, |key| equal(&entry.key, key))
+                    .is_some_and(|index| equal(&entry.value, &other.entries[index].value))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(map: &mut Map<i32>, key: i32, value: i32) {
+        // Every key has one of two hashes, so that some of them share a bucket
+        map.insert((key % 2) as u64, key, value, |other| *other == key);
+    }
+
+    fn find(map: &Map<i32>, key: i32) -> Option<i32> {
+        let index = map.find((key % 2) as u64, |other| *other == key)?;
+        Some(map.entries()[index].value)
+    }
+
+    #[test]
+    fn test_map_entries() {
+        let mut map = Map::default();
+        for key in [5, 2, 8, 3] {
+            insert(&mut map, key, key * 10);
+        }
+        insert(&mut map, 2, 0);
+        assert_eq!(map.len(), 4);
+        assert_eq!(
+            (find(&map, 2), find(&map, 3), find(&map, 4)),
+            (Some(0), Some(30), None)
+        );
+
+        let index = map.find(0, |key| *key == 2).unwrap();
+        assert_eq!(map.remove(index).value, 0);
+        let keys: Vec<i32> = map.entries().iter().map(|entry| entry.key).collect();
+        assert_eq!(keys, [5, 8, 3]);
+        assert_eq!(
+            (find(&map, 2), find(&map, 8), find(&map, 3)),
+            (None, Some(80), Some(30))
+        );
+
+        let mut other = Map::default();
+        for key in [3, 8, 5] {
+            insert(&mut other, key, key * 10);
+        }
+        assert!(map.equal(&other, |a, b| a == b));
+        insert(&mut other, 8, 0);
+        assert!(!map.equal(&other, |a, b| a == b));
+    }
+}
@@ -0,0 +1,1315 @@
+//! Builds the MIR from the HIR.
+//!
+//! Variables become values while the blocks are built, with the algorithm from "Simple and
+//! Efficient Construction of Static Single Assignment Form" by Braun et al. Each block remembers
+//! the value each variable has when it ends. Reading a variable that a block hasn't assigned looks
+//! in the block that jumps to it, or when more than one does, gives the block a parameter for the
+//! variable and passes it the variable's value from each of them. A block that can still gain
+//! jumps to it, such as the start of a loop before the body is built, isn't sealed yet, and the
+//! parameters it needs get their values once it is.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use super::*;
+use crate::{
+    ast::Literal,
+    hir::{self, LocalId, PatternKind},
+    resolve::Resolution,
+    typeck::IntTy,
+};
+
+/// Builds the MIR of a lowered program. `res` names the functions that don't have a body, such
+/// as the methods that traits require.
+pub fn build(program: &hir::Program, res: &Resolution) -> Program {
+    let mut builder = Builder {
+        hir: program,
+        res,
+        fns: Vec::new(),
+        fn_ids: HashMap::new(),
+        global_ids: HashMap::new(),
+    };
+    // Every function has an id before any are built, so that they can call those after them
+    for func in &program.fns {
+        let id = builder.declare(&func.name, Some(func.def), ret_ty(&func.ty), &func.span);
+        builder.fn_ids.insert(func.def, id);
+    }
+    for (i, global) in program.globals.iter().enumerate() {
+        builder.global_ids.insert(global.def, GlobalId(i));
+    }
+    // An extern function calls the host's, so that it's called like any other
+    for func in &program.externs {
+        let id = builder.declare(&func.name, Some(func.def), ret_ty(&func.ty), &func.span);
+        let name = func.name.clone();
+        builder.forward(id, &func.ty, &func.span, true, |args| InstKind::Extern {
+            name,
+            args,
+        });
+        builder.fn_ids.insert(func.def, id);
+    }
+
+    for (i, func) in program.fns.iter().enumerate() {
+        // A generator's body is a function of its own, which the generator runs as it's resumed
+        let id = match func.generator {
+            true => {
+                let body = builder.declare(&func.name, None, Ty::unit(), &func.span);
+                builder.forward(FuncId(i), &func.ty, &func.span, true, |args| {
+                    InstKind::Generator { func: body, args }
+                });
+                body
+            }
+            false => FuncId(i),
+        };
+        builder.define(id, &func.body, &[], &func.body.params, &func.body.value);
+    }
+    let globals = program
+        .globals
+        .iter()
+        .map(|global| {
+            let init = builder.declare(&global.name, None, global.ty.clone(), &global.span);
+            builder.define(init, &global.body, &[], &[], &global.body.value);
+            Global {
+                def: global.def,
+                name: global.name.clone(),
+                ty: global.ty.clone(),
+                is_static: global.is_static,
+                init,
+            }
+        })
+        .collect();
+    Program {
+        adts: program.adts.clone(),
+        fns: builder.fns,
+        globals,
+    }
+}
+
+/// Returns the return type of a [`Ty::Fn`].
+fn ret_ty(ty: &Ty) -> Ty {
+    match ty {
+        Ty::Fn { ret, .. } => (**ret).clone(),
+        _ => Ty::Error,
+    }
+}
+
+/// Builds the functions of a program.
+struct Builder<'a> {
+    hir: &'a hir::Program,
+    res: &'a Resolution,
+    fns: Vec<Function>,
+    fn_ids: HashMap<DefId, FuncId>,
+    global_ids: HashMap<DefId, GlobalId>,
+}
+
+impl<'a> Builder<'a> {
+    /// Adds a function without any blocks, which [`Builder::define`] fills in.
+    fn declare(&mut self, name: &str, def: Option<DefId>, ret: Ty, span: &Span) -> FuncId {
+        self.fns.push(Function {
+            name: name.to_string(),
+            def,
+            ret,
+            blocks: Vec::new(),
+            values: Vec::new(),
+            vars: Vec::new(),
+            span: span.clone(),
+        });
+        FuncId(self.fns.len() - 1)
+    }
+
+    /// Builds the blocks of a function that returns `value`. Its parameters are the variables of
+    /// `body` in `captures` and then those in `params`.
+    fn define(
+        &mut self,
+        id: FuncId,
+        body: &'a hir::Body,
+        captures: &[LocalId],
+        params: &[LocalId],
+        value: &'a hir::Expr,
+    ) {
+        let mut builder = FnBuilder {
+            builder: self,
+            body,
+            blocks: Vec::new(),
+            values: Vec::new(),
+            current: BlockId::ENTRY,
+            preds: Vec::new(),
+            sealed: Vec::new(),
+            defs: Vec::new(),
+            incomplete: Vec::new(),
+            loops: Vec::new(),
+            vars: HashMap::new(),
+        };
+        let entry = builder.new_block();
+        builder.seal(entry);
+        for &local in captures.iter().chain(params) {
+            let param = builder.new_value(body.local(local).ty.clone());
+            builder.blocks[entry.0].params.push(param);
+            builder.write_var(local, entry, param);
+        }
+        let result = builder.expr(value);
+        builder.terminate(Terminator::Return(result));
+
+        let mut vars: Vec<_> = builder.vars.into_iter().collect();
+        vars.sort_by_key(|(local, _)| *local);
+        let (blocks, values) = (builder.blocks, builder.values);
+        let func = &mut self.fns[id.0];
+        func.blocks = blocks;
+        func.values = values;
+        func.vars = vars.into_iter().map(|(_, var)| var).collect();
+        cleanup(func);
+    }
+
+    /// Returns the function with the definition `def`, whose type is `ty`, declaring it if it
+    /// doesn't have a body.
+    fn func(&mut self, def: DefId, ty: &Ty) -> FuncId {
+        if let Some(&id) = self.fn_ids.get(&def) {
+            return id;
+        }
+        let definition = self.res.def(def);
+        let id = self.declare(&definition.name, Some(def), ret_ty(ty), &definition.span);
+        if let Some(intrinsic) = Intrinsic::of_builtin(def) {
+            // A built in function used as a value calls its intrinsic
+            let returns = intrinsic != Intrinsic::Panic;
+            self.forward(id, ty, &definition.span, returns, |args| {
+                InstKind::Intrinsic { intrinsic, args }
+            });
+        }
+        self.fn_ids.insert(def, id);
+        id
+    }
+
+    /// Gives a declared function of type `ty` one block, which passes its parameters to the
+    /// instruction `kind` makes from them, and returns its value if `returns`.
+    fn forward(
+        &mut self,
+        id: FuncId,
+        ty: &Ty,
+        span: &Span,
+        returns: bool,
+        kind: impl FnOnce(Vec<Value>) -> InstKind,
+    ) {
+        let Ty::Fn { params, ret } = ty else {
+            return;
+        };
+        let func = &mut self.fns[id.0];
+        func.values = params.iter().chain([&**ret]).cloned().collect();
+        let args: Vec<_> = (0..params.len()).map(Value).collect();
+        let value = Value(params.len());
+        func.blocks = vec![Block {
+            params: args.clone(),
+            insts: vec![Inst {
+                value,
+                kind: kind(args),
+                span: span.clone(),
+            }],
+            terminator: match returns {
+                true => Terminator::Return(value),
+                false => Terminator::Unreachable,
+            },
+        }];
+    }
+}
+
+/// Builds the blocks of one function.
+struct FnBuilder<'a, 'b> {
+    builder: &'b mut Builder<'a>,
+    /// The body the function comes from, which has its variables.
+    body: &'a hir::Body,
+    blocks: Vec<Block>,
+    values: Vec<Ty>,
+    /// The block that instructions are added to.
+    current: BlockId,
+    /// The blocks that jump to each block, once for each jump.
+    preds: Vec<Vec<BlockId>>,
+    sealed: Vec<bool>,
+    /// The value of each variable at the end of each block, for those the block knows.
+    defs: Vec<HashMap<LocalId, Value>>,
+    /// The parameters that blocks which aren't sealed have been given for variables, which are
+    /// passed values when the block is sealed.
+    incomplete: Vec<Vec<(LocalId, Value)>>,
+    /// The start and the exit of each loop that's being built, innermost last.
+    loops: Vec<(BlockId, BlockId)>,
+    vars: HashMap<LocalId, Var>,
+}
+
+impl<'a> FnBuilder<'a, '_> {
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(Block {
+            params: Vec::new(),
+            insts: Vec::new(),
+            terminator: Terminator::Unreachable,
+        });
+        self.preds.push(Vec::new());
+        self.sealed.push(false);
+        self.defs.push(HashMap::new());
+        self.incomplete.push(Vec::new());
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn new_value(&mut self, ty: Ty) -> Value {
+        self.values.push(ty);
+        Value(self.values.len() - 1)
+    }
+
+    /// Adds an instruction to the current block, returning the value it defines.
+    fn emit(&mut self, kind: InstKind, ty: Ty, span: &Span) -> Value {
+        let value = self.new_value(ty);
+        self.blocks[self.current.0].insts.push(Inst {
+            value,
+            kind,
+            span: span.clone(),
+        });
+        value
+    }
+
+    fn unit(&mut self, span: &Span) -> Value {
+        self.emit(InstKind::Const(Constant::Unit), Ty::unit(), span)
+    }
+
+    /// Ends the current block, and moves to a new block that nothing jumps to, which holds any
+    /// code that comes after it and can't be reached.
+    fn terminate(&mut self, terminator: Terminator) {
+        for target in terminator.targets() {
+            self.preds[target.block.0].push(self.current);
+        }
+        self.blocks[self.current.0].terminator = terminator;
+        self.current = self.new_block();
+        self.seal(self.current);
+    }
+
+    fn jump(&mut self, block: BlockId, args: Vec<Value>) {
+        self.terminate(Terminator::Jump(BlockCall { block, args }));
+    }
+
+    fn branch(&mut self, cond: Value, then_block: BlockId, else_block: BlockId) {
+        self.terminate(Terminator::Branch {
+            cond,
+            then_block: BlockCall {
+                block: then_block,
+                args: Vec::new(),
+            },
+            else_block: BlockCall {
+                block: else_block,
+                args: Vec::new(),
+            },
+        });
+    }
+
+    /// Makes `block` the current block.
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = block;
+    }
+
+    fn write_var(&mut self, var: LocalId, block: BlockId, value: Value) {
+        self.defs[block.0].insert(var, value);
+        let local = self.body.local(var);
+        if local.name.starts_with('$') {
+            return;
+        }
+        let var = self.vars.entry(var).or_insert_with(|| Var {
+            name: local.name.clone(),
+            span: local.span.clone(),
+            values: Vec::new(),
+        });
+        if !var.values.contains(&value) {
+            var.values.push(value);
+        }
+    }
+
+    fn read_var(&mut self, var: LocalId, block: BlockId) -> Value {
+        if let Some(&value) = self.defs[block.0].get(&var) {
+            return value;
+        }
+        let ty = self.body.local(var).ty.clone();
+        let value = if !self.sealed[block.0] {
+            let param = self.new_value(ty);
+            self.blocks[block.0].params.push(param);
+            self.incomplete[block.0].push((var, param));
+            param
+        } else if let [pred] = self.preds[block.0][..] {
+            self.read_var(var, pred)
+        } else if self.preds[block.0].is_empty() {
+            // Only a variable that's read before it's assigned gets here from a block that's
+            // reached, and the flow checker reports those
+            let value = self.new_value(ty);
+            let span = self.body.local(var).span.clone();
+            let inst = Inst {
+                value,
+                kind: InstKind::Undef,
+                span,
+            };
+            self.blocks[block.0].insts.insert(0, inst);
+            value
+        } else {
+            let param = self.new_value(ty);
+            self.blocks[block.0].params.push(param);
+            // The parameter is the variable's value while finding the values passed to it, in
+            // case the block jumps back to itself
+            self.write_var(var, block, param);
+            self.pass_args(var, block);
+            param
+        };
+        self.write_var(var, block, value);
+        value
+    }
+
+    /// Passes the value of `var` to the parameter that was just added to `block`, from each block
+    /// that jumps to it.
+    fn pass_args(&mut self, var: LocalId, block: BlockId) {
+        let mut seen = HashSet::new();
+        for pred in self.preds[block.0].clone() {
+            if !seen.insert(pred) {
+                continue;
+            }
+            let value = self.read_var(var, pred);
+            for target in self.blocks[pred.0].terminator.targets_mut() {
+                if target.block == block {
+                    target.args.push(value);
+                }
+            }
+        }
+    }
+
+    /// Marks that nothing else jumps to `block`.
+    fn seal(&mut self, block: BlockId) {
+        self.sealed[block.0] = true;
+        for (var, _) in std::mem::take(&mut self.incomplete[block.0]) {
+            self.pass_args(var, block);
+        }
+    }
+
+    fn block(&mut self, block: &'a hir::Block, span: &Span) -> Value {
+        for stmt in &block.stmts {
+            match stmt {
+                hir::Stmt::Let { pattern, value } => {
+                    if let Some(value) = value {
+                        let value = self.expr(value);
+                        self.pattern(pattern, value, None);
+                    }
+                }
+                hir::Stmt::Expr(expr) => {
+                    self.expr(expr);
+                }
+            }
+        }
+        match &block.tail {
+            Some(tail) => self.expr(tail),
+            None => self.unit(span),
+        }
+    }
+
+    fn expr(&mut self, expr: &'a hir::Expr) -> Value {
+        let span = &expr.span;
+        let ty = expr.ty.clone();
+        let value = match &expr.kind {
+            hir::ExprKind::Literal(literal) => {
+                let constant = match literal {
+                    Literal::Integer(value) => Constant::Int(*value as i128),
+                    Literal::Float(value) => Constant::Float(*value),
+                    Literal::String(value) => Constant::String(value.clone()),
+                    Literal::Char(value) => Constant::Char(*value),
+                    Literal::Bool(value) => Constant::Bool(*value),
+                    // Too large for any type, which the checker reports
+                    Literal::OversizedInteger(_) => return self.emit(InstKind::Undef, ty, span),
+                };
+                self.emit(InstKind::Const(constant), ty, span)
+            }
+            hir::ExprKind::Local(id) => self.read_var(*id, self.current),
+            hir::ExprKind::Fn(def) => {
+                let func = self.builder.func(*def, &expr.ty);
+                self.emit(InstKind::FnRef(func), ty, span)
+            }
+            hir::ExprKind::Global(def) => match self.builder.global_ids.get(def) {
+                Some(&global) => self.emit(InstKind::Global(global), ty, span),
+                None => self.emit(InstKind::Undef, ty, span),
+            },
+            hir::ExprKind::Tuple(elems) => {
+                let elems = elems.iter().map(|elem| self.expr(elem)).collect();
+                self.emit(InstKind::Tuple(elems), ty, span)
+            }
+            hir::ExprKind::Array(elems) => {
+                let elems = elems.iter().map(|elem| self.expr(elem)).collect();
+                self.emit(InstKind::Array(elems), ty, span)
+            }
+            hir::ExprKind::Construct { def, fields } => {
+                let fields = fields.iter().map(|field| self.expr(field)).collect();
+                self.emit(InstKind::Construct { def: *def, fields }, ty, span)
+            }
+            hir::ExprKind::Block(block) => self.block(block, span),
+            hir::ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                let cond = self.expr(cond);
+                let (then_block, else_block) = (self.new_block(), self.new_block());
+                self.branch(cond, then_block, else_block);
+                self.seal(then_block);
+                self.seal(else_block);
+
+                let (join, result) = self.join_block(&ty);
+                self.switch_to(then_block);
+                let value = self.expr(then_branch);
+                self.jump_to_join(join, result, value);
+                self.switch_to(else_block);
+                let value = match else_branch {
+                    Some(else_branch) => self.expr(else_branch),
+                    None => self.unit(span),
+                };
+                self.jump_to_join(join, result, value);
+                self.seal(join);
+                self.switch_to(join);
+                self.join_value(result, span)
+            }
+            hir::ExprKind::While { cond, body } => {
+                // The start of the loop isn't sealed until the body, which jumps back to it, is
+                // built
+                let header = self.new_block();
+                self.jump(header, Vec::new());
+                self.switch_to(header);
+                let cond = self.expr(cond);
+                let (body_block, exit) = (self.new_block(), self.new_block());
+                self.branch(cond, body_block, exit);
+                self.seal(body_block);
+
+                self.switch_to(body_block);
+                self.loops.push((header, exit));
+                self.block(body, span);
+                self.loops.pop();
+                self.jump(header, Vec::new());
+                self.seal(header);
+                self.seal(exit);
+                self.switch_to(exit);
+                self.unit(span)
+            }
+            hir::ExprKind::Match { scrutinee, arms } => {
+                let scrutinee = self.expr(scrutinee);
+                let (join, result) = self.join_block(&ty);
+                // Each arm is tried in turn, and a pattern that doesn't match moves to the next
+                for arm in arms {
+                    let next = self.new_block();
+                    self.pattern(&arm.pattern, scrutinee, Some(next));
+                    let value = self.expr(&arm.body);
+                    self.jump_to_join(join, result, value);
+                    self.seal(next);
+                    self.switch_to(next);
+                }
+                self.terminate(Terminator::Unreachable);
+                self.seal(join);
+                self.switch_to(join);
+                self.join_value(result, span)
+            }
+            hir::ExprKind::Closure { params, body } => {
+                let captures = free_locals(params, body);
+                let func = self.builder.declare("{closure}", None, ret_ty(&ty), span);
+                self.builder
+                    .define(func, self.body, &captures, params, body);
+                let captures = captures
+                    .into_iter()
+                    .map(|var| self.read_var(var, self.current))
+                    .collect();
+                self.emit(InstKind::Closure { func, captures }, ty, span)
+            }
+            hir::ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = start.as_ref().map(|start| self.expr(start));
+                let end = end.as_ref().map(|end| self.expr(end));
+                let kind = InstKind::Range {
+                    start,
+                    end,
+                    inclusive: *inclusive,
+                };
+                self.emit(kind, ty, span)
+            }
+            hir::ExprKind::Call { callee, args } => {
+                let callee = match callee.kind {
+                    hir::ExprKind::Fn(def) => match Intrinsic::of_builtin(def) {
+                        Some(intrinsic) => Err(intrinsic),
+                        None => Ok(Callee::Direct(self.builder.func(def, &callee.ty))),
+                    },
+                    _ => Ok(Callee::Indirect(self.expr(callee))),
+                };
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                let kind = match callee {
+                    Ok(callee) => InstKind::Call { callee, args },
+                    Err(intrinsic) => InstKind::Intrinsic { intrinsic, args },
+                };
+                self.emit(kind, ty, span)
+            }
+            hir::ExprKind::Intrinsic { intrinsic, args } => {
+                let intrinsic = match intrinsic {
+                    hir::Intrinsic::Len => Intrinsic::Len,
+                    hir::Intrinsic::Chars => Intrinsic::Chars,
+                    hir::Intrinsic::Find => Intrinsic::Find,
+                    hir::Intrinsic::EntryKey => Intrinsic::EntryKey,
+                    hir::Intrinsic::EntryValue => Intrinsic::EntryValue,
+                    hir::Intrinsic::Remove => Intrinsic::Remove,
+                    hir::Intrinsic::ReadFile => Intrinsic::ReadFile,
+                    hir::Intrinsic::WriteFile => Intrinsic::WriteFile,
+                    hir::Intrinsic::FileExists => Intrinsic::FileExists,
+                    hir::Intrinsic::FsError => Intrinsic::FsError,
+                    hir::Intrinsic::Next => Intrinsic::Next,
+                };
+                let args = args.iter().map(|arg| self.expr(arg)).collect();
+                self.emit(InstKind::Intrinsic { intrinsic, args }, ty, span)
+            }
+            hir::ExprKind::Field { base, index } => {
+                let base = self.expr(base);
+                self.emit(
+                    InstKind::Field {
+                        base,
+                        index: *index,
+                    },
+                    ty,
+                    span,
+                )
+            }
+            // Strings aren't arrays, so indexing one is an intrinsic
+            hir::ExprKind::Index { base, index } if base.ty == Ty::String => {
+                let intrinsic = match index.ty {
+                    Ty::Range(_) => Intrinsic::Slice,
+                    _ => Intrinsic::CharAt,
+                };
+                let args = vec![self.expr(base), self.expr(index)];
+                self.emit(InstKind::Intrinsic { intrinsic, args }, ty, span)
+            }
+            hir::ExprKind::Index { base, index } => {
+                let base = self.expr(base);
+                let index = self.expr(index);
+                self.emit(InstKind::Index { base, index }, ty, span)
+            }
+            hir::ExprKind::Cast(inner) => {
+                let inner = self.expr(inner);
+                self.emit(InstKind::Cast(inner), ty, span)
+            }
+            hir::ExprKind::Unary { op, expr } => {
+                let value = self.expr(expr);
+                self.emit(InstKind::Unary { op: *op, value }, ty, span)
+            }
+            hir::ExprKind::Binary { op, lhs, rhs } => {
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                self.emit(InstKind::Binary { op: *op, lhs, rhs }, ty, span)
+            }
+            hir::ExprKind::Assign { target, value } => {
+                let value = self.expr(value);
+                self.assign(target, value);
+                self.unit(span)
+            }
+            hir::ExprKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expr(value),
+                    None => self.unit(span),
+                };
+                self.terminate(Terminator::Return(value));
+                return self.emit(InstKind::Undef, ty, span);
+            }
+            hir::ExprKind::Yield(value) => {
+                let args = vec![self.expr(value)];
+                let kind = InstKind::Intrinsic {
+                    intrinsic: Intrinsic::Yield,
+                    args,
+                };
+                self.emit(kind, ty, span)
+            }
+            hir::ExprKind::Break | hir::ExprKind::Continue => {
+                let &(header, exit) = self.loops.last().unwrap();
+                let target = match expr.kind {
+                    hir::ExprKind::Break => exit,
+                    _ => header,
+                };
+                self.jump(target, Vec::new());
+                return self.emit(InstKind::Undef, ty, span);
+            }
+            hir::ExprKind::Error => self.emit(InstKind::Undef, ty, span),
+        };
+        if expr.ty == Ty::Never {
+            // Such as a call to `panic`, which doesn't return
+            self.terminate(Terminator::Unreachable);
+        }
+        value
+    }
+
+    /// Makes the block that the branches of an `if` or `match` jump to, which takes the value of
+    /// the branch that was taken unless the value is `()` or there isn't one.
+    fn join_block(&mut self, ty: &Ty) -> (BlockId, Option<Value>) {
+        let join = self.new_block();
+        if ty.is_unit() || *ty == Ty::Never {
+            return (join, None);
+        }
+        let param = self.new_value(ty.clone());
+        self.blocks[join.0].params.push(param);
+        (join, Some(param))
+    }
+
+    fn jump_to_join(&mut self, join: BlockId, result: Option<Value>, value: Value) {
+        let args = result.map(|_| value).into_iter().collect();
+        self.jump(join, args);
+    }
+
+    fn join_value(&mut self, result: Option<Value>, span: &Span) -> Value {
+        match result {
+            Some(result) => result,
+            None => self.unit(span),
+        }
+    }
+
+    /// Assigns `value` to a place. Assigning to a field or element assigns a copy of the whole
+    /// struct or array with that part replaced.
+    fn assign(&mut self, target: &'a hir::Expr, value: Value) {
+        let span = &target.span;
+        match &target.kind {
+            hir::ExprKind::Local(id) => self.write_var(*id, self.current, value),
+            hir::ExprKind::Global(def) => {
+                if let Some(&global) = self.builder.global_ids.get(def) {
+                    let kind = InstKind::SetGlobal { global, value };
+                    self.emit(kind, Ty::unit(), span);
+                }
+            }
+            hir::ExprKind::Field { base, index } => {
+                let whole = self.expr(base);
+                let kind = InstKind::SetField {
+                    base: whole,
+                    index: *index,
+                    value,
+                };
+                let updated = self.emit(kind, base.ty.clone(), span);
+                self.assign(base, updated);
+            }
+            hir::ExprKind::Index { base, index } => {
+                let whole = self.expr(base);
+                let index = self.expr(index);
+                let kind = InstKind::SetIndex {
+                    base: whole,
+                    index,
+                    value,
+                };
+                let updated = self.emit(kind, base.ty.clone(), span);
+                self.assign(base, updated);
+            }
+            // Only places are assigned to
+            _ => {}
+        }
+    }
+
+    /// Matches `value` against a pattern, assigning the variables it binds. When it doesn't
+    /// match, it jumps to `fail`, which is `None` for a pattern that always matches.
+    fn pattern(&mut self, pattern: &'a hir::Pattern, value: Value, fail: Option<BlockId>) {
+        let span = &pattern.span;
+        let ty = self.values[value.0].clone();
+        match &pattern.kind {
+            PatternKind::Wildcard => {}
+            PatternKind::Binding(id) => self.write_var(*id, self.current, value),
+            PatternKind::Literal(literal) => {
+                let constant = match literal {
+                    Literal::Integer(value) => Constant::Int(*value as i128),
+                    Literal::Float(value) => Constant::Float(*value),
+                    Literal::String(value) => Constant::String(value.clone()),
+                    Literal::Char(value) => Constant::Char(*value),
+                    Literal::Bool(value) => Constant::Bool(*value),
+                    Literal::OversizedInteger(_) => return,
+                };
+                let expected = self.emit(InstKind::Const(constant), ty, span);
+                self.check_eq(value, expected, fail, span);
+            }
+            PatternKind::Global(def) => {
+                if let Some(&global) = self.builder.global_ids.get(def) {
+                    let expected = self.emit(InstKind::Global(global), ty, span);
+                    self.check_eq(value, expected, fail, span);
+                }
+            }
+            PatternKind::Tuple(elems) => {
+                let tys = match &ty {
+                    Ty::Tuple(tys) => tys.clone(),
+                    _ => Vec::new(),
+                };
+                for (index, elem) in elems.iter().enumerate() {
+                    let elem_ty = tys.get(index).cloned().unwrap_or(Ty::Error);
+                    let kind = InstKind::Field { base: value, index };
+                    let elem_value = self.emit(kind, elem_ty, span);
+                    self.pattern(elem, elem_value, fail);
+                }
+            }
+            PatternKind::Variant { def, fields } => {
+                let hir = self.builder.hir;
+                let Some((adt, variant)) = hir.variant(*def) else {
+                    return;
+                };
+                if adt.is_enum {
+                    let int = Ty::Int(IntTy::I32);
+                    let found = self.emit(InstKind::Discriminant(value), int.clone(), span);
+                    let constant = InstKind::Const(Constant::Int(variant as i128));
+                    let expected = self.emit(constant, int, span);
+                    self.check_eq(found, expected, fail, span);
+                }
+                for (index, field) in fields.iter().enumerate() {
+                    let field_ty = hir.field_ty(&ty, *def, index);
+                    let kind = match adt.is_enum {
+                        true => InstKind::VariantField {
+                            base: value,
+                            def: *def,
+                            index,
+                        },
+                        false => InstKind::Field { base: value, index },
+                    };
+                    let field_value = self.emit(kind, field_ty, span);
+                    self.pattern(field, field_value, fail);
+                }
+            }
+        }
+    }
+
+    /// Moves on when `found` equals `expected`, and jumps to `fail` when it doesn't.
+    fn check_eq(&mut self, found: Value, expected: Value, fail: Option<BlockId>, span: &Span) {
+        let Some(fail) = fail else {
+            return;
+        };
+        let kind = InstKind::Binary {
+            op: BinaryOp::Eq,
+            lhs: found,
+            rhs: expected,
+        };
+        let eq = self.emit(kind, Ty::Bool, span);
+        let matched = self.new_block();
+        self.branch(eq, matched, fail);
+        self.seal(matched);
+        self.switch_to(matched);
+    }
+}
+
+/// Returns the variables that a closure uses from the function around it, which it captures.
+fn free_locals(params: &[LocalId], body: &hir::Expr) -> Vec<LocalId> {
+    let mut finder = FreeLocals {
+        used: BTreeSet::new(),
+        declared: params.iter().copied().collect(),
+    };
+    finder.expr(body);
+    finder
+        .used
+        .into_iter()
+        .filter(|local| !finder.declared.contains(local))
+        .collect()
+}
+
+struct FreeLocals {
+    used: BTreeSet<LocalId>,
+    declared: HashSet<LocalId>,
+}
+
+impl FreeLocals {
+    fn block(&mut self, block: &hir::Block) {
+        for stmt in &block.stmts {
+            match stmt {
+                hir::Stmt::Let { pattern, value } => {
+                    self.pattern(pattern);
+                    if let Some(value) = value {
+                        self.expr(value);
+                    }
+                }
+                hir::Stmt::Expr(expr) => self.expr(expr),
+            }
+        }
+        if let Some(tail) = &block.tail {
+            self.expr(tail);
+        }
+    }
+
+    fn pattern(&mut self, pattern: &hir::Pattern) {
+        match &pattern.kind {
+            PatternKind::Binding(id) => {
+                self.declared.insert(*id);
+            }
+            PatternKind::Tuple(patterns)
+            | PatternKind::Variant {
+                fields: patterns, ..
+            } => patterns.iter().for_each(|pattern| self.pattern(pattern)),
+            PatternKind::Wildcard | PatternKind::Literal(_) | PatternKind::Global(_) => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &hir::Expr) {
+        match &expr.kind {
+            hir::ExprKind::Local(id) => {
+                self.used.insert(*id);
+            }
+            hir::ExprKind::Tuple(exprs)
+            | hir::ExprKind::Array(exprs)
+            | hir::ExprKind::Construct { fields: exprs, .. }
+            | hir::ExprKind::Intrinsic { args: exprs, .. } => {
+                exprs.iter().for_each(|expr| self.expr(expr))
+            }
+            hir::ExprKind::Block(block) => self.block(block),
+            hir::ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(cond);
+                self.expr(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.expr(else_branch);
+                }
+            }
+            hir::ExprKind::While { cond, body } => {
+                self.expr(cond);
+                self.block(body);
+            }
+            hir::ExprKind::Match { scrutinee, arms } => {
+                self.expr(scrutinee);
+                for arm in arms {
+                    self.pattern(&arm.pattern);
+                    self.expr(&arm.body);
+                }
+            }
+            hir::ExprKind::Closure { params, body } => {
+                self.declared.extend(params);
+                self.expr(body);
+            }
+            hir::ExprKind::Range { start, end, .. } => {
+                start.iter().chain(end).for_each(|bound| self.expr(bound));
+            }
+            hir::ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                args.iter().for_each(|arg| self.expr(arg));
+            }
+            hir::ExprKind::Field { base: expr, .. }
+            | hir::ExprKind::Cast(expr)
+            | hir::ExprKind::Unary { expr, .. } => self.expr(expr),
+            hir::ExprKind::Index {
+                base: lhs,
+                index: rhs,
+            }
+            | hir::ExprKind::Binary { lhs, rhs, .. }
+            | hir::ExprKind::Assign {
+                target: lhs,
+                value: rhs,
+            } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            hir::ExprKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            hir::ExprKind::Yield(value) => self.expr(value),
+            hir::ExprKind::Literal(_)
+            | hir::ExprKind::Fn(_)
+            | hir::ExprKind::Global(_)
+            | hir::ExprKind::Break
+            | hir::ExprKind::Continue
+            | hir::ExprKind::Error => {}
+        }
+    }
+}
+
+/// Tidies a function that was just built: removes the blocks that can't be reached, the block
+/// parameters that are always passed the same value, and the instructions whose values aren't
+/// used, merges blocks that only follow each other, then numbers the values that are left in the
+/// order they're defined.
+fn cleanup(func: &mut Function) {
+    order_blocks(func);
+    remove_trivial_params(func);
+    remove_dead_values(func);
+    merge_blocks(func);
+    // The merged blocks are no longer jumped to
+    order_blocks(func);
+    renumber_values(func);
+}
+
+/// Removes the blocks that can't be reached, and puts the rest in reverse postorder.
+fn order_blocks(func: &mut Function) {
+    let order = Cfg::new(func).reverse_postorder();
+    let mut new_ids = vec![None; func.blocks.len()];
+    for (i, &block) in order.iter().enumerate() {
+        new_ids[block.0] = Some(BlockId(i));
+    }
+    let mut blocks: Vec<_> = std::mem::take(&mut func.blocks)
+        .into_iter()
+        .map(Some)
+        .collect();
+    func.blocks = order
+        .iter()
+        .map(|block| blocks[block.0].take().unwrap())
+        .collect();
+    for block in &mut func.blocks {
+        for target in block.terminator.targets_mut() {
+            target.block = new_ids[target.block.0].unwrap();
+        }
+    }
+}
+
+/// Replaces each parameter that's always passed the same value, other than itself, with that
+/// value.
+fn remove_trivial_params(func: &mut Function) {
+    loop {
+        let mut trivial = None;
+        'blocks: for block in func.block_ids().skip(1) {
+            for (index, &param) in func.block(block).params.iter().enumerate() {
+                let mut passed = HashSet::new();
+                for from in &func.blocks {
+                    for target in from.terminator.targets() {
+                        if target.block == block && target.args[index] != param {
+                            passed.insert(target.args[index]);
+                        }
+                    }
+                }
+                if passed.len() == 1 {
+                    trivial = Some((block, index, passed.into_iter().next().unwrap()));
+                    break 'blocks;
+                }
+            }
+        }
+        let Some((block, index, value)) = trivial else {
+            return;
+        };
+        let param = func.blocks[block.0].params.remove(index);
+        remove_args(func, block, index);
+        replace_uses(func, param, value);
+    }
+}
+
+/// Removes the values passed for a parameter of `block` that has been removed.
+fn remove_args(func: &mut Function, block: BlockId, index: usize) {
+    for from in &mut func.blocks {
+        for target in from.terminator.targets_mut() {
+            if target.block == block {
+                target.args.remove(index);
+            }
+        }
+    }
+}
+
+fn replace_uses(func: &mut Function, old: Value, new: Value) {
+    let replace = |value: &mut Value| {
+        if *value == old {
+            *value = new;
+        }
+    };
+    for block in &mut func.blocks {
+        for inst in &mut block.insts {
+            inst.kind.operands_mut(replace);
+        }
+        block.terminator.operands_mut(replace);
+    }
+    for var in &mut func.vars {
+        var.values.iter_mut().for_each(replace);
+    }
+}
+
+/// Removes the instructions without effects and the block parameters whose values aren't used,
+/// other than the function's parameters.
+fn remove_dead_values(func: &mut Function) {
+    enum Def {
+        Inst,
+        Param(BlockId, usize),
+    }
+    let mut defs = HashMap::new();
+    let mut operands = HashMap::new();
+    let mut live = HashSet::new();
+    let mut work = Vec::new();
+    for id in func.block_ids() {
+        let block = func.block(id);
+        for (index, &param) in block.params.iter().enumerate() {
+            defs.insert(param, Def::Param(id, index));
+        }
+        for inst in &block.insts {
+            defs.insert(inst.value, Def::Inst);
+            operands.insert(inst.value, inst.kind.operands());
+            if inst.kind.has_effects() {
+                work.push(inst.value);
+            }
+        }
+        match &block.terminator {
+            Terminator::Branch { cond: value, .. } | Terminator::Return(value) => work.push(*value),
+            Terminator::Jump(_) | Terminator::Unreachable => {}
+        }
+    }
+    work.extend(func.params().to_vec());
+
+    while let Some(value) = work.pop() {
+        if !live.insert(value) {
+            continue;
+        }
+        match defs.get(&value) {
+            Some(Def::Inst) => work.extend(&operands[&value]),
+            Some(&Def::Param(block, index)) => {
+                for from in &func.blocks {
+                    for target in from.terminator.targets() {
+                        if target.block == block {
+                            work.push(target.args[index]);
+                        }
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    for id in func.block_ids().skip(1) {
+        for index in (0..func.block(id).params.len()).rev() {
+            if !live.contains(&func.block(id).params[index]) {
+                func.blocks[id.0].params.remove(index);
+                remove_args(func, id, index);
+            }
+        }
+    }
+    for block in &mut func.blocks {
+        block.insts.retain(|inst| live.contains(&inst.value));
+    }
+}
+
+/// Moves each block without parameters that's only jumped to by one block, which jumps nowhere
+/// else, onto the end of that block.
+fn merge_blocks(func: &mut Function) {
+    let cfg = Cfg::new(func);
+    let mergeable: Vec<_> = func
+        .block_ids()
+        .map(|id| match *cfg.preds(id) {
+            [pred] if pred != id && func.block(id).params.is_empty() => {
+                matches!(&func.block(pred).terminator, Terminator::Jump(_)).then_some(pred)
+            }
+            _ => None,
+        })
+        .collect();
+    // The block each block has been moved onto the end of
+    let mut merged: Vec<_> = func.block_ids().collect();
+    for id in func.block_ids().skip(1) {
+        let Some(mut into) = mergeable[id.0] else {
+            continue;
+        };
+        while merged[into.0] != into {
+            into = merged[into.0];
+        }
+        let mut block = std::mem::replace(
+            &mut func.blocks[id.0],
+            Block {
+                params: Vec::new(),
+                insts: Vec::new(),
+                terminator: Terminator::Unreachable,
+            },
+        );
+        let into_block = &mut func.blocks[into.0];
+        into_block.insts.append(&mut block.insts);
+        into_block.terminator = block.terminator;
+        merged[id.0] = into;
+    }
+}
+
+/// Numbers the values in the order they're defined, dropping those that are no longer defined.
+fn renumber_values(func: &mut Function) {
+    let mut new_ids = HashMap::new();
+    let mut values = Vec::new();
+    let mut define = |value: &mut Value| {
+        new_ids.insert(*value, Value(values.len()));
+        values.push(func.values[value.0].clone());
+        *value = Value(values.len() - 1);
+    };
+    for block in &mut func.blocks {
+        block.params.iter_mut().for_each(&mut define);
+        block
+            .insts
+            .iter_mut()
+            .for_each(|inst| define(&mut inst.value));
+    }
+    let rename = |value: &mut Value| *value = new_ids[value];
+    for block in &mut func.blocks {
+        for inst in &mut block.insts {
+            inst.kind.operands_mut(rename);
+        }
+        block.terminator.operands_mut(rename);
+    }
+    // A variable only keeps the values that are still used
+    for var in &mut func.vars {
+        var.values = (var.values.iter())
+            .filter_map(|value| new_ids.get(value).copied())
+            .collect();
+        var.values.dedup();
+    }
+    func.vars.retain(|var| !var.values.is_empty());
+    func.values = values;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::{built, print_program};
+
+    /// Builds a program without errors, checks that it's valid and prints it.
+    fn printed(source: &str) -> String {
+        let mir = built(source);
+        for func in &mir.fns {
+            assert_eq!(verify(func), Ok(()));
+        }
+        print_program(&mir)
+    }
+
+    #[test]
+    fn test_build_loops() {
+        let source = "
+fn sum(n: int) int {
+    let mut total = 0;
+    let mut i = 0;
+    while i < n {
+        if i == 3 { break; }
+        total += i;
+        i += 1;
+    }
+    total
+}";
+        assert_eq!(
+            printed(source),
+            "fn0 sum i32 {
+bb0(v0: i32):
+    v1: i32 = const 0
+    v2: i32 = const 0
+    jump bb1(v2, v1)
+bb1(v3: i32, v4: i32):
+    v5: bool = v3 < v0
+    branch v5, bb2, bb5
+bb2:
+    v6: i32 = const 3
+    v7: bool = v3 == v6
+    branch v7, bb3, bb4
+bb3:
+    jump bb5
+bb4:
+    v8: i32 = v4 + v3
+    v9: i32 = const 1
+    v10: i32 = v3 + v9
+    jump bb1(v10, v8)
+bb5:
+    return v4
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_build_match() {
+        let source = "
+enum Shape { Circle(float), Rect { w: float, h: float } }
+fn area(shape: Shape) float {
+    match shape {
+        Shape::Circle(r) => r * r * 3.0,
+        Shape::Rect { w, h } => w * h,
+    }
+}
+fn first(x: Option<int>) Option<int> {
+    Some(x? + 1)
+}";
+        assert_eq!(
+            printed(source),
+            "fn0 area float {
+bb0(v0: Shape):
+    v1: i32 = discriminant v0
+    v2: i32 = const 0
+    v3: bool = v1 == v2
+    branch v3, bb1, bb2
+bb1:
+    v4: float = (v0 as Circle).0
+    v5: float = v4 * v4
+    v6: float = const 3.0
+    v7: float = v5 * v6
+    jump bb4(v7)
+bb2:
+    v8: i32 = discriminant v0
+    v9: i32 = const 1
+    v10: bool = v8 == v9
+    branch v10, bb3, bb5
+bb3:
+    v11: float = (v0 as Rect).0
+    v12: float = (v0 as Rect).1
+    v13: float = v11 * v12
+    jump bb4(v13)
+bb4(v14: float):
+    return v14
+bb5:
+    unreachable
+}
+
+fn1 first Option<i32> {
+bb0(v0: Option<i32>):
+    v1: i32 = discriminant v0
+    v2: i32 = const 0
+    v3: bool = v1 == v2
+    branch v3, bb1, bb2
+bb1:
+    v4: i32 = (v0 as Some).0
+    v5: i32 = const 1
+    v6: i32 = v4 + v5
+    v7: Option<i32> = Some(v6)
+    return v7
+bb2:
+    v8: i32 = discriminant v0
+    v9: i32 = const 1
+    v10: bool = v8 == v9
+    branch v10, bb3, bb4
+bb3:
+    v11: Option<i32> = None
+    return v11
+bb4:
+    unreachable
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_build_closures_and_places() {
+        let source = "
+struct Point { x: int, y: int }
+static COUNT: int = 0;
+fn f(mut p: Point, mut xs: [int], k: int) Point {
+    let add = |n: int| n + k;
+    p.x = add(p.y);
+    xs[0] = p.x;
+    COUNT = COUNT + 1;
+    if k > 0 { panic(\"positive\"); }
+    p
+}";
+        assert_eq!(
+            printed(source),
+            "static g0 COUNT: i32 = fn2
+
+fn0 f Point {
+bb0(v0: Point, v1: [i32], v2: i32):
+    v3: fn(i32) -> i32 = closure fn1(v2)
+    v4: i32 = v0.1
+    v5: i32 = call v3(v4)
+    v6: Point = v0 with .0 = v5
+    v7: i32 = v6.0
+    v8: i32 = const 0
+    v9: [i32] = v1 with [v8] = v7
+    v10: i32 = g0
+    v11: i32 = const 1
+    v12: i32 = v10 + v11
+    v13: () = g0 = v12
+    v14: i32 = const 0
+    v15: bool = v2 > v14
+    branch v15, bb1, bb2
+bb1:
+    v16: string = const \"positive\"
+    v17: ! = @panic(v16)
+    unreachable
+bb2:
+    return v6
+}
+
+fn1 {closure} i32 {
+bb0(v0: i32, v1: i32):
+    v2: i32 = v1 + v0
+    return v2
+}
+
+fn2 COUNT i32 {
+bb0:
+    v0: i32 = const 0
+    return v0
+}
+"
+        );
+    }
+}
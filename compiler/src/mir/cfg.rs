@@ -0,0 +1,341 @@
+//! The control-flow graph of a function, which has an edge from each block to each block it can
+//! jump to, and printing it in Graphviz's dot format.
+
+use super::{print::block_lines, *};
+
+/// The blocks that each block of a function jumps to and is jumped to from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    succs: Vec<Vec<BlockId>>,
+    preds: Vec<Vec<BlockId>>,
+}
+
+impl Cfg {
+    pub fn new(func: &Function) -> Cfg {
+        let mut succs = vec![Vec::new(); func.blocks.len()];
+        let mut preds = vec![Vec::new(); func.blocks.len()];
+        for id in func.block_ids() {
+            for target in func.block(id).terminator.targets() {
+                succs[id.0].push(target.block);
+                preds[target.block.0].push(id);
+            }
+        }
+        Cfg { succs, preds }
+    }
+
+    /// Returns the blocks that `block` jumps to, once for each jump, in the order they're written
+    /// in its terminator.
+    pub fn succs(&self, block: BlockId) -> &[BlockId] {
+        &self.succs[block.0]
+    }
+
+    /// Returns the blocks that jump to `block`, once for each jump.
+    pub fn preds(&self, block: BlockId) -> &[BlockId] {
+        &self.preds[block.0]
+    }
+
+    /// Returns the blocks that can be reached from the entry in reverse postorder, where each
+    /// block comes after those that jump to it, other than those that jump back to the start of a
+    /// loop. The first target of a branch comes before the second.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.succs.len()];
+        visited[BlockId::ENTRY.0] = true;
+        let mut postorder = Vec::new();
+        let mut stack = vec![(BlockId::ENTRY, 0)];
+        while let Some(top) = stack.last_mut() {
+            let (block, next) = *top;
+            // Going through the targets backwards puts the first one first in the end
+            match self.succs(block).iter().rev().nth(next) {
+                Some(&target) => {
+                    top.1 += 1;
+                    if !std::mem::replace(&mut visited[target.0], true) {
+                        stack.push((target, 0));
+                    }
+                }
+                None => {
+                    postorder.push(block);
+                    stack.pop();
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+}
+
+/// A natural loop: a header, which dominates every block of the loop, and the blocks that can get
+/// back to it without leaving the loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub header: BlockId,
+    /// The blocks of the loop, including the header, in order.
+    pub blocks: Vec<BlockId>,
+}
+
+impl Loop {
+    pub fn contains(&self, block: BlockId) -> bool {
+        self.blocks.binary_search(&block).is_ok()
+    }
+}
+
+impl Cfg {
+    /// Returns the loops of a function, found from the jumps back to a block that dominates the
+    /// block they're from. A loop inside another comes before it.
+    pub fn loops(&self, dominators: &Dominators) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = Vec::new();
+        for block in (0..self.succs.len()).map(BlockId) {
+            if !dominators.is_reachable(block) {
+                continue;
+            }
+            for &header in self.succs(block) {
+                if !dominators.dominates(header, block) {
+                    continue;
+                }
+                // The blocks that get to the jump back without going through the header
+                let mut blocks = vec![header];
+                let mut stack = vec![block];
+                while let Some(block) = stack.pop() {
+                    if !blocks.contains(&block) {
+                        blocks.push(block);
+                        let preds = self.preds(block).iter();
+                        stack.extend(preds.filter(|&&pred| dominators.is_reachable(pred)));
+                    }
+                }
+                match loops.iter_mut().find(|l| l.header == header) {
+                    Some(existing) => existing.blocks.extend(blocks),
+                    None => loops.push(Loop { header, blocks }),
+                }
+            }
+        }
+        for l in &mut loops {
+            l.blocks.sort();
+            l.blocks.dedup();
+        }
+        loops.sort_by_key(|l| l.blocks.len());
+        loops
+    }
+}
+
+/// The dominator tree of a function. A block dominates another when every path from the entry to
+/// the other goes through it, and its immediate dominator is the closest block that dominates it
+/// other than itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dominators {
+    /// The immediate dominator of each block, which is the entry for the entry itself, and `None`
+    /// for the blocks that can't be reached.
+    idoms: Vec<Option<BlockId>>,
+    children: Vec<Vec<BlockId>>,
+}
+
+impl Dominators {
+    /// Finds the dominators with the algorithm from "A Simple, Fast Dominance Algorithm" by
+    /// Cooper, Harvey and Kennedy.
+    pub fn new(cfg: &Cfg) -> Dominators {
+        let order = cfg.reverse_postorder();
+        let mut position = vec![usize::MAX; cfg.succs.len()];
+        for (i, block) in order.iter().enumerate() {
+            position[block.0] = i;
+        }
+        let mut idoms = vec![None; cfg.succs.len()];
+        idoms[BlockId::ENTRY.0] = Some(BlockId::ENTRY);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order[1..] {
+                let mut idom = None;
+                for &pred in cfg.preds(block) {
+                    if idoms[pred.0].is_none() {
+                        continue;
+                    }
+                    idom = Some(match idom {
+                        None => pred,
+                        Some(other) => intersect(&idoms, &position, pred, other),
+                    });
+                }
+                if idoms[block.0] != idom {
+                    idoms[block.0] = idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); cfg.succs.len()];
+        for &block in &order[1..] {
+            children[idoms[block.0].unwrap().0].push(block);
+        }
+        Dominators { idoms, children }
+    }
+
+    /// Returns the immediate dominator of a block, which the entry doesn't have.
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idoms[block.0].filter(|_| block != BlockId::ENTRY)
+    }
+
+    /// Returns the blocks that `block` is the immediate dominator of, its children in the tree.
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block.0]
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.idoms[block.0].is_some()
+    }
+
+    /// Returns whether every path from the entry to `block` goes through `dominator`, which is
+    /// true when they're the same block.
+    pub fn dominates(&self, dominator: BlockId, block: BlockId) -> bool {
+        let mut current = Some(block);
+        while let Some(block) = current {
+            if block == dominator {
+                return true;
+            }
+            current = self.idom(block);
+        }
+        false
+    }
+
+    /// Returns the blocks that can be reached in preorder of the tree, where each block comes
+    /// after the blocks that dominate it.
+    pub fn preorder(&self) -> Vec<BlockId> {
+        let mut preorder = Vec::new();
+        let mut stack = vec![BlockId::ENTRY];
+        while let Some(block) = stack.pop() {
+            preorder.push(block);
+            stack.extend(self.children(block).iter().rev());
+        }
+        preorder
+    }
+}
+
+/// Returns the closest block that dominates both `a` and `b`, going up the tree from whichever is
+/// later in reverse postorder.
+fn intersect(
+    idoms: &[Option<BlockId>],
+    position: &[usize],
+    mut a: BlockId,
+    mut b: BlockId,
+) -> BlockId {
+    while a != b {
+        while position[a.0] > position[b.0] {
+            a = idoms[a.0].unwrap();
+        }
+        while position[b.0] > position[a.0] {
+            b = idoms[b.0].unwrap();
+        }
+    }
+    a
+}
+
+/// Prints the control-flow graph of every function with blocks as a Graphviz graph, with a
+/// cluster for each function and a node for each block, showing its instructions.
+pub fn print_dot(program: &Program) -> String {
+    let mut out = String::from("digraph mir {\n");
+    out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+    for (i, func) in program.fns.iter().enumerate() {
+        if func.blocks.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("    subgraph cluster_fn{} {{\n", i));
+        out.push_str(&format!(
+            "        label=\"fn{} {}\";\n",
+            i,
+            escape(&func.name)
+        ));
+        for id in func.block_ids() {
+            // `\l` ends a line that's aligned to the left
+            let label: String = block_lines(program, func, id)
+                .iter()
+                .map(|line| format!("{}\\l", escape(line)))
+                .collect();
+            out.push_str(&format!(
+                "        fn{}_bb{} [label=\"{}\"];\n",
+                i, id.0, label
+            ));
+        }
+        for id in func.block_ids() {
+            let edges = match &func.block(id).terminator {
+                Terminator::Branch {
+                    then_block,
+                    else_block,
+                    ..
+                } => vec![
+                    (then_block.block, " [label=\"true\"]"),
+                    (else_block.block, " [label=\"false\"]"),
+                ],
+                terminator => terminator
+                    .targets()
+                    .iter()
+                    .map(|target| (target.block, ""))
+                    .collect(),
+            };
+            for (target, attrs) in edges {
+                out.push_str(&format!(
+                    "        fn{}_bb{} -> fn{}_bb{}{};\n",
+                    i, id.0, i, target.0, attrs
+                ));
+            }
+        }
+        out.push_str("    }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Escapes text for a string in the dot format.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::built;
+
+    #[test]
+    fn test_cfg() {
+        let source = "
+fn count(n: int) int {
+    let mut i = 0;
+    while i < n { i += 1; }
+    i
+}";
+        let program = built(source);
+        let cfg = Cfg::new(&program.fns[0]);
+        let blocks: Vec<_> = (0..4).map(BlockId).collect();
+        assert_eq!(cfg.succs(blocks[0]), &[blocks[1]]);
+        assert_eq!(cfg.succs(blocks[1]), &[blocks[2], blocks[3]]);
+        assert_eq!(cfg.preds(blocks[1]), &[blocks[0], blocks[2]]);
+        assert_eq!(cfg.preds(blocks[3]), &[blocks[1]]);
+        assert_eq!(cfg.reverse_postorder(), blocks);
+
+        let dominators = Dominators::new(&cfg);
+        assert_eq!(dominators.idom(blocks[0]), None);
+        assert_eq!(dominators.idom(blocks[2]), Some(blocks[1]));
+        assert_eq!(dominators.idom(blocks[3]), Some(blocks[1]));
+        assert!(dominators.dominates(blocks[1], blocks[3]));
+        assert!(!dominators.dominates(blocks[2], blocks[3]));
+        let loops = vec![Loop {
+            header: blocks[1],
+            blocks: vec![blocks[1], blocks[2]],
+        }];
+        assert_eq!(cfg.loops(&dominators), loops);
+
+        assert_eq!(
+            print_dot(&program),
+            "digraph mir {
+    node [shape=box, fontname=\"monospace\"];
+    subgraph cluster_fn0 {
+        label=\"fn0 count\";
+        fn0_bb0 [label=\"bb0(v0: i32):\\l    v1: i32 = const 0\\l    jump bb1(v1)\\l\"];
+        fn0_bb1 [label=\"bb1(v2: i32):\\l    v3: bool = v2 < v0\\l    branch v3, bb2, bb3\\l\"];
+        fn0_bb2 [label=\"bb2:\\l    v4: i32 = const 1\\l    v5: i32 = v2 + v4\\l    jump bb1(v5)\\l\"];
+        fn0_bb3 [label=\"bb3:\\l    return v2\\l\"];
+        fn0_bb0 -> fn0_bb1;
+        fn0_bb1 -> fn0_bb2 [label=\"true\"];
+        fn0_bb1 -> fn0_bb3 [label=\"false\"];
+        fn0_bb2 -> fn0_bb1;
+    }
+}
+"
+        );
+    }
+}
@@ -0,0 +1,748 @@
+//! The mid-level IR, where each function is a graph of basic blocks in SSA form. It's built from
+//! the HIR, and it's what optimizations and backends work on.
+//!
+//! Every [`Value`] is defined once, either by an instruction or as a parameter of a block.
+//! Instead of phi nodes, a block that's reached from more than one place takes parameters, and
+//! the jumps to it pass their values. Variables of the program are gone: assigning to one makes a
+//! new value, and aggregates are values too, so assigning to a field or element makes a copy of
+//! the aggregate with that part replaced. Closures capture the values of the variables they use
+//! when they're created.
+
+mod build;
+pub mod cfg;
+pub mod opt;
+mod print;
+
+use std::fmt::Display;
+
+use crate::{
+    ast::{BinaryOp, UnaryOp},
+    hir::Adt,
+    lexer::Span,
+    resolve::DefId,
+    typeck::Ty,
+};
+
+pub use build::build;
+pub use cfg::{print_dot, Cfg, Dominators, Loop};
+pub use print::print_program;
+
+/// Identifies a function in a [`Program`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FuncId(pub usize);
+
+/// Identifies a global in a [`Program`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GlobalId(pub usize);
+
+/// Identifies a block in a [`Function`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+impl BlockId {
+    /// The block a function starts in, whose parameters are the function's parameters.
+    pub const ENTRY: BlockId = BlockId(0);
+}
+
+/// Identifies a value in a [`Function`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// The structs and enums, which [`InstKind::Construct`] and friends refer to by [`DefId`].
+    pub adts: Vec<Adt>,
+    pub fns: Vec<Function>,
+    pub globals: Vec<Global>,
+}
+
+impl Program {
+    pub fn func(&self, id: FuncId) -> &Function {
+        &self.fns[id.0]
+    }
+
+    pub fn global(&self, id: GlobalId) -> &Global {
+        &self.globals[id.0]
+    }
+
+    /// Returns the host functions that [`InstKind::Extern`] calls, with the types of their
+    /// parameters and of what they return, in the order they're first called.
+    pub fn externs(&self) -> Vec<(&str, Vec<Ty>, Ty)> {
+        let mut externs: Vec<(&str, Vec<Ty>, Ty)> = Vec::new();
+        for func in &self.fns {
+            for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+                let InstKind::Extern { name, args } = &inst.kind else {
+                    continue;
+                };
+                if !externs.iter().any(|(other, ..)| other == name) {
+                    let params = args.iter().map(|&arg| func.value_ty(arg).clone());
+                    let ret = func.value_ty(inst.value).clone();
+                    externs.push((name, params.collect(), ret));
+                }
+            }
+        }
+        externs
+    }
+
+    /// Returns the struct or enum a struct or variant belongs to, and the index of the variant,
+    /// which is what [`InstKind::Discriminant`] gives for a value of that variant.
+    pub fn variant(&self, def: DefId) -> Option<(&Adt, usize)> {
+        self.adts.iter().find_map(|adt| {
+            let index = adt.variants.iter().position(|variant| variant.def == def)?;
+            Some((adt, index))
+        })
+    }
+}
+
+/// A `const` or `static`, whose value is computed by calling `init` before the program starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub def: DefId,
+    pub name: String,
+    pub ty: Ty,
+    pub is_static: bool,
+    pub init: FuncId,
+}
+
+/// A function, a closure, or the initializer of a global. A function without blocks is only
+/// declared, such as a method that a trait requires, which the trait's implementations define.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    /// The function's definition, `None` for closures and initializers.
+    pub def: Option<DefId>,
+    pub ret: Ty,
+    pub blocks: Vec<Block>,
+    /// The type of every value.
+    pub values: Vec<Ty>,
+    /// The variables of the source, for debug info.
+    pub vars: Vec<Var>,
+    pub span: Span,
+}
+
+/// A variable of the source, with the values that it holds at some point. Temporaries that lowering
+/// adds aren't variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: String,
+    pub span: Span,
+    pub values: Vec<Value>,
+}
+
+impl Function {
+    pub fn block(&self, id: BlockId) -> &Block {
+        &self.blocks[id.0]
+    }
+
+    pub fn params(&self) -> &[Value] {
+        self.blocks
+            .first()
+            .map_or(&[], |entry| entry.params.as_slice())
+    }
+
+    pub fn value_ty(&self, value: Value) -> &Ty {
+        &self.values[value.0]
+    }
+
+    pub fn block_ids(&self) -> impl Iterator<Item = BlockId> {
+        (0..self.blocks.len()).map(BlockId)
+    }
+}
+
+/// A straight line of instructions, ending in a terminator that leaves the block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub params: Vec<Value>,
+    pub insts: Vec<Inst>,
+    pub terminator: Terminator,
+}
+
+impl Block {
+    /// Returns the callee and arguments of the block's last instruction when it's a call whose
+    /// value the block returns, so that the call can reuse the caller's frame.
+    pub fn tail_call(&self) -> Option<(Callee, &[Value])> {
+        let inst = self.insts.last()?;
+        match (&inst.kind, &self.terminator) {
+            (InstKind::Call { callee, args }, Terminator::Return(value))
+                if *value == inst.value =>
+            {
+                Some((*callee, args))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An instruction, which defines `value`. Instructions done for their effect define a value of
+/// the unit type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inst {
+    pub value: Value,
+    pub kind: InstKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InstKind {
+    Const(Constant),
+    /// A value that's never used, such as a variable read before it's assigned on a path that
+    /// can't happen.
+    Undef,
+    Unary {
+        op: UnaryOp,
+        value: Value,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Value,
+        rhs: Value,
+    },
+    /// Converts a value to the type of the instruction's value.
+    Cast(Value),
+    Tuple(Vec<Value>),
+    Array(Vec<Value>),
+    /// A struct, or a variant of an enum, with its fields in the order they're declared.
+    Construct {
+        def: DefId,
+        fields: Vec<Value>,
+    },
+    /// A field of a struct, an element of a tuple, or a bound of a range.
+    Field {
+        base: Value,
+        index: usize,
+    },
+    /// A copy of a struct or tuple with one field replaced.
+    SetField {
+        base: Value,
+        index: usize,
+        value: Value,
+    },
+    /// The index of the variant of an enum value, as an `i32`.
+    Discriminant(Value),
+    /// A field of an enum value, which has to be the variant `def`.
+    VariantField {
+        base: Value,
+        def: DefId,
+        index: usize,
+    },
+    /// An element of an array, failing when the index is out of bounds.
+    Index {
+        base: Value,
+        index: Value,
+    },
+    /// A copy of an array with one element replaced, failing when the index is out of bounds.
+    SetIndex {
+        base: Value,
+        index: Value,
+        value: Value,
+    },
+    Range {
+        start: Option<Value>,
+        end: Option<Value>,
+        inclusive: bool,
+    },
+    /// The value of a global.
+    Global(GlobalId),
+    /// Assigns to a `static`.
+    SetGlobal {
+        global: GlobalId,
+        value: Value,
+    },
+    /// A function, as a value that can be called.
+    FnRef(FuncId),
+    /// A closure, whose function takes the captured values before its own parameters.
+    Closure {
+        func: FuncId,
+        captures: Vec<Value>,
+    },
+    /// A generator that hasn't started yet, which runs `func` with the arguments when it's first
+    /// resumed. Each one is a new generator, with its own place in `func`.
+    Generator {
+        func: FuncId,
+        args: Vec<Value>,
+    },
+    Call {
+        callee: Callee,
+        args: Vec<Value>,
+    },
+    Intrinsic {
+        intrinsic: Intrinsic,
+        args: Vec<Value>,
+    },
+    /// A call of the host's function with the name of an `extern fn`. The arguments are integers,
+    /// floats, `bool`s and `char`s, and so is the value, unless it's the unit value.
+    Extern {
+        name: String,
+        args: Vec<Value>,
+    },
+}
+
+impl InstKind {
+    /// Returns the values the instruction uses.
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            InstKind::Const(_) | InstKind::Undef | InstKind::Global(_) | InstKind::FnRef(_) => {
+                Vec::new()
+            }
+            InstKind::Unary { value, .. }
+            | InstKind::Cast(value)
+            | InstKind::Discriminant(value)
+            | InstKind::Field { base: value, .. }
+            | InstKind::VariantField { base: value, .. }
+            | InstKind::SetGlobal { value, .. } => vec![*value],
+            InstKind::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+            InstKind::Index { base, index } => vec![*base, *index],
+            InstKind::SetField { base, value, .. } => vec![*base, *value],
+            InstKind::SetIndex { base, index, value } => vec![*base, *index, *value],
+            InstKind::Tuple(values)
+            | InstKind::Array(values)
+            | InstKind::Construct { fields: values, .. }
+            | InstKind::Closure {
+                captures: values, ..
+            }
+            | InstKind::Generator { args: values, .. }
+            | InstKind::Intrinsic { args: values, .. }
+            | InstKind::Extern { args: values, .. } => values.clone(),
+            InstKind::Range { start, end, .. } => start.iter().chain(end).copied().collect(),
+            InstKind::Call { callee, args } => {
+                let callee = match callee {
+                    Callee::Direct(_) => None,
+                    Callee::Indirect(value) => Some(*value),
+                };
+                callee.into_iter().chain(args.iter().copied()).collect()
+            }
+        }
+    }
+
+    /// Calls `f` on each value the instruction uses, so that it can change them.
+    pub fn operands_mut(&mut self, mut f: impl FnMut(&mut Value)) {
+        match self {
+            InstKind::Const(_) | InstKind::Undef | InstKind::Global(_) | InstKind::FnRef(_) => {}
+            InstKind::Unary { value, .. }
+            | InstKind::Cast(value)
+            | InstKind::Discriminant(value)
+            | InstKind::Field { base: value, .. }
+            | InstKind::VariantField { base: value, .. }
+            | InstKind::SetGlobal { value, .. } => f(value),
+            InstKind::Binary { lhs, rhs, .. } => {
+                f(lhs);
+                f(rhs);
+            }
+            InstKind::Index { base, index } => {
+                f(base);
+                f(index);
+            }
+            InstKind::SetField { base, value, .. } => {
+                f(base);
+                f(value);
+            }
+            InstKind::SetIndex { base, index, value } => {
+                f(base);
+                f(index);
+                f(value);
+            }
+            InstKind::Tuple(values)
+            | InstKind::Array(values)
+            | InstKind::Construct { fields: values, .. }
+            | InstKind::Closure {
+                captures: values, ..
+            }
+            | InstKind::Generator { args: values, .. }
+            | InstKind::Intrinsic { args: values, .. }
+            | InstKind::Extern { args: values, .. } => values.iter_mut().for_each(f),
+            InstKind::Range { start, end, .. } => {
+                start.iter_mut().chain(end).for_each(f);
+            }
+            InstKind::Call { callee, args } => {
+                if let Callee::Indirect(value) = callee {
+                    f(value);
+                }
+                args.iter_mut().for_each(f);
+            }
+        }
+    }
+
+    /// Returns whether the instruction does anything besides computing its value, including
+    /// failing at run time, so that it can't be removed when its value isn't used.
+    pub fn has_effects(&self) -> bool {
+        match self {
+            // Integer arithmetic fails when it overflows
+            InstKind::Unary { .. }
+            | InstKind::Binary { .. }
+            | InstKind::Cast(_)
+            | InstKind::Index { .. }
+            | InstKind::SetIndex { .. }
+            | InstKind::SetGlobal { .. }
+            | InstKind::Call { .. }
+            | InstKind::Generator { .. }
+            | InstKind::Extern { .. } => true,
+            InstKind::Intrinsic { intrinsic, .. } => intrinsic.has_effects(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Constant {
+    /// An integer, of the integer type of the instruction's value.
+    Int(i128),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    String(String),
+    Unit,
+}
+
+// Floats are the same constant when their bits are, so that a NaN is equal to itself and `0.0`
+// isn't equal to `-0.0`
+impl PartialEq for Constant {
+    fn eq(&self, other: &Constant) -> bool {
+        match (self, other) {
+            (Constant::Int(a), Constant::Int(b)) => a == b,
+            (Constant::Float(a), Constant::Float(b)) => a.to_bits() == b.to_bits(),
+            (Constant::Bool(a), Constant::Bool(b)) => a == b,
+            (Constant::Char(a), Constant::Char(b)) => a == b,
+            (Constant::String(a), Constant::String(b)) => a == b,
+            (Constant::Unit, Constant::Unit) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Constant {}
+
+impl std::hash::Hash for Constant {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Constant::Int(value) => value.hash(state),
+            Constant::Float(value) => value.to_bits().hash(state),
+            Constant::Bool(value) => value.hash(state),
+            Constant::Char(value) => value.hash(state),
+            Constant::String(value) => value.hash(state),
+            Constant::Unit => {}
+        }
+    }
+}
+
+/// The function that a call calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Callee {
+    Direct(FuncId),
+    /// A function or closure value.
+    Indirect(Value),
+}
+
+/// An operation the language provides that can't be written in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Intrinsic {
+    /// `len(array)`, the number of elements in an array, or of entries in a map.
+    Len,
+    /// `chars(string)`, the characters of a string as an array.
+    Chars,
+    /// `panic(message)`, stopping the program.
+    Panic,
+    /// `print(text)`, writing to the standard output.
+    Print,
+    /// `println(text)`, writing a line to the standard output.
+    Println,
+    /// `input()`, reading a line from the standard input.
+    Input,
+    /// `string.len()`, the number of characters in a string.
+    StringLen,
+    /// `string[index]`, a character of a string, failing when the index is out of bounds.
+    CharAt,
+    /// `string[range]`, the characters of a string in a range, failing when it's out of bounds.
+    Slice,
+    /// `string.contains(pattern)`
+    Contains,
+    /// `string.split(separator)`, the parts of a string between its separators, failing when the
+    /// separator is empty.
+    Split,
+    /// `string.to_upper()` and `string.to_lower()`, a string with its ASCII letters changed.
+    ToUpper,
+    ToLower,
+    /// `push(array, value)`, a copy of an array with a value added to its end.
+    Push,
+    /// `pop(array)`, a copy of an array without its last element, if it has one.
+    Pop,
+    /// `new_map()`, an empty map.
+    NewMap,
+    /// `map_insert(map, key, value)`, a copy of a map with the value of a key added or replaced.
+    Insert,
+    /// `map_find(map, key)`, the index of the map's entry with the key, or -1 when it has none.
+    Find,
+    /// `map_key(map, index)` and `map_value(map, index)`, the key and the value of an entry of a map,
+    /// failing when the index is out of bounds.
+    EntryKey,
+    EntryValue,
+    /// `map_remove(map, index)`, a copy of a map without one of its entries, failing when the index
+    /// is out of bounds.
+    Remove,
+    /// `read_file(path)`, `write_file(path, contents)`, `file_exists(path)` and `fs_error()`, which
+    /// the functions of the `fs` module call.
+    ReadFile,
+    WriteFile,
+    FileExists,
+    FsError,
+    /// `sqrt(x)`, `floor(x)`, `ceil(x)` and `pow(x, y)` of floats, from the `math` module.
+    Sqrt,
+    Floor,
+    Ceil,
+    Pow,
+    /// `yield(value)`, handing a value to the code that resumed the generator running it, and
+    /// waiting to be resumed again.
+    Yield,
+    /// `next(gen)`, resuming a generator until it yields a value, which is returned in a `Some`,
+    /// or `None` once it's finished.
+    Next,
+}
+
+impl Intrinsic {
+    /// Returns the intrinsic a built in function calls.
+    pub fn of_builtin(def: DefId) -> Option<Intrinsic> {
+        match def {
+            DefId::PANIC => Some(Intrinsic::Panic),
+            DefId::PRINT => Some(Intrinsic::Print),
+            DefId::PRINTLN => Some(Intrinsic::Println),
+            DefId::INPUT => Some(Intrinsic::Input),
+            DefId::STRING_LEN => Some(Intrinsic::StringLen),
+            DefId::STRING_CONTAINS => Some(Intrinsic::Contains),
+            DefId::STRING_SPLIT => Some(Intrinsic::Split),
+            DefId::STRING_TO_UPPER => Some(Intrinsic::ToUpper),
+            DefId::STRING_TO_LOWER => Some(Intrinsic::ToLower),
+            DefId::ARRAY_LEN => Some(Intrinsic::Len),
+            DefId::ARRAY_PUSH => Some(Intrinsic::Push),
+            DefId::ARRAY_POP => Some(Intrinsic::Pop),
+            DefId::MAP_NEW => Some(Intrinsic::NewMap),
+            DefId::MAP_LEN => Some(Intrinsic::Len),
+            DefId::MAP_INSERT => Some(Intrinsic::Insert),
+            DefId::MATH_SQRT => Some(Intrinsic::Sqrt),
+            DefId::MATH_FLOOR => Some(Intrinsic::Floor),
+            DefId::MATH_CEIL => Some(Intrinsic::Ceil),
+            DefId::MATH_POW => Some(Intrinsic::Pow),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Intrinsic::Len => "len",
+            Intrinsic::Chars => "chars",
+            Intrinsic::Panic => "panic",
+            Intrinsic::Print => "print",
+            Intrinsic::Println => "println",
+            Intrinsic::Input => "input",
+            Intrinsic::StringLen => "string_len",
+            Intrinsic::CharAt => "char_at",
+            Intrinsic::Slice => "slice",
+            Intrinsic::Contains => "contains",
+            Intrinsic::Split => "split",
+            Intrinsic::ToUpper => "to_upper",
+            Intrinsic::ToLower => "to_lower",
+            Intrinsic::Push => "push",
+            Intrinsic::Pop => "pop",
+            Intrinsic::NewMap => "new_map",
+            Intrinsic::Insert => "map_insert",
+            Intrinsic::Find => "map_find",
+            Intrinsic::EntryKey => "map_key",
+            Intrinsic::EntryValue => "map_value",
+            Intrinsic::Remove => "map_remove",
+            Intrinsic::ReadFile => "read_file",
+            Intrinsic::WriteFile => "write_file",
+            Intrinsic::FileExists => "file_exists",
+            Intrinsic::FsError => "fs_error",
+            Intrinsic::Sqrt => "sqrt",
+            Intrinsic::Floor => "floor",
+            Intrinsic::Ceil => "ceil",
+            Intrinsic::Pow => "pow",
+            Intrinsic::Yield => "yield",
+            Intrinsic::Next => "next",
+        }
+    }
+
+    /// Returns whether it does anything besides computing its value, including failing, so that
+    /// it has to run where and as often as the program says.
+    pub fn has_effects(self) -> bool {
+        !matches!(
+            self,
+            Intrinsic::Len
+                | Intrinsic::Chars
+                | Intrinsic::StringLen
+                | Intrinsic::Contains
+                | Intrinsic::ToUpper
+                | Intrinsic::ToLower
+                | Intrinsic::Push
+                | Intrinsic::Pop
+                | Intrinsic::NewMap
+                | Intrinsic::Insert
+                | Intrinsic::Find
+                | Intrinsic::Sqrt
+                | Intrinsic::Floor
+                | Intrinsic::Ceil
+                | Intrinsic::Pow
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockCall),
+    Branch {
+        cond: Value,
+        then_block: BlockCall,
+        else_block: BlockCall,
+    },
+    Return(Value),
+    /// The end of a block that's never reached the end of, such as one that calls `panic`.
+    Unreachable,
+}
+
+impl Terminator {
+    /// Returns the jumps to other blocks.
+    pub fn targets(&self) -> Vec<&BlockCall> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![then_block, else_block],
+            Terminator::Return(_) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    pub fn targets_mut(&mut self) -> Vec<&mut BlockCall> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![then_block, else_block],
+            Terminator::Return(_) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    /// Calls `f` on each value the terminator uses, including those passed to blocks.
+    pub fn operands_mut(&mut self, mut f: impl FnMut(&mut Value)) {
+        match self {
+            Terminator::Jump(target) => target.args.iter_mut().for_each(f),
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                f(cond);
+                then_block.args.iter_mut().for_each(&mut f);
+                else_block.args.iter_mut().for_each(f);
+            }
+            Terminator::Return(value) => f(value),
+            Terminator::Unreachable => {}
+        }
+    }
+}
+
+/// A jump to a block, passing values for its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockCall {
+    pub block: BlockId,
+    pub args: Vec<Value>,
+}
+
+/// A way a function isn't in valid SSA form.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    /// A value that's defined more than once.
+    Redefined { func: String, value: Value },
+    /// A value that's used but never defined.
+    Undefined { func: String, value: Value },
+    /// A jump that passes the wrong number of values to a block.
+    ArgCount {
+        func: String,
+        block: BlockId,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::Redefined { func, value } => {
+                write!(f, "`{}` defines v{} more than once", func, value.0)
+            }
+            VerifyError::Undefined { func, value } => {
+                write!(f, "`{}` uses v{}, which isn't defined", func, value.0)
+            }
+            VerifyError::ArgCount {
+                func,
+                block,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` passes {} values to bb{}, which takes {}",
+                func, found, block.0, expected
+            ),
+        }
+    }
+}
+
+/// Checks that every value in a function is defined once, and that every jump passes a value for
+/// each parameter of its block.
+pub fn verify(func: &Function) -> Result<(), VerifyError> {
+    let name = || func.name.clone();
+    let mut defined = vec![false; func.values.len()];
+    let mut define = |value: Value| {
+        if std::mem::replace(&mut defined[value.0], true) {
+            return Err(VerifyError::Redefined {
+                func: name(),
+                value,
+            });
+        }
+        Ok(())
+    };
+    for block in &func.blocks {
+        for &param in &block.params {
+            define(param)?;
+        }
+        for inst in &block.insts {
+            define(inst.value)?;
+        }
+    }
+
+    for block in &func.blocks {
+        let mut terminator = block.terminator.clone();
+        let mut used = Vec::new();
+        terminator.operands_mut(|value| used.push(*value));
+        for inst in &block.insts {
+            used.extend(inst.kind.operands());
+        }
+        if let Some(&value) = used.iter().find(|value| !defined[value.0]) {
+            return Err(VerifyError::Undefined {
+                func: name(),
+                value,
+            });
+        }
+        for target in block.terminator.targets() {
+            let expected = func.block(target.block).params.len();
+            if target.args.len() != expected {
+                return Err(VerifyError::ArgCount {
+                    func: name(),
+                    block: target.block,
+                    expected,
+                    found: target.args.len(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Builds the MIR of a program for a test, which expects it to have no errors.
+#[cfg(test)]
+pub(crate) fn built(source: &str) -> Program {
+    let (res, lowered) = crate::hir::lowered(source);
+    build(&lowered, &res)
+}
@@ -0,0 +1,234 @@
+//! Global value numbering, which removes each instruction that computes the same value as one
+//! that dominates it, using that one's value instead.
+//!
+//! The blocks are visited in preorder of the dominator tree, keeping the instructions that
+//! dominate the current block in a table, so an instruction only finds those that run before it
+//! on every path. Instructions that can fail, such as division, are numbered too, as one that
+//! does the same as an instruction that has already run without failing can't fail either.
+
+use std::collections::HashMap;
+
+use super::*;
+
+/// Removes the instructions in a function that repeat one that dominates them. `globals` tells
+/// the constants, which don't change, from the statics, which can.
+pub fn gvn(func: &mut Function, globals: &[Global]) {
+    if func.blocks.is_empty() {
+        return;
+    }
+    let dominators = Dominators::new(&Cfg::new(func));
+    let mut numberer = Numberer {
+        func,
+        globals,
+        dominators: &dominators,
+        available: HashMap::new(),
+        replaced: HashMap::new(),
+    };
+    numberer.visit(BlockId::ENTRY);
+    let replaced = numberer.replaced;
+    for var in &mut func.vars {
+        for value in &mut var.values {
+            if let Some(&new) = replaced.get(value) {
+                *value = new;
+            }
+        }
+    }
+}
+
+struct Numberer<'a> {
+    func: &'a mut Function,
+    globals: &'a [Global],
+    dominators: &'a Dominators,
+    /// The values computed by the instructions that dominate the current block.
+    available: HashMap<(InstKind, Ty), Value>,
+    /// The values of the removed instructions, by the values they were replaced with.
+    replaced: HashMap<Value, Value>,
+}
+
+impl Numberer<'_> {
+    fn visit(&mut self, block: BlockId) {
+        let mut added = Vec::new();
+        let mut insts = std::mem::take(&mut self.func.blocks[block.0].insts);
+        insts.retain_mut(|inst| {
+            inst.kind.operands_mut(|value| self.replace(value));
+            if !is_numbered(&inst.kind, self.globals) {
+                return true;
+            }
+            let ty = self.func.values[inst.value.0].clone();
+            let key = (normalize(inst.kind.clone()), ty);
+            match self.available.get(&key) {
+                Some(&value) => {
+                    self.replaced.insert(inst.value, value);
+                    false
+                }
+                None => {
+                    self.available.insert(key.clone(), inst.value);
+                    added.push(key);
+                    true
+                }
+            }
+        });
+        self.func.blocks[block.0].insts = insts;
+        let terminator = &mut self.func.blocks[block.0].terminator;
+        let mut terminator = std::mem::replace(terminator, Terminator::Unreachable);
+        terminator.operands_mut(|value| self.replace(value));
+        self.func.blocks[block.0].terminator = terminator;
+
+        for &child in self.dominators.children(block) {
+            self.visit(child);
+        }
+        for key in added {
+            self.available.remove(&key);
+        }
+    }
+
+    fn replace(&self, value: &mut Value) {
+        if let Some(&new) = self.replaced.get(value) {
+            *value = new;
+        }
+    }
+}
+
+/// Returns whether an instruction always gives the same value for the same operands, without
+/// doing anything else.
+fn is_numbered(kind: &InstKind, globals: &[Global]) -> bool {
+    match kind {
+        InstKind::Global(global) => !globals[global.0].is_static,
+        InstKind::Intrinsic { intrinsic, .. } => !intrinsic.has_effects(),
+        InstKind::Undef
+        | InstKind::SetGlobal { .. }
+        | InstKind::Call { .. }
+        | InstKind::Generator { .. }
+        | InstKind::Extern { .. } => false,
+        _ => true,
+    }
+}
+
+/// Puts the operands of a commutative operator in order, so that `a + b` and `b + a` are the same.
+fn normalize(kind: InstKind) -> InstKind {
+    match kind {
+        InstKind::Binary { op, lhs, rhs } if rhs < lhs && is_commutative(op) => InstKind::Binary {
+            op,
+            lhs: rhs,
+            rhs: lhs,
+        },
+        kind => kind,
+    }
+}
+
+fn is_commutative(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Add
+            | BinaryOp::Mul
+            | BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::BitAnd
+            | BinaryOp::BitOr
+            | BinaryOp::BitXor
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::{built, print_program};
+
+    #[test]
+    fn test_gvn() {
+        let source = "
+const K: int = 2;
+static S: int = 0;
+fn f(a: int, b: int, c: bool) int {
+    let x = a * b + K;
+    let s = S;
+    if c {
+        b * a + K + S
+    } else {
+        a - b + x - s
+    }
+}";
+        let mut mir = built(source);
+        assert_eq!(
+            print_program(&mir),
+            "const g0 K: i32 = fn1
+static g1 S: i32 = fn2
+
+fn0 f i32 {
+bb0(v0: i32, v1: i32, v2: bool):
+    v3: i32 = v0 * v1
+    v4: i32 = g0
+    v5: i32 = v3 + v4
+    v6: i32 = g1
+    branch v2, bb1, bb2
+bb1:
+    v7: i32 = v1 * v0
+    v8: i32 = g0
+    v9: i32 = v7 + v8
+    v10: i32 = g1
+    v11: i32 = v9 + v10
+    jump bb3(v11)
+bb2:
+    v12: i32 = v0 - v1
+    v13: i32 = v12 + v5
+    v14: i32 = v13 - v6
+    jump bb3(v14)
+bb3(v15: i32):
+    return v15
+}
+
+fn1 K i32 {
+bb0:
+    v0: i32 = const 2
+    return v0
+}
+
+fn2 S i32 {
+bb0:
+    v0: i32 = const 0
+    return v0
+}
+"
+        );
+        gvn(&mut mir.fns[0], &mir.globals);
+        assert_eq!(verify(&mir.fns[0]), Ok(()));
+        assert_eq!(
+            print_program(&mir),
+            "const g0 K: i32 = fn1
+static g1 S: i32 = fn2
+
+fn0 f i32 {
+bb0(v0: i32, v1: i32, v2: bool):
+    v3: i32 = v0 * v1
+    v4: i32 = g0
+    v5: i32 = v3 + v4
+    v6: i32 = g1
+    branch v2, bb1, bb2
+bb1:
+    v10: i32 = g1
+    v11: i32 = v5 + v10
+    jump bb3(v11)
+bb2:
+    v12: i32 = v0 - v1
+    v13: i32 = v12 + v5
+    v14: i32 = v13 - v6
+    jump bb3(v14)
+bb3(v15: i32):
+    return v15
+}
+
+fn1 K i32 {
+bb0:
+    v0: i32 = const 2
+    return v0
+}
+
+fn2 S i32 {
+bb0:
+    v0: i32 = const 0
+    return v0
+}
+"
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Loop-invariant code motion, which moves the instructions that compute the same value on every
+//! iteration of a loop to before the loop, so that they run once.
+//!
+//! An instruction is invariant when its operands are defined outside the loop, or by invariant
+//! instructions. Only instructions without effects are moved. Those that can fail, such as
+//! division, are only moved from blocks that run on every iteration, since moving one from a
+//! branch that isn't taken would make it fail when it wouldn't have. Reading a static is only
+//! moved when nothing in the loop can assign to it.
+
+use std::collections::HashSet;
+
+use super::*;
+
+/// Moves the invariant instructions out of the loops of a function, starting from the innermost.
+/// `globals` tells the constants from the statics.
+pub fn licm(func: &mut Function, globals: &[Global]) {
+    if func.blocks.is_empty() {
+        return;
+    }
+    let cfg = Cfg::new(func);
+    let headers: Vec<_> = cfg
+        .loops(&Dominators::new(&cfg))
+        .iter()
+        .map(|l| l.header)
+        .collect();
+    for header in headers {
+        // The loops are found again each time, since moving code out of a loop adds a block to
+        // the loop around it
+        let cfg = Cfg::new(func);
+        let dominators = Dominators::new(&cfg);
+        let loops = cfg.loops(&dominators);
+        if let Some(l) = loops.iter().find(|l| l.header == header) {
+            hoist(func, globals, &cfg, &dominators, l);
+        }
+    }
+}
+
+/// Whether an instruction can be moved out of a loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    /// It can always be moved, as running it has no effect.
+    Always,
+    /// It can fail, so it can only be moved from a block that runs on every iteration.
+    IfRun,
+    Never,
+}
+
+fn motion(kind: &InstKind, globals: &[Global], writes: &Writes) -> Motion {
+    match kind {
+        InstKind::Call { .. }
+        | InstKind::SetGlobal { .. }
+        | InstKind::Generator { .. }
+        | InstKind::Extern { .. } => Motion::Never,
+        InstKind::Intrinsic { intrinsic, .. } if intrinsic.has_effects() => Motion::Never,
+        InstKind::Global(global) if globals[global.0].is_static && writes.may_write(*global) => {
+            Motion::Never
+        }
+        // Overflow, division by zero and shifting too far
+        InstKind::Binary { op, .. } if !op.is_comparison() => match op {
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => Motion::Always,
+            _ => Motion::IfRun,
+        },
+        InstKind::Unary {
+            op: UnaryOp::Neg, ..
+        } => Motion::IfRun,
+        InstKind::Index { .. } | InstKind::SetIndex { .. } | InstKind::VariantField { .. } => {
+            Motion::IfRun
+        }
+        _ => Motion::Always,
+    }
+}
+
+/// The statics that a loop assigns to.
+struct Writes {
+    globals: HashSet<GlobalId>,
+    /// Whether the loop calls a function, which could assign to any static.
+    calls: bool,
+}
+
+impl Writes {
+    fn may_write(&self, global: GlobalId) -> bool {
+        self.calls || self.globals.contains(&global)
+    }
+}
+
+fn hoist(func: &mut Function, globals: &[Global], cfg: &Cfg, dominators: &Dominators, l: &Loop) {
+    let mut variant = HashSet::new();
+    let mut writes = Writes {
+        globals: HashSet::new(),
+        calls: false,
+    };
+    for &block in &l.blocks {
+        let block = func.block(block);
+        variant.extend(&block.params);
+        for inst in &block.insts {
+            variant.insert(inst.value);
+            match inst.kind {
+                InstKind::SetGlobal { global, .. } => {
+                    writes.globals.insert(global);
+                }
+                // Resuming a generator runs its code, and yielding runs the code that resumed it
+                InstKind::Call { .. }
+                | InstKind::Intrinsic {
+                    intrinsic: Intrinsic::Next | Intrinsic::Yield,
+                    ..
+                } => writes.calls = true,
+                _ => {}
+            }
+        }
+    }
+    // A block runs on every iteration that finishes, whether it goes around again or leaves the
+    // loop, when it dominates the blocks that do both
+    let ends: Vec<_> = l
+        .blocks
+        .iter()
+        .copied()
+        .filter(|&block| {
+            cfg.succs(block)
+                .iter()
+                .any(|&succ| succ == l.header || !l.contains(succ))
+        })
+        .collect();
+
+    let mut hoisted = Vec::new();
+    // Blocks are visited after those that dominate them, so their instructions are visited after
+    // those they use
+    for block in dominators.preorder() {
+        if !l.contains(block) {
+            continue;
+        }
+        let runs = ends.iter().all(|&end| dominators.dominates(block, end));
+        let insts = std::mem::take(&mut func.blocks[block.0].insts);
+        for inst in insts {
+            let invariant = inst
+                .kind
+                .operands()
+                .iter()
+                .all(|value| !variant.contains(value));
+            let movable = match motion(&inst.kind, globals, &writes) {
+                Motion::Always => true,
+                Motion::IfRun => runs,
+                Motion::Never => false,
+            };
+            if invariant && movable {
+                variant.remove(&inst.value);
+                hoisted.push(inst);
+            } else {
+                func.blocks[block.0].insts.push(inst);
+            }
+        }
+    }
+    if !hoisted.is_empty() {
+        let preheader = preheader(func, cfg, l);
+        func.blocks[preheader.0].insts.extend(hoisted);
+    }
+}
+
+/// Returns the block that jumps to the header of a loop from outside it, and nowhere else, adding
+/// one when there isn't one.
+fn preheader(func: &mut Function, cfg: &Cfg, l: &Loop) -> BlockId {
+    let mut outside: Vec<_> = cfg
+        .preds(l.header)
+        .iter()
+        .copied()
+        .filter(|&pred| !l.contains(pred))
+        .collect();
+    outside.dedup();
+    if let [pred] = outside[..] {
+        if let Terminator::Jump(_) = func.block(pred).terminator {
+            return pred;
+        }
+    }
+
+    let params: Vec<_> = func
+        .block(l.header)
+        .params
+        .clone()
+        .into_iter()
+        .map(|param| {
+            func.values.push(func.values[param.0].clone());
+            Value(func.values.len() - 1)
+        })
+        .collect();
+    let preheader = BlockId(func.blocks.len());
+    func.blocks.push(Block {
+        params: params.clone(),
+        insts: Vec::new(),
+        terminator: Terminator::Jump(BlockCall {
+            block: l.header,
+            args: params,
+        }),
+    });
+    for pred in outside {
+        for target in func.blocks[pred.0].terminator.targets_mut() {
+            if target.block == l.header {
+                target.block = preheader;
+            }
+        }
+    }
+    preheader
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::{built, print_program};
+
+    #[test]
+    fn test_licm() {
+        let source = "
+static S: int = 1;
+fn f(xs: [int], a: int, b: int) int {
+    let mut total = 0;
+    for x in xs {
+        total += x * (a + b) + S;
+        if x > 0 {
+            total += a / b;
+        }
+    }
+    total
+}";
+        let mut mir = built(source);
+        assert_eq!(
+            print_program(&mir),
+            "static g0 S: i32 = fn1
+
+fn0 f i32 {
+bb0(v0: [i32], v1: i32, v2: i32):
+    v3: i32 = const 0
+    v4: i32 = const 0
+    jump bb1(v4, v3)
+bb1(v5: i32, v6: i32):
+    v7: i32 = @len(v0)
+    v8: bool = v5 < v7
+    branch v8, bb2, bb6
+bb2:
+    v9: i32 = v0[v5]
+    v10: i32 = const 1
+    v11: i32 = v5 + v10
+    v12: i32 = v1 + v2
+    v13: i32 = v9 * v12
+    v14: i32 = g0
+    v15: i32 = v13 + v14
+    v16: i32 = v6 + v15
+    v17: i32 = const 0
+    v18: bool = v9 > v17
+    branch v18, bb3, bb4
+bb3:
+    v19: i32 = v1 / v2
+    v20: i32 = v16 + v19
+    jump bb5(v20)
+bb4:
+    jump bb5(v16)
+bb5(v21: i32):
+    jump bb1(v11, v21)
+bb6:
+    return v6
+}
+
+fn1 S i32 {
+bb0:
+    v0: i32 = const 1
+    return v0
+}
+"
+        );
+        licm(&mut mir.fns[0], &mir.globals);
+        assert_eq!(verify(&mir.fns[0]), Ok(()));
+        assert_eq!(
+            print_program(&mir),
+            "static g0 S: i32 = fn1
+
+fn0 f i32 {
+bb0(v0: [i32], v1: i32, v2: i32):
+    v3: i32 = const 0
+    v4: i32 = const 0
+    v7: i32 = @len(v0)
+    v10: i32 = const 1
+    v14: i32 = g0
+    v17: i32 = const 0
+    jump bb1(v4, v3)
+bb1(v5: i32, v6: i32):
+    v8: bool = v5 < v7
+    branch v8, bb2, bb6
+bb2:
+    v9: i32 = v0[v5]
+    v11: i32 = v5 + v10
+    v12: i32 = v1 + v2
+    v13: i32 = v9 * v12
+    v15: i32 = v13 + v14
+    v16: i32 = v6 + v15
+    v18: bool = v9 > v17
+    branch v18, bb3, bb4
+bb3:
+    v19: i32 = v1 / v2
+    v20: i32 = v16 + v19
+    jump bb5(v20)
+bb4:
+    jump bb5(v16)
+bb5(v21: i32):
+    jump bb1(v11, v21)
+bb6:
+    return v6
+}
+
+fn1 S i32 {
+bb0:
+    v0: i32 = const 1
+    return v0
+}
+"
+        );
+    }
+}
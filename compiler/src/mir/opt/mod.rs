@@ -0,0 +1,51 @@
+//! Optimizations of the MIR. Each pass changes one function, which stays valid SSA.
+
+mod gvn;
+mod licm;
+mod tail;
+
+use super::*;
+
+pub use gvn::gvn;
+pub use licm::licm;
+pub use tail::tail_calls;
+
+/// How hard the compiler works to make a program fast, chosen with `-O0` to `-O3`. `-O0` leaves
+/// the MIR as it's built, other than its tail calls, and the backends' code unoptimized, so that
+/// debug info can describe every variable. `-O1` numbers values, and `-O2` also hoists code out of
+/// loops. `-O3` runs the same passes as `-O2`, and asks the C compiler and LLVM for their own
+/// `-O3`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+    O3,
+}
+
+impl OptLevel {
+    /// The flag that asks the C compiler, `opt` and `llc` for the same level.
+    pub fn flag(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "-O0",
+            OptLevel::O1 => "-O1",
+            OptLevel::O2 => "-O2",
+            OptLevel::O3 => "-O3",
+        }
+    }
+}
+
+/// Runs the optimizations of a level on every function of a program. Tail calls are turned into
+/// jumps at every level, since a program that recurses deeply relies on it.
+pub fn optimize(program: &mut Program, level: OptLevel) {
+    for (i, func) in program.fns.iter_mut().enumerate() {
+        tail_calls(func, FuncId(i));
+        if level >= OptLevel::O1 {
+            gvn(func, &program.globals);
+        }
+        if level >= OptLevel::O2 {
+            licm(func, &program.globals);
+        }
+    }
+}
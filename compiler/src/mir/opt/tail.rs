@@ -0,0 +1,183 @@
+//! Tail calls, so that a function that recurses instead of looping doesn't run out of stack.
+//!
+//! A call is in tail position when the function returns its value, either right after the call or
+//! through blocks that only pass the value on, which are skipped. A function that calls itself in
+//! tail position becomes a loop: the code of its entry moves to a new block, which the entry and
+//! each of those calls jump to with their arguments. Other calls in tail position, such as those
+//! between mutually recursive functions, are left to the backends, which find them with
+//! [`Block::tail_call`] and make them reuse the caller's frame: the bytecode has a `tail_call` for
+//! direct calls, Cranelift and WebAssembly have `return_call`, LLVM has the `tailcc` convention,
+//! and C goes through a trampoline.
+
+use super::*;
+
+/// Makes the blocks of a function that pass the value of a call on to be returned return it
+/// themselves, and turns the calls in tail position to the function itself, `id`, into jumps.
+pub fn tail_calls(func: &mut Function, id: FuncId) {
+    if func.blocks.is_empty() {
+        return;
+    }
+    for id in func.block_ids() {
+        let block = func.block(id);
+        let Some(inst) = block.insts.last() else {
+            continue;
+        };
+        if let (InstKind::Call { .. }, Terminator::Jump(target)) = (&inst.kind, &block.terminator) {
+            if returns(func, target, inst.value) {
+                func.blocks[id.0].terminator = Terminator::Return(inst.value);
+            }
+        }
+    }
+    let calls: Vec<_> = func
+        .block_ids()
+        .filter(|&block| {
+            matches!(func.block(block).tail_call(), Some((Callee::Direct(callee), _)) if callee == id)
+        })
+        .collect();
+    if calls.is_empty() {
+        return;
+    }
+
+    // The entry gets new parameters, as the header takes over its code and the old ones
+    let header = BlockId(func.blocks.len());
+    let params: Vec<_> = func
+        .params()
+        .to_vec()
+        .into_iter()
+        .map(|param| {
+            func.values.push(func.values[param.0].clone());
+            Value(func.values.len() - 1)
+        })
+        .collect();
+    let entry = std::mem::replace(
+        &mut func.blocks[BlockId::ENTRY.0],
+        Block {
+            params: params.clone(),
+            insts: Vec::new(),
+            terminator: Terminator::Jump(BlockCall {
+                block: header,
+                args: params,
+            }),
+        },
+    );
+    func.blocks.push(entry);
+    let mut removed = Vec::new();
+    for block in calls {
+        let block = if block == BlockId::ENTRY {
+            header
+        } else {
+            block
+        };
+        let block = &mut func.blocks[block.0];
+        let inst = block.insts.pop().unwrap();
+        let InstKind::Call { args, .. } = inst.kind else {
+            unreachable!("a tail call is a call");
+        };
+        block.terminator = Terminator::Jump(BlockCall {
+            block: header,
+            args,
+        });
+        removed.push(inst.value);
+    }
+    for var in &mut func.vars {
+        var.values.retain(|value| !removed.contains(value));
+    }
+}
+
+/// Whether a jump passes `value` on to be returned, through blocks that do nothing else.
+fn returns(func: &Function, target: &BlockCall, mut value: Value) -> bool {
+    let mut target = target;
+    // Blocks that only pass values on can jump to each other in a loop that never returns
+    for _ in 0..func.blocks.len() {
+        let block = func.block(target.block);
+        if !block.insts.is_empty() {
+            return false;
+        }
+        let Some(i) = target.args.iter().position(|&arg| arg == value) else {
+            return false;
+        };
+        value = block.params[i];
+        match &block.terminator {
+            Terminator::Return(returned) => return *returned == value,
+            Terminator::Jump(next) => target = next,
+            Terminator::Branch { .. } | Terminator::Unreachable => return false,
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mir::{built, print_program};
+
+    #[test]
+    fn test_tail_calls() {
+        let source = "
+fn sum(n: int, acc: int) int {
+    if n == 0 { acc } else { sum(n - 1, acc + n) }
+}
+fn even(n: int) bool { if n == 0 { true } else { odd(n - 1) } }
+fn odd(n: int) bool { n != 0 && even(n - 1) }";
+        let mut mir = built(source);
+        for (i, func) in mir.fns.iter_mut().enumerate() {
+            tail_calls(func, FuncId(i));
+            assert_eq!(verify(func), Ok(()));
+        }
+        assert_eq!(
+            print_program(&mir),
+            "fn0 sum i32 {
+bb0(v9: i32, v10: i32):
+    jump bb4(v9, v10)
+bb1:
+    jump bb3(v1)
+bb2:
+    v4: i32 = const 1
+    v5: i32 = v0 - v4
+    v6: i32 = v1 + v0
+    jump bb4(v5, v6)
+bb3(v8: i32):
+    return v8
+bb4(v0: i32, v1: i32):
+    v2: i32 = const 0
+    v3: bool = v0 == v2
+    branch v3, bb1, bb2
+}
+
+fn1 even bool {
+bb0(v0: i32):
+    v1: i32 = const 0
+    v2: bool = v0 == v1
+    branch v2, bb1, bb2
+bb1:
+    v3: bool = const true
+    jump bb3(v3)
+bb2:
+    v4: i32 = const 1
+    v5: i32 = v0 - v4
+    v6: bool = call fn2(v5)
+    return v6
+bb3(v7: bool):
+    return v7
+}
+
+fn2 odd bool {
+bb0(v0: i32):
+    v1: i32 = const 0
+    v2: bool = v0 != v1
+    branch v2, bb1, bb2
+bb1:
+    v3: i32 = const 1
+    v4: i32 = v0 - v3
+    v5: bool = call fn1(v4)
+    return v5
+bb2:
+    v6: bool = const false
+    jump bb3(v6)
+bb3(v7: bool):
+    return v7
+}
+"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Prints the MIR as text, for looking at what a program builds to. Functions are named `fn0`,
+//! `fn1` and so on, globals `g0`, `g1`, blocks `bb0`, `bb1`, and values `v0`, `v1`.
+
+use super::*;
+
+pub fn print_program(program: &Program) -> String {
+    let mut out = String::new();
+    for (i, global) in program.globals.iter().enumerate() {
+        let keyword = if global.is_static { "static" } else { "const" };
+        out.push_str(&format!(
+            "{} g{} {}: {} = fn{}\n",
+            keyword, i, global.name, global.ty, global.init.0
+        ));
+    }
+    for (i, func) in program.fns.iter().enumerate() {
+        if i > 0 || !program.globals.is_empty() {
+            out.push('\n');
+        }
+        print_func(program, FuncId(i), func, &mut out);
+    }
+    out
+}
+
+fn print_func(program: &Program, id: FuncId, func: &Function, out: &mut String) {
+    out.push_str(&format!("fn{} {}", id.0, func.name));
+    if !func.ret.is_unit() {
+        out.push_str(&format!(" {}", func.ret));
+    }
+    if func.blocks.is_empty() {
+        out.push_str(";\n");
+        return;
+    }
+    out.push_str(" {\n");
+    for block in func.block_ids() {
+        for line in block_lines(program, func, block) {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out.push_str("}\n");
+}
+
+/// Returns the lines that print a block: its label and parameters, then its instructions and its
+/// terminator, indented.
+pub(super) fn block_lines(program: &Program, func: &Function, id: BlockId) -> Vec<String> {
+    let block = func.block(id);
+    let mut label = format!("bb{}", id.0);
+    if !block.params.is_empty() {
+        let params: Vec<_> = block
+            .params
+            .iter()
+            .map(|&param| format!("v{}: {}", param.0, func.value_ty(param)))
+            .collect();
+        label.push_str(&format!("({})", params.join(", ")));
+    }
+    label.push(':');
+    let mut lines = vec![label];
+    for inst in &block.insts {
+        lines.push(format!(
+            "    v{}: {} = {}",
+            inst.value.0,
+            func.value_ty(inst.value),
+            inst_kind(program, &inst.kind)
+        ));
+    }
+    lines.push(format!("    {}", terminator(&block.terminator)));
+    lines
+}
+
+fn values(values: &[Value]) -> String {
+    let values: Vec<_> = values.iter().map(|value| format!("v{}", value.0)).collect();
+    values.join(", ")
+}
+
+fn variant_name(program: &Program, def: DefId) -> String {
+    match program.variant(def) {
+        Some((adt, variant)) => adt.variants[variant].name.clone(),
+        None => "<error>".to_string(),
+    }
+}
+
+fn inst_kind(program: &Program, kind: &InstKind) -> String {
+    match kind {
+        InstKind::Const(constant) => match constant {
+            Constant::Int(value) => format!("const {}", value),
+            Constant::Float(value) => format!("const {:?}", value),
+            Constant::Bool(value) => format!("const {}", value),
+            Constant::Char(value) => format!("const {:?}", value),
+            Constant::String(value) => format!("const {:?}", value),
+            Constant::Unit => "const ()".to_string(),
+        },
+        InstKind::Undef => "undef".to_string(),
+        InstKind::Unary { op, value } => format!("{}v{}", op.as_str(), value.0),
+        InstKind::Binary { op, lhs, rhs } => format!("v{} {} v{}", lhs.0, op.as_str(), rhs.0),
+        InstKind::Cast(value) => format!("cast v{}", value.0),
+        InstKind::Tuple(elems) => format!("tuple({})", values(elems)),
+        InstKind::Array(elems) => format!("[{}]", values(elems)),
+        InstKind::Construct { def, fields } if fields.is_empty() => variant_name(program, *def),
+        InstKind::Construct { def, fields } => {
+            format!("{}({})", variant_name(program, *def), values(fields))
+        }
+        InstKind::Field { base, index } => format!("v{}.{}", base.0, index),
+        InstKind::SetField { base, index, value } => {
+            format!("v{} with .{} = v{}", base.0, index, value.0)
+        }
+        InstKind::Discriminant(value) => format!("discriminant v{}", value.0),
+        InstKind::VariantField { base, def, index } => {
+            format!("(v{} as {}).{}", base.0, variant_name(program, *def), index)
+        }
+        InstKind::Index { base, index } => format!("v{}[v{}]", base.0, index.0),
+        InstKind::SetIndex { base, index, value } => {
+            format!("v{} with [v{}] = v{}", base.0, index.0, value.0)
+        }
+        InstKind::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let bound =
+                |bound: &Option<Value>| bound.map_or(String::new(), |b| format!("v{}", b.0));
+            let op = if *inclusive { "..=" } else { ".." };
+            format!("range {}{}{}", bound(start), op, bound(end))
+        }
+        InstKind::Global(global) => format!("g{}", global.0),
+        InstKind::SetGlobal { global, value } => format!("g{} = v{}", global.0, value.0),
+        InstKind::FnRef(func) => format!("fn{}", func.0),
+        InstKind::Closure { func, captures } => {
+            format!("closure fn{}({})", func.0, values(captures))
+        }
+        InstKind::Generator { func, args } => format!("generator fn{}({})", func.0, values(args)),
+        InstKind::Call { callee, args } => {
+            let callee = match callee {
+                Callee::Direct(func) => format!("fn{}", func.0),
+                Callee::Indirect(value) => format!("v{}", value.0),
+            };
+            format!("call {}({})", callee, values(args))
+        }
+        InstKind::Intrinsic { intrinsic, args } => {
+            format!("@{}({})", intrinsic.name(), values(args))
+        }
+        InstKind::Extern { name, args } => format!("extern {}({})", name, values(args)),
+    }
+}
+
+fn block_call(call: &BlockCall) -> String {
+    if call.args.is_empty() {
+        format!("bb{}", call.block.0)
+    } else {
+        format!("bb{}({})", call.block.0, values(&call.args))
+    }
+}
+
+fn terminator(terminator: &Terminator) -> String {
+    match terminator {
+        Terminator::Jump(target) => format!("jump {}", block_call(target)),
+        Terminator::Branch {
+            cond,
+            then_block,
+            else_block,
+        } => format!(
+            "branch v{}, {}, {}",
+            cond.0,
+            block_call(then_block),
+            block_call(else_block)
+        ),
+        Terminator::Return(value) => format!("return v{}", value.0),
+        Terminator::Unreachable => "unreachable".to_string(),
+    }
+}
@@ -1 +1,2685 @@
-// pub enum
+//! Contains the recursive descent parser that turns a Ruffle source file into an AST.
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::{Debug, Display},
+};
+
+use crate::{
+    ast::*,
+    lexer::{Lexer, LexingError, SlicedToken, Span, Token},
+    utils::rows_cols_index,
+};
+
+/// Error type returned from parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParsingError {
+    Lexing(LexingError),
+    Expected { expected: String, found: Token },
+    UnexpectedEof { expected: String },
+}
+
+impl Error for ParsingError {}
+
+impl Display for ParsingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParsingError::Lexing(err) => write!(f, "{}", err),
+            ParsingError::Expected { expected, found } => {
+                write!(f, "expected {}, found `{}`", expected, found)
+            }
+            ParsingError::UnexpectedEof { expected } => {
+                write!(f, "expected {}, found end of file", expected)
+            }
+        }
+    }
+}
+
+/// Wraps a parsing error with its span in the source code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError<'a> {
+    pub error: ParsingError,
+    pub span: Span,
+    pub source: &'a str,
+}
+
+impl<'a> ParseError<'a> {
+    pub fn slice(&self) -> &str {
+        &self.source[self.span.clone()]
+    }
+}
+
+impl<'a> Display for ParseError<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (rows, cols) = rows_cols_index(self.source, self.span.start);
+        writeln!(f, "error at {}:{}:", rows, cols)?;
+        writeln!(f, "{}", self.slice())?;
+        writeln!(f, "^ {}", self.error)?;
+        Ok(())
+    }
+}
+
+pub type ParseResult<'a, T> = Result<T, ParseError<'a>>;
+
+/// The name, type parameters, parameters and return type of a function.
+type FnSignature = (Ident, Vec<GenericParam>, Vec<Param>, Option<TypeExpr>);
+
+/// A token that hasn't been consumed yet, along with the doc comments before it.
+struct Lookahead<'a> {
+    token: SlicedToken<'a>,
+    docs: Vec<String>,
+}
+
+/// Parses a stream of tokens into an AST.
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
+    source: &'a str,
+    lookahead: VecDeque<Lookahead<'a>>,
+    /// End of the last consumed token, used to end the spans of nodes.
+    prev_end: usize,
+    /// Errors that have been recovered from so far.
+    errors: Vec<ParseError<'a>>,
+    /// Whether `Name {` is parsed as the start of a struct literal. This is turned off in
+    /// conditions, where the `{` starts the body instead.
+    struct_literals: bool,
+}
+
+/// A statement in a block, or the block's tail expression.
+enum BlockEntry {
+    Stmt(Stmt),
+    Tail(Expr),
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            lexer: Lexer::new(source).with_recovery(),
+            source,
+            lookahead: VecDeque::new(),
+            prev_end: 0,
+            errors: Vec::new(),
+            struct_literals: true,
+        }
+    }
+
+    /// Starts parsing at byte `offset` of the source, which is used when several files share one
+    /// source buffer so that their spans don't overlap.
+    pub fn starting_at(mut self, offset: usize) -> Self {
+        self.lexer = Lexer::new(self.source).with_recovery().starting_at(offset);
+        self.prev_end = offset;
+        self
+    }
+
+    /// Parses the whole source file. Syntax errors are recovered from by replacing the broken item
+    /// or statement with an error node, and can be retrieved with [`Parser::errors`].
+    pub fn parse_program(&mut self) -> Program {
+        let mut items = Vec::new();
+        while self.peek().is_some() {
+            items.push(self.parse_item_or_recover());
+        }
+
+        Program { items }
+    }
+
+    /// Returns the errors that have been recovered from so far.
+    pub fn errors(&self) -> &[ParseError<'a>] {
+        &self.errors
+    }
+
+    /// Consumes the parser, returning the errors that have been recovered from.
+    pub fn into_errors(self) -> Vec<ParseError<'a>> {
+        self.errors
+    }
+
+    /// Parses an item, or records the error and skips to the start of the next item.
+    fn parse_item_or_recover(&mut self) -> Item {
+        let start = self.peek_span().start;
+        match self.parse_item() {
+            Ok(item) => item,
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize(start, |token| {
+                    matches!(
+                        token,
+                        Token::Pub
+                            | Token::Const
+                            | Token::Static
+                            | Token::Extern
+                            | Token::Fn
+                            | Token::Struct
+                            | Token::Enum
+                            | Token::Trait
+                            | Token::Impl
+                            | Token::Mod
+                            | Token::Use
+                    )
+                });
+                Item {
+                    kind: ItemKind::Error,
+                    public: false,
+                    span: self.span_from(start),
+                    docs: Vec::new(),
+                }
+            }
+        }
+    }
+
+    /// Skips tokens until one outside of any brackets is accepted by `stop`, which is consumed if
+    /// it's a `;`, or until the `}` that closes the enclosing block. At least one token is
+    /// skipped if nothing has been consumed since `start`, so the parser always makes progress.
+    fn synchronize(&mut self, start: usize, stop: impl Fn(&Token) -> bool) {
+        let mut depth = 0usize;
+        let mut progressed = self.prev_end > start;
+        while let Some(token) = self.peek() {
+            if depth == 0 && progressed && (*token == Token::RBrace || stop(token)) {
+                if *token == Token::Semi {
+                    self.next();
+                }
+                break;
+            }
+
+            match token {
+                Token::LParen | Token::LSquare | Token::LBrace => depth += 1,
+                Token::RParen | Token::RSquare | Token::RBrace => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            self.next();
+            progressed = true;
+        }
+    }
+
+    /// Pulls tokens from the lexer until there are more than `n` tokens of lookahead, or the
+    /// lexer runs out. Doc comments are set aside for the token that follows them.
+    fn fill(&mut self, n: usize) {
+        let mut docs = Vec::new();
+        while self.lookahead.len() <= n {
+            let Some(token) = self.lexer.next() else {
+                break;
+            };
+
+            // The lexer is in recovery mode, so errors are emitted as `Token::Error`
+            let token = token.expect("recovering lexer yielded an error");
+            match token.token {
+                Token::DocComment(doc) => docs.push(doc),
+                _ => self.lookahead.push_back(Lookahead {
+                    token,
+                    docs: std::mem::take(&mut docs),
+                }),
+            }
+        }
+    }
+
+    /// Returns the `n`th token after the next one without consuming anything.
+    fn peek_nth(&mut self, n: usize) -> Option<&Token> {
+        self.fill(n);
+        self.lookahead.get(n).map(|l| &l.token.token)
+    }
+
+    /// Returns the next token without consuming it.
+    fn peek(&mut self) -> Option<&Token> {
+        self.peek_nth(0)
+    }
+
+    /// Returns the span of the next token, or an empty span at the end of the file.
+    fn peek_span(&mut self) -> Span {
+        self.fill(0);
+        match self.lookahead.front() {
+            Some(l) => l.token.span.clone(),
+            None => self.source.len()..self.source.len(),
+        }
+    }
+
+    /// Takes the doc comments written before the next token.
+    fn take_docs(&mut self) -> Vec<String> {
+        self.fill(0);
+        self.lookahead
+            .front_mut()
+            .map(|l| std::mem::take(&mut l.docs))
+            .unwrap_or_default()
+    }
+
+    /// Consumes the next token.
+    fn next(&mut self) -> Option<SlicedToken<'a>> {
+        self.fill(0);
+        let token = self.lookahead.pop_front()?.token;
+        self.prev_end = token.span.end;
+        Some(token)
+    }
+
+    /// Returns true if the next token is `token`.
+    fn at(&mut self, token: &Token) -> bool {
+        self.peek() == Some(token)
+    }
+
+    /// Consumes the next token if it's `token`.
+    fn eat(&mut self, token: &Token) -> bool {
+        let at = self.at(token);
+        if at {
+            self.next();
+        }
+        at
+    }
+
+    /// Consumes the next token, which must be `token`, and returns its span.
+    fn expect(&mut self, token: &Token) -> ParseResult<'a, Span> {
+        if self.at(token) {
+            return Ok(self.next().unwrap().span);
+        }
+        Err(self.error_expected(format!("`{}`", token)))
+    }
+
+    /// Consumes an identifier.
+    fn expect_ident(&mut self) -> ParseResult<'a, Ident> {
+        match self.peek() {
+            Some(Token::Ident(_)) => {
+                let token = self.next().unwrap();
+                let Token::Ident(name) = token.token else {
+                    unreachable!()
+                };
+                Ok(Ident {
+                    name,
+                    span: token.span,
+                })
+            }
+            _ => Err(self.error_expected("identifier")),
+        }
+    }
+
+    /// Consumes a `self` or `super` keyword, returning it as an identifier.
+    fn expect_path_keyword(&mut self) -> Ident {
+        let token = self.next().unwrap();
+        Ident {
+            name: token.token.to_string(),
+            span: token.span,
+        }
+    }
+
+    /// Returns the span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: usize) -> Span {
+        start..self.prev_end.max(start)
+    }
+
+    /// Creates an error for the next token not being what was expected.
+    fn error_expected(&mut self, expected: impl Into<String>) -> ParseError<'a> {
+        let expected = expected.into();
+        let span = self.peek_span();
+        let error = match self.peek() {
+            Some(Token::Error(error)) => ParsingError::Lexing(error.clone()),
+            Some(found) => ParsingError::Expected {
+                expected,
+                found: found.clone(),
+            },
+            None => ParsingError::UnexpectedEof { expected },
+        };
+
+        ParseError {
+            error,
+            span,
+            source: self.source,
+        }
+    }
+
+    /// Parses a top level declaration.
+    fn parse_item(&mut self) -> ParseResult<'a, Item> {
+        let docs = self.take_docs();
+        let start = self.peek_span().start;
+        let public = self.eat(&Token::Pub);
+
+        let kind = match self.peek() {
+            Some(Token::Const) => {
+                self.next();
+                ItemKind::Const(self.parse_global_decl()?)
+            }
+            Some(Token::Static) => {
+                self.next();
+                ItemKind::Static(self.parse_global_decl()?)
+            }
+            Some(Token::Fn) => {
+                self.next();
+                ItemKind::Fn(self.parse_function_decl()?)
+            }
+            Some(Token::Extern) => {
+                self.next();
+                self.expect(&Token::Fn)?;
+                ItemKind::Extern(self.parse_extern_decl()?)
+            }
+            Some(Token::Struct) => {
+                self.next();
+                ItemKind::Struct(self.parse_struct_decl()?)
+            }
+            Some(Token::Enum) => {
+                self.next();
+                ItemKind::Enum(self.parse_enum_decl()?)
+            }
+            Some(Token::Trait) => {
+                self.next();
+                ItemKind::Trait(self.parse_trait_decl()?)
+            }
+            Some(Token::Impl) => {
+                self.next();
+                ItemKind::Impl(self.parse_impl_decl()?)
+            }
+            Some(Token::Mod) => {
+                self.next();
+                ItemKind::Mod(self.parse_mod_decl()?)
+            }
+            Some(Token::Use) => {
+                self.next();
+                let path = self.parse_path()?;
+                self.expect(&Token::Semi)?;
+                ItemKind::Use(path)
+            }
+            _ => return Err(self.error_expected("item")),
+        };
+
+        Ok(Item {
+            kind,
+            public,
+            span: self.span_from(start),
+            docs,
+        })
+    }
+
+    /// Parses the rest of a module declaration after the `mod` keyword.
+    fn parse_mod_decl(&mut self) -> ParseResult<'a, ModDecl> {
+        let name = self.expect_ident()?;
+        if self.eat(&Token::Semi) {
+            return Ok(ModDecl { name, items: None });
+        }
+
+        self.expect(&Token::LBrace)?;
+        let mut items = Vec::new();
+        while !self.eat(&Token::RBrace) {
+            if self.peek().is_none() {
+                return Err(self.error_expected("`}`"));
+            }
+            items.push(self.parse_item_or_recover());
+        }
+
+        Ok(ModDecl {
+            name,
+            items: Some(items),
+        })
+    }
+
+    /// Parses the rest of a `const` or `static` declaration after the keyword.
+    fn parse_global_decl(&mut self) -> ParseResult<'a, GlobalDecl> {
+        let name = self.expect_ident()?;
+        let ty = self.parse_type_annotation(&[Token::Eq])?;
+        self.expect(&Token::Eq)?;
+        let value = self.parse_expr()?;
+        self.expect(&Token::Semi)?;
+
+        Ok(GlobalDecl { name, ty, value })
+    }
+
+    /// Parses an optional type annotation. The `:` before the type is optional, so
+    /// `let x: i32` and `let x i32` are both accepted. `ends` are the tokens that can follow when
+    /// the annotation is left out.
+    fn parse_type_annotation(&mut self, ends: &[Token]) -> ParseResult<'a, Option<TypeExpr>> {
+        let omitted = match self.peek() {
+            Some(token) => ends.contains(token),
+            None => true,
+        };
+        if self.eat(&Token::Colon) || !omitted {
+            return Ok(Some(self.parse_type()?));
+        }
+        Ok(None)
+    }
+
+    /// Returns true if the next token starts an item.
+    fn at_item(&mut self) -> bool {
+        matches!(
+            self.peek(),
+            Some(
+                Token::Pub
+                    | Token::Const
+                    | Token::Static
+                    | Token::Extern
+                    | Token::Fn
+                    | Token::Struct
+                    | Token::Enum
+                    | Token::Trait
+                    | Token::Impl
+                    | Token::Mod
+                    | Token::Use
+            )
+        )
+    }
+
+    /// Parses the rest of a struct declaration after the `struct` keyword.
+    fn parse_struct_decl(&mut self) -> ParseResult<'a, StructDecl> {
+        let name = self.expect_ident()?;
+        let fields = self.parse_field_decls()?;
+
+        Ok(StructDecl { name, fields })
+    }
+
+    /// Parses the rest of an enum declaration after the `enum` keyword.
+    fn parse_enum_decl(&mut self) -> ParseResult<'a, EnumDecl> {
+        let name = self.expect_ident()?;
+        self.expect(&Token::LBrace)?;
+        let mut variants = Vec::new();
+        while !self.eat(&Token::RBrace) {
+            variants.push(self.parse_variant()?);
+
+            if !self.eat(&Token::Comma) {
+                self.expect(&Token::RBrace)?;
+                break;
+            }
+        }
+
+        Ok(EnumDecl { name, variants })
+    }
+
+    /// Parses a unit, tuple or struct enum variant.
+    fn parse_variant(&mut self) -> ParseResult<'a, Variant> {
+        let start = self.peek_span().start;
+        let name = self.expect_ident()?;
+        let kind = match self.peek() {
+            Some(Token::LParen) => {
+                self.next();
+                let mut types = Vec::new();
+                while !self.eat(&Token::RParen) {
+                    types.push(self.parse_type()?);
+
+                    if !self.eat(&Token::Comma) {
+                        self.expect(&Token::RParen)?;
+                        break;
+                    }
+                }
+                VariantKind::Tuple(types)
+            }
+            Some(Token::LBrace) => VariantKind::Struct(self.parse_field_decls()?),
+            _ => VariantKind::Unit,
+        };
+
+        Ok(Variant {
+            name,
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses the `{ field: Type, ... }` fields of a struct.
+    fn parse_field_decls(&mut self) -> ParseResult<'a, Vec<FieldDecl>> {
+        self.expect(&Token::LBrace)?;
+        let mut fields = Vec::new();
+        while !self.eat(&Token::RBrace) {
+            let start = self.peek_span().start;
+            let public = self.eat(&Token::Pub);
+            let name = self.expect_ident()?;
+            self.eat(&Token::Colon);
+            let ty = self.parse_type()?;
+            fields.push(FieldDecl {
+                public,
+                name,
+                ty,
+                span: self.span_from(start),
+            });
+
+            if !self.eat(&Token::Comma) {
+                self.expect(&Token::RBrace)?;
+                break;
+            }
+        }
+
+        Ok(fields)
+    }
+
+    /// Parses the rest of a function declaration after the `fn` keyword.
+    fn parse_function_decl(&mut self) -> ParseResult<'a, FunctionDecl> {
+        let (name, generics, params, ret) = self.parse_fn_signature()?;
+        let body = self.parse_block()?;
+
+        Ok(FunctionDecl {
+            name,
+            generics,
+            params,
+            ret,
+            body,
+        })
+    }
+
+    /// Parses the rest of an `extern fn` declaration after the `fn` keyword, which has no type
+    /// parameters and ends in `;` instead of a body.
+    fn parse_extern_decl(&mut self) -> ParseResult<'a, ExternDecl> {
+        if self.peek_nth(1) == Some(&Token::Less) {
+            self.next();
+            return Err(self.error_expected("`(`"));
+        }
+        let (name, _, params, ret) = self.parse_fn_signature()?;
+        self.expect(&Token::Semi)?;
+
+        Ok(ExternDecl { name, params, ret })
+    }
+
+    /// Parses the name, type parameters, parameters and return type of a function after the `fn`
+    /// keyword.
+    fn parse_fn_signature(&mut self) -> ParseResult<'a, FnSignature> {
+        let name = self.expect_ident()?;
+        let generics = if self.at(&Token::Less) {
+            self.parse_generics()?
+        } else {
+            Vec::new()
+        };
+
+        self.expect(&Token::LParen)?;
+        let mut params = Vec::new();
+        while !self.eat(&Token::RParen) {
+            params.push(self.parse_param()?);
+
+            if !self.eat(&Token::Comma) {
+                self.expect(&Token::RParen)?;
+                break;
+            }
+        }
+
+        // Like type annotations, the `->` before the return type is optional
+        let ret = if self.eat(&Token::StraightArrow)
+            || !matches!(self.peek(), Some(Token::LBrace | Token::Semi))
+        {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        Ok((name, generics, params, ret))
+    }
+
+    /// Parses a `<T: Bound + Other, U>` list of type parameters.
+    fn parse_generics(&mut self) -> ParseResult<'a, Vec<GenericParam>> {
+        self.expect(&Token::Less)?;
+        let mut generics = Vec::new();
+        while !self.eat(&Token::Greater) {
+            let start = self.peek_span().start;
+            let name = self.expect_ident()?;
+            let mut bounds = Vec::new();
+            if self.eat(&Token::Colon) {
+                bounds.push(self.parse_path()?);
+                while self.eat(&Token::Plus) {
+                    bounds.push(self.parse_path()?);
+                }
+            }
+            generics.push(GenericParam {
+                name,
+                bounds,
+                span: self.span_from(start),
+            });
+
+            if !self.eat(&Token::Comma) {
+                self.expect(&Token::Greater)?;
+                break;
+            }
+        }
+        Ok(generics)
+    }
+
+    /// Parses a `name: Type` parameter, or a `self` parameter.
+    fn parse_param(&mut self) -> ParseResult<'a, Param> {
+        let start = self.peek_span().start;
+        let mutable = self.at(&Token::Mut) && self.peek_nth(1) == Some(&Token::SelfValue);
+        if mutable || self.at(&Token::SelfValue) {
+            if mutable {
+                self.next();
+            }
+            let span = self.next().unwrap().span;
+            let ident = |name: &str| Ident {
+                name: name.to_string(),
+                span: span.clone(),
+            };
+            return Ok(Param {
+                pattern: Pattern {
+                    kind: if mutable {
+                        PatternKind::Mut(ident("self"))
+                    } else {
+                        PatternKind::Ident(ident("self"))
+                    },
+                    span: self.span_from(start),
+                },
+                ty: TypeExpr {
+                    kind: TypeExprKind::Path(Path {
+                        segments: vec![ident("Self")],
+                        span: span.clone(),
+                    }),
+                    span: span.clone(),
+                },
+                span: self.span_from(start),
+            });
+        }
+
+        let pattern = self.parse_pattern()?;
+        self.eat(&Token::Colon);
+        let ty = self.parse_type()?;
+
+        Ok(Param {
+            pattern,
+            ty,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses the rest of a trait declaration after the `trait` keyword.
+    fn parse_trait_decl(&mut self) -> ParseResult<'a, TraitDecl> {
+        let name = self.expect_ident()?;
+        self.expect(&Token::LBrace)?;
+        let mut methods = Vec::new();
+        while !self.eat(&Token::RBrace) {
+            let docs = self.take_docs();
+            let start = self.peek_span().start;
+            self.expect(&Token::Fn)?;
+            let (name, generics, params, ret) = self.parse_fn_signature()?;
+            let body = if self.eat(&Token::Semi) {
+                None
+            } else {
+                Some(self.parse_block()?)
+            };
+            methods.push(TraitMethod {
+                name,
+                generics,
+                params,
+                ret,
+                body,
+                span: self.span_from(start),
+                docs,
+            });
+        }
+
+        Ok(TraitDecl { name, methods })
+    }
+
+    /// Parses the rest of an impl block after the `impl` keyword.
+    fn parse_impl_decl(&mut self) -> ParseResult<'a, ImplDecl> {
+        let ty = self.parse_type()?;
+        let (trait_ref, self_ty) = if self.eat(&Token::For) {
+            let TypeExprKind::Path(path) = ty.kind else {
+                return Err(ParseError {
+                    error: ParsingError::Expected {
+                        expected: "trait name".to_string(),
+                        found: Token::For,
+                    },
+                    span: ty.span,
+                    source: self.source,
+                });
+            };
+            (Some(path), self.parse_type()?)
+        } else {
+            (None, ty)
+        };
+
+        self.expect(&Token::LBrace)?;
+        let mut methods = Vec::new();
+        while !self.eat(&Token::RBrace) {
+            let at_fn = match self.peek() {
+                Some(Token::Pub) => self.peek_nth(1) == Some(&Token::Fn),
+                token => token == Some(&Token::Fn),
+            };
+            if !at_fn {
+                return Err(self.error_expected("`fn`"));
+            }
+            methods.push(self.parse_item()?);
+        }
+
+        Ok(ImplDecl {
+            trait_ref,
+            self_ty,
+            methods,
+        })
+    }
+
+    /// Parses a `{ ... }` block of statements.
+    pub fn parse_block(&mut self) -> ParseResult<'a, Block> {
+        let start = self.expect(&Token::LBrace)?.start;
+        let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+        let mut stmts = Vec::new();
+        let mut tail = None;
+        while !self.eat(&Token::RBrace) {
+            if self.peek().is_none() {
+                return Err(self.error_expected("`}`"));
+            }
+            let stmt_start = self.peek_span().start;
+            let entry = match self.parse_stmt() {
+                Ok(entry) => entry,
+                Err(error) => {
+                    self.errors.push(error);
+                    self.synchronize(stmt_start, |token| *token == Token::Semi);
+                    BlockEntry::Stmt(Stmt {
+                        kind: StmtKind::Error,
+                        span: self.span_from(stmt_start),
+                    })
+                }
+            };
+            match entry {
+                BlockEntry::Stmt(stmt) => stmts.push(stmt),
+                BlockEntry::Tail(expr) => {
+                    tail = Some(Box::new(expr));
+                    self.expect(&Token::RBrace)?;
+                    break;
+                }
+            }
+        }
+        self.struct_literals = struct_literals;
+
+        Ok(Block {
+            stmts,
+            tail,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses a statement, or the tail expression if it's the last thing in the block.
+    fn parse_stmt(&mut self) -> ParseResult<'a, BlockEntry> {
+        let start = self.peek_span().start;
+        if self.at_item() {
+            let item = self.parse_item()?;
+            return Ok(BlockEntry::Stmt(Stmt {
+                span: item.span.clone(),
+                kind: StmtKind::Item(item),
+            }));
+        }
+
+        let kind = match self.peek() {
+            Some(Token::Let) => {
+                self.next();
+                let pattern = self.parse_pattern()?;
+                let ty = self.parse_type_annotation(&[Token::Eq, Token::Semi])?;
+                let value = if self.eat(&Token::Eq) {
+                    Some(self.parse_expr()?)
+                } else {
+                    None
+                };
+                self.expect(&Token::Semi)?;
+                StmtKind::Let { pattern, ty, value }
+            }
+            Some(Token::Return) => {
+                self.next();
+                let value = if self.at(&Token::Semi) {
+                    None
+                } else {
+                    Some(self.parse_expr()?)
+                };
+                self.expect(&Token::Semi)?;
+                StmtKind::Return(value)
+            }
+            Some(Token::Yield) => {
+                self.next();
+                let value = self.parse_expr()?;
+                self.expect(&Token::Semi)?;
+                StmtKind::Yield(value)
+            }
+            Some(Token::Break) => {
+                self.next();
+                self.expect(&Token::Semi)?;
+                StmtKind::Break
+            }
+            Some(Token::Continue) => {
+                self.next();
+                self.expect(&Token::Semi)?;
+                StmtKind::Continue
+            }
+            _ => {
+                let expr = self.parse_expr()?;
+                if !self.eat(&Token::Semi) {
+                    if self.at(&Token::RBrace) {
+                        return Ok(BlockEntry::Tail(expr));
+                    }
+                    if !expr.kind.is_block_like() {
+                        self.expect(&Token::Semi)?;
+                    }
+                }
+                StmtKind::Expr(expr)
+            }
+        };
+
+        Ok(BlockEntry::Stmt(Stmt {
+            kind,
+            span: self.span_from(start),
+        }))
+    }
+
+    /// Parses a type.
+    fn parse_type(&mut self) -> ParseResult<'a, TypeExpr> {
+        let start = self.peek_span().start;
+        let kind = match self.peek() {
+            Some(Token::Ident(_) | Token::SelfValue | Token::Super) => {
+                let path = self.parse_path()?;
+                if self.eat(&Token::Less) {
+                    let mut args = Vec::new();
+                    while !self.eat_closing_angle() {
+                        args.push(self.parse_type()?);
+
+                        if !self.eat(&Token::Comma) {
+                            if !self.eat_closing_angle() {
+                                return Err(self.error_expected("`>`"));
+                            }
+                            break;
+                        }
+                    }
+                    TypeExprKind::Generic { path, args }
+                } else {
+                    TypeExprKind::Path(path)
+                }
+            }
+            Some(Token::LParen) => {
+                self.next();
+                let mut types = Vec::new();
+                let mut trailing_comma = false;
+                while !self.eat(&Token::RParen) {
+                    types.push(self.parse_type()?);
+
+                    trailing_comma = self.eat(&Token::Comma);
+                    if !trailing_comma {
+                        self.expect(&Token::RParen)?;
+                        break;
+                    }
+                }
+
+                // `(int)` is just a parenthesized type, while `(int,)` is a tuple
+                if types.len() == 1 && !trailing_comma {
+                    types.pop().unwrap().kind
+                } else {
+                    TypeExprKind::Tuple(types)
+                }
+            }
+            Some(Token::Fn) => {
+                self.next();
+                self.expect(&Token::LParen)?;
+                let mut params = Vec::new();
+                while !self.eat(&Token::RParen) {
+                    params.push(self.parse_type()?);
+
+                    if !self.eat(&Token::Comma) {
+                        self.expect(&Token::RParen)?;
+                        break;
+                    }
+                }
+                let ret = if self.eat(&Token::StraightArrow) {
+                    Some(Box::new(self.parse_type()?))
+                } else {
+                    None
+                };
+                TypeExprKind::Fn { params, ret }
+            }
+            Some(Token::LSquare) => {
+                self.next();
+                let elem = Box::new(self.parse_type()?);
+                let len = if self.eat(&Token::Semi) {
+                    Some(Box::new(self.parse_expr()?))
+                } else {
+                    None
+                };
+                self.expect(&Token::RSquare)?;
+                TypeExprKind::Array { elem, len }
+            }
+            Some(Token::Bang) => {
+                self.next();
+                TypeExprKind::Never
+            }
+            _ => return Err(self.error_expected("type")),
+        };
+
+        Ok(TypeExpr {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Consumes a `>` that closes a list of generic arguments. Tokens starting with `>`, such as
+    /// the `>>` in `Vec<Vec<int>>`, are split so that the rest can be consumed separately.
+    fn eat_closing_angle(&mut self) -> bool {
+        let rest = match self.peek() {
+            Some(Token::Greater) => return self.eat(&Token::Greater),
+            Some(Token::Shr) => Token::Greater,
+            Some(Token::GreaterEq) => Token::Eq,
+            Some(Token::ShrEq) => Token::GreaterEq,
+            _ => return false,
+        };
+
+        let front = &mut self.lookahead.front_mut().unwrap().token;
+        front.token = rest;
+        front.span.start += 1;
+        self.prev_end = front.span.start;
+        true
+    }
+
+    /// Returns whether the next token is `..` or `..=`.
+    fn at_range_op(&mut self) -> bool {
+        matches!(self.peek(), Some(Token::DotDot | Token::DotDotEq))
+    }
+
+    /// Parses a range operator and its optional end, given the already parsed start.
+    fn parse_range(&mut self, start: usize, lhs: Option<Expr>) -> ParseResult<'a, Expr> {
+        let inclusive = self.at(&Token::DotDotEq);
+        self.next();
+
+        // The end is optional, so only parse one if an expression could start here
+        let struct_literals = self.struct_literals;
+        let end = match self.peek() {
+            None
+            | Some(Token::RParen | Token::RSquare | Token::RBrace | Token::Comma | Token::Semi) => {
+                None
+            }
+            Some(Token::LBrace) if !struct_literals => None,
+            _ => Some(Box::new(self.parse_expr_bp(RANGE_BP + 1)?)),
+        };
+        if inclusive && end.is_none() {
+            return Err(self.error_expected("range end"));
+        }
+
+        Ok(Expr {
+            kind: ExprKind::Range {
+                start: lhs.map(Box::new),
+                end,
+                inclusive,
+            },
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses an expression in a position followed by a block, such as an `if` condition, where
+    /// struct literals aren't allowed.
+    fn parse_cond_expr(&mut self) -> ParseResult<'a, Expr> {
+        let struct_literals = std::mem::replace(&mut self.struct_literals, false);
+        let expr = self.parse_expr();
+        self.struct_literals = struct_literals;
+        expr
+    }
+
+    /// Parses the rest of an `if` expression after the `if` keyword.
+    fn parse_if_expr(&mut self) -> ParseResult<'a, ExprKind> {
+        let cond = Box::new(self.parse_cond_expr()?);
+        let then_branch = self.parse_block()?;
+        let else_branch = if self.eat(&Token::Else) {
+            let start = self.peek_span().start;
+            let kind = if self.eat(&Token::If) {
+                self.parse_if_expr()?
+            } else {
+                ExprKind::Block(self.parse_block()?)
+            };
+            Some(Box::new(Expr {
+                kind,
+                span: self.span_from(start),
+            }))
+        } else {
+            None
+        };
+
+        Ok(ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    /// Parses a `::` separated path.
+    fn parse_path(&mut self) -> ParseResult<'a, Path> {
+        let start = self.peek_span().start;
+        // A path can start with `self::` for the current module, or with any number of `super::`
+        // for the modules around it
+        let mut segments = vec![match self.peek() {
+            Some(Token::SelfValue | Token::Super) => self.expect_path_keyword(),
+            _ => self.expect_ident()?,
+        }];
+        while self.eat(&Token::ColonColon) {
+            let leading = segments.iter().all(|segment| segment.name == "super");
+            segments.push(match self.peek() {
+                Some(Token::Super) if leading => self.expect_path_keyword(),
+                _ => self.expect_ident()?,
+            });
+        }
+
+        Ok(Path {
+            segments,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses an expression.
+    pub fn parse_expr(&mut self) -> ParseResult<'a, Expr> {
+        self.parse_expr_bp(0)
+    }
+
+    /// Parses an expression whose binary operators all bind tighter than `min_bp`, using
+    /// precedence climbing.
+    fn parse_expr_bp(&mut self, min_bp: u8) -> ParseResult<'a, Expr> {
+        let start = self.peek_span().start;
+        let mut lhs = if self.at_range_op() {
+            self.parse_range(start, None)?
+        } else {
+            self.parse_unary_expr()?
+        };
+
+        while let Some((op, left_bp, right_bp)) = self.peek().and_then(infix_op) {
+            if left_bp < min_bp {
+                break;
+            }
+
+            if let InfixOp::Range = op {
+                lhs = self.parse_range(start, Some(lhs))?;
+                continue;
+            }
+            if let InfixOp::Cast = op {
+                self.next();
+                let ty = self.parse_type()?;
+                lhs = Expr {
+                    kind: ExprKind::Cast {
+                        expr: Box::new(lhs),
+                        ty,
+                    },
+                    span: self.span_from(start),
+                };
+                continue;
+            }
+
+            self.next();
+            let rhs = Box::new(self.parse_expr_bp(right_bp)?);
+            let lhs_box = Box::new(lhs);
+
+            let kind = match op {
+                InfixOp::Range | InfixOp::Cast => unreachable!(),
+                InfixOp::Binary(op) => ExprKind::Binary {
+                    op,
+                    lhs: lhs_box,
+                    rhs,
+                },
+                InfixOp::Assign => ExprKind::Assign {
+                    target: lhs_box,
+                    value: rhs,
+                },
+                InfixOp::CompoundAssign(op) => ExprKind::CompoundAssign {
+                    op,
+                    target: lhs_box,
+                    value: rhs,
+                },
+            };
+
+            lhs = Expr {
+                kind,
+                span: self.span_from(start),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    /// Parses a postfix expression with any number of prefix operators, which bind tighter than
+    /// binary operators but looser than postfix ones, so `-a.b()` is `-(a.b())`.
+    fn parse_unary_expr(&mut self) -> ParseResult<'a, Expr> {
+        let op = match self.peek() {
+            Some(Token::Minus) => UnaryOp::Neg,
+            Some(Token::Bang) => UnaryOp::Not,
+            _ => return self.parse_postfix_expr(),
+        };
+
+        let start = self.next().unwrap().span.start;
+        let expr = Box::new(self.parse_unary_expr()?);
+        Ok(Expr {
+            kind: ExprKind::Unary { op, expr },
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses a primary expression followed by any number of calls, field accesses and
+    /// indexes, such as `a.b.c(d)[e]`.
+    fn parse_postfix_expr(&mut self) -> ParseResult<'a, Expr> {
+        let start = self.peek_span().start;
+        let mut expr = self.parse_primary_expr()?;
+
+        loop {
+            let kind = match self.peek() {
+                Some(Token::LParen) => ExprKind::Call {
+                    callee: Box::new(expr),
+                    args: self.parse_call_args()?,
+                },
+                Some(Token::Period) => {
+                    self.next();
+                    let name = self.expect_ident()?;
+                    if self.at(&Token::LParen) {
+                        ExprKind::MethodCall {
+                            receiver: Box::new(expr),
+                            method: name,
+                            args: self.parse_call_args()?,
+                        }
+                    } else {
+                        ExprKind::Field {
+                            base: Box::new(expr),
+                            field: name,
+                        }
+                    }
+                }
+                Some(Token::LSquare) => {
+                    self.next();
+                    let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+                    let index = self.parse_expr()?;
+                    self.struct_literals = struct_literals;
+                    self.expect(&Token::RSquare)?;
+                    ExprKind::Index {
+                        base: Box::new(expr),
+                        index: Box::new(index),
+                    }
+                }
+                Some(Token::Question) => {
+                    self.next();
+                    ExprKind::Try(Box::new(expr))
+                }
+                _ => break,
+            };
+
+            expr = Expr {
+                kind,
+                span: self.span_from(start),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses the `(arg, ...)` arguments of a call.
+    fn parse_call_args(&mut self) -> ParseResult<'a, Vec<Expr>> {
+        self.expect(&Token::LParen)?;
+        let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+        let mut args = Vec::new();
+        while !self.eat(&Token::RParen) {
+            args.push(self.parse_expr()?);
+
+            if !self.eat(&Token::Comma) {
+                self.expect(&Token::RParen)?;
+                break;
+            }
+        }
+        self.struct_literals = struct_literals;
+
+        Ok(args)
+    }
+
+    /// Parses an expression that starts with a token rather than an operator, such as a literal,
+    /// path, block or loop.
+    fn parse_primary_expr(&mut self) -> ParseResult<'a, Expr> {
+        let start = self.peek_span().start;
+
+        let kind = match self.peek() {
+            Some(Token::Ident(_) | Token::SelfValue | Token::Super) => {
+                let path = self.parse_path()?;
+                // A lone `self` is the receiver, never the name of a struct
+                let receiver = path.segments.len() == 1 && path.segments[0].name == "self";
+                if self.struct_literals && !receiver && self.at(&Token::LBrace) {
+                    ExprKind::StructLit {
+                        path,
+                        fields: self.parse_field_inits()?,
+                    }
+                } else {
+                    ExprKind::Path(path)
+                }
+            }
+            Some(Token::LBrace) => ExprKind::Block(self.parse_block()?),
+            Some(Token::If) => {
+                self.next();
+                self.parse_if_expr()?
+            }
+            Some(Token::For) => {
+                self.next();
+                let pattern = self.parse_pattern()?;
+                self.expect(&Token::In)?;
+                let iter = Box::new(self.parse_cond_expr()?);
+                let body = self.parse_block()?;
+                ExprKind::For(ForLoop {
+                    pattern,
+                    iter,
+                    body,
+                })
+            }
+            Some(Token::Or | Token::OrOr) => ExprKind::Closure(self.parse_closure()?),
+            Some(Token::Match) => {
+                self.next();
+                ExprKind::Match(self.parse_match_expr()?)
+            }
+            Some(Token::While) => {
+                self.next();
+                let cond = Box::new(self.parse_cond_expr()?);
+                let body = self.parse_block()?;
+                ExprKind::While { cond, body }
+            }
+            Some(Token::Loop) => {
+                self.next();
+                ExprKind::Loop(self.parse_block()?)
+            }
+            Some(Token::LParen) => {
+                self.next();
+                let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+                let mut exprs = Vec::new();
+                let mut trailing_comma = false;
+                while !self.eat(&Token::RParen) {
+                    exprs.push(self.parse_expr()?);
+
+                    trailing_comma = self.eat(&Token::Comma);
+                    if !trailing_comma {
+                        self.expect(&Token::RParen)?;
+                        break;
+                    }
+                }
+                self.struct_literals = struct_literals;
+
+                // `(a)` is just a parenthesized expression, while `(a,)` is a tuple
+                if exprs.len() == 1 && !trailing_comma {
+                    ExprKind::Paren(Box::new(exprs.pop().unwrap()))
+                } else {
+                    ExprKind::Tuple(exprs)
+                }
+            }
+            Some(Token::LSquare) => {
+                self.next();
+                let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+                let mut exprs = Vec::new();
+                while !self.eat(&Token::RSquare) {
+                    exprs.push(self.parse_expr()?);
+
+                    if !self.eat(&Token::Comma) {
+                        self.expect(&Token::RSquare)?;
+                        break;
+                    }
+                }
+                self.struct_literals = struct_literals;
+                ExprKind::Array(exprs)
+            }
+            Some(token) if literal(token).is_some() => {
+                ExprKind::Literal(literal(&self.next().unwrap().token).unwrap())
+            }
+            _ => return Err(self.error_expected("expression")),
+        };
+
+        Ok(Expr {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+}
+
+impl<'a> Parser<'a> {
+    /// Parses a `|params| body` closure.
+    fn parse_closure(&mut self) -> ParseResult<'a, Closure> {
+        let mut params = Vec::new();
+        // `||` is lexed as a single token when there are no parameters
+        if !self.eat(&Token::OrOr) {
+            self.expect(&Token::Or)?;
+            while !self.eat(&Token::Or) {
+                let start = self.peek_span().start;
+                let pattern = self.parse_pattern()?;
+                let ty = self.parse_type_annotation(&[Token::Comma, Token::Or])?;
+                params.push(ClosureParam {
+                    pattern,
+                    ty,
+                    span: self.span_from(start),
+                });
+
+                if !self.eat(&Token::Comma) {
+                    self.expect(&Token::Or)?;
+                    break;
+                }
+            }
+        }
+        let body = Box::new(self.parse_expr()?);
+
+        Ok(Closure { params, body })
+    }
+
+    /// Parses the rest of a `match` expression after the `match` keyword.
+    fn parse_match_expr(&mut self) -> ParseResult<'a, MatchExpr> {
+        let scrutinee = Box::new(self.parse_cond_expr()?);
+        self.expect(&Token::LBrace)?;
+        let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+        let mut arms = Vec::new();
+        while !self.eat(&Token::RBrace) {
+            let start = self.peek_span().start;
+            let pattern = self.parse_pattern()?;
+            self.expect(&Token::EqArrow)?;
+            let body = self.parse_expr()?;
+            let block_like = body.kind.is_block_like();
+            arms.push(MatchArm {
+                pattern,
+                body,
+                span: self.span_from(start),
+            });
+
+            // The comma after an arm ending in a block can be left out
+            if !self.eat(&Token::Comma) && !block_like {
+                self.expect(&Token::RBrace)?;
+                break;
+            }
+        }
+        self.struct_literals = struct_literals;
+
+        Ok(MatchExpr { scrutinee, arms })
+    }
+
+    /// Parses a pattern.
+    fn parse_pattern(&mut self) -> ParseResult<'a, Pattern> {
+        let start = self.peek_span().start;
+        let kind = match self.peek() {
+            Some(Token::Ident(name)) if name == "_" => {
+                self.next();
+                PatternKind::Wildcard
+            }
+            Some(Token::LParen) => {
+                self.next();
+                let mut patterns = Vec::new();
+                let mut trailing_comma = false;
+                while !self.eat(&Token::RParen) {
+                    patterns.push(self.parse_pattern()?);
+
+                    trailing_comma = self.eat(&Token::Comma);
+                    if !trailing_comma {
+                        self.expect(&Token::RParen)?;
+                        break;
+                    }
+                }
+
+                // `(a)` is just a parenthesized pattern, while `(a,)` is a tuple
+                if patterns.len() == 1 && !trailing_comma {
+                    patterns.pop().unwrap().kind
+                } else {
+                    PatternKind::Tuple(patterns)
+                }
+            }
+            Some(Token::Ident(_)) => {
+                let mut path = self.parse_path()?;
+                match self.peek() {
+                    Some(Token::LParen) => {
+                        self.next();
+                        let mut fields = Vec::new();
+                        while !self.eat(&Token::RParen) {
+                            fields.push(self.parse_pattern()?);
+
+                            if !self.eat(&Token::Comma) {
+                                self.expect(&Token::RParen)?;
+                                break;
+                            }
+                        }
+                        PatternKind::TupleVariant { path, fields }
+                    }
+                    Some(Token::LBrace) => PatternKind::StructVariant {
+                        path,
+                        fields: self.parse_field_patterns()?,
+                    },
+                    _ if path.segments.len() == 1 => PatternKind::Ident(path.segments.remove(0)),
+                    _ => PatternKind::Path(path),
+                }
+            }
+            Some(Token::Mut) => {
+                self.next();
+                PatternKind::Mut(self.expect_ident()?)
+            }
+            Some(token) if literal(token).is_some() => {
+                PatternKind::Literal(literal(&self.next().unwrap().token).unwrap())
+            }
+            _ => return Err(self.error_expected("pattern")),
+        };
+
+        Ok(Pattern {
+            kind,
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses the `{ field: pattern, ... }` fields of a struct variant pattern.
+    fn parse_field_patterns(&mut self) -> ParseResult<'a, Vec<FieldPattern>> {
+        self.expect(&Token::LBrace)?;
+        let mut fields = Vec::new();
+        while !self.eat(&Token::RBrace) {
+            let start = self.peek_span().start;
+            // `Rect { mut w }` is shorthand for `Rect { w: mut w }`
+            let mutable = self.eat(&Token::Mut);
+            let name = self.expect_ident()?;
+            let pattern = if !mutable && self.eat(&Token::Colon) {
+                self.parse_pattern()?
+            } else {
+                Pattern {
+                    kind: if mutable {
+                        PatternKind::Mut(name.clone())
+                    } else {
+                        PatternKind::Ident(name.clone())
+                    },
+                    span: self.span_from(start),
+                }
+            };
+            fields.push(FieldPattern {
+                name,
+                pattern,
+                span: self.span_from(start),
+            });
+
+            if !self.eat(&Token::Comma) {
+                self.expect(&Token::RBrace)?;
+                break;
+            }
+        }
+
+        Ok(fields)
+    }
+
+    /// Parses the `{ field: value, ... }` fields of a struct literal.
+    fn parse_field_inits(&mut self) -> ParseResult<'a, Vec<FieldInit>> {
+        self.expect(&Token::LBrace)?;
+        let mut fields = Vec::new();
+        while !self.eat(&Token::RBrace) {
+            let start = self.peek_span().start;
+            let name = self.expect_ident()?;
+            let value = if self.eat(&Token::Colon) {
+                self.parse_expr()?
+            } else {
+                Expr {
+                    kind: ExprKind::Path(Path {
+                        segments: vec![name.clone()],
+                        span: name.span.clone(),
+                    }),
+                    span: name.span.clone(),
+                }
+            };
+            fields.push(FieldInit {
+                name,
+                value,
+                span: self.span_from(start),
+            });
+
+            if !self.eat(&Token::Comma) {
+                self.expect(&Token::RBrace)?;
+                break;
+            }
+        }
+
+        Ok(fields)
+    }
+}
+
+/// Returns the literal value of a token, if it is a literal.
+fn literal(token: &Token) -> Option<Literal> {
+    Some(match token {
+        Token::Integer(value) => Literal::Integer(*value),
+        Token::OversizedInteger(value) => Literal::OversizedInteger(value.clone()),
+        Token::Float(value) => Literal::Float(*value),
+        Token::String(value) => Literal::String(value.clone()),
+        Token::Char(value) => Literal::Char(*value),
+        Token::Bool(value) => Literal::Bool(*value),
+        _ => return None,
+    })
+}
+
+/// The left binding power of `..` and `..=`, which bind looser than `||` but tighter than
+/// assignment. Ranges don't chain, so the end is parsed with a higher binding power.
+const RANGE_BP: u8 = 4;
+
+/// The left binding power of `as`, which binds tighter than every binary operator, so
+/// `a * b as float` only converts `b`. Casts chain from left to right.
+const CAST_BP: u8 = 24;
+
+/// An operator that goes between two expressions.
+#[derive(Debug, Clone, Copy)]
+enum InfixOp {
+    Binary(BinaryOp),
+    Assign,
+    CompoundAssign(BinaryOp),
+    /// `..` or `..=`, which is handled separately as the end is optional.
+    Range,
+    /// `as`, which is handled separately as it's followed by a type.
+    Cast,
+}
+
+/// Returns the infix operator for a token, along with its left and right binding powers. A left
+/// binding power lower than the right makes the operator left associative, and vice versa.
+fn infix_op(token: &Token) -> Option<(InfixOp, u8, u8)> {
+    use BinaryOp::*;
+
+    let (op, precedence) = match token {
+        Token::Eq => return Some((InfixOp::Assign, 2, 1)),
+        Token::PlusEq => return Some((InfixOp::CompoundAssign(Add), 2, 1)),
+        Token::MinusEq => return Some((InfixOp::CompoundAssign(Sub), 2, 1)),
+        Token::StarEq => return Some((InfixOp::CompoundAssign(Mul), 2, 1)),
+        Token::SlashEq => return Some((InfixOp::CompoundAssign(Div), 2, 1)),
+        Token::ModulusEq => return Some((InfixOp::CompoundAssign(Rem), 2, 1)),
+        Token::AndEq => return Some((InfixOp::CompoundAssign(BitAnd), 2, 1)),
+        Token::OrEq => return Some((InfixOp::CompoundAssign(BitOr), 2, 1)),
+        Token::CaretEq => return Some((InfixOp::CompoundAssign(BitXor), 2, 1)),
+        Token::ShlEq => return Some((InfixOp::CompoundAssign(Shl), 2, 1)),
+        Token::ShrEq => return Some((InfixOp::CompoundAssign(Shr), 2, 1)),
+
+        Token::DotDot | Token::DotDotEq => return Some((InfixOp::Range, RANGE_BP, RANGE_BP + 1)),
+        Token::As => return Some((InfixOp::Cast, CAST_BP, CAST_BP + 1)),
+
+        Token::OrOr => (Or, 3),
+        Token::AndAnd => (And, 4),
+        Token::EqEq => (Eq, 5),
+        Token::Ne => (Ne, 5),
+        Token::EqEqEq => (TripleEq, 5),
+        Token::Nee => (TripleNe, 5),
+        Token::Less => (Lt, 5),
+        Token::LessEq => (Le, 5),
+        Token::Greater => (Gt, 5),
+        Token::GreaterEq => (Ge, 5),
+        Token::Or => (BitOr, 6),
+        Token::Caret => (BitXor, 7),
+        Token::And => (BitAnd, 8),
+        Token::Shl => (Shl, 9),
+        Token::Shr => (Shr, 9),
+        Token::Plus => (Add, 10),
+        Token::Minus => (Sub, 10),
+        Token::Star => (Mul, 11),
+        Token::Slash => (Div, 11),
+        Token::Modulus => (Rem, 11),
+        _ => return None,
+    };
+
+    // Binding powers are spaced out so each precedence level has a distinct pair
+    Some((InfixOp::Binary(op), precedence * 2, precedence * 2 + 1))
+}
+
+/// Parses a source file into an AST.
+pub fn parse_source(source: &str) -> Result<Program, Vec<ParseError<'_>>> {
+    let mut parser = Parser::new(source);
+    let program = parser.parse_program();
+    if parser.errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(parser.into_errors())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ident(name: &str, span: Span) -> Ident {
+        Ident {
+            name: name.to_string(),
+            span,
+        }
+    }
+
+    #[test]
+    fn test_parse_empty_source() {
+        let program = parse_source("").unwrap();
+
+        assert!(program.items.is_empty());
+    }
+
+    #[test]
+    fn test_parse_globals() {
+        let source = "/// The answer\nconst ANSWER: i32 = 42;\nstatic NAME = \"ruffle\";";
+        let program = parse_source(source).unwrap();
+
+        assert_eq!(program.items.len(), 2);
+
+        let item = &program.items[0];
+        assert_eq!(item.docs, vec![" The answer".to_string()]);
+        assert_eq!(&source[item.span.clone()], "const ANSWER: i32 = 42;");
+
+        let ItemKind::Const(decl) = &item.kind else {
+            panic!("expected const, found {:?}", item.kind);
+        };
+        assert_eq!(decl.name, ident("ANSWER", 21..27));
+        assert_eq!(
+            decl.ty,
+            Some(TypeExpr {
+                kind: TypeExprKind::Path(Path {
+                    segments: vec![ident("i32", 29..32)],
+                    span: 29..32,
+                }),
+                span: 29..32,
+            })
+        );
+        assert_eq!(decl.value.kind, ExprKind::Literal(Literal::Integer(42)));
+
+        let ItemKind::Static(decl) = &program.items[1].kind else {
+            panic!("expected static, found {:?}", program.items[1].kind);
+        };
+        assert_eq!(decl.ty, None);
+        assert_eq!(
+            decl.value.kind,
+            ExprKind::Literal(Literal::String("ruffle".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_type_annotation_without_colon() {
+        let program = parse_source("const X u8 = 1;").unwrap();
+
+        let ItemKind::Const(decl) = &program.items[0].kind else {
+            panic!("expected const, found {:?}", program.items[0].kind);
+        };
+        assert_eq!(decl.ty.as_ref().unwrap().span, 8..10);
+    }
+
+    #[test]
+    fn test_parse_paths_and_parens() {
+        let program = parse_source("const E = (Error::OhNo);").unwrap();
+
+        let ItemKind::Const(decl) = &program.items[0].kind else {
+            panic!("expected const, found {:?}", program.items[0].kind);
+        };
+        let ExprKind::Paren(inner) = &decl.value.kind else {
+            panic!("expected parentheses, found {:?}", decl.value.kind);
+        };
+        assert_eq!(
+            inner.kind,
+            ExprKind::Path(Path {
+                segments: vec![ident("Error", 11..16), ident("OhNo", 18..22)],
+                span: 11..22,
+            })
+        );
+    }
+
+    /// Parses an expression and prints it with explicit parentheses.
+    fn parenthesize(source: &str) -> String {
+        fn print(expr: &Expr) -> String {
+            match &expr.kind {
+                ExprKind::Literal(Literal::Integer(value)) => value.to_string(),
+                ExprKind::Path(path) => path.segments[0].name.clone(),
+                ExprKind::Paren(inner) => print(inner),
+                ExprKind::Binary { op, lhs, rhs } => {
+                    format!("({} {} {})", print(lhs), op.as_str(), print(rhs))
+                }
+                ExprKind::Assign { target, value } => {
+                    format!("({} = {})", print(target), print(value))
+                }
+                ExprKind::CompoundAssign { op, target, value } => {
+                    format!("({} {}= {})", print(target), op.as_str(), print(value))
+                }
+                ExprKind::Unary { op, expr } => format!("({}{})", op.as_str(), print(expr)),
+                ExprKind::Cast { expr, ty } => {
+                    format!("({} as {})", print(expr), crate::pretty::print_type(ty))
+                }
+                _ => crate::pretty::print_expr(expr),
+            }
+        }
+
+        print(&Parser::new(source).parse_expr().unwrap())
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        assert_eq!(
+            parenthesize("1 + 2 * 3 == 7 && x"),
+            "(((1 + (2 * 3)) == 7) && x)"
+        );
+        assert_eq!(parenthesize("a || b && c"), "(a || (b && c))");
+        assert_eq!(parenthesize("a | b ^ c & d"), "(a | (b ^ (c & d)))");
+        assert_eq!(parenthesize("1 << 2 + 3"), "(1 << (2 + 3))");
+        assert_eq!(
+            parenthesize("x === 0 || x !== 1"),
+            "((x === 0) || (x !== 1))"
+        );
+        assert_eq!(parenthesize("(1 + 2) * 3"), "((1 + 2) * 3)");
+        assert_eq!(parenthesize("a * b as float"), "(a * (b as float))");
+        assert_eq!(parenthesize("-x as int as char"), "(((-x) as int) as char)");
+    }
+
+    #[test]
+    fn test_parse_associativity() {
+        assert_eq!(parenthesize("1 - 2 - 3"), "((1 - 2) - 3)");
+        assert_eq!(parenthesize("8 / 4 % 3"), "((8 / 4) % 3)");
+        assert_eq!(parenthesize("a = b = c"), "(a = (b = c))");
+        assert_eq!(parenthesize("a += b -= 1 + 2"), "(a += (b -= (1 + 2)))");
+        assert_eq!(parenthesize("a <<= b >>= c"), "(a <<= (b >>= c))");
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        let source = "{ let x: i32 = 1; let y u8; let z = x + 1; x = z; return x; return; }";
+        let block = Parser::new(source).parse_block().unwrap();
+
+        assert_eq!(block.span, 0..source.len());
+        assert_eq!(block.stmts.len(), 6);
+
+        let StmtKind::Let { pattern, ty, value } = &block.stmts[0].kind else {
+            panic!("expected let, found {:?}", block.stmts[0].kind);
+        };
+        assert!(matches!(&pattern.kind, PatternKind::Ident(name) if name.name == "x"));
+        assert!(ty.is_some());
+        assert_eq!(
+            value.as_ref().unwrap().kind,
+            ExprKind::Literal(Literal::Integer(1))
+        );
+        assert_eq!(&source[block.stmts[0].span.clone()], "let x: i32 = 1;");
+
+        let StmtKind::Let { ty, value, .. } = &block.stmts[1].kind else {
+            panic!("expected let, found {:?}", block.stmts[1].kind);
+        };
+        assert_eq!(&source[ty.as_ref().unwrap().span.clone()], "u8");
+        assert!(value.is_none());
+
+        let StmtKind::Let { ty, .. } = &block.stmts[2].kind else {
+            panic!("expected let, found {:?}", block.stmts[2].kind);
+        };
+        assert!(ty.is_none());
+
+        assert!(matches!(
+            &block.stmts[3].kind,
+            StmtKind::Expr(Expr {
+                kind: ExprKind::Assign { .. },
+                ..
+            })
+        ));
+        assert!(matches!(&block.stmts[4].kind, StmtKind::Return(Some(_))));
+        assert!(matches!(&block.stmts[5].kind, StmtKind::Return(None)));
+    }
+
+    #[test]
+    fn test_parse_missing_semicolon() {
+        let mut parser = Parser::new("{ let x = 1 }");
+        let block = parser.parse_block().unwrap();
+
+        assert_eq!(block.stmts[0].kind, StmtKind::Error);
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!(
+            parser.errors()[0].error,
+            ParsingError::Expected {
+                expected: "`;`".to_string(),
+                found: Token::RBrace,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_if_expressions() {
+        let source = "{ if a { b = 1; } let x = if a { 1 } else if b { 2 } else { 3 }; x }";
+        let block = Parser::new(source).parse_block().unwrap();
+
+        assert_eq!(block.stmts.len(), 2);
+        assert!(matches!(
+            &block.stmts[0].kind,
+            StmtKind::Expr(Expr {
+                kind: ExprKind::If {
+                    else_branch: None,
+                    ..
+                },
+                ..
+            })
+        ));
+        assert!(matches!(
+            block.tail.as_deref(),
+            Some(Expr {
+                kind: ExprKind::Path(_),
+                ..
+            })
+        ));
+
+        let StmtKind::Let {
+            value: Some(value), ..
+        } = &block.stmts[1].kind
+        else {
+            panic!("expected let, found {:?}", block.stmts[1].kind);
+        };
+        let ExprKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } = &value.kind
+        else {
+            panic!("expected if, found {:?}", value.kind);
+        };
+        assert_eq!(
+            then_branch.tail.as_deref().map(|tail| &tail.kind),
+            Some(&ExprKind::Literal(Literal::Integer(1)))
+        );
+        assert!(matches!(else_branch.kind, ExprKind::If { .. }));
+        assert_eq!(&source[else_branch.span.clone()], "if b { 2 } else { 3 }");
+    }
+
+    #[test]
+    fn test_parse_if_condition_struct_literal() {
+        let source = "if p == Point { x: 1 } {}";
+        let mut parser = Parser::new(source);
+        parser.parse_expr().unwrap();
+        assert!(matches!(
+            parser.errors()[0].error,
+            ParsingError::Expected {
+                found: Token::Colon,
+                ..
+            }
+        ));
+
+        let source = "if p == (Point { x: 1 }) {}";
+        let expr = Parser::new(source).parse_expr().unwrap();
+        assert!(matches!(expr.kind, ExprKind::If { .. }));
+    }
+
+    #[test]
+    fn test_parse_while_loops() {
+        let source = "{ while i < 10 { if i == 5 { break; } i += 1; continue; } }";
+        let block = Parser::new(source).parse_block().unwrap();
+
+        let Some(Expr {
+            kind: ExprKind::While { cond, body },
+            ..
+        }) = block.tail.as_deref()
+        else {
+            panic!("expected while, found {:?}", block.tail);
+        };
+        assert_eq!(&source[cond.span.clone()], "i < 10");
+        assert_eq!(body.stmts.len(), 3);
+        assert_eq!(body.stmts[2].kind, StmtKind::Continue);
+
+        let StmtKind::Expr(Expr {
+            kind: ExprKind::If { then_branch, .. },
+            ..
+        }) = &body.stmts[0].kind
+        else {
+            panic!("expected if, found {:?}", body.stmts[0].kind);
+        };
+        assert_eq!(then_branch.stmts[0].kind, StmtKind::Break);
+
+        let source = "{ loop { break; } 1 }";
+        let block = Parser::new(source).parse_block().unwrap();
+        let StmtKind::Expr(Expr {
+            kind: ExprKind::Loop(body),
+            ..
+        }) = &block.stmts[0].kind
+        else {
+            panic!("expected loop, found {:?}", block.stmts[0].kind);
+        };
+        assert_eq!(body.stmts[0].kind, StmtKind::Break);
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        let expr = Parser::new("a + 1..b * 2").parse_expr().unwrap();
+        let ExprKind::Range {
+            start: Some(start),
+            end: Some(end),
+            inclusive: false,
+        } = &expr.kind
+        else {
+            panic!("expected range, found {:?}", expr.kind);
+        };
+        assert!(matches!(
+            start.kind,
+            ExprKind::Binary {
+                op: BinaryOp::Add,
+                ..
+            }
+        ));
+        assert!(matches!(
+            end.kind,
+            ExprKind::Binary {
+                op: BinaryOp::Mul,
+                ..
+            }
+        ));
+
+        let expr = Parser::new("..=10").parse_expr().unwrap();
+        assert!(matches!(
+            expr.kind,
+            ExprKind::Range {
+                start: None,
+                end: Some(_),
+                inclusive: true
+            }
+        ));
+
+        let expr = Parser::new("(1..)").parse_expr().unwrap();
+        assert!(matches!(
+            &expr.kind,
+            ExprKind::Paren(inner) if matches!(inner.kind, ExprKind::Range { end: None, .. })
+        ));
+
+        assert!(Parser::new("(1..=)").parse_expr().is_err());
+    }
+
+    #[test]
+    fn test_parse_for_loops() {
+        let source = "{ for i in 0..10 { total += i; } for x in items {} }";
+        let block = Parser::new(source).parse_block().unwrap();
+
+        let StmtKind::Expr(Expr {
+            kind: ExprKind::For(for_loop),
+            ..
+        }) = &block.stmts[0].kind
+        else {
+            panic!("expected for, found {:?}", block.stmts[0].kind);
+        };
+        assert!(matches!(&for_loop.pattern.kind, PatternKind::Ident(ident) if ident.name == "i"));
+        assert!(matches!(for_loop.iter.kind, ExprKind::Range { .. }));
+        assert_eq!(for_loop.body.stmts.len(), 1);
+
+        let Some(Expr {
+            kind: ExprKind::For(for_loop),
+            ..
+        }) = block.tail.as_deref()
+        else {
+            panic!("expected for, found {:?}", block.tail);
+        };
+        assert_eq!(&source[for_loop.iter.span.clone()], "items");
+    }
+
+    #[test]
+    fn test_parse_match_expressions() {
+        let source = r#"match shape {
+            Shape::Circle(r) => r,
+            Shape::Rect { w, h: 1.0 } => w,
+            Shape::Empty => 0.0,
+            "literal" => 1.0,
+            other => 2.0,
+            _ => 3.0
+        }"#;
+        let expr = Parser::new(source).parse_expr().unwrap();
+
+        let ExprKind::Match(match_expr) = &expr.kind else {
+            panic!("expected match, found {:?}", expr.kind);
+        };
+        assert_eq!(&source[match_expr.scrutinee.span.clone()], "shape");
+        assert_eq!(match_expr.arms.len(), 6);
+
+        let patterns: Vec<_> = match_expr
+            .arms
+            .iter()
+            .map(|arm| &arm.pattern.kind)
+            .collect();
+        assert!(
+            matches!(patterns[0], PatternKind::TupleVariant { fields, .. } if fields.len() == 1)
+        );
+        let PatternKind::StructVariant { fields, .. } = patterns[1] else {
+            panic!("expected struct variant, found {:?}", patterns[1]);
+        };
+        assert!(matches!(&fields[0].pattern.kind, PatternKind::Ident(name) if name.name == "w"));
+        assert_eq!(
+            fields[1].pattern.kind,
+            PatternKind::Literal(Literal::Float(1.0))
+        );
+        assert!(matches!(patterns[2], PatternKind::Path(path) if path.segments.len() == 2));
+        assert_eq!(
+            patterns[3],
+            &PatternKind::Literal(Literal::String("literal".to_string()))
+        );
+        assert!(matches!(patterns[4], PatternKind::Ident(_)));
+        assert_eq!(patterns[5], &PatternKind::Wildcard);
+
+        assert_eq!(
+            &source[match_expr.arms[1].span.clone()],
+            "Shape::Rect { w, h: 1.0 } => w"
+        );
+    }
+
+    #[test]
+    fn test_parse_closures() {
+        let source = "|x, y: int| x + y";
+        let expr = Parser::new(source).parse_expr().unwrap();
+
+        let ExprKind::Closure(closure) = &expr.kind else {
+            panic!("expected closure, found {:?}", expr.kind);
+        };
+        assert_eq!(closure.params.len(), 2);
+        assert!(closure.params[0].ty.is_none());
+        assert_eq!(&source[closure.params[1].span.clone()], "y: int");
+        assert!(matches!(
+            closure.body.kind,
+            ExprKind::Binary {
+                op: BinaryOp::Add,
+                ..
+            }
+        ));
+        assert_eq!(expr.span, 0..source.len());
+
+        let expr = Parser::new("callback = || 1").parse_expr().unwrap();
+        let ExprKind::Assign { value, .. } = &expr.kind else {
+            panic!("expected assignment, found {:?}", expr.kind);
+        };
+        assert!(matches!(&value.kind, ExprKind::Closure(closure) if closure.params.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_block_expressions() {
+        let source = "fn f() int { let x = { let y = 1; y * 2 }; { x; } match x { _ => { x } } }";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Fn(f) = &program.items[0].kind else {
+            panic!("expected function, found {:?}", program.items[0].kind);
+        };
+        assert_eq!(f.body.stmts.len(), 2);
+
+        let StmtKind::Let {
+            value: Some(value), ..
+        } = &f.body.stmts[0].kind
+        else {
+            panic!("expected let, found {:?}", f.body.stmts[0].kind);
+        };
+        let ExprKind::Block(block) = &value.kind else {
+            panic!("expected block, found {:?}", value.kind);
+        };
+        assert_eq!(block.stmts.len(), 1);
+        assert_eq!(&source[block.tail.as_ref().unwrap().span.clone()], "y * 2");
+
+        let StmtKind::Expr(Expr {
+            kind: ExprKind::Block(block),
+            ..
+        }) = &f.body.stmts[1].kind
+        else {
+            panic!("expected block, found {:?}", f.body.stmts[1].kind);
+        };
+        assert!(block.tail.is_none());
+
+        let Some(Expr {
+            kind: ExprKind::Match(match_expr),
+            ..
+        }) = f.body.tail.as_deref()
+        else {
+            panic!("expected match, found {:?}", f.body.tail);
+        };
+        assert!(matches!(match_expr.arms[0].body.kind, ExprKind::Block(_)));
+    }
+
+    #[test]
+    fn test_parse_types() {
+        let parse_type = |source| Parser::new(source).parse_type().unwrap().kind;
+
+        let TypeExprKind::Generic { path, args } = parse_type("Map<string, Vec<Vec<int>>>") else {
+            panic!("expected generic type");
+        };
+        assert_eq!(path.segments[0].name, "Map");
+        assert_eq!(args.len(), 2);
+        let TypeExprKind::Generic { args, .. } = &args[1].kind else {
+            panic!("expected generic type, found {:?}", args[1].kind);
+        };
+        assert!(matches!(&args[0].kind, TypeExprKind::Generic { args, .. } if args.len() == 1));
+
+        assert_eq!(parse_type("()"), TypeExprKind::Tuple(Vec::new()));
+        assert!(
+            matches!(parse_type("(int, bool)"), TypeExprKind::Tuple(types) if types.len() == 2)
+        );
+        assert!(matches!(parse_type("(int,)"), TypeExprKind::Tuple(types) if types.len() == 1));
+        assert!(matches!(parse_type("(int)"), TypeExprKind::Path(_)));
+
+        let TypeExprKind::Fn { params, ret } = parse_type("fn(int, fn()) -> int") else {
+            panic!("expected function type");
+        };
+        assert_eq!(params.len(), 2);
+        assert!(matches!(params[1].kind, TypeExprKind::Fn { ret: None, .. }));
+        assert!(ret.is_some());
+
+        assert!(matches!(
+            parse_type("[int]"),
+            TypeExprKind::Array { len: None, .. }
+        ));
+        assert!(matches!(
+            parse_type("[[u8; 4]; 2]"),
+            TypeExprKind::Array { len: Some(_), .. }
+        ));
+        assert_eq!(parse_type("!"), TypeExprKind::Never);
+    }
+
+    #[test]
+    fn test_parse_split_closing_angles() {
+        let source = "{ let v: Vec<Vec<int>>= x; }";
+        let block = Parser::new(source).parse_block().unwrap();
+
+        let StmtKind::Let {
+            ty: Some(ty),
+            value: Some(value),
+            ..
+        } = &block.stmts[0].kind
+        else {
+            panic!("expected let, found {:?}", block.stmts[0].kind);
+        };
+        assert_eq!(&source[ty.span.clone()], "Vec<Vec<int>>");
+        assert_eq!(&source[value.span.clone()], "x");
+    }
+
+    #[test]
+    fn test_parse_modules() {
+        let source = "mod shapes;\nmod math { use shapes::circle::area; fn pi() float { 3.0 } }";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Mod(shapes) = &program.items[0].kind else {
+            panic!("expected module, found {:?}", program.items[0].kind);
+        };
+        assert_eq!(shapes.name.name, "shapes");
+        assert!(shapes.items.is_none());
+
+        let ItemKind::Mod(math) = &program.items[1].kind else {
+            panic!("expected module, found {:?}", program.items[1].kind);
+        };
+        let items = math.items.as_ref().unwrap();
+        assert_eq!(items.len(), 2);
+        let ItemKind::Use(path) = &items[0].kind else {
+            panic!("expected use, found {:?}", items[0].kind);
+        };
+        assert_eq!(path.segments.len(), 3);
+        assert_eq!(&source[path.span.clone()], "shapes::circle::area");
+        assert!(matches!(items[1].kind, ItemKind::Fn(_)));
+
+        assert!(parse_source("mod unclosed {").is_err());
+    }
+
+    #[test]
+    fn test_parse_tuples() {
+        let source = r#"(1, "two", 3.0)"#;
+        let expr = Parser::new(source).parse_expr().unwrap();
+        assert!(matches!(&expr.kind, ExprKind::Tuple(exprs) if exprs.len() == 3));
+        assert_eq!(expr.span, 0..source.len());
+
+        let parse_expr = |source| Parser::new(source).parse_expr().unwrap().kind;
+        assert_eq!(parse_expr("()"), ExprKind::Tuple(Vec::new()));
+        assert!(matches!(parse_expr("(1,)"), ExprKind::Tuple(exprs) if exprs.len() == 1));
+        assert!(matches!(parse_expr("(1)"), ExprKind::Paren(_)));
+        assert!(
+            matches!(parse_expr("((1, 2), 3)"), ExprKind::Tuple(exprs) if matches!(exprs[0].kind, ExprKind::Tuple(_)))
+        );
+        assert_eq!(parse_expr("[]"), ExprKind::Array(Vec::new()));
+        assert!(matches!(parse_expr("[1, 2,]"), ExprKind::Array(exprs) if exprs.len() == 2));
+        assert!(matches!(parse_expr("[(1, 2)][0]"), ExprKind::Index { .. }));
+    }
+
+    #[test]
+    fn test_parse_tuple_destructuring() {
+        let source = "{ let (a, (b, _)): (int, (int, int)) = pair; let (c) = a; }";
+        let block = Parser::new(source).parse_block().unwrap();
+
+        let StmtKind::Let { pattern, ty, .. } = &block.stmts[0].kind else {
+            panic!("expected let, found {:?}", block.stmts[0].kind);
+        };
+        let PatternKind::Tuple(patterns) = &pattern.kind else {
+            panic!("expected tuple pattern, found {:?}", pattern.kind);
+        };
+        assert!(matches!(patterns[0].kind, PatternKind::Ident(_)));
+        assert!(
+            matches!(&patterns[1].kind, PatternKind::Tuple(inner) if inner[1].kind == PatternKind::Wildcard)
+        );
+        assert_eq!(&source[pattern.span.clone()], "(a, (b, _))");
+        assert!(matches!(ty.as_ref().unwrap().kind, TypeExprKind::Tuple(_)));
+
+        let StmtKind::Let { pattern, .. } = &block.stmts[1].kind else {
+            panic!("expected let, found {:?}", block.stmts[1].kind);
+        };
+        assert!(matches!(pattern.kind, PatternKind::Ident(_)));
+    }
+
+    #[test]
+    fn test_parse_postfix_chains() {
+        let source = "a.b.c(d, 1)[e] + f()";
+        let expr = Parser::new(source).parse_expr().unwrap();
+
+        let ExprKind::Binary { lhs, rhs, .. } = &expr.kind else {
+            panic!("expected binary expression, found {:?}", expr.kind);
+        };
+        assert!(matches!(&rhs.kind, ExprKind::Call { args, .. } if args.is_empty()));
+
+        let ExprKind::Index { base, index } = &lhs.kind else {
+            panic!("expected index, found {:?}", lhs.kind);
+        };
+        assert_eq!(&source[index.span.clone()], "e");
+        let ExprKind::MethodCall {
+            receiver,
+            method,
+            args,
+        } = &base.kind
+        else {
+            panic!("expected method call, found {:?}", base.kind);
+        };
+        assert_eq!(method.name, "c");
+        assert_eq!(args.len(), 2);
+        assert_eq!(&source[base.span.clone()], "a.b.c(d, 1)");
+        assert!(matches!(&receiver.kind, ExprKind::Field { field, .. } if field.name == "b"));
+    }
+
+    #[test]
+    fn test_parse_self_and_super_paths() {
+        let source = "use super::super::shapes; fn f(p: self::Point) { self.x; self::Point { x: 1 }; super::g(); }";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Use(path) = &program.items[0].kind else {
+            panic!("expected use, found {:?}", program.items[0].kind);
+        };
+        let names: Vec<_> = path.segments.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["super", "super", "shapes"]);
+
+        let ItemKind::Fn(f) = &program.items[1].kind else {
+            panic!("expected function, found {:?}", program.items[1].kind);
+        };
+        assert!(
+            matches!(&f.params[0].ty.kind, TypeExprKind::Path(path) if path.segments[0].name == "self")
+        );
+        let exprs: Vec<_> = f
+            .body
+            .stmts
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StmtKind::Expr(expr) => &expr.kind,
+                kind => panic!("expected expression, found {:?}", kind),
+            })
+            .collect();
+        assert!(
+            matches!(exprs[0], ExprKind::Field { base, .. } if matches!(&base.kind, ExprKind::Path(path) if path.segments.len() == 1))
+        );
+        assert!(matches!(exprs[1], ExprKind::StructLit { path, .. } if path.segments.len() == 2));
+        assert!(matches!(exprs[2], ExprKind::Call { .. }));
+
+        // `super` can only lead a path
+        assert!(parse_source("use a::super::b;").is_err());
+    }
+
+    #[test]
+    fn test_parse_generic_fns() {
+        let source = "fn print_all<T: Printable + shapes::Shape, U>(items: [T], u: U) {} trait A { fn f<T>(t: T); }";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Fn(f) = &program.items[0].kind else {
+            panic!("expected function, found {:?}", program.items[0].kind);
+        };
+        let generics: Vec<_> = f
+            .generics
+            .iter()
+            .map(|param| (param.name.name.as_str(), param.bounds.len()))
+            .collect();
+        assert_eq!(generics, vec![("T", 2), ("U", 0)]);
+        assert_eq!(
+            &source[f.generics[0].span.clone()],
+            "T: Printable + shapes::Shape"
+        );
+        assert_eq!(f.params.len(), 2);
+
+        let ItemKind::Trait(a) = &program.items[1].kind else {
+            panic!("expected trait, found {:?}", program.items[1].kind);
+        };
+        assert_eq!(a.methods[0].generics[0].name.name, "T");
+
+        assert!(parse_source("fn f<T: >() {}").is_err());
+    }
+
+    #[test]
+    fn test_parse_extern_fns() {
+        let source = "extern fn labs(x: i64) i64; pub extern fn srand(seed: u32);";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Extern(labs) = &program.items[0].kind else {
+            panic!("expected extern, found {:?}", program.items[0].kind);
+        };
+        assert_eq!(labs.name.name, "labs");
+        assert_eq!(labs.params.len(), 1);
+        assert!(labs.ret.is_some());
+        assert!(program.items[1].public);
+        assert_eq!(
+            &source[program.items[1].span.clone()],
+            "pub extern fn srand(seed: u32);"
+        );
+
+        assert!(parse_source("extern fn f() {}").is_err());
+        assert!(parse_source("extern fn f<T>(t: T);").is_err());
+        assert!(parse_source("extern struct S;").is_err());
+    }
+
+    #[test]
+    fn test_parse_yield() {
+        let source = "fn f() Gen<int> { yield 1 + 2; yield g(); }";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Fn(f) = &program.items[0].kind else {
+            panic!("expected function, found {:?}", program.items[0].kind);
+        };
+        assert_eq!(f.body.stmts.len(), 2);
+        let StmtKind::Yield(value) = &f.body.stmts[0].kind else {
+            panic!("expected yield, found {:?}", f.body.stmts[0].kind);
+        };
+        assert_eq!(&source[value.span.clone()], "1 + 2");
+        assert_eq!(&source[f.body.stmts[1].span.clone()], "yield g();");
+        assert!(f.body.tail.is_none());
+
+        assert!(parse_source("fn f() Gen<int> { yield; }").is_err());
+        assert!(parse_source("fn f() Gen<int> { yield 1 }").is_err());
+    }
+
+    #[test]
+    fn test_parse_pub_items() {
+        let source =
+            "pub struct P { pub x: int, y: int } fn f() {} impl P { pub fn new() {} fn g() {} }";
+        let program = parse_source(source).unwrap();
+
+        let public: Vec<_> = program.items.iter().map(|item| item.public).collect();
+        assert_eq!(public, vec![true, false, false]);
+        assert_eq!(
+            &source[program.items[0].span.clone()],
+            "pub struct P { pub x: int, y: int }"
+        );
+
+        let ItemKind::Struct(p) = &program.items[0].kind else {
+            panic!("expected struct, found {:?}", program.items[0].kind);
+        };
+        let fields: Vec<_> = p.fields.iter().map(|field| field.public).collect();
+        assert_eq!(fields, vec![true, false]);
+
+        let ItemKind::Impl(decl) = &program.items[2].kind else {
+            panic!("expected impl, found {:?}", program.items[2].kind);
+        };
+        let methods: Vec<_> = decl.methods.iter().map(|item| item.public).collect();
+        assert_eq!(methods, vec![true, false]);
+    }
+
+    #[test]
+    fn test_parse_mut_patterns() {
+        let source = "fn f(mut self, mut n: int) { let (mut a, b) = n; match s { Rect { mut w, h: mut t } => w } }";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Fn(f) = &program.items[0].kind else {
+            panic!("expected function, found {:?}", program.items[0].kind);
+        };
+        assert!(f.params[0].is_self());
+        assert_eq!(&source[f.params[0].span.clone()], "mut self");
+        assert!(matches!(&f.params[1].pattern.kind, PatternKind::Mut(name) if name.name == "n"));
+
+        let StmtKind::Let { pattern, .. } = &f.body.stmts[0].kind else {
+            panic!("expected let, found {:?}", f.body.stmts[0].kind);
+        };
+        let PatternKind::Tuple(patterns) = &pattern.kind else {
+            panic!("expected tuple pattern, found {:?}", pattern.kind);
+        };
+        assert!(matches!(patterns[0].kind, PatternKind::Mut(_)));
+        assert!(matches!(patterns[1].kind, PatternKind::Ident(_)));
+
+        let Some(ExprKind::Match(match_expr)) = f.body.tail.as_ref().map(|tail| &tail.kind) else {
+            panic!("expected match as the tail");
+        };
+        let PatternKind::StructVariant { fields, .. } = &match_expr.arms[0].pattern.kind else {
+            panic!("expected struct pattern");
+        };
+        assert_eq!(&source[fields[0].span.clone()], "mut w");
+        assert!(matches!(&fields[0].pattern.kind, PatternKind::Mut(name) if name.name == "w"));
+        assert!(matches!(&fields[1].pattern.kind, PatternKind::Mut(name) if name.name == "t"));
+    }
+
+    #[test]
+    fn test_parse_binding_patterns() {
+        let source = "fn dist(Point { x, y }: Point, (a, _) (int, int)) { for (i, Shape::Circle(r)) in shapes { let f = |(k, v), _: int| k; } }";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Fn(dist) = &program.items[0].kind else {
+            panic!("expected function, found {:?}", program.items[0].kind);
+        };
+        let [point, pair] = &dist.params[..] else {
+            panic!("expected two parameters, found {:?}", dist.params);
+        };
+        assert!(
+            matches!(&point.pattern.kind, PatternKind::StructVariant { fields, .. } if fields.len() == 2)
+        );
+        assert!(matches!(&point.ty.kind, TypeExprKind::Path(_)));
+        assert!(
+            matches!(&pair.pattern.kind, PatternKind::Tuple(patterns) if matches!(patterns[1].kind, PatternKind::Wildcard))
+        );
+        assert!(matches!(&pair.ty.kind, TypeExprKind::Tuple(_)));
+
+        let Some(tail) = &dist.body.tail else {
+            panic!("expected for loop as the tail");
+        };
+        let ExprKind::For(for_loop) = &tail.kind else {
+            panic!("expected for loop, found {:?}", tail.kind);
+        };
+        assert_eq!(
+            &source[for_loop.pattern.span.clone()],
+            "(i, Shape::Circle(r))"
+        );
+
+        let StmtKind::Let {
+            value: Some(value), ..
+        } = &for_loop.body.stmts[0].kind
+        else {
+            panic!("expected let, found {:?}", for_loop.body.stmts[0].kind);
+        };
+        let ExprKind::Closure(closure) = &value.kind else {
+            panic!("expected closure, found {:?}", value.kind);
+        };
+        assert!(matches!(
+            &closure.params[0].pattern.kind,
+            PatternKind::Tuple(_)
+        ));
+        assert!(closure.params[0].ty.is_none());
+        assert!(matches!(
+            &closure.params[1].pattern.kind,
+            PatternKind::Wildcard
+        ));
+        assert!(closure.params[1].ty.is_some());
+    }
+
+    #[test]
+    fn test_parse_traits_and_impls() {
+        let source = "trait Printable {\n    /// Prints it\n    fn print(self);\n    fn twice(self, sep: string) { self.print(); }\n}\nimpl Printable for Point { fn print(self) {} }\nimpl Point { fn new() Point { Point { x: 0 } } }";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Trait(printable) = &program.items[0].kind else {
+            panic!("expected trait, found {:?}", program.items[0].kind);
+        };
+        assert_eq!(printable.name.name, "Printable");
+        let required: Vec<_> = printable.required().map(|m| &m.name.name).collect();
+        let provided: Vec<_> = printable.provided().map(|m| &m.name.name).collect();
+        assert_eq!(required, vec!["print"]);
+        assert_eq!(provided, vec!["twice"]);
+        assert_eq!(printable.methods[0].docs, vec![" Prints it"]);
+        assert_eq!(
+            &source[printable.methods[0].span.clone()],
+            "fn print(self);"
+        );
+
+        let params = &printable.methods[1].params;
+        assert!(params[0].is_self());
+        assert!(
+            matches!(&params[0].ty.kind, TypeExprKind::Path(path) if path.segments[0].name == "Self")
+        );
+        assert!(
+            matches!(&params[1].pattern.kind, PatternKind::Ident(ident) if ident.name == "sep")
+        );
+
+        let ItemKind::Impl(impl_printable) = &program.items[1].kind else {
+            panic!("expected impl, found {:?}", program.items[1].kind);
+        };
+        let trait_ref = impl_printable.trait_ref.as_ref().unwrap();
+        assert_eq!(trait_ref.segments[0].name, "Printable");
+        assert!(
+            matches!(&impl_printable.self_ty.kind, TypeExprKind::Path(path) if path.segments[0].name == "Point")
+        );
+        assert!(
+            matches!(&impl_printable.methods[0].kind, ItemKind::Fn(decl) if decl.name.name == "print")
+        );
+
+        let ItemKind::Impl(impl_point) = &program.items[2].kind else {
+            panic!("expected impl, found {:?}", program.items[2].kind);
+        };
+        assert!(impl_point.trait_ref.is_none());
+        assert!(matches!(&impl_point.methods[0].kind, ItemKind::Fn(decl) if decl.ret.is_some()));
+
+        let errors = parse_source("impl Point { const X = 1; }").unwrap_err();
+        assert!(
+            matches!(&errors[0].error, ParsingError::Expected { expected, .. } if expected == "`fn`")
+        );
+    }
+
+    #[test]
+    fn test_parse_try_operator() {
+        let source = "-read(path)?.len()? + 1";
+        let expr = Parser::new(source).parse_expr().unwrap();
+
+        let ExprKind::Binary { lhs, .. } = &expr.kind else {
+            panic!("expected binary expression, found {:?}", expr.kind);
+        };
+        let ExprKind::Unary { expr: operand, .. } = &lhs.kind else {
+            panic!("expected unary expression, found {:?}", lhs.kind);
+        };
+        let ExprKind::Try(inner) = &operand.kind else {
+            panic!("expected try, found {:?}", operand.kind);
+        };
+        assert_eq!(&source[inner.span.clone()], "read(path)?.len()");
+        assert!(matches!(&inner.kind, ExprKind::MethodCall { receiver, .. }
+            if matches!(receiver.kind, ExprKind::Try(_))));
+    }
+
+    #[test]
+    fn test_parse_calls_in_conditions() {
+        let source = "if check(Point { x: 1 }) { run(); }";
+        let expr = Parser::new(source).parse_expr().unwrap();
+
+        let ExprKind::If {
+            cond, then_branch, ..
+        } = &expr.kind
+        else {
+            panic!("expected if, found {:?}", expr.kind);
+        };
+        assert!(
+            matches!(&cond.kind, ExprKind::Call { args, .. } if matches!(args[0].kind, ExprKind::StructLit { .. }))
+        );
+        assert_eq!(then_branch.stmts.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_unary_operators() {
+        assert_eq!(parenthesize("-a * b"), "((-a) * b)");
+        assert_eq!(parenthesize("!!a || b"), "((!(!a)) || b)");
+        assert_eq!(parenthesize("-a.b(c)[0]"), "(-a.b(c)[0])");
+        assert_eq!(parenthesize("a - -b"), "(a - (-b))");
+        assert_eq!(parenthesize("-(a + b)"), "(-(a + b))");
+        assert_eq!(parenthesize("!a == b"), "((!a) == b)");
+    }
+
+    #[test]
+    fn test_parse_functions() {
+        let source = "fn main() {}\nfn add(x: i32, y i32,) -> i32 { return x + y; }\nfn one() i32 { return 1; }";
+        let program = parse_source(source).unwrap();
+
+        assert_eq!(program.items.len(), 3);
+
+        let ItemKind::Fn(main) = &program.items[0].kind else {
+            panic!("expected function, found {:?}", program.items[0].kind);
+        };
+        assert_eq!(main.name.name, "main");
+        assert!(main.params.is_empty());
+        assert!(main.ret.is_none());
+        assert!(main.body.stmts.is_empty());
+
+        let ItemKind::Fn(add) = &program.items[1].kind else {
+            panic!("expected function, found {:?}", program.items[1].kind);
+        };
+        assert_eq!(add.params.len(), 2);
+        assert_eq!(&source[add.params[0].span.clone()], "x: i32");
+        assert_eq!(&source[add.params[1].span.clone()], "y i32");
+        assert_eq!(&source[add.ret.as_ref().unwrap().span.clone()], "i32");
+        assert_eq!(add.body.stmts.len(), 1);
+
+        let ItemKind::Fn(one) = &program.items[2].kind else {
+            panic!("expected function, found {:?}", program.items[2].kind);
+        };
+        assert!(one.ret.is_some());
+    }
+
+    #[test]
+    fn test_parse_nested_function() {
+        let block = Parser::new("{ fn inner() {} }").parse_block().unwrap();
+
+        assert!(matches!(
+            &block.stmts[0].kind,
+            StmtKind::Item(Item {
+                kind: ItemKind::Fn(_),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_structs() {
+        let source = "struct Point { x: int, y int }\nstruct Unit {}";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Struct(point) = &program.items[0].kind else {
+            panic!("expected struct, found {:?}", program.items[0].kind);
+        };
+        assert_eq!(point.name.name, "Point");
+        assert_eq!(point.fields.len(), 2);
+        assert_eq!(&source[point.fields[0].span.clone()], "x: int");
+        assert_eq!(&source[point.fields[1].span.clone()], "y int");
+
+        let ItemKind::Struct(unit) = &program.items[1].kind else {
+            panic!("expected struct, found {:?}", program.items[1].kind);
+        };
+        assert!(unit.fields.is_empty());
+    }
+
+    #[test]
+    fn test_parse_enums() {
+        let source = "enum Shape { Circle(float), Rect { w: float, h: float }, Empty, }";
+        let program = parse_source(source).unwrap();
+
+        let ItemKind::Enum(shape) = &program.items[0].kind else {
+            panic!("expected enum, found {:?}", program.items[0].kind);
+        };
+        assert_eq!(shape.name.name, "Shape");
+        assert_eq!(shape.variants.len(), 3);
+        assert!(matches!(&shape.variants[0].kind, VariantKind::Tuple(types) if types.len() == 1));
+        assert!(
+            matches!(&shape.variants[1].kind, VariantKind::Struct(fields) if fields.len() == 2)
+        );
+        assert_eq!(shape.variants[2].kind, VariantKind::Unit);
+        assert_eq!(
+            &source[shape.variants[1].span.clone()],
+            "Rect { w: float, h: float }"
+        );
+    }
+
+    #[test]
+    fn test_parse_struct_literals() {
+        let source = "Point { x: 1, y }";
+        let expr = Parser::new(source).parse_expr().unwrap();
+
+        let ExprKind::StructLit { path, fields } = &expr.kind else {
+            panic!("expected struct literal, found {:?}", expr.kind);
+        };
+        assert_eq!(path.segments[0].name, "Point");
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].value.kind, ExprKind::Literal(Literal::Integer(1)));
+        assert_eq!(fields[1].name.name, "y");
+        assert!(matches!(fields[1].value.kind, ExprKind::Path(_)));
+        assert_eq!(expr.span, 0..source.len());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = &parse_source("const = 1;").unwrap_err()[0];
+        assert_eq!(
+            error.error,
+            ParsingError::Expected {
+                expected: "identifier".to_string(),
+                found: Token::Eq,
+            }
+        );
+        assert_eq!(error.span, 6..7);
+
+        let error = &parse_source("const X = 1").unwrap_err()[0];
+        assert_eq!(
+            error.error,
+            ParsingError::UnexpectedEof {
+                expected: "`;`".to_string(),
+            }
+        );
+
+        let error = &parse_source("const X = @;").unwrap_err()[0];
+        assert_eq!(
+            error.error,
+            ParsingError::Lexing(LexingError::UnexpectedCharacter('@'))
+        );
+    }
+
+    #[test]
+    fn test_parse_error_recovery() {
+        let source = "const = 1;\nfn f() { let = 2; x + ; if x { ) } y }\n}\nstatic S: int = 1;";
+        let mut parser = Parser::new(source);
+        let program = parser.parse_program();
+
+        let errors: Vec<_> = parser.errors().iter().map(ParseError::slice).collect();
+        assert_eq!(errors, vec!["=", "=", ";", ")", "}"]);
+
+        assert_eq!(program.items.len(), 4);
+        assert_eq!(program.items[0].kind, ItemKind::Error);
+        assert_eq!(&source[program.items[0].span.clone()], "const = 1;");
+        assert_eq!(program.items[2].kind, ItemKind::Error);
+        assert!(matches!(program.items[3].kind, ItemKind::Static(_)));
+
+        let ItemKind::Fn(f) = &program.items[1].kind else {
+            panic!("expected function, found {:?}", program.items[1].kind);
+        };
+        assert_eq!(f.body.stmts.len(), 3);
+        assert_eq!(f.body.stmts[0].kind, StmtKind::Error);
+        assert_eq!(&source[f.body.stmts[1].span.clone()], "x + ;");
+        assert!(f.body.tail.is_some());
+    }
+}
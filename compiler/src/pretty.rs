@@ -0,0 +1,758 @@
+//! Prints an AST back out as formatted Ruffle source code.
+
+use crate::ast::*;
+
+/// Prints a program as formatted source code.
+pub fn print_program(program: &Program) -> String {
+    let mut printer = Printer::default();
+    for (i, item) in program.items.iter().enumerate() {
+        if i > 0 {
+            printer.out.push('\n');
+        }
+        printer.item(item);
+        printer.out.push('\n');
+    }
+    printer.out
+}
+
+/// Prints an expression as source code.
+pub fn print_expr(expr: &Expr) -> String {
+    let mut printer = Printer::default();
+    printer.expr(expr);
+    printer.out
+}
+
+/// Prints a type as source code.
+pub fn print_type(ty: &TypeExpr) -> String {
+    let mut printer = Printer::default();
+    printer.ty(ty);
+    printer.out
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+}
+
+impl Printer {
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+
+    /// Prints each element with `print`, separated by `, `.
+    fn comma_separated<T>(&mut self, elems: &[T], mut print: impl FnMut(&mut Self, &T)) {
+        for (i, elem) in elems.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            print(self, elem);
+        }
+    }
+
+    fn ident(&mut self, ident: &Ident) {
+        self.out.push_str(&ident.name);
+    }
+
+    fn path(&mut self, path: &Path) {
+        for (i, segment) in path.segments.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str("::");
+            }
+            self.ident(segment);
+        }
+    }
+
+    fn docs(&mut self, docs: &[String]) {
+        for doc in docs {
+            self.out.push_str("///");
+            self.out.push_str(doc);
+            self.newline();
+        }
+    }
+
+    fn fn_signature(
+        &mut self,
+        name: &Ident,
+        generics: &[GenericParam],
+        params: &[Param],
+        ret: &Option<TypeExpr>,
+    ) {
+        self.out.push_str("fn ");
+        self.ident(name);
+        if !generics.is_empty() {
+            self.out.push('<');
+            self.comma_separated(generics, |p, param| {
+                p.ident(&param.name);
+                for (i, bound) in param.bounds.iter().enumerate() {
+                    p.out.push_str(if i == 0 { ": " } else { " + " });
+                    p.path(bound);
+                }
+            });
+            self.out.push('>');
+        }
+        self.out.push('(');
+        self.comma_separated(params, |p, param| {
+            p.pattern(&param.pattern);
+            if !param.is_self() {
+                p.out.push_str(": ");
+                p.ty(&param.ty);
+            }
+        });
+        self.out.push(')');
+        if let Some(ret) = ret {
+            self.out.push_str(" -> ");
+            self.ty(ret);
+        }
+    }
+
+    fn item(&mut self, item: &Item) {
+        self.docs(&item.docs);
+        if item.public {
+            self.out.push_str("pub ");
+        }
+
+        match &item.kind {
+            ItemKind::Const(decl) => self.global_decl("const", decl),
+            ItemKind::Static(decl) => self.global_decl("static", decl),
+            ItemKind::Fn(decl) => {
+                self.fn_signature(&decl.name, &decl.generics, &decl.params, &decl.ret);
+                self.out.push(' ');
+                self.block(&decl.body);
+            }
+            ItemKind::Extern(decl) => {
+                self.out.push_str("extern ");
+                self.fn_signature(&decl.name, &[], &decl.params, &decl.ret);
+                self.out.push(';');
+            }
+            ItemKind::Struct(decl) => {
+                self.out.push_str("struct ");
+                self.ident(&decl.name);
+                self.out.push(' ');
+                self.field_decls(&decl.fields);
+            }
+            ItemKind::Enum(decl) => {
+                self.out.push_str("enum ");
+                self.ident(&decl.name);
+                self.out.push_str(" {");
+                self.indent += 1;
+                for variant in &decl.variants {
+                    self.newline();
+                    self.ident(&variant.name);
+                    match &variant.kind {
+                        VariantKind::Unit => {}
+                        VariantKind::Tuple(types) => {
+                            self.out.push('(');
+                            self.comma_separated(types, Self::ty);
+                            self.out.push(')');
+                        }
+                        VariantKind::Struct(fields) => {
+                            self.out.push(' ');
+                            self.field_decls(fields);
+                        }
+                    }
+                    self.out.push(',');
+                }
+                self.indent -= 1;
+                if !decl.variants.is_empty() {
+                    self.newline();
+                }
+                self.out.push('}');
+            }
+            ItemKind::Trait(decl) => {
+                self.out.push_str("trait ");
+                self.ident(&decl.name);
+                self.out.push_str(" {");
+                self.indent += 1;
+                for method in &decl.methods {
+                    self.newline();
+                    self.docs(&method.docs);
+                    self.fn_signature(&method.name, &method.generics, &method.params, &method.ret);
+                    match &method.body {
+                        Some(body) => {
+                            self.out.push(' ');
+                            self.block(body);
+                        }
+                        None => self.out.push(';'),
+                    }
+                }
+                self.indent -= 1;
+                if !decl.methods.is_empty() {
+                    self.newline();
+                }
+                self.out.push('}');
+            }
+            ItemKind::Impl(decl) => {
+                self.out.push_str("impl ");
+                if let Some(trait_ref) = &decl.trait_ref {
+                    self.path(trait_ref);
+                    self.out.push_str(" for ");
+                }
+                self.ty(&decl.self_ty);
+                self.out.push_str(" {");
+                self.indent += 1;
+                for method in &decl.methods {
+                    self.newline();
+                    self.item(method);
+                }
+                self.indent -= 1;
+                if !decl.methods.is_empty() {
+                    self.newline();
+                }
+                self.out.push('}');
+            }
+            ItemKind::Mod(decl) => {
+                self.out.push_str("mod ");
+                self.ident(&decl.name);
+                match &decl.items {
+                    Some(items) => {
+                        self.out.push_str(" {");
+                        self.indent += 1;
+                        for item in items {
+                            self.newline();
+                            self.item(item);
+                        }
+                        self.indent -= 1;
+                        if !items.is_empty() {
+                            self.newline();
+                        }
+                        self.out.push('}');
+                    }
+                    None => self.out.push(';'),
+                }
+            }
+            ItemKind::Use(path) => {
+                self.out.push_str("use ");
+                self.path(path);
+                self.out.push(';');
+            }
+            ItemKind::Error => self.out.push_str("<error>"),
+        }
+    }
+
+    fn global_decl(&mut self, keyword: &str, decl: &GlobalDecl) {
+        self.out.push_str(keyword);
+        self.out.push(' ');
+        self.ident(&decl.name);
+        if let Some(ty) = &decl.ty {
+            self.out.push_str(": ");
+            self.ty(ty);
+        }
+        self.out.push_str(" = ");
+        self.expr(&decl.value);
+        self.out.push(';');
+    }
+
+    fn field_decls(&mut self, fields: &[FieldDecl]) {
+        if fields.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push('{');
+        self.indent += 1;
+        for field in fields {
+            self.newline();
+            if field.public {
+                self.out.push_str("pub ");
+            }
+            self.ident(&field.name);
+            self.out.push_str(": ");
+            self.ty(&field.ty);
+            self.out.push(',');
+        }
+        self.indent -= 1;
+        self.newline();
+        self.out.push('}');
+    }
+
+    fn block(&mut self, block: &Block) {
+        if block.stmts.is_empty() && block.tail.is_none() {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push('{');
+        self.indent += 1;
+        for stmt in &block.stmts {
+            self.newline();
+            self.stmt(stmt);
+        }
+        if let Some(tail) = &block.tail {
+            self.newline();
+            self.expr(tail);
+        }
+        self.indent -= 1;
+        self.newline();
+        self.out.push('}');
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { pattern, ty, value } => {
+                self.out.push_str("let ");
+                self.pattern(pattern);
+                if let Some(ty) = ty {
+                    self.out.push_str(": ");
+                    self.ty(ty);
+                }
+                if let Some(value) = value {
+                    self.out.push_str(" = ");
+                    self.expr(value);
+                }
+                self.out.push(';');
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+                if !expr.kind.is_block_like() {
+                    self.out.push(';');
+                }
+            }
+            StmtKind::Return(value) => {
+                self.out.push_str("return");
+                if let Some(value) = value {
+                    self.out.push(' ');
+                    self.expr(value);
+                }
+                self.out.push(';');
+            }
+            StmtKind::Yield(value) => {
+                self.out.push_str("yield ");
+                self.expr(value);
+                self.out.push(';');
+            }
+            StmtKind::Break => self.out.push_str("break;"),
+            StmtKind::Continue => self.out.push_str("continue;"),
+            StmtKind::Item(item) => self.item(item),
+            StmtKind::Error => self.out.push_str("<error>;"),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.literal(literal),
+            ExprKind::Path(path) => self.path(path),
+            ExprKind::Paren(inner) => {
+                self.out.push('(');
+                self.expr(inner);
+                self.out.push(')');
+            }
+            ExprKind::Tuple(exprs) => {
+                self.out.push('(');
+                self.comma_separated(exprs, Self::expr);
+                if exprs.len() == 1 {
+                    self.out.push(',');
+                }
+                self.out.push(')');
+            }
+            ExprKind::Array(exprs) => {
+                self.out.push('[');
+                self.comma_separated(exprs, Self::expr);
+                self.out.push(']');
+            }
+            ExprKind::Block(block) => self.block(block),
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.out.push_str("if ");
+                self.expr(cond);
+                self.out.push(' ');
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.out.push_str(" else ");
+                    self.expr(else_branch);
+                }
+            }
+            ExprKind::While { cond, body } => {
+                self.out.push_str("while ");
+                self.expr(cond);
+                self.out.push(' ');
+                self.block(body);
+            }
+            ExprKind::Loop(body) => {
+                self.out.push_str("loop ");
+                self.block(body);
+            }
+            ExprKind::For(for_loop) => {
+                self.out.push_str("for ");
+                self.pattern(&for_loop.pattern);
+                self.out.push_str(" in ");
+                self.expr(&for_loop.iter);
+                self.out.push(' ');
+                self.block(&for_loop.body);
+            }
+            ExprKind::Closure(closure) => {
+                self.out.push('|');
+                self.comma_separated(&closure.params, |p, param| {
+                    p.pattern(&param.pattern);
+                    if let Some(ty) = &param.ty {
+                        p.out.push_str(": ");
+                        p.ty(ty);
+                    }
+                });
+                self.out.push_str("| ");
+                self.expr(&closure.body);
+            }
+            ExprKind::Match(match_expr) => {
+                self.out.push_str("match ");
+                self.expr(&match_expr.scrutinee);
+                self.out.push_str(" {");
+                self.indent += 1;
+                for arm in &match_expr.arms {
+                    self.newline();
+                    self.pattern(&arm.pattern);
+                    self.out.push_str(" => ");
+                    self.expr(&arm.body);
+                    self.out.push(',');
+                }
+                self.indent -= 1;
+                if !match_expr.arms.is_empty() {
+                    self.newline();
+                }
+                self.out.push('}');
+            }
+            ExprKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                if let Some(start) = start {
+                    self.expr(start);
+                }
+                self.out.push_str(if *inclusive { "..=" } else { ".." });
+                if let Some(end) = end {
+                    self.expr(end);
+                }
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr(callee);
+                self.out.push('(');
+                self.comma_separated(args, Self::expr);
+                self.out.push(')');
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                self.expr(receiver);
+                self.out.push('.');
+                self.ident(method);
+                self.out.push('(');
+                self.comma_separated(args, Self::expr);
+                self.out.push(')');
+            }
+            ExprKind::Field { base, field } => {
+                self.expr(base);
+                self.out.push('.');
+                self.ident(field);
+            }
+            ExprKind::Index { base, index } => {
+                self.expr(base);
+                self.out.push('[');
+                self.expr(index);
+                self.out.push(']');
+            }
+            ExprKind::Try(expr) => {
+                self.expr(expr);
+                self.out.push('?');
+            }
+            ExprKind::StructLit { path, fields } => {
+                self.path(path);
+                if fields.is_empty() {
+                    self.out.push_str(" {}");
+                    return;
+                }
+
+                self.out.push_str(" { ");
+                self.comma_separated(fields, |p, field| {
+                    p.ident(&field.name);
+                    if !is_shorthand(&field.name, &field.value) {
+                        p.out.push_str(": ");
+                        p.expr(&field.value);
+                    }
+                });
+                self.out.push_str(" }");
+            }
+            ExprKind::Cast { expr, ty } => {
+                self.expr(expr);
+                self.out.push_str(" as ");
+                self.ty(ty);
+            }
+            ExprKind::Unary { op, expr } => {
+                self.out.push_str(op.as_str());
+                self.expr(expr);
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.expr(lhs);
+                self.out.push(' ');
+                self.out.push_str(op.as_str());
+                self.out.push(' ');
+                self.expr(rhs);
+            }
+            ExprKind::Assign { target, value } => {
+                self.expr(target);
+                self.out.push_str(" = ");
+                self.expr(value);
+            }
+            ExprKind::CompoundAssign { op, target, value } => {
+                self.expr(target);
+                self.out.push(' ');
+                self.out.push_str(op.as_str());
+                self.out.push_str("= ");
+                self.expr(value);
+            }
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        match literal {
+            Literal::Integer(value) => self.out.push_str(&value.to_string()),
+            Literal::OversizedInteger(value) => self.out.push_str(value),
+            Literal::Float(value) => {
+                let value = value.to_string();
+                self.out.push_str(&value);
+                // Floats need a fractional part to be lexed as floats
+                if !value.contains('.') {
+                    self.out.push_str(".0");
+                }
+            }
+            Literal::String(value) => self.out.push_str(&format!("{:?}", value)),
+            Literal::Char(value) => self.out.push_str(&format!("{:?}", value)),
+            Literal::Bool(value) => self.out.push_str(&value.to_string()),
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match &pattern.kind {
+            PatternKind::Wildcard => self.out.push('_'),
+            PatternKind::Literal(literal) => self.literal(literal),
+            PatternKind::Ident(ident) => self.ident(ident),
+            PatternKind::Mut(ident) => {
+                self.out.push_str("mut ");
+                self.ident(ident);
+            }
+            PatternKind::Tuple(patterns) => {
+                self.out.push('(');
+                self.comma_separated(patterns, Self::pattern);
+                if patterns.len() == 1 {
+                    self.out.push(',');
+                }
+                self.out.push(')');
+            }
+            PatternKind::Path(path) => self.path(path),
+            PatternKind::TupleVariant { path, fields } => {
+                self.path(path);
+                self.out.push('(');
+                self.comma_separated(fields, Self::pattern);
+                self.out.push(')');
+            }
+            PatternKind::StructVariant { path, fields } => {
+                self.path(path);
+                if fields.is_empty() {
+                    self.out.push_str(" {}");
+                    return;
+                }
+
+                self.out.push_str(" { ");
+                self.comma_separated(fields, |p, field| match &field.pattern.kind {
+                    PatternKind::Ident(ident) | PatternKind::Mut(ident)
+                        if ident.name == field.name.name =>
+                    {
+                        p.pattern(&field.pattern)
+                    }
+                    _ => {
+                        p.ident(&field.name);
+                        p.out.push_str(": ");
+                        p.pattern(&field.pattern);
+                    }
+                });
+                self.out.push_str(" }");
+            }
+        }
+    }
+
+    fn ty(&mut self, ty: &TypeExpr) {
+        match &ty.kind {
+            TypeExprKind::Path(path) => self.path(path),
+            TypeExprKind::Generic { path, args } => {
+                self.path(path);
+                self.out.push('<');
+                self.comma_separated(args, Self::ty);
+                self.out.push('>');
+            }
+            TypeExprKind::Tuple(types) => {
+                self.out.push('(');
+                self.comma_separated(types, Self::ty);
+                if types.len() == 1 {
+                    self.out.push(',');
+                }
+                self.out.push(')');
+            }
+            TypeExprKind::Fn { params, ret } => {
+                self.out.push_str("fn(");
+                self.comma_separated(params, Self::ty);
+                self.out.push(')');
+                if let Some(ret) = ret {
+                    self.out.push_str(" -> ");
+                    self.ty(ret);
+                }
+            }
+            TypeExprKind::Array { elem, len } => {
+                self.out.push('[');
+                self.ty(elem);
+                if let Some(len) = len {
+                    self.out.push_str("; ");
+                    self.expr(len);
+                }
+                self.out.push(']');
+            }
+            TypeExprKind::Never => self.out.push('!'),
+        }
+    }
+}
+
+/// Returns whether a struct literal field can be written as just its name.
+fn is_shorthand(name: &Ident, value: &Expr) -> bool {
+    matches!(
+        &value.kind,
+        ExprKind::Path(path) if path.segments.len() == 1 && path.segments[0].name == name.name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_source, Parser};
+
+    #[test]
+    fn test_print_program() {
+        let source = "/// A point\nstruct Point{x:int,y int}\nenum Shape{Circle(float),Rect{w:float,h:float},Empty}\nmod math{use shapes::circle;}\nfn main()->int{let (a,_)=(1,);let p=Point{x:1,y};if p.x>=0{p.y+=1;}else{return 0;}match p{Point{x,y:0}=>x,_=>{2}}}";
+        let program = parse_source(source).unwrap();
+
+        let expected = r#"/// A point
+struct Point {
+    x: int,
+    y: int,
+}
+
+enum Shape {
+    Circle(float),
+    Rect {
+        w: float,
+        h: float,
+    },
+    Empty,
+}
+
+mod math {
+    use shapes::circle;
+}
+
+fn main() -> int {
+    let (a, _) = (1,);
+    let p = Point { x: 1, y };
+    if p.x >= 0 {
+        p.y += 1;
+    } else {
+        return 0;
+    }
+    match p {
+        Point { x, y: 0 } => x,
+        _ => {
+            2
+        },
+    }
+}
+"#;
+        assert_eq!(print_program(&program), expected);
+    }
+
+    #[test]
+    fn test_print_round_trip() {
+        let source = "fn f(cb: fn(int) -> [int; 2], m: Map<string, (int,)>) {\n    for i in 0..=10 { while done { break; } }\n    let g = |x, y: int| x * (y - 1);\n    g(\"a\\n\", 'c', 2.0, 1.5, 0xff)[0].len();\n}";
+        let program = parse_source(source).unwrap();
+        let printed = print_program(&program);
+
+        assert_eq!(print_program(&parse_source(&printed).unwrap()), printed);
+    }
+
+    #[test]
+    fn test_print_traits() {
+        let source = "trait Show{fn show(self)->string;fn print(self){println(self.show());}}impl Show for Point{fn show(self) string{name}}";
+        let program = parse_source(source).unwrap();
+
+        let expected = r#"trait Show {
+    fn show(self) -> string;
+    fn print(self) {
+        println(self.show());
+    }
+}
+
+impl Show for Point {
+    fn show(self) -> string {
+        name
+    }
+}
+"#;
+        assert_eq!(print_program(&program), expected);
+    }
+
+    #[test]
+    fn test_print_pub_items() {
+        let source = "pub struct P{pub x:int,y:int}pub mod m{pub fn f(){}}";
+        let program = parse_source(source).unwrap();
+
+        let expected = r#"pub struct P {
+    pub x: int,
+    y: int,
+}
+
+pub mod m {
+    pub fn f() {}
+}
+"#;
+        assert_eq!(print_program(&program), expected);
+    }
+
+    #[test]
+    fn test_print_mut_patterns() {
+        let source = "fn f(mut self,mut n:int){let (mut a,b)=n;match s{Rect{mut w,h:mut t}=>w}}";
+        let program = parse_source(source).unwrap();
+
+        let expected = r#"fn f(mut self, mut n: int) {
+    let (mut a, b) = n;
+    match s {
+        Rect { mut w, h: mut t } => w,
+    }
+}
+"#;
+        assert_eq!(print_program(&program), expected);
+    }
+
+    #[test]
+    fn test_print_generic_fns() {
+        let source = "fn f<T:A+b::C,U>(t:T)->U{t}";
+        let program = parse_source(source).unwrap();
+        assert_eq!(
+            print_program(&program),
+            "fn f<T: A + b::C, U>(t: T) -> U {\n    t\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_print_expr() {
+        let expr = Parser::new("a   +(b*-c)..").parse_expr().unwrap();
+        assert_eq!(print_expr(&expr), "a + (b * -c)..");
+
+        let expr = Parser::new("(x  as   float)/2.0 as int")
+            .parse_expr()
+            .unwrap();
+        assert_eq!(print_expr(&expr), "(x as float) / 2.0 as int");
+    }
+}
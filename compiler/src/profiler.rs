@@ -0,0 +1,202 @@
+//! Profiles programs that the VM runs, which `profile` starts. The VM counts every instruction
+//! that runs, and the report lists the functions and lines that ran the most of them: a
+//! function's own instructions are its `self`, and with those of the calls it made, its `total`.
+//!
+//! The chains of calls are also written as folded stacks, one `main;outer;inner count` line for
+//! each, which is what `flamegraph.pl` and `inferno-flamegraph` draw flame graphs from.
+
+use std::collections::HashMap;
+
+use crate::{
+    codegen::bytecode::{GcConfig, Module, Profile, Vm},
+    console::Console,
+    ffi::Externs,
+    interpreter::Panic,
+    lexer::Span,
+    limits::Limits,
+    source_map::{FileId, SourceMap},
+};
+
+/// How many of the hottest functions and lines the report lists.
+const HOTTEST: usize = 10;
+
+/// Runs a module's `main` in the VM, returning the profile of what it ran, even if it panicked.
+/// The initializers of globals run before the profile starts.
+pub fn run<'a>(
+    module: &'a Module,
+    config: GcConfig,
+    limits: Limits,
+    console: Console<'a>,
+    host: &'a Externs,
+) -> (Profile, Result<(), Panic>) {
+    let mut vm = match Vm::new(module, config, limits, console, host) {
+        Ok(vm) => vm,
+        Err(panic) => return (Profile::new(module), Err(panic)),
+    };
+    vm.start_profile();
+    let result = match module.main {
+        Some(main) => vm.call(main, Vec::new()).map(|_| ()),
+        None => Ok(()),
+    };
+    (vm.take_profile().unwrap(), result)
+}
+
+/// Describes the hottest functions and lines of a profile.
+pub fn report(profile: &Profile, module: &Module, source_map: &SourceMap) -> String {
+    let total = profile.total();
+    let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+    let mut report = format!("{} instructions ran\n\n", total);
+
+    // A function counts once towards the total of a chain of calls, however often it recurses
+    let mut funcs: HashMap<u16, (u64, u64)> = HashMap::new();
+    for (i, call) in profile.calls.iter().enumerate() {
+        funcs.entry(call.func).or_default().0 += call.count;
+        let mut stack = profile.stack(i);
+        stack.sort();
+        stack.dedup();
+        for func in stack {
+            funcs.entry(func).or_default().1 += call.count;
+        }
+    }
+    let mut funcs: Vec<_> = funcs.into_iter().collect();
+    funcs.sort_by_key(|&(func, (own, _))| (std::cmp::Reverse(own), func));
+    report.push_str("  self   total  function\n");
+    for (func, (own, all)) in funcs.into_iter().take(HOTTEST) {
+        let name = &module.functions[func as usize].name;
+        let line = format!("{:>5.1}% {:>6.1}%  {}\n", percent(own), percent(all), name);
+        report.push_str(&line);
+    }
+
+    let mut lines: HashMap<(FileId, usize), u64> = HashMap::new();
+    for (func, counts) in module.functions.iter().zip(&profile.counts) {
+        let starts = line_starts(&func.spans, source_map);
+        for (offset, &count) in counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            let index = starts.partition_point(|&(start, _)| start <= offset);
+            if let Some(index) = index.checked_sub(1) {
+                *lines.entry(starts[index].1).or_default() += count;
+            }
+        }
+    }
+    let mut lines: Vec<_> = lines.into_iter().collect();
+    lines.sort_by_key(|&(line, count)| (std::cmp::Reverse(count), line));
+    report.push_str("\n  self  line\n");
+    for ((file, row), count) in lines.into_iter().take(HOTTEST) {
+        let path = source_map.file(file).path.display();
+        let text = source_map.source(file).lines().nth(row - 1);
+        let line = format!(
+            "{:>5.1}%  {}:{}  {}\n",
+            percent(count),
+            path,
+            row,
+            text.unwrap_or_default().trim()
+        );
+        report.push_str(&line);
+    }
+    report
+}
+
+/// Returns the offsets where the code of each line starts, in order, with its file and row. Code
+/// that lowering made up has no span, and belongs to the line before it.
+fn line_starts(spans: &[(u32, Span)], source_map: &SourceMap) -> Vec<(usize, (FileId, usize))> {
+    let mut starts: Vec<(usize, (FileId, usize))> = Vec::new();
+    for (offset, span) in spans.iter().filter(|(_, span)| !span.is_empty()) {
+        let (Some(file), Some(location)) = (
+            source_map.file_at(span.start),
+            source_map.location(span.start),
+        ) else {
+            continue;
+        };
+        if starts.last().map(|&(_, line)| line) != Some((file, location.row)) {
+            starts.push((*offset as usize, (file, location.row)));
+        }
+    }
+    starts
+}
+
+/// Returns the chains of calls of a profile as folded stacks, with the instructions that ran in
+/// the innermost call of each.
+pub fn folded(profile: &Profile, module: &Module) -> String {
+    let mut stacks: Vec<_> = (profile.calls.iter().enumerate())
+        .filter(|(_, call)| call.count > 0)
+        .map(|(i, call)| {
+            let names: Vec<_> = (profile.stack(i).into_iter())
+                .map(|func| module.functions[func as usize].name.as_str())
+                .collect();
+            format!("{} {}\n", names.join(";"), call.count)
+        })
+        .collect();
+    stacks.sort();
+    stacks.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        codegen::bytecode,
+        hir,
+        loader::load_source,
+        mir::{self, opt::OptLevel},
+        resolve, typeck,
+    };
+
+    #[test]
+    fn test_profiler() {
+        let source = "fn square(x: int) int { x * x }
+fn total(n: int) int {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < n {
+        sum += square(i);
+        i += 1;
+    }
+    sum
+}
+fn main() {
+    if total(100) != 328350 { panic(\"wrong total\"); }
+}";
+        let loaded = load_source("main.rf", source);
+        let res = resolve::resolve(&loaded.program);
+        let types = typeck::check(&loaded.program, &res);
+        assert!(types.errors.is_empty(), "{:?}", types.errors);
+        let mut program = mir::build(&hir::lower(&loaded.program, &res, &types), &res);
+        mir::opt::optimize(&mut program, OptLevel::O0);
+        let module = bytecode::emit(&program);
+
+        let mut output = Vec::new();
+        let console = Console::new(&b""[..], &mut output);
+        let (profile, ran) = run(
+            &module,
+            GcConfig::default(),
+            Limits::default(),
+            console,
+            &Externs::default(),
+        );
+        assert_eq!(ran, Ok(()));
+        assert!(output.is_empty());
+        let report = report(&profile, &module, &loaded.source_map);
+        assert_eq!(
+            report,
+            "3033 instructions ran
+
+  self   total  function
+ 79.7%   99.4%  total
+ 19.8%   19.8%  square
+  0.6%  100.0%  main
+
+  self  line
+ 36.3%  main.rf:7  i += 1;
+ 23.1%  main.rf:6  sum += square(i);
+ 20.0%  main.rf:5  while i < n {
+ 19.8%  main.rf:1  fn square(x: int) int { x * x }
+  0.6%  main.rf:12  if total(100) != 328350 { panic(\"wrong total\"); }
+  0.2%  main.rf:4  let mut i = 0;
+  0.1%  main.rf:3  let mut sum = 0;
+"
+        );
+        assert_eq!(
+            folded(&profile, &module),
+            "main 17\nmain;total 2416\nmain;total;square 600\n"
+        );
+    }
+}
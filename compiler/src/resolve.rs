@@ -0,0 +1,2210 @@
+//! Name resolution, which builds the scopes of a program, gives every definition a unique id and
+//! links each use of a name to the definition it refers to.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+};
+
+use crate::{
+    ast::*,
+    diagnostic::Diagnostic,
+    lexer::Span,
+    utils::edit_distance,
+    visit::{self, Visit},
+};
+
+/// Identifies a definition in a [`Resolution`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DefId(pub usize);
+
+impl DefId {
+    /// The definition of the root module, which is what `super` refers to in the modules
+    /// declared at the top of the program.
+    pub const ROOT: DefId = DefId(0);
+
+    /// The built in `Option` enum and its variants, `Some(value)` and `None`.
+    pub const OPTION: DefId = DefId(1);
+    pub const SOME: DefId = DefId(2);
+    pub const NONE: DefId = DefId(3);
+
+    /// The built in `Result` enum and its variants, `Ok(value)` and `Err(error)`.
+    pub const RESULT: DefId = DefId(4);
+    pub const OK: DefId = DefId(5);
+    pub const ERR: DefId = DefId(6);
+
+    /// The built in `panic(message)` function, which stops the program and never returns.
+    pub const PANIC: DefId = DefId(7);
+
+    /// The built in `print(text)` and `println(text)` functions, which write to the standard
+    /// output, the second followed by a newline. They take strings, and integers, `bool`s and
+    /// `char`s, which they write the text of.
+    pub const PRINT: DefId = DefId(8);
+    pub const PRINTLN: DefId = DefId(9);
+
+    /// The built in `input()` function, which reads a line from the standard input, without its
+    /// newline.
+    pub const INPUT: DefId = DefId(10);
+
+    /// The built in methods of `string`: `len()`, the number of its characters,
+    /// `contains(pattern)`, `split(separator)`, and `to_upper()` and `to_lower()`, which change the
+    /// case of its ASCII letters.
+    pub const STRING_LEN: DefId = DefId(11);
+    pub const STRING_CONTAINS: DefId = DefId(12);
+    pub const STRING_SPLIT: DefId = DefId(13);
+    pub const STRING_TO_UPPER: DefId = DefId(14);
+    pub const STRING_TO_LOWER: DefId = DefId(15);
+
+    /// The built in methods of arrays: `len()`, `push(value)`, which adds an element to the end,
+    /// and `pop()`, which removes the last element and returns it, or `None` when there isn't one.
+    pub const ARRAY_LEN: DefId = DefId(16);
+    pub const ARRAY_PUSH: DefId = DefId(17);
+    pub const ARRAY_POP: DefId = DefId(18);
+
+    /// The built in `Map<K, V>` type, which maps keys to values, and its members: `Map::new()`,
+    /// which makes an empty map, and the methods `len()`, `insert(key, value)`, which replaces
+    /// the value of a key that's already there, `get(key)`, `remove(key)`, which returns the
+    /// value it removes, and `contains(key)`.
+    pub const MAP: DefId = DefId(19);
+    pub const MAP_NEW: DefId = DefId(20);
+    pub const MAP_LEN: DefId = DefId(21);
+    pub const MAP_INSERT: DefId = DefId(22);
+    pub const MAP_GET: DefId = DefId(23);
+    pub const MAP_REMOVE: DefId = DefId(24);
+    pub const MAP_CONTAINS: DefId = DefId(25);
+
+    /// The built in `math` module: `sqrt(x)`, `abs(x)`, `pow(x, y)`, `floor(x)`, `ceil(x)`,
+    /// `min(a, b)`, `max(a, b)` and the constant `pi`. `abs`, `min` and `max` take integers or
+    /// floats, and the others floats.
+    pub const MATH: DefId = DefId(26);
+    pub const MATH_SQRT: DefId = DefId(27);
+    pub const MATH_ABS: DefId = DefId(28);
+    pub const MATH_POW: DefId = DefId(29);
+    pub const MATH_FLOOR: DefId = DefId(30);
+    pub const MATH_CEIL: DefId = DefId(31);
+    pub const MATH_MIN: DefId = DefId(32);
+    pub const MATH_MAX: DefId = DefId(33);
+    pub const MATH_PI: DefId = DefId(34);
+
+    /// The built in `fs` module: `read_to_string(path)`, `write(path, contents)` and
+    /// `exists(path)`, which return a `Result` whose error is a message.
+    pub const FS: DefId = DefId(35);
+    pub const FS_READ_TO_STRING: DefId = DefId(36);
+    pub const FS_WRITE: DefId = DefId(37);
+    pub const FS_EXISTS: DefId = DefId(38);
+
+    /// The built in `Gen<T>` type of generators, which functions that `yield` return, and its
+    /// `next()` method, which runs the generator until it yields its next value, or returns `None`
+    /// once it has finished.
+    pub const GEN: DefId = DefId(39);
+    pub const GEN_NEXT: DefId = DefId(40);
+}
+
+/// Identifies a scope in a [`Resolution`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScopeId(pub usize);
+
+impl ScopeId {
+    /// The scope of the items at the top of the program.
+    pub const ROOT: ScopeId = ScopeId(0);
+
+    /// The scope of the built in names, which every program can use without declaring or
+    /// importing them. Names declared by the program hide them.
+    pub const PRELUDE: ScopeId = ScopeId(1);
+
+    /// The built in methods of `string`, which are only found through a string.
+    pub const STRING: ScopeId = ScopeId(4);
+
+    /// The built in methods of arrays, which are only found through an array.
+    pub const ARRAY: ScopeId = ScopeId(5);
+
+    /// The members of `Map`, where its methods are found through a map and `new` through `Map`.
+    pub const MAP: ScopeId = ScopeId(6);
+
+    /// The items of the built in `math` module.
+    pub const MATH: ScopeId = ScopeId(7);
+
+    /// The items of the built in `fs` module.
+    pub const FS: ScopeId = ScopeId(8);
+
+    /// The methods of `Gen`, which are only found through a generator.
+    pub const GEN: ScopeId = ScopeId(9);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefKind {
+    Mod,
+    Fn,
+    Const,
+    Static,
+    Struct,
+    Enum,
+    Variant,
+    Trait,
+    /// A function in a trait or `impl` block.
+    Method,
+    Param,
+    /// A type parameter of a generic function.
+    TypeParam,
+    /// A variable bound by `let`, a `for` loop, a closure or a `match` arm.
+    Local,
+}
+
+impl DefKind {
+    /// Returns whether the definition is an item rather than a local binding. Only items are
+    /// visible from inside nested functions.
+    pub fn is_item(self) -> bool {
+        !matches!(self, DefKind::Param | DefKind::TypeParam | DefKind::Local)
+    }
+
+    /// Returns the name of the kind, as used in messages.
+    pub fn describe(self) -> &'static str {
+        match self {
+            DefKind::Mod => "module",
+            DefKind::Fn => "function",
+            DefKind::Const => "constant",
+            DefKind::Static => "static",
+            DefKind::Struct => "struct",
+            DefKind::Enum => "enum",
+            DefKind::Variant => "variant",
+            DefKind::Trait => "trait",
+            DefKind::Method => "method",
+            DefKind::Param => "parameter",
+            DefKind::TypeParam => "type parameter",
+            DefKind::Local => "variable",
+        }
+    }
+}
+
+/// A named definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub kind: DefKind,
+    /// The span of the identifier that introduces the name.
+    pub span: Span,
+    /// The scope the name is declared in.
+    pub scope: ScopeId,
+    /// The names inside a module, enum, struct or trait, such as its items, variants and methods.
+    pub members: Option<ScopeId>,
+    /// The number of times the name is used. Assigning to a variable doesn't count as a use.
+    pub uses: usize,
+    /// Whether the variable was declared with `mut`, so it can be assigned to.
+    pub mutable: bool,
+    /// Whether the item can be used outside of its module. Variants and trait methods are as
+    /// visible as their enum or trait.
+    pub public: bool,
+}
+
+/// A method that a trait impl takes from its trait, as the trait provides a body for it that the
+/// impl doesn't override.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvidedMethod {
+    /// The method of the impl, which is declared alongside the impl's own.
+    pub def: DefId,
+    /// The method of the trait, whose body is checked and lowered again for the impl.
+    pub method: DefId,
+    /// The span of the impl's type.
+    pub impl_ty: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeKind {
+    Module,
+    /// The variants and methods of a type, or the methods of a trait.
+    Members,
+    Function,
+    Closure,
+    Block,
+}
+
+/// A set of names, which sees the names of its parent scopes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    pub kind: ScopeKind,
+    /// The enclosing scope, `None` for modules and members, which don't see the names around them.
+    pub parent: Option<ScopeId>,
+    /// The module the scope is part of. For a module's own scope, this is the module it's
+    /// declared in, which is `None` for the root.
+    pub module: Option<ScopeId>,
+    pub names: BTreeMap<String, DefId>,
+}
+
+/// What an undefined name was expected to be, based on where it was used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameKind {
+    Value,
+    Type,
+    Import,
+}
+
+/// Error type returned from name resolution.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    /// A name that isn't in scope. `candidate` is the most similar name that is.
+    Undefined {
+        kind: NameKind,
+        name: String,
+        span: Span,
+        candidate: Option<Ident>,
+    },
+    /// A path segment that isn't a member of the module or type before it.
+    NoMember {
+        parent: String,
+        name: String,
+        span: Span,
+        candidate: Option<Ident>,
+    },
+    /// A private item or field that's used outside of the module it's declared in. `kind`
+    /// describes what it is, and `definition` is where it's declared.
+    Private {
+        kind: &'static str,
+        name: String,
+        span: Span,
+        definition: Span,
+    },
+    /// A `self` value outside of a method with a `self` parameter.
+    SelfOutsideMethod { span: Span },
+    /// A `super` in the root module, which has no parent.
+    TooManySupers { span: Span },
+    /// A second definition of a name in the same scope.
+    Duplicate {
+        name: String,
+        span: Span,
+        original: Span,
+    },
+    /// An assignment to a variable or parameter that isn't declared with `mut`. `reassign` is
+    /// whether the variable itself is assigned to, rather than one of its fields or elements.
+    Immutable {
+        kind: DefKind,
+        name: String,
+        span: Span,
+        binding: Span,
+        reassign: bool,
+    },
+    /// `use` declarations that each import a name the next one needs, with the last one needing
+    /// the name imported by the first. Each import is its path and the path's span.
+    ImportCycle { imports: Vec<(String, Span)> },
+}
+
+impl ResolveError {
+    pub fn span(&self) -> &Span {
+        match self {
+            ResolveError::Undefined { span, .. }
+            | ResolveError::NoMember { span, .. }
+            | ResolveError::Private { span, .. }
+            | ResolveError::SelfOutsideMethod { span }
+            | ResolveError::TooManySupers { span }
+            | ResolveError::Duplicate { span, .. }
+            | ResolveError::Immutable { span, .. } => span,
+            ResolveError::ImportCycle { imports } => &imports[0].1,
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.to_string(), self.span().clone());
+        match self {
+            ResolveError::Undefined {
+                candidate: Some(candidate),
+                ..
+            }
+            | ResolveError::NoMember {
+                candidate: Some(candidate),
+                ..
+            } => diagnostic.with_note(
+                format!("a similar name exists: `{}`", candidate.name),
+                Some(candidate.span.clone()),
+            ),
+            ResolveError::Private {
+                name, definition, ..
+            } => diagnostic.with_note(
+                format!("`{}` is declared here without `pub`", name),
+                Some(definition.clone()),
+            ),
+            ResolveError::Duplicate { name, original, .. } => diagnostic.with_note(
+                format!("`{}` was first defined here", name),
+                Some(original.clone()),
+            ),
+            ResolveError::Immutable { name, binding, .. } => diagnostic.with_note(
+                format!(
+                    "`{}` is declared here, use `mut {}` to make it mutable",
+                    name, name
+                ),
+                Some(binding.clone()),
+            ),
+            ResolveError::ImportCycle { imports } => {
+                imports[1..]
+                    .iter()
+                    .fold(diagnostic, |diagnostic, (path, span)| {
+                        diagnostic
+                            .with_note(format!("`{}` is imported here", path), Some(span.clone()))
+                    })
+            }
+            _ => diagnostic,
+        }
+    }
+}
+
+impl Error for ResolveError {}
+
+impl Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolveError::Undefined { kind, name, .. } => match kind {
+                NameKind::Value => write!(f, "cannot find value `{}` in this scope", name),
+                NameKind::Type => write!(f, "cannot find type `{}` in this scope", name),
+                NameKind::Import => write!(f, "unresolved import `{}`", name),
+            },
+            ResolveError::NoMember { parent, name, .. } => {
+                write!(f, "cannot find `{}` in `{}`", name, parent)
+            }
+            ResolveError::Private { kind, name, .. } => write!(f, "{} `{}` is private", kind, name),
+            ResolveError::SelfOutsideMethod { .. } => {
+                write!(
+                    f,
+                    "`self` is only available in methods with a `self` parameter"
+                )
+            }
+            ResolveError::TooManySupers { .. } => {
+                write!(f, "there are too many leading `super` keywords")
+            }
+            ResolveError::Duplicate { name, .. } => {
+                write!(f, "the name `{}` is defined multiple times", name)
+            }
+            ResolveError::Immutable {
+                kind,
+                name,
+                reassign,
+                ..
+            } => {
+                let action = if *reassign { "assign to" } else { "mutate" };
+                let kind = if *kind == DefKind::Param {
+                    "parameter"
+                } else {
+                    "variable"
+                };
+                write!(f, "cannot {} immutable {} `{}`", action, kind, name)
+            }
+            ResolveError::ImportCycle { imports } => {
+                write!(f, "import cycle: ")?;
+                for (path, _) in imports {
+                    write!(f, "`{}` -> ", path)?;
+                }
+                write!(f, "`{}`", imports[0].0)
+            }
+        }
+    }
+}
+
+/// A warning about code that's allowed, but is probably a mistake.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lint {
+    UnusedVariable {
+        name: String,
+        span: Span,
+    },
+    UnusedFunction {
+        name: String,
+        span: Span,
+    },
+    UnusedMethod {
+        name: String,
+        span: Span,
+    },
+    /// A `let` that hides a variable of the same name from a scope around it. `original` is the
+    /// hidden variable.
+    Shadowing {
+        name: String,
+        span: Span,
+        original: Span,
+    },
+}
+
+impl Lint {
+    pub fn span(&self) -> &Span {
+        match self {
+            Lint::UnusedVariable { span, .. }
+            | Lint::UnusedFunction { span, .. }
+            | Lint::UnusedMethod { span, .. }
+            | Lint::Shadowing { span, .. } => span,
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::warning(self.to_string(), self.span().clone());
+        match self {
+            Lint::UnusedVariable { name, .. }
+            | Lint::UnusedFunction { name, .. }
+            | Lint::UnusedMethod { name, .. } => diagnostic.with_note(
+                format!(
+                    "if this is intentional, prefix it with an underscore: `_{}`",
+                    name
+                ),
+                None,
+            ),
+            Lint::Shadowing { name, original, .. } => diagnostic.with_note(
+                format!("the shadowed `{}` is declared here", name),
+                Some(original.clone()),
+            ),
+        }
+    }
+}
+
+/// The lints that are off unless asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LintOptions {
+    /// Warn about `let` bindings that shadow a variable from an enclosing scope.
+    pub shadowing: bool,
+}
+
+impl LintOptions {
+    /// Turns on a lint by the name that `--warn` and the manifest's `[lints]` give it, returning
+    /// whether there's one by that name.
+    pub fn warn(&mut self, name: &str) -> bool {
+        match name {
+            "shadowing" => self.shadowing = true,
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lint::UnusedVariable { name, .. } => write!(f, "unused variable `{}`", name),
+            Lint::UnusedFunction { name, .. } => write!(f, "function `{}` is never used", name),
+            Lint::UnusedMethod { name, .. } => write!(f, "method `{}` is never used", name),
+            Lint::Shadowing { name, .. } => {
+                write!(f, "`{}` shadows a variable from an enclosing scope", name)
+            }
+        }
+    }
+}
+
+/// The result of resolving a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolution {
+    pub defs: Vec<Definition>,
+    pub scopes: Vec<Scope>,
+    /// Maps the span of every resolved identifier, at definitions and uses alike, to its
+    /// definition.
+    pub idents: HashMap<Span, DefId>,
+    /// The methods that trait impls take from their traits, in the order the impls are declared.
+    pub provided: Vec<ProvidedMethod>,
+    pub errors: Vec<ResolveError>,
+    pub lints: Vec<Lint>,
+}
+
+impl Resolution {
+    pub fn def(&self, id: DefId) -> &Definition {
+        &self.defs[id.0]
+    }
+
+    pub fn scope(&self, id: ScopeId) -> &Scope {
+        &self.scopes[id.0]
+    }
+
+    /// Returns the definition that the identifier at `span` refers to.
+    pub fn lookup(&self, span: &Span) -> Option<DefId> {
+        self.idents.get(span).copied()
+    }
+
+    /// Returns the module that a scope is part of, which is itself for a module's scope.
+    pub fn module_of(&self, scope: ScopeId) -> ScopeId {
+        let current = self.scope(scope);
+        match current.kind {
+            ScopeKind::Module => scope,
+            _ => current.module.unwrap(),
+        }
+    }
+
+    /// Returns whether the names private to `module` can be used from `from`, which is the case
+    /// inside the module and the modules nested in it.
+    pub fn is_accessible(&self, module: ScopeId, from: ScopeId) -> bool {
+        let mut current = Some(self.module_of(from));
+        while let Some(id) = current {
+            if id == module {
+                return true;
+            }
+            current = self.scope(id).module;
+        }
+        false
+    }
+
+    /// Returns the path that names an item from the root module, such as `shapes::Circle::new`, or
+    /// `None` for a name that no path reaches, such as one declared in a function's body or in an
+    /// impl of a trait.
+    pub fn path(&self, id: DefId) -> Option<String> {
+        let def = self.def(id);
+        if def.scope == ScopeId::ROOT {
+            return Some(def.name.clone());
+        }
+        let owner = (self.defs.iter()).position(|owner| owner.members == Some(def.scope))?;
+        Some(format!("{}::{}", self.path(DefId(owner))?, def.name))
+    }
+
+    /// Returns whether a definition can be used from `from`.
+    pub fn is_visible(&self, def: DefId, from: ScopeId) -> bool {
+        let def = self.def(def);
+        def.public || self.is_accessible(self.module_of(def.scope), from)
+    }
+}
+
+/// Resolves every name in the program, with the default lints.
+pub fn resolve(program: &Program) -> Resolution {
+    resolve_with(program, LintOptions::default())
+}
+
+/// Resolves every name in the program, also reporting the opt-in lints enabled in `options`.
+pub fn resolve_with(program: &Program, options: LintOptions) -> Resolution {
+    let mut resolver = Resolver {
+        options,
+        res: Resolution {
+            defs: Vec::new(),
+            scopes: Vec::new(),
+            idents: HashMap::new(),
+            provided: Vec::new(),
+            errors: Vec::new(),
+            lints: Vec::new(),
+        },
+        scope: ScopeId::ROOT,
+        imports: Vec::new(),
+        impls: Vec::new(),
+        provided: HashSet::new(),
+        bindings: HashMap::new(),
+        fields: HashMap::new(),
+        modules: HashMap::new(),
+        item: None,
+        refs: Vec::new(),
+        method_calls: Vec::new(),
+    };
+    resolver.new_scope(ScopeKind::Module, None, None);
+    resolver.res.defs.push(Definition {
+        name: "crate".to_string(),
+        kind: DefKind::Mod,
+        span: 0..0,
+        scope: ScopeId::ROOT,
+        members: Some(ScopeId::ROOT),
+        uses: 0,
+        mutable: false,
+        public: true,
+    });
+    resolver.modules.insert(ScopeId::ROOT, DefId::ROOT);
+    resolver.declare_prelude();
+
+    // Items can be used before they're declared, so they're all declared before any bodies are
+    // resolved
+    resolver.declare_items(ScopeId::ROOT, &program.items);
+    resolver.resolve_imports();
+    resolver.attach_impls();
+    resolver.visit_program(program);
+
+    let mut lints = unused_lints(&resolver.res, &resolver.refs, &resolver.method_calls);
+    let mut res = resolver.res;
+    lints.append(&mut res.lints);
+    lints.sort_by_key(|lint| lint.span().start);
+    res.lints = lints;
+    res
+}
+
+/// Finds variables that are never read, and functions and inherent methods that are never called
+/// from the code that runs: `main`, the public functions and methods, which can be used by code
+/// that isn't part of the program, the methods of traits, and the code outside of functions, such
+/// as the values of statics. Names starting with an underscore are left alone, as are `self`
+/// parameters, and the functions named so count as code that runs.
+///
+/// `refs` are the definitions that each function or method uses, and `method_calls` the names of
+/// the methods it calls on values. A method call counts as a call of every inherent method with
+/// the name, as the type of the value isn't known yet.
+fn unused_lints(
+    res: &Resolution,
+    refs: &[(Option<DefId>, DefId)],
+    method_calls: &[(Option<DefId>, String)],
+) -> Vec<Lint> {
+    let types: HashSet<ScopeId> = (res.defs.iter())
+        .filter(|def| matches!(def.kind, DefKind::Struct | DefKind::Enum))
+        .filter_map(|def| def.members)
+        .collect();
+    let is_inherent = |def: &Definition| def.kind == DefKind::Method && types.contains(&def.scope);
+    let is_fn = |def: &Definition| def.kind == DefKind::Fn || is_inherent(def);
+    let is_entry = |def: &Definition| {
+        !is_fn(def)
+            || def.public
+            || def.name.starts_with('_')
+            || (def.name == "main" && def.scope == ScopeId::ROOT)
+    };
+
+    let mut uses: HashMap<Option<DefId>, Vec<DefId>> = HashMap::new();
+    for &(from, def) in refs {
+        uses.entry(from).or_default().push(def);
+    }
+    let mut methods: HashMap<&str, Vec<DefId>> = HashMap::new();
+    for (i, def) in res
+        .defs
+        .iter()
+        .enumerate()
+        .filter(|(_, def)| is_inherent(def))
+    {
+        methods.entry(&def.name).or_default().push(DefId(i));
+    }
+    for (from, name) in method_calls {
+        let called = methods.get(name.as_str()).into_iter().flatten();
+        uses.entry(*from).or_default().extend(called);
+    }
+
+    let mut reached = HashSet::new();
+    let mut queue: Vec<Option<DefId>> = vec![None];
+    let entries = (0..res.defs.len())
+        .map(DefId)
+        .filter(|&id| is_entry(res.def(id)));
+    queue.extend(entries.map(Some));
+    while let Some(from) = queue.pop() {
+        if !reached.insert(from) {
+            continue;
+        }
+        let used = uses.get(&from).into_iter().flatten();
+        queue.extend(used.map(|&def| Some(def)));
+    }
+
+    (res.defs.iter().enumerate())
+        .filter(|(_, def)| !def.name.starts_with('_'))
+        .filter_map(|(i, def)| {
+            let name = def.name.clone();
+            let span = def.span.clone();
+            match def.kind {
+                DefKind::Local | DefKind::Param if def.uses == 0 && def.name != "self" => {
+                    Some(Lint::UnusedVariable { name, span })
+                }
+                _ if !is_fn(def) || reached.contains(&Some(DefId(i))) => None,
+                DefKind::Method => Some(Lint::UnusedMethod { name, span }),
+                _ => Some(Lint::UnusedFunction { name, span }),
+            }
+        })
+        .collect()
+}
+
+/// Returns the scope that an import's path is resolved from. Imports are absolute, unless they
+/// start with `self` or `super`, which are relative to the import's module.
+fn import_scope(scope: ScopeId, path: &Path) -> ScopeId {
+    match path.segments[0].name.as_str() {
+        "self" | "super" => scope,
+        _ => ScopeId::ROOT,
+    }
+}
+
+struct Resolver<'ast> {
+    res: Resolution,
+    options: LintOptions,
+    /// The scope that names are currently declared in and looked up from.
+    scope: ScopeId,
+    /// `use` declarations that haven't been resolved yet, along with the scope they import into.
+    imports: Vec<(ScopeId, &'ast Path)>,
+    /// `impl` blocks whose methods haven't been declared yet, along with their scope.
+    impls: Vec<(ScopeId, &'ast ImplDecl)>,
+    /// The trait methods with a body, which the trait's impls don't have to define.
+    provided: HashSet<DefId>,
+    /// The variables bound so far by the current pattern or parameter list.
+    bindings: HashMap<String, DefId>,
+    /// The fields of each struct, to check that the private ones aren't used outside of its
+    /// module.
+    fields: HashMap<DefId, &'ast [FieldDecl]>,
+    /// The definition of each module, by the module's scope.
+    modules: HashMap<ScopeId, DefId>,
+    /// The function or method whose body is being resolved, `None` outside of them.
+    item: Option<DefId>,
+    /// The definitions that each function or method uses, and those used outside of them.
+    refs: Vec<(Option<DefId>, DefId)>,
+    /// The names of the methods that each function or method calls on a value, which only the
+    /// type checker can tell apart.
+    method_calls: Vec<(Option<DefId>, String)>,
+}
+
+impl<'ast> Resolver<'ast> {
+    fn new_scope(
+        &mut self,
+        kind: ScopeKind,
+        parent: Option<ScopeId>,
+        module: Option<ScopeId>,
+    ) -> ScopeId {
+        self.res.scopes.push(Scope {
+            kind,
+            parent,
+            module,
+            names: BTreeMap::new(),
+        });
+        ScopeId(self.res.scopes.len() - 1)
+    }
+
+    /// Starts a new scope inside the current one, returning the current scope so it can be
+    /// restored afterwards.
+    fn enter(&mut self, kind: ScopeKind) -> ScopeId {
+        let outer = self.scope;
+        self.scope = self.new_scope(kind, Some(outer), Some(self.res.module_of(outer)));
+        outer
+    }
+
+    /// Declares `ident` in `scope`. Variables shadow earlier definitions with the same name,
+    /// unless both are bound by the same pattern or parameter list, while items can't share a name
+    /// with another item in the same scope. A duplicate is reported, and the original stays in
+    /// scope.
+    fn define(
+        &mut self,
+        scope: ScopeId,
+        ident: &Ident,
+        kind: DefKind,
+        members: Option<ScopeId>,
+    ) -> DefId {
+        let original = if kind.is_item() {
+            self.res
+                .scope(scope)
+                .names
+                .get(&ident.name)
+                .copied()
+                .filter(|&def| {
+                    let def = self.res.def(def);
+                    def.scope == scope && def.kind.is_item()
+                })
+        } else {
+            self.bindings.get(&ident.name).copied()
+        };
+
+        let id = DefId(self.res.defs.len());
+        self.res.defs.push(Definition {
+            name: ident.name.clone(),
+            kind,
+            span: ident.span.clone(),
+            scope,
+            members,
+            uses: 0,
+            mutable: false,
+            public: false,
+        });
+        match original {
+            Some(original) => self.res.errors.push(ResolveError::Duplicate {
+                name: ident.name.clone(),
+                span: ident.span.clone(),
+                original: self.res.def(original).span.clone(),
+            }),
+            None => {
+                self.res.scopes[scope.0]
+                    .names
+                    .insert(ident.name.clone(), id);
+            }
+        }
+        if !kind.is_item() {
+            self.bindings.insert(ident.name.clone(), id);
+        }
+        self.res.idents.insert(ident.span.clone(), id);
+        id
+    }
+
+    /// Declares the built in enums and functions in the prelude, along with the enums' variants so
+    /// that they can be used without the enum's name, the methods of `string` and arrays, `Map`
+    /// and `Gen` with their members, and the `math` and `fs` modules. They have empty spans, as they
+    /// aren't written anywhere.
+    fn declare_prelude(&mut self) {
+        let prelude = self.new_scope(ScopeKind::Module, None, None);
+        debug_assert_eq!(prelude, ScopeId::PRELUDE);
+        for (name, variants) in [("Option", ["Some", "None"]), ("Result", ["Ok", "Err"])] {
+            let members = self.new_scope(ScopeKind::Members, None, Some(prelude));
+            self.declare_builtin(prelude, name, DefKind::Enum, Some(members));
+            for variant in variants {
+                let def = self.declare_builtin(members, variant, DefKind::Variant, None);
+                self.res.scopes[prelude.0]
+                    .names
+                    .insert(variant.to_string(), def);
+            }
+        }
+        for name in ["panic", "print", "println", "input"] {
+            self.declare_builtin(prelude, name, DefKind::Fn, None);
+        }
+        let string = self.new_scope(ScopeKind::Members, None, Some(prelude));
+        debug_assert_eq!(string, ScopeId::STRING);
+        for name in ["len", "contains", "split", "to_upper", "to_lower"] {
+            self.declare_builtin(string, name, DefKind::Method, None);
+        }
+        let array = self.new_scope(ScopeKind::Members, None, Some(prelude));
+        debug_assert_eq!(array, ScopeId::ARRAY);
+        for name in ["len", "push", "pop"] {
+            self.declare_builtin(array, name, DefKind::Method, None);
+        }
+        let map = self.new_scope(ScopeKind::Members, None, Some(prelude));
+        debug_assert_eq!(map, ScopeId::MAP);
+        self.declare_builtin(prelude, "Map", DefKind::Struct, Some(map));
+        self.declare_builtin(map, "new", DefKind::Fn, None);
+        for name in ["len", "insert", "get", "remove", "contains"] {
+            self.declare_builtin(map, name, DefKind::Method, None);
+        }
+        let math = self.new_scope(ScopeKind::Module, None, Some(prelude));
+        debug_assert_eq!(math, ScopeId::MATH);
+        let def = self.declare_builtin(prelude, "math", DefKind::Mod, Some(math));
+        self.modules.insert(math, def);
+        for name in ["sqrt", "abs", "pow", "floor", "ceil", "min", "max"] {
+            self.declare_builtin(math, name, DefKind::Fn, None);
+        }
+        self.declare_builtin(math, "pi", DefKind::Const, None);
+        let fs = self.new_scope(ScopeKind::Module, None, Some(prelude));
+        debug_assert_eq!(fs, ScopeId::FS);
+        let def = self.declare_builtin(prelude, "fs", DefKind::Mod, Some(fs));
+        self.modules.insert(fs, def);
+        for name in ["read_to_string", "write", "exists"] {
+            self.declare_builtin(fs, name, DefKind::Fn, None);
+        }
+        let gen = self.new_scope(ScopeKind::Members, None, Some(prelude));
+        debug_assert_eq!(gen, ScopeId::GEN);
+        self.declare_builtin(prelude, "Gen", DefKind::Struct, Some(gen));
+        self.declare_builtin(gen, "next", DefKind::Method, None);
+        debug_assert_eq!(self.res.defs.len(), DefId::GEN_NEXT.0 + 1);
+    }
+
+    fn declare_builtin(
+        &mut self,
+        scope: ScopeId,
+        name: &str,
+        kind: DefKind,
+        members: Option<ScopeId>,
+    ) -> DefId {
+        let id = DefId(self.res.defs.len());
+        self.res.defs.push(Definition {
+            name: name.to_string(),
+            kind,
+            span: 0..0,
+            scope,
+            members,
+            uses: 0,
+            mutable: false,
+            public: true,
+        });
+        self.res.scopes[scope.0].names.insert(name.to_string(), id);
+        id
+    }
+
+    /// Declares the items in a module or block, along with the members of any modules, types and
+    /// traits among them.
+    fn declare_items(&mut self, scope: ScopeId, items: impl IntoIterator<Item = &'ast Item>) {
+        for item in items {
+            let module = Some(self.res.module_of(scope));
+            let def = match &item.kind {
+                ItemKind::Const(decl) => self.define(scope, &decl.name, DefKind::Const, None),
+                ItemKind::Static(decl) => self.define(scope, &decl.name, DefKind::Static, None),
+                ItemKind::Fn(decl) => self.define(scope, &decl.name, DefKind::Fn, None),
+                ItemKind::Extern(decl) => self.define(scope, &decl.name, DefKind::Fn, None),
+                ItemKind::Struct(decl) => {
+                    let members = self.new_scope(ScopeKind::Members, None, module);
+                    let def = self.define(scope, &decl.name, DefKind::Struct, Some(members));
+                    self.fields.insert(def, &decl.fields);
+                    def
+                }
+                ItemKind::Enum(decl) => {
+                    let members = self.new_scope(ScopeKind::Members, None, module);
+                    let def = self.define(scope, &decl.name, DefKind::Enum, Some(members));
+                    for variant in &decl.variants {
+                        let variant = self.define(members, &variant.name, DefKind::Variant, None);
+                        self.res.defs[variant.0].public = true;
+                    }
+                    def
+                }
+                ItemKind::Trait(decl) => {
+                    let members = self.new_scope(ScopeKind::Members, None, module);
+                    let def = self.define(scope, &decl.name, DefKind::Trait, Some(members));
+                    for method in &decl.methods {
+                        let def = self.define(members, &method.name, DefKind::Method, None);
+                        self.res.defs[def.0].public = true;
+                        if method.body.is_some() {
+                            self.provided.insert(def);
+                        }
+                    }
+                    def
+                }
+                ItemKind::Mod(decl) => {
+                    let members = self.new_scope(ScopeKind::Module, None, module);
+                    let def = self.define(scope, &decl.name, DefKind::Mod, Some(members));
+                    self.modules.insert(members, def);
+                    if let Some(items) = &decl.items {
+                        self.declare_items(members, items);
+                    }
+                    def
+                }
+                ItemKind::Impl(decl) => {
+                    self.impls.push((scope, decl));
+                    continue;
+                }
+                ItemKind::Use(path) => {
+                    self.imports.push((scope, path));
+                    continue;
+                }
+                ItemKind::Error => continue,
+            };
+            self.res.defs[def.0].public = item.public;
+        }
+    }
+
+    /// Resolves the pending `use` declarations. Paths are resolved from the root of the program,
+    /// and an import can go through a name brought in by another import, so they're retried
+    /// until no more can be resolved.
+    fn resolve_imports(&mut self) {
+        loop {
+            let pending = self.imports.len();
+            for (scope, path) in std::mem::take(&mut self.imports) {
+                match self.resolve_path(import_scope(scope, path), path) {
+                    Ok(defs) => {
+                        self.record(path, &defs);
+                        self.check_visibility(scope, path, &defs);
+                        let name = path.segments.last().unwrap().name.clone();
+                        self.res.scopes[scope.0]
+                            .names
+                            .insert(name, *defs.last().unwrap());
+                    }
+                    Err(_) => self.imports.push((scope, path)),
+                }
+            }
+
+            if self.imports.len() == pending {
+                break;
+            }
+        }
+
+        let imports = std::mem::take(&mut self.imports);
+        let cycles = self.import_cycles(&imports);
+        for (i, &(scope, path)) in imports.iter().enumerate() {
+            if !cycles.iter().flatten().any(|&j| i == j) {
+                self.resolve_and_record(import_scope(scope, path), path, NameKind::Import);
+            }
+        }
+        for cycle in cycles {
+            let imports = cycle
+                .into_iter()
+                .map(|i| {
+                    let path = imports[i].1;
+                    let names: Vec<_> = path.segments.iter().map(|s| s.name.as_str()).collect();
+                    (names.join("::"), path.span.clone())
+                })
+                .collect();
+            self.res.errors.push(ResolveError::ImportCycle { imports });
+        }
+    }
+
+    /// Finds the cycles among the imports that couldn't be resolved, where each import is stuck
+    /// on a name that the next one would import. Returns the indices of the imports in each cycle.
+    fn import_cycles(&self, imports: &[(ScopeId, &Path)]) -> Vec<Vec<usize>> {
+        // The import that would define the name each import is stuck on
+        let next: Vec<Option<usize>> = imports
+            .iter()
+            .map(|&(scope, path)| {
+                let parents = self.resolve_path(import_scope(scope, path), path).err()?;
+                let scope = match parents.last() {
+                    None => ScopeId::ROOT,
+                    Some(&parent) => self.res.def(parent).members?,
+                };
+                let name = &path.segments[parents.len()].name;
+                imports.iter().position(|(into, import)| {
+                    *into == scope && import.segments.last().unwrap().name == *name
+                })
+            })
+            .collect();
+
+        let mut cycles: Vec<Vec<usize>> = Vec::new();
+        let mut visited = vec![false; imports.len()];
+        for start in 0..imports.len() {
+            let mut chain = Vec::new();
+            let mut current = Some(start);
+            while let Some(i) = current {
+                if let Some(pos) = chain.iter().position(|&j| j == i) {
+                    cycles.push(chain.split_off(pos));
+                    break;
+                }
+                if visited[i] {
+                    break;
+                }
+                visited[i] = true;
+                chain.push(i);
+                current = next[i];
+            }
+        }
+        cycles
+    }
+
+    /// Declares the methods of the pending `impl` blocks. Methods that aren't part of a trait are
+    /// members of their type, so they can be called like `Point::new()`.
+    fn attach_impls(&mut self) {
+        for (scope, decl) in std::mem::take(&mut self.impls) {
+            let ty = match &decl.self_ty.kind {
+                // Trait methods are called on values, so they're found by the type checker
+                _ if decl.trait_ref.is_some() => None,
+                TypeExprKind::Path(path) | TypeExprKind::Generic { path, .. } => self
+                    .resolve_path(scope, path)
+                    .ok()
+                    .and_then(|defs| self.res.def(*defs.last().unwrap()).members),
+                _ => None,
+            };
+            // The methods still need a scope to be declared in, to catch duplicates
+            let members = ty.unwrap_or_else(|| {
+                let module = Some(self.res.module_of(scope));
+                self.new_scope(ScopeKind::Members, None, module)
+            });
+            for method in &decl.methods {
+                if let ItemKind::Fn(func) = &method.kind {
+                    let def = self.define(members, &func.name, DefKind::Method, None);
+                    // The methods of a trait are as visible as the trait
+                    self.res.defs[def.0].public = method.public || decl.trait_ref.is_some();
+                }
+            }
+            if let Some(trait_ref) = &decl.trait_ref {
+                self.provide_methods(scope, trait_ref, members, &decl.self_ty.span);
+            }
+        }
+    }
+
+    /// Declares the methods that an impl of a trait takes from the trait in `members`, the scope
+    /// of the impl's own methods. They're copies of the trait's methods, which have no identifier
+    /// of their own in the source.
+    fn provide_methods(&mut self, scope: ScopeId, trait_ref: &Path, members: ScopeId, ty: &Span) {
+        let Ok(defs) = self.resolve_path(scope, trait_ref) else {
+            return;
+        };
+        let trait_def = self.res.def(*defs.last().unwrap());
+        let (DefKind::Trait, Some(trait_members)) = (trait_def.kind, trait_def.members) else {
+            return;
+        };
+        let methods: Vec<DefId> = self
+            .res
+            .scope(trait_members)
+            .names
+            .values()
+            .copied()
+            .collect();
+        for method in methods {
+            let definition = self.res.def(method);
+            if !self.provided.contains(&method)
+                || self.res.scope(members).names.contains_key(&definition.name)
+            {
+                continue;
+            }
+            let def = DefId(self.res.defs.len());
+            let name = definition.name.clone();
+            self.res.defs.push(Definition {
+                scope: members,
+                ..definition.clone()
+            });
+            self.res.scopes[members.0].names.insert(name, def);
+            self.res.provided.push(ProvidedMethod {
+                def,
+                method,
+                impl_ty: ty.clone(),
+            });
+        }
+    }
+
+    /// Looks up a name in `scope` and the scopes around it. Once the lookup leaves a function,
+    /// only items are visible, as functions can't use the local variables of another function.
+    /// Names that aren't declared anywhere around the scope are looked up in the prelude.
+    fn lookup(&self, scope: ScopeId, name: &str) -> Option<DefId> {
+        let mut scope = Some(scope);
+        let mut items_only = false;
+        while let Some(id) = scope {
+            let current = self.res.scope(id);
+            if let Some(&def) = current.names.get(name) {
+                if !items_only || self.res.def(def).kind.is_item() {
+                    return Some(def);
+                }
+            }
+
+            items_only |= current.kind == ScopeKind::Function;
+            scope = current.parent;
+        }
+        self.res.scope(ScopeId::PRELUDE).names.get(name).copied()
+    }
+
+    /// Returns the visible name most similar to `name`, if one is close enough to be a likely
+    /// typo. Names in the scopes around `scope` are only considered when `lexical` is set.
+    fn similar_name(&self, scope: ScopeId, name: &str, lexical: bool) -> Option<DefId> {
+        let max_distance = (name.chars().count() / 3).max(1);
+        let mut best: Option<(usize, DefId)> = None;
+        let mut scope = Some(scope);
+        let mut items_only = false;
+        while let Some(id) = scope {
+            let current = self.res.scope(id);
+            for (candidate, &def) in &current.names {
+                if items_only && !self.res.def(def).kind.is_item() {
+                    continue;
+                }
+                let distance = edit_distance(name, candidate);
+                if distance <= max_distance && best.is_none_or(|(best, _)| distance < best) {
+                    best = Some((distance, def));
+                }
+            }
+
+            if !lexical {
+                break;
+            }
+            items_only |= current.kind == ScopeKind::Function;
+            scope = current.parent;
+        }
+        best.map(|(_, def)| def)
+    }
+
+    /// Resolves each segment of a path, where the first is looked up from `scope` and the rest
+    /// are members of the segment before. On failure, returns the definitions of the segments
+    /// before the one that couldn't be found.
+    fn resolve_path(&self, scope: ScopeId, path: &Path) -> Result<Vec<DefId>, Vec<DefId>> {
+        let mut defs: Vec<DefId> = Vec::with_capacity(path.segments.len());
+        for segment in &path.segments {
+            let def = match (defs.last(), segment.name.as_str()) {
+                // `self::` and `super::` are relative to the module that `scope` is part of
+                (None, "self") if path.segments.len() > 1 => {
+                    self.modules.get(&self.res.module_of(scope)).copied()
+                }
+                (None, "super") => self.parent_module(self.res.module_of(scope)),
+                (Some(&parent), "super") => {
+                    self.parent_module(self.res.def(parent).members.unwrap())
+                }
+                (None, _) => self.lookup(scope, &segment.name),
+                (Some(&parent), _) => self
+                    .res
+                    .def(parent)
+                    .members
+                    .and_then(|members| self.res.scope(members).names.get(&segment.name))
+                    .copied(),
+            };
+            match def {
+                Some(def) => defs.push(def),
+                None => return Err(defs),
+            }
+        }
+        Ok(defs)
+    }
+
+    /// Returns the definition of the module that `module` is declared in.
+    fn parent_module(&self, module: ScopeId) -> Option<DefId> {
+        let parent = self.res.scope(module).module?;
+        self.modules.get(&parent).copied()
+    }
+
+    /// Records the definitions of a path's segments, counting each as used by the current item.
+    fn record(&mut self, path: &Path, defs: &[DefId]) {
+        for (segment, &def) in path.segments.iter().zip(defs) {
+            self.res.idents.insert(segment.span.clone(), def);
+            self.res.defs[def.0].uses += 1;
+            self.refs.push((self.item, def));
+        }
+    }
+
+    /// Reports the first segment of a resolved path that's private to a module `scope` isn't
+    /// part of. The first segment is in scope, so it's always visible.
+    fn check_visibility(&mut self, scope: ScopeId, path: &Path, defs: &[DefId]) {
+        for (segment, &def) in path.segments.iter().zip(defs).skip(1) {
+            if !self.res.is_visible(def, scope) {
+                let def = self.res.def(def);
+                self.res.errors.push(ResolveError::Private {
+                    kind: def.kind.describe(),
+                    name: def.name.clone(),
+                    span: segment.span.clone(),
+                    definition: def.span.clone(),
+                });
+                return;
+            }
+        }
+    }
+
+    /// Reports the private fields among `names` when `def` is a struct declared in a module that
+    /// the current scope isn't part of.
+    fn check_fields<'a>(&mut self, def: Option<DefId>, names: impl Iterator<Item = &'a Ident>) {
+        let Some(decls) = def.and_then(|def| self.fields.get(&def)) else {
+            return;
+        };
+        let module = self.res.module_of(self.res.def(def.unwrap()).scope);
+        if self.res.is_accessible(module, self.scope) {
+            return;
+        }
+
+        for name in names {
+            if let Some(field) = decls.iter().find(|field| field.name.name == name.name) {
+                if !field.public {
+                    self.res.errors.push(ResolveError::Private {
+                        kind: "field",
+                        name: name.name.clone(),
+                        span: name.span.clone(),
+                        definition: field.name.span.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Resolves a path, recording its definitions or reporting the segment that's undefined.
+    /// `kind` is what the path is expected to name.
+    fn resolve_and_record(&mut self, scope: ScopeId, path: &Path, kind: NameKind) -> Option<DefId> {
+        let parents = match self.resolve_path(scope, path) {
+            Ok(defs) => {
+                self.record(path, &defs);
+                self.check_visibility(scope, path, &defs);
+                return defs.last().copied();
+            }
+            Err(parents) => parents,
+        };
+
+        let segment = &path.segments[parents.len()];
+        let candidate = |def: Option<DefId>| {
+            def.map(|def| {
+                let def = self.res.def(def);
+                Ident {
+                    name: def.name.clone(),
+                    span: def.span.clone(),
+                }
+            })
+        };
+        let error = match parents.last() {
+            _ if segment.name == "super" => ResolveError::TooManySupers {
+                span: segment.span.clone(),
+            },
+            None if segment.name == "self" => ResolveError::SelfOutsideMethod {
+                span: segment.span.clone(),
+            },
+            None => ResolveError::Undefined {
+                kind,
+                name: segment.name.clone(),
+                span: segment.span.clone(),
+                candidate: candidate(self.similar_name(scope, &segment.name, true)),
+            },
+            Some(&parent) => {
+                let members = self.res.def(parent).members;
+                ResolveError::NoMember {
+                    parent: path.segments[parents.len() - 1].name.clone(),
+                    name: segment.name.clone(),
+                    span: segment.span.clone(),
+                    candidate: candidate(
+                        members
+                            .and_then(|members| self.similar_name(members, &segment.name, false)),
+                    ),
+                }
+            }
+        };
+        self.res.errors.push(error);
+        None
+    }
+
+    /// Reports the variables just bound by a `let` that hide a variable from a scope around the
+    /// current one. Redeclaring a variable in the same scope is a common way to transform a value,
+    /// so it isn't reported.
+    fn check_shadowing(&mut self) {
+        let Some(parent) = self.res.scope(self.scope).parent else {
+            return;
+        };
+        let mut lints: Vec<_> = self
+            .bindings
+            .iter()
+            .filter_map(|(name, &def)| {
+                let original = self.lookup(parent, name)?;
+                let original = self.res.def(original);
+                matches!(original.kind, DefKind::Local | DefKind::Param).then(|| Lint::Shadowing {
+                    name: name.clone(),
+                    span: self.res.def(def).span.clone(),
+                    original: original.span.clone(),
+                })
+            })
+            .collect();
+        self.res.lints.append(&mut lints);
+    }
+
+    /// Reports an assignment of `span` to `target` when the variable that `target` is part of
+    /// isn't mutable. The target must already be resolved.
+    fn check_mutable(&mut self, target: &Expr, span: Span) {
+        let mut place = target;
+        let mut reassign = true;
+        loop {
+            match &place.kind {
+                ExprKind::Paren(inner) => place = inner,
+                ExprKind::Field { base, .. } | ExprKind::Index { base, .. } => {
+                    place = base;
+                    reassign = false;
+                }
+                _ => break,
+            }
+        }
+        let ExprKind::Path(path) = &place.kind else {
+            return;
+        };
+        let Some(def) = self.res.lookup(&path.segments.last().unwrap().span) else {
+            return;
+        };
+
+        let def = self.res.def(def);
+        if matches!(def.kind, DefKind::Local | DefKind::Param) && !def.mutable {
+            self.res.errors.push(ResolveError::Immutable {
+                kind: def.kind,
+                name: def.name.clone(),
+                span,
+                binding: def.span.clone(),
+                reassign,
+            });
+        }
+    }
+
+    /// Resolves the parameters, return type and body of a function.
+    fn resolve_fn(
+        &mut self,
+        generics: &'ast [GenericParam],
+        params: &'ast [Param],
+        ret: &'ast Option<TypeExpr>,
+        body: Option<&'ast Block>,
+    ) {
+        let outer = self.enter(ScopeKind::Function);
+        self.bindings.clear();
+        // The type parameters are declared first, so every bound and parameter can use them
+        for generic in generics {
+            self.define(self.scope, &generic.name, DefKind::TypeParam, None);
+        }
+        for generic in generics {
+            for bound in &generic.bounds {
+                self.resolve_and_record(self.scope, bound, NameKind::Type);
+            }
+        }
+        let first_param = self.res.defs.len();
+        for param in params {
+            self.visit_type(&param.ty);
+            self.bind_pattern(&param.pattern, DefKind::Param);
+        }
+        // The parameters of a declaration without a body only name what it takes, so they're
+        // never unused
+        if body.is_none() {
+            for def in &mut self.res.defs[first_param..] {
+                def.uses += 1;
+            }
+        }
+        if let Some(ret) = ret {
+            self.visit_type(ret);
+        }
+        if let Some(body) = body {
+            self.visit_block(body);
+        }
+        self.scope = outer;
+    }
+
+    /// Resolves a function or method like [`Resolver::resolve_fn`], with the uses in its body
+    /// counted as its own.
+    fn resolve_item_fn(
+        &mut self,
+        name: &Ident,
+        generics: &'ast [GenericParam],
+        params: &'ast [Param],
+        ret: &'ast Option<TypeExpr>,
+        body: Option<&'ast Block>,
+    ) {
+        let outer = std::mem::replace(&mut self.item, self.res.lookup(&name.span));
+        self.resolve_fn(generics, params, ret, body);
+        self.item = outer;
+    }
+
+    /// Declares the names bound by a pattern in the current scope, and resolves the paths in it.
+    /// A lone name that refers to an enum variant or constant matches against it rather than
+    /// binding a new variable.
+    fn bind_pattern(&mut self, pattern: &'ast Pattern, kind: DefKind) {
+        match &pattern.kind {
+            PatternKind::Wildcard | PatternKind::Literal(_) => {}
+            PatternKind::Ident(ident) => match self.lookup(self.scope, &ident.name) {
+                Some(def)
+                    if matches!(self.res.def(def).kind, DefKind::Variant | DefKind::Const) =>
+                {
+                    self.res.idents.insert(ident.span.clone(), def);
+                    self.res.defs[def.0].uses += 1;
+                }
+                _ => {
+                    self.define(self.scope, ident, kind, None);
+                }
+            },
+            PatternKind::Mut(ident) => {
+                let def = self.define(self.scope, ident, kind, None);
+                self.res.defs[def.0].mutable = true;
+            }
+            PatternKind::Tuple(patterns) => {
+                for pattern in patterns {
+                    self.bind_pattern(pattern, kind);
+                }
+            }
+            PatternKind::Path(path) => {
+                self.resolve_and_record(self.scope, path, NameKind::Value);
+            }
+            PatternKind::TupleVariant { path, fields } => {
+                self.resolve_and_record(self.scope, path, NameKind::Value);
+                for pattern in fields {
+                    self.bind_pattern(pattern, kind);
+                }
+            }
+            PatternKind::StructVariant { path, fields } => {
+                let def = self.resolve_and_record(self.scope, path, NameKind::Value);
+                self.check_fields(def, fields.iter().map(|field| &field.name));
+                for field in fields {
+                    self.bind_pattern(&field.pattern, kind);
+                }
+            }
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Resolver<'ast> {
+    fn visit_item(&mut self, item: &'ast Item) {
+        match &item.kind {
+            ItemKind::Fn(decl) => self.resolve_item_fn(
+                &decl.name,
+                &decl.generics,
+                &decl.params,
+                &decl.ret,
+                Some(&decl.body),
+            ),
+            ItemKind::Extern(decl) => self.resolve_fn(&[], &decl.params, &decl.ret, None),
+            ItemKind::Trait(decl) => {
+                for method in &decl.methods {
+                    self.resolve_item_fn(
+                        &method.name,
+                        &method.generics,
+                        &method.params,
+                        &method.ret,
+                        method.body.as_ref(),
+                    );
+                }
+            }
+            ItemKind::Impl(decl) => {
+                if let Some(trait_ref) = &decl.trait_ref {
+                    self.resolve_and_record(self.scope, trait_ref, NameKind::Type);
+                }
+                self.visit_type(&decl.self_ty);
+                for method in &decl.methods {
+                    self.visit_item(method);
+                }
+            }
+            ItemKind::Mod(decl) => {
+                let def = self.res.lookup(&decl.name.span).unwrap();
+                let outer = std::mem::replace(&mut self.scope, self.res.def(def).members.unwrap());
+                for item in decl.items.iter().flatten() {
+                    self.visit_item(item);
+                }
+                self.scope = outer;
+            }
+            // The names of these were declared up front
+            ItemKind::Use(_) | ItemKind::Error => {}
+            ItemKind::Const(_) | ItemKind::Static(_) | ItemKind::Struct(_) | ItemKind::Enum(_) => {
+                visit::walk_item(self, item)
+            }
+        }
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        let outer = self.enter(ScopeKind::Block);
+        let items = block.stmts.iter().filter_map(|stmt| match &stmt.kind {
+            StmtKind::Item(item) => Some(item),
+            _ => None,
+        });
+        self.declare_items(self.scope, items);
+        self.resolve_imports();
+        self.attach_impls();
+
+        visit::walk_block(self, block);
+        self.scope = outer;
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        match &stmt.kind {
+            // The value is resolved first, so `let x = x + 1;` uses the previous `x`
+            StmtKind::Let { pattern, ty, value } => {
+                if let Some(ty) = ty {
+                    self.visit_type(ty);
+                }
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+                self.bindings.clear();
+                self.bind_pattern(pattern, DefKind::Local);
+                if self.options.shadowing {
+                    self.check_shadowing();
+                }
+            }
+            _ => visit::walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        match &expr.kind {
+            ExprKind::Path(path) => {
+                self.resolve_and_record(self.scope, path, NameKind::Value);
+            }
+            ExprKind::Assign { target, value } => {
+                if let ExprKind::Path(path) = &target.kind {
+                    // Assigning to a variable doesn't read it, so it isn't counted as a use
+                    if let Some(def) = self.resolve_and_record(self.scope, path, NameKind::Value) {
+                        self.res.defs[def.0].uses -= 1;
+                    }
+                } else {
+                    self.visit_expr(target);
+                }
+                self.visit_expr(value);
+                self.check_mutable(target, expr.span.clone());
+            }
+            ExprKind::CompoundAssign { target, value, .. } => {
+                self.visit_expr(target);
+                self.visit_expr(value);
+                self.check_mutable(target, expr.span.clone());
+            }
+            ExprKind::StructLit { path, fields } => {
+                let def = self.resolve_and_record(self.scope, path, NameKind::Type);
+                self.check_fields(def, fields.iter().map(|field| &field.name));
+                for field in fields {
+                    self.visit_expr(&field.value);
+                }
+            }
+            ExprKind::For(for_loop) => {
+                self.visit_expr(&for_loop.iter);
+                let outer = self.enter(ScopeKind::Block);
+                self.bindings.clear();
+                self.bind_pattern(&for_loop.pattern, DefKind::Local);
+                self.visit_block(&for_loop.body);
+                self.scope = outer;
+            }
+            ExprKind::Closure(closure) => {
+                let outer = self.enter(ScopeKind::Closure);
+                self.bindings.clear();
+                for param in &closure.params {
+                    if let Some(ty) = &param.ty {
+                        self.visit_type(ty);
+                    }
+                    self.bind_pattern(&param.pattern, DefKind::Param);
+                }
+                self.visit_expr(&closure.body);
+                self.scope = outer;
+            }
+            ExprKind::Match(match_expr) => {
+                self.visit_expr(&match_expr.scrutinee);
+                for arm in &match_expr.arms {
+                    let outer = self.enter(ScopeKind::Block);
+                    self.bindings.clear();
+                    self.bind_pattern(&arm.pattern, DefKind::Local);
+                    self.visit_expr(&arm.body);
+                    self.scope = outer;
+                }
+            }
+            ExprKind::MethodCall { method, .. } => {
+                self.method_calls.push((self.item, method.name.clone()));
+                visit::walk_expr(self, expr);
+            }
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+
+    fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+        self.bindings.clear();
+        self.bind_pattern(pattern, DefKind::Local);
+    }
+
+    /// Names of types that aren't defined in the program, such as `int`, are left for the type
+    /// checker, which knows the built in types.
+    fn visit_type(&mut self, ty: &'ast TypeExpr) {
+        if let TypeExprKind::Path(path) | TypeExprKind::Generic { path, .. } = &ty.kind {
+            match self.resolve_path(self.scope, path) {
+                Ok(defs) => self.record(path, &defs),
+                Err(_) if path.segments.len() > 1 => {
+                    self.resolve_and_record(self.scope, path, NameKind::Type);
+                }
+                Err(_) => {}
+            }
+        }
+        visit::walk_type(self, ty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_source;
+
+    /// Returns the definition referred to by the `nth` occurrence of the word `name` in the
+    /// source.
+    fn def_of<'r>(
+        res: &'r Resolution,
+        source: &str,
+        name: &str,
+        nth: usize,
+    ) -> Option<&'r Definition> {
+        let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let (start, _) = source
+            .match_indices(name)
+            .filter(|(start, _)| {
+                !is_word(source[..*start].chars().next_back())
+                    && !is_word(source[start + name.len()..].chars().next())
+            })
+            .nth(nth)
+            .unwrap();
+        res.lookup(&(start..start + name.len()))
+            .map(|id| res.def(id))
+    }
+
+    #[test]
+    fn test_resolve_locals() {
+        let source = "fn main(a: int) { let x = a; let x = x + 1; { let y = x; } x }";
+        let res = resolve(&parse_source(source).unwrap());
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+
+        let a = def_of(&res, source, "a", 0).unwrap();
+        assert_eq!(a.kind, DefKind::Param);
+        assert_eq!(def_of(&res, source, "a", 1), Some(a));
+
+        // Each `let x` gets its own id, and the second one's value uses the first
+        let first = res.lookup(&(22..23)).unwrap();
+        let second = res.lookup(&(33..34)).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(res.lookup(&(37..38)), Some(first));
+        assert_eq!(
+            def_of(&res, source, "x", 3).map(|d| &d.span),
+            Some(&(33..34))
+        );
+        assert_eq!(
+            def_of(&res, source, "x", 4).map(|d| &d.span),
+            Some(&(33..34))
+        );
+        assert_eq!(def_of(&res, source, "y", 0).unwrap().kind, DefKind::Local);
+    }
+
+    #[test]
+    fn test_resolve_items_and_paths() {
+        let source = "fn main() { helper(); let s = Shape::Circle(1.0); match s { Shape::Circle(r) => r, Empty => math::PI } }
+fn helper() {}
+use Shape::Empty;
+enum Shape { Circle(float), Empty }
+mod math { pub const PI: float = 3.14; fn tau() float { PI * 2.0 } }";
+        let res = resolve(&parse_source(source).unwrap());
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+
+        let helper = def_of(&res, source, "helper", 0).unwrap();
+        assert_eq!(helper.kind, DefKind::Fn);
+        assert_eq!(def_of(&res, source, "helper", 1), Some(helper));
+
+        let circle = def_of(&res, source, "Circle", 0).unwrap();
+        assert_eq!(circle.kind, DefKind::Variant);
+        assert_eq!(def_of(&res, source, "Circle", 1), Some(circle));
+        assert_eq!(def_of(&res, source, "Circle", 2), Some(circle));
+        assert_eq!(
+            def_of(&res, source, "Shape", 0).unwrap().kind,
+            DefKind::Enum
+        );
+
+        // `Empty` in the match arm matches the imported variant instead of binding a variable
+        let empty = def_of(&res, source, "Empty", 0).unwrap();
+        assert_eq!(empty.kind, DefKind::Variant);
+        assert_eq!(def_of(&res, source, "Empty", 1), Some(empty));
+        assert_eq!(def_of(&res, source, "Empty", 2), Some(empty));
+        assert_eq!(def_of(&res, source, "r", 0).unwrap().kind, DefKind::Local);
+
+        let pi = def_of(&res, source, "PI", 0).unwrap();
+        assert_eq!(pi.kind, DefKind::Const);
+        assert_eq!(def_of(&res, source, "PI", 2), Some(pi));
+    }
+
+    #[test]
+    fn test_resolve_prelude() {
+        let source = "fn f(x: Option<int>) Result<int, string> { match x { Some(v) => Ok(v), None => Err(\"none\") } }
+mod m { fn g() { Option::Some(1); } enum Local { None } fn h(l: Local) { use self::Local::None; match l { None => {} } } }";
+        let res = resolve(&parse_source(source).unwrap());
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+
+        assert_eq!(res.lookup(&(8..14)), Some(DefId::OPTION));
+        assert_eq!(
+            def_of(&res, source, "Result", 0),
+            Some(res.def(DefId::RESULT))
+        );
+        assert_eq!(def_of(&res, source, "Some", 0), Some(res.def(DefId::SOME)));
+        assert_eq!(def_of(&res, source, "Some", 1), Some(res.def(DefId::SOME)));
+        assert_eq!(def_of(&res, source, "None", 0), Some(res.def(DefId::NONE)));
+        assert_eq!(def_of(&res, source, "Ok", 0), Some(res.def(DefId::OK)));
+        assert_eq!(def_of(&res, source, "Err", 0), Some(res.def(DefId::ERR)));
+
+        // Names declared by the program hide the prelude
+        let none = def_of(&res, source, "None", 1).unwrap();
+        assert_ne!(none, res.def(DefId::NONE));
+        assert_eq!(def_of(&res, source, "None", 3), Some(none));
+    }
+
+    #[test]
+    fn test_resolve_methods() {
+        let source = "trait Show { fn show(self) string; }
+struct Point { x: int }
+impl Show for Point { fn show(self) string { self.x } }
+impl Point { fn new() Point { Point { x: 0 } } }
+fn main() { let p = Point::new(); p.show() }";
+        let res = resolve(&parse_source(source).unwrap());
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+
+        assert_eq!(
+            def_of(&res, source, "Show", 1).unwrap().kind,
+            DefKind::Trait
+        );
+        let new = def_of(&res, source, "new", 1).unwrap();
+        assert_eq!(new.kind, DefKind::Method);
+        assert_eq!(new.span, def_of(&res, source, "new", 0).unwrap().span);
+        assert_eq!(
+            def_of(&res, source, "self", 2).unwrap().kind,
+            DefKind::Param
+        );
+        // Method calls depend on the receiver's type, so they're left to the type checker
+        assert_eq!(def_of(&res, source, "show", 2), None);
+    }
+
+    #[test]
+    fn test_resolve_nested_scopes() {
+        let source = "fn main() { let x = 1; fn inner() { x } let f = |y| x + y; f(x) }";
+        let res = resolve(&parse_source(source).unwrap());
+
+        // Nested functions can't see the locals around them, but closures can
+        assert_eq!(
+            res.errors,
+            vec![ResolveError::Undefined {
+                kind: NameKind::Value,
+                name: "x".to_string(),
+                span: 36..37,
+                candidate: None,
+            }]
+        );
+        let x = def_of(&res, source, "x", 0).unwrap();
+        assert_eq!(def_of(&res, source, "x", 2), Some(x));
+        assert_eq!(def_of(&res, source, "x", 3), Some(x));
+        assert_eq!(def_of(&res, source, "y", 1).unwrap().kind, DefKind::Param);
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let source =
+            "use missing::thing; fn main() { let a = b; Shape::Square; } enum Shape { Circle }";
+        let res = resolve(&parse_source(source).unwrap());
+
+        let errors: Vec<_> = res.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "unresolved import `missing`",
+                "cannot find value `b` in this scope",
+                "cannot find `Square` in `Shape`",
+            ]
+        );
+        assert_eq!(&source[res.errors[2].span().clone()], "Square");
+    }
+
+    #[test]
+    fn test_resolve_import_cycles() {
+        let source = "mod a { use b::x; }
+mod b { use a::x; }
+use c::y; use z;
+mod c { use crate_root::y; }
+use d::w;
+mod d { }";
+        let res = resolve(&parse_source(source).unwrap());
+
+        let errors: Vec<_> = res.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                // Imports that depend on a broken one report the name they're missing
+                "cannot find `y` in `c`",
+                "unresolved import `crate_root`",
+                "cannot find `w` in `d`",
+                "import cycle: `b::x` -> `a::x` -> `b::x`",
+                "import cycle: `z` -> `z`",
+            ]
+        );
+        let diagnostic = res.errors[3].to_diagnostic();
+        assert_eq!(&source[diagnostic.span.clone()], "b::x");
+        assert_eq!(diagnostic.notes[0].message, "`a::x` is imported here");
+        assert_eq!(diagnostic.notes[0].span, Some(32..36));
+    }
+
+    #[test]
+    fn test_resolve_similar_names() {
+        let source = "fn main() { let count = 1; let total = cuont + Shape::Circel; }
+enum Shape { Circle }";
+        let res = resolve(&parse_source(source).unwrap());
+
+        let diagnostics: Vec<_> = res.errors.iter().map(|e| e.to_diagnostic()).collect();
+        let [cuont, circel] = &diagnostics[..] else {
+            panic!("expected two errors, found {:?}", diagnostics);
+        };
+        assert_eq!(cuont.message, "cannot find value `cuont` in this scope");
+        assert_eq!(cuont.notes[0].message, "a similar name exists: `count`");
+        assert_eq!(cuont.notes[0].span, Some(16..21));
+        assert_eq!(circel.message, "cannot find `Circel` in `Shape`");
+        assert_eq!(circel.notes[0].message, "a similar name exists: `Circle`");
+
+        // Names that aren't close aren't suggested
+        let res = resolve(&parse_source("fn main() { let a = 1; zebra }").unwrap());
+        assert!(res.errors[0].to_diagnostic().notes.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_duplicates() {
+        let source = "fn area() {}
+struct Point { x: int }
+fn area(a: int, a: int) { let (b, b) = (1, 2); let c = 1; let c = 2; }
+enum Shape { Circle, Circle }
+mod math { fn area() {} }
+struct Point {}";
+        let res = resolve(&parse_source(source).unwrap());
+
+        let errors: Vec<_> = res
+            .errors
+            .iter()
+            .map(|error| {
+                let ResolveError::Duplicate { span, original, .. } = error else {
+                    panic!("expected duplicate, found {:?}", error);
+                };
+                (span.start, original.start)
+            })
+            .collect();
+        // Shadowing `c` and reusing `area` in another module are allowed
+        let at = |name: &str, nth: usize| source.match_indices(name).nth(nth).unwrap().0;
+        assert_eq!(
+            errors,
+            vec![
+                (at("area", 1), at("area", 0)),
+                (at("Circle", 1), at("Circle", 0)),
+                (at("Point", 1), at("Point", 0)),
+                (at("a:", 1), at("a:", 0)),
+                (at("b", 1), at("b", 0)),
+            ]
+        );
+
+        let diagnostic = res.errors[0].to_diagnostic();
+        assert_eq!(
+            diagnostic.message,
+            "the name `area` is defined multiple times"
+        );
+        assert_eq!(diagnostic.notes[0].message, "`area` was first defined here");
+        assert_eq!(diagnostic.notes[0].span, Some(3..7));
+    }
+
+    #[test]
+    fn test_resolve_impl_methods() {
+        let source = "struct Point {}
+trait Show { fn show(self); }
+impl Point { fn show(self) {} fn new() {} }
+impl Show for Point { fn show(self) {} }
+impl Point { fn new() {} }";
+        let res = resolve(&parse_source(source).unwrap());
+
+        // Only the second inherent `new` clashes, as trait methods don't share the type's names
+        assert_eq!(res.errors.len(), 1, "{:?}", res.errors);
+        assert!(matches!(&res.errors[0], ResolveError::Duplicate { name, .. } if name == "new"));
+    }
+
+    #[test]
+    fn test_resolve_visibility() {
+        let source = "use shapes::Point;
+use shapes::hidden;
+fn main() {
+    let p = Point { x: 1, y: 2 };
+    let Point { x, y } = p;
+    shapes::area(p) + shapes::inner::deep() + shapes::inner::open();
+    shapes::Kind::Round;
+}
+pub fn api() {}
+fn unused() {}
+mod shapes {
+    pub struct Point { pub x: int, y: int }
+    pub enum Kind { Round }
+    fn hidden() {}
+    pub fn area(p: Point) int { inner::deep(); Point { x: 0, y: 0 }; hidden(); 0 }
+    pub mod inner {
+        fn deep() int { 0 }
+        pub fn open() int { 0 }
+    }
+}";
+        let res = resolve(&parse_source(source).unwrap());
+
+        let errors: Vec<_> = res
+            .errors
+            .iter()
+            .map(|e| (e.to_string(), e.span().start))
+            .collect();
+        let at = |name: &str, nth: usize| source.match_indices(name).nth(nth).unwrap().0;
+        assert_eq!(
+            errors,
+            vec![
+                ("function `hidden` is private".to_string(), at("hidden", 0)),
+                ("field `y` is private".to_string(), at("y: 2", 0)),
+                ("field `y` is private".to_string(), at("y }", 0)),
+                ("function `deep` is private".to_string(), at("deep", 0)),
+                ("function `deep` is private".to_string(), at("deep", 1)),
+            ]
+        );
+
+        let diagnostic = res.errors[1].to_diagnostic();
+        assert_eq!(
+            diagnostic.notes[0].message,
+            "`y` is declared here without `pub`"
+        );
+        assert_eq!(
+            diagnostic.notes[0].span.clone().unwrap().start,
+            at("y: int", 0)
+        );
+
+        // Public functions may be used from outside of the program
+        let lints: Vec<_> = res
+            .lints
+            .iter()
+            .filter(|lint| matches!(lint, Lint::UnusedFunction { .. }))
+            .map(|lint| lint.to_string())
+            .collect();
+        assert_eq!(lints, vec!["function `unused` is never used"]);
+    }
+
+    #[test]
+    fn test_resolve_self_and_super() {
+        let source = "fn helper() {}
+mod shapes {
+    use super::helper;
+    struct Circle { r: float }
+    impl Circle {
+        fn area(self) float { helper(); self.r * self::PI }
+        fn unit() Circle { let c = self::Circle { r: 1.0 }; c }
+    }
+    const PI: float = 3.14;
+    mod nested { fn f() { super::super::helper(); super::PI; super::super::super::x; } }
+}
+fn main() { self; self::helper(); super::helper(); }";
+        let res = resolve(&parse_source(source).unwrap());
+
+        let helper = def_of(&res, source, "helper", 0).unwrap();
+        assert_eq!(def_of(&res, source, "helper", 1), Some(helper));
+        assert_eq!(def_of(&res, source, "helper", 2), Some(helper));
+        assert_eq!(def_of(&res, source, "helper", 3), Some(helper));
+        assert_eq!(def_of(&res, source, "helper", 4), Some(helper));
+
+        // `self` in a method is its receiver
+        let receiver = def_of(&res, source, "self", 0).unwrap();
+        assert_eq!(receiver.kind, DefKind::Param);
+        assert_eq!(def_of(&res, source, "self", 1), Some(receiver));
+        // `self::` and `super::` lead to modules
+        let shapes = def_of(&res, source, "shapes", 0).unwrap();
+        assert_eq!(def_of(&res, source, "self", 2), Some(shapes));
+        assert_eq!(def_of(&res, source, "self", 3), Some(shapes));
+        assert_eq!(def_of(&res, source, "super", 1), Some(shapes));
+        assert_eq!(def_of(&res, source, "super", 2), Some(res.def(DefId::ROOT)));
+        assert_eq!(def_of(&res, source, "super", 3), Some(shapes));
+        let pi = def_of(&res, source, "PI", 1).unwrap();
+        assert_eq!(def_of(&res, source, "PI", 0), Some(pi));
+        assert_eq!(def_of(&res, source, "PI", 2), Some(pi));
+
+        let errors: Vec<_> = res
+            .errors
+            .iter()
+            .map(|e| (e.to_string(), e.span().start))
+            .collect();
+        let at = |name: &str, nth: usize| source.match_indices(name).nth(nth).unwrap().0;
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "there are too many leading `super` keywords".to_string(),
+                    at("super", 6)
+                ),
+                (
+                    "`self` is only available in methods with a `self` parameter".to_string(),
+                    at("self", 4)
+                ),
+                (
+                    "there are too many leading `super` keywords".to_string(),
+                    at("super", 7)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_shadowing() {
+        let source = "fn f(n: int) {
+    let n = n + 1;
+    let a = 1;
+    let a = a * 2;
+    if a > 0 { let (a, b) = (n, 2); }
+    let g = |x: int| { let n = x; };
+    let helper = 1;
+}
+fn helper() { let n = 1; }";
+        let program = parse_source(source).unwrap();
+
+        // Off by default
+        assert!(resolve(&program)
+            .lints
+            .iter()
+            .all(|lint| !matches!(lint, Lint::Shadowing { .. })));
+
+        let res = resolve_with(&program, LintOptions { shadowing: true });
+        let lints: Vec<_> = res
+            .lints
+            .iter()
+            .filter_map(|lint| match lint {
+                Lint::Shadowing { span, original, .. } => {
+                    Some((lint.to_string(), span.start, original.start))
+                }
+                _ => None,
+            })
+            .collect();
+        let at = |name: &str, nth: usize| source.match_indices(name).nth(nth).unwrap().0;
+        assert_eq!(
+            lints,
+            vec![
+                (
+                    "`n` shadows a variable from an enclosing scope".to_string(),
+                    at("n =", 0),
+                    at("n:", 0)
+                ),
+                (
+                    "`a` shadows a variable from an enclosing scope".to_string(),
+                    at("a, b", 0),
+                    at("a = a", 0)
+                ),
+                (
+                    "`n` shadows a variable from an enclosing scope".to_string(),
+                    at("n = x", 0),
+                    at("n =", 0)
+                ),
+            ]
+        );
+
+        let diagnostic = res
+            .lints
+            .iter()
+            .find(|lint| matches!(lint, Lint::Shadowing { .. }))
+            .unwrap()
+            .to_diagnostic();
+        assert_eq!(
+            diagnostic.notes[0].message,
+            "the shadowed `n` is declared here"
+        );
+        assert_eq!(diagnostic.notes[0].span, Some(5..6));
+    }
+
+    #[test]
+    fn test_resolve_mutability() {
+        let source = "fn f(n: int, mut m: int) {
+    let a = 1; let mut b = 2; let (c, mut d) = (3, 4);
+    b = 1; d += 1; m = 2;
+    a = 5; n += 1; c.x = 1; (a) = 2;
+    let p = (1, 2); p.x = 2; p.items[0] = 1;
+    let f = || { b = 3; a = 4; };
+}";
+        let res = resolve(&parse_source(source).unwrap());
+
+        let errors: Vec<_> = res
+            .errors
+            .iter()
+            .map(|e| (e.to_string(), &source[e.span().clone()]))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "cannot assign to immutable variable `a`".to_string(),
+                    "a = 5"
+                ),
+                (
+                    "cannot assign to immutable parameter `n`".to_string(),
+                    "n += 1"
+                ),
+                (
+                    "cannot mutate immutable variable `c`".to_string(),
+                    "c.x = 1"
+                ),
+                (
+                    "cannot assign to immutable variable `a`".to_string(),
+                    "(a) = 2"
+                ),
+                (
+                    "cannot mutate immutable variable `p`".to_string(),
+                    "p.x = 2"
+                ),
+                (
+                    "cannot mutate immutable variable `p`".to_string(),
+                    "p.items[0] = 1"
+                ),
+                (
+                    "cannot assign to immutable variable `a`".to_string(),
+                    "a = 4"
+                ),
+            ]
+        );
+
+        let diagnostic = res.errors[1].to_diagnostic();
+        assert_eq!(
+            diagnostic.notes[0].message,
+            "`n` is declared here, use `mut n` to make it mutable"
+        );
+        assert_eq!(diagnostic.notes[0].span, Some(5..6));
+
+        // `mut self` is needed to change the receiver's fields
+        let source =
+            "impl Counter { fn inc(mut self) { self.n += 1; } fn get(self) { self.n = 0; } }";
+        let res = resolve(&parse_source(source).unwrap());
+        let errors: Vec<_> = res.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(errors, vec!["cannot mutate immutable parameter `self`"]);
+    }
+
+    #[test]
+    fn test_resolve_unused() {
+        let source = "fn main() { let used = 1; let mut_only = 2; mut_only = used; let _ignored = 3; helper(|x, _y| 0); host(0); }
+fn helper(f: int) {}
+fn unused() {}
+fn _unused() {}
+impl Point { fn method(self) {} }
+extern fn host(code: int);
+trait Shape { fn scale(self, by: int); }";
+        let res = resolve(&parse_source(source).unwrap());
+
+        let lints: Vec<_> = res.lints.iter().map(|lint| lint.to_string()).collect();
+        assert_eq!(
+            lints,
+            vec![
+                "unused variable `mut_only`",
+                "unused variable `x`",
+                "unused variable `f`",
+                "function `unused` is never used",
+            ]
+        );
+
+        let diagnostic = res.lints[0].to_diagnostic();
+        assert_eq!(diagnostic.severity, crate::diagnostic::Severity::Warning);
+        assert_eq!(
+            diagnostic.notes[0].message,
+            "if this is intentional, prefix it with an underscore: `_mut_only`"
+        );
+    }
+
+    #[test]
+    fn test_resolve_unreachable() {
+        let source = "fn main() { let p = Point { x: 1 }; p.used(); helper(); }
+fn helper() {}
+fn countdown(n: int) int { if n == 0 { 0 } else { countdown(n - 1) } }
+fn ping() { pong(); }
+fn pong() { ping(); }
+fn _kept() { kept(); }
+fn kept() {}
+struct Point { x: int }
+impl Point {
+    fn used(self) { self.also(); }
+    fn also(self) {}
+    fn unused(self) { Point::helper(); }
+    fn helper() {}
+    pub fn api(self) {}
+}";
+        let res = resolve(&parse_source(source).unwrap());
+
+        // Calling itself, or a function only it calls, doesn't make a function used
+        let lints: Vec<_> = res.lints.iter().map(|lint| lint.to_string()).collect();
+        assert_eq!(
+            lints,
+            vec![
+                "function `countdown` is never used",
+                "function `ping` is never used",
+                "function `pong` is never used",
+                "method `unused` is never used",
+                "method `helper` is never used",
+            ]
+        );
+    }
+}
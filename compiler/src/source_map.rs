@@ -0,0 +1,119 @@
+//! Keeps the source code of every file in a program in one buffer, so that spans from different
+//! files never overlap and can be traced back to their file.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{lexer::Span, utils::rows_cols_index};
+
+/// Identifies a file in a [`SourceMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(pub usize);
+
+/// A file whose source code occupies `span` of the source map's buffer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub span: Span,
+}
+
+/// A position in a file, where rows and columns start at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location<'a> {
+    pub path: &'a Path,
+    pub row: usize,
+    pub col: usize,
+}
+
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.row, self.col)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    text: String,
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a file's source code to the buffer.
+    pub fn add_file(&mut self, path: impl Into<PathBuf>, source: &str) -> FileId {
+        let start = self.text.len();
+        self.text.push_str(source);
+        self.files.push(SourceFile {
+            path: path.into(),
+            span: start..self.text.len(),
+        });
+        FileId(self.files.len() - 1)
+    }
+
+    /// Returns the source code of every file.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn file(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0]
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Returns the source code of a file.
+    pub fn source(&self, id: FileId) -> &str {
+        &self.text[self.file(id).span.clone()]
+    }
+
+    /// Returns the file containing the byte at `offset`. An offset at the end of a file, where
+    /// errors about an unexpected end of file point, belongs to that file.
+    pub fn file_at(&self, offset: usize) -> Option<FileId> {
+        self.files
+            .iter()
+            .position(|file| file.span.contains(&offset))
+            .or_else(|| self.files.iter().rposition(|file| file.span.end == offset))
+            .map(FileId)
+    }
+
+    /// Returns the file, row and column of the byte at `offset`.
+    pub fn location(&self, offset: usize) -> Option<Location<'_>> {
+        let file = self.file(self.file_at(offset)?);
+        let (row, col) = rows_cols_index(&self.text[file.span.clone()], offset - file.span.start);
+        Some(Location {
+            path: &file.path,
+            row,
+            col,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_map_locations() {
+        let mut map = SourceMap::new();
+        let main = map.add_file("main.rf", "mod shapes;\nfn main() {}");
+        let shapes = map.add_file("shapes.rf", "struct Point {}\n\nfn area() {}");
+
+        assert_eq!(map.source(shapes), "struct Point {}\n\nfn area() {}");
+        assert_eq!(map.file_at(0), Some(main));
+        assert_eq!(map.file_at(24), Some(shapes));
+        // The end of the last file still belongs to it
+        assert_eq!(map.file_at(map.text().len()), Some(shapes));
+        assert_eq!(map.file_at(map.text().len() + 1), None);
+
+        let location = map.location(24 + 20).unwrap();
+        assert_eq!(location.path, Path::new("shapes.rf"));
+        assert_eq!((location.row, location.col), (3, 4));
+    }
+}
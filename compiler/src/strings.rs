@@ -0,0 +1,88 @@
+//! The operations on strings that programs call as methods or do by indexing, which the interpreter
+//! and the VM share so that they behave the same, and the same as the runtime libraries of
+//! compiled code.
+//!
+//! Strings are indexed, sliced and measured by their characters rather than their bytes, the same
+//! as iterating over one. Changing the case of a string only changes its ASCII letters.
+
+/// The message of an index or a slice that's out of bounds, the same as an array's.
+pub const OUT_OF_BOUNDS: &str = "index out of bounds";
+
+/// The message of splitting a string by an empty separator.
+pub const EMPTY_SEPARATOR: &str = "empty separator";
+
+/// The number of characters of a string.
+pub fn len(string: &str) -> i128 {
+    string.chars().count() as i128
+}
+
+/// The character at an index.
+pub fn char_at(string: &str, index: i128) -> Result<char, &'static str> {
+    (usize::try_from(index).ok())
+        .and_then(|index| string.chars().nth(index))
+        .ok_or(OUT_OF_BOUNDS)
+}
+
+/// The characters from `start` up to `end`, or through it when `inclusive` is set. A range without
+/// a start starts at the first character, and one without an end ends after the last.
+pub fn slice(
+    string: &str,
+    start: Option<i128>,
+    end: Option<i128>,
+    inclusive: bool,
+) -> Result<&str, &'static str> {
+    let start = start.unwrap_or(0);
+    let end = end.map_or(len(string), |end| end + inclusive as i128);
+    if start > end {
+        return Err(OUT_OF_BOUNDS);
+    }
+    // The byte offset of each character, and of the end of the string
+    let offset = |index: i128| {
+        let mut offsets = (string.char_indices().map(|(offset, _)| offset)).chain([string.len()]);
+        (usize::try_from(index).ok())
+            .and_then(|index| offsets.nth(index))
+            .ok_or(OUT_OF_BOUNDS)
+    };
+    Ok(&string[offset(start)?..offset(end)?])
+}
+
+/// The parts of a string between each occurrence of a separator, which can't be empty.
+pub fn split<'a>(string: &'a str, separator: &str) -> Result<Vec<&'a str>, &'static str> {
+    if separator.is_empty() {
+        return Err(EMPTY_SEPARATOR);
+    }
+    Ok(string.split(separator).collect())
+}
+
+pub fn to_upper(string: &str) -> String {
+    string.to_ascii_uppercase()
+}
+
+pub fn to_lower(string: &str) -> String {
+    string.to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strings() {
+        let string = "héllo, wörld";
+        assert_eq!(len(string), 12);
+        assert_eq!(char_at(string, 1), Ok('é'));
+        assert_eq!(char_at(string, 12), Err(OUT_OF_BOUNDS));
+        assert_eq!(char_at(string, -1), Err(OUT_OF_BOUNDS));
+        assert_eq!(slice(string, Some(7), Some(10), false), Ok("wör"));
+        assert_eq!(slice(string, Some(7), Some(10), true), Ok("wörl"));
+        assert_eq!(slice(string, None, Some(5), false), Ok("héllo"));
+        assert_eq!(slice(string, Some(12), None, false), Ok(""));
+        assert_eq!(slice(string, Some(3), Some(2), false), Err(OUT_OF_BOUNDS));
+        assert_eq!(slice(string, Some(0), Some(13), false), Err(OUT_OF_BOUNDS));
+        assert_eq!(split(string, ", "), Ok(vec!["héllo", "wörld"]));
+        assert_eq!(split(",a,", ","), Ok(vec!["", "a", ""]));
+        assert_eq!(split(string, ""), Err(EMPTY_SEPARATOR));
+        assert_eq!(to_upper(string), "HéLLO, WöRLD");
+        assert_eq!(to_lower("ÉCOLE"), "École");
+    }
+}
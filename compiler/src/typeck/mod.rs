@@ -0,0 +1,3265 @@
+//! Type checking, which gives a type to every expression of a resolved program and reports the
+//! values that don't have the type they're expected to have.
+
+mod ops;
+mod ty;
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+};
+
+pub use ops::eval_int;
+pub use ty::{InferTable, IntTy, Ty, TyVar};
+
+use crate::{
+    ast::*,
+    diagnostic::Diagnostic,
+    flow,
+    lexer::Span,
+    resolve::{DefId, DefKind, Resolution, ScopeId},
+    visit::{self, Visit},
+};
+
+/// Error type returned from type checking.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    /// A value whose type isn't the one expected where it's used. `origin` is what made the
+    /// expected type the expected one, when it's written somewhere.
+    Mismatch {
+        expected: Ty,
+        found: Ty,
+        span: Span,
+        origin: Option<Origin>,
+    },
+    /// A type name that isn't built in or defined in the program.
+    UnknownType {
+        name: String,
+        span: Span,
+    },
+    /// A call with the wrong number of arguments. `span` is the whole call.
+    ArgCount {
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// A built in generic type with the wrong number of type arguments.
+    TypeArgCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    NotCallable {
+        ty: Ty,
+        span: Span,
+    },
+    NotIndexable {
+        ty: Ty,
+        span: Span,
+    },
+    /// An assignment to a character of a string, which can't be changed in place.
+    StringAssign {
+        span: Span,
+    },
+    /// A call of a method that changes its receiver, like an array's `push`, on a variable or
+    /// parameter that isn't declared with `mut`.
+    ImmutableReceiver {
+        kind: DefKind,
+        name: String,
+        span: Span,
+        binding: Span,
+    },
+    NotIterable {
+        ty: Ty,
+        span: Span,
+    },
+    /// A map made with `Map::new()` whose keys have a type that can't be hashed.
+    MapKey {
+        ty: Ty,
+        span: Span,
+    },
+    /// `math::abs`, `math::min` or `math::max` used with something other than numbers.
+    NotNumber {
+        ty: Ty,
+        span: Span,
+    },
+    /// `print` or `println` used with something that doesn't convert to a string.
+    NotDisplayable {
+        ty: Ty,
+        span: Span,
+    },
+    /// A parameter or return type of an `extern` function that can't cross to the host.
+    ExternType {
+        ty: Ty,
+        span: Span,
+    },
+    /// A function with `yield` in its body whose return type isn't a `Gen`.
+    GeneratorReturn {
+        ty: Ty,
+        span: Span,
+    },
+    /// A `yield` outside of a generator, including one in a closure inside of a generator.
+    YieldOutside {
+        span: Span,
+    },
+    /// A name that refers to the wrong kind of thing, such as a module used as a value. `expected`
+    /// and `found` describe the kinds, like "a value" and "module".
+    WrongKind {
+        expected: &'static str,
+        found: &'static str,
+        name: String,
+        span: Span,
+    },
+    NoField {
+        ty: Ty,
+        name: String,
+        span: Span,
+    },
+    NoMethod {
+        ty: Ty,
+        name: String,
+        span: Span,
+    },
+    /// A field or method used outside of the module of its type without being `pub`.
+    /// `definition` is the span of its name where it's declared.
+    Private {
+        kind: &'static str,
+        name: String,
+        span: Span,
+        definition: Span,
+    },
+    /// A struct literal that leaves out some of the fields.
+    MissingFields {
+        ty: Ty,
+        names: Vec<String>,
+        span: Span,
+    },
+    /// A field given twice in a struct literal. `first` is the span of the first one.
+    DuplicateField {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    /// A type given for a type parameter that doesn't implement a trait in the parameter's bounds.
+    /// `missing` are the methods of the trait that the type doesn't have, and `bound` is the
+    /// span of the bound.
+    NotImplemented {
+        ty: Ty,
+        trait_name: String,
+        missing: Vec<String>,
+        span: Span,
+        bound: Span,
+    },
+    /// A tuple variant pattern with the wrong number of fields.
+    PatternFields {
+        variant: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// An operator applied to operands it doesn't support. `rhs` is `None` for prefix operators.
+    InvalidOperands {
+        op: String,
+        lhs: Ty,
+        rhs: Option<Ty>,
+        span: Span,
+    },
+    /// An integer literal that's too large or too small for its type.
+    LiteralOutOfRange {
+        ty: IntTy,
+        span: Span,
+    },
+    /// An `as` conversion between types that can't be converted.
+    InvalidCast {
+        from: Ty,
+        to: Ty,
+        span: Span,
+    },
+    /// A `?` applied to a value that isn't an `Option` or a `Result`.
+    TryOperand {
+        ty: Ty,
+        span: Span,
+    },
+    /// A `?` in a function that doesn't return the same kind of value as the operand, so the
+    /// `None` or `Err` can't be returned. `kind` is `Option` or `Result`.
+    TryReturn {
+        kind: &'static str,
+        ret: Ty,
+        span: Span,
+    },
+}
+
+/// Where the type that a value is expected to have comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    /// The type annotation of a variable or global.
+    Annotation(Span),
+    /// A parameter of the function being called.
+    Param(Span),
+    /// The return type in the signature of the function the value is returned from.
+    Return(Span),
+    /// An earlier branch of the same `if` or `match`, whose value has the expected type.
+    Branch(Span),
+}
+
+impl Origin {
+    pub fn span(&self) -> &Span {
+        match self {
+            Origin::Annotation(span)
+            | Origin::Param(span)
+            | Origin::Return(span)
+            | Origin::Branch(span) => span,
+        }
+    }
+
+    /// Returns the note that points at the origin of the type `expected`.
+    fn note(&self, expected: &Ty) -> String {
+        let cause = match self {
+            Origin::Annotation(_) => "this annotation",
+            Origin::Param(_) => "this parameter",
+            Origin::Return(_) => "the return type",
+            Origin::Branch(_) => "this branch",
+        };
+        format!("expected `{}` because of {}", expected, cause)
+    }
+}
+
+impl TypeError {
+    pub fn span(&self) -> &Span {
+        match self {
+            TypeError::Mismatch { span, .. }
+            | TypeError::UnknownType { span, .. }
+            | TypeError::ArgCount { span, .. }
+            | TypeError::TypeArgCount { span, .. }
+            | TypeError::NotCallable { span, .. }
+            | TypeError::NotIndexable { span, .. }
+            | TypeError::StringAssign { span }
+            | TypeError::ImmutableReceiver { span, .. }
+            | TypeError::NotIterable { span, .. }
+            | TypeError::MapKey { span, .. }
+            | TypeError::NotNumber { span, .. }
+            | TypeError::NotDisplayable { span, .. }
+            | TypeError::ExternType { span, .. }
+            | TypeError::GeneratorReturn { span, .. }
+            | TypeError::YieldOutside { span }
+            | TypeError::WrongKind { span, .. }
+            | TypeError::NoField { span, .. }
+            | TypeError::NoMethod { span, .. }
+            | TypeError::Private { span, .. }
+            | TypeError::MissingFields { span, .. }
+            | TypeError::DuplicateField { span, .. }
+            | TypeError::PatternFields { span, .. }
+            | TypeError::NotImplemented { span, .. }
+            | TypeError::InvalidOperands { span, .. }
+            | TypeError::LiteralOutOfRange { span, .. }
+            | TypeError::InvalidCast { span, .. }
+            | TypeError::TryOperand { span, .. }
+            | TypeError::TryReturn { span, .. } => span,
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.to_string(), self.span().clone());
+        match self {
+            TypeError::Private {
+                name, definition, ..
+            } => diagnostic.with_note(
+                format!("`{}` is declared here without `pub`", name),
+                Some(definition.clone()),
+            ),
+            TypeError::LiteralOutOfRange { ty, .. } => diagnostic.with_note(
+                format!(
+                    "`{}` holds values from {} to {}",
+                    ty.name(),
+                    ty.min(),
+                    ty.max()
+                ),
+                None,
+            ),
+            TypeError::DuplicateField { first, .. } => {
+                diagnostic.with_note("the field is first given here", Some(first.clone()))
+            }
+            TypeError::ExternType { .. } => diagnostic.with_note(
+                "`extern` functions take integers, floats, `bool`s and `char`s, and return those or nothing",
+                None,
+            ),
+            TypeError::GeneratorReturn { .. } => diagnostic.with_note(
+                "a function that yields values of type `T` is a generator, which returns `Gen<T>`",
+                None,
+            ),
+            TypeError::YieldOutside { .. } => diagnostic.with_note(
+                "only the body of a function that returns `Gen<T>` can yield",
+                None,
+            ),
+            TypeError::StringAssign { .. } => diagnostic.with_note(
+                "strings can't be changed in place, so make a new one with slices and `+`",
+                None,
+            ),
+            TypeError::ImmutableReceiver { name, binding, .. } => diagnostic.with_note(
+                format!(
+                    "`{}` is declared here, use `mut {}` to make it mutable",
+                    name, name
+                ),
+                Some(binding.clone()),
+            ),
+            TypeError::NotImplemented {
+                ty, missing, bound, ..
+            } => {
+                let diagnostic = if missing.is_empty() {
+                    diagnostic
+                } else {
+                    let names: Vec<String> =
+                        missing.iter().map(|name| format!("`{}`", name)).collect();
+                    diagnostic.with_note(
+                        format!(
+                            "`{}` is missing the method{} {}",
+                            ty,
+                            if names.len() == 1 { "" } else { "s" },
+                            names.join(", ")
+                        ),
+                        None,
+                    )
+                };
+                diagnostic.with_note("required by this bound", Some(bound.clone()))
+            }
+            // numbers are never converted implicitly, so point out the conversion that was meant
+            TypeError::Mismatch {
+                expected,
+                found,
+                origin,
+                ..
+            } => {
+                let diagnostic = match origin {
+                    Some(origin) => {
+                        diagnostic.with_note(origin.note(expected), Some(origin.span().clone()))
+                    }
+                    None => diagnostic,
+                };
+                if is_scalar(expected) && is_scalar(found) && ops::supports_cast(found, expected) {
+                    diagnostic.with_note(format!("convert the value with `as {}`", expected), None)
+                } else {
+                    diagnostic
+                }
+            }
+            _ => diagnostic,
+        }
+    }
+}
+
+impl Error for TypeError {}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypeError::Mismatch {
+                expected, found, ..
+            } => write!(
+                f,
+                "mismatched types: expected `{}`, found `{}`",
+                expected, found
+            ),
+            TypeError::UnknownType { name, .. } => {
+                write!(f, "cannot find type `{}` in this scope", name)
+            }
+            TypeError::ArgCount {
+                expected, found, ..
+            } => write!(
+                f,
+                "this function takes {} argument{} but {} {} supplied",
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found,
+                if *found == 1 { "was" } else { "were" }
+            ),
+            TypeError::TypeArgCount {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "`{}` takes {} type argument{} but {} {} supplied",
+                name,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                found,
+                if *found == 1 { "was" } else { "were" }
+            ),
+            TypeError::NotCallable { ty, .. } => write!(f, "`{}` is not a function", ty),
+            TypeError::NotIndexable { ty, .. } => {
+                write!(f, "cannot index into a value of type `{}`", ty)
+            }
+            TypeError::StringAssign { .. } => {
+                write!(f, "cannot assign to a character of a string")
+            }
+            TypeError::ImmutableReceiver { kind, name, .. } => {
+                let kind = if *kind == DefKind::Param {
+                    "parameter"
+                } else {
+                    "variable"
+                };
+                write!(f, "cannot mutate immutable {} `{}`", kind, name)
+            }
+            TypeError::NotIterable { ty, .. } => write!(f, "`{}` is not iterable", ty),
+            TypeError::MapKey { ty, .. } => write!(f, "`{}` cannot be used as a map key", ty),
+            TypeError::NotNumber { ty, .. } => write!(f, "`{}` is not a number", ty),
+            TypeError::NotDisplayable { ty, .. } => write!(f, "`{}` cannot be printed", ty),
+            TypeError::ExternType { ty, .. } => {
+                write!(f, "`{}` cannot cross to an `extern` function", ty)
+            }
+            TypeError::GeneratorReturn { ty, .. } => {
+                write!(f, "a function that yields returns a `Gen`, not `{}`", ty)
+            }
+            TypeError::YieldOutside { .. } => write!(f, "`yield` outside of a generator"),
+            TypeError::WrongKind {
+                expected,
+                found,
+                name,
+                ..
+            } => write!(f, "expected {}, found {} `{}`", expected, found, name),
+            TypeError::NoField { ty, name, .. } => {
+                write!(f, "no field `{}` on type `{}`", name, ty)
+            }
+            TypeError::NoMethod { ty, name, .. } => {
+                write!(f, "no method named `{}` on type `{}`", name, ty)
+            }
+            TypeError::Private { kind, name, .. } => write!(f, "{} `{}` is private", kind, name),
+            TypeError::MissingFields { ty, names, .. } => {
+                let names: Vec<String> = names.iter().map(|name| format!("`{}`", name)).collect();
+                write!(
+                    f,
+                    "missing field{} {} in `{}`",
+                    if names.len() == 1 { "" } else { "s" },
+                    names.join(", "),
+                    ty
+                )
+            }
+            TypeError::DuplicateField { name, .. } => {
+                write!(f, "field `{}` is given more than once", name)
+            }
+            TypeError::NotImplemented { ty, trait_name, .. } => {
+                write!(f, "`{}` doesn't implement `{}`", ty, trait_name)
+            }
+            TypeError::PatternFields {
+                variant,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "this pattern has {} field{}, but `{}` has {}",
+                found,
+                if *found == 1 { "" } else { "s" },
+                variant,
+                expected
+            ),
+            TypeError::InvalidOperands { op, lhs, rhs, .. } => match rhs {
+                Some(rhs) if rhs != lhs => {
+                    write!(f, "cannot apply `{}` to `{}` and `{}`", op, lhs, rhs)
+                }
+                _ => write!(f, "cannot apply `{}` to `{}`", op, lhs),
+            },
+            TypeError::LiteralOutOfRange { ty, .. } => {
+                write!(f, "literal out of range for `{}`", ty.name())
+            }
+            TypeError::InvalidCast { from, to, .. } => {
+                write!(f, "cannot cast `{}` as `{}`", from, to)
+            }
+            TypeError::TryOperand { ty, .. } => write!(
+                f,
+                "the `?` operator can only be applied to an `Option` or a `Result`, not `{}`",
+                ty
+            ),
+            TypeError::TryReturn { kind, ret, .. } => write!(
+                f,
+                "cannot use `?` on {} `{}` in a function that returns `{}`",
+                if *kind == "Option" { "an" } else { "a" },
+                kind,
+                ret
+            ),
+        }
+    }
+}
+
+/// The types found by checking a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeckResults {
+    /// The type of every expression, by the expression's span.
+    pub types: HashMap<Span, Ty>,
+    /// The types of the variables, parameters, constants and statics, and the signatures of the
+    /// functions.
+    pub defs: HashMap<DefId, Ty>,
+    /// The method each method call calls, by the span of the method's name in the call.
+    pub methods: HashMap<Span, DefId>,
+    /// The types of the fields of every struct and enum variant, in the order they're declared.
+    pub fields: HashMap<DefId, Vec<Ty>>,
+    /// The types in the methods that trait impls take from their traits, by the impl's method.
+    pub instances: HashMap<DefId, Instance>,
+    pub errors: Vec<TypeError>,
+}
+
+/// The types found in the body of a method that a trait impl takes from its trait, where `Self`
+/// is the impl's type. They're kept apart from those of the trait's method, which has the same
+/// body, and from those of the other impls that take it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Instance {
+    pub types: HashMap<Span, Ty>,
+    /// The types of the variables and parameters of the body.
+    pub defs: HashMap<DefId, Ty>,
+    pub methods: HashMap<Span, DefId>,
+}
+
+impl TypeckResults {
+    pub fn type_of(&self, expr: &Expr) -> Option<&Ty> {
+        self.types.get(&expr.span)
+    }
+}
+
+/// Checks the types of a program whose names have been resolved. Types that can't be inferred
+/// are left as [`Ty::Var`].
+pub fn check(program: &Program, res: &Resolution) -> TypeckResults {
+    let mut collector = Collector {
+        res,
+        fns: Vec::new(),
+        globals: Vec::new(),
+        structs: Vec::new(),
+        variants: Vec::new(),
+        traits: Vec::new(),
+        trait_impls: Vec::new(),
+        externs: Vec::new(),
+        owner: Owner::None,
+    };
+    collector.visit_program(program);
+
+    let mut checker = Checker {
+        res,
+        fns: collector.fns.iter().copied().collect(),
+        globals: collector.globals.iter().copied().collect(),
+        structs: collector.structs.into_iter().collect(),
+        variants: collector
+            .variants
+            .into_iter()
+            .map(|(def, parent, variant)| (def, (parent, variant)))
+            .collect(),
+        traits: collector.traits.into_iter().collect(),
+        fields: HashMap::new(),
+        trait_methods: HashMap::new(),
+        impls: HashSet::new(),
+        generics: HashMap::new(),
+        bounds: HashMap::new(),
+        obligations: Vec::new(),
+        map_keys: Vec::new(),
+        numbers: Vec::new(),
+        displayed: Vec::new(),
+        owners: HashMap::new(),
+        table: InferTable::default(),
+        defs: HashMap::from([
+            (
+                DefId::PANIC,
+                Ty::Fn {
+                    params: vec![Ty::String],
+                    ret: Box::new(Ty::Never),
+                },
+            ),
+            (
+                DefId::PRINT,
+                Ty::Fn {
+                    params: vec![Ty::String],
+                    ret: Box::new(Ty::unit()),
+                },
+            ),
+            (
+                DefId::PRINTLN,
+                Ty::Fn {
+                    params: vec![Ty::String],
+                    ret: Box::new(Ty::unit()),
+                },
+            ),
+            (
+                DefId::INPUT,
+                Ty::Fn {
+                    params: Vec::new(),
+                    ret: Box::new(Ty::String),
+                },
+            ),
+            (
+                DefId::MATH_SQRT,
+                Ty::Fn {
+                    params: vec![Ty::Float],
+                    ret: Box::new(Ty::Float),
+                },
+            ),
+            (
+                DefId::MATH_POW,
+                Ty::Fn {
+                    params: vec![Ty::Float, Ty::Float],
+                    ret: Box::new(Ty::Float),
+                },
+            ),
+            (
+                DefId::MATH_FLOOR,
+                Ty::Fn {
+                    params: vec![Ty::Float],
+                    ret: Box::new(Ty::Float),
+                },
+            ),
+            (
+                DefId::MATH_CEIL,
+                Ty::Fn {
+                    params: vec![Ty::Float],
+                    ret: Box::new(Ty::Float),
+                },
+            ),
+            (DefId::MATH_PI, Ty::Float),
+            (
+                DefId::FS_READ_TO_STRING,
+                Ty::Fn {
+                    params: vec![Ty::String],
+                    ret: Box::new(Ty::Result(Box::new(Ty::String), Box::new(Ty::String))),
+                },
+            ),
+            (
+                DefId::FS_WRITE,
+                Ty::Fn {
+                    params: vec![Ty::String, Ty::String],
+                    ret: Box::new(Ty::Result(Box::new(Ty::unit()), Box::new(Ty::String))),
+                },
+            ),
+            (
+                DefId::FS_EXISTS,
+                Ty::Fn {
+                    params: vec![Ty::String],
+                    ret: Box::new(Ty::Result(Box::new(Ty::Bool), Box::new(Ty::String))),
+                },
+            ),
+            (
+                DefId::STRING_LEN,
+                Ty::Fn {
+                    params: vec![Ty::String],
+                    ret: Box::new(Ty::Int(IntTy::DEFAULT)),
+                },
+            ),
+            (
+                DefId::STRING_CONTAINS,
+                Ty::Fn {
+                    params: vec![Ty::String, Ty::String],
+                    ret: Box::new(Ty::Bool),
+                },
+            ),
+            (
+                DefId::STRING_SPLIT,
+                Ty::Fn {
+                    params: vec![Ty::String, Ty::String],
+                    ret: Box::new(Ty::Array(Box::new(Ty::String))),
+                },
+            ),
+            (
+                DefId::STRING_TO_UPPER,
+                Ty::Fn {
+                    params: vec![Ty::String],
+                    ret: Box::new(Ty::String),
+                },
+            ),
+            (
+                DefId::STRING_TO_LOWER,
+                Ty::Fn {
+                    params: vec![Ty::String],
+                    ret: Box::new(Ty::String),
+                },
+            ),
+        ]),
+        types: HashMap::new(),
+        errors: Vec::new(),
+        returns: Vec::new(),
+        yields: None,
+        self_ty: None,
+        scope: ScopeId::ROOT,
+        methods: HashMap::new(),
+        int_literals: Vec::new(),
+    };
+    // Methods from trait impls aren't members of their type, so they're found by the type
+    let mut provided_methods = Vec::new();
+    for decl in collector.trait_impls {
+        let Some(ty) = checker.owner_ty(Owner::Impl(&decl.self_ty)) else {
+            continue;
+        };
+        let trait_def = decl
+            .trait_ref
+            .as_ref()
+            .and_then(|path| res.lookup(&path.segments.last().unwrap().span));
+        if let Some(trait_def) = trait_def {
+            checker.impls.insert((trait_def, ty.clone()));
+        }
+        let mut methods: Vec<DefId> = decl
+            .methods
+            .iter()
+            .filter_map(|method| match &method.kind {
+                ItemKind::Fn(func) => res.lookup(&func.name.span),
+                _ => None,
+            })
+            .collect();
+        // The methods the impl takes from its trait have the trait method's signature and body,
+        // with `Self` as the impl's type
+        for provided in &res.provided {
+            if provided.impl_ty != decl.self_ty.span {
+                continue;
+            }
+            let Some(sig) = checker.fns.get(&provided.method).copied() else {
+                continue;
+            };
+            let sig = Signature {
+                owner: Owner::Impl(&decl.self_ty),
+                ..sig
+            };
+            checker.fns.insert(provided.def, sig);
+            provided_methods.push((provided.def, sig));
+            methods.push(provided.def);
+        }
+        checker.trait_methods.entry(ty).or_default().extend(methods);
+    }
+    for (def, _) in &collector.globals {
+        checker.global_ty(*def);
+    }
+    for (def, sig) in &collector.fns {
+        checker.check_fn(*def, sig);
+    }
+    let instances: Vec<_> = provided_methods
+        .into_iter()
+        .map(|(def, sig)| (def, checker.check_instance(def, &sig)))
+        .collect();
+    for (def, decl) in &collector.externs {
+        checker.check_extern(*def, decl);
+    }
+    let adts: Vec<DefId> = checker
+        .structs
+        .keys()
+        .chain(checker.variants.keys())
+        .copied()
+        .collect();
+    let fields = adts
+        .into_iter()
+        .map(|def| {
+            let fields = checker.fields(def);
+            (def, fields.into_iter().map(|field| field.ty).collect())
+        })
+        .collect();
+    checker.table.default_ints();
+    checker.check_obligations();
+    checker.check_map_keys();
+    checker.check_numbers();
+    checker.check_displayed();
+    checker.check_int_literals();
+
+    let table = checker.table;
+    let mut errors = checker.errors;
+    errors.sort_by_key(|error| error.span().start);
+    TypeckResults {
+        types: checker
+            .types
+            .into_iter()
+            .map(|(span, ty)| (span, table.resolve(&ty)))
+            .collect(),
+        defs: checker
+            .defs
+            .into_iter()
+            .map(|(def, ty)| (def, table.resolve(&ty)))
+            .collect(),
+        methods: checker.methods,
+        fields,
+        instances: instances
+            .into_iter()
+            .map(|(def, instance)| {
+                let instance = Instance {
+                    types: (instance.types.into_iter())
+                        .map(|(span, ty)| (span, table.resolve(&ty)))
+                        .collect(),
+                    defs: (instance.defs.into_iter())
+                        .map(|(def, ty)| (def, table.resolve(&ty)))
+                        .collect(),
+                    methods: instance.methods,
+                };
+                (def, instance)
+            })
+            .collect(),
+        errors,
+    }
+}
+
+/// What a function is declared in, which decides what `Self` is in its signature and body.
+#[derive(Debug, Clone, Copy)]
+enum Owner<'a> {
+    None,
+    Impl(&'a TypeExpr),
+    /// A trait, where `Self` is whichever type implements it.
+    Trait,
+}
+
+/// The parts of a function or trait method that are checked.
+#[derive(Debug, Clone, Copy)]
+struct Signature<'a> {
+    generics: &'a [GenericParam],
+    params: &'a [Param],
+    ret: Option<&'a TypeExpr>,
+    body: Option<&'a Block>,
+    owner: Owner<'a>,
+}
+
+/// An integer literal, which is checked to fit in its type once the type is inferred. `value` is
+/// `None` when the literal doesn't even fit in a `u64`.
+struct IntLiteral {
+    value: Option<u64>,
+    negated: bool,
+    ty: Ty,
+    span: Span,
+}
+
+/// A field of a struct or variant, with its type. The fields of a tuple variant are named by their
+/// position.
+#[derive(Debug, Clone)]
+struct FieldTy {
+    name: String,
+    ty: Ty,
+    public: bool,
+    span: Span,
+}
+
+/// Finds the declarations of a program, including those inside blocks.
+struct Collector<'a> {
+    res: &'a Resolution,
+    fns: Vec<(DefId, Signature<'a>)>,
+    globals: Vec<(DefId, &'a GlobalDecl)>,
+    structs: Vec<(DefId, &'a StructDecl)>,
+    /// Each variant, with the enum it's part of.
+    variants: Vec<(DefId, DefId, &'a Variant)>,
+    traits: Vec<(DefId, &'a TraitDecl)>,
+    trait_impls: Vec<&'a ImplDecl>,
+    externs: Vec<(DefId, &'a ExternDecl)>,
+    owner: Owner<'a>,
+}
+
+/// A type given for a type parameter, which has to implement a trait in the parameter's bounds.
+#[derive(Debug, Clone)]
+struct Obligation {
+    ty: Ty,
+    trait_def: DefId,
+    /// The use of the generic function.
+    span: Span,
+    bound: Span,
+}
+
+impl<'a> Visit<'a> for Collector<'a> {
+    fn visit_item(&mut self, item: &'a Item) {
+        let outer = self.owner;
+        match &item.kind {
+            ItemKind::Fn(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    let sig = Signature {
+                        generics: &decl.generics,
+                        params: &decl.params,
+                        ret: decl.ret.as_ref(),
+                        body: Some(&decl.body),
+                        owner: self.owner,
+                    };
+                    self.fns.push((def, sig));
+                }
+                self.owner = Owner::None;
+            }
+            ItemKind::Extern(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    let sig = Signature {
+                        generics: &[],
+                        params: &decl.params,
+                        ret: decl.ret.as_ref(),
+                        body: None,
+                        owner: Owner::None,
+                    };
+                    self.fns.push((def, sig));
+                    self.externs.push((def, decl));
+                }
+                self.owner = Owner::None;
+            }
+            ItemKind::Const(decl) | ItemKind::Static(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    self.globals.push((def, decl));
+                }
+            }
+            ItemKind::Trait(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    self.traits.push((def, decl));
+                }
+                for method in &decl.methods {
+                    if let Some(def) = self.res.lookup(&method.name.span) {
+                        let sig = Signature {
+                            generics: &method.generics,
+                            params: &method.params,
+                            ret: method.ret.as_ref(),
+                            body: method.body.as_ref(),
+                            owner: Owner::Trait,
+                        };
+                        self.fns.push((def, sig));
+                    }
+                }
+                self.owner = Owner::Trait;
+            }
+            ItemKind::Struct(decl) => {
+                if let Some(def) = self.res.lookup(&decl.name.span) {
+                    self.structs.push((def, decl));
+                }
+            }
+            ItemKind::Enum(decl) => {
+                if let Some(parent) = self.res.lookup(&decl.name.span) {
+                    for variant in &decl.variants {
+                        if let Some(def) = self.res.lookup(&variant.name.span) {
+                            self.variants.push((def, parent, variant));
+                        }
+                    }
+                }
+            }
+            ItemKind::Impl(decl) => {
+                if decl.trait_ref.is_some() {
+                    self.trait_impls.push(decl);
+                }
+                self.owner = Owner::Impl(&decl.self_ty);
+            }
+            _ => self.owner = Owner::None,
+        }
+        visit::walk_item(self, item);
+        self.owner = outer;
+    }
+}
+
+struct Checker<'a> {
+    res: &'a Resolution,
+    fns: HashMap<DefId, Signature<'a>>,
+    globals: HashMap<DefId, &'a GlobalDecl>,
+    structs: HashMap<DefId, &'a StructDecl>,
+    variants: HashMap<DefId, (DefId, &'a Variant)>,
+    /// The fields of the structs and variants that have been used.
+    fields: HashMap<DefId, Vec<FieldTy>>,
+    traits: HashMap<DefId, &'a TraitDecl>,
+    /// The methods of each type from trait impls.
+    trait_methods: HashMap<Ty, Vec<DefId>>,
+    /// The traits each type implements.
+    impls: HashSet<(DefId, Ty)>,
+    /// The type parameters of the functions whose signatures have been checked.
+    generics: HashMap<DefId, Vec<DefId>>,
+    /// The traits in the bounds of each type parameter, with the spans of the bounds.
+    bounds: HashMap<DefId, Vec<(DefId, Span)>>,
+    /// The bounds to check once the types given for type parameters have been inferred.
+    obligations: Vec<Obligation>,
+    /// The key types of the maps made with `Map::new()`, with the spans of the calls, to check
+    /// once they've been inferred.
+    map_keys: Vec<(Ty, Span)>,
+    /// The types that `math::abs`, `math::min` and `math::max` are used with, with the spans of
+    /// the paths, to check that they're numbers once they've been inferred.
+    numbers: Vec<(Ty, Span)>,
+    /// The types that `print` and `println` are used with, with the spans of the paths, to check
+    /// that they convert to strings once they've been inferred.
+    displayed: Vec<(Ty, Span)>,
+    /// The types of the `impl` blocks, by the span of the type, so each is only checked once.
+    owners: HashMap<Span, Ty>,
+    table: InferTable,
+    /// The types of the variables as they're bound, and of the functions and globals once
+    /// they've been used.
+    defs: HashMap<DefId, Ty>,
+    types: HashMap<Span, Ty>,
+    errors: Vec<TypeError>,
+    /// The return types of the functions and closures around the current expression.
+    returns: Vec<(Ty, Option<Origin>)>,
+    /// The type of the values the current generator yields, or `None` outside of a generator's
+    /// body, such as in a closure inside of it.
+    yields: Option<Ty>,
+    /// What `Self` refers to in the current function.
+    self_ty: Option<Ty>,
+    /// The scope the current function or global is declared in, which decides the private fields
+    /// and methods it can use.
+    scope: ScopeId,
+    methods: HashMap<Span, DefId>,
+    int_literals: Vec<IntLiteral>,
+}
+
+impl<'a> Checker<'a> {
+    /// Reports an error unless `found` can be made the same type as `expected`.
+    fn expect(&mut self, expected: &Ty, found: &Ty, span: Span) {
+        self.expect_from(expected, found, span, None);
+    }
+
+    /// Like [`Checker::expect`], also pointing at where the expected type comes from.
+    fn expect_from(&mut self, expected: &Ty, found: &Ty, span: Span, origin: Option<Origin>) {
+        // `!` fits where any type is expected, but only `!` fits where `!` is expected, such as
+        // the value of a function that never returns
+        let escapes = self.is_never(expected)
+            && !matches!(
+                self.table.shallow_resolve(found),
+                Ty::Never | Ty::Var(_) | Ty::Error
+            );
+        if escapes || !self.table.unify(expected, found) {
+            self.errors.push(TypeError::Mismatch {
+                expected: self.table.resolve(expected),
+                found: self.table.resolve(found),
+                span,
+                origin,
+            });
+        }
+    }
+
+    /// Combines the types of two branches that produce the value of the same expression, such
+    /// as the arms of a `match`. `first_span` is the value of the first branch. A branch that
+    /// never produces a value takes the type of the other.
+    fn join(&mut self, first: Ty, first_span: Span, other: Ty, span: Span) -> Ty {
+        if self.table.shallow_resolve(&first) == Ty::Never {
+            return other;
+        }
+        self.expect_from(&first, &other, span, Some(Origin::Branch(first_span)));
+        first
+    }
+
+    fn is_never(&self, ty: &Ty) -> bool {
+        self.table.shallow_resolve(ty) == Ty::Never
+    }
+
+    /// Returns what `Self` refers to inside a function declared in `owner`.
+    fn owner_ty(&mut self, owner: Owner<'a>) -> Option<Ty> {
+        match owner {
+            Owner::None => None,
+            Owner::Trait => Some(Ty::Error),
+            Owner::Impl(ty) => {
+                if let Some(ty) = self.owners.get(&ty.span) {
+                    return Some(ty.clone());
+                }
+                let lowered = self.lower_ty(ty);
+                self.owners.insert(ty.span.clone(), lowered.clone());
+                Some(lowered)
+            }
+        }
+    }
+
+    /// Returns the type of a function, checking its signature the first time.
+    fn fn_ty(&mut self, def: DefId) -> Ty {
+        if let Some(ty) = self.defs.get(&def) {
+            return ty.clone();
+        }
+        let Some(sig) = self.fns.get(&def).copied() else {
+            return Ty::Error;
+        };
+
+        let self_ty = self.owner_ty(sig.owner);
+        let outer = std::mem::replace(&mut self.self_ty, self_ty);
+        let mut generics = Vec::new();
+        for generic in sig.generics {
+            let Some(param) = self.res.lookup(&generic.name.span) else {
+                continue;
+            };
+            let mut bounds = Vec::new();
+            for bound in &generic.bounds {
+                let Some(trait_def) = self.res.lookup(&bound.segments.last().unwrap().span) else {
+                    continue;
+                };
+                if self.res.def(trait_def).kind == DefKind::Trait {
+                    bounds.push((trait_def, bound.span.clone()));
+                } else {
+                    self.wrong_kind("a trait", trait_def, bound.span.clone());
+                }
+            }
+            self.bounds.insert(param, bounds);
+            generics.push(param);
+        }
+        self.generics.insert(def, generics);
+        let ty = Ty::Fn {
+            params: sig
+                .params
+                .iter()
+                .map(|param| self.lower_ty(&param.ty))
+                .collect(),
+            ret: Box::new(match sig.ret {
+                Some(ret) => self.lower_ty(ret),
+                None => Ty::unit(),
+            }),
+        };
+        self.self_ty = outer;
+        self.defs.insert(def, ty.clone());
+        ty
+    }
+
+    /// Gives each type parameter of a generic function a new type variable, so the types given for
+    /// them can be inferred from a use of the function with type `ty`.
+    fn instantiate(&mut self, def: DefId, ty: Ty, span: &Span) -> Ty {
+        let params = match self.generics.get(&def) {
+            Some(params) if !params.is_empty() => params.clone(),
+            _ => return ty,
+        };
+        let mut args = HashMap::new();
+        for param in params {
+            let arg = self.table.new_var();
+            for (trait_def, bound) in &self.bounds[&param] {
+                self.obligations.push(Obligation {
+                    ty: arg.clone(),
+                    trait_def: *trait_def,
+                    span: span.clone(),
+                    bound: bound.clone(),
+                });
+            }
+            args.insert(param, arg);
+        }
+        ty.substitute(&args)
+    }
+
+    /// Checks that the types given for type parameters implement the traits in their bounds.
+    fn check_obligations(&mut self) {
+        for obligation in std::mem::take(&mut self.obligations) {
+            let ty = self.table.resolve(&obligation.ty);
+            let implemented = match &ty {
+                // Types that couldn't be inferred are left alone
+                Ty::Var(_) | Ty::Error | Ty::Never => true,
+                Ty::Param { def, .. } => self.bounds[def]
+                    .iter()
+                    .any(|(trait_def, _)| *trait_def == obligation.trait_def),
+                _ => self.impls.contains(&(obligation.trait_def, ty.clone())),
+            };
+            if implemented {
+                continue;
+            }
+
+            let missing = match self.traits.get(&obligation.trait_def) {
+                Some(decl) => decl
+                    .required()
+                    .filter(|method| self.find_method(&ty, &method.name.name).is_none())
+                    .map(|method| method.name.name.clone())
+                    .collect(),
+                None => Vec::new(),
+            };
+            self.errors.push(TypeError::NotImplemented {
+                ty,
+                trait_name: self.res.def(obligation.trait_def).name.clone(),
+                missing,
+                span: obligation.span,
+                bound: obligation.bound,
+            });
+        }
+    }
+
+    /// Returns the type of a constant or static, checking its value the first time. Without a
+    /// type annotation, the type is inferred from the value.
+    fn global_ty(&mut self, def: DefId) -> Ty {
+        if let Some(ty) = self.defs.get(&def) {
+            return ty.clone();
+        }
+        let Some(decl) = self.globals.get(&def).copied() else {
+            return Ty::Error;
+        };
+
+        // A global is outside of every function, even when it's declared inside one
+        let scope = self.res.def(def).scope;
+        let outer = (
+            std::mem::take(&mut self.returns),
+            self.yields.take(),
+            self.self_ty.take(),
+            std::mem::replace(&mut self.scope, scope),
+        );
+        let ty = match &decl.ty {
+            Some(ty) => {
+                let origin = Origin::Annotation(ty.span.clone());
+                let ty = self.lower_ty(ty);
+                self.defs.insert(def, ty.clone());
+                self.check_expr_from(&decl.value, &ty, Some(origin));
+                ty
+            }
+            None => {
+                // A global whose value uses itself can't be inferred
+                self.defs.insert(def, Ty::Error);
+                let ty = self.infer_expr(&decl.value);
+                self.defs.insert(def, ty.clone());
+                ty
+            }
+        };
+        (self.returns, self.yields, self.self_ty, self.scope) = outer;
+        ty
+    }
+
+    fn check_fn(&mut self, def: DefId, sig: &Signature<'a>) {
+        let Ty::Fn { params, ret } = self.fn_ty(def) else {
+            return;
+        };
+        let Some(body) = sig.body else {
+            return;
+        };
+
+        self.self_ty = self.owner_ty(sig.owner);
+        self.scope = self.res.def(def).scope;
+        let origin = sig.ret.map(|ret| Origin::Return(ret.span.clone()));
+        self.returns = vec![(*ret.clone(), origin.clone())];
+        for (param, ty) in sig.params.iter().zip(&params) {
+            self.bind_pattern(&param.pattern, ty);
+        }
+        if flow::yields(body) {
+            // A generator's body runs as it's resumed, and `return` just finishes it
+            let elem = match self.table.resolve(&ret) {
+                Ty::Gen(elem) => *elem,
+                Ty::Error => Ty::Error,
+                ty => {
+                    let span = sig.ret.map_or(body.span.clone(), |ret| ret.span.clone());
+                    self.errors.push(TypeError::GeneratorReturn { ty, span });
+                    Ty::Error
+                }
+            };
+            self.returns = vec![(Ty::unit(), None)];
+            self.yields = Some(elem);
+            let found = self.check_block(body);
+            self.expect(&Ty::unit(), &found, body.span.clone());
+            self.returns.clear();
+            self.yields = None;
+            self.self_ty = None;
+            return;
+        }
+        let found = self.check_block(body);
+        // A body that can end without a value is reported by the control flow checks
+        if flow::block_missing_value(body, &self.types).is_none() {
+            let span = match &body.tail {
+                Some(tail) => tail.span.clone(),
+                None => body.span.clone(),
+            };
+            self.expect_from(&ret, &found, span, origin);
+        }
+        self.returns.clear();
+        self.self_ty = None;
+    }
+
+    /// Checks a method that a trait impl takes from its trait, whose body is the trait method's,
+    /// keeping the types found in it apart. Errors that don't depend on `Self` were already
+    /// reported for the trait's method.
+    fn check_instance(&mut self, def: DefId, sig: &Signature<'a>) -> Instance {
+        let types = std::mem::take(&mut self.types);
+        let methods = std::mem::take(&mut self.methods);
+        let res = self.res;
+        let is_var = |def: &DefId| matches!(res.def(*def).kind, DefKind::Local | DefKind::Param);
+        let vars: Vec<_> = self.defs.extract_if(|def, _| is_var(def)).collect();
+        let errors = self.errors.len();
+        self.check_fn(def, sig);
+
+        for error in self.errors.split_off(errors) {
+            if !self.errors.contains(&error) {
+                self.errors.push(error);
+            }
+        }
+        let instance = Instance {
+            types: std::mem::replace(&mut self.types, types),
+            defs: self.defs.extract_if(|def, _| is_var(def)).collect(),
+            methods: std::mem::replace(&mut self.methods, methods),
+        };
+        self.defs.extend(vars);
+        instance
+    }
+
+    /// Checks that the host can pass the parameters of an `extern` function and take what it
+    /// returns.
+    fn check_extern(&mut self, def: DefId, decl: &ExternDecl) {
+        let Ty::Fn { params, ret } = self.fn_ty(def) else {
+            return;
+        };
+        let crosses =
+            |ty: &Ty| matches!(ty, Ty::Int(_) | Ty::Float | Ty::Bool | Ty::Char | Ty::Error);
+        for (param, ty) in decl.params.iter().zip(params) {
+            if !crosses(&ty) {
+                let span = param.ty.span.clone();
+                self.errors.push(TypeError::ExternType { ty, span });
+            }
+        }
+        if let Some(span) = decl.ret.as_ref().map(|ret| ret.span.clone()) {
+            if !crosses(&ret) && !ret.is_unit() {
+                self.errors.push(TypeError::ExternType { ty: *ret, span });
+            }
+        }
+    }
+
+    /// Converts a type annotation to the type it names.
+    fn lower_ty(&mut self, ty: &TypeExpr) -> Ty {
+        match &ty.kind {
+            TypeExprKind::Path(path) => self.lower_path(path),
+            TypeExprKind::Generic { path, args } => {
+                let args: Vec<Ty> = args.iter().map(|arg| self.lower_ty(arg)).collect();
+                let def = self.res.lookup(&path.segments.last().unwrap().span);
+                let Some((def, expected)) = def.and_then(|def| Some((def, type_params(def)?)))
+                else {
+                    // Only the built in types take type arguments
+                    self.lower_path(path);
+                    return Ty::Error;
+                };
+                if args.len() != expected {
+                    self.errors.push(TypeError::TypeArgCount {
+                        name: self.res.def(def).name.clone(),
+                        expected,
+                        found: args.len(),
+                        span: ty.span.clone(),
+                    });
+                    return Ty::Error;
+                }
+                let mut args = args.into_iter().map(Box::new);
+                let first = args.next().unwrap();
+                match (def, args.next()) {
+                    (DefId::MAP, Some(value)) => Ty::Map(first, value),
+                    (DefId::GEN, None) => Ty::Gen(first),
+                    (_, Some(error)) => Ty::Result(first, error),
+                    (_, None) => Ty::Option(first),
+                }
+            }
+            TypeExprKind::Tuple(elems) => {
+                Ty::Tuple(elems.iter().map(|elem| self.lower_ty(elem)).collect())
+            }
+            TypeExprKind::Fn { params, ret } => Ty::Fn {
+                params: params.iter().map(|param| self.lower_ty(param)).collect(),
+                ret: Box::new(match ret {
+                    Some(ret) => self.lower_ty(ret),
+                    None => Ty::unit(),
+                }),
+            },
+            TypeExprKind::Array { elem, len } => {
+                if let Some(len) = len {
+                    let ty = self.table.new_int_var();
+                    self.check_expr(len, &ty);
+                }
+                Ty::Array(Box::new(self.lower_ty(elem)))
+            }
+            TypeExprKind::Never => Ty::Never,
+        }
+    }
+
+    fn lower_path(&mut self, path: &Path) -> Ty {
+        let segment = path.segments.last().unwrap();
+        let single = path.segments.len() == 1;
+        if single && segment.name == "Self" {
+            if let Some(ty) = &self.self_ty {
+                return ty.clone();
+            }
+        } else if let Some(def) = self.res.lookup(&segment.span) {
+            let definition = self.res.def(def);
+            if let Some(expected) = type_params(def) {
+                self.errors.push(TypeError::TypeArgCount {
+                    name: definition.name.clone(),
+                    expected,
+                    found: 0,
+                    span: path.span.clone(),
+                });
+                return Ty::Error;
+            }
+            return match definition.kind {
+                DefKind::Struct | DefKind::Enum => self.adt(def),
+                DefKind::TypeParam => Ty::Param {
+                    def,
+                    name: definition.name.clone(),
+                },
+                _ => {
+                    self.errors.push(TypeError::WrongKind {
+                        expected: "a type",
+                        found: self.describe(def),
+                        name: definition.name.clone(),
+                        span: path.span.clone(),
+                    });
+                    Ty::Error
+                }
+            };
+        } else if !single {
+            // Longer paths that don't lead to anything are reported by the resolver
+            return Ty::Error;
+        } else if let Some(ty) = Ty::builtin(&segment.name) {
+            return ty;
+        }
+        self.errors.push(TypeError::UnknownType {
+            name: segment.name.clone(),
+            span: segment.span.clone(),
+        });
+        Ty::Error
+    }
+
+    /// Binds the variables in a pattern that matches a value of type `ty`.
+    fn bind_pattern(&mut self, pattern: &Pattern, ty: &Ty) {
+        match &pattern.kind {
+            PatternKind::Wildcard => {}
+            PatternKind::Literal(literal) => {
+                let found = self.literal_ty(literal, &pattern.span);
+                self.expect(ty, &found, pattern.span.clone());
+            }
+            PatternKind::Ident(ident) | PatternKind::Mut(ident) => {
+                if let Some(def) = self.res.lookup(&ident.span) {
+                    self.defs.insert(def, ty.clone());
+                }
+            }
+            PatternKind::Tuple(elems) => {
+                let elem_tys: Vec<Ty> = elems.iter().map(|_| self.table.new_var()).collect();
+                let tuple = Ty::Tuple(elem_tys.clone());
+                if self.table.unify(ty, &tuple) {
+                    for (elem, elem_ty) in elems.iter().zip(&elem_tys) {
+                        self.bind_pattern(elem, elem_ty);
+                    }
+                } else {
+                    self.errors.push(TypeError::Mismatch {
+                        expected: self.table.resolve(ty),
+                        found: self.table.resolve(&tuple),
+                        span: pattern.span.clone(),
+                        origin: None,
+                    });
+                    for elem in elems {
+                        self.bind_pattern(elem, &Ty::Error);
+                    }
+                }
+            }
+            PatternKind::Path(path) => {
+                let Some(def) = self.res.lookup(&path.segments.last().unwrap().span) else {
+                    return;
+                };
+                let found = match self.res.def(def).kind {
+                    DefKind::Variant if is_builtin_variant(def) => {
+                        let (found, fields) = self.builtin_variant(def);
+                        if !fields.is_empty() {
+                            self.wrong_kind("a unit variant or constant", def, path.span.clone());
+                            return;
+                        }
+                        found
+                    }
+                    DefKind::Const | DefKind::Static => self.global_ty(def),
+                    DefKind::Variant if matches!(self.variants[&def].1.kind, VariantKind::Unit) => {
+                        self.adt(self.variants[&def].0)
+                    }
+                    _ => {
+                        self.wrong_kind("a unit variant or constant", def, path.span.clone());
+                        return;
+                    }
+                };
+                self.expect(ty, &found, pattern.span.clone());
+            }
+            PatternKind::TupleVariant { path, fields } => {
+                let decls = match self.variant_pattern(path, ty, &pattern.span, false) {
+                    Some(decls) if decls.len() != fields.len() => {
+                        self.errors.push(TypeError::PatternFields {
+                            variant: path.segments.last().unwrap().name.clone(),
+                            expected: decls.len(),
+                            found: fields.len(),
+                            span: pattern.span.clone(),
+                        });
+                        Vec::new()
+                    }
+                    decls => decls.unwrap_or_default(),
+                };
+                for (i, field) in fields.iter().enumerate() {
+                    let ty = decls.get(i).map_or(Ty::Error, |decl| decl.ty.clone());
+                    self.bind_pattern(field, &ty);
+                }
+            }
+            PatternKind::StructVariant { path, fields } => {
+                let decls = self.variant_pattern(path, ty, &pattern.span, true);
+                for field in fields {
+                    let ty = match &decls {
+                        Some(decls) => self.field_ty(decls, &field.name, ty),
+                        None => Ty::Error,
+                    };
+                    self.bind_pattern(&field.pattern, &ty);
+                }
+            }
+        }
+    }
+
+    /// Checks that a variant pattern matches a value of type `ty`, returning the fields of the
+    /// variant. `named` is whether the pattern has named fields.
+    fn variant_pattern(
+        &mut self,
+        path: &Path,
+        ty: &Ty,
+        span: &Span,
+        named: bool,
+    ) -> Option<Vec<FieldTy>> {
+        let def = self.res.lookup(&path.segments.last().unwrap().span)?;
+        if is_builtin_variant(def) {
+            let (found, fields) = self.builtin_variant(def);
+            if named || fields.is_empty() {
+                self.wrong_kind(variant_kind(named), def, path.span.clone());
+                return None;
+            }
+            self.expect(ty, &found, span.clone());
+            return Some(
+                fields
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, ty)| FieldTy {
+                        name: i.to_string(),
+                        ty,
+                        public: true,
+                        span: path.span.clone(),
+                    })
+                    .collect(),
+            );
+        }
+        let found = match self.res.def(def).kind {
+            DefKind::Struct if named => self.adt(def),
+            DefKind::Variant => match (&self.variants[&def].1.kind, named) {
+                (VariantKind::Tuple(_), false) | (VariantKind::Struct(_), true) => {
+                    self.adt(self.variants[&def].0)
+                }
+                _ => {
+                    self.wrong_kind(variant_kind(named), def, path.span.clone());
+                    return None;
+                }
+            },
+            _ => {
+                self.wrong_kind(variant_kind(named), def, path.span.clone());
+                return None;
+            }
+        };
+        self.expect(ty, &found, span.clone());
+        Some(self.fields(def))
+    }
+
+    /// Returns the type of the field `name` among the fields of a struct or variant of type
+    /// `ty`, reporting it when there's no such field.
+    fn field_ty(&mut self, fields: &[FieldTy], name: &Ident, ty: &Ty) -> Ty {
+        match fields.iter().find(|field| field.name == name.name) {
+            Some(field) => field.ty.clone(),
+            None => {
+                self.errors.push(TypeError::NoField {
+                    ty: self.table.resolve(ty),
+                    name: name.name.clone(),
+                    span: name.span.clone(),
+                });
+                Ty::Error
+            }
+        }
+    }
+
+    /// Returns the type of a built in variant's enum, with new variables for its type arguments,
+    /// along with the types of the variant's fields.
+    fn builtin_variant(&mut self, def: DefId) -> (Ty, Vec<Ty>) {
+        let value = self.table.new_var();
+        if def == DefId::SOME || def == DefId::NONE {
+            let fields = if def == DefId::SOME {
+                vec![value.clone()]
+            } else {
+                Vec::new()
+            };
+            return (Ty::Option(Box::new(value)), fields);
+        }
+        let error = self.table.new_var();
+        let field = if def == DefId::OK {
+            value.clone()
+        } else {
+            error.clone()
+        };
+        (Ty::Result(Box::new(value), Box::new(error)), vec![field])
+    }
+
+    fn adt(&self, def: DefId) -> Ty {
+        Ty::Adt {
+            def,
+            name: self.res.def(def).name.clone(),
+        }
+    }
+
+    /// Returns the fields of a struct or variant, checking their types the first time.
+    fn fields(&mut self, def: DefId) -> Vec<FieldTy> {
+        if let Some(fields) = self.fields.get(&def) {
+            return fields.clone();
+        }
+        let outer = self.self_ty.take();
+        let fields = if let Some(decl) = self.structs.get(&def).copied() {
+            decl.fields
+                .iter()
+                .map(|field| self.lower_field(field))
+                .collect()
+        } else if let Some((_, variant)) = self.variants.get(&def).copied() {
+            match &variant.kind {
+                VariantKind::Unit => Vec::new(),
+                VariantKind::Tuple(tys) => tys
+                    .iter()
+                    .enumerate()
+                    .map(|(i, ty)| FieldTy {
+                        name: i.to_string(),
+                        ty: self.lower_ty(ty),
+                        public: true,
+                        span: ty.span.clone(),
+                    })
+                    .collect(),
+                // The fields of a variant are as visible as its enum
+                VariantKind::Struct(fields) => fields
+                    .iter()
+                    .map(|field| FieldTy {
+                        public: true,
+                        ..self.lower_field(field)
+                    })
+                    .collect(),
+            }
+        } else {
+            Vec::new()
+        };
+        self.self_ty = outer;
+        self.fields.insert(def, fields.clone());
+        fields
+    }
+
+    fn lower_field(&mut self, field: &FieldDecl) -> FieldTy {
+        FieldTy {
+            name: field.name.name.clone(),
+            ty: self.lower_ty(&field.ty),
+            public: field.public,
+            span: field.name.span.clone(),
+        }
+    }
+
+    /// Finds the method `name` of a type, either declared on the type itself or in a trait impl.
+    fn find_method(&self, ty: &Ty, name: &str) -> Option<DefId> {
+        if *ty == Ty::String {
+            return self.res.scope(ScopeId::STRING).names.get(name).copied();
+        }
+        if let Ty::Array(_) = ty {
+            return self.res.scope(ScopeId::ARRAY).names.get(name).copied();
+        }
+        // `Map::new` is a member too, but not a method
+        if let Ty::Map(..) = ty {
+            let method = self.res.scope(ScopeId::MAP).names.get(name).copied()?;
+            return (self.res.def(method).kind == DefKind::Method).then_some(method);
+        }
+        if let Ty::Gen(_) = ty {
+            return self.res.scope(ScopeId::GEN).names.get(name).copied();
+        }
+        // The methods of a type parameter are those of the traits in its bounds
+        if let Ty::Param { def, .. } = ty {
+            return self.bounds.get(def)?.iter().find_map(|(trait_def, _)| {
+                let members = self.res.def(*trait_def).members?;
+                self.res.scope(members).names.get(name).copied()
+            });
+        }
+        if let Ty::Adt { def, .. } = ty {
+            let members = self.res.def(*def).members?;
+            if let Some(&method) = self.res.scope(members).names.get(name) {
+                if self.res.def(method).kind == DefKind::Method {
+                    return Some(method);
+                }
+            }
+        }
+        self.trait_methods
+            .get(ty)?
+            .iter()
+            .copied()
+            .find(|&method| self.res.def(method).name == name)
+    }
+
+    /// Describes the kind of a definition in messages, telling the kinds of variants apart.
+    fn describe(&self, def: DefId) -> &'static str {
+        match self.variants.get(&def) {
+            Some((_, variant)) => match variant.kind {
+                VariantKind::Unit => "unit variant",
+                VariantKind::Tuple(_) => "tuple variant",
+                VariantKind::Struct(_) => "struct variant",
+            },
+            None if def == DefId::NONE => "unit variant",
+            None if is_builtin_variant(def) => "tuple variant",
+            None if matches!(def, DefId::MAP | DefId::GEN) => "type",
+            None => self.res.def(def).kind.describe(),
+        }
+    }
+
+    fn wrong_kind(&mut self, expected: &'static str, def: DefId, span: Span) {
+        self.errors.push(TypeError::WrongKind {
+            expected,
+            found: self.describe(def),
+            name: self.res.def(def).name.clone(),
+            span,
+        });
+    }
+
+    /// Checks the arguments of a call to a function with the given parameters. `decls` are the
+    /// declarations of the parameters, when the function being called is known.
+    fn check_args(&mut self, params: &[Ty], decls: &[Span], args: &[Expr], span: &Span) {
+        if params.len() != args.len() {
+            self.errors.push(TypeError::ArgCount {
+                expected: params.len(),
+                found: args.len(),
+                span: span.clone(),
+            });
+        }
+        for (i, arg) in args.iter().enumerate() {
+            match params.get(i) {
+                Some(param) => {
+                    let origin = decls.get(i).cloned().map(Origin::Param);
+                    self.check_expr_from(arg, param, origin)
+                }
+                None => self.infer_expr(arg),
+            };
+        }
+    }
+
+    /// Returns the spans of the parameters of a function, which are empty for anything else.
+    fn param_spans(&self, def: DefId) -> Vec<Span> {
+        self.fns.get(&def).map_or(Vec::new(), |sig| {
+            sig.params.iter().map(|param| param.span.clone()).collect()
+        })
+    }
+
+    /// Checks a block, returning the type of its value.
+    fn check_block(&mut self, block: &Block) -> Ty {
+        let mut diverges = false;
+        for stmt in &block.stmts {
+            diverges |= self.check_stmt(stmt);
+        }
+        let ty = match &block.tail {
+            Some(tail) => self.infer_expr(tail),
+            None => Ty::unit(),
+        };
+        if diverges {
+            Ty::Never
+        } else {
+            ty
+        }
+    }
+
+    /// Checks a statement, returning whether it never lets control reach the next one.
+    fn check_stmt(&mut self, stmt: &Stmt) -> bool {
+        match &stmt.kind {
+            StmtKind::Let { pattern, ty, value } => {
+                let origin = ty.as_ref().map(|ty| Origin::Annotation(ty.span.clone()));
+                let ty = match ty {
+                    Some(ty) => self.lower_ty(ty),
+                    None => self.table.new_var(),
+                };
+                let diverges = match value {
+                    Some(value) => {
+                        let found = self.check_expr_from(value, &ty, origin);
+                        self.is_never(&found)
+                    }
+                    None => false,
+                };
+                self.bind_pattern(pattern, &ty);
+                diverges
+            }
+            StmtKind::Expr(expr) => {
+                let ty = self.infer_expr(expr);
+                self.is_never(&ty)
+            }
+            StmtKind::Return(value) => {
+                // A `return` outside of a function is reported by the control flow checks
+                let (ret, origin) = self.returns.last().cloned().unwrap_or((Ty::Error, None));
+                match value {
+                    Some(value) => {
+                        self.check_expr_from(value, &ret, origin);
+                    }
+                    None => self.expect_from(&ret, &Ty::unit(), stmt.span.clone(), origin),
+                }
+                true
+            }
+            StmtKind::Yield(value) => {
+                match self.yields.clone() {
+                    Some(elem) => {
+                        self.check_expr(value, &elem);
+                    }
+                    None => {
+                        self.infer_expr(value);
+                        let span = stmt.span.clone();
+                        self.errors.push(TypeError::YieldOutside { span });
+                    }
+                }
+                false
+            }
+            StmtKind::Break | StmtKind::Continue => true,
+            StmtKind::Item(_) | StmtKind::Error => false,
+        }
+    }
+
+    /// Infers the type of an expression and reports an error unless it's `expected`.
+    fn check_expr(&mut self, expr: &Expr, expected: &Ty) -> Ty {
+        self.check_expr_from(expr, expected, None)
+    }
+
+    /// Like [`Checker::check_expr`], also pointing at where the expected type comes from.
+    fn check_expr_from(&mut self, expr: &Expr, expected: &Ty, origin: Option<Origin>) -> Ty {
+        let found = self.infer_expr(expr);
+        self.expect_from(expected, &found, expr.span.clone(), origin);
+        found
+    }
+
+    /// Infers the type of an expression and records it.
+    fn infer_expr(&mut self, expr: &Expr) -> Ty {
+        let ty = self.infer_expr_kind(expr);
+        self.types.insert(expr.span.clone(), ty.clone());
+        ty
+    }
+
+    fn infer_expr_kind(&mut self, expr: &Expr) -> Ty {
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.literal_ty(literal, &expr.span),
+            ExprKind::Path(path) => self.path_ty(path),
+            ExprKind::Paren(inner) => self.infer_expr(inner),
+            ExprKind::Tuple(elems) => {
+                Ty::Tuple(elems.iter().map(|elem| self.infer_expr(elem)).collect())
+            }
+            // The first element decides the type of the rest
+            ExprKind::Array(elems) => {
+                let elem_ty = self.table.new_var();
+                for elem in elems {
+                    self.check_expr(elem, &elem_ty);
+                }
+                Ty::Array(Box::new(elem_ty))
+            }
+            ExprKind::Block(block) => self.check_block(block),
+            ExprKind::If {
+                cond,
+                then_branch,
+                else_branch,
+            } => {
+                self.check_expr(cond, &Ty::Bool);
+                let then_ty = self.check_block(then_branch);
+                match else_branch {
+                    Some(else_branch) => {
+                        let else_ty = self.infer_expr(else_branch);
+                        let then_span = match &then_branch.tail {
+                            Some(tail) => tail.span.clone(),
+                            None => then_branch.span.clone(),
+                        };
+                        self.join(then_ty, then_span, else_ty, else_branch.span.clone())
+                    }
+                    None => {
+                        // Without an `else`, there's no value when the condition is false
+                        self.expect_unit_block(then_branch, then_ty);
+                        Ty::unit()
+                    }
+                }
+            }
+            ExprKind::While { cond, body } => {
+                self.check_expr(cond, &Ty::Bool);
+                let ty = self.check_block(body);
+                self.expect_unit_block(body, ty);
+                if flow::diverges(expr, &self.types) {
+                    Ty::Never
+                } else {
+                    Ty::unit()
+                }
+            }
+            ExprKind::Loop(body) => {
+                let ty = self.check_block(body);
+                self.expect_unit_block(body, ty);
+                if flow::diverges(expr, &self.types) {
+                    Ty::Never
+                } else {
+                    Ty::unit()
+                }
+            }
+            ExprKind::For(for_loop) => {
+                let iter = self.infer_expr(&for_loop.iter);
+                let elem = match self.table.shallow_resolve(&iter) {
+                    Ty::Array(elem) | Ty::Range(elem) => *elem,
+                    Ty::String => Ty::Char,
+                    Ty::Map(key, value) => Ty::Tuple(vec![*key, *value]),
+                    Ty::Gen(elem) => *elem,
+                    Ty::Var(_) | Ty::Error | Ty::Never => Ty::Error,
+                    ty => {
+                        self.errors.push(TypeError::NotIterable {
+                            ty: self.table.resolve(&ty),
+                            span: for_loop.iter.span.clone(),
+                        });
+                        Ty::Error
+                    }
+                };
+                self.bind_pattern(&for_loop.pattern, &elem);
+                let ty = self.check_block(&for_loop.body);
+                self.expect_unit_block(&for_loop.body, ty);
+                Ty::unit()
+            }
+            ExprKind::Closure(closure) => {
+                let params = closure
+                    .params
+                    .iter()
+                    .map(|param| {
+                        let ty = match &param.ty {
+                            Some(ty) => self.lower_ty(ty),
+                            None => self.table.new_var(),
+                        };
+                        self.bind_pattern(&param.pattern, &ty);
+                        ty
+                    })
+                    .collect();
+                let ret = self.table.new_var();
+                self.returns.push((ret.clone(), None));
+                let yields = self.yields.take();
+                self.check_expr(&closure.body, &ret);
+                self.yields = yields;
+                self.returns.pop();
+                Ty::Fn {
+                    params,
+                    ret: Box::new(ret),
+                }
+            }
+            ExprKind::Match(match_expr) => {
+                let scrutinee = self.infer_expr(&match_expr.scrutinee);
+                let mut ty = Ty::Never;
+                let mut first_span = match_expr.scrutinee.span.clone();
+                for arm in &match_expr.arms {
+                    self.bind_pattern(&arm.pattern, &scrutinee);
+                    let arm_ty = self.infer_expr(&arm.body);
+                    if self.is_never(&ty) {
+                        first_span = arm.body.span.clone();
+                    }
+                    ty = self.join(ty, first_span.clone(), arm_ty, arm.body.span.clone());
+                }
+                ty
+            }
+            ExprKind::Range { start, end, .. } => {
+                let elem = self.table.new_var();
+                for bound in [start, end].into_iter().flatten() {
+                    self.check_expr(bound, &elem);
+                }
+                Ty::Range(Box::new(elem))
+            }
+            ExprKind::Call { callee, args } => {
+                let callee_ty = self.infer_expr(callee);
+                match self.table.shallow_resolve(&callee_ty) {
+                    Ty::Fn { params, ret } => {
+                        let decls = match &callee.kind {
+                            ExprKind::Path(path) => self
+                                .res
+                                .lookup(&path.segments.last().unwrap().span)
+                                .map(|def| self.param_spans(def))
+                                .unwrap_or_default(),
+                            _ => Vec::new(),
+                        };
+                        self.check_args(&params, &decls, args, &expr.span);
+                        *ret
+                    }
+                    // Calling a closure parameter tells what kind of function it is
+                    Ty::Var(_) => {
+                        let params = args.iter().map(|arg| self.infer_expr(arg)).collect();
+                        let ret = self.table.new_var();
+                        let fn_ty = Ty::Fn {
+                            params,
+                            ret: Box::new(ret.clone()),
+                        };
+                        self.expect(&callee_ty, &fn_ty, callee.span.clone());
+                        ret
+                    }
+                    ty => {
+                        if !matches!(ty, Ty::Error | Ty::Never) {
+                            self.errors.push(TypeError::NotCallable {
+                                ty: self.table.resolve(&ty),
+                                span: callee.span.clone(),
+                            });
+                        }
+                        for arg in args {
+                            self.infer_expr(arg);
+                        }
+                        Ty::Error
+                    }
+                }
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let receiver_ty = self.infer_expr(receiver);
+                // Methods are found by the receiver's type, so a literal can't wait to be inferred
+                if let Ty::IntVar(_) = self.table.shallow_resolve(&receiver_ty) {
+                    self.table.unify(&receiver_ty, &Ty::Int(IntTy::DEFAULT));
+                }
+                let receiver_ty = self.table.resolve(&receiver_ty);
+                let def = match receiver_ty {
+                    Ty::Var(_) | Ty::Error | Ty::Never => None,
+                    _ => {
+                        let def = self.find_method(&receiver_ty, &method.name);
+                        if def.is_none() {
+                            self.errors.push(TypeError::NoMethod {
+                                ty: receiver_ty.clone(),
+                                name: method.name.clone(),
+                                span: method.span.clone(),
+                            });
+                        }
+                        def
+                    }
+                };
+                let Some(def) = def else {
+                    for arg in args {
+                        self.infer_expr(arg);
+                    }
+                    return Ty::Error;
+                };
+
+                self.methods.insert(method.span.clone(), def);
+                if !self.res.is_visible(def, self.scope) {
+                    self.errors.push(TypeError::Private {
+                        kind: "method",
+                        name: method.name.clone(),
+                        span: method.span.clone(),
+                        definition: self.res.def(def).span.clone(),
+                    });
+                }
+                // The receiver is passed as the `self` parameter, which the built in methods of
+                // strings, arrays, maps and generators all take
+                let scope = self.res.def(def).scope;
+                let builtin = [ScopeId::STRING, ScopeId::ARRAY, ScopeId::MAP, ScopeId::GEN];
+                let takes_self = builtin.contains(&scope)
+                    || (self.fns.get(&def))
+                        .is_some_and(|sig| sig.params.first().is_some_and(|param| param.is_self()));
+                let fn_ty = match &receiver_ty {
+                    Ty::Array(elem) if scope == ScopeId::ARRAY => array_method_ty(def, elem),
+                    Ty::Map(key, value) if scope == ScopeId::MAP => map_method_ty(def, key, value),
+                    Ty::Gen(elem) if scope == ScopeId::GEN => gen_method_ty(elem),
+                    _ => self.fn_ty(def),
+                };
+                if matches!(
+                    def,
+                    DefId::ARRAY_PUSH | DefId::ARRAY_POP | DefId::MAP_INSERT | DefId::MAP_REMOVE
+                ) {
+                    self.check_mutable_receiver(receiver, &expr.span);
+                }
+                match self.instantiate(def, fn_ty, &method.span) {
+                    Ty::Fn { params, ret } if takes_self => {
+                        self.expect(&params[0], &receiver_ty, receiver.span.clone());
+                        let decls = self.param_spans(def);
+                        let decls = decls.get(1..).unwrap_or_default();
+                        self.check_args(&params[1..], decls, args, &expr.span);
+                        *ret
+                    }
+                    _ => {
+                        self.wrong_kind("a method", def, method.span.clone());
+                        for arg in args {
+                            self.infer_expr(arg);
+                        }
+                        Ty::Error
+                    }
+                }
+            }
+            ExprKind::Field { base, field } => {
+                let base_ty = self.infer_expr(base);
+                match self.table.resolve(&base_ty) {
+                    Ty::Adt { def, .. } if self.structs.contains_key(&def) => {
+                        let fields = self.fields(def);
+                        let module = self.res.module_of(self.res.def(def).scope);
+                        if let Some(decl) = fields.iter().find(|decl| decl.name == field.name) {
+                            if !decl.public && !self.res.is_accessible(module, self.scope) {
+                                self.errors.push(TypeError::Private {
+                                    kind: "field",
+                                    name: field.name.clone(),
+                                    span: field.span.clone(),
+                                    definition: decl.span.clone(),
+                                });
+                            }
+                        }
+                        self.field_ty(&fields, field, &base_ty)
+                    }
+                    Ty::Var(_) | Ty::Error | Ty::Never => Ty::Error,
+                    ty => {
+                        self.errors.push(TypeError::NoField {
+                            ty,
+                            name: field.name.clone(),
+                            span: field.span.clone(),
+                        });
+                        Ty::Error
+                    }
+                }
+            }
+            ExprKind::Index { base, index } => {
+                let base_ty = self.infer_expr(base);
+                match self.table.shallow_resolve(&base_ty) {
+                    Ty::Array(elem) => {
+                        let ty = self.table.new_int_var();
+                        self.check_expr(index, &ty);
+                        *elem
+                    }
+                    // A string is indexed by its characters, and sliced by a range of them
+                    Ty::String => {
+                        let index_ty = self.infer_expr(index);
+                        let int = self.table.new_int_var();
+                        match self.table.shallow_resolve(&index_ty) {
+                            Ty::Range(elem) => {
+                                self.expect(&int, &elem, index.span.clone());
+                                Ty::String
+                            }
+                            _ => {
+                                self.expect(&int, &index_ty, index.span.clone());
+                                Ty::Char
+                            }
+                        }
+                    }
+                    ty => {
+                        if !matches!(ty, Ty::Var(_) | Ty::Error | Ty::Never) {
+                            self.errors.push(TypeError::NotIndexable {
+                                ty: self.table.resolve(&ty),
+                                span: base.span.clone(),
+                            });
+                        }
+                        self.infer_expr(index);
+                        Ty::Error
+                    }
+                }
+            }
+            ExprKind::Try(inner) => self.check_try(inner, &expr.span),
+            ExprKind::StructLit { path, fields } => self.check_struct_lit(path, fields),
+            ExprKind::Cast { expr: operand, ty } => {
+                let from = self.infer_expr(operand);
+                let from = self.table.resolve(&from);
+                let to = self.lower_ty(ty);
+                if !ops::supports_cast(&from, &to) {
+                    self.errors.push(TypeError::InvalidCast {
+                        from,
+                        to: to.clone(),
+                        span: expr.span.clone(),
+                    });
+                }
+                to
+            }
+            ExprKind::Unary { op, expr: operand } => {
+                let ty = self.infer_expr(operand);
+                // `-128` fits in an `i8`, even though `128` doesn't
+                if let (UnaryOp::Neg, ExprKind::Literal(Literal::Integer(_))) = (op, &operand.kind)
+                {
+                    self.int_literals.last_mut().unwrap().negated = true;
+                }
+                let ty = self.table.resolve(&ty);
+                if ops::supports_unary(*op, &ty) {
+                    ty
+                } else {
+                    self.errors.push(TypeError::InvalidOperands {
+                        op: op.as_str().to_string(),
+                        lhs: ty,
+                        rhs: None,
+                        span: expr.span.clone(),
+                    });
+                    Ty::Error
+                }
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                self.check_binary(*op, false, lhs, rhs, &expr.span)
+            }
+            ExprKind::Assign { target, value } => {
+                let ty = self.infer_expr(target);
+                self.check_expr(value, &ty);
+                self.check_place(target);
+                Ty::unit()
+            }
+            ExprKind::CompoundAssign { op, target, value } => {
+                self.check_binary(*op, true, target, value, &expr.span);
+                self.check_place(target);
+                Ty::unit()
+            }
+        }
+    }
+
+    /// Reports an assignment to a place inside a character of a string, whose types are already
+    /// inferred.
+    fn check_place(&mut self, target: &Expr) {
+        let mut place = target;
+        loop {
+            match &place.kind {
+                ExprKind::Paren(inner) | ExprKind::Field { base: inner, .. } => place = inner,
+                ExprKind::Index { base, .. } => {
+                    let ty = self.types.get(&base.span).cloned().unwrap_or(Ty::Error);
+                    if self.table.resolve(&ty) == Ty::String {
+                        self.errors.push(TypeError::StringAssign {
+                            span: target.span.clone(),
+                        });
+                        return;
+                    }
+                    place = base;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Reports a call of a method that changes its receiver when the variable the receiver is part
+    /// of isn't mutable, the same as an assignment to it.
+    fn check_mutable_receiver(&mut self, receiver: &Expr, span: &Span) {
+        let mut place = receiver;
+        while let ExprKind::Paren(base)
+        | ExprKind::Field { base, .. }
+        | ExprKind::Index { base, .. } = &place.kind
+        {
+            place = base;
+        }
+        let ExprKind::Path(path) = &place.kind else {
+            return;
+        };
+        let Some(def) = self.res.lookup(&path.segments.last().unwrap().span) else {
+            return;
+        };
+        let def = self.res.def(def);
+        if matches!(def.kind, DefKind::Local | DefKind::Param) && !def.mutable {
+            self.errors.push(TypeError::ImmutableReceiver {
+                kind: def.kind,
+                name: def.name.clone(),
+                span: span.clone(),
+                binding: def.span.clone(),
+            });
+        }
+    }
+
+    /// Checks the operands of a binary operator, or of a compound assignment when `assign` is set,
+    /// returning the type of the result.
+    fn check_binary(
+        &mut self,
+        op: BinaryOp,
+        assign: bool,
+        lhs: &Expr,
+        rhs: &Expr,
+        span: &Span,
+    ) -> Ty {
+        let lhs_ty = self.infer_expr(lhs);
+        let rhs_ty = self.infer_expr(rhs);
+        if let Some(ty) = ops::operand_ty(op) {
+            for operand in [&lhs_ty, &rhs_ty] {
+                if let Ty::Var(_) = self.table.shallow_resolve(operand) {
+                    self.table.unify(operand, &ty);
+                }
+            }
+        }
+
+        // The amount of a shift is any integer, whatever is being shifted
+        let matched = match op {
+            BinaryOp::Shl | BinaryOp::Shr => {
+                let amount = self.table.new_int_var();
+                self.table.unify(&amount, &rhs_ty)
+            }
+            _ => self.table.unify(&lhs_ty, &rhs_ty),
+        };
+        let ty = self.table.resolve(&lhs_ty);
+        if !matched || !ops::supports_binary(op, &ty) {
+            self.errors.push(TypeError::InvalidOperands {
+                op: format!("{}{}", op.as_str(), if assign { "=" } else { "" }),
+                lhs: ty,
+                rhs: Some(self.table.resolve(&rhs_ty)),
+                span: span.clone(),
+            });
+            return ops::binary_result(op, Ty::Error);
+        }
+        ops::binary_result(op, ty)
+    }
+
+    /// Checks `inner?`, which evaluates to the value inside a `Some` or `Ok`, and otherwise returns
+    /// the `None` or `Err` from the function. An operand whose type isn't known yet is taken to
+    /// be the kind of value the function returns.
+    fn check_try(&mut self, inner: &Expr, span: &Span) -> Ty {
+        let ty = self.infer_expr(inner);
+        let ty = self.table.shallow_resolve(&ty);
+        let (ret, origin) = self.returns.last().cloned().unwrap_or((Ty::Error, None));
+        let ret = self.table.shallow_resolve(&ret);
+        let kind = match (&ty, &ret) {
+            (Ty::Option(_), _) | (Ty::Var(_), Ty::Option(_)) => "Option",
+            (Ty::Result(..), _) | (Ty::Var(_), Ty::Result(..)) => "Result",
+            (Ty::Var(_) | Ty::Error | Ty::Never, _) => return Ty::Error,
+            _ => {
+                self.errors.push(TypeError::TryOperand {
+                    ty: self.table.resolve(&ty),
+                    span: inner.span.clone(),
+                });
+                return Ty::Error;
+            }
+        };
+
+        // The operand is split into the value that `?` produces and the `None` or `Err` that it
+        // returns, which keeps the error but can have any value type
+        let value = self.table.new_var();
+        let (operand, residual) = if kind == "Option" {
+            (
+                Ty::Option(Box::new(value.clone())),
+                Ty::Option(Box::new(self.table.new_var())),
+            )
+        } else {
+            let error = Box::new(self.table.new_var());
+            (
+                Ty::Result(Box::new(value.clone()), error.clone()),
+                Ty::Result(Box::new(self.table.new_var()), error),
+            )
+        };
+        self.table.unify(&ty, &operand);
+        let found = self.table.resolve(&residual);
+        if !self.table.unify(&ret, &residual) {
+            let same_kind = matches!(
+                (kind, &ret),
+                ("Option", Ty::Option(_)) | ("Result", Ty::Result(..))
+            );
+            self.errors.push(if same_kind {
+                TypeError::Mismatch {
+                    expected: self.table.resolve(&ret),
+                    found,
+                    span: span.clone(),
+                    origin,
+                }
+            } else {
+                TypeError::TryReturn {
+                    kind,
+                    ret: self.table.resolve(&ret),
+                    span: span.clone(),
+                }
+            });
+        }
+        value
+    }
+
+    /// Returns the type of a literal. An integer literal can have any integer type, and is
+    /// checked to fit in it once it's inferred.
+    fn literal_ty(&mut self, literal: &Literal, span: &Span) -> Ty {
+        let value = match literal {
+            Literal::Integer(value) => Some(*value),
+            Literal::OversizedInteger(_) => None,
+            Literal::Float(_) => return Ty::Float,
+            Literal::String(_) => return Ty::String,
+            Literal::Char(_) => return Ty::Char,
+            Literal::Bool(_) => return Ty::Bool,
+        };
+        let ty = self.table.new_int_var();
+        self.int_literals.push(IntLiteral {
+            value,
+            negated: false,
+            ty: ty.clone(),
+            span: span.clone(),
+        });
+        ty
+    }
+
+    /// Reports the uses of `math::abs`, `math::min` and `math::max` whose type was inferred as one
+    /// that isn't a number.
+    fn check_numbers(&mut self) {
+        for (ty, span) in std::mem::take(&mut self.numbers) {
+            let ty = self.table.resolve(&ty);
+            if !ops::is_number(&ty) {
+                self.errors.push(TypeError::NotNumber { ty, span });
+            }
+        }
+    }
+
+    /// Reports the uses of `print` and `println` whose type was inferred as one that doesn't
+    /// convert to a string.
+    fn check_displayed(&mut self) {
+        for (ty, span) in std::mem::take(&mut self.displayed) {
+            let ty = self.table.resolve(&ty);
+            if !ops::is_displayable(&ty) {
+                self.errors.push(TypeError::NotDisplayable { ty, span });
+            }
+        }
+    }
+
+    /// Reports the maps whose key type was inferred as one that can't be hashed.
+    fn check_map_keys(&mut self) {
+        for (ty, span) in std::mem::take(&mut self.map_keys) {
+            let ty = self.table.resolve(&ty);
+            if !ops::supports_hashing(&ty) {
+                self.errors.push(TypeError::MapKey { ty, span });
+            }
+        }
+    }
+
+    /// Reports the integer literals that don't fit in the type inferred for them.
+    fn check_int_literals(&mut self) {
+        for literal in std::mem::take(&mut self.int_literals) {
+            let Ty::Int(ty) = self.table.resolve(&literal.ty) else {
+                continue;
+            };
+            let fits = literal.value.is_some_and(|value| {
+                let value = value as i128;
+                ty.contains(if literal.negated { -value } else { value })
+            });
+            if !fits {
+                self.errors.push(TypeError::LiteralOutOfRange {
+                    ty,
+                    span: literal.span,
+                });
+            }
+        }
+    }
+
+    /// Checks a struct literal, which has to give every field of its struct or variant once.
+    fn check_struct_lit(&mut self, path: &Path, fields: &[FieldInit]) -> Ty {
+        let def = self.res.lookup(&path.segments.last().unwrap().span);
+        let ty = match def.map(|def| (def, self.res.def(def).kind)) {
+            Some((def, DefKind::Struct)) if !matches!(def, DefId::MAP | DefId::GEN) => {
+                Some(self.adt(def))
+            }
+            Some((def, DefKind::Variant))
+                if self
+                    .variants
+                    .get(&def)
+                    .is_some_and(|(_, variant)| matches!(variant.kind, VariantKind::Struct(_))) =>
+            {
+                Some(self.adt(self.variants[&def].0))
+            }
+            Some((def, _)) => {
+                self.wrong_kind("a struct", def, path.span.clone());
+                None
+            }
+            None => None,
+        };
+        let (Some(def), Some(ty)) = (def, ty) else {
+            for field in fields {
+                self.infer_expr(&field.value);
+            }
+            return Ty::Error;
+        };
+
+        let decls = self.fields(def);
+        let mut given: HashMap<&str, Span> = HashMap::new();
+        for field in fields {
+            if let Some(first) = given.get(field.name.name.as_str()) {
+                self.errors.push(TypeError::DuplicateField {
+                    name: field.name.name.clone(),
+                    span: field.name.span.clone(),
+                    first: first.clone(),
+                });
+            }
+            given.insert(&field.name.name, field.name.span.clone());
+            let field_ty = self.field_ty(&decls, &field.name, &ty);
+            self.check_expr(&field.value, &field_ty);
+        }
+        let missing: Vec<String> = decls
+            .iter()
+            .filter(|decl| !given.contains_key(decl.name.as_str()))
+            .map(|decl| decl.name.clone())
+            .collect();
+        if !missing.is_empty() {
+            self.errors.push(TypeError::MissingFields {
+                ty: ty.clone(),
+                names: missing,
+                span: path.span.clone(),
+            });
+        }
+        ty
+    }
+
+    /// Reports an error unless a block whose value is thrown away produces `()`.
+    fn expect_unit_block(&mut self, block: &Block, ty: Ty) {
+        let span = match &block.tail {
+            Some(tail) => tail.span.clone(),
+            None => block.span.clone(),
+        };
+        self.expect(&Ty::unit(), &ty, span);
+    }
+
+    /// Returns the type of the value a path refers to.
+    fn path_ty(&mut self, path: &Path) -> Ty {
+        let segment = path.segments.last().unwrap();
+        // Names that aren't defined are reported by the resolver
+        let Some(def) = self.res.lookup(&segment.span) else {
+            return Ty::Error;
+        };
+        let definition = self.res.def(def);
+        match definition.kind {
+            DefKind::Param | DefKind::Local => self.defs.get(&def).cloned().unwrap_or(Ty::Error),
+            // Each map can have its own key and value types
+            DefKind::Fn if def == DefId::MAP_NEW => {
+                let key = self.table.new_var();
+                self.map_keys.push((key.clone(), path.span.clone()));
+                map_method_ty(def, &key, &self.table.new_var())
+            }
+            // These work on any number, so each use has its own type
+            DefKind::Fn if matches!(def, DefId::MATH_ABS | DefId::MATH_MIN | DefId::MATH_MAX) => {
+                let ty = self.table.new_var();
+                self.numbers.push((ty.clone(), path.span.clone()));
+                let params = if def == DefId::MATH_ABS { 1 } else { 2 };
+                Ty::Fn {
+                    params: vec![ty.clone(); params],
+                    ret: Box::new(ty),
+                }
+            }
+            // These print anything that converts to a string
+            DefKind::Fn if matches!(def, DefId::PRINT | DefId::PRINTLN) => {
+                let ty = self.table.new_var();
+                self.displayed.push((ty.clone(), path.span.clone()));
+                Ty::Fn {
+                    params: vec![ty],
+                    ret: Box::new(Ty::unit()),
+                }
+            }
+            DefKind::Fn | DefKind::Method => {
+                let ty = self.fn_ty(def);
+                self.instantiate(def, ty, &path.span)
+            }
+            DefKind::Const | DefKind::Static => self.global_ty(def),
+            DefKind::Variant if is_builtin_variant(def) => {
+                let (ty, fields) = self.builtin_variant(def);
+                if fields.is_empty() {
+                    ty
+                } else {
+                    Ty::Fn {
+                        params: fields,
+                        ret: Box::new(ty),
+                    }
+                }
+            }
+            DefKind::Variant => {
+                let (parent, variant) = self.variants[&def];
+                match variant.kind {
+                    VariantKind::Unit => self.adt(parent),
+                    // A tuple variant is constructed by calling it
+                    VariantKind::Tuple(_) => Ty::Fn {
+                        params: self.fields(def).into_iter().map(|field| field.ty).collect(),
+                        ret: Box::new(self.adt(parent)),
+                    },
+                    VariantKind::Struct(_) => {
+                        self.wrong_kind("a value", def, path.span.clone());
+                        Ty::Error
+                    }
+                }
+            }
+            DefKind::Mod
+            | DefKind::Struct
+            | DefKind::Enum
+            | DefKind::Trait
+            | DefKind::TypeParam => {
+                self.wrong_kind("a value", def, path.span.clone());
+                Ty::Error
+            }
+        }
+    }
+}
+
+/// Returns the type of a built in method of arrays of `elem`, as it's called. `push` and `pop`
+/// also change the array they're called on.
+pub fn array_method_ty(def: DefId, elem: &Ty) -> Ty {
+    let array = Ty::Array(Box::new(elem.clone()));
+    let (params, ret) = match def {
+        DefId::ARRAY_PUSH => (vec![array, elem.clone()], Ty::unit()),
+        DefId::ARRAY_POP => (vec![array], Ty::Option(Box::new(elem.clone()))),
+        _ => (vec![array], Ty::Int(IntTy::DEFAULT)),
+    };
+    Ty::Fn {
+        params,
+        ret: Box::new(ret),
+    }
+}
+
+/// Returns the type of `Map::new` or a built in method of maps from `key` to `value`, as it's
+/// called. `insert` and `remove` also change the map they're called on.
+pub fn map_method_ty(def: DefId, key: &Ty, value: &Ty) -> Ty {
+    let map = Ty::Map(Box::new(key.clone()), Box::new(value.clone()));
+    let option = Ty::Option(Box::new(value.clone()));
+    let (params, ret) = match def {
+        DefId::MAP_NEW => (Vec::new(), map),
+        DefId::MAP_INSERT => (vec![map, key.clone(), value.clone()], Ty::unit()),
+        DefId::MAP_GET | DefId::MAP_REMOVE => (vec![map, key.clone()], option),
+        DefId::MAP_CONTAINS => (vec![map, key.clone()], Ty::Bool),
+        _ => (vec![map], Ty::Int(IntTy::DEFAULT)),
+    };
+    Ty::Fn {
+        params,
+        ret: Box::new(ret),
+    }
+}
+
+/// Returns the type of `next`, the built in method of generators that yield `elem`, as it's
+/// called. It resumes the generator, which is finished once it returns `None`.
+pub fn gen_method_ty(elem: &Ty) -> Ty {
+    let gen = Ty::Gen(Box::new(elem.clone()));
+    Ty::Fn {
+        params: vec![gen],
+        ret: Box::new(Ty::Option(Box::new(elem.clone()))),
+    }
+}
+
+/// Returns the number of type arguments a built in generic type takes.
+fn type_params(def: DefId) -> Option<usize> {
+    match def {
+        DefId::OPTION | DefId::GEN => Some(1),
+        DefId::RESULT | DefId::MAP => Some(2),
+        _ => None,
+    }
+}
+
+fn is_builtin_variant(def: DefId) -> bool {
+    matches!(def, DefId::SOME | DefId::NONE | DefId::OK | DefId::ERR)
+}
+
+fn variant_kind(named: bool) -> &'static str {
+    if named {
+        "a struct or struct variant"
+    } else {
+        "a tuple variant"
+    }
+}
+
+/// Returns whether a type is a single built in value that `as` can convert.
+fn is_scalar(ty: &Ty) -> bool {
+    ops::is_int(ty) || matches!(ty, Ty::Float | Ty::Bool | Ty::Char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse_source, resolve::resolve};
+
+    fn typeck(source: &str) -> TypeckResults {
+        let program = parse_source(source).unwrap();
+        let res = resolve(&program);
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+        check(&program, &res)
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        typeck(source)
+            .errors
+            .iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    /// Returns the type of the first expression that's written as `text` in the source.
+    fn type_at(results: &TypeckResults, source: &str, text: &str) -> String {
+        let start = source.find(text).unwrap();
+        results.types[&(start..start + text.len())].to_string()
+    }
+
+    #[test]
+    fn test_infer_types() {
+        let source = "const LIMIT = 10;
+fn apply(f: fn(i32) -> i32, x: int) int { f(x) }
+fn main() {
+    let mut total = 0;
+    let pair = (1.5, \"a\");
+    let double = |n| n * 2;
+    for i in 0..LIMIT { total += apply(double, i); }
+    let (a, b) = pair;
+    let big = if total > 100 { true } else { false };
+    let both = (b, a);
+}";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "(1.5, \"a\")"), "(float, string)");
+        assert_eq!(type_at(&results, source, "|n| n * 2"), "fn(i32) -> i32");
+        assert_eq!(type_at(&results, source, "0..LIMIT"), "Range<i32>");
+        assert_eq!(type_at(&results, source, "apply(double, i)"), "i32");
+        assert_eq!(
+            type_at(&results, source, "if total > 100 { true } else { false }"),
+            "bool"
+        );
+        assert_eq!(type_at(&results, source, "(b, a)"), "(string, float)");
+    }
+
+    #[test]
+    fn test_report_mismatches() {
+        assert_eq!(
+            errors(
+                "fn f(x: int) bool { x }
+fn g() { let s: string = 'c'; f(true, 1); if 1 { } }
+fn h() int { if true { 1 } else { \"no\" } }"
+            ),
+            vec![
+                "mismatched types: expected `bool`, found `i32`",
+                "mismatched types: expected `string`, found `char`",
+                "this function takes 1 argument but 2 were supplied",
+                "mismatched types: expected `i32`, found `bool`",
+                "mismatched types: expected `bool`, found `{integer}`",
+                "mismatched types: expected `{integer}`, found `string`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mismatch_origins() {
+        let source = "fn f(x: int, flag: bool) string {
+    let a: float = x;
+    f(1, \"yes\");
+    let b = if flag { 1 } else { \"one\" };
+    let c = match x { 0 => { return \"zero\"; } 1 => 'a', _ => 2 };
+    x
+}";
+        let program = parse_source(source).unwrap();
+        let results = check(&program, &resolve(&program));
+        let notes: Vec<(String, &str, Vec<&str>)> = results
+            .errors
+            .iter()
+            .map(|error| {
+                let diagnostic = error.to_diagnostic();
+                let notes = diagnostic
+                    .notes
+                    .iter()
+                    .map(|note| note.span.clone().map_or("", |span| &source[span]))
+                    .collect();
+                (diagnostic.message, &source[diagnostic.span], notes)
+            })
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                (
+                    "mismatched types: expected `float`, found `i32`".to_string(),
+                    "x",
+                    vec!["float", ""]
+                ),
+                (
+                    "mismatched types: expected `bool`, found `string`".to_string(),
+                    "\"yes\"",
+                    vec!["flag: bool"]
+                ),
+                (
+                    "mismatched types: expected `{integer}`, found `string`".to_string(),
+                    "{ \"one\" }",
+                    vec!["1"]
+                ),
+                (
+                    "mismatched types: expected `char`, found `{integer}`".to_string(),
+                    "2",
+                    vec!["'a'", ""]
+                ),
+                (
+                    "mismatched types: expected `string`, found `i32`".to_string(),
+                    "x",
+                    vec!["string"]
+                ),
+            ]
+        );
+        assert_eq!(
+            results.errors[0].to_diagnostic().notes[0].message,
+            "expected `float` because of this annotation"
+        );
+    }
+
+    #[test]
+    fn test_check_types_and_values() {
+        assert_eq!(
+            errors(
+                "mod m {}
+fn f(x: Vec<int>, y: float) { x; m; y(); y[0]; for c in 1.0 {} }"
+            ),
+            vec![
+                "cannot find type `Vec` in this scope",
+                "expected a value, found module `m`",
+                "`float` is not a function",
+                "cannot index into a value of type `float`",
+                "`float` is not iterable",
+            ]
+        );
+        assert_eq!(
+            errors("fn f(s: string) char { let mut t = s; t[0] = 'a'; s[0] }"),
+            vec!["cannot assign to a character of a string"]
+        );
+        assert_eq!(
+            errors("fn f(xs: [int]) int { xs.push(1); let ys = [1, 'a']; xs.len() }"),
+            vec![
+                "cannot mutate immutable parameter `xs`",
+                "mismatched types: expected `{integer}`, found `char`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_operators() {
+        assert_eq!(
+            errors(
+                "fn f(mut s: string, c: char, b: bool) {
+    let ok = (1 + 2 * 3, 1.5 / 2.0, s + \"!\", c < 'z', !b && 1 << 2 == 4, (1, 'a') == (2, 'b'));
+    s -= \"x\";
+    let a = 1 + 1.5;
+    let n = -b;
+    let m = 1 && b;
+    let k = (|x| x) == (|x| x);
+    let shifted = 1.0 << 2;
+}"
+            ),
+            vec![
+                "cannot apply `-=` to `string`",
+                "cannot apply `+` to `{integer}` and `float`",
+                "cannot apply `-` to `bool`",
+                "cannot apply `&&` to `{integer}` and `bool`",
+                "cannot apply `==` to `fn(_) -> _`",
+                "cannot apply `<<` to `float` and `{integer}`",
+            ]
+        );
+
+        // Operands that only have one possible type are inferred as it
+        let source = "fn f() { let g = |a, b| a && b; }";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(
+            type_at(&results, source, "|a, b| a && b"),
+            "fn(bool, bool) -> bool"
+        );
+    }
+
+    #[test]
+    fn test_divergence() {
+        let source = "
+fn first(c: bool) int {
+    let x = if c { 1 } else { return 0; };
+    let y = match x { 1 => \"one\", _ => panic(\"not one\") };
+    x
+}
+fn fail(message: string) ! { panic(message) }
+fn unwrap(value: Option<int>) int {
+    match value { Some(x) => x, None => { fail(\"none\"); } }
+}
+fn forever() ! { while true { } }
+fn again() ! { loop { } }";
+        let results = typeck(source);
+        assert_eq!(results.errors, vec![]);
+        assert_eq!(type_at(&results, source, "panic(\"not one\")"), "!");
+        assert_eq!(type_at(&results, source, "fail(\"none\")"), "!");
+
+        let source = "
+fn returns() ! { 1 }
+fn early() ! { return 2; }
+fn panics() string { panic(3) }";
+        assert_eq!(
+            errors(source),
+            vec![
+                "mismatched types: expected `!`, found `{integer}`",
+                "mismatched types: expected `!`, found `{integer}`",
+                "mismatched types: expected `string`, found `{integer}`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sized_ints() {
+        let source = "fn f(a: u8, b: i64) {
+    let x = a + 1;
+    let y = 2 * b;
+    let z = 3;
+    let big: u32 = 4000000000;
+    let cast = big as i8;
+    let w: u64 = 1 << 40;
+    let small: i8 = -128;
+    let wide = a as i64 + b;
+}";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "a + 1"), "u8");
+        assert_eq!(type_at(&results, source, "2 * b"), "i64");
+        assert_eq!(type_at(&results, source, "3"), "i32");
+        assert_eq!(type_at(&results, source, "big as i8"), "i8");
+        assert_eq!(type_at(&results, source, "1 << 40"), "u64");
+        assert_eq!(type_at(&results, source, "a as i64 + b"), "i64");
+
+        assert_eq!(
+            errors(
+                "fn f(a: u8, b: i64, c: u32) {
+    a + b;
+    let x: u8 = 256;
+    let y: i8 = -129;
+    let z: u16 = -1;
+    -c;
+    let big = 99999999999999999999;
+    let ok: u64 = 18446744073709551615;
+}"
+            ),
+            vec![
+                "cannot apply `+` to `u8` and `i64`",
+                "literal out of range for `u8`",
+                "literal out of range for `i8`",
+                "literal out of range for `u16`",
+                "cannot apply `-` to `u32`",
+                "literal out of range for `i32`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_casts() {
+        let source =
+            "fn f(x: int, c: char) { let avg = x as float / 2.0; let n = c as int + true as int; }";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "x as float / 2.0"), "float");
+        assert_eq!(type_at(&results, source, "c as int + true as int"), "i32");
+
+        assert_eq!(
+            errors(
+                "fn f(s: string, c: char) { s as int; 1 as bool; c as float; s as string; 65 as char; c as string; 1.5 as string; }"
+            ),
+            vec![
+                "cannot cast `string` as `i32`",
+                "cannot cast `{integer}` as `bool`",
+                "cannot cast `char` as `float`",
+                "cannot cast `float` as `string`",
+            ]
+        );
+
+        // numbers are never converted without `as`
+        let program = parse_source("fn f(x: int) float { x }").unwrap();
+        let results = check(&program, &resolve(&program));
+        let diagnostic = results.errors[0].to_diagnostic();
+        assert_eq!(
+            diagnostic.message,
+            "mismatched types: expected `float`, found `i32`"
+        );
+        assert_eq!(
+            diagnostic.notes[1].message,
+            "convert the value with `as float`"
+        );
+    }
+
+    #[test]
+    fn test_option_and_result() {
+        let source = "fn parse(s: string) Result<int, string> { if s == \"\" { Err(\"empty\") } else { Ok(1) } }
+fn first(xs: [int]) Option<int> { if true { Some(xs[0]) } else { None } }
+fn sum(a: string, xs: [int]) Result<int, string> { let n = parse(a)?; Ok(n + parse(\"1\")?) }
+fn both(xs: [int]) Option<(int, int)> { let a = first(xs)?; let f = |ys| { let y = first(ys)?; Some(y) }; Some((a, f(xs)?)) }
+fn get(o: Option<float>) float { match o { Some(x) => x, None => 0.0 } }";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "parse(a)?"), "i32");
+        assert_eq!(type_at(&results, source, "first(xs)?"), "i32");
+        assert_eq!(
+            type_at(&results, source, "|ys| { let y = first(ys)?; Some(y) }"),
+            "fn([i32]) -> Option<i32>"
+        );
+
+        assert_eq!(
+            errors(
+                "fn f(o: Option<int>, r: Result<int, string>) Result<int, int> {
+    let a: Option = None;
+    let b: Result<int> = Ok(1);
+    let c: Option<bool> = Some(1);
+    match o { Some(x, y) => {}, Ok(v) => {} }
+    let d = r?;
+    let e = o?;
+    let n = 5?;
+    Ok(1)
+}"
+            ),
+            vec![
+                "`Option` takes 1 type argument but 0 were supplied",
+                "`Result` takes 2 type arguments but 1 was supplied",
+                "mismatched types: expected `Option<bool>`, found `Option<{integer}>`",
+                "this pattern has 2 fields, but `Some` has 1",
+                "mismatched types: expected `Option<i32>`, found `Result<_, _>`",
+                "mismatched types: expected `Result<i32, i32>`, found `Result<_, string>`",
+                "cannot use `?` on an `Option` in a function that returns `Result<i32, i32>`",
+                "the `?` operator can only be applied to an `Option` or a `Result`, not `{integer}`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_maps() {
+        let source = "fn count(words: [string]) Map<string, int> {
+    let mut counts = Map::new();
+    for word in words { let n = match counts.get(word) { Some(n) => n, None => 0 }; counts.insert(word, n + 1); }
+    counts
+}
+fn keys(m: Map<(int, bool), float>) int { let mut n = 0; for entry in m { match (entry) { ((a, _), _) => n += a } } n }";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "Map::new()"), "Map<string, i32>");
+        assert_eq!(type_at(&results, source, "counts.get(word)"), "Option<i32>");
+        assert_eq!(type_at(&results, source, "(entry)"), "((i32, bool), float)");
+
+        assert_eq!(
+            errors(
+                "fn f(m: Map<int, string>) bool {
+    let floats = Map::new();
+    let n = floats.len() + 1;
+    let nested: Map<Map<int, int>, int> = Map::new();
+    m.insert(1, \"a\");
+    m.remove(\"b\");
+    floats.get(0.5) == None && m.contains(2)
+}"
+            ),
+            vec![
+                "`float` cannot be used as a map key",
+                "`Map<i32, i32>` cannot be used as a map key",
+                "cannot mutate immutable parameter `m`",
+                "cannot mutate immutable parameter `m`",
+                "mismatched types: expected `i32`, found `string`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_math() {
+        let source = "use math::pi;
+fn f(x: float, n: i64) float { let m = math::max; math::abs(n); m(math::sqrt(x), pi) + math::min(1.5, x) }";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "math::abs(n)"), "i64");
+        assert_eq!(type_at(&results, source, "m(math::sqrt(x), pi)"), "float");
+
+        assert_eq!(
+            errors("fn f() { math::abs(\"a\"); math::max(1, 2.0); math::pow(2, 3.0); }"),
+            vec![
+                "`string` is not a number",
+                "mismatched types: expected `{integer}`, found `float`",
+                "mismatched types: expected `float`, found `{integer}`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print() {
+        let source = "fn f(c: char, n: u8) { let p = println; print(n); p(c); println(true); }";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "p(c)"), "()");
+
+        assert_eq!(
+            errors("fn f() { println([1]); print(1.5); print(\"a\"); }"),
+            vec!["`[i32]` cannot be printed", "`float` cannot be printed"]
+        );
+    }
+
+    #[test]
+    fn test_extern_fns() {
+        let source = "extern fn labs(x: i64) i64; extern fn srand(seed: u32);
+fn f(c: char) { srand(labs(-1 as i64) as u32); }";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "labs(-1 as i64)"), "i64");
+
+        assert_eq!(
+            errors("extern fn f(s: string) [int]; extern fn g(x: float, c: char) bool;"),
+            vec![
+                "`string` cannot cross to an `extern` function",
+                "`[i32]` cannot cross to an `extern` function",
+            ]
+        );
+        assert_eq!(
+            errors("extern fn f(x: u8); fn g() { f(256); }"),
+            vec!["literal out of range for `u8`"]
+        );
+    }
+
+    #[test]
+    fn test_generators() {
+        let source = "fn count(n: int) Gen<int> { let mut i = 0; while i < n { yield i; i += 1; } }
+fn f() int { let mut g = count(3); for x in count(2) { x; } match g.next() { Some(x) => x, None => 0 } }";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "count(3)"), "Gen<i32>");
+        assert_eq!(type_at(&results, source, "g.next()"), "Option<i32>");
+
+        assert_eq!(
+            errors("fn f() int { yield 1; } fn g() Gen<bool> { yield 1; } fn h() { yield 1; }"),
+            vec![
+                "a function that yields returns a `Gen`, not `i32`",
+                "mismatched types: expected `bool`, found `{integer}`",
+                "a function that yields returns a `Gen`, not `()`",
+            ]
+        );
+        assert_eq!(
+            errors("fn f() Gen<int> { let g = || { yield 1; }; } fn h() int { let x = 1; x }"),
+            vec!["`yield` outside of a generator"]
+        );
+        assert_eq!(
+            errors("fn f() Gen<int> { yield 1; 2 }"),
+            vec!["mismatched types: expected `()`, found `{integer}`"]
+        );
+    }
+
+    #[test]
+    fn test_user_defined_types() {
+        let source = "struct Point { x: int, y: int }
+impl Point {
+    fn new(x: int, y: int) Self { Point { x, y } }
+    fn sum(self) int { self.x + self.y }
+}
+trait Show { fn show(self) string; }
+impl Show for Point { fn show(self) string { \"point\" } }
+enum Shape { Circle(float), Rect { w: float, h: float }, Empty }
+fn area(s: Shape) float {
+    match s { Shape::Circle(r) => r * r, Shape::Rect { w, h } => w * h, Shape::Empty => 0.0 }
+}
+fn main() {
+    let p = Point::new(1, 2);
+    let n = p.sum() + p.x;
+    let shown = p.show();
+    area(Shape::Circle(1.0)) + area(Shape::Rect { w: 1.0, h: 2.0 });
+}";
+        let results = typeck(source);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(type_at(&results, source, "Point::new(1, 2)"), "Point");
+        assert_eq!(type_at(&results, source, "p.sum() + p.x"), "i32");
+        assert_eq!(type_at(&results, source, "p.show()"), "string");
+        assert_eq!(type_at(&results, source, "Shape::Circle(1.0)"), "Shape");
+        assert_eq!(results.methods.len(), 2);
+    }
+
+    #[test]
+    fn test_check_user_defined_types() {
+        assert_eq!(
+            errors(
+                "struct Point { x: int, y: int }
+enum Shape { Circle(float), Rect { w: float, h: float } }
+fn f(p: Point, s: Shape) {
+    let a = Point { x: 1 };
+    let b = Point { x: 1, y: 2, x: 3, z: 4 };
+    let c = p.z + p.x.y;
+    p.len();
+    let d: Point = Shape::Circle(true);
+    let e = Shape::Rect;
+    match s { Shape::Circle(r, q) => {}, Shape::Rect { w, d } => {} }
+}
+fn g(x: f) {}"
+            ),
+            vec![
+                "missing field `y` in `Point`",
+                "field `x` is given more than once",
+                "no field `z` on type `Point`",
+                "no field `z` on type `Point`",
+                "no field `y` on type `i32`",
+                "no method named `len` on type `Point`",
+                "mismatched types: expected `Point`, found `Shape`",
+                "mismatched types: expected `float`, found `bool`",
+                "expected a value, found struct variant `Rect`",
+                "this pattern has 2 fields, but `Circle` has 1",
+                "no field `d` on type `Shape`",
+                "expected a type, found function `f`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_private_fields_and_methods() {
+        assert_eq!(
+            errors(
+                "mod shapes {
+    pub struct Square { pub side: int, area: int }
+    impl Square {
+        pub fn new(side: int) Square { Square { side, area: side * side } }
+        fn scale(self) int { self.area }
+    }
+}
+fn main() {
+    let s = shapes::Square::new(2);
+    s.side + s.area + s.scale();
+}"
+            ),
+            vec!["field `area` is private", "method `scale` is private"]
+        );
+    }
+
+    #[test]
+    fn test_generic_fns() {
+        let source = "trait Printable { fn print(self) string; fn width(self) int; }
+struct Point { x: int }
+struct Line { a: Point }
+impl Printable for Point {
+    fn print(self) string { \"point\" }
+    fn width(self) int { 1 }
+}
+fn print_all<T: Printable>(items: [T]) int {
+    let mut total = 0;
+    for item in items { item.print(); total += item.width(); }
+    total
+}
+fn first<T>(items: [T]) T { items[0] }
+fn main(points: [Point], lines: [Line], nums: [int]) {
+    print_all(points);
+    print_all(lines);
+    let n: int = first(nums);
+    let p = first(points);
+}";
+        let results = typeck(source);
+        let [error] = &results.errors[..] else {
+            panic!("expected one error, found {:?}", results.errors);
+        };
+        assert_eq!(error.to_string(), "`Line` doesn't implement `Printable`");
+        let notes: Vec<_> = error
+            .to_diagnostic()
+            .notes
+            .into_iter()
+            .map(|note| note.message)
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                "`Line` is missing the methods `print`, `width`",
+                "required by this bound"
+            ]
+        );
+        assert_eq!(type_at(&results, source, "first(points)"), "Point");
+        assert_eq!(type_at(&results, source, "print_all(points)"), "i32");
+    }
+
+    #[test]
+    fn test_check_generic_fn_bodies() {
+        assert_eq!(
+            errors(
+                "trait Show { fn show(self) string; }
+struct S {}
+fn f<T: Show, U: S>(t: T, u: U) int {
+    t.show();
+    t.len();
+    let x: int = t;
+    t + 1
+}
+fn g<T: Show>(t: T) { f(t, t); }"
+            ),
+            vec![
+                "expected a trait, found struct `S`",
+                "no method named `len` on type `T`",
+                "mismatched types: expected `i32`, found `T`",
+                "cannot apply `+` to `T` and `{integer}`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diverging_blocks() {
+        // Branches that leave the function fit any type
+        assert_eq!(
+            errors(
+                "fn f(c: bool) int {
+    let x = if c { 1 } else { return 0; };
+    if c { return x; }
+    while true { return 2; }
+}
+fn g() string { return 1; }"
+            ),
+            vec!["mismatched types: expected `string`, found `{integer}`"]
+        );
+    }
+
+    #[test]
+    fn test_provided_methods() {
+        let source = "trait Shape {
+    fn area(self) int;
+    fn double(self) int { self.area() * 2 }
+    fn name(self) string { \"shape\" }
+}
+struct Square { side: int }
+impl Shape for Square {
+    fn area(self) int { self.side * self.side }
+    fn name(self) string { \"square\" }
+}
+struct Dot {}
+impl Shape for Dot { fn area(self) int { 0 } }
+fn main() {
+    let a = Square { side: 3 }.double();
+    let b = Dot {}.name();
+}";
+        let program = parse_source(source).unwrap();
+        let res = resolve(&program);
+        let results = check(&program, &res);
+        assert!(results.errors.is_empty(), "{:?}", results.errors);
+        assert_eq!(
+            type_at(&results, source, "Square { side: 3 }.double()"),
+            "i32"
+        );
+        assert_eq!(type_at(&results, source, "Dot {}.name()"), "string");
+
+        // Each impl takes the methods it doesn't define, whose bodies call its own methods
+        let provided: Vec<_> = res
+            .provided
+            .iter()
+            .map(|provided| res.def(provided.def).name.as_str())
+            .collect();
+        assert_eq!(provided, ["double", "double", "name"]);
+        let start = source.find("area() * 2").unwrap();
+        let callees: Vec<_> = res.provided[..2]
+            .iter()
+            .map(|provided| results.instances[&provided.def].methods[&(start..start + 4)])
+            .collect();
+        let area = |text: &str| {
+            let start = source.find(text).unwrap();
+            res.lookup(&(start..start + 4)).unwrap()
+        };
+        assert_eq!(
+            callees,
+            [area("area(self) int { self"), area("area(self) int { 0")]
+        );
+
+        // Errors that only some of the impls have are reported for the method they take
+        assert_eq!(
+            errors(
+                "trait Sized { fn size(self) int { self.len } }
+struct A { len: int }
+struct B {}
+impl Sized for A {}
+impl Sized for B {}"
+            ),
+            vec!["no field `len` on type `B`"]
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! The operators each built in type supports, and what they do to integers.
+
+use super::{IntTy, Ty};
+use crate::ast::{BinaryOp, UnaryOp};
+
+/// Returns whether `lhs op rhs` is allowed when the operands have type `ty`. Both operands have
+/// the same type, except for shifts, whose amount can be any integer type. Types that aren't
+/// known yet are allowed, as are types with errors.
+pub fn supports_binary(op: BinaryOp, ty: &Ty) -> bool {
+    if is_unknown(ty) {
+        return true;
+    }
+    let int = is_int(ty);
+    match op {
+        BinaryOp::Add => int || matches!(ty, Ty::Float | Ty::String),
+        BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => int || *ty == Ty::Float,
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::TripleEq | BinaryOp::TripleNe => {
+            supports_equality(ty)
+        }
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+            int || matches!(ty, Ty::Float | Ty::String | Ty::Char)
+        }
+        BinaryOp::And | BinaryOp::Or => *ty == Ty::Bool,
+        BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => int || *ty == Ty::Bool,
+        BinaryOp::Shl | BinaryOp::Shr => int,
+    }
+}
+
+/// Returns whether `op expr` is allowed when the operand has type `ty`.
+pub fn supports_unary(op: UnaryOp, ty: &Ty) -> bool {
+    if is_unknown(ty) {
+        return true;
+    }
+    match op {
+        UnaryOp::Neg => match ty {
+            Ty::Int(int) => int.is_signed(),
+            _ => matches!(ty, Ty::IntVar(_) | Ty::Float),
+        },
+        // `!` on an integer flips its bits
+        UnaryOp::Not => is_int(ty) || *ty == Ty::Bool,
+    }
+}
+
+/// Returns the only type an operator's operands can have, which is what operands whose type
+/// isn't known yet are inferred as.
+pub fn operand_ty(op: BinaryOp) -> Option<Ty> {
+    match op {
+        BinaryOp::And | BinaryOp::Or => Some(Ty::Bool),
+        _ => None,
+    }
+}
+
+/// Returns the type of `lhs op rhs` when the operands have type `ty`.
+pub fn binary_result(op: BinaryOp, ty: Ty) -> Ty {
+    if op.is_comparison() || matches!(op, BinaryOp::And | BinaryOp::Or) {
+        Ty::Bool
+    } else {
+        ty
+    }
+}
+
+/// Evaluates `lhs op rhs` on integers of type `ty`, for the operators that produce an integer.
+/// Integer arithmetic is checked, so this is `None` when the result doesn't fit in the type, when
+/// dividing by zero, and when shifting by a negative amount or at least the width of the type.
+/// Shifting right keeps the sign of a signed integer.
+pub fn eval_int(op: BinaryOp, ty: IntTy, lhs: i128, rhs: i128) -> Option<i128> {
+    let value = match op {
+        BinaryOp::Add => lhs + rhs,
+        BinaryOp::Sub => lhs - rhs,
+        BinaryOp::Mul => lhs.checked_mul(rhs)?,
+        BinaryOp::Div => lhs.checked_div(rhs)?,
+        // The remainder is 0, but the quotient that machines divide to get it overflows
+        BinaryOp::Rem if lhs == ty.min() && rhs == -1 && ty.min() < 0 => return None,
+        BinaryOp::Rem => lhs.checked_rem(rhs)?,
+        BinaryOp::BitAnd => lhs & rhs,
+        BinaryOp::BitOr => lhs | rhs,
+        BinaryOp::BitXor => lhs ^ rhs,
+        BinaryOp::Shl | BinaryOp::Shr if !(0..ty.bits() as i128).contains(&rhs) => return None,
+        // Bits shifted out of the type are dropped rather than overflowing
+        BinaryOp::Shl => return Some(ty.wrap(lhs << rhs)),
+        BinaryOp::Shr => lhs >> rhs,
+        _ => return None,
+    };
+    ty.contains(value).then_some(value)
+}
+
+/// Returns whether `expr as to` is allowed when `expr` has type `from`. Numbers convert to each
+/// other, `char`s and integers convert both ways, and `bool`s convert to integers. Integers,
+/// `bool`s and `char`s convert to strings, which is their text. Every type converts to itself.
+pub fn supports_cast(from: &Ty, to: &Ty) -> bool {
+    if is_unknown(from) || is_unknown(to) {
+        return true;
+    }
+    let number = |ty: &Ty| is_int(ty) || *ty == Ty::Float;
+    match (from, to) {
+        _ if number(from) && number(to) => true,
+        (Ty::Char | Ty::Bool, to) if is_int(to) => true,
+        (from, Ty::Char) if is_int(from) => true,
+        (from, Ty::String) => is_displayable(from),
+        _ => from == to,
+    }
+}
+
+/// Returns whether `print` and `println` take a value of a type, which is a string, or a type
+/// that converts to one with `as string`.
+pub fn is_displayable(ty: &Ty) -> bool {
+    is_int(ty) || matches!(ty, Ty::Bool | Ty::Char | Ty::String) || is_unknown(ty)
+}
+
+/// Values of the built in types, and tuples and arrays of them, can be compared with `==`.
+/// Functions, ranges and generators can't. Maps are equal when they have the same entries, in any
+/// order.
+fn supports_equality(ty: &Ty) -> bool {
+    match ty {
+        Ty::Tuple(elems) => elems.iter().all(supports_equality),
+        Ty::Array(elem) | Ty::Option(elem) | Ty::Map(_, elem) => supports_equality(elem),
+        Ty::Result(value, error) => supports_equality(value) && supports_equality(error),
+        Ty::Fn { .. } | Ty::Range(_) | Ty::Gen(_) => false,
+        _ => true,
+    }
+}
+
+/// Returns whether values of a type can be the keys of a map. They're the values that can be
+/// compared with `==`, except for floats, as NaN isn't equal to itself, and maps, whose entries
+/// have no order to hash them in. Types that aren't known yet are allowed.
+pub fn supports_hashing(ty: &Ty) -> bool {
+    match ty {
+        Ty::Tuple(elems) => elems.iter().all(supports_hashing),
+        Ty::Array(elem) | Ty::Option(elem) => supports_hashing(elem),
+        Ty::Result(value, error) => supports_hashing(value) && supports_hashing(error),
+        Ty::Float | Ty::Map(..) => false,
+        _ => supports_equality(ty),
+    }
+}
+
+/// Returns whether a type is an integer or float type, or one that isn't known yet.
+pub fn is_number(ty: &Ty) -> bool {
+    is_int(ty) || matches!(ty, Ty::Float) || is_unknown(ty)
+}
+
+/// Returns whether a type is an integer type, or a literal's type that will be one.
+pub fn is_int(ty: &Ty) -> bool {
+    matches!(ty, Ty::Int(_) | Ty::IntVar(_))
+}
+
+fn is_unknown(ty: &Ty) -> bool {
+    matches!(ty, Ty::Var(_) | Ty::Error | Ty::Never)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INT: Ty = Ty::Int(IntTy::I32);
+
+    #[test]
+    fn test_operator_rules() {
+        assert!(supports_binary(BinaryOp::Add, &Ty::String));
+        assert!(!supports_binary(BinaryOp::Sub, &Ty::String));
+        assert!(!supports_binary(BinaryOp::And, &INT));
+        assert!(supports_binary(BinaryOp::Lt, &Ty::Char));
+        assert!(!supports_binary(BinaryOp::Lt, &Ty::Bool));
+        assert!(supports_binary(BinaryOp::Eq, &Ty::unit()));
+        assert!(!supports_binary(
+            BinaryOp::Eq,
+            &Ty::Tuple(vec![INT, Ty::Range(Box::new(INT))])
+        ));
+        assert!(supports_binary(BinaryOp::Mul, &Ty::Error));
+        let map = Ty::Map(Box::new(Ty::String), Box::new(Ty::Float));
+        assert!(supports_binary(BinaryOp::Eq, &map));
+        assert!(supports_hashing(&Ty::Tuple(vec![INT, Ty::Char])));
+        assert!(!supports_hashing(&Ty::Array(Box::new(Ty::Float))));
+        assert!(!supports_hashing(&map));
+        assert!(supports_binary(BinaryOp::Shl, &Ty::Int(IntTy::U8)));
+
+        assert!(supports_unary(UnaryOp::Neg, &Ty::Float));
+        assert!(!supports_unary(UnaryOp::Neg, &Ty::Bool));
+        assert!(!supports_unary(UnaryOp::Neg, &Ty::Int(IntTy::U32)));
+        assert!(!supports_unary(UnaryOp::Not, &Ty::String));
+
+        assert!(supports_cast(&Ty::Float, &INT));
+        assert!(supports_cast(&Ty::Char, &INT));
+        assert!(supports_cast(&Ty::Int(IntTy::U64), &Ty::Int(IntTy::I8)));
+        assert!(supports_cast(&Ty::String, &Ty::String));
+        assert!(!supports_cast(&INT, &Ty::Bool));
+        assert!(!supports_cast(&Ty::String, &INT));
+        assert!(!supports_cast(&Ty::Char, &Ty::Float));
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        assert_eq!(eval_int(BinaryOp::Add, IntTy::U8, 200, 55), Some(255));
+        assert_eq!(eval_int(BinaryOp::Add, IntTy::U8, 200, 56), None);
+        assert_eq!(eval_int(BinaryOp::Sub, IntTy::U32, 1, 2), None);
+        assert_eq!(
+            eval_int(BinaryOp::Mul, IntTy::I64, i64::MAX as i128, 2),
+            None
+        );
+        assert_eq!(eval_int(BinaryOp::Div, IntTy::I32, -7, 2), Some(-3));
+        assert_eq!(eval_int(BinaryOp::Rem, IntTy::I32, -7, 2), Some(-1));
+        assert_eq!(eval_int(BinaryOp::Div, IntTy::I32, 1, 0), None);
+        // The one quotient of two `i8`s that doesn't fit, and its remainder
+        assert_eq!(eval_int(BinaryOp::Div, IntTy::I8, -128, -1), None);
+        assert_eq!(eval_int(BinaryOp::Rem, IntTy::I8, -128, -1), None);
+        assert_eq!(eval_int(BinaryOp::Rem, IntTy::I8, -127, -1), Some(0));
+        assert_eq!(
+            eval_int(BinaryOp::Rem, IntTy::I64, i64::MIN as i128, -1),
+            None
+        );
+
+        assert_eq!(
+            eval_int(BinaryOp::Shl, IntTy::U8, 0b1100_0000, 1),
+            Some(0b1000_0000)
+        );
+        assert_eq!(eval_int(BinaryOp::Shl, IntTy::I32, 1, 32), None);
+        assert_eq!(eval_int(BinaryOp::Shr, IntTy::I8, -8, 1), Some(-4));
+        assert_eq!(eval_int(BinaryOp::Shr, IntTy::I8, 1, -1), None);
+        assert_eq!(
+            eval_int(BinaryOp::BitXor, IntTy::U16, 0xff, 0x0f),
+            Some(0xf0)
+        );
+    }
+}
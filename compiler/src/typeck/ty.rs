@@ -0,0 +1,506 @@
+//! The types the checker gives to expressions, and the table that infers the types that aren't
+//! written down.
+
+use std::{collections::HashMap, fmt::Display};
+
+use crate::resolve::DefId;
+
+/// A type variable, standing for a type that hasn't been inferred yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TyVar(pub usize);
+
+/// An integer type. `int` is another name for `i32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntTy {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl IntTy {
+    /// The type of an integer literal that nothing else decides the type of.
+    pub const DEFAULT: IntTy = IntTy::I32;
+
+    pub fn bits(self) -> u32 {
+        match self {
+            IntTy::I8 | IntTy::U8 => 8,
+            IntTy::I16 | IntTy::U16 => 16,
+            IntTy::I32 | IntTy::U32 => 32,
+            IntTy::I64 | IntTy::U64 => 64,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, IntTy::I8 | IntTy::I16 | IntTy::I32 | IntTy::I64)
+    }
+
+    pub fn min(self) -> i128 {
+        if self.is_signed() {
+            -(1 << (self.bits() - 1))
+        } else {
+            0
+        }
+    }
+
+    pub fn max(self) -> i128 {
+        if self.is_signed() {
+            (1 << (self.bits() - 1)) - 1
+        } else {
+            (1 << self.bits()) - 1
+        }
+    }
+
+    /// Returns whether a value of the type can be `value`.
+    pub fn contains(self, value: i128) -> bool {
+        (self.min()..=self.max()).contains(&value)
+    }
+
+    /// Converts an integer of any type to this one with `as`, keeping the low bits, so a value
+    /// that doesn't fit wraps around.
+    pub fn wrap(self, value: i128) -> i128 {
+        let bits = self.bits();
+        let low = value & ((1 << bits) - 1);
+        if self.is_signed() && low > self.max() {
+            low - (1 << bits)
+        } else {
+            low
+        }
+    }
+
+    /// Converts a `float` to this type with `as`, rounding toward zero. Values past the ends of
+    /// the type become the end they're past, and NaN becomes 0.
+    pub fn from_float(self, value: f64) -> i128 {
+        if value.is_nan() {
+            0
+        } else {
+            (value.trunc() as i128).clamp(self.min(), self.max())
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IntTy::I8 => "i8",
+            IntTy::I16 => "i16",
+            IntTy::I32 => "i32",
+            IntTy::I64 => "i64",
+            IntTy::U8 => "u8",
+            IntTy::U16 => "u16",
+            IntTy::U32 => "u32",
+            IntTy::U64 => "u64",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Int(IntTy),
+    Float,
+    Bool,
+    String,
+    Char,
+    /// `(a, b)`, where `()` is the unit type.
+    Tuple(Vec<Ty>),
+    /// `[T]`
+    Array(Box<Ty>),
+    /// The type of a `start..end` range over values of the type.
+    Range(Box<Ty>),
+    /// The built in `Option<T>`, which is `Some(value)` or `None`.
+    Option(Box<Ty>),
+    /// The built in `Result<T, E>`, which is `Ok(value)` or `Err(error)`.
+    Result(Box<Ty>, Box<Ty>),
+    /// The built in `Map<K, V>`, from keys of type `K` to values of type `V`.
+    Map(Box<Ty>, Box<Ty>),
+    /// The built in `Gen<T>`, a generator that yields values of type `T`.
+    Gen(Box<Ty>),
+    /// A struct or enum, which is only the same as itself, whatever its contents.
+    Adt {
+        def: DefId,
+        name: String,
+    },
+    Fn {
+        params: Vec<Ty>,
+        ret: Box<Ty>,
+    },
+    /// A type parameter inside its generic function, where it stands for any type that has its
+    /// bounds.
+    Param {
+        def: DefId,
+        name: String,
+    },
+    /// The type of an expression that never produces a value, such as a block that ends with
+    /// `return`. It fits wherever a value is expected.
+    Never,
+    Var(TyVar),
+    /// A variable that can only be inferred as an integer type, which is the type of an integer
+    /// literal. It becomes [`IntTy::DEFAULT`] when nothing decides which integer type it is.
+    IntVar(TyVar),
+    /// The type of an expression that has an error. It fits everywhere, so that one mistake isn't
+    /// reported again by every expression around it.
+    Error,
+}
+
+impl Ty {
+    pub fn unit() -> Ty {
+        Ty::Tuple(Vec::new())
+    }
+
+    pub fn is_unit(&self) -> bool {
+        matches!(self, Ty::Tuple(elems) if elems.is_empty())
+    }
+
+    /// Replaces the type parameters in a type with the types given for them.
+    pub fn substitute(&self, args: &HashMap<DefId, Ty>) -> Ty {
+        match self {
+            Ty::Param { def, .. } => args.get(def).cloned().unwrap_or_else(|| self.clone()),
+            Ty::Tuple(elems) => Ty::Tuple(elems.iter().map(|elem| elem.substitute(args)).collect()),
+            Ty::Array(elem) => Ty::Array(Box::new(elem.substitute(args))),
+            Ty::Range(elem) => Ty::Range(Box::new(elem.substitute(args))),
+            Ty::Gen(elem) => Ty::Gen(Box::new(elem.substitute(args))),
+            Ty::Option(value) => Ty::Option(Box::new(value.substitute(args))),
+            Ty::Result(value, error) => Ty::Result(
+                Box::new(value.substitute(args)),
+                Box::new(error.substitute(args)),
+            ),
+            Ty::Map(key, value) => Ty::Map(
+                Box::new(key.substitute(args)),
+                Box::new(value.substitute(args)),
+            ),
+            Ty::Fn { params, ret } => Ty::Fn {
+                params: params.iter().map(|param| param.substitute(args)).collect(),
+                ret: Box::new(ret.substitute(args)),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// Returns the built in type with the given name.
+    pub fn builtin(name: &str) -> Option<Ty> {
+        match name {
+            "int" | "i32" => Some(Ty::Int(IntTy::I32)),
+            "i8" => Some(Ty::Int(IntTy::I8)),
+            "i16" => Some(Ty::Int(IntTy::I16)),
+            "i64" => Some(Ty::Int(IntTy::I64)),
+            "u8" => Some(Ty::Int(IntTy::U8)),
+            "u16" => Some(Ty::Int(IntTy::U16)),
+            "u32" => Some(Ty::Int(IntTy::U32)),
+            "u64" => Some(Ty::Int(IntTy::U64)),
+            "float" => Some(Ty::Float),
+            "bool" => Some(Ty::Bool),
+            "string" => Some(Ty::String),
+            "char" => Some(Ty::Char),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ty::Int(int) => write!(f, "{}", int.name()),
+            Ty::Float => write!(f, "float"),
+            Ty::Bool => write!(f, "bool"),
+            Ty::String => write!(f, "string"),
+            Ty::Char => write!(f, "char"),
+            Ty::Tuple(elems) => {
+                write!(f, "(")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                if elems.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Ty::Array(elem) => write!(f, "[{}]", elem),
+            Ty::Range(elem) => write!(f, "Range<{}>", elem),
+            Ty::Option(value) => write!(f, "Option<{}>", value),
+            Ty::Result(value, error) => write!(f, "Result<{}, {}>", value, error),
+            Ty::Map(key, value) => write!(f, "Map<{}, {}>", key, value),
+            Ty::Gen(elem) => write!(f, "Gen<{}>", elem),
+            Ty::Adt { name, .. } | Ty::Param { name, .. } => write!(f, "{}", name),
+            Ty::Fn { params, ret } => {
+                write!(f, "fn(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", param)?;
+                }
+                write!(f, ")")?;
+                if !ret.is_unit() {
+                    write!(f, " -> {}", ret)?;
+                }
+                Ok(())
+            }
+            Ty::Never => write!(f, "!"),
+            Ty::IntVar(_) => write!(f, "{{integer}}"),
+            Ty::Var(_) | Ty::Error => write!(f, "_"),
+        }
+    }
+}
+
+/// The types inferred for the type variables so far.
+#[derive(Debug, Clone, Default)]
+pub struct InferTable {
+    vars: Vec<Option<Ty>>,
+    /// The variables created by [`InferTable::new_int_var`].
+    int_vars: Vec<TyVar>,
+}
+
+impl InferTable {
+    pub fn new_var(&mut self) -> Ty {
+        self.vars.push(None);
+        Ty::Var(TyVar(self.vars.len() - 1))
+    }
+
+    /// Returns a new variable that can only be inferred as an integer type.
+    pub fn new_int_var(&mut self) -> Ty {
+        self.vars.push(None);
+        let var = TyVar(self.vars.len() - 1);
+        self.int_vars.push(var);
+        Ty::IntVar(var)
+    }
+
+    /// Infers the integer variables that nothing else has inferred as [`IntTy::DEFAULT`].
+    pub fn default_ints(&mut self) {
+        for var in &self.int_vars {
+            if self.vars[var.0].is_none() {
+                self.vars[var.0] = Some(Ty::Int(IntTy::DEFAULT));
+            }
+        }
+    }
+
+    /// Replaces the variables in a type with the types inferred for them, as far as they're known.
+    pub fn resolve(&self, ty: &Ty) -> Ty {
+        let ty = self.shallow_resolve(ty);
+        match &ty {
+            Ty::Tuple(elems) => Ty::Tuple(elems.iter().map(|elem| self.resolve(elem)).collect()),
+            Ty::Array(elem) => Ty::Array(Box::new(self.resolve(elem))),
+            Ty::Range(elem) => Ty::Range(Box::new(self.resolve(elem))),
+            Ty::Gen(elem) => Ty::Gen(Box::new(self.resolve(elem))),
+            Ty::Option(value) => Ty::Option(Box::new(self.resolve(value))),
+            Ty::Result(value, error) => {
+                Ty::Result(Box::new(self.resolve(value)), Box::new(self.resolve(error)))
+            }
+            Ty::Map(key, value) => {
+                Ty::Map(Box::new(self.resolve(key)), Box::new(self.resolve(value)))
+            }
+            Ty::Fn { params, ret } => Ty::Fn {
+                params: params.iter().map(|param| self.resolve(param)).collect(),
+                ret: Box::new(self.resolve(ret)),
+            },
+            _ => ty,
+        }
+    }
+
+    /// Follows a variable to the type inferred for it, without looking inside the type.
+    pub fn shallow_resolve(&self, ty: &Ty) -> Ty {
+        let mut ty = ty;
+        while let Ty::Var(var) | Ty::IntVar(var) = ty {
+            match &self.vars[var.0] {
+                Some(inferred) => ty = inferred,
+                None => break,
+            }
+        }
+        ty.clone()
+    }
+
+    /// Follows a variable to the type inferred for it like [`InferTable::shallow_resolve`], and
+    /// points every variable on the way straight at that type, so that following them again takes
+    /// one step. Variables inferred as each other otherwise form chains as long as the code that
+    /// inferred them.
+    fn compress(&mut self, ty: &Ty) -> Ty {
+        let resolved = self.shallow_resolve(ty);
+        let mut ty = ty.clone();
+        while let Ty::Var(var) | Ty::IntVar(var) = ty {
+            match &mut self.vars[var.0] {
+                Some(inferred) if *inferred != resolved => {
+                    ty = std::mem::replace(inferred, resolved.clone());
+                }
+                _ => break,
+            }
+        }
+        resolved
+    }
+
+    /// Makes two types the same by inferring the variables in them, returning whether they can
+    /// be. A variable can't be inferred as [`Ty::Never`], as that would make the variable fit
+    /// anywhere, and an integer variable can only be inferred as an integer type.
+    pub fn unify(&mut self, a: &Ty, b: &Ty) -> bool {
+        let a = self.compress(a);
+        let b = self.compress(b);
+        match (&a, &b) {
+            (Ty::Error, _) | (_, Ty::Error) | (Ty::Never, _) | (_, Ty::Never) => true,
+            (Ty::Var(a), Ty::Var(b)) if a == b => true,
+            (Ty::Var(var), ty) | (ty, Ty::Var(var)) => {
+                if self.occurs(*var, ty) {
+                    return false;
+                }
+                self.vars[var.0] = Some(ty.clone());
+                true
+            }
+            (Ty::IntVar(a), Ty::IntVar(b)) if a == b => true,
+            // The newer variable is inferred as the older one, which is usually the one that's
+            // kept around, such as a variable's type rather than a literal's
+            (Ty::IntVar(a), Ty::IntVar(b)) => {
+                let (old, new) = if a.0 < b.0 { (a, b) } else { (b, a) };
+                self.vars[new.0] = Some(Ty::IntVar(*old));
+                true
+            }
+            (Ty::IntVar(var), ty @ Ty::Int(_)) | (ty @ Ty::Int(_), Ty::IntVar(var)) => {
+                self.vars[var.0] = Some(ty.clone());
+                true
+            }
+            (Ty::Tuple(a), Ty::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| self.unify(a, b))
+            }
+            (Ty::Array(a), Ty::Array(b))
+            | (Ty::Range(a), Ty::Range(b))
+            | (Ty::Gen(a), Ty::Gen(b))
+            | (Ty::Option(a), Ty::Option(b)) => self.unify(a, b),
+            (Ty::Result(a, b), Ty::Result(other_a, other_b))
+            | (Ty::Map(a, b), Ty::Map(other_a, other_b)) => {
+                self.unify(a, other_a) && self.unify(b, other_b)
+            }
+            (
+                Ty::Fn { params, ret },
+                Ty::Fn {
+                    params: other_params,
+                    ret: other_ret,
+                },
+            ) => {
+                params.len() == other_params.len()
+                    && params
+                        .iter()
+                        .zip(other_params)
+                        .all(|(a, b)| self.unify(a, b))
+                    && self.unify(ret, other_ret)
+            }
+            _ => a == b,
+        }
+    }
+
+    /// Returns whether a variable appears in a type, which would make inferring the variable as
+    /// the type create an infinite type.
+    fn occurs(&self, var: TyVar, ty: &Ty) -> bool {
+        match self.shallow_resolve(ty) {
+            Ty::Var(other) => other == var,
+            Ty::Tuple(elems) => elems.iter().any(|elem| self.occurs(var, elem)),
+            Ty::Array(elem) | Ty::Range(elem) | Ty::Gen(elem) | Ty::Option(elem) => {
+                self.occurs(var, &elem)
+            }
+            Ty::Result(a, b) | Ty::Map(a, b) => self.occurs(var, &a) || self.occurs(var, &b),
+            Ty::Fn { params, ret } => {
+                params.iter().any(|param| self.occurs(var, param)) || self.occurs(var, &ret)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_types() {
+        let f = Ty::Fn {
+            params: vec![Ty::Int(IntTy::I32), Ty::Array(Box::new(Ty::String))],
+            ret: Box::new(Ty::Tuple(vec![Ty::Bool])),
+        };
+        assert_eq!(f.to_string(), "fn(i32, [string]) -> (bool,)");
+        assert_eq!(Ty::unit().to_string(), "()");
+        let result = Ty::Result(
+            Box::new(Ty::Option(Box::new(Ty::Int(IntTy::I32)))),
+            Box::new(Ty::String),
+        );
+        assert_eq!(result.to_string(), "Result<Option<i32>, string>");
+        let map = Ty::Map(
+            Box::new(Ty::String),
+            Box::new(Ty::Array(Box::new(Ty::Char))),
+        );
+        assert_eq!(map.to_string(), "Map<string, [char]>");
+    }
+
+    #[test]
+    fn test_unify() {
+        let mut table = InferTable::default();
+        let a = table.new_var();
+        let b = table.new_var();
+        let pair = Ty::Tuple(vec![a.clone(), Ty::Int(IntTy::I32)]);
+        assert!(table.unify(&pair, &Ty::Tuple(vec![Ty::Char, b.clone()])));
+        assert_eq!(
+            table.resolve(&pair),
+            Ty::Tuple(vec![Ty::Char, Ty::Int(IntTy::I32)])
+        );
+        assert_eq!(table.resolve(&b), Ty::Int(IntTy::I32));
+
+        assert!(!table.unify(&a, &Ty::Float));
+        assert!(table.unify(&a, &Ty::Error));
+
+        // `a = [a]` has no solution
+        let c = table.new_var();
+        assert!(!table.unify(&c, &Ty::Array(Box::new(c.clone()))));
+    }
+
+    #[test]
+    fn test_int_vars() {
+        let mut table = InferTable::default();
+        let a = table.new_int_var();
+        let b = table.new_int_var();
+        let c = table.new_int_var();
+        assert!(!table.unify(&a, &Ty::Float));
+        assert!(table.unify(&a, &b));
+        assert!(table.unify(&Ty::Int(IntTy::U8), &b));
+        assert_eq!(table.resolve(&a), Ty::Int(IntTy::U8));
+        assert_eq!(c.to_string(), "{integer}");
+
+        let v = table.new_var();
+        assert!(table.unify(&v, &c));
+        assert!(!table.unify(&v, &Ty::Bool));
+        table.default_ints();
+        assert_eq!(table.resolve(&v), Ty::Int(IntTy::I32));
+    }
+
+    #[test]
+    fn test_int_semantics() {
+        assert_eq!((IntTy::I8.min(), IntTy::I8.max()), (-128, 127));
+        assert_eq!(IntTy::U64.max(), u64::MAX as i128);
+        assert!(IntTy::U16.contains(65535));
+        assert!(!IntTy::U16.contains(-1));
+
+        assert_eq!(IntTy::U8.wrap(300), 44);
+        assert_eq!(IntTy::U8.wrap(-1), 255);
+        assert_eq!(IntTy::I8.wrap(200), -56);
+        assert_eq!(IntTy::I64.wrap(u64::MAX as i128), -1);
+
+        assert_eq!(IntTy::I32.from_float(-2.9), -2);
+        assert_eq!(IntTy::U8.from_float(1e10), 255);
+        assert_eq!(IntTy::U8.from_float(-5.0), 0);
+        assert_eq!(IntTy::I64.from_float(f64::NAN), 0);
+    }
+
+    #[test]
+    fn test_long_int_var_chains() {
+        // Each variable is bound to the one before it, so resolving the last walks all of them
+        let mut table = InferTable::default();
+        let vars: Vec<_> = (0..100_000).map(|_| table.new_int_var()).collect();
+        for pair in vars.windows(2).rev() {
+            assert!(table.unify(&pair[0], &pair[1]));
+        }
+        let last = vars.last().unwrap();
+        assert_eq!(table.resolve(last), vars[0]);
+        assert!(table.unify(last, &Ty::Int(IntTy::I64)));
+        assert!(vars
+            .iter()
+            .all(|var| table.resolve(var) == Ty::Int(IntTy::I64)));
+    }
+}
@@ -0,0 +1,772 @@
+//! Traversal of the AST, so that analysis and transformation passes don't each have to match on
+//! every node.
+
+use crate::ast::*;
+
+/// Walks the AST by reference. Each method's default implementation visits the node's children
+/// with the matching `walk_*` function, so implementors only override the nodes they care about
+/// and call the `walk_*` function to keep descending.
+pub trait Visit<'ast> {
+    fn visit_program(&mut self, program: &'ast Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_item(&mut self, item: &'ast Item) {
+        walk_item(self, item);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_pattern(&mut self, pattern: &'ast Pattern) {
+        walk_pattern(self, pattern);
+    }
+
+    fn visit_type(&mut self, ty: &'ast TypeExpr) {
+        walk_type(self, ty);
+    }
+
+    fn visit_path(&mut self, path: &'ast Path) {
+        walk_path(self, path);
+    }
+
+    fn visit_ident(&mut self, _ident: &'ast Ident) {}
+}
+
+pub fn walk_program<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, program: &'ast Program) {
+    for item in &program.items {
+        v.visit_item(item);
+    }
+}
+
+pub fn walk_item<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, item: &'ast Item) {
+    match &item.kind {
+        ItemKind::Const(decl) | ItemKind::Static(decl) => {
+            v.visit_ident(&decl.name);
+            if let Some(ty) = &decl.ty {
+                v.visit_type(ty);
+            }
+            v.visit_expr(&decl.value);
+        }
+        ItemKind::Fn(decl) => {
+            v.visit_ident(&decl.name);
+            for generic in &decl.generics {
+                v.visit_ident(&generic.name);
+                for bound in &generic.bounds {
+                    v.visit_path(bound);
+                }
+            }
+            for param in &decl.params {
+                v.visit_pattern(&param.pattern);
+                v.visit_type(&param.ty);
+            }
+            if let Some(ret) = &decl.ret {
+                v.visit_type(ret);
+            }
+            v.visit_block(&decl.body);
+        }
+        ItemKind::Extern(decl) => {
+            v.visit_ident(&decl.name);
+            for param in &decl.params {
+                v.visit_pattern(&param.pattern);
+                v.visit_type(&param.ty);
+            }
+            if let Some(ret) = &decl.ret {
+                v.visit_type(ret);
+            }
+        }
+        ItemKind::Struct(decl) => {
+            v.visit_ident(&decl.name);
+            for field in &decl.fields {
+                v.visit_ident(&field.name);
+                v.visit_type(&field.ty);
+            }
+        }
+        ItemKind::Enum(decl) => {
+            v.visit_ident(&decl.name);
+            for variant in &decl.variants {
+                v.visit_ident(&variant.name);
+                match &variant.kind {
+                    VariantKind::Unit => {}
+                    VariantKind::Tuple(types) => {
+                        for ty in types {
+                            v.visit_type(ty);
+                        }
+                    }
+                    VariantKind::Struct(fields) => {
+                        for field in fields {
+                            v.visit_ident(&field.name);
+                            v.visit_type(&field.ty);
+                        }
+                    }
+                }
+            }
+        }
+        ItemKind::Trait(decl) => {
+            v.visit_ident(&decl.name);
+            for method in &decl.methods {
+                v.visit_ident(&method.name);
+                for generic in &method.generics {
+                    v.visit_ident(&generic.name);
+                    for bound in &generic.bounds {
+                        v.visit_path(bound);
+                    }
+                }
+                for param in &method.params {
+                    v.visit_pattern(&param.pattern);
+                    v.visit_type(&param.ty);
+                }
+                if let Some(ret) = &method.ret {
+                    v.visit_type(ret);
+                }
+                if let Some(body) = &method.body {
+                    v.visit_block(body);
+                }
+            }
+        }
+        ItemKind::Impl(decl) => {
+            if let Some(trait_ref) = &decl.trait_ref {
+                v.visit_path(trait_ref);
+            }
+            v.visit_type(&decl.self_ty);
+            for method in &decl.methods {
+                v.visit_item(method);
+            }
+        }
+        ItemKind::Mod(decl) => {
+            v.visit_ident(&decl.name);
+            for item in decl.items.iter().flatten() {
+                v.visit_item(item);
+            }
+        }
+        ItemKind::Use(path) => v.visit_path(path),
+        ItemKind::Error => {}
+    }
+}
+
+pub fn walk_block<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, block: &'ast Block) {
+    for stmt in &block.stmts {
+        v.visit_stmt(stmt);
+    }
+    if let Some(tail) = &block.tail {
+        v.visit_expr(tail);
+    }
+}
+
+pub fn walk_stmt<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, stmt: &'ast Stmt) {
+    match &stmt.kind {
+        StmtKind::Let { pattern, ty, value } => {
+            v.visit_pattern(pattern);
+            if let Some(ty) = ty {
+                v.visit_type(ty);
+            }
+            if let Some(value) = value {
+                v.visit_expr(value);
+            }
+        }
+        StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) | StmtKind::Yield(expr) => {
+            v.visit_expr(expr)
+        }
+        StmtKind::Item(item) => v.visit_item(item),
+        StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue | StmtKind::Error => {}
+    }
+}
+
+pub fn walk_expr<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, expr: &'ast Expr) {
+    match &expr.kind {
+        ExprKind::Literal(_) => {}
+        ExprKind::Path(path) => v.visit_path(path),
+        ExprKind::Paren(inner) => v.visit_expr(inner),
+        ExprKind::Tuple(exprs) | ExprKind::Array(exprs) => {
+            for expr in exprs {
+                v.visit_expr(expr);
+            }
+        }
+        ExprKind::Block(block) => v.visit_block(block),
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            v.visit_expr(cond);
+            v.visit_block(then_branch);
+            if let Some(else_branch) = else_branch {
+                v.visit_expr(else_branch);
+            }
+        }
+        ExprKind::While { cond, body } => {
+            v.visit_expr(cond);
+            v.visit_block(body);
+        }
+        ExprKind::Loop(body) => v.visit_block(body),
+        ExprKind::For(for_loop) => {
+            v.visit_pattern(&for_loop.pattern);
+            v.visit_expr(&for_loop.iter);
+            v.visit_block(&for_loop.body);
+        }
+        ExprKind::Closure(closure) => {
+            for param in &closure.params {
+                v.visit_pattern(&param.pattern);
+                if let Some(ty) = &param.ty {
+                    v.visit_type(ty);
+                }
+            }
+            v.visit_expr(&closure.body);
+        }
+        ExprKind::Match(match_expr) => {
+            v.visit_expr(&match_expr.scrutinee);
+            for arm in &match_expr.arms {
+                v.visit_pattern(&arm.pattern);
+                v.visit_expr(&arm.body);
+            }
+        }
+        ExprKind::Range { start, end, .. } => {
+            if let Some(start) = start {
+                v.visit_expr(start);
+            }
+            if let Some(end) = end {
+                v.visit_expr(end);
+            }
+        }
+        ExprKind::Call { callee, args } => {
+            v.visit_expr(callee);
+            for arg in args {
+                v.visit_expr(arg);
+            }
+        }
+        ExprKind::MethodCall {
+            receiver,
+            method,
+            args,
+        } => {
+            v.visit_expr(receiver);
+            v.visit_ident(method);
+            for arg in args {
+                v.visit_expr(arg);
+            }
+        }
+        ExprKind::Field { base, field } => {
+            v.visit_expr(base);
+            v.visit_ident(field);
+        }
+        ExprKind::Index { base, index } => {
+            v.visit_expr(base);
+            v.visit_expr(index);
+        }
+        ExprKind::Try(expr) => v.visit_expr(expr),
+        ExprKind::StructLit { path, fields } => {
+            v.visit_path(path);
+            for field in fields {
+                v.visit_ident(&field.name);
+                v.visit_expr(&field.value);
+            }
+        }
+        ExprKind::Cast { expr, ty } => {
+            v.visit_expr(expr);
+            v.visit_type(ty);
+        }
+        ExprKind::Unary { expr, .. } => v.visit_expr(expr),
+        ExprKind::Binary { lhs, rhs, .. } => {
+            v.visit_expr(lhs);
+            v.visit_expr(rhs);
+        }
+        ExprKind::Assign { target, value } | ExprKind::CompoundAssign { target, value, .. } => {
+            v.visit_expr(target);
+            v.visit_expr(value);
+        }
+    }
+}
+
+pub fn walk_pattern<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, pattern: &'ast Pattern) {
+    match &pattern.kind {
+        PatternKind::Wildcard | PatternKind::Literal(_) => {}
+        PatternKind::Ident(ident) | PatternKind::Mut(ident) => v.visit_ident(ident),
+        PatternKind::Tuple(patterns) => {
+            for pattern in patterns {
+                v.visit_pattern(pattern);
+            }
+        }
+        PatternKind::Path(path) => v.visit_path(path),
+        PatternKind::TupleVariant { path, fields } => {
+            v.visit_path(path);
+            for pattern in fields {
+                v.visit_pattern(pattern);
+            }
+        }
+        PatternKind::StructVariant { path, fields } => {
+            v.visit_path(path);
+            for field in fields {
+                v.visit_ident(&field.name);
+                v.visit_pattern(&field.pattern);
+            }
+        }
+    }
+}
+
+pub fn walk_type<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, ty: &'ast TypeExpr) {
+    match &ty.kind {
+        TypeExprKind::Path(path) => v.visit_path(path),
+        TypeExprKind::Generic { path, args } => {
+            v.visit_path(path);
+            for arg in args {
+                v.visit_type(arg);
+            }
+        }
+        TypeExprKind::Tuple(types) => {
+            for ty in types {
+                v.visit_type(ty);
+            }
+        }
+        TypeExprKind::Fn { params, ret } => {
+            for param in params {
+                v.visit_type(param);
+            }
+            if let Some(ret) = ret {
+                v.visit_type(ret);
+            }
+        }
+        TypeExprKind::Array { elem, len } => {
+            v.visit_type(elem);
+            if let Some(len) = len {
+                v.visit_expr(len);
+            }
+        }
+        TypeExprKind::Never => {}
+    }
+}
+
+pub fn walk_path<'ast, V: Visit<'ast> + ?Sized>(v: &mut V, path: &'ast Path) {
+    for segment in &path.segments {
+        v.visit_ident(segment);
+    }
+}
+
+/// Walks the AST by mutable reference, for passes that rewrite it in place. Works like [`Visit`],
+/// with `walk_*_mut` functions to keep descending.
+pub trait VisitMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_item_mut(&mut self, item: &mut Item) {
+        walk_item_mut(self, item);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_pattern_mut(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern);
+    }
+
+    fn visit_type_mut(&mut self, ty: &mut TypeExpr) {
+        walk_type_mut(self, ty);
+    }
+
+    fn visit_path_mut(&mut self, path: &mut Path) {
+        walk_path_mut(self, path);
+    }
+
+    fn visit_ident_mut(&mut self, _ident: &mut Ident) {}
+}
+
+pub fn walk_program_mut<V: VisitMut + ?Sized>(v: &mut V, program: &mut Program) {
+    for item in &mut program.items {
+        v.visit_item_mut(item);
+    }
+}
+
+pub fn walk_item_mut<V: VisitMut + ?Sized>(v: &mut V, item: &mut Item) {
+    match &mut item.kind {
+        ItemKind::Const(decl) | ItemKind::Static(decl) => {
+            v.visit_ident_mut(&mut decl.name);
+            if let Some(ty) = &mut decl.ty {
+                v.visit_type_mut(ty);
+            }
+            v.visit_expr_mut(&mut decl.value);
+        }
+        ItemKind::Fn(decl) => {
+            v.visit_ident_mut(&mut decl.name);
+            for generic in &mut decl.generics {
+                v.visit_ident_mut(&mut generic.name);
+                for bound in &mut generic.bounds {
+                    v.visit_path_mut(bound);
+                }
+            }
+            for param in &mut decl.params {
+                v.visit_pattern_mut(&mut param.pattern);
+                v.visit_type_mut(&mut param.ty);
+            }
+            if let Some(ret) = &mut decl.ret {
+                v.visit_type_mut(ret);
+            }
+            v.visit_block_mut(&mut decl.body);
+        }
+        ItemKind::Extern(decl) => {
+            v.visit_ident_mut(&mut decl.name);
+            for param in &mut decl.params {
+                v.visit_pattern_mut(&mut param.pattern);
+                v.visit_type_mut(&mut param.ty);
+            }
+            if let Some(ret) = &mut decl.ret {
+                v.visit_type_mut(ret);
+            }
+        }
+        ItemKind::Struct(decl) => {
+            v.visit_ident_mut(&mut decl.name);
+            for field in &mut decl.fields {
+                v.visit_ident_mut(&mut field.name);
+                v.visit_type_mut(&mut field.ty);
+            }
+        }
+        ItemKind::Enum(decl) => {
+            v.visit_ident_mut(&mut decl.name);
+            for variant in &mut decl.variants {
+                v.visit_ident_mut(&mut variant.name);
+                match &mut variant.kind {
+                    VariantKind::Unit => {}
+                    VariantKind::Tuple(types) => {
+                        for ty in types {
+                            v.visit_type_mut(ty);
+                        }
+                    }
+                    VariantKind::Struct(fields) => {
+                        for field in fields {
+                            v.visit_ident_mut(&mut field.name);
+                            v.visit_type_mut(&mut field.ty);
+                        }
+                    }
+                }
+            }
+        }
+        ItemKind::Trait(decl) => {
+            v.visit_ident_mut(&mut decl.name);
+            for method in &mut decl.methods {
+                v.visit_ident_mut(&mut method.name);
+                for generic in &mut method.generics {
+                    v.visit_ident_mut(&mut generic.name);
+                    for bound in &mut generic.bounds {
+                        v.visit_path_mut(bound);
+                    }
+                }
+                for param in &mut method.params {
+                    v.visit_pattern_mut(&mut param.pattern);
+                    v.visit_type_mut(&mut param.ty);
+                }
+                if let Some(ret) = &mut method.ret {
+                    v.visit_type_mut(ret);
+                }
+                if let Some(body) = &mut method.body {
+                    v.visit_block_mut(body);
+                }
+            }
+        }
+        ItemKind::Impl(decl) => {
+            if let Some(trait_ref) = &mut decl.trait_ref {
+                v.visit_path_mut(trait_ref);
+            }
+            v.visit_type_mut(&mut decl.self_ty);
+            for method in &mut decl.methods {
+                v.visit_item_mut(method);
+            }
+        }
+        ItemKind::Mod(decl) => {
+            v.visit_ident_mut(&mut decl.name);
+            for item in decl.items.iter_mut().flatten() {
+                v.visit_item_mut(item);
+            }
+        }
+        ItemKind::Use(path) => v.visit_path_mut(path),
+        ItemKind::Error => {}
+    }
+}
+
+pub fn walk_block_mut<V: VisitMut + ?Sized>(v: &mut V, block: &mut Block) {
+    for stmt in &mut block.stmts {
+        v.visit_stmt_mut(stmt);
+    }
+    if let Some(tail) = &mut block.tail {
+        v.visit_expr_mut(tail);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitMut + ?Sized>(v: &mut V, stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::Let { pattern, ty, value } => {
+            v.visit_pattern_mut(pattern);
+            if let Some(ty) = ty {
+                v.visit_type_mut(ty);
+            }
+            if let Some(value) = value {
+                v.visit_expr_mut(value);
+            }
+        }
+        StmtKind::Expr(expr) | StmtKind::Return(Some(expr)) | StmtKind::Yield(expr) => {
+            v.visit_expr_mut(expr)
+        }
+        StmtKind::Item(item) => v.visit_item_mut(item),
+        StmtKind::Return(None) | StmtKind::Break | StmtKind::Continue | StmtKind::Error => {}
+    }
+}
+
+pub fn walk_expr_mut<V: VisitMut + ?Sized>(v: &mut V, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Literal(_) => {}
+        ExprKind::Path(path) => v.visit_path_mut(path),
+        ExprKind::Paren(inner) => v.visit_expr_mut(inner),
+        ExprKind::Tuple(exprs) | ExprKind::Array(exprs) => {
+            for expr in exprs {
+                v.visit_expr_mut(expr);
+            }
+        }
+        ExprKind::Block(block) => v.visit_block_mut(block),
+        ExprKind::If {
+            cond,
+            then_branch,
+            else_branch,
+        } => {
+            v.visit_expr_mut(cond);
+            v.visit_block_mut(then_branch);
+            if let Some(else_branch) = else_branch {
+                v.visit_expr_mut(else_branch);
+            }
+        }
+        ExprKind::While { cond, body } => {
+            v.visit_expr_mut(cond);
+            v.visit_block_mut(body);
+        }
+        ExprKind::Loop(body) => v.visit_block_mut(body),
+        ExprKind::For(for_loop) => {
+            v.visit_pattern_mut(&mut for_loop.pattern);
+            v.visit_expr_mut(&mut for_loop.iter);
+            v.visit_block_mut(&mut for_loop.body);
+        }
+        ExprKind::Closure(closure) => {
+            for param in &mut closure.params {
+                v.visit_pattern_mut(&mut param.pattern);
+                if let Some(ty) = &mut param.ty {
+                    v.visit_type_mut(ty);
+                }
+            }
+            v.visit_expr_mut(&mut closure.body);
+        }
+        ExprKind::Match(match_expr) => {
+            v.visit_expr_mut(&mut match_expr.scrutinee);
+            for arm in &mut match_expr.arms {
+                v.visit_pattern_mut(&mut arm.pattern);
+                v.visit_expr_mut(&mut arm.body);
+            }
+        }
+        ExprKind::Range { start, end, .. } => {
+            if let Some(start) = start {
+                v.visit_expr_mut(start);
+            }
+            if let Some(end) = end {
+                v.visit_expr_mut(end);
+            }
+        }
+        ExprKind::Call { callee, args } => {
+            v.visit_expr_mut(callee);
+            for arg in args {
+                v.visit_expr_mut(arg);
+            }
+        }
+        ExprKind::MethodCall {
+            receiver,
+            method,
+            args,
+        } => {
+            v.visit_expr_mut(receiver);
+            v.visit_ident_mut(method);
+            for arg in args {
+                v.visit_expr_mut(arg);
+            }
+        }
+        ExprKind::Field { base, field } => {
+            v.visit_expr_mut(base);
+            v.visit_ident_mut(field);
+        }
+        ExprKind::Index { base, index } => {
+            v.visit_expr_mut(base);
+            v.visit_expr_mut(index);
+        }
+        ExprKind::Try(expr) => v.visit_expr_mut(expr),
+        ExprKind::StructLit { path, fields } => {
+            v.visit_path_mut(path);
+            for field in fields {
+                v.visit_ident_mut(&mut field.name);
+                v.visit_expr_mut(&mut field.value);
+            }
+        }
+        ExprKind::Cast { expr, ty } => {
+            v.visit_expr_mut(expr);
+            v.visit_type_mut(ty);
+        }
+        ExprKind::Unary { expr, .. } => v.visit_expr_mut(expr),
+        ExprKind::Binary { lhs, rhs, .. } => {
+            v.visit_expr_mut(lhs);
+            v.visit_expr_mut(rhs);
+        }
+        ExprKind::Assign { target, value } | ExprKind::CompoundAssign { target, value, .. } => {
+            v.visit_expr_mut(target);
+            v.visit_expr_mut(value);
+        }
+    }
+}
+
+pub fn walk_pattern_mut<V: VisitMut + ?Sized>(v: &mut V, pattern: &mut Pattern) {
+    match &mut pattern.kind {
+        PatternKind::Wildcard | PatternKind::Literal(_) => {}
+        PatternKind::Ident(ident) | PatternKind::Mut(ident) => v.visit_ident_mut(ident),
+        PatternKind::Tuple(patterns) => {
+            for pattern in patterns {
+                v.visit_pattern_mut(pattern);
+            }
+        }
+        PatternKind::Path(path) => v.visit_path_mut(path),
+        PatternKind::TupleVariant { path, fields } => {
+            v.visit_path_mut(path);
+            for pattern in fields {
+                v.visit_pattern_mut(pattern);
+            }
+        }
+        PatternKind::StructVariant { path, fields } => {
+            v.visit_path_mut(path);
+            for field in fields {
+                v.visit_ident_mut(&mut field.name);
+                v.visit_pattern_mut(&mut field.pattern);
+            }
+        }
+    }
+}
+
+pub fn walk_type_mut<V: VisitMut + ?Sized>(v: &mut V, ty: &mut TypeExpr) {
+    match &mut ty.kind {
+        TypeExprKind::Path(path) => v.visit_path_mut(path),
+        TypeExprKind::Generic { path, args } => {
+            v.visit_path_mut(path);
+            for arg in args {
+                v.visit_type_mut(arg);
+            }
+        }
+        TypeExprKind::Tuple(types) => {
+            for ty in types {
+                v.visit_type_mut(ty);
+            }
+        }
+        TypeExprKind::Fn { params, ret } => {
+            for param in params {
+                v.visit_type_mut(param);
+            }
+            if let Some(ret) = ret {
+                v.visit_type_mut(ret);
+            }
+        }
+        TypeExprKind::Array { elem, len } => {
+            v.visit_type_mut(elem);
+            if let Some(len) = len {
+                v.visit_expr_mut(len);
+            }
+        }
+        TypeExprKind::Never => {}
+    }
+}
+
+pub fn walk_path_mut<V: VisitMut + ?Sized>(v: &mut V, path: &mut Path) {
+    for segment in &mut path.segments {
+        v.visit_ident_mut(segment);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::parse_source, pretty::print_program};
+
+    /// Collects every identifier in the order they're visited.
+    #[derive(Default)]
+    struct Idents<'ast>(Vec<&'ast str>);
+
+    impl<'ast> Visit<'ast> for Idents<'ast> {
+        fn visit_ident(&mut self, ident: &'ast Ident) {
+            self.0.push(&ident.name);
+        }
+    }
+
+    #[test]
+    fn test_visit_idents() {
+        let source = "fn f(a: Vec<T>) { let (b, _) = a; match b { E::V { x } => g(x).h, } }";
+        let program = parse_source(source).unwrap();
+
+        let mut idents = Idents::default();
+        idents.visit_program(&program);
+        assert_eq!(
+            idents.0,
+            vec!["f", "a", "Vec", "T", "b", "a", "b", "E", "V", "x", "x", "g", "x", "h"]
+        );
+    }
+
+    /// Counts literals, but doesn't look inside closures.
+    struct Literals(usize);
+
+    impl Visit<'_> for Literals {
+        fn visit_expr(&mut self, expr: &Expr) {
+            match expr.kind {
+                ExprKind::Literal(_) => self.0 += 1,
+                ExprKind::Closure(_) => {}
+                _ => walk_expr(self, expr),
+            }
+        }
+    }
+
+    #[test]
+    fn test_visit_override() {
+        let program = parse_source("const X: int = 1 + (2, |x| 3)[4];").unwrap();
+
+        let mut literals = Literals(0);
+        literals.visit_program(&program);
+        assert_eq!(literals.0, 3);
+    }
+
+    /// Renames every identifier called `old` to `new`.
+    struct Rename;
+
+    impl VisitMut for Rename {
+        fn visit_ident_mut(&mut self, ident: &mut Ident) {
+            if ident.name == "old" {
+                ident.name = "new".to_string();
+            }
+        }
+    }
+
+    #[test]
+    fn test_visit_mut_rename() {
+        let source = "fn old(old: int) -> int { let y = old + old::x; old(y) }";
+        let mut program = parse_source(source).unwrap();
+        Rename.visit_program_mut(&mut program);
+
+        let expected = "fn new(new: int) -> int { let y = new + new::x; new(y) }";
+        assert_eq!(
+            print_program(&program),
+            print_program(&parse_source(expected).unwrap())
+        );
+    }
+}
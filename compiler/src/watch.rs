@@ -0,0 +1,357 @@
+//! Runs a program in the VM as its files change, for `run --watch`. While the program runs, its
+//! files are checked every so often, and when they change, it's compiled again and patched into
+//! the VM: the functions whose code changed run the new code from their next call, and the globals
+//! keep their values. A change that the running program can't take, which gives a function other
+//! types of parameters or of what it returns, or changes a struct, enum or global, runs the new
+//! version from the start instead. Once the program returns or panics, it runs again the next time
+//! its files change.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    check::check,
+    codegen::bytecode::{self, GcConfig, Module, Reloader, Vm},
+    console::Console,
+    ffi::Externs,
+    limits::Limits,
+    loader::load_program,
+    mir::{self, opt::OptLevel, Program},
+    source_map::{FileId, SourceMap},
+};
+
+/// How long to wait between checks of whether the files changed.
+const INTERVAL: Duration = Duration::from_millis(250);
+
+/// The files of a program, with their sources when it was compiled.
+type Sources = Vec<(PathBuf, String)>;
+
+/// Runs a program, and runs it again whenever its files change.
+pub fn run(path: &Path, level: OptLevel, config: GcConfig, limits: Limits, host: &Externs) -> ! {
+    let mut sources = Sources::new();
+    let mut restart = None;
+    loop {
+        let build = match restart.take() {
+            Some(build) => build,
+            None => wait(path, level, sources),
+        };
+        let mut watcher = Watcher::new(path, level, INTERVAL, &build);
+        let ran = watcher.run(&build, config, limits, Console::stdio(), host);
+        match watcher.restart.take() {
+            Some((build, reason)) => {
+                eprintln!("restarting, since {}", reason);
+                restart = Some(build);
+            }
+            None => {
+                if let Err(panic) = ran {
+                    eprintln!("{}", panic);
+                }
+                eprintln!("waiting for changes");
+            }
+        }
+        sources = watcher.sources;
+    }
+}
+
+/// Waits for the files of a program to change from their sources, then compiles it, until it
+/// compiles without errors. A program without sources is compiled right away.
+fn wait(path: &Path, level: OptLevel, mut sources: Sources) -> Build {
+    loop {
+        while !sources.is_empty() && !changed(&sources) {
+            thread::sleep(INTERVAL);
+        }
+        match compile(path, level) {
+            Ok(build) => return build,
+            Err((errors, new)) => {
+                eprint!("{}", errors);
+                eprintln!("waiting for changes");
+                sources = new;
+            }
+        }
+    }
+}
+
+/// Returns whether any of the files has changed. A file that can't be read counts as empty.
+fn changed(sources: &Sources) -> bool {
+    (sources.iter()).any(|(path, source)| fs::read_to_string(path).unwrap_or_default() != *source)
+}
+
+/// Returns the files of a source map with their sources.
+fn sources(source_map: &SourceMap) -> Sources {
+    (source_map.files().iter().enumerate())
+        .map(|(i, file)| (file.path.clone(), source_map.source(FileId(i)).to_string()))
+        .collect()
+}
+
+/// A version of the program, compiled.
+struct Build {
+    program: Program,
+    module: Module,
+    source_map: SourceMap,
+}
+
+/// Compiles the program, or returns its errors, each shown with the source it points at, with
+/// the sources of its files.
+fn compile(path: &Path, level: OptLevel) -> Result<Build, (String, Sources)> {
+    let loaded = load_program(path).map_err(|error| {
+        let errors = format!("error: can't read `{}`: {}\n", path.display(), error);
+        (errors, vec![(path.to_path_buf(), String::new())])
+    })?;
+    let (diagnostics, checked) = check(&loaded);
+    let Some((res, lowered)) = checked else {
+        let errors = (diagnostics.iter())
+            .map(|diagnostic| format!("{}\n", diagnostic.with_source_map(&loaded.source_map)));
+        return Err((errors.collect(), sources(&loaded.source_map)));
+    };
+
+    let mut program = mir::build(&lowered, &res);
+    mir::opt::optimize(&mut program, level);
+    let mut module = bytecode::emit(&program);
+    if level >= OptLevel::O1 {
+        bytecode::peephole(&mut module);
+    }
+    Ok(Build {
+        program,
+        module,
+        source_map: loaded.source_map,
+    })
+}
+
+/// What code that's patched into a running program has to agree with it on: its structs, enums
+/// and globals, and the signatures of its functions by name, in order for the ones that share
+/// one, the way the VM matches them.
+#[derive(Debug, Clone, PartialEq)]
+struct Interface {
+    adts: Vec<String>,
+    globals: Vec<String>,
+    fns: BTreeMap<String, Vec<String>>,
+}
+
+impl Interface {
+    fn new(program: &Program) -> Self {
+        let adts = (program.adts.iter())
+            .map(|adt| {
+                let variants: Vec<_> = (adt.variants.iter())
+                    .map(|variant| {
+                        let fields: Vec<_> = (variant.fields.iter())
+                            .map(|field| format!("{}: {}", field.name, field.ty))
+                            .collect();
+                        format!("{}({})", variant.name, fields.join(", "))
+                    })
+                    .collect();
+                format!("{} {}", adt.name, variants.join(" | "))
+            })
+            .collect();
+        let globals = (program.globals.iter())
+            .map(|global| {
+                let kind = if global.is_static { "static" } else { "const" };
+                format!("{} {}: {}", kind, global.name, global.ty)
+            })
+            .collect();
+        let mut fns: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for func in &program.fns {
+            let params: Vec<_> = (func.params().iter())
+                .map(|&param| func.value_ty(param).to_string())
+                .collect();
+            let signature = format!("fn({}) {}", params.join(", "), func.ret);
+            fns.entry(func.name.clone()).or_default().push(signature);
+        }
+        Interface { adts, globals, fns }
+    }
+
+    /// Returns why a program with another interface can't be patched into one with this one, if
+    /// it can't.
+    fn patches(&self, new: &Interface) -> Result<(), String> {
+        if new.adts != self.adts {
+            return Err("its structs and enums changed".to_string());
+        }
+        if new.globals != self.globals {
+            return Err("its globals changed".to_string());
+        }
+        for (name, signatures) in &new.fns {
+            let old = self.fns.get(name).map_or(&[][..], Vec::as_slice);
+            if old.iter().zip(signatures).any(|(old, new)| old != new) {
+                return Err(format!("the signature of `{}` changed", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Patches a running program as its files change.
+struct Watcher<'a> {
+    path: &'a Path,
+    level: OptLevel,
+    /// How long to wait between checks of whether the files changed.
+    interval: Duration,
+    checked: Instant,
+    sources: Sources,
+    /// The source map of the version that was last patched in, which panics point into.
+    source_map: SourceMap,
+    interface: Interface,
+    /// The version that the program has to restart with, since it couldn't be patched in, and
+    /// why.
+    restart: Option<(Build, String)>,
+}
+
+impl<'a> Watcher<'a> {
+    fn new(path: &'a Path, level: OptLevel, interval: Duration, build: &Build) -> Self {
+        Watcher {
+            path,
+            level,
+            interval,
+            checked: Instant::now(),
+            sources: sources(&build.source_map),
+            source_map: build.source_map.clone(),
+            interface: Interface::new(&build.program),
+            restart: None,
+        }
+    }
+
+    /// Runs a version of the program, patching it as its files change, until it returns, panics
+    /// or has to restart. Returns the panic it stopped with, shown with the source it points at.
+    fn run(
+        &mut self,
+        build: &Build,
+        config: GcConfig,
+        limits: Limits,
+        console: Console,
+        host: &Externs,
+    ) -> Result<(), String> {
+        let ran = Vm::new(&build.module, config, limits, console, host).and_then(|mut vm| {
+            vm.set_reloader(self);
+            match build.module.main {
+                Some(main) => vm.call(main, Vec::new()).map(|_| ()),
+                None => Ok(()),
+            }
+        });
+        ran.map_err(|panic| panic.with_source_map(&self.source_map).to_string())
+    }
+}
+
+impl Reloader for Watcher<'_> {
+    fn poll(&mut self, vm: &mut Vm) -> Result<(), String> {
+        if self.checked.elapsed() < self.interval {
+            return Ok(());
+        }
+        // Reading the files takes time too, so they're read once an interval, changed or not
+        self.checked = Instant::now();
+        if !changed(&self.sources) {
+            return Ok(());
+        }
+        let build = match compile(self.path, self.level) {
+            Ok(build) => build,
+            Err((errors, sources)) => {
+                // The program keeps running until it's fixed
+                eprint!("{}", errors);
+                self.sources = sources;
+                return Ok(());
+            }
+        };
+        self.sources = sources(&build.source_map);
+        let interface = Interface::new(&build.program);
+        match (self.interface.patches(&interface)).and_then(|()| vm.reload(&build.module)) {
+            Ok(patched) => {
+                let names: Vec<_> = (patched.iter())
+                    .map(|&func| format!("`{}`", vm.module().functions[func as usize].name))
+                    .collect();
+                if !names.is_empty() {
+                    eprintln!("patched {}", names.join(", "));
+                }
+                self.interface = interface;
+                self.source_map = build.source_map;
+                Ok(())
+            }
+            Err(reason) => {
+                self.restart = Some((build, reason.clone()));
+                Err(format!("restarting, since {}", reason))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::ffi::Value;
+
+    #[test]
+    fn test_watch() {
+        let dir = std::env::temp_dir().join(format!("ruffle-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.rf");
+        let version = |step: &str, after: &str| {
+            format!(
+                "extern fn edit();
+extern fn report(total: int);
+static TOTAL: int = 0;
+{}
+fn main() {{
+    let mut i = 0;
+    while i < 100000 {{
+        TOTAL += step() as int;
+        if i == 10 {{ edit(); }}
+        i += 1;
+    }}
+    report(TOTAL);
+}}
+{}",
+                step, after
+            )
+        };
+        let total = Arc::new(Mutex::new(None));
+        let host = |next: String| {
+            let mut host = Externs::default();
+            let edited = path.clone();
+            host.register("edit", move |_| {
+                fs::write(&edited, &next).unwrap();
+                Ok(Value::Unit)
+            });
+            let total = total.clone();
+            host.register("report", move |args| {
+                *total.lock().unwrap() = Some(args[0].clone());
+                Ok(Value::Unit)
+            });
+            host
+        };
+        let run = |host: &Externs| {
+            let build = compile(&path, OptLevel::O1).unwrap();
+            let mut watcher = Watcher::new(&path, OptLevel::O1, Duration::ZERO, &build);
+            let console = Console::new(&b""[..], std::io::sink());
+            let ran = watcher.run(
+                &build,
+                GcConfig::default(),
+                Limits::default(),
+                console,
+                host,
+            );
+            (ran, watcher)
+        };
+
+        // `main` keeps running its old code, but calls the new `step`, and `TOTAL` keeps counting
+        fs::write(&path, version("fn step() int { 1 }", "")).unwrap();
+        let v2 = version("fn step() int { two() }", "fn two() int { 2 }");
+        let (ran, watcher) = run(&host(v2.clone()));
+        assert_eq!(ran, Ok(()));
+        assert!(watcher.restart.is_none());
+        assert_eq!(watcher.sources[0].1, v2);
+        let Some(Value::Int(reported)) = total.lock().unwrap().take() else {
+            panic!("expected a total");
+        };
+        assert!(100_000 < reported && reported < 200_000, "{}", reported);
+
+        let (ran, watcher) = run(&host(version("fn step() float { 1.0 }", "")));
+        let (_, reason) = watcher.restart.unwrap();
+        assert_eq!(reason, "the signature of `step` changed");
+        assert!(ran.unwrap_err().contains("restarting, since"));
+        assert_eq!(*total.lock().unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+//! Runs the `ruffle` command with the options that only it reads, on programs written to a
+//! temporary directory.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+const RUFFLE: &str = env!("CARGO_BIN_EXE_ruffle");
+
+/// A directory of its own for a test, which is removed when it's dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("ruffle-cli-{}-{}", process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// Writes a file in the directory, making the directories it's in.
+    fn write(&self, path: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs `ruffle` in a directory, returning its exit code and what it printed to stderr.
+fn ruffle(args: &[&str], dir: &Path) -> (Option<i32>, String) {
+    let output = Command::new(RUFFLE)
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    (output.status.code(), stderr)
+}
+
+const SHADOWING: &str = "fn main() {
+    let x = 1;
+    if x > 0 {
+        let x = 2;
+        println(x);
+    }
+}
+";
+
+#[test]
+fn warn() {
+    let dir = TempDir::new("warn");
+    dir.write("main.rf", SHADOWING);
+    let warning = "`x` shadows a variable from an enclosing scope";
+
+    let (code, stderr) = ruffle(&["check", "main.rf"], &dir.0);
+    assert_eq!(code, Some(0));
+    assert!(!stderr.contains(warning), "{}", stderr);
+
+    let (code, stderr) = ruffle(&["check", "--warn", "shadowing", "main.rf"], &dir.0);
+    assert_eq!(code, Some(0));
+    assert!(stderr.contains(warning), "{}", stderr);
+
+    let (code, stderr) = ruffle(&["run", "--warn=shadowing", "main.rf"], &dir.0);
+    assert_eq!(code, Some(0));
+    assert!(stderr.contains(warning), "{}", stderr);
+
+    let (code, stderr) = ruffle(&["check", "--warn", "shadows", "main.rf"], &dir.0);
+    assert_eq!(code, Some(2));
+    assert!(stderr.contains("unknown lint `shadows`"), "{}", stderr);
+
+    // A project's manifest turns them on for every command
+    let project = TempDir::new("warn-project");
+    project.write(
+        "ruffle.toml",
+        "[package]\nname = \"shadows\"\n\n[lints]\nwarn = [\"shadowing\"]\n",
+    );
+    project.write("src/main.rf", SHADOWING);
+    let (code, stderr) = ruffle(&["check"], &project.0);
+    assert_eq!(code, Some(0));
+    assert!(stderr.contains(warning), "{}", stderr);
+}
+
+#[test]
+fn binary_outputs() {
+    let dir = TempDir::new("binary");
+    dir.write("main.rf", "fn main() {}\n");
+
+    // Binary goes to a file named after the source rather than stdout, even when it's the only
+    // output
+    for (emit, file) in [("wasm", "main.wasm"), ("obj", "main.o")] {
+        let output = Command::new(RUFFLE)
+            .args(["build", &format!("--emit={}", emit), "main.rf"])
+            .current_dir(&dir.0)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(0), "{}", emit);
+        assert!(output.stdout.is_empty(), "{}", emit);
+        assert!(!fs::read(dir.0.join(file)).unwrap().is_empty(), "{}", emit);
+    }
+
+    // Text still goes to stdout
+    let output = Command::new(RUFFLE)
+        .args(["build", "--emit=mir", "main.rf"])
+        .current_dir(&dir.0)
+        .output()
+        .unwrap();
+    assert!(String::from_utf8(output.stdout).unwrap().contains("main"));
+}
@@ -0,0 +1,157 @@
+//! Runs each program in `tests/programs` on every engine, and checks that it prints what the
+//! `.out` file next to it says. A program that panics ends its output with the line saying so,
+//! and has to panic with the same message whatever runs it.
+
+#[path = "../src/codegen/wasm/wasi.rs"]
+mod wasi;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{self, Command, Output},
+};
+
+const RUFFLE: &str = env!("CARGO_BIN_EXE_ruffle");
+
+/// The exit code of a program that panics.
+const PANICKED: i32 = 101;
+
+/// The programs, in order.
+fn programs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "rf"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+/// Runs the ruffle command, failing when it can't compile the program.
+fn ruffle(args: &[&str], program: &Path, dir: &Path) -> Output {
+    let output = Command::new(RUFFLE)
+        .args(args)
+        .arg(program)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    if output.status.code() == Some(1) {
+        panic!(
+            "`ruffle {}` failed:\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    output
+}
+
+/// Returns what a program printed to stdout, followed by the panic it printed to stderr, if it
+/// exited with the code of one.
+fn printed(stdout: &[u8], stderr: &[u8], code: Option<i32>) -> String {
+    let mut printed = String::from_utf8_lossy(stdout).into_owned();
+    let stderr = String::from_utf8_lossy(stderr);
+    let panic = stderr.lines().find(|line| line.starts_with("panicked: "));
+    assert_eq!(
+        code,
+        Some(if panic.is_some() { PANICKED } else { 0 }),
+        "{}",
+        stderr
+    );
+    if let Some(panic) = panic {
+        printed += panic;
+        printed.push('\n');
+    }
+    printed
+}
+
+/// Runs a program on an engine, with `dir` as its working directory, returning what it printed.
+fn run(engine: &str, program: &Path, dir: &Path) -> String {
+    match engine {
+        "vm" | "interpreter" | "jit" => {
+            let output = ruffle(&["run", "--engine", engine], program, dir);
+            printed(&output.stdout, &output.stderr, output.status.code())
+        }
+        "wasm" => {
+            ruffle(&["build", "--emit=wasm"], program, dir);
+            let stem = program.file_stem().unwrap();
+            let module = fs::read(dir.join(stem).with_extension("wasm")).unwrap();
+            let (mut store, instance) = wasi::instantiate(&module);
+            let start = instance
+                .get_typed_func::<(), ()>(&mut store, "_start")
+                .unwrap();
+            // Exiting traps, with the code the module exits with
+            let code = match start.call(&mut store, ()) {
+                Ok(()) => Some(0),
+                Err(_) => store.data().exit,
+            };
+            let host = store.data();
+            printed(&host.stdout, &host.stderr, code)
+        }
+        backend => {
+            ruffle(&["build", "--backend", backend], program, dir);
+            let stem = program.file_stem().unwrap();
+            let output = Command::new(dir.join(stem))
+                .current_dir(dir)
+                .output()
+                .unwrap();
+            printed(&output.stdout, &output.stderr, output.status.code())
+        }
+    }
+}
+
+/// Runs every program on an engine, reporting each that printed something other than it should.
+fn check(engine: &str) {
+    let dir = std::env::temp_dir().join(format!("ruffle-engines-{}-{}", process::id(), engine));
+    fs::create_dir_all(&dir).unwrap();
+    let mut failures = Vec::new();
+    for program in programs() {
+        let expected = fs::read_to_string(program.with_extension("out")).unwrap();
+        let printed = run(engine, &program, &dir);
+        if printed != expected {
+            let name = program.file_name().unwrap().to_string_lossy();
+            failures.push(format!(
+                "{}:\nexpected:\n{}\nprinted:\n{}",
+                name, expected, printed
+            ));
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn vm() {
+    check("vm");
+}
+
+#[test]
+fn interpreter() {
+    check("interpreter");
+}
+
+#[test]
+fn jit() {
+    check("jit");
+}
+
+#[test]
+fn native() {
+    check("native");
+}
+
+#[test]
+fn c() {
+    check("c");
+}
+
+#[cfg(feature = "backend-llvm")]
+#[test]
+fn llvm() {
+    check("llvm");
+}
+
+#[test]
+fn wasm() {
+    check("wasm");
+}